@@ -0,0 +1,419 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::RwLock,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::cluster::command_keys;
+use crate::glob::glob_match;
+use crate::parser::{CommandSpec, UserCommand, Value, COMMANDS};
+
+pub mod tests_acl;
+
+/// The categories `+@category` and `-@category` accept. Each comes from the flags in
+/// the command table: @read holds the read-only commands, @slow everything that is not
+/// @fast, and @all every command.
+pub const CATEGORIES: [&str; 8] = [
+    "all", "read", "write", "admin", "fast", "slow", "pubsub", "blocking",
+];
+
+/// The users connections authenticate as and what each of them may run. The locks are
+/// never held across an await, so std locks are enough here.
+#[derive(Debug)]
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+    // Lower-case names of commands outside the command table, such as plugins, which
+    // rules may name
+    unlisted: RwLock<BTreeSet<String>>,
+}
+
+/// A user as ACL SETUSER builds it. A new user is disabled, has no password and may
+/// run nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct User {
+    pub enabled: bool,
+    // Any password is accepted
+    pub nopass: bool,
+    // Digests of the accepted passwords, in the `#<digest>` form ACL LIST shows
+    passwords: Vec<String>,
+    // Applied in order, so a later rule overrides an earlier one it overlaps
+    commands: Vec<CommandRule>,
+    // Glob patterns of the keys commands may touch
+    keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CommandRule {
+    allow: bool,
+    target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Command(&'static str),
+    Category(&'static str),
+    // A command outside the table, by lower-case name
+    Unlisted(String),
+}
+
+impl Acl {
+    /// Starts out with just the default user, which may run everything and is
+    /// protected by `requirepass` when there is one.
+    pub fn new(requirepass: Option<&str>) -> Self {
+        let mut default = User::default();
+        for rule in ["on", "allkeys", "allcommands"] {
+            default.apply(rule).unwrap();
+        }
+        default.set_requirepass(requirepass);
+        Self {
+            users: RwLock::new(BTreeMap::from([("default".to_owned(), default)])),
+            unlisted: RwLock::new(BTreeSet::new()),
+        }
+    }
+
+    /// Lets rules name a command the command table does not know, such as a plugin.
+    pub fn register_command(&self, name: &str) {
+        self.unlisted.write().unwrap().insert(name.to_lowercase());
+    }
+
+    /// Follows CONFIG SET requirepass, which is the password of the default user.
+    pub fn set_requirepass(&self, requirepass: Option<&str>) {
+        if let Some(default) = self.users.write().unwrap().get_mut("default") {
+            default.set_requirepass(requirepass);
+        }
+    }
+
+    /// Whether connections are logged in as `username` without AUTH, as they are as
+    /// the default user while it has no password.
+    pub fn is_nopass(&self, username: &str) -> bool {
+        self.users
+            .read()
+            .unwrap()
+            .get(username)
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    /// Whether `password` logs in as `username`. Disabled users never log in.
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        let users = self.users.read().unwrap();
+        let Some(user) = users.get(username).filter(|user| user.enabled) else {
+            return false;
+        };
+        user.nopass || user.passwords.contains(&digest(password))
+    }
+
+    /// The NOPERM error when `username` may not run the command on the keys it names,
+    /// None when it may. A command outside the table, which keeps its name as the first
+    /// argument, has no flags a category could match, so only +@all or a rule naming
+    /// it lets it run.
+    pub fn check(&self, username: &str, command: UserCommand, args: &[Value]) -> Option<Value> {
+        let users = self.users.read().unwrap();
+        let user = users.get(username);
+        let (allowed, name) = match command.spec() {
+            Some(spec) => (
+                user.is_some_and(|user| user.can_run(spec)),
+                spec.name.to_lowercase(),
+            ),
+            None => {
+                let name = match args.first() {
+                    Some(Value::BulkString(name)) => String::from_utf8_lossy(name).to_lowercase(),
+                    _ => String::new(),
+                };
+                (user.is_some_and(|user| user.can_run_unlisted(&name)), name)
+            }
+        };
+        if !allowed {
+            return Some(Value::SimpleError(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                username, name
+            )));
+        }
+        let keys = command_keys(command, args);
+        let mut denied = keys.iter().any(|key| match key {
+            Value::BulkString(key) => !user.is_some_and(|user| user.can_access(key)),
+            _ => false,
+        });
+        // SORT BY and GET read keys named by patterns, so like Redis they need every key
+        if command == UserCommand::Sort && sorts_by_pattern(args) {
+            denied |= !user.is_some_and(User::can_access_all);
+        }
+        denied.then(|| Value::SimpleError("NOPERM No permissions to access a key".to_owned()))
+    }
+
+    /// ACL SETUSER: creates the user when needed and applies every rule in order. Nothing
+    /// changes when one of them is invalid.
+    pub fn set_user(&self, username: &str, rules: &[String]) -> Result<(), String> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(username).cloned().unwrap_or_default();
+        let unlisted = self.unlisted.read().unwrap();
+        for rule in rules {
+            let named = rule
+                .get(1..)
+                .map(str::to_lowercase)
+                .filter(|name| unlisted.contains(name));
+            match (rule.chars().next(), named) {
+                (Some(sign @ ('+' | '-')), Some(name)) => {
+                    user.set_rule(sign == '+', Target::Unlisted(name))
+                }
+                _ => user.apply(rule).map_err(|err| {
+                    format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, err)
+                })?,
+            }
+        }
+        users.insert(username.to_owned(), user);
+        Ok(())
+    }
+
+    /// ACL DELUSER. Returns how many of the users existed; the default user cannot go.
+    pub fn del_users(&self, usernames: &[String]) -> Result<usize, String> {
+        if usernames.iter().any(|username| username == "default") {
+            return Err("ERR The 'default' user cannot be removed".to_owned());
+        }
+        let mut users = self.users.write().unwrap();
+        Ok(usernames
+            .iter()
+            .filter(|username| users.remove(*username).is_some())
+            .count())
+    }
+
+    pub fn user(&self, username: &str) -> Option<User> {
+        self.users.read().unwrap().get(username).cloned()
+    }
+
+    /// Every user name, sorted.
+    pub fn usernames(&self) -> Vec<String> {
+        self.users.read().unwrap().keys().cloned().collect()
+    }
+
+    /// One `user <name> <rules>` line per user, as ACL LIST shows them.
+    pub fn list(&self) -> Vec<String> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, user)| format!("user {} {}", name, user.describe()))
+            .collect()
+    }
+}
+
+impl User {
+    /// Applies one ACL rule: on, off, >password, <password, #digest, nopass, resetpass,
+    /// ~pattern, allkeys, resetkeys, +command, -command, +@category, -@category,
+    /// allcommands, nocommands or reset.
+    pub fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        let lower = rule.to_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_owned()],
+            "resetkeys" => self.keys.clear(),
+            "allcommands" => self.apply("+@all")?,
+            "nocommands" => self.apply("-@all")?,
+            "reset" => *self = User::default(),
+            _ => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", password) => self.add_password(digest(password)),
+                ("#", hash) if is_digest(hash) => {
+                    self.add_password(format!("#{}", hash.to_lowercase()))
+                }
+                ("<", password) => {
+                    let hash = digest(password);
+                    if !self.passwords.contains(&hash) {
+                        return Err("no such password");
+                    }
+                    self.passwords.retain(|other| *other != hash);
+                }
+                ("~", pattern) => self.keys.push(pattern.to_owned()),
+                ("+" | "-", name) => {
+                    let target = match name.strip_prefix('@') {
+                        Some(category) => Target::Category(
+                            CATEGORIES
+                                .into_iter()
+                                .find(|known| known.eq_ignore_ascii_case(category))
+                                .ok_or("Unknown command or category name in ACL")?,
+                        ),
+                        None => Target::Command(
+                            CommandSpec::lookup(name)
+                                .ok_or("Unknown command or category name in ACL")?
+                                .name,
+                        ),
+                    };
+                    self.set_rule(rule.starts_with('+'), target);
+                }
+                _ => return Err("Syntax error"),
+            },
+        }
+        Ok(())
+    }
+
+    /// Whether the user may run a command; the last rule covering it decides.
+    pub fn can_run(&self, spec: &CommandSpec) -> bool {
+        self.commands
+            .iter()
+            .rev()
+            .find(|rule| rule.target.covers(spec))
+            .is_some_and(|rule| rule.allow)
+    }
+
+    /// Whether the user may run a command outside the table, going by the last rule
+    /// that is +@all, -@all or names it.
+    pub fn can_run_unlisted(&self, name: &str) -> bool {
+        self.commands
+            .iter()
+            .rev()
+            .find(|rule| match &rule.target {
+                Target::Category(category) => *category == "all",
+                Target::Unlisted(unlisted) => unlisted == name,
+                Target::Command(_) => false,
+            })
+            .is_some_and(|rule| rule.allow)
+    }
+
+    pub fn can_access(&self, key: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key))
+    }
+
+    /// Whether the user may touch any key, as allkeys or ~* allow.
+    pub fn can_access_all(&self) -> bool {
+        self.keys.iter().any(|pattern| pattern == "*")
+    }
+
+    /// The flags ACL GETUSER reports.
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    /// The command rules in the form SETUSER takes them, such as `+@all -flushdb`.
+    pub fn command_rules(&self) -> String {
+        if self.commands.is_empty() {
+            return "-@all".to_owned();
+        }
+        let rules: Vec<String> = self
+            .commands
+            .iter()
+            .map(|rule| {
+                let sign = if rule.allow { '+' } else { '-' };
+                match &rule.target {
+                    Target::Command(name) => format!("{}{}", sign, name.to_lowercase()),
+                    Target::Category(category) => format!("{}@{}", sign, category),
+                    Target::Unlisted(name) => format!("{}{}", sign, name),
+                }
+            })
+            .collect();
+        rules.join(" ")
+    }
+
+    /// The key patterns in the form SETUSER takes them, such as `~cache:*`.
+    pub fn key_rules(&self) -> String {
+        let rules: Vec<String> = self.keys.iter().map(|key| format!("~{}", key)).collect();
+        rules.join(" ")
+    }
+
+    // Every rule needed to build the user again, as ACL LIST shows them
+    fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().into_iter().map(str::to_owned).collect();
+        rules.extend(self.passwords.iter().cloned());
+        rules.extend(self.keys.iter().map(|key| format!("~{}", key)));
+        rules.push(self.command_rules());
+        rules.join(" ")
+    }
+
+    // A rule only matters after the last one it overrides completely
+    fn set_rule(&mut self, allow: bool, target: Target) {
+        if target == Target::Category("all") {
+            self.commands.clear();
+        }
+        self.commands.retain(|other| other.target != target);
+        self.commands.push(CommandRule { allow, target });
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn set_requirepass(&mut self, requirepass: Option<&str>) {
+        match requirepass {
+            Some(password) => {
+                self.passwords = vec![digest(password)];
+                self.nopass = false;
+            }
+            None => {
+                self.passwords.clear();
+                self.nopass = true;
+            }
+        }
+    }
+}
+
+impl Target {
+    fn covers(&self, spec: &CommandSpec) -> bool {
+        match *self {
+            Self::Command(name) => name == spec.name,
+            Self::Unlisted(_) => false,
+            Self::Category("all") => true,
+            Self::Category("read") => spec.flags.contains(&"readonly"),
+            Self::Category("slow") => !spec.flags.contains(&"fast"),
+            Self::Category(category) => spec.flags.contains(&category),
+        }
+    }
+}
+
+/// The commands in a category, for ACL CAT.
+pub fn category_commands(category: &str) -> Option<Vec<&'static str>> {
+    let category = CATEGORIES
+        .into_iter()
+        .find(|known| known.eq_ignore_ascii_case(category))?;
+    let target = Target::Category(category);
+    Some(
+        COMMANDS
+            .iter()
+            .filter(|spec| target.covers(spec))
+            .map(|spec| spec.name)
+            .collect(),
+    )
+}
+
+// Whether SORT is given a BY or GET pattern. BY nosort skips sorting rather than
+// reading any key
+fn sorts_by_pattern(args: &[Value]) -> bool {
+    args.windows(2).skip(1).any(|pair| match pair {
+        [Value::BulkString(option), Value::BulkString(pattern)] => {
+            option.eq_ignore_ascii_case(b"GET")
+                || (option.eq_ignore_ascii_case(b"BY") && !pattern.eq_ignore_ascii_case(b"nosort"))
+        }
+        _ => false,
+    })
+}
+
+// Passwords are only kept as `#<sha256 in hex>`, as Redis keeps them, so ACL LIST never
+// shows them in the clear and its rules load in Redis too
+fn digest(password: &str) -> String {
+    let hash = Sha256::digest(password.as_bytes());
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("#{}", hex)
+}
+
+fn is_digest(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::testing::args;
+
+    fn rules(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| part.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_user_follows_requirepass() {
+        let acl = Acl::new(None);
+        assert!(acl.is_nopass("default"));
+        assert!(acl.authenticate("default", "anything"));
+
+        acl.set_requirepass(Some("secret"));
+        assert!(!acl.is_nopass("default"));
+        assert!(acl.authenticate("default", "secret"));
+        assert!(!acl.authenticate("default", "wrong"));
+        assert!(!acl.authenticate("nobody", "secret"));
+        assert_eq!(acl.usernames(), vec!["default"]);
+        assert!(acl.list()[0].starts_with("user default on #"));
+        assert!(acl.list()[0].ends_with(" ~* +@all"));
+    }
+
+    #[test]
+    fn test_set_user_and_permissions() {
+        let acl = Acl::new(None);
+        acl.set_user(
+            "alice",
+            &rules(&["on", ">pw", "~cache:*", "+@read", "-hget", "+set"]),
+        )
+        .unwrap();
+        assert!(acl.authenticate("alice", "pw"));
+        assert_eq!(
+            acl.user("alice").unwrap().command_rules(),
+            "+@read -hget +set"
+        );
+
+        assert_eq!(
+            acl.check("alice", UserCommand::Get, &args(&["cache:1"])),
+            None
+        );
+        assert_eq!(
+            acl.check("alice", UserCommand::Set, &args(&["cache:1", "v"])),
+            None
+        );
+        assert_eq!(
+            acl.check("alice", UserCommand::HGet, &args(&["cache:1", "f"])),
+            Some(Value::SimpleError(
+                "NOPERM User alice has no permissions to run the 'hget' command".to_owned()
+            ))
+        );
+        assert_eq!(
+            acl.check("alice", UserCommand::Del, &args(&["cache:1"])),
+            Some(Value::SimpleError(
+                "NOPERM User alice has no permissions to run the 'del' command".to_owned()
+            ))
+        );
+        assert_eq!(
+            acl.check("alice", UserCommand::Get, &args(&["other"])),
+            Some(Value::SimpleError(
+                "NOPERM No permissions to access a key".to_owned()
+            ))
+        );
+
+        // Disabling the user or removing the password locks it out
+        acl.set_user("alice", &rules(&["off"])).unwrap();
+        assert!(!acl.authenticate("alice", "pw"));
+        acl.set_user("alice", &rules(&["on", "<pw"])).unwrap();
+        assert!(!acl.authenticate("alice", "pw"));
+    }
+
+    #[test]
+    fn test_keyword_keys_and_unlisted_commands() {
+        let acl = Acl::new(None);
+        acl.register_command("MY.TOUCH");
+        acl.set_user("dave", &rules(&["on", "nopass", "~foo*", "+@all"]))
+            .unwrap();
+        let denied_key = Some(Value::SimpleError(
+            "NOPERM No permissions to access a key".to_owned(),
+        ));
+        assert_eq!(
+            acl.check(
+                "dave",
+                UserCommand::XRead,
+                &args(&["COUNT", "1", "STREAMS", "foo", "bar", "0", "0"])
+            ),
+            denied_key
+        );
+        assert_eq!(
+            acl.check(
+                "dave",
+                UserCommand::XReadGroup,
+                &args(&["GROUP", "g", "c", "STREAMS", "foo", ">"])
+            ),
+            None
+        );
+        assert_eq!(
+            acl.check(
+                "dave",
+                UserCommand::Sort,
+                &args(&["foo", "ALPHA", "STORE", "bar"])
+            ),
+            denied_key
+        );
+        assert_eq!(
+            acl.check("dave", UserCommand::Sort, &args(&["foo", "BY", "w_*"])),
+            denied_key
+        );
+        assert_eq!(
+            acl.check("dave", UserCommand::Sort, &args(&["foo", "BY", "nosort"])),
+            None
+        );
+
+        // Commands outside the table need +@all or a rule naming them
+        assert_eq!(
+            acl.check("dave", UserCommand::Invalid, &args(&["my.touch", "foo"])),
+            None
+        );
+        acl.set_user("erin", &rules(&["on", "nopass", "allkeys", "+@write"]))
+            .unwrap();
+        assert_eq!(
+            acl.check("erin", UserCommand::Invalid, &args(&["my.touch", "foo"])),
+            Some(Value::SimpleError(
+                "NOPERM User erin has no permissions to run the 'my.touch' command".to_owned()
+            ))
+        );
+        acl.set_user("erin", &rules(&["+MY.TOUCH"])).unwrap();
+        assert_eq!(
+            acl.user("erin").unwrap().command_rules(),
+            "+@write +my.touch"
+        );
+        assert_eq!(
+            acl.check("erin", UserCommand::Invalid, &args(&["my.touch", "foo"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_change_nothing() {
+        let acl = Acl::new(None);
+        acl.set_user("bob", &rules(&["on", "nopass"])).unwrap();
+        for rule in ["+nosuchcommand", "+@nosuchcategory", "<unknown", "bogus"] {
+            let err = acl
+                .set_user("bob", &rules(&["allcommands", rule]))
+                .unwrap_err();
+            assert!(err.starts_with(&format!("ERR Error in ACL SETUSER modifier '{}'", rule)));
+        }
+        assert_eq!(acl.user("bob").unwrap().command_rules(), "-@all");
+        assert_eq!(acl.list()[0], "user bob on nopass -@all");
+
+        assert_eq!(acl.del_users(&rules(&["bob", "missing"])), Ok(1));
+        assert!(acl.del_users(&rules(&["default"])).is_err());
+    }
+
+    #[test]
+    fn test_listed_passwords_set_the_same_user_again() {
+        let acl = Acl::new(None);
+        acl.set_user(
+            "carol",
+            &rules(&["on", ">pw", "allkeys", "+@all", "-@admin"]),
+        )
+        .unwrap();
+        let line = acl.list().remove(0);
+        // The SHA-256 of "pw", as Redis lists it
+        assert!(line.starts_with(
+            "user carol on #30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4 "
+        ));
+        let listed: Vec<String> = line.split(' ').skip(2).map(str::to_owned).collect();
+        acl.set_user("copy", &listed).unwrap();
+        assert_eq!(acl.user("copy"), acl.user("carol"));
+        assert!(acl.authenticate("copy", "pw"));
+        assert!(acl.set_user("copy", &rules(&["#0123abcd"])).is_err());
+
+        let admin = category_commands("admin").unwrap();
+        assert!(admin.contains(&"CONFIG"));
+        assert!(!admin.contains(&"GET"));
+        assert!(category_commands("strings").is_none());
+    }
+}
@@ -0,0 +1,241 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use redis_rust::parser::{RespHandler, Value};
+use tokio::net::TcpStream;
+
+pub mod tests_bench;
+
+/// The commands a run can be made of.
+const TESTS: [&str; 3] = ["set", "get", "incr"];
+
+/// How the benchmark was started, with the flags redis-benchmark uses for the same
+/// settings.
+#[derive(Debug, PartialEq)]
+struct Options {
+    host: String,
+    port: u16,
+    // -c: connections sending commands at the same time
+    clients: usize,
+    // -n: commands sent per test
+    requests: u64,
+    // -P: commands each connection sends before reading the replies
+    pipeline: usize,
+    // -d: bytes in each SET value
+    data_size: usize,
+    // -r: spread keys over this many names instead of using a single one
+    keyspace: Option<u64>,
+    // -t: the tests to run, in order
+    tests: Vec<&'static str>,
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            host: "127.0.0.1".to_owned(),
+            port: 6379,
+            clients: 50,
+            requests: 100_000,
+            pipeline: 1,
+            data_size: 3,
+            keyspace: None,
+            tests: TESTS.to_vec(),
+        };
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for '{}'", flag))?;
+            let invalid = || format!("Invalid value '{}' for '{}'", value, flag);
+            match flag.as_str() {
+                "-h" => options.host = value.clone(),
+                "-p" => options.port = value.parse().map_err(|_| invalid())?,
+                "-c" => options.clients = positive(&value).ok_or_else(invalid)?,
+                "-n" => options.requests = positive(&value).ok_or_else(invalid)?,
+                "-P" => options.pipeline = positive(&value).ok_or_else(invalid)?,
+                "-d" => options.data_size = value.parse().map_err(|_| invalid())?,
+                "-r" => options.keyspace = Some(positive(&value).ok_or_else(invalid)?),
+                "-t" => {
+                    options.tests = value
+                        .split(',')
+                        .map(|name| {
+                            TESTS
+                                .into_iter()
+                                .find(|test| test.eq_ignore_ascii_case(name.trim()))
+                                .ok_or_else(|| format!("Unknown test '{}'", name))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => return Err(format!("Unknown option '{}'", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// A number above zero
+fn positive<T: std::str::FromStr + PartialOrd + Default>(value: &str) -> Option<T> {
+    value.parse().ok().filter(|value| *value > T::default())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_args(std::env::args().skip(1))?;
+    let address = format!("{}:{}", options.host, options.port);
+    for test in &options.tests {
+        let report = run_test(&address, test, &options).await?;
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+/// What one test measured. Latencies are per pipeline round trip, in microseconds,
+/// and count for every command in it, as redis-benchmark does.
+#[derive(Debug)]
+struct Report {
+    test: &'static str,
+    requests: u64,
+    clients: usize,
+    pipeline: usize,
+    elapsed: Duration,
+    errors: u64,
+    latencies: Vec<u64>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let millis = |percent| percentile(&self.latencies, percent) as f64 / 1000.0;
+        writeln!(f, "====== {} ======", self.test.to_uppercase())?;
+        writeln!(
+            f,
+            "  {} requests completed in {:.2} seconds",
+            self.requests,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "  {} parallel clients, pipeline {}",
+            self.clients, self.pipeline
+        )?;
+        if self.errors > 0 {
+            writeln!(f, "  {} error replies", self.errors)?;
+        }
+        writeln!(
+            f,
+            "  throughput: {:.2} requests per second",
+            self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        write!(
+            f,
+            "  latency (msec): p50={:.3} p95={:.3} p99={:.3} max={:.3}",
+            millis(50.0),
+            millis(95.0),
+            millis(99.0),
+            millis(100.0)
+        )
+    }
+}
+
+/// The value below which `percent` of the sorted samples fall, 0 without samples.
+fn percentile(sorted: &[u64], percent: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// The command a test sends. SET and GET share `key:<suffix>` and INCR counts in
+/// `counter:<suffix>`, so the counters never meet a non-number.
+fn test_command(test: &str, suffix: &str, value: &[u8]) -> Value {
+    let key = |prefix| format!("{}:{}", prefix, suffix).into_bytes();
+    let args: Vec<Vec<u8>> = match test {
+        "set" => vec![b"SET".to_vec(), key("key"), value.to_vec()],
+        "get" => vec![b"GET".to_vec(), key("key")],
+        _ => vec![b"INCR".to_vec(), key("counter")],
+    };
+    Value::Array(
+        args.into_iter()
+            .map(|arg| Value::BulkString(arg.into()))
+            .collect(),
+    )
+}
+
+// Runs one test over `clients` connections, which take the requests in pipeline
+// batches until all were sent
+async fn run_test(address: &str, test: &'static str, options: &Options) -> anyhow::Result<Report> {
+    let remaining = Arc::new(AtomicU64::new(options.requests));
+    let value = vec![b'x'; options.data_size];
+    let started = Instant::now();
+    let mut tasks = Vec::with_capacity(options.clients);
+    for client in 0..options.clients {
+        let socket = TcpStream::connect(address).await?;
+        socket.set_nodelay(true)?;
+        let remaining = Arc::clone(&remaining);
+        let value = value.clone();
+        let (pipeline, keyspace) = (options.pipeline as u64, options.keyspace);
+        tasks.push(tokio::spawn(async move {
+            let mut server = RespHandler::new(socket);
+            // Every connection draws its own keys, from a small xorshift generator
+            let mut seed = (client as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            let mut latencies = Vec::new();
+            let mut errors = 0;
+            loop {
+                let taken = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    (left > 0).then(|| left - left.min(pipeline))
+                });
+                let Ok(left) = taken else {
+                    return anyhow::Ok((latencies, errors));
+                };
+                let batch = left.min(pipeline);
+                let commands: Vec<Value> = (0..batch)
+                    .map(|_| {
+                        let suffix = match keyspace {
+                            Some(keyspace) => {
+                                seed ^= seed << 13;
+                                seed ^= seed >> 7;
+                                seed ^= seed << 17;
+                                format!("{:012}", seed % keyspace)
+                            }
+                            None => "__rand_int__".to_owned(),
+                        };
+                        test_command(test, &suffix, &value)
+                    })
+                    .collect();
+                let sent = Instant::now();
+                server.write_values(&commands).await?;
+                for _ in 0..batch {
+                    match server.read_value().await? {
+                        Some(Value::SimpleError(_)) => errors += 1,
+                        Some(_) => {}
+                        None => anyhow::bail!("Connection closed by the server"),
+                    }
+                }
+                let latency = sent.elapsed().as_micros() as u64;
+                latencies.extend(std::iter::repeat_n(latency, batch as usize));
+            }
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(options.requests as usize);
+    let mut errors = 0;
+    for task in tasks {
+        let (client_latencies, client_errors) = task.await??;
+        latencies.extend(client_latencies);
+        errors += client_errors;
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+    Ok(Report {
+        test,
+        requests: options.requests,
+        clients: options.clients,
+        pipeline: options.pipeline,
+        elapsed,
+        errors,
+        latencies,
+    })
+}
@@ -0,0 +1,85 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| part.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::from_args(args(&[
+            "-c", "4", "-n", "1000", "-P", "16", "-t", "get,INCR",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                host: "127.0.0.1".to_owned(),
+                port: 6379,
+                clients: 4,
+                requests: 1000,
+                pipeline: 16,
+                data_size: 3,
+                keyspace: None,
+                tests: vec!["get", "incr"],
+            }
+        );
+        assert!(Options::from_args(args(&["-c", "0"])).is_err());
+        assert!(Options::from_args(args(&["-t", "set,del"])).is_err());
+        assert!(Options::from_args(args(&["-n"])).is_err());
+        assert!(Options::from_args(args(&["--fast", "yes"])).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&samples, 50.0), 50);
+        assert_eq!(percentile(&samples, 99.0), 99);
+        assert_eq!(percentile(&samples, 100.0), 100);
+        assert_eq!(percentile(&samples, 0.0), 1);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn test_commands() {
+        let bulk = |text: &str| Value::BulkString(text.to_owned().into());
+        assert_eq!(
+            test_command("set", "7", b"xxx"),
+            Value::Array(vec![bulk("SET"), bulk("key:7"), bulk("xxx")])
+        );
+        assert_eq!(
+            test_command("get", "7", b"xxx"),
+            Value::Array(vec![bulk("GET"), bulk("key:7")])
+        );
+        assert_eq!(
+            test_command("incr", "7", b"xxx"),
+            Value::Array(vec![bulk("INCR"), bulk("counter:7")])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_against_a_server() -> anyhow::Result<()> {
+        let server = redis_rust::Server::builder()
+            .bind("127.0.0.1:0".parse()?)
+            .spawn()
+            .await?;
+        let options = Options {
+            clients: 3,
+            requests: 100,
+            pipeline: 8,
+            keyspace: Some(10),
+            ..Options::from_args(Vec::new()).unwrap()
+        };
+
+        let address = server.local_addr().to_string();
+        for test in TESTS {
+            let report = run_test(&address, test, &options).await?;
+            assert_eq!(report.latencies.len(), 100);
+            assert_eq!(report.errors, 0);
+            assert!(report.to_string().contains("100 requests completed"));
+        }
+        server.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use redis_rust::error::RespError;
+use redis_rust::parser::{parse_inline, ParseStatus, RespHandler, Value};
+use tokio::net::TcpStream;
+
+pub mod tests_cli;
+
+/// Where typed commands are kept between sessions.
+const HISTORY_FILE: &str = ".redis_rust_cli_history";
+
+/// How the client was started: the server to talk to and, when given, a single
+/// command to run instead of the interactive prompt.
+#[derive(Debug, PartialEq)]
+struct Options {
+    host: String,
+    port: u16,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            host: "127.0.0.1".to_owned(),
+            port: 6379,
+            command: Vec::new(),
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" => {
+                    options.host = args.next().ok_or("Missing value for '-h'")?;
+                }
+                "-p" => {
+                    let port = args.next().ok_or("Missing value for '-p'")?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("Invalid port '{}'", port))?;
+                }
+                // Everything from the first other argument on is the command
+                _ => {
+                    options.command.push(arg);
+                    options.command.extend(args.by_ref());
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_args(std::env::args().skip(1))?;
+    let address = format!("{}:{}", options.host, options.port);
+    let socket = TcpStream::connect(&address)
+        .await
+        .map_err(|err| format!("Could not connect to {}: {}", address, err))?;
+    let mut server = RespHandler::new(socket);
+
+    if !options.command.is_empty() {
+        let command = Value::Array(
+            options
+                .command
+                .into_iter()
+                .map(|arg| Value::BulkString(arg.into()))
+                .collect(),
+        );
+        return Ok(run(&mut server, command).await?);
+    }
+
+    let mut history = History::load();
+    let stdin = io::stdin();
+    loop {
+        print!("{}> ", address);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        let line = match line {
+            "" => continue,
+            "quit" | "exit" => break,
+            "history" => {
+                history.print();
+                continue;
+            }
+            _ => match history.expand(line) {
+                Some(line) => line,
+                None => {
+                    println!("(error) No such history entry '{}'", line);
+                    continue;
+                }
+            },
+        };
+        history.push(&line);
+
+        let command = match parse_inline(format!("{}\n", line).as_bytes()) {
+            Ok(ParseStatus::Complete(command, _)) => command,
+            Ok(ParseStatus::NeedMoreData) => continue,
+            Err(err) => {
+                println!("Invalid argument(s): {}", err);
+                continue;
+            }
+        };
+        run(&mut server, command).await?;
+    }
+    Ok(())
+}
+
+// Sends one command and prints its reply. After SUBSCRIBE, PSUBSCRIBE or MONITOR
+// the server keeps sending, so everything is printed until the connection closes
+async fn run(server: &mut RespHandler, command: Value) -> Result<(), RespError> {
+    let streaming = match &command {
+        Value::Array(args) => matches!(
+            args.first(),
+            Some(Value::BulkString(name))
+                if ["subscribe", "psubscribe", "monitor"]
+                    .iter()
+                    .any(|streaming| name.eq_ignore_ascii_case(streaming.as_bytes()))
+        ),
+        _ => false,
+    };
+    server.write_value(&command).await?;
+    loop {
+        match server.read_value().await? {
+            Some(reply) => println!("{}", format_reply(&reply)),
+            None => {
+                println!("Connection closed by the server");
+                std::process::exit(1);
+            }
+        }
+        if !streaming {
+            return Ok(());
+        }
+    }
+}
+
+/// A reply the way redis-cli prints it: strings quoted, other scalars tagged with
+/// their type and aggregates as numbered lines.
+fn format_reply(reply: &Value) -> String {
+    match reply {
+        Value::SimpleString(string) => string.clone(),
+        Value::SimpleError(message) => format!("(error) {}", message),
+        Value::Integer(n) => format!("(integer) {}", n),
+        Value::BulkString(bytes) => quote(bytes),
+        Value::Null | Value::NullArray => "(nil)".to_owned(),
+        Value::Double(n) => format!("(double) {}", n),
+        Value::Boolean(b) => format!("({})", b),
+        Value::BigNumber(n) => format!("(big number) {}", n),
+        Value::Array(items) | Value::Set(items) | Value::Push(items) => {
+            let lines: Vec<_> = items.iter().map(format_reply).collect();
+            numbered(&lines, ")", "(empty array)")
+        }
+        Value::Map(pairs) => {
+            let lines: Vec<_> = pairs
+                .iter()
+                .map(|(key, value)| format!("{} => {}", format_reply(key), format_reply(value)))
+                .collect();
+            numbered(&lines, "#", "(empty hash)")
+        }
+    }
+}
+
+// Numbers the items of an aggregate. Lines of nested items are indented to line up
+// under the first one
+fn numbered(items: &[String], marker: &str, empty: &str) -> String {
+    if items.is_empty() {
+        return empty.to_owned();
+    }
+    let width = items.len().to_string().len();
+    let mut lines = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let prefix = format!("{:>width$}{} ", index + 1, marker, width = width);
+        let indent = " ".repeat(prefix.len());
+        for (number, line) in item.lines().enumerate() {
+            match number {
+                0 => lines.push(format!("{}{}", prefix, line)),
+                _ => lines.push(format!("{}{}", indent, line)),
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+// A bulk string in double quotes, with quotes, backslashes and unprintable bytes
+// escaped the way they would be typed back in
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Commands typed so far, oldest first, saved to `HISTORY_FILE` in the home
+/// directory. `history` lists them and `!n` runs the nth again.
+#[derive(Debug, Default)]
+struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    fn load() -> Self {
+        let path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let entries = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_owned).collect())
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    fn push(&mut self, line: &str) {
+        self.entries.push(line.to_owned());
+        if let Some(path) = &self.path {
+            let appended = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            // History is a convenience, so a read-only home only loses it
+            if appended.is_err() {
+                self.path = None;
+            }
+        }
+    }
+
+    /// The command a line stands for: `!n` is the nth entry, `!!` the last one and
+    /// anything else the line itself. None when the entry does not exist.
+    fn expand(&self, line: &str) -> Option<String> {
+        let Some(reference) = line.strip_prefix('!') else {
+            return Some(line.to_owned());
+        };
+        let entry = match reference {
+            "!" => self.entries.last(),
+            n => self.entries.get(n.parse::<usize>().ok()?.checked_sub(1)?),
+        };
+        entry.cloned()
+    }
+
+    fn print(&self) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            println!("{:>5}  {}", index + 1, entry);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| part.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::from_args(args(&["-p", "7000", "get", "-h"])).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                host: "127.0.0.1".to_owned(),
+                port: 7000,
+                command: args(&["get", "-h"]),
+            }
+        );
+        assert!(Options::from_args(args(&["-p", "high"])).is_err());
+        assert!(Options::from_args(args(&["-h"])).is_err());
+    }
+
+    #[test]
+    fn test_format_scalars() {
+        assert_eq!(format_reply(&Value::SimpleString("OK".to_owned())), "OK");
+        assert_eq!(
+            format_reply(&Value::SimpleError("ERR nope".to_owned())),
+            "(error) ERR nope"
+        );
+        assert_eq!(format_reply(&Value::Integer(3)), "(integer) 3");
+        assert_eq!(format_reply(&Value::Null), "(nil)");
+        assert_eq!(format_reply(&Value::Double(1.5)), "(double) 1.5");
+        assert_eq!(format_reply(&Value::Boolean(true)), "(true)");
+        assert_eq!(
+            format_reply(&Value::BulkString("say \"hi\"\n\x01".into())),
+            "\"say \\\"hi\\\"\\n\\x01\""
+        );
+    }
+
+    #[test]
+    fn test_format_aggregates() {
+        let bulk = |s: &str| Value::BulkString(s.to_owned().into());
+        let reply = Value::Array(vec![
+            bulk("message"),
+            Value::Array(vec![bulk("a"), Value::Integer(1)]),
+            Value::Array(Vec::new()),
+        ]);
+        assert_eq!(
+            format_reply(&reply),
+            "1) \"message\"\n2) 1) \"a\"\n   2) (integer) 1\n3) (empty array)"
+        );
+
+        let map = Value::Map(vec![(bulk("field"), bulk("value"))]);
+        assert_eq!(format_reply(&map), "1# \"field\" => \"value\"");
+        assert_eq!(format_reply(&Value::Map(Vec::new())), "(empty hash)");
+
+        // Numbers are right-aligned once there are ten or more items
+        let long = Value::Array((0..10).map(Value::Integer).collect());
+        assert!(format_reply(&long).starts_with(" 1) (integer) 0\n"));
+        assert!(format_reply(&long).ends_with("\n10) (integer) 9"));
+    }
+
+    #[test]
+    fn test_history_expansion() {
+        let mut history = History::default();
+        history.push("SET key value");
+        history.push("GET key");
+
+        assert_eq!(history.expand("!1"), Some("SET key value".to_owned()));
+        assert_eq!(history.expand("!!"), Some("GET key".to_owned()));
+        assert_eq!(history.expand("PING"), Some("PING".to_owned()));
+        assert_eq!(history.expand("!0"), None);
+        assert_eq!(history.expand("!3"), None);
+        assert_eq!(history.expand("!x"), None);
+    }
+}
@@ -0,0 +1,87 @@
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::error::ClientError;
+use crate::parser::{RespHandler, Value};
+
+pub mod tests_client;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// A connection to a Redis server, this one or any other, with typed methods for the
+/// common commands and `command` for everything else. Requests are sent one at a
+/// time, each waiting for its reply.
+#[derive(Debug)]
+pub struct Client<S = TcpStream> {
+    server: RespHandler<S>,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let socket = TcpStream::connect(addr)
+            .await
+            .map_err(|err| ClientError::Resp(err.into()))?;
+        Ok(Self::new(socket))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    /// A client over an already connected stream.
+    pub fn new(stream: S) -> Self {
+        Self {
+            server: RespHandler::new(stream),
+        }
+    }
+
+    /// Sends any command and returns its reply as is, except that an error reply
+    /// becomes `ClientError::Reply`.
+    pub async fn command<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Result<Value> {
+        let command = Value::Array(
+            args.iter()
+                .map(|arg| Value::BulkString(Bytes::copy_from_slice(arg.as_ref())))
+                .collect(),
+        );
+        self.server.write_value(&command).await?;
+        match self.server.read_value().await? {
+            Some(Value::SimpleError(message)) => Err(ClientError::Reply(message)),
+            Some(reply) => Ok(reply),
+            None => Err(ClientError::Closed),
+        }
+    }
+
+    /// The value of a string key, None when it does not exist.
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Value::BulkString(value) => Ok(Some(value)),
+            Value::Null => Ok(None),
+            reply => Err(ClientError::UnexpectedReply(reply)),
+        }
+    }
+
+    pub async fn set(&mut self, key: &str, value: impl AsRef<[u8]>) -> Result<()> {
+        self.command(&[b"SET", key.as_bytes(), value.as_ref()])
+            .await?;
+        Ok(())
+    }
+
+    /// Removes keys and returns how many existed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        let mut args = vec!["DEL"];
+        args.extend(keys);
+        integer(self.command(&args).await?)
+    }
+
+    /// Sets a timeout in seconds. Returns false when the key does not exist.
+    pub async fn expire(&mut self, key: &str, seconds: u64) -> Result<bool> {
+        let seconds = seconds.to_string();
+        Ok(integer(self.command(&["EXPIRE", key, &seconds]).await?)? == 1)
+    }
+}
+
+fn integer(reply: Value) -> Result<i64> {
+    match reply {
+        Value::Integer(n) => Ok(n),
+        reply => Err(ClientError::UnexpectedReply(reply)),
+    }
+}
@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::server::{Server, ServerHandle};
+
+    async fn spawn_server(name: &str) -> ServerHandle {
+        let config = Config {
+            dir: std::env::temp_dir(),
+            dbfilename: format!("redis-rust-{}-{}.rdb", name, std::process::id()),
+            ..Config::new()
+        };
+        Server::builder()
+            .config(config)
+            .bind("127.0.0.1:0".parse().unwrap())
+            .spawn()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_typed_commands() -> Result<()> {
+        let server = spawn_server("client").await;
+        let mut client = Client::connect(server.local_addr()).await?;
+
+        assert_eq!(client.get("key").await?, None);
+        client.set("key", "value").await?;
+        assert_eq!(client.get("key").await?, Some(Bytes::from("value")));
+
+        assert!(client.expire("key", 100).await?);
+        assert!(!client.expire("missing", 100).await?);
+        assert_eq!(client.del(&["key", "missing"]).await?, 1);
+        assert_eq!(client.get("key").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_commands_and_errors() -> Result<()> {
+        let server = spawn_server("client-raw").await;
+        let mut client = Client::connect(server.local_addr()).await?;
+
+        assert_eq!(
+            client.command(&["RPUSH", "list", "a", "b"]).await?,
+            Value::Integer(2)
+        );
+        assert!(matches!(
+            client.get("list").await,
+            Err(ClientError::Reply(message)) if message.starts_with("WRONGTYPE")
+        ));
+        assert!(matches!(
+            client.command(&["NOPE"]).await,
+            Err(ClientError::Reply(_))
+        ));
+
+        // SHUTDOWN closes the connection without a reply
+        assert!(matches!(
+            client.command(&["SHUTDOWN", "NOSAVE"]).await,
+            Err(ClientError::Closed)
+        ));
+        assert!(client.get("list").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use tokio::sync::Notify;
+
+use crate::config::BufferLimit;
+
+pub mod tests_clients;
+
+/// Registry of the connected clients, for CLIENT LIST and CLIENT KILL.
+/// The lock is never held across an await, so a std lock is enough here.
+#[derive(Debug, Default)]
+pub struct Clients {
+    next_id: AtomicU64,
+    // Ordered by id, which is the order clients connected in
+    clients: Mutex<BTreeMap<u64, ClientInfo>>,
+}
+
+#[derive(Debug)]
+struct ClientInfo {
+    addr: SocketAddr,
+    name: Option<String>,
+    connected_at: Instant,
+    last_active: Instant,
+    // Database and name of the last command run
+    db: usize,
+    command: Option<&'static str>,
+    kill: Arc<Notify>,
+}
+
+/// A connection's entry in the registry. Dropping it removes the entry.
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    clients: Arc<Clients>,
+    kill: Arc<Notify>,
+}
+
+/// Bytes queued for a connection but not written yet, checked against the
+/// client-output-buffer-limit of its class. Whoever queues replies adds them, and the
+/// connection takes them off once they are written.
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    pending: AtomicU64,
+    // When the buffer went over the soft limit, cleared once it is back under it
+    soft_since: Mutex<Option<Instant>>,
+    overflowed: Notify,
+}
+
+/// Which clients CLIENT KILL closes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KillFilter {
+    Id(u64),
+    Addr(String),
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(self: &Arc<Self>, addr: SocketAddr) -> Client {
+        // Ids start at 1 like in Redis
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let kill = Arc::new(Notify::new());
+        let now = Instant::now();
+        self.clients.lock().unwrap().insert(
+            id,
+            ClientInfo {
+                addr,
+                name: None,
+                connected_at: now,
+                last_active: now,
+                db: 0,
+                command: None,
+                kill: Arc::clone(&kill),
+            },
+        );
+        Client {
+            id,
+            clients: Arc::clone(self),
+            kill,
+        }
+    }
+
+    /// How many clients are connected.
+    pub fn count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// One line per client in CLIENT LIST format.
+    pub fn list(&self) -> String {
+        let now = Instant::now();
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, info)| {
+                format!(
+                    "id={} addr={} name={} age={} idle={} db={} cmd={}\n",
+                    id,
+                    info.addr,
+                    info.name.as_deref().unwrap_or(""),
+                    now.duration_since(info.connected_at).as_secs(),
+                    now.duration_since(info.last_active).as_secs(),
+                    info.db,
+                    info.command.map_or("NULL".to_owned(), str::to_lowercase)
+                )
+            })
+            .collect()
+    }
+
+    /// Signals every matching client to disconnect. Returns how many matched.
+    pub fn kill(&self, filter: &KillFilter) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let matching = clients.iter().filter(|(id, info)| match filter {
+            KillFilter::Id(wanted) => *id == wanted,
+            KillFilter::Addr(addr) => info.addr.to_string() == *addr,
+        });
+        let mut killed = 0;
+        for (_, info) in matching {
+            info.kill.notify_one();
+            killed += 1;
+        }
+        killed
+    }
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `len` more queued bytes. Returns false once the buffer is over `limit`,
+    /// waking `overflowed` so the connection is closed.
+    pub fn push(&self, len: usize, limit: &BufferLimit) -> bool {
+        let pending = self.pending.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        let fits = self.fits(pending, limit);
+        if !fits {
+            self.overflowed.notify_one();
+        }
+        fits
+    }
+
+    /// Takes `len` written bytes off the buffer.
+    pub fn drain(&self, len: usize) {
+        self.pending.fetch_sub(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once a push went over the limit.
+    pub async fn overflowed(&self) {
+        self.overflowed.notified().await
+    }
+
+    fn fits(&self, pending: u64, limit: &BufferLimit) -> bool {
+        if limit.hard > 0 && pending > limit.hard {
+            return false;
+        }
+        let mut soft_since = self.soft_since.lock().unwrap();
+        if limit.soft == 0 || pending <= limit.soft {
+            *soft_since = None;
+            return true;
+        }
+        // Like Redis, the soft limit counts whole seconds
+        let since = soft_since.get_or_insert_with(Instant::now);
+        since.elapsed().as_secs() <= limit.soft_seconds
+    }
+}
+
+impl Client {
+    pub fn name(&self) -> Option<String> {
+        self.with_info(|info| info.name.clone()).flatten()
+    }
+
+    pub fn set_name(&self, name: Option<String>) {
+        self.with_info(|info| info.name = name);
+    }
+
+    /// Records a command the client is about to run.
+    pub fn record_command(&self, command: &'static str, db: usize) {
+        self.with_info(|info| {
+            info.command = Some(command);
+            info.db = db;
+            info.last_active = Instant::now();
+        });
+    }
+
+    /// Resolves once CLIENT KILL targets this client.
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+
+    fn with_info<T>(&self, f: impl FnOnce(&mut ClientInfo) -> T) -> Option<T> {
+        self.clients
+            .clients
+            .lock()
+            .unwrap()
+            .get_mut(&self.id)
+            .map(f)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.clients.clients.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_register_and_list() {
+        let clients = Arc::new(Clients::new());
+        let first = clients.register(addr(5000));
+        let second = clients.register(addr(5001));
+        assert_eq!((first.id, second.id), (1, 2));
+
+        first.set_name(Some("worker".to_owned()));
+        second.record_command("GET", 3);
+        assert_eq!(
+            clients.list(),
+            "id=1 addr=127.0.0.1:5000 name=worker age=0 idle=0 db=0 cmd=NULL\n\
+             id=2 addr=127.0.0.1:5001 name= age=0 idle=0 db=3 cmd=get\n"
+        );
+
+        // Dropping the handle unregisters the client
+        assert_eq!(clients.count(), 2);
+        drop(first);
+        assert!(clients.list().starts_with("id=2 "));
+        assert_eq!(clients.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_kill() {
+        let clients = Arc::new(Clients::new());
+        let first = clients.register(addr(5000));
+        let second = clients.register(addr(5001));
+
+        assert_eq!(clients.kill(&KillFilter::Id(7)), 0);
+        assert_eq!(
+            clients.kill(&KillFilter::Addr("127.0.0.1:5001".to_owned())),
+            1
+        );
+        tokio::time::timeout(Duration::from_secs(1), second.killed())
+            .await
+            .unwrap();
+
+        // Only the matching client is signalled
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), first.killed())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limits() {
+        let limit = BufferLimit {
+            hard: 100,
+            soft: 50,
+            soft_seconds: 60,
+        };
+        let buffer = OutputBuffer::new();
+        assert!(buffer.push(40, &limit));
+        // Over the soft limit, but not for long enough
+        assert!(buffer.push(40, &limit));
+        buffer.drain(70);
+        assert_eq!(buffer.pending(), 10);
+        assert!(!buffer.push(91, &limit));
+        tokio::time::timeout(Duration::from_secs(1), buffer.overflowed())
+            .await
+            .unwrap();
+
+        // Without limits anything goes
+        let buffer = OutputBuffer::new();
+        assert!(buffer.push(usize::MAX / 2, &BufferLimit::default()));
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod tests_clock;
+
+/// Where the server reads the wall-clock time from. Expiry times, TTLs, TIME and idle
+/// times all ask the clock on `ServerState`, which every database shares, so a test
+/// can swap it for a `MockClock` whatever runtime the server runs on.
+pub trait Clock: Debug + Send + Sync {
+    /// Microseconds since the Unix epoch.
+    fn now_micros(&self) -> u64;
+
+    fn now_millis(&self) -> u64 {
+        self.now_micros() / 1000
+    }
+}
+
+/// The operating system's clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// A clock that only moves when told to, so tests can let keys expire without
+/// sleeping.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    micros: AtomicU64,
+}
+
+impl MockClock {
+    /// A clock stopped at `millis` milliseconds since the Unix epoch.
+    pub fn new(millis: u64) -> Self {
+        Self {
+            micros: AtomicU64::new(millis * 1000),
+        }
+    }
+
+    /// A clock stopped at the current system time.
+    pub fn starting_now() -> Self {
+        Self {
+            micros: AtomicU64::new(SystemClock.now_micros()),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.micros
+            .fetch_add(by.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_micros(&self) -> u64 {
+        self.micros.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::storage::{DataType, Db, Entry};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mock_clock_moves_when_told() {
+        let clock = MockClock::new(5_000);
+        assert_eq!(clock.now_millis(), 5_000);
+        clock.advance(Duration::from_micros(1_500));
+        assert_eq!(clock.now_micros(), 5_001_500);
+        assert_eq!(clock.now_millis(), 5_001);
+        assert!(SystemClock.now_millis() > clock.now_millis());
+    }
+
+    #[test]
+    fn test_keys_expire_on_the_mock_clock() {
+        let clock = Arc::new(MockClock::starting_now());
+        let mut db = Db::with_stats(Arc::default(), clock.clone());
+        db.insert_entry(
+            "key".to_owned(),
+            Entry::new(DataType::String("value".into())),
+        );
+        db.set_expiry("key", Some(db.now_millis() + 1000));
+
+        clock.advance(Duration::from_millis(400));
+        assert_eq!(db.ttl_millis("key"), Some(Some(600)));
+        clock.advance(Duration::from_millis(600));
+        assert_eq!(db.get("key"), None);
+        assert_eq!(db.remove_expired(), 1);
+    }
+}
@@ -0,0 +1,388 @@
+use bytes::Bytes;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::parser::{UserCommand, Value};
+use crate::replication::random_id;
+
+pub mod tests_cluster;
+
+/// The number of hash slots keys are spread over.
+pub const SLOTS: usize = 16384;
+
+/// The slot of a key: CRC16 of the key modulo `SLOTS`. When the key holds a non-empty
+/// `{tag}`, only the tag is hashed, so related keys can be kept on one node.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|&byte| byte == b'{')
+        .and_then(|open| {
+            let tag = &key[open + 1..];
+            tag.iter()
+                .position(|&byte| byte == b'}')
+                .filter(|&close| close > 0)
+                .map(|close| &tag[..close])
+        })
+        .unwrap_or(key);
+    crc16(hashed) % SLOTS as u16
+}
+
+// CRC-16/XMODEM, the variant Redis Cluster uses
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The key arguments of a command, found from its key positions in the command table,
+/// or for the commands whose keys follow a keyword, by reading their options.
+pub fn command_keys(command: UserCommand, args: &[Value]) -> Vec<&Value> {
+    match command {
+        UserCommand::XRead | UserCommand::XReadGroup => return stream_keys(args),
+        UserCommand::Sort => return sort_keys(args),
+        _ => {}
+    }
+    let Some(spec) = command.spec() else {
+        return Vec::new();
+    };
+    if spec.first_key == 0 {
+        return Vec::new();
+    }
+    // Positions count the command name, which `args` does not hold
+    let last = if spec.last_key < 0 {
+        args.len() as i64 + 1 + spec.last_key
+    } else {
+        spec.last_key.min(args.len() as i64)
+    };
+    (spec.first_key..=last)
+        .step_by(spec.step.max(1) as usize)
+        .filter_map(|position| args.get(position as usize - 1))
+        .collect()
+}
+
+// XREAD and XREADGROUP name their streams after STREAMS, followed by as many IDs
+fn stream_keys(args: &[Value]) -> Vec<&Value> {
+    let mut index = 0;
+    while let Some(option) = args.get(index) {
+        index += match uppercase(option).as_deref() {
+            Some("STREAMS") => {
+                let streams = &args[index + 1..];
+                return streams[..streams.len() / 2].iter().collect();
+            }
+            Some("GROUP") => 3,
+            Some("COUNT" | "BLOCK") => 2,
+            Some("NOACK") => 1,
+            _ => break,
+        };
+    }
+    Vec::new()
+}
+
+// SORT reads its first argument and may STORE into another key
+fn sort_keys(args: &[Value]) -> Vec<&Value> {
+    let Some(key) = args.first() else {
+        return Vec::new();
+    };
+    let mut keys = vec![key];
+    let mut index = 1;
+    while let Some(option) = args.get(index) {
+        index += match uppercase(option).as_deref() {
+            Some("STORE") => {
+                keys.extend(args.get(index + 1));
+                2
+            }
+            Some("BY" | "GET") => 2,
+            Some("LIMIT") => 3,
+            Some("ASC" | "DESC" | "ALPHA") => 1,
+            _ => break,
+        };
+    }
+    keys
+}
+
+fn uppercase(value: &Value) -> Option<String> {
+    match value {
+        Value::BulkString(bytes) => std::str::from_utf8(bytes).ok().map(str::to_uppercase),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Node {
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// What CLUSTER SETSLOT does to a slot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetSlot {
+    // The slot now belongs to the node
+    Node(String),
+    // Keys of the slot are moving from here to the node
+    Migrating(String),
+    // Keys of the slot are moving from the node to here
+    Importing(String),
+    // Any migration of the slot is over
+    Stable,
+}
+
+/// This node's view of the cluster: the nodes it met and which of them serves each
+/// slot. Nothing is gossiped, so every node is told about the others with CLUSTER
+/// MEET and about slot owners with CLUSTER ADDSLOTS and SETSLOT. The lock is never
+/// held across an await.
+#[derive(Debug)]
+pub struct Cluster {
+    table: Mutex<Table>,
+}
+
+#[derive(Debug)]
+struct Table {
+    // This node comes first
+    nodes: Vec<Node>,
+    // Index into `nodes` of each slot's owner
+    owners: Vec<Option<usize>>,
+    migrating: HashMap<u16, usize>,
+    importing: HashMap<u16, usize>,
+}
+
+impl Table {
+    fn node_index(&self, id: &str) -> Result<usize, String> {
+        self.nodes
+            .iter()
+            .position(|node| node.id == id)
+            .ok_or_else(|| format!("ERR I don't know about node {}", id))
+    }
+
+    // The slots each node serves as inclusive ranges, in slot order
+    fn ranges(&self) -> Vec<(u16, u16, usize)> {
+        let mut ranges: Vec<(u16, u16, usize)> = Vec::new();
+        for (slot, owner) in self.owners.iter().enumerate() {
+            let Some(owner) = *owner else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((_, end, node)) if *node == owner && *end as usize + 1 == slot => {
+                    *end = slot as u16
+                }
+                _ => ranges.push((slot as u16, slot as u16, owner)),
+            }
+        }
+        ranges
+    }
+}
+
+impl Cluster {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            table: Mutex::new(Table {
+                nodes: vec![Node {
+                    id: random_id(),
+                    host: host.to_owned(),
+                    port,
+                }],
+                owners: vec![None; SLOTS],
+                migrating: HashMap::new(),
+                importing: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn myid(&self) -> String {
+        self.table.lock().unwrap().nodes[0].id.clone()
+    }
+
+    /// Adds a node, or updates the address of one already known.
+    pub fn meet(&self, node: Node) {
+        let mut table = self.table.lock().unwrap();
+        match table.nodes.iter_mut().find(|known| known.id == node.id) {
+            Some(known) => *known = node,
+            None => table.nodes.push(node),
+        }
+    }
+
+    /// Assigns unassigned slots to this node. Nothing changes when one is taken.
+    pub fn add_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut table = self.table.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| table.owners[slot as usize].is_some())
+        {
+            return Err(format!("ERR Slot {} is already busy", slot));
+        }
+        for &slot in slots {
+            table.owners[slot as usize] = Some(0);
+        }
+        Ok(())
+    }
+
+    /// Forgets who serves the slots. Nothing changes when one is unassigned.
+    pub fn del_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut table = self.table.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| table.owners[slot as usize].is_none())
+        {
+            return Err(format!("ERR Slot {} is already unassigned", slot));
+        }
+        for &slot in slots {
+            table.owners[slot as usize] = None;
+            table.migrating.remove(&slot);
+            table.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    pub fn set_slot(&self, slot: u16, action: SetSlot) -> Result<(), String> {
+        let mut table = self.table.lock().unwrap();
+        let owner = table.owners[slot as usize];
+        match action {
+            SetSlot::Node(id) => {
+                let node = table.node_index(&id)?;
+                table.owners[slot as usize] = Some(node);
+                table.migrating.remove(&slot);
+                table.importing.remove(&slot);
+            }
+            SetSlot::Migrating(id) => {
+                let node = table.node_index(&id)?;
+                if owner != Some(0) {
+                    return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+                }
+                table.migrating.insert(slot, node);
+            }
+            SetSlot::Importing(id) => {
+                let node = table.node_index(&id)?;
+                if owner == Some(0) {
+                    return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+                }
+                table.importing.insert(slot, node);
+            }
+            SetSlot::Stable => {
+                table.migrating.remove(&slot);
+                table.importing.remove(&slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// The error sending a client elsewhere for a command on `slot`, None when this node
+    /// serves it. A migrating slot still serves the keys that have not left, and an
+    /// importing one serves clients that sent ASKING.
+    pub fn redirect(&self, slot: u16, asking: bool, keys_present: bool) -> Option<Value> {
+        let table = self.table.lock().unwrap();
+        let error = match table.owners[slot as usize] {
+            None => "CLUSTERDOWN Hash slot not served".to_owned(),
+            Some(0) => match table.migrating.get(&slot) {
+                Some(&node) if !keys_present => {
+                    format!("ASK {} {}", slot, table.nodes[node].address())
+                }
+                _ => return None,
+            },
+            Some(_) if asking && table.importing.contains_key(&slot) => return None,
+            Some(owner) => format!("MOVED {} {}", slot, table.nodes[owner].address()),
+        };
+        Some(Value::SimpleError(error))
+    }
+
+    /// The CLUSTER INFO report.
+    pub fn info(&self) -> String {
+        let table = self.table.lock().unwrap();
+        let assigned = table.owners.iter().filter(|owner| owner.is_some()).count();
+        let mut serving: Vec<usize> = table.owners.iter().flatten().copied().collect();
+        serving.sort_unstable();
+        serving.dedup();
+        let state = if assigned == SLOTS { "ok" } else { "fail" };
+        format!(
+            "cluster_enabled:1\r\ncluster_state:{}\r\ncluster_slots_assigned:{}\r\ncluster_slots_ok:{}\r\ncluster_slots_pfail:0\r\ncluster_slots_fail:0\r\ncluster_known_nodes:{}\r\ncluster_size:{}\r\n",
+            state,
+            assigned,
+            assigned,
+            table.nodes.len(),
+            serving.len()
+        )
+    }
+
+    /// The CLUSTER SLOTS reply: each range of slots with the node serving it.
+    pub fn slots(&self) -> Value {
+        let table = self.table.lock().unwrap();
+        Value::Array(
+            table
+                .ranges()
+                .into_iter()
+                .map(|(start, end, owner)| {
+                    Value::Array(vec![
+                        Value::Integer(start.into()),
+                        Value::Integer(end.into()),
+                        node_entry(&table.nodes[owner]),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// The CLUSTER SHARDS reply: every node that serves slots, with its ranges.
+    pub fn shards(&self) -> Value {
+        let table = self.table.lock().unwrap();
+        let ranges = table.ranges();
+        Value::Array(
+            table
+                .nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, node)| {
+                    let slots: Vec<Value> = ranges
+                        .iter()
+                        .filter(|(_, _, owner)| *owner == index)
+                        .flat_map(|&(start, end, _)| {
+                            [Value::Integer(start.into()), Value::Integer(end.into())]
+                        })
+                        .collect();
+                    (!slots.is_empty()).then(|| {
+                        Value::Array(vec![
+                            bulk("slots"),
+                            Value::Array(slots),
+                            bulk("nodes"),
+                            Value::Array(vec![Value::Array(vec![
+                                bulk("id"),
+                                bulk(&node.id),
+                                bulk("port"),
+                                Value::Integer(node.port.into()),
+                                bulk("ip"),
+                                bulk(&node.host),
+                                bulk("endpoint"),
+                                bulk(&node.host),
+                                bulk("role"),
+                                bulk("master"),
+                                bulk("health"),
+                                bulk("online"),
+                            ])]),
+                        ])
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+fn bulk(value: &str) -> Value {
+    Value::BulkString(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+fn node_entry(node: &Node) -> Value {
+    Value::Array(vec![
+        bulk(&node.host),
+        Value::Integer(node.port.into()),
+        bulk(&node.id),
+    ])
+}
@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn bulk(parts: &[&str]) -> Vec<Value> {
+        parts
+            .iter()
+            .map(|part| Value::BulkString(part.to_string().into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_key_hash_slot() {
+        // Values from the Redis Cluster specification and CLUSTER KEYSLOT
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(key_hash_slot(b"bar"), 5061);
+        assert_eq!(key_hash_slot(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot(b""), 0);
+
+        // Only a non-empty tag is hashed
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), key_hash_slot(b"foo{}{bar}"));
+        assert_ne!(key_hash_slot(b"foo{}{bar}"), key_hash_slot(b"bar"));
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+        assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+    }
+
+    #[test]
+    fn test_command_keys() {
+        let args = bulk(&["a", "b"]);
+        assert_eq!(
+            command_keys(UserCommand::Mget, &args),
+            vec![&args[0], &args[1]]
+        );
+        let args = bulk(&["hash", "field", "value"]);
+        assert_eq!(command_keys(UserCommand::HSet, &args), vec![&args[0]]);
+
+        let args = bulk(&["x", "y", "z", "0"]);
+        assert_eq!(
+            command_keys(UserCommand::BLPop, &args),
+            vec![&args[0], &args[1], &args[2]]
+        );
+
+        // Keys found by keyword rather than position
+        let args = bulk(&["COUNT", "5", "BLOCK", "0", "STREAMS", "s1", "s2", "0", "$"]);
+        assert_eq!(
+            command_keys(UserCommand::XRead, &args),
+            vec![&args[5], &args[6]]
+        );
+        let args = bulk(&["GROUP", "g", "c", "NOACK", "STREAMS", "s1", ">"]);
+        assert_eq!(command_keys(UserCommand::XReadGroup, &args), vec![&args[5]]);
+        let args = bulk(&["list", "BY", "w_*", "LIMIT", "0", "5", "STORE", "dest"]);
+        assert_eq!(
+            command_keys(UserCommand::Sort, &args),
+            vec![&args[0], &args[7]]
+        );
+
+        assert!(command_keys(UserCommand::Ping, &[]).is_empty());
+        assert!(command_keys(UserCommand::Invalid, &bulk(&["key"])).is_empty());
+    }
+
+    #[test]
+    fn test_slots_and_redirects() {
+        let cluster = Cluster::new("127.0.0.1", 7000);
+        let other = Node {
+            id: "b".repeat(40),
+            host: "127.0.0.1".to_owned(),
+            port: 7001,
+        };
+        cluster.meet(other.clone());
+        let error = |message: &str| Some(Value::SimpleError(message.to_owned()));
+
+        assert_eq!(
+            cluster.redirect(0, false, true),
+            error("CLUSTERDOWN Hash slot not served")
+        );
+        assert_eq!(cluster.add_slots(&[0, 1, 2]), Ok(()));
+        assert_eq!(
+            cluster.add_slots(&[3, 2]),
+            Err("ERR Slot 2 is already busy".to_owned())
+        );
+        assert_eq!(cluster.redirect(1, false, true), None);
+
+        assert_eq!(cluster.set_slot(5, SetSlot::Node(other.id.clone())), Ok(()));
+        assert_eq!(
+            cluster.redirect(5, false, false),
+            error("MOVED 5 127.0.0.1:7001")
+        );
+        assert_eq!(
+            cluster.set_slot(5, SetSlot::Node("c".repeat(40))),
+            Err(format!("ERR I don't know about node {}", "c".repeat(40)))
+        );
+
+        // While slot 1 migrates, keys that already left are asked for elsewhere
+        assert_eq!(
+            cluster.set_slot(5, SetSlot::Migrating(other.id.clone())),
+            Err("ERR I'm not the owner of hash slot 5".to_owned())
+        );
+        assert_eq!(
+            cluster.set_slot(1, SetSlot::Migrating(other.id.clone())),
+            Ok(())
+        );
+        assert_eq!(cluster.redirect(1, false, true), None);
+        assert_eq!(
+            cluster.redirect(1, false, false),
+            error("ASK 1 127.0.0.1:7001")
+        );
+        assert_eq!(cluster.set_slot(1, SetSlot::Stable), Ok(()));
+        assert_eq!(cluster.redirect(1, false, false), None);
+
+        // An importing slot serves only clients that sent ASKING
+        assert_eq!(
+            cluster.set_slot(5, SetSlot::Importing(other.id.clone())),
+            Ok(())
+        );
+        assert_eq!(cluster.redirect(5, true, false), None);
+        assert_eq!(
+            cluster.redirect(5, false, false),
+            error("MOVED 5 127.0.0.1:7001")
+        );
+
+        assert_eq!(cluster.del_slots(&[0, 1]), Ok(()));
+        assert_eq!(
+            cluster.del_slots(&[0]),
+            Err("ERR Slot 0 is already unassigned".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_slots_reply() {
+        let cluster = Cluster::new("127.0.0.1", 7000);
+        cluster.add_slots(&[0, 1, 2, 10]).unwrap();
+        let id = Value::BulkString(cluster.myid().into());
+        let node = Value::Array(vec![
+            Value::BulkString("127.0.0.1".into()),
+            Value::Integer(7000),
+            id,
+        ]);
+        assert_eq!(
+            cluster.slots(),
+            Value::Array(vec![
+                Value::Array(vec![Value::Integer(0), Value::Integer(2), node.clone()]),
+                Value::Array(vec![Value::Integer(10), Value::Integer(10), node]),
+            ])
+        );
+        assert!(cluster.info().contains("cluster_state:fail\r\n"));
+        assert!(cluster.info().contains("cluster_slots_assigned:4\r\n"));
+        assert!(cluster.info().contains("cluster_size:1\r\n"));
+    }
+}
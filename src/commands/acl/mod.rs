@@ -0,0 +1,77 @@
+use crate::acl::{category_commands, CATEGORIES};
+use crate::commands::server::help_value;
+use crate::connection::unpack_bulk_string;
+use crate::error::{unknown_subcommand, CommandError, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+
+pub mod tests_acl;
+
+/// ACL WHOAMI | USERS | LIST | SETUSER name [rule ...] | DELUSER name [name ...] |
+/// GETUSER name | CAT [category], run on behalf of `user`.
+pub fn acl_value(args: &[Value], state: &ServerState, user: &str) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let args = args[1..]
+        .iter()
+        .map(|arg| unpack_bulk_string(arg.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let bulk = |text: &str| Value::BulkString(text.to_owned().into());
+
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    let response = match (subcommand.as_str(), args.as_slice()) {
+        ("HELP", []) => help_value(UserCommand::Acl),
+        ("WHOAMI", []) => bulk(user),
+        ("USERS", []) => Value::Array(
+            state
+                .acl
+                .usernames()
+                .iter()
+                .map(|name| bulk(name))
+                .collect(),
+        ),
+        ("LIST", []) => Value::Array(state.acl.list().iter().map(|line| bulk(line)).collect()),
+        ("SETUSER", [name, rules @ ..]) => match state.acl.set_user(name, rules) {
+            Ok(()) => Value::SimpleString("OK".to_owned()),
+            Err(err) => Value::SimpleError(err),
+        },
+        ("DELUSER", [_, ..]) => match state.acl.del_users(&args) {
+            Ok(deleted) => Value::Integer(deleted as i64),
+            Err(err) => Value::SimpleError(err),
+        },
+        ("GETUSER", [name]) => match state.acl.user(name) {
+            Some(found) => Value::Map(vec![
+                (
+                    bulk("flags"),
+                    Value::Array(found.flags().into_iter().map(bulk).collect()),
+                ),
+                (
+                    bulk("passwords"),
+                    Value::Array(found.passwords().iter().map(|hash| bulk(hash)).collect()),
+                ),
+                (bulk("commands"), bulk(&found.command_rules())),
+                (bulk("keys"), bulk(&found.key_rules())),
+            ]),
+            None => Value::Null,
+        },
+        ("CAT", []) => Value::Array(CATEGORIES.into_iter().map(bulk).collect()),
+        ("CAT", [category]) => match category_commands(category) {
+            Some(commands) => Value::Array(
+                commands
+                    .into_iter()
+                    .map(|name| bulk(&name.to_lowercase()))
+                    .collect(),
+            ),
+            None => Value::SimpleError(format!(
+                "ERR Unknown category '{}'",
+                category.to_lowercase()
+            )),
+        },
+        ("WHOAMI" | "USERS" | "LIST" | "SETUSER" | "DELUSER" | "GETUSER" | "CAT", _) => {
+            CommandError::WrongArity.into()
+        }
+        (other, _) => unknown_subcommand("ACL", other),
+    };
+    Ok(response)
+}
@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::testing::args;
+
+    fn bulk(text: &str) -> Value {
+        Value::BulkString(text.to_owned().into())
+    }
+
+    #[test]
+    fn test_manage_users() -> Result<()> {
+        let state = ServerState::new(Config::new());
+
+        assert_eq!(
+            acl_value(&args(&["WHOAMI"]), &state, "default")?,
+            bulk("default")
+        );
+        assert_eq!(
+            acl_value(
+                &args(&["SETUSER", "reader", "on", "nopass", "~cache:*", "+@read"]),
+                &state,
+                "default"
+            )?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            acl_value(&args(&["users"]), &state, "default")?,
+            Value::Array(vec![bulk("default"), bulk("reader")])
+        );
+        assert_eq!(
+            acl_value(&args(&["GETUSER", "reader"]), &state, "default")?,
+            Value::Map(vec![
+                (
+                    bulk("flags"),
+                    Value::Array(vec![bulk("on"), bulk("nopass")])
+                ),
+                (bulk("passwords"), Value::Array(vec![])),
+                (bulk("commands"), bulk("+@read")),
+                (bulk("keys"), bulk("~cache:*")),
+            ])
+        );
+        assert_eq!(
+            acl_value(&args(&["LIST"]), &state, "default")?,
+            Value::Array(vec![
+                bulk("user default on nopass ~* +@all"),
+                bulk("user reader on nopass ~cache:* +@read"),
+            ])
+        );
+
+        // An invalid rule leaves the user as it was
+        assert_eq!(
+            acl_value(
+                &args(&["SETUSER", "reader", "+@write", "bogus"]),
+                &state,
+                "default"
+            )?,
+            Value::SimpleError(
+                "ERR Error in ACL SETUSER modifier 'bogus': Syntax error".to_owned()
+            )
+        );
+        assert_eq!(state.acl.user("reader").unwrap().command_rules(), "+@read");
+
+        assert_eq!(
+            acl_value(&args(&["DELUSER", "default"]), &state, "default")?,
+            Value::SimpleError("ERR The 'default' user cannot be removed".to_owned())
+        );
+        assert_eq!(
+            acl_value(&args(&["DELUSER", "reader", "nobody"]), &state, "default")?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            acl_value(&args(&["GETUSER", "reader"]), &state, "default")?,
+            Value::Null
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_categories() -> Result<()> {
+        let state = ServerState::new(Config::new());
+
+        let Value::Array(categories) = acl_value(&args(&["CAT"]), &state, "default")? else {
+            panic!("ACL CAT did not reply with an array");
+        };
+        assert!(categories.contains(&bulk("read")));
+
+        let Value::Array(commands) = acl_value(&args(&["CAT", "pubsub"]), &state, "default")?
+        else {
+            panic!("ACL CAT pubsub did not reply with an array");
+        };
+        assert!(commands.contains(&bulk("publish")));
+        assert!(!commands.contains(&bulk("get")));
+
+        assert_eq!(
+            acl_value(&args(&["CAT", "nope"]), &state, "default")?,
+            Value::SimpleError("ERR Unknown category 'nope'".to_owned())
+        );
+        assert_eq!(
+            acl_value(&args(&["SETUSER"]), &state, "default")?,
+            CommandError::WrongArity.into()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use bytes::{Bytes, BytesMut};
+use std::sync::Arc;
+
+use crate::commands::zset::normalize_range;
+use crate::connection::{integer_arg, unpack_bulk_string, wrong_type};
+use crate::error::{CommandError, Result};
+use crate::parser::Value;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::string::StringValue;
+use crate::storage::{DataType, Entry};
+
+pub mod tests_bitmap;
+
+// Highest bit SETBIT may address, keeping strings within 512MB like Redis
+const MAX_BIT_OFFSET: i64 = (1 << 32) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+// Looks up a string for the bit commands, which treat a missing key as empty; the
+// error is the WRONGTYPE reply
+fn read_bytes(instance: &impl Storage, key: &str) -> std::result::Result<Bytes, Value> {
+    match instance.get(key) {
+        Some(DataType::String(value)) => Ok(value.to_bytes()),
+        Some(_) => Err(wrong_type()),
+        None => Ok(Bytes::new()),
+    }
+}
+
+fn bit_offset(offset: &Value) -> Option<usize> {
+    integer_arg(offset)
+        .filter(|offset| (0..=MAX_BIT_OFFSET).contains(offset))
+        .map(|offset| offset as usize)
+}
+
+// Bits are numbered from the most significant bit of the first byte
+fn bit_at(bytes: &[u8], offset: usize) -> bool {
+    bytes
+        .get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+fn parse_bit(bit: &Value) -> Option<bool> {
+    match bit {
+        Value::BulkString(bit) if bit.as_ref() == b"0" => Some(false),
+        Value::BulkString(bit) if bit.as_ref() == b"1" => Some(true),
+        _ => None,
+    }
+}
+
+/// SETBIT key offset 0|1: sets one bit, growing the string with zero bytes as needed,
+/// and replies with the bit's previous value.
+pub async fn setbit_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some(offset) = bit_offset(&args[1]) else {
+        return Ok(Value::SimpleError(
+            "ERR bit offset is not an integer or out of range".to_owned(),
+        ));
+    };
+    let Some(bit) = parse_bit(&args[2]) else {
+        return Ok(Value::SimpleError(
+            "ERR bit is not an integer or out of range".to_owned(),
+        ));
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    let mut value = match read_bytes(&instance, &key) {
+        Ok(value) => BytesMut::from(&value[..]),
+        Err(reply) => return Ok(reply),
+    };
+    if value.len() <= offset / 8 {
+        value.resize(offset / 8 + 1, 0);
+    }
+    let previous = bit_at(&value, offset);
+    let mask = 0x80 >> (offset % 8);
+    if bit {
+        value[offset / 8] |= mask;
+    } else {
+        value[offset / 8] &= !mask;
+    }
+
+    // Changing a bit keeps the TTL of an existing key
+    let value = DataType::String(StringValue::Raw(value.freeze()));
+    match instance.get_entry_mut(&key) {
+        Some(entry) => entry.value = value,
+        None => {
+            instance.insert_entry(key, Entry::new(value));
+        }
+    }
+
+    Ok(Value::Integer(previous as i64))
+}
+
+pub async fn getbit_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some(offset) = bit_offset(&args[1]) else {
+        return Ok(Value::SimpleError(
+            "ERR bit offset is not an integer or out of range".to_owned(),
+        ));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match read_bytes(&instance, &key) {
+        Ok(value) => Ok(Value::Integer(bit_at(&value, offset) as i64)),
+        Err(reply) => Ok(reply),
+    }
+}
+
+/// The start and end of BITCOUNT and BITPOS, and whether they count bits rather
+/// than bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: Option<i64>,
+    pub bits: bool,
+}
+
+impl BitRange {
+    /// Parses `start [end [BYTE|BIT]]`; an empty slice is the whole string.
+    pub fn parse(args: &[Value]) -> std::result::Result<Self, CommandError> {
+        let mut range = BitRange {
+            start: 0,
+            end: None,
+            bits: false,
+        };
+        if let Some(start) = args.first() {
+            range.start = integer_arg(start).ok_or(CommandError::NotInteger)?;
+        }
+        if let Some(end) = args.get(1) {
+            range.end = Some(integer_arg(end).ok_or(CommandError::NotInteger)?);
+        }
+        match args.get(2) {
+            None => {}
+            Some(Value::BulkString(unit)) if unit.eq_ignore_ascii_case(b"BYTE") => {}
+            Some(Value::BulkString(unit)) if unit.eq_ignore_ascii_case(b"BIT") => range.bits = true,
+            Some(_) => return Err(CommandError::Syntax),
+        }
+        if args.len() > 3 {
+            return Err(CommandError::Syntax);
+        }
+        Ok(range)
+    }
+
+    // The inclusive bit offsets covered in a string of `len` bytes, None when empty
+    fn resolve(&self, len: usize) -> Option<(usize, usize)> {
+        if self.bits {
+            normalize_range(self.start, self.end.unwrap_or(-1), len * 8)
+        } else {
+            let (start, end) = normalize_range(self.start, self.end.unwrap_or(-1), len)?;
+            Some((start * 8, end * 8 + 7))
+        }
+    }
+}
+
+/// BITCOUNT key [start end [BYTE|BIT]]: the number of set bits, in the whole string or
+/// a range of it where negative offsets count from the end.
+pub async fn bitcount_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+    // A start without an end is not allowed here, unlike BITPOS
+    if args.len() == 2 {
+        return Ok(CommandError::Syntax.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let range = match BitRange::parse(&args[1..]) {
+        Ok(range) => range,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let value = match read_bytes(&instance, &key) {
+        Ok(value) => value,
+        Err(reply) => return Ok(reply),
+    };
+    let count = match range.resolve(value.len()) {
+        Some((start, end)) if !range.bits => value[start / 8..=end / 8]
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum(),
+        Some((start, end)) => (start..=end).filter(|&bit| bit_at(&value, bit)).count(),
+        None => 0,
+    };
+    Ok(Value::Integer(count as i64))
+}
+
+/// BITOP AND|OR|XOR|NOT destkey key [key ...]: combines the strings byte by byte into
+/// destkey, padding shorter ones with zero bytes, and replies with the result's
+/// length. An empty result deletes destkey.
+pub async fn bitop_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let operation = match unpack_bulk_string(args[0].clone())?
+        .to_ascii_uppercase()
+        .as_str()
+    {
+        "AND" => BitOperation::And,
+        "OR" => BitOperation::Or,
+        "XOR" => BitOperation::Xor,
+        "NOT" => BitOperation::Not,
+        _ => return Ok(CommandError::Syntax.into()),
+    };
+    if operation == BitOperation::Not && args.len() != 3 {
+        return Ok(Value::SimpleError(
+            "ERR BITOP NOT must be called with a single source key.".to_owned(),
+        ));
+    }
+    let keys = args[1..]
+        .iter()
+        .map(|key| unpack_bulk_string(key.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let (destination, sources) = (&keys[0], &keys[1..]);
+
+    let mut instance = db_instance.write_keys(&keys).await;
+    let mut values = Vec::with_capacity(sources.len());
+    for key in sources {
+        match read_bytes(&instance, key) {
+            Ok(value) => values.push(value),
+            Err(reply) => return Ok(reply),
+        }
+    }
+
+    let length = values.iter().map(Bytes::len).max().unwrap_or(0);
+    let result: BytesMut = (0..length)
+        .map(|index| {
+            let mut bytes = values
+                .iter()
+                .map(|value| value.get(index).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match operation {
+                BitOperation::And => bytes.fold(first, |acc, byte| acc & byte),
+                BitOperation::Or => bytes.fold(first, |acc, byte| acc | byte),
+                BitOperation::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                BitOperation::Not => !first,
+            }
+        })
+        .collect();
+
+    if result.is_empty() {
+        instance.remove(destination);
+    } else {
+        instance.insert_entry(
+            destination.clone(),
+            Entry::new(DataType::String(StringValue::Raw(result.freeze()))),
+        );
+    }
+    Ok(Value::Integer(length as i64))
+}
+
+/// BITPOS key 0|1 [start [end [BYTE|BIT]]]: the offset of the first bit with the given
+/// value, or -1. Looking for a 0 without an end treats the string as followed by
+/// zero bytes, so a string of all ones answers with the bit just past its end.
+pub async fn bitpos_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some(bit) = parse_bit(&args[1]) else {
+        return Ok(Value::SimpleError(
+            "ERR The bit argument must be 1 or 0.".to_owned(),
+        ));
+    };
+    let range = match BitRange::parse(&args[2..]) {
+        Ok(range) => range,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let value = match read_bytes(&instance, &key) {
+        Ok(value) => value,
+        Err(reply) => return Ok(reply),
+    };
+    if value.is_empty() {
+        return Ok(Value::Integer(if bit { -1 } else { 0 }));
+    }
+
+    let Some((start, end)) = range.resolve(value.len()) else {
+        return Ok(Value::Integer(-1));
+    };
+    let position = match (start..=end).find(|&offset| bit_at(&value, offset) == bit) {
+        Some(offset) => offset as i64,
+        None if !bit && range.end.is_none() => (value.len() * 8) as i64,
+        None => -1,
+    };
+    Ok(Value::Integer(position))
+}
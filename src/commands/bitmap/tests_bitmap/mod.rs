@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::testing::args;
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    async fn string(db: &Arc<ShardedDb>, key: &str, value: &[u8]) {
+        db.write().await.insert_entry(
+            key.to_owned(),
+            Entry::new(DataType::String(Bytes::copy_from_slice(value).into())),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_setbit_and_getbit() -> Result<()> {
+        let db = db();
+
+        assert_eq!(
+            setbit_value(&args(&["bits", "7", "1"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            setbit_value(&args(&["bits", "7", "0"]), &db).await?,
+            Value::Integer(1)
+        );
+        setbit_value(&args(&["bits", "1", "1"]), &db).await?;
+        setbit_value(&args(&["bits", "18", "1"]), &db).await?;
+        assert_eq!(
+            db.read().await.get("bits"),
+            Some(&DataType::String(
+                Bytes::from_static(&[0x40, 0x00, 0x20]).into()
+            ))
+        );
+
+        assert_eq!(
+            getbit_value(&args(&["bits", "1"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            getbit_value(&args(&["bits", "1000"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            setbit_value(&args(&["bits", "-1", "1"]), &db).await?,
+            Value::SimpleError("ERR bit offset is not an integer or out of range".to_owned())
+        );
+        assert_eq!(
+            setbit_value(&args(&["bits", "0", "2"]), &db).await?,
+            Value::SimpleError("ERR bit is not an integer or out of range".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bitcount_ranges() -> Result<()> {
+        let db = db();
+        string(&db, "key", b"foobar").await;
+
+        assert_eq!(
+            bitcount_value(&args(&["key"]), &db).await?,
+            Value::Integer(26)
+        );
+        assert_eq!(
+            bitcount_value(&args(&["key", "1", "1"]), &db).await?,
+            Value::Integer(6)
+        );
+        assert_eq!(
+            bitcount_value(&args(&["key", "-2", "-1"]), &db).await?,
+            Value::Integer(7)
+        );
+        assert_eq!(
+            bitcount_value(&args(&["key", "5", "30", "BIT"]), &db).await?,
+            Value::Integer(17)
+        );
+        assert_eq!(
+            bitcount_value(&args(&["key", "1"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            bitcount_value(&args(&["missing"]), &db).await?,
+            Value::Integer(0)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bitop() -> Result<()> {
+        let db = db();
+        string(&db, "a", &[0b1100, 0xff]).await;
+        string(&db, "b", &[0b1010]).await;
+
+        assert_eq!(
+            bitop_value(&args(&["AND", "dest", "a", "b"]), &db).await?,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            db.read().await.get("dest"),
+            Some(&DataType::String(Bytes::from_static(&[0b1000, 0]).into()))
+        );
+        bitop_value(&args(&["or", "dest", "a", "b"]), &db).await?;
+        assert_eq!(
+            db.read().await.get("dest"),
+            Some(&DataType::String(
+                Bytes::from_static(&[0b1110, 0xff]).into()
+            ))
+        );
+        bitop_value(&args(&["XOR", "dest", "a", "b"]), &db).await?;
+        assert_eq!(
+            db.read().await.get("dest"),
+            Some(&DataType::String(
+                Bytes::from_static(&[0b0110, 0xff]).into()
+            ))
+        );
+        bitop_value(&args(&["NOT", "dest", "b"]), &db).await?;
+        assert_eq!(
+            db.read().await.get("dest"),
+            Some(&DataType::String(Bytes::from_static(&[0b1111_0101]).into()))
+        );
+
+        assert_eq!(
+            bitop_value(&args(&["NOT", "dest", "a", "b"]), &db).await?,
+            Value::SimpleError("ERR BITOP NOT must be called with a single source key.".to_owned())
+        );
+        assert_eq!(
+            bitop_value(&args(&["AND", "dest", "missing"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert!(db.read().await.get("dest").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bitpos() -> Result<()> {
+        let db = db();
+        string(&db, "key", &[0xff, 0xf0, 0x00]).await;
+        string(&db, "ones", &[0xff, 0xff]).await;
+
+        assert_eq!(
+            bitpos_value(&args(&["key", "0"]), &db).await?,
+            Value::Integer(12)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["key", "1", "1"]), &db).await?,
+            Value::Integer(8)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["key", "1", "2", "-1"]), &db).await?,
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["key", "1", "3", "15", "BIT"]), &db).await?,
+            Value::Integer(3)
+        );
+
+        // Without an end the string is treated as followed by zero bits
+        assert_eq!(
+            bitpos_value(&args(&["ones", "0"]), &db).await?,
+            Value::Integer(16)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["ones", "0", "0", "-1"]), &db).await?,
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["missing", "0"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            bitpos_value(&args(&["key", "2"]), &db).await?,
+            Value::SimpleError("ERR The bit argument must be 1 or 0.".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::clients::{Client, KillFilter};
+use crate::commands::server::help_value;
+use crate::connection::unpack_bulk_string;
+use crate::error::{unknown_subcommand, CommandError, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+
+pub mod tests_client;
+
+/// CLIENT ID | GETNAME | SETNAME name | LIST | KILL addr | KILL ID id | KILL ADDR addr,
+/// run on behalf of `client`.
+pub fn client_value(args: &[Value], state: &ServerState, client: &Client) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let args = args[1..]
+        .iter()
+        .map(|arg| unpack_bulk_string(arg.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    let response = match (subcommand.as_str(), args.as_slice()) {
+        ("HELP", []) => help_value(UserCommand::Client),
+        ("ID", []) => Value::Integer(client.id as i64),
+        ("GETNAME", []) => client
+            .name()
+            .map_or(Value::Null, |name| Value::BulkString(name.into())),
+        ("SETNAME", [name]) => {
+            if name.chars().any(|c| !c.is_ascii_graphic()) {
+                return Ok(Value::SimpleError(
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                        .to_owned(),
+                ));
+            }
+            // An empty name removes it
+            client.set_name((!name.is_empty()).then(|| name.clone()));
+            Value::SimpleString("OK".to_owned())
+        }
+        ("LIST", []) => Value::BulkString(state.clients.list().into()),
+        // The old form takes just an address and fails when nobody matched
+        ("KILL", [addr]) => match state.clients.kill(&KillFilter::Addr(addr.clone())) {
+            0 => Value::SimpleError("ERR No such client".to_owned()),
+            _ => Value::SimpleString("OK".to_owned()),
+        },
+        ("KILL", [filter, value]) => {
+            let filter = match filter.to_uppercase().as_str() {
+                "ID" => match value.parse() {
+                    Ok(id) => KillFilter::Id(id),
+                    Err(_) => {
+                        return Ok(Value::SimpleError(
+                            "ERR client-id should be greater than 0".to_owned(),
+                        ))
+                    }
+                },
+                "ADDR" => KillFilter::Addr(value.clone()),
+                _ => return Ok(CommandError::Syntax.into()),
+            };
+            Value::Integer(state.clients.kill(&filter) as i64)
+        }
+        ("ID" | "GETNAME" | "SETNAME" | "LIST" | "KILL", _) => CommandError::WrongArity.into(),
+        (other, _) => unknown_subcommand("CLIENT", other),
+    };
+    Ok(response)
+}
@@ -0,0 +1,94 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::testing::args;
+    use std::net::SocketAddr;
+
+    fn client(state: &ServerState) -> Client {
+        state
+            .clients
+            .register(SocketAddr::from(([127, 0, 0, 1], 5000)))
+    }
+
+    #[test]
+    fn test_id_and_names() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let client = client(&state);
+
+        assert_eq!(
+            client_value(&args(&["ID"]), &state, &client)?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            client_value(&args(&["getname"]), &state, &client)?,
+            Value::Null
+        );
+        assert_eq!(
+            client_value(&args(&["SETNAME", "worker"]), &state, &client)?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            client_value(&args(&["GETNAME"]), &state, &client)?,
+            Value::BulkString("worker".into())
+        );
+        assert_eq!(
+            client_value(&args(&["SETNAME", "two words"]), &state, &client)?,
+            Value::SimpleError(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_owned()
+            )
+        );
+
+        // An empty name clears it
+        client_value(&args(&["SETNAME", ""]), &state, &client)?;
+        assert_eq!(
+            client_value(&args(&["GETNAME"]), &state, &client)?,
+            Value::Null
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_kill_forms() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let client = client(&state);
+
+        assert_eq!(
+            client_value(&args(&["KILL", "127.0.0.1:5000"]), &state, &client)?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            client_value(&args(&["KILL", "127.0.0.1:1"]), &state, &client)?,
+            Value::SimpleError("ERR No such client".to_owned())
+        );
+        assert_eq!(
+            client_value(&args(&["KILL", "ID", "1"]), &state, &client)?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            client_value(&args(&["KILL", "addr", "127.0.0.1:1"]), &state, &client)?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            client_value(&args(&["KILL", "USER", "default"]), &state, &client)?,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_subcommand() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let client = client(&state);
+        assert_eq!(
+            client_value(&args(&["PAUSE", "10"]), &state, &client)?,
+            Value::SimpleError("ERR unknown subcommand 'pause'. Try CLIENT HELP.".to_owned())
+        );
+        assert_eq!(
+            client_value(&args(&["LIST", "extra"]), &state, &client)?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        Ok(())
+    }
+}
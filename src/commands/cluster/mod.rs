@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::cluster::{key_hash_slot, Node, SetSlot, SLOTS};
+use crate::commands::server::help_value;
+use crate::connection::{integer_arg, unpack_bulk_string};
+use crate::error::{unknown_subcommand, CommandError, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+use crate::storage::sharded::Storage;
+
+pub mod tests_cluster;
+
+/// CLUSTER INFO | MYID | KEYSLOT key | SLOTS | SHARDS | ADDSLOTS slot [slot ...] |
+/// ADDSLOTSRANGE start end [start end ...] | DELSLOTS slot [slot ...] | MEET host port |
+/// SETSLOT slot NODE id | MIGRATING id | IMPORTING id | STABLE | COUNTKEYSINSLOT slot |
+/// GETKEYSINSLOT slot count.
+pub async fn cluster_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let Some(cluster) = state.cluster.as_ref() else {
+        return Ok(Value::SimpleError(
+            "ERR This instance has cluster support disabled".to_owned(),
+        ));
+    };
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    let args = &args[1..];
+    let ok = Value::SimpleString("OK".to_owned());
+
+    let response = match (subcommand.as_str(), args) {
+        ("HELP", []) => help_value(UserCommand::Cluster),
+        ("INFO", []) => Value::BulkString(cluster.info().into()),
+        ("MYID", []) => Value::BulkString(cluster.myid().into()),
+        ("KEYSLOT", [key]) => {
+            let key = unpack_bulk_string(key.clone())?;
+            Value::Integer(key_hash_slot(key.as_bytes()).into())
+        }
+        ("SLOTS", []) => cluster.slots(),
+        ("SHARDS", []) => cluster.shards(),
+        ("ADDSLOTS" | "DELSLOTS", [_, ..]) => {
+            let Some(slots) = args.iter().map(slot_arg).collect::<Option<Vec<_>>>() else {
+                return Ok(invalid_slot());
+            };
+            let result = match subcommand.as_str() {
+                "ADDSLOTS" => cluster.add_slots(&slots),
+                _ => cluster.del_slots(&slots),
+            };
+            result.map_or_else(Value::SimpleError, |_| ok)
+        }
+        ("ADDSLOTSRANGE", [_, _, ..]) if args.len().is_multiple_of(2) => {
+            let mut slots = Vec::new();
+            for range in args.chunks(2) {
+                let (Some(start), Some(end)) = (slot_arg(&range[0]), slot_arg(&range[1])) else {
+                    return Ok(invalid_slot());
+                };
+                if start > end {
+                    return Ok(Value::SimpleError(format!(
+                        "ERR start slot number {} is greater than end slot number {}",
+                        start, end
+                    )));
+                }
+                slots.extend(start..=end);
+            }
+            cluster
+                .add_slots(&slots)
+                .map_or_else(Value::SimpleError, |_| ok)
+        }
+        ("MEET", [host, port]) => {
+            let host = unpack_bulk_string(host.clone())?;
+            let Some(port) = integer_arg(port).and_then(|port| u16::try_from(port).ok()) else {
+                return Ok(Value::SimpleError(format!(
+                    "ERR Invalid base port specified: {}",
+                    unpack_bulk_string(port.clone())?
+                )));
+            };
+            match node_id(&host, port).await {
+                Some(id) => {
+                    cluster.meet(Node { id, host, port });
+                    ok
+                }
+                None => Value::SimpleError(format!(
+                    "ERR Invalid node address specified: {}:{}",
+                    host, port
+                )),
+            }
+        }
+        ("SETSLOT", [slot, action, rest @ ..]) => {
+            let Some(slot) = slot_arg(slot) else {
+                return Ok(invalid_slot());
+            };
+            let action = unpack_bulk_string(action.clone())?.to_uppercase();
+            let action = match (action.as_str(), rest) {
+                ("STABLE", []) => SetSlot::Stable,
+                ("NODE" | "MIGRATING" | "IMPORTING", [id]) => {
+                    let id = unpack_bulk_string(id.clone())?;
+                    match action.as_str() {
+                        "NODE" => SetSlot::Node(id),
+                        "MIGRATING" => SetSlot::Migrating(id),
+                        _ => SetSlot::Importing(id),
+                    }
+                }
+                _ => return Ok(CommandError::Syntax.into()),
+            };
+            cluster
+                .set_slot(slot, action)
+                .map_or_else(Value::SimpleError, |_| ok)
+        }
+        ("COUNTKEYSINSLOT", [slot]) => {
+            let Some(slot) = slot_arg(slot) else {
+                return Ok(invalid_slot());
+            };
+            let instance = state.databases[0].read().await;
+            let count = instance
+                .keys()
+                .filter(|key| key_hash_slot(key.as_bytes()) == slot)
+                .count();
+            Value::Integer(count as i64)
+        }
+        ("GETKEYSINSLOT", [slot, count]) => {
+            let Some(slot) = slot_arg(slot) else {
+                return Ok(invalid_slot());
+            };
+            let Some(count) = integer_arg(count).and_then(|count| usize::try_from(count).ok())
+            else {
+                return Ok(Value::SimpleError("ERR Invalid number of keys".to_owned()));
+            };
+            let instance = state.databases[0].read().await;
+            let mut keys: Vec<&String> = instance
+                .keys()
+                .filter(|key| key_hash_slot(key.as_bytes()) == slot)
+                .collect();
+            keys.sort();
+            Value::Array(
+                keys.into_iter()
+                    .take(count)
+                    .map(|key| Value::BulkString(key.clone().into()))
+                    .collect(),
+            )
+        }
+        (
+            "INFO" | "MYID" | "KEYSLOT" | "SLOTS" | "SHARDS" | "ADDSLOTS" | "ADDSLOTSRANGE"
+            | "DELSLOTS" | "MEET" | "SETSLOT" | "COUNTKEYSINSLOT" | "GETKEYSINSLOT",
+            _,
+        ) => CommandError::WrongArity.into(),
+        (other, _) => unknown_subcommand("CLUSTER", other),
+    };
+    Ok(response)
+}
+
+fn slot_arg(value: &Value) -> Option<u16> {
+    integer_arg(value)
+        .and_then(|slot| u16::try_from(slot).ok())
+        .filter(|slot| (*slot as usize) < SLOTS)
+}
+
+fn invalid_slot() -> Value {
+    Value::SimpleError("ERR Invalid or out of range slot".to_owned())
+}
+
+// Asks the node at host:port for its id, None when it cannot be reached or is not in
+// cluster mode
+async fn node_id(host: &str, port: u16) -> Option<String> {
+    let timeout = Duration::from_secs(1);
+    let mut node = tokio::time::timeout(timeout, Client::connect(format!("{}:{}", host, port)))
+        .await
+        .ok()?
+        .ok()?;
+    match tokio::time::timeout(timeout, node.command(&["CLUSTER", "MYID"])).await {
+        Ok(Ok(Value::BulkString(id))) => String::from_utf8(id.to_vec()).ok(),
+        _ => None,
+    }
+}
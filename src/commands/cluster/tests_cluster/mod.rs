@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::storage::sharded::StorageMut;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn cluster_state() -> ServerState {
+        let mut config = Config::new();
+        config.cluster_enabled = true;
+        ServerState::new(config)
+    }
+
+    #[tokio::test]
+    async fn test_cluster_disabled() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        assert_eq!(
+            cluster_value(&args(&["INFO"]), &state).await?,
+            Value::SimpleError("ERR This instance has cluster support disabled".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_slot_subcommands() -> Result<()> {
+        let state = cluster_state();
+        let ok = Value::SimpleString("OK".to_owned());
+
+        assert_eq!(
+            cluster_value(&args(&["KEYSLOT", "foo"]), &state).await?,
+            Value::Integer(12182)
+        );
+        assert_eq!(
+            cluster_value(&args(&["ADDSLOTSRANGE", "0", "8191"]), &state).await?,
+            ok
+        );
+        assert_eq!(
+            cluster_value(&args(&["ADDSLOTS", "8192", "16383"]), &state).await?,
+            ok
+        );
+        assert_eq!(
+            cluster_value(&args(&["ADDSLOTS", "16384"]), &state).await?,
+            Value::SimpleError("ERR Invalid or out of range slot".to_owned())
+        );
+        assert_eq!(
+            cluster_value(&args(&["ADDSLOTSRANGE", "9", "3"]), &state).await?,
+            Value::SimpleError(
+                "ERR start slot number 9 is greater than end slot number 3".to_owned()
+            )
+        );
+        assert_eq!(
+            cluster_value(&args(&["DELSLOTS", "100"]), &state).await?,
+            ok
+        );
+        let Value::Array(ranges) = cluster_value(&args(&["SLOTS"]), &state).await? else {
+            panic!("CLUSTER SLOTS should reply with an array");
+        };
+        assert_eq!(ranges.len(), 3);
+
+        assert_eq!(
+            cluster_value(&args(&["SETSLOT", "1", "NODE", "unknown"]), &state).await?,
+            Value::SimpleError("ERR I don't know about node unknown".to_owned())
+        );
+        assert_eq!(
+            cluster_value(&args(&["SETSLOT", "1", "BOGUS"]), &state).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            cluster_value(&args(&["NOPE"]), &state).await?,
+            Value::SimpleError("ERR unknown subcommand 'nope'. Try CLUSTER HELP.".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keys_in_slot() -> Result<()> {
+        let state = cluster_state();
+        {
+            let mut instance = state.databases[0].write().await;
+            for key in ["{a}2", "{a}1", "b"] {
+                instance.insert_entry(key.to_owned(), Entry::new(DataType::String("v".into())));
+            }
+        }
+        let slot = key_hash_slot(b"a").to_string();
+
+        assert_eq!(
+            cluster_value(&args(&["COUNTKEYSINSLOT", &slot]), &state).await?,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            cluster_value(&args(&["GETKEYSINSLOT", &slot, "1"]), &state).await?,
+            Value::Array(vec![Value::BulkString("{a}1".into())])
+        );
+        Ok(())
+    }
+}
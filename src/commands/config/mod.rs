@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::commands::server::help_value;
+use crate::config::{Config, PARAMETERS};
+use crate::connection::unpack_bulk_string;
+use crate::error::{unknown_subcommand, CommandError, Result};
+use crate::glob::glob_match;
+use crate::logging;
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+
+pub mod tests_config;
+
+pub async fn config_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+
+    match unpack_bulk_string(subcommand.clone())?
+        .to_uppercase()
+        .as_str()
+    {
+        "HELP" if args.len() == 1 => Ok(help_value(UserCommand::Config)),
+        "GET" => config_get(&args[1..], &state.config).await,
+        "SET" => {
+            let reply = config_set(&args[1..], &state.config).await?;
+            let config = state.config.read().await;
+            state
+                .latency
+                .set_threshold(config.latency_monitor_threshold);
+            let limits = config.client_output_buffer_limit;
+            state.pubsub.set_output_limit(limits.pubsub);
+            state.replication.set_output_limit(limits.replica);
+            state.replication.set_backlog_size(config.repl_backlog_size);
+            logging::configure(config.loglevel, config.log_format);
+            // Only when it changes, so passwords ACL SETUSER gave the default user stay
+            let requirepass = args[1..].iter().step_by(2).any(|param| {
+                matches!(param, Value::BulkString(param) if param.eq_ignore_ascii_case(b"requirepass"))
+            });
+            if requirepass {
+                state.acl.set_requirepass(config.requirepass.as_deref());
+            }
+            Ok(reply)
+        }
+        "RESETSTAT" if args.len() > 1 => Ok(CommandError::WrongArity.into()),
+        // The keyspace counters of every database are reset along with `Stats`
+        "RESETSTAT" => {
+            state.stats.reset();
+            for db in &state.databases {
+                db.stats().reset();
+            }
+            Ok(Value::SimpleString("OK".to_owned()))
+        }
+        other => Ok(unknown_subcommand("CONFIG", other)),
+    }
+}
+
+// CONFIG GET pattern [pattern ...] replies with a name/value map
+async fn config_get(args: &[Value], config: &Arc<RwLock<Config>>) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let patterns = args
+        .iter()
+        .map(|pattern| Ok(unpack_bulk_string(pattern.clone())?.to_lowercase()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let config = config.read().await;
+    let mut result = Vec::new();
+    for name in PARAMETERS {
+        if patterns
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+        {
+            let value = config.get(name).unwrap_or_default();
+            result.push((
+                Value::BulkString(name.into()),
+                Value::BulkString(value.into()),
+            ));
+        }
+    }
+    Ok(Value::Map(result))
+}
+
+// CONFIG SET name value [name value ...] applies every pair or none of them
+async fn config_set(args: &[Value], config: &Arc<RwLock<Config>>) -> Result<Value> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    // Acquire a write lock and validate the changes on a copy before publishing them
+    let mut config = config.write().await;
+    let mut updated = config.clone();
+    for pair in args.chunks(2) {
+        let name = unpack_bulk_string(pair[0].clone())?.to_lowercase();
+        let value = unpack_bulk_string(pair[1].clone())?;
+
+        if !PARAMETERS.contains(&name.as_str()) {
+            return Ok(Value::SimpleError(format!(
+                "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            )));
+        }
+        if !Config::is_mutable(&name) {
+            return Ok(Value::SimpleError(format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                name
+            )));
+        }
+        if let Err(err) = updated.set(&name, &value) {
+            return Ok(Value::SimpleError(format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                name, err
+            )));
+        }
+    }
+    *config = updated;
+
+    Ok(Value::SimpleString("OK".to_owned()))
+}
@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::testing::args;
+
+    fn state() -> ServerState {
+        ServerState::new(Config::new())
+    }
+
+    fn pairs(parts: &[&str]) -> Value {
+        let parts = args(parts);
+        Value::Map(
+            parts
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_config_get_patterns() -> Result<()> {
+        let state = state();
+        assert_eq!(
+            config_value(&args(&["GET", "port"]), &state).await?,
+            pairs(&["port", "6379"])
+        );
+        assert_eq!(
+            config_value(&args(&["get", "APPEND*"]), &state).await?,
+            pairs(&[
+                "appendonly",
+                "no",
+                "appendfilename",
+                "appendonly.aof",
+                "appendfsync",
+                "everysec"
+            ])
+        );
+        // Several patterns, each parameter reported once
+        assert_eq!(
+            config_value(&args(&["GET", "maxmemory", "max*", "db*"]), &state).await?,
+            pairs(&[
+                "maxclients",
+                "10000",
+                "maxmemory",
+                "0",
+                "maxmemory-policy",
+                "noeviction",
+                "maxmemory-samples",
+                "5",
+                "dbfilename",
+                "dump.rdb"
+            ])
+        );
+        assert_eq!(
+            config_value(&args(&["GET", "nothing*"]), &state).await?,
+            Value::Map(Vec::new())
+        );
+        assert_eq!(
+            config_value(&args(&["GET"]), &state).await?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_set() -> Result<()> {
+        let state = state();
+        assert_eq!(
+            config_value(
+                &args(&["SET", "maxmemory", "1mb", "dbfilename", "backup.rdb"]),
+                &state
+            )
+            .await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(state.config.read().await.maxmemory, 1024 * 1024);
+        assert_eq!(state.config.read().await.dbfilename, "backup.rdb");
+        assert_eq!(
+            config_value(&args(&["GET", "maxmemory"]), &state).await?,
+            pairs(&["maxmemory", "1048576"])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_set_errors_change_nothing() -> Result<()> {
+        let state = state();
+        let errors = [
+            vec!["SET", "port", "7000"],
+            vec!["SET", "nonsense", "1"],
+            vec!["SET", "maxmemory", "10mb", "maxmemory", "lots"],
+            vec!["SET", "appendonly", "yes"],
+            vec!["SET", "maxmemory"],
+            vec!["RESET"],
+        ];
+        for parts in errors {
+            assert!(matches!(
+                config_value(&args(&parts), &state).await?,
+                Value::SimpleError(_)
+            ));
+        }
+        assert_eq!(*state.config.read().await, Config::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::commands::zset::zadd_value;
+use crate::connection::{integer_arg, unpack_bulk_string, wrong_type};
+use crate::error::{CommandError, Error, Result};
+use crate::parser::Value;
+use crate::storage::sharded::{ShardedDb, Storage};
+use crate::storage::sorted_set::parse_score;
+use crate::storage::DataType;
+
+pub mod tests_geo;
+
+// Latitudes beyond these cannot be projected, same limits as Redis
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+
+// Bits of precision per coordinate; interleaved they fill 52 bits, which a double
+// holds exactly so the hash can be the sorted set score
+const STEP: u32 = 26;
+
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+
+/// Interleaves the latitude and longitude cells into a 52-bit geohash, latitude in
+/// the even bits.
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    let cells = (1u64 << STEP) as f64;
+    let lat_cell = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * cells) as u64;
+    let lon_cell = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * cells) as u64;
+
+    (0..STEP).fold(0, |hash, bit| {
+        hash | ((lat_cell >> bit) & 1) << (2 * bit) | ((lon_cell >> bit) & 1) << (2 * bit + 1)
+    })
+}
+
+/// The longitude and latitude at the centre of a geohash cell.
+pub fn decode(hash: u64) -> (f64, f64) {
+    let (lat_cell, lon_cell) = (0..STEP).fold((0u64, 0u64), |(lat, lon), bit| {
+        (
+            lat | ((hash >> (2 * bit)) & 1) << bit,
+            lon | ((hash >> (2 * bit + 1)) & 1) << bit,
+        )
+    });
+
+    let cells = (1u64 << STEP) as f64;
+    let centre = |cell: u64, min: f64, max: f64| {
+        let low = min + cell as f64 / cells * (max - min);
+        let high = min + (cell + 1) as f64 / cells * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (
+        centre(lon_cell, LON_MIN, LON_MAX),
+        centre(lat_cell, LAT_MIN, LAT_MAX),
+    )
+}
+
+/// Great-circle distance in meters between two points, by the haversine formula.
+pub fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+// Meters per unit of M, KM, FT or MI
+fn unit_factor(unit: &Value) -> std::result::Result<f64, Value> {
+    let Value::BulkString(unit) = unit else {
+        return Err(CommandError::Syntax.into());
+    };
+    match unit.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(Value::SimpleError(
+            "ERR unsupported unit provided. please use M, KM, FT, MI".to_owned(),
+        )),
+    }
+}
+
+fn float_arg(value: &Value) -> Option<f64> {
+    match value {
+        Value::BulkString(value) => parse_score(value),
+        _ => None,
+    }
+}
+
+// Parses a longitude and latitude pair; the error is the reply to send
+fn coordinates(lon: &Value, lat: &Value) -> std::result::Result<(f64, f64), Value> {
+    let (Some(lon), Some(lat)) = (float_arg(lon), float_arg(lat)) else {
+        return Err(CommandError::NotFloat.into());
+    };
+    if !(LON_MIN..=LON_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+        return Err(Value::SimpleError(format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            lon, lat
+        )));
+    }
+    Ok((lon, lat))
+}
+
+// The position a member's score encodes, None when the member is not in the set;
+// the error is the WRONGTYPE reply
+fn position(
+    instance: &impl Storage,
+    key: &str,
+    member: &Bytes,
+) -> std::result::Result<Option<(f64, f64)>, Value> {
+    match instance.get(key) {
+        Some(DataType::SortedSet(set)) => Ok(set.score(member).map(|score| decode(score as u64))),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// GEOADD key [NX|XX] [CH] longitude latitude member [...]: a ZADD with each position
+/// encoded as the member's score.
+pub async fn geoadd_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 4 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let mut zadd_args = vec![args[0].clone()];
+    let mut index = 1;
+    while let Some(Value::BulkString(flag)) = args.get(index) {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" | b"XX" | b"CH" => zadd_args.push(args[index].clone()),
+            _ => break,
+        }
+        index += 1;
+    }
+
+    let triples = &args[index..];
+    if triples.is_empty() || !triples.len().is_multiple_of(3) {
+        return Ok(CommandError::Syntax.into());
+    }
+    for triple in triples.chunks(3) {
+        let (lon, lat) = match coordinates(&triple[0], &triple[1]) {
+            Ok(position) => position,
+            Err(reply) => return Ok(reply),
+        };
+        zadd_args.push(Value::BulkString(encode(lon, lat).to_string().into()));
+        zadd_args.push(triple[2].clone());
+    }
+
+    zadd_value(&zadd_args, db_instance).await
+}
+
+/// GEODIST key member1 member2 [M|KM|FT|MI]: the distance between two members, or
+/// null when either is missing.
+pub async fn geodist_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 || args.len() > 4 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Value::BulkString(first), Value::BulkString(second)) = (&args[1], &args[2]) else {
+        return Err(Error::InvalidArgument("Invalid member type"));
+    };
+    let factor = match args.get(3).map(unit_factor) {
+        None => 1.0,
+        Some(Ok(factor)) => factor,
+        Some(Err(reply)) => return Ok(reply),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let positions = position(&instance, &key, first)
+        .and_then(|first| Ok((first, position(&instance, &key, second)?)));
+    match positions {
+        Ok((Some(first), Some(second))) => Ok(Value::BulkString(
+            format!("{:.4}", distance(first, second) / factor).into(),
+        )),
+        Ok(_) => Ok(Value::Null),
+        Err(reply) => Ok(reply),
+    }
+}
+
+/// Where GEOSEARCH looks from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoOrigin {
+    Member(Bytes),
+    LonLat(f64, f64),
+}
+
+/// The area GEOSEARCH covers, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl GeoShape {
+    // The distance from the centre to a point inside the shape, None when outside.
+    // A box is measured along the meridian and the parallel through the point
+    fn distance_within(&self, centre: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        let full = distance(centre, point);
+        match *self {
+            GeoShape::Radius(radius) => (full <= radius).then_some(full),
+            GeoShape::Box { width, height } => {
+                let lat_distance = distance((centre.0, centre.1), (centre.0, point.1));
+                let lon_distance = distance((centre.0, point.1), point);
+                (lat_distance <= height / 2.0 && lon_distance <= width / 2.0).then_some(full)
+            }
+        }
+    }
+}
+
+/// Everything GEOSEARCH takes after the key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoSearch {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    /// Meters per unit of the shape, used for WITHDIST too
+    pub factor: f64,
+    pub descending: Option<bool>,
+    pub count: Option<usize>,
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+}
+
+impl GeoSearch {
+    /// Parses the options; the error is the reply to send.
+    pub fn parse(args: &[Value]) -> std::result::Result<Self, Value> {
+        let (mut origin, mut shape, mut factor) = (None, None, 1.0);
+        let mut search = GeoSearch {
+            origin: GeoOrigin::LonLat(0.0, 0.0),
+            shape: GeoShape::Radius(0.0),
+            factor: 1.0,
+            descending: None,
+            count: None,
+            any: false,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+        };
+        let syntax = || Value::from(CommandError::Syntax);
+        let only_one_origin = || {
+            Value::SimpleError(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                    .to_owned(),
+            )
+        };
+        let only_one_shape = || {
+            Value::SimpleError(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH".to_owned(),
+            )
+        };
+
+        let mut index = 0;
+        while let Some(option) = args.get(index) {
+            let Value::BulkString(option) = option else {
+                return Err(syntax());
+            };
+            let rest = &args[index + 1..];
+            match option.to_ascii_uppercase().as_slice() {
+                b"FROMMEMBER" => {
+                    let Some(Value::BulkString(member)) = rest.first() else {
+                        return Err(syntax());
+                    };
+                    if origin.is_some() {
+                        return Err(only_one_origin());
+                    }
+                    origin = Some(GeoOrigin::Member(member.clone()));
+                    index += 2;
+                }
+                b"FROMLONLAT" => {
+                    let [lon, lat, ..] = rest else {
+                        return Err(syntax());
+                    };
+                    if origin.is_some() {
+                        return Err(only_one_origin());
+                    }
+                    let (lon, lat) = coordinates(lon, lat)?;
+                    origin = Some(GeoOrigin::LonLat(lon, lat));
+                    index += 3;
+                }
+                b"BYRADIUS" => {
+                    let [radius, unit, ..] = rest else {
+                        return Err(syntax());
+                    };
+                    if shape.is_some() {
+                        return Err(only_one_shape());
+                    }
+                    let radius = float_arg(radius)
+                        .filter(|radius| *radius >= 0.0)
+                        .ok_or_else(|| {
+                            Value::SimpleError("ERR radius cannot be negative".to_owned())
+                        })?;
+                    factor = unit_factor(unit)?;
+                    shape = Some(GeoShape::Radius(radius * factor));
+                    index += 3;
+                }
+                b"BYBOX" => {
+                    let [width, height, unit, ..] = rest else {
+                        return Err(syntax());
+                    };
+                    if shape.is_some() {
+                        return Err(only_one_shape());
+                    }
+                    let (Some(width), Some(height)) = (
+                        float_arg(width).filter(|width| *width >= 0.0),
+                        float_arg(height).filter(|height| *height >= 0.0),
+                    ) else {
+                        return Err(Value::SimpleError(
+                            "ERR height or width cannot be negative".to_owned(),
+                        ));
+                    };
+                    factor = unit_factor(unit)?;
+                    shape = Some(GeoShape::Box {
+                        width: width * factor,
+                        height: height * factor,
+                    });
+                    index += 4;
+                }
+                b"ASC" => {
+                    search.descending = Some(false);
+                    index += 1;
+                }
+                b"DESC" => {
+                    search.descending = Some(true);
+                    index += 1;
+                }
+                b"COUNT" => {
+                    let count = rest.first().ok_or_else(syntax)?;
+                    match integer_arg(count) {
+                        Some(count) if count > 0 => search.count = Some(count as usize),
+                        Some(_) => {
+                            return Err(Value::SimpleError("ERR COUNT must be > 0".to_owned()))
+                        }
+                        None => return Err(CommandError::NotInteger.into()),
+                    }
+                    index += 2;
+                    if let Some(Value::BulkString(any)) = args.get(index) {
+                        if any.eq_ignore_ascii_case(b"ANY") {
+                            search.any = true;
+                            index += 1;
+                        }
+                    }
+                }
+                b"WITHCOORD" => {
+                    search.with_coord = true;
+                    index += 1;
+                }
+                b"WITHDIST" => {
+                    search.with_dist = true;
+                    index += 1;
+                }
+                b"WITHHASH" => {
+                    search.with_hash = true;
+                    index += 1;
+                }
+                _ => return Err(syntax()),
+            }
+        }
+
+        search.origin = origin.ok_or_else(only_one_origin)?;
+        search.shape = shape.ok_or_else(only_one_shape)?;
+        search.factor = factor;
+        Ok(search)
+    }
+}
+
+/// GEOSEARCH key FROMMEMBER member|FROMLONLAT longitude latitude BYRADIUS radius
+/// unit|BYBOX width height unit [ASC|DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST]
+/// [WITHHASH]: the members inside a circle or box. Each match is just its name, or
+/// an array of the name and what was asked for, in the order distance, hash,
+/// coordinates.
+pub async fn geosearch_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 6 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let mut search = match GeoSearch::parse(&args[1..]) {
+        Ok(search) => search,
+        Err(reply) => return Ok(reply),
+    };
+    // Without ANY, COUNT keeps the nearest matches
+    if search.count.is_some() && !search.any && search.descending.is_none() {
+        search.descending = Some(false);
+    }
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get(&key) {
+        Some(DataType::SortedSet(set)) => set,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(Value::Array(vec![])),
+    };
+    let centre = match &search.origin {
+        GeoOrigin::LonLat(lon, lat) => (*lon, *lat),
+        GeoOrigin::Member(member) => match set.score(member) {
+            Some(score) => decode(score as u64),
+            None => {
+                return Ok(Value::SimpleError(
+                    "ERR could not decode requested zset member".to_owned(),
+                ))
+            }
+        },
+    };
+
+    let mut matches = Vec::new();
+    for (member, score) in set.iter() {
+        let point = decode(score as u64);
+        if let Some(distance) = search.shape.distance_within(centre, point) {
+            matches.push((member.clone(), score as u64, point, distance));
+            // ANY settles for the first matches found
+            if search.any && Some(matches.len()) == search.count {
+                break;
+            }
+        }
+    }
+    match search.descending {
+        Some(false) => matches.sort_by(|a, b| a.3.total_cmp(&b.3)),
+        Some(true) => matches.sort_by(|a, b| b.3.total_cmp(&a.3)),
+        None => {}
+    }
+    matches.truncate(search.count.unwrap_or(usize::MAX));
+
+    let plain = !(search.with_coord || search.with_dist || search.with_hash);
+    Ok(Value::Array(
+        matches
+            .into_iter()
+            .map(|(member, hash, (lon, lat), distance)| {
+                if plain {
+                    return Value::BulkString(member);
+                }
+                let mut reply = vec![Value::BulkString(member)];
+                if search.with_dist {
+                    reply.push(Value::BulkString(
+                        format!("{:.4}", distance / search.factor).into(),
+                    ));
+                }
+                if search.with_hash {
+                    reply.push(Value::Integer(hash as i64));
+                }
+                if search.with_coord {
+                    reply.push(Value::Array(vec![
+                        Value::BulkString(lon.to_string().into()),
+                        Value::BulkString(lat.to_string().into()),
+                    ]));
+                }
+                Value::Array(reply)
+            })
+            .collect(),
+    ))
+}
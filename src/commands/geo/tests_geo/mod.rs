@@ -0,0 +1,229 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::testing::args;
+
+    fn bulk(value: &str) -> Value {
+        Value::BulkString(value.to_string().into())
+    }
+
+    async fn sicily() -> Result<Arc<ShardedDb>> {
+        let db = Arc::new(ShardedDb::new());
+        geoadd_value(
+            &args(&[
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ]),
+            &db,
+        )
+        .await?;
+        Ok(db)
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        // The scores Redis gives these cities
+        assert_eq!(encode(13.361389, 38.115556), 3479099956230698);
+        assert_eq!(encode(15.087269, 37.502669), 3479447370796909);
+
+        let (lon, lat) = decode(3479099956230698);
+        assert!((lon - 13.361389).abs() < 1e-5);
+        assert!((lat - 38.115556).abs() < 1e-5);
+    }
+
+    #[tokio::test]
+    async fn test_geoadd_and_geodist() -> Result<()> {
+        let db = sicily().await?;
+
+        assert_eq!(
+            db.read().await.get("Sicily").map(|value| match value {
+                DataType::SortedSet(set) => set.score(&Bytes::from("Palermo")),
+                _ => None,
+            }),
+            Some(Some(3479099956230698.0))
+        );
+        assert_eq!(
+            geoadd_value(&args(&["Sicily", "NX", "0", "0", "Palermo"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            geoadd_value(&args(&["Sicily", "13", "100", "Nowhere"]), &db).await?,
+            Value::SimpleError(
+                "ERR invalid longitude,latitude pair 13.000000,100.000000".to_owned()
+            )
+        );
+        assert_eq!(
+            geoadd_value(&args(&["Sicily", "13", "38", "Rome", "12"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+
+        assert_eq!(
+            geodist_value(&args(&["Sicily", "Palermo", "Catania"]), &db).await?,
+            bulk("166274.1516")
+        );
+        assert_eq!(
+            geodist_value(&args(&["Sicily", "Palermo", "Catania", "km"]), &db).await?,
+            bulk("166.2742")
+        );
+        assert_eq!(
+            geodist_value(&args(&["Sicily", "Palermo", "Rome"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(
+            geodist_value(&args(&["Sicily", "Palermo", "Catania", "yd"]), &db).await?,
+            Value::SimpleError(
+                "ERR unsupported unit provided. please use M, KM, FT, MI".to_owned()
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_geosearch() -> Result<()> {
+        let db = sicily().await?;
+
+        assert_eq!(
+            geosearch_value(
+                &args(&[
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "200",
+                    "km",
+                    "ASC"
+                ]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![bulk("Catania"), bulk("Palermo")])
+        );
+        assert_eq!(
+            geosearch_value(
+                &args(&[
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "100",
+                    "km",
+                    "WITHDIST",
+                    "WITHHASH"
+                ]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![Value::Array(vec![
+                bulk("Catania"),
+                bulk("56.4413"),
+                Value::Integer(3479447370796909),
+            ])])
+        );
+        assert_eq!(
+            geosearch_value(
+                &args(&[
+                    "Sicily",
+                    "FROMMEMBER",
+                    "Palermo",
+                    "BYBOX",
+                    "400",
+                    "400",
+                    "km",
+                    "DESC",
+                    "COUNT",
+                    "1"
+                ]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![bulk("Catania")])
+        );
+
+        let Value::Array(matches) = geosearch_value(
+            &args(&[
+                "Sicily",
+                "FROMMEMBER",
+                "Palermo",
+                "BYRADIUS",
+                "1",
+                "m",
+                "WITHCOORD",
+            ]),
+            &db,
+        )
+        .await?
+        else {
+            panic!("expected an array");
+        };
+        let Value::Array(ref found) = matches[0] else {
+            panic!("expected a match with coordinates");
+        };
+        assert_eq!(found[0], bulk("Palermo"));
+        assert_eq!(
+            found[1],
+            Value::Array(vec![
+                bulk(&decode(3479099956230698).0.to_string()),
+                bulk(&decode(3479099956230698).1.to_string()),
+            ])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_geosearch_errors() -> Result<()> {
+        let db = sicily().await?;
+
+        assert_eq!(
+            geosearch_value(
+                &args(&["Sicily", "FROMMEMBER", "Rome", "BYRADIUS", "1", "km"]),
+                &db
+            )
+            .await?,
+            Value::SimpleError("ERR could not decode requested zset member".to_owned())
+        );
+        assert_eq!(
+            geosearch_value(
+                &args(&["Sicily", "BYRADIUS", "1", "km", "BYBOX", "1", "1", "km"]),
+                &db
+            )
+            .await?,
+            Value::SimpleError(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH".to_owned()
+            )
+        );
+        assert_eq!(
+            geosearch_value(
+                &args(&["Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "1", "km"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            geosearch_value(
+                &args(&[
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "1",
+                    "km",
+                    "COUNT",
+                    "0"
+                ]),
+                &db
+            )
+            .await?,
+            Value::SimpleError("ERR COUNT must be > 0".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use bytes::Bytes;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::commands::set::{out_of_range, random_below, repeated_picks, shuffled_prefix};
+use crate::connection::{integer_arg, parse_scan_options, scan_reply, unpack_bulk_string};
+use crate::error::{CommandError, Error, Result};
+use crate::parser::Value;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+
+pub mod tests_hash;
+
+pub async fn hset_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    // HSET key field value [field value ...]
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for pair in args[1..].chunks(2) {
+        let field = unpack_bulk_string(pair[0].clone())?;
+        let Value::BulkString(value) = &pair[1] else {
+            return Err(Error::InvalidArgument("Invalid value type"));
+        };
+        pairs.push((field, value.clone()));
+    }
+
+    // Acquire a write lock on the database instance, creating the hash on first write
+    let mut instance = db_instance.write_key(&key).await;
+    let hash = match instance.get_or_create_hash(&key) {
+        Ok(hash) => hash,
+        Err(err) => return Ok(err.into()),
+    };
+
+    // Number of fields that were newly added, updates are not counted
+    let added = pairs
+        .into_iter()
+        .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+        .count();
+
+    Ok(Value::Integer(added as i64))
+}
+
+pub async fn hget_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let field = unpack_bulk_string(args[1].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_hash(&key) {
+        Ok(Some(hash)) => Ok(hash
+            .get(&field)
+            .map_or(Value::Null, |value| Value::BulkString(value.clone()))),
+        Ok(None) => Ok(Value::Null),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn hmget_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let fields = args[1..]
+        .iter()
+        .map(|field| unpack_bulk_string(field.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let instance = db_instance.read_key(&key).await;
+    let empty = HashMap::new();
+    let hash = match instance.get_hash(&key) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => &empty,
+        Err(err) => return Ok(err.into()),
+    };
+
+    // One reply per requested field, null for the ones that are missing
+    let values = fields
+        .iter()
+        .map(|field| {
+            hash.get(field)
+                .map_or(Value::Null, |value| Value::BulkString(value.clone()))
+        })
+        .collect();
+
+    Ok(Value::Array(values))
+}
+
+pub async fn hdel_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let fields = args[1..]
+        .iter()
+        .map(|field| unpack_bulk_string(field.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    let (removed, now_empty) = match instance.get_hash_mut(&key) {
+        Ok(Some(hash)) => {
+            let removed = fields
+                .iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .count();
+            (removed, hash.is_empty())
+        }
+        Ok(None) => (0, false),
+        Err(err) => return Ok(err.into()),
+    };
+
+    // A hash never outlives its last field
+    if now_empty {
+        instance.remove(&key);
+    }
+
+    Ok(Value::Integer(removed as i64))
+}
+
+pub async fn hgetall_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_hash(&key) {
+        // Flattened as field1, value1, field2, value2, ... for RESP2 clients
+        Ok(Some(hash)) => Ok(Value::Map(
+            hash.iter()
+                .map(|(field, value)| {
+                    (
+                        Value::BulkString(field.clone().into()),
+                        Value::BulkString(value.clone()),
+                    )
+                })
+                .collect(),
+        )),
+        Ok(None) => Ok(Value::Map(vec![])),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn hexists_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let field = unpack_bulk_string(args[1].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_hash(&key) {
+        Ok(Some(hash)) => Ok(Value::Integer(hash.contains_key(&field) as i64)),
+        Ok(None) => Ok(Value::Integer(0)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn hlen_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_hash(&key) {
+        Ok(Some(hash)) => Ok(Value::Integer(hash.len() as i64)),
+        Ok(None) => Ok(Value::Integer(0)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES] iterates over the fields
+/// of a hash, each followed by its value unless NOVALUES is given.
+pub async fn hscan_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let key = unpack_bulk_string(args[0].clone())?;
+    let options = match parse_scan_options(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let hash = match instance.get_hash(&key) {
+        Ok(Some(hash)) => Some(hash),
+        Ok(None) => None,
+        Err(err) => return Ok(err.into()),
+    };
+    let (cursor, fields) = match hash {
+        Some(hash) => hash.scan(options.cursor, options.count),
+        None => (0, Vec::new()),
+    };
+    let mut elements = Vec::new();
+    for (field, value) in fields {
+        if !options.matches(field.as_bytes()) {
+            continue;
+        }
+        elements.push(Value::BulkString(field.clone().into()));
+        if !options.novalues {
+            elements.push(Value::BulkString(value.clone()));
+        }
+    }
+    Ok(scan_reply(cursor, elements))
+}
+
+/// HRANDFIELD key [count [WITHVALUES]] picks random fields the way SRANDMEMBER picks
+/// members: one without a count, that many distinct ones for a positive count and that
+/// many that may repeat for a negative one. WITHVALUES puts each value after its field.
+pub async fn hrandfield_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let (key, count, with_values) = match args {
+        [key] => (key, None, false),
+        [key, count] => (key, Some(count), false),
+        [key, count, option]
+            if unpack_bulk_string(option.clone())?.eq_ignore_ascii_case("WITHVALUES") =>
+        {
+            (key, Some(count), true)
+        }
+        [_, _, _] => return Ok(CommandError::Syntax.into()),
+        _ => return Ok(CommandError::WrongArity.into()),
+    };
+    let count = match count.map(integer_arg) {
+        Some(None) => return Ok(CommandError::NotInteger.into()),
+        // Like Redis, a count must have a magnitude that fits, twice over with values
+        Some(Some(i64::MIN)) => return Ok(out_of_range()),
+        Some(Some(count)) if with_values && count < -(i64::MAX / 2) => return Ok(out_of_range()),
+        Some(count) => count,
+        None => None,
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    let hash = match instance.get_hash(&key) {
+        Ok(Some(hash)) => hash,
+        Ok(None) if count.is_some() => return Ok(Value::Array(vec![])),
+        Ok(None) => return Ok(Value::Null),
+        Err(err) => return Ok(err.into()),
+    };
+    let fields: Vec<(&String, &Bytes)> = hash.iter().collect();
+    let picked = match count {
+        None => {
+            let (field, _) = fields[random_below(fields.len())];
+            return Ok(Value::BulkString(field.clone().into()));
+        }
+        Some(count) if count < 0 => repeated_picks(&fields, count.unsigned_abs()),
+        Some(count) => shuffled_prefix(fields, count as usize),
+    };
+    let mut elements = Vec::new();
+    for (field, value) in picked {
+        elements.push(Value::BulkString(field.clone().into()));
+        if with_values {
+            elements.push(Value::BulkString(value.clone()));
+        }
+    }
+    Ok(Value::Array(elements))
+}
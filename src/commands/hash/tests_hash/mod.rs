@@ -0,0 +1,263 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::connection::wrong_type;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    #[tokio::test]
+    async fn test_hset_and_hget() -> Result<()> {
+        let db = db();
+        assert_eq!(
+            hset_value(&args(&["user", "name", "ada", "lang", "rust"]), &db).await?,
+            Value::Integer(2)
+        );
+        // Updating an existing field is not counted as added
+        assert_eq!(
+            hset_value(&args(&["user", "name", "grace", "age", "36"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            hget_value(&args(&["user", "name"]), &db).await?,
+            Value::BulkString("grace".into())
+        );
+        assert_eq!(
+            hget_value(&args(&["user", "missing"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(
+            hget_value(&args(&["nobody", "name"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(hlen_value(&args(&["user"]), &db).await?, Value::Integer(3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hmget_and_hexists() -> Result<()> {
+        let db = db();
+        hset_value(&args(&["user", "name", "ada"]), &db).await?;
+
+        assert_eq!(
+            hmget_value(&args(&["user", "name", "missing"]), &db).await?,
+            Value::Array(vec![Value::BulkString("ada".into()), Value::Null])
+        );
+        assert_eq!(
+            hmget_value(&args(&["nobody", "name"]), &db).await?,
+            Value::Array(vec![Value::Null])
+        );
+        assert_eq!(
+            hexists_value(&args(&["user", "name"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            hexists_value(&args(&["user", "age"]), &db).await?,
+            Value::Integer(0)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hgetall_is_a_map() -> Result<()> {
+        let db = db();
+        hset_value(&args(&["user", "name", "ada", "lang", "rust"]), &db).await?;
+
+        let Value::Map(mut pairs) = hgetall_value(&args(&["user"]), &db).await? else {
+            panic!("expected a map reply");
+        };
+        pairs.sort_by_key(|(field, _)| format!("{:?}", field));
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    Value::BulkString("lang".into()),
+                    Value::BulkString("rust".into())
+                ),
+                (
+                    Value::BulkString("name".into()),
+                    Value::BulkString("ada".into())
+                ),
+            ]
+        );
+        assert_eq!(
+            hgetall_value(&args(&["nobody"]), &db).await?,
+            Value::Map(vec![])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hdel_removes_empty_hash() -> Result<()> {
+        let db = db();
+        hset_value(&args(&["user", "name", "ada", "lang", "rust"]), &db).await?;
+
+        assert_eq!(
+            hdel_value(&args(&["user", "name", "missing"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            hdel_value(&args(&["user", "lang"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert!(db.read().await.get("user").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() -> Result<()> {
+        let db = db();
+        db.write()
+            .await
+            .insert_entry("text".to_owned(), Entry::new(DataType::String("v".into())));
+
+        assert_eq!(
+            hset_value(&args(&["text", "field", "value"]), &db).await?,
+            wrong_type()
+        );
+        assert_eq!(
+            hget_value(&args(&["text", "field"]), &db).await?,
+            wrong_type()
+        );
+        assert_eq!(hgetall_value(&args(&["text"]), &db).await?, wrong_type());
+        assert_eq!(
+            hset_value(&args(&["user", "field"]), &db).await?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        Ok(())
+    }
+
+    // Every element HSCAN gives from cursor 0 until it ends, as strings
+    async fn hscan_all(db: &Arc<ShardedDb>, options: &[&str]) -> Result<Vec<String>> {
+        let mut cursor = "0".to_owned();
+        let mut elements = Vec::new();
+        loop {
+            let mut parts = vec!["user", cursor.as_str()];
+            parts.extend(options);
+            let reply = hscan_value(&args(&parts), db).await?;
+            let Value::Array(reply) = reply else {
+                panic!("expected an array reply, got {:?}", reply);
+            };
+            let [Value::BulkString(next), Value::Array(page)] = &reply[..] else {
+                panic!("unexpected HSCAN reply {:?}", reply);
+            };
+            for element in page {
+                let Value::BulkString(element) = element else {
+                    panic!("unexpected element {:?}", element);
+                };
+                elements.push(String::from_utf8(element.to_vec()).unwrap());
+            }
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                return Ok(elements);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hscan() -> Result<()> {
+        let db = db();
+        for i in 0..20 {
+            let (field, value) = (format!("field:{}", i), format!("value:{}", i));
+            hset_value(&args(&["user", &field, &value]), &db).await?;
+        }
+        hset_value(&args(&["user", "name", "ada"]), &db).await?;
+
+        // Each field comes once, followed by its value
+        let elements = hscan_all(&db, &["MATCH", "field:*", "COUNT", "3"]).await?;
+        let mut pairs: Vec<(String, String)> = elements
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        assert_eq!(pairs.len(), 20);
+        assert!(pairs
+            .iter()
+            .all(|(field, value)| field.replace("field", "value") == *value));
+
+        let mut fields = hscan_all(&db, &["NOVALUES"]).await?;
+        fields.sort();
+        assert_eq!(fields.len(), 21);
+        assert!(fields.contains(&"name".to_owned()));
+
+        assert_eq!(
+            hscan_value(&args(&["missing", "0"]), &db).await?,
+            Value::Array(vec![Value::BulkString("0".into()), Value::Array(vec![])])
+        );
+        assert!(matches!(
+            hscan_value(&args(&["user", "x"]), &db).await?,
+            Value::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hrandfield() -> Result<()> {
+        let db = db();
+        hset_value(&args(&["user", "a", "1", "b", "2", "c", "3"]), &db).await?;
+        let strings = |value: Value| -> Vec<String> {
+            let Value::Array(items) = value else {
+                panic!("expected an array reply, got {:?}", value);
+            };
+            items
+                .into_iter()
+                .map(|item| match item {
+                    Value::BulkString(item) => String::from_utf8(item.to_vec()).unwrap(),
+                    other => panic!("unexpected element {:?}", other),
+                })
+                .collect()
+        };
+
+        let Value::BulkString(field) = hrandfield_value(&args(&["user"]), &db).await? else {
+            panic!("expected a field");
+        };
+        assert!([&b"a"[..], b"b", b"c"].contains(&&field[..]));
+        // A positive count never repeats and stops at the size of the hash
+        let mut fields = strings(hrandfield_value(&args(&["user", "10"]), &db).await?);
+        fields.sort();
+        assert_eq!(fields, ["a", "b", "c"]);
+        // A negative count may repeat fields, and WITHVALUES pairs them with values
+        let pairs = strings(hrandfield_value(&args(&["user", "-5", "WITHVALUES"]), &db).await?);
+        assert_eq!(pairs.len(), 10);
+        for pair in pairs.chunks(2) {
+            let expected = match pair[0].as_str() {
+                "a" => "1",
+                "b" => "2",
+                _ => "3",
+            };
+            assert_eq!(pair[1], expected);
+        }
+
+        assert_eq!(
+            hrandfield_value(&args(&["missing"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(
+            hrandfield_value(&args(&["missing", "2"]), &db).await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            hrandfield_value(&args(&["user", "x"]), &db).await?,
+            CommandError::NotInteger.into()
+        );
+        assert_eq!(
+            hrandfield_value(&args(&["user", "1", "WITHSCORES"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        // Counts whose reply size does not fit are refused, not allocated for
+        let out_of_range = Value::SimpleError("ERR value is out of range".to_owned());
+        assert_eq!(
+            hrandfield_value(&args(&["user", "-9223372036854775808"]), &db).await?,
+            out_of_range
+        );
+        assert_eq!(
+            hrandfield_value(&args(&["user", "-4611686018427387904", "WITHVALUES"]), &db).await?,
+            out_of_range
+        );
+        Ok(())
+    }
+}
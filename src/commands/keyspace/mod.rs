@@ -0,0 +1,580 @@
+use bytes::Bytes;
+use std::{cmp::Ordering, collections::VecDeque, sync::Arc, time::Duration};
+
+use crate::client::Client;
+use crate::commands::server::help_value;
+use crate::config::MaxMemoryPolicy;
+use crate::connection::dispatch::Caller;
+use crate::connection::{integer_arg, run_command, unpack_bulk_string};
+use crate::error::{unknown_subcommand, ClientError, CommandError, Error, Result};
+use crate::parser::{UserCommand, Value};
+use crate::persistence::rdb::{dump, undump};
+use crate::server::ServerState;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::sorted_set::parse_score;
+use crate::storage::{DataType, Entry};
+
+pub mod tests_keyspace;
+
+// Resolves a database index argument, None when it is not a valid index
+fn db_index(value: &Value, count: usize) -> Option<usize> {
+    integer_arg(value)
+        .and_then(|index| usize::try_from(index).ok())
+        .filter(|index| *index < count)
+}
+
+pub fn select_value(args: &[Value], count: usize, selected: &mut usize) -> Result<Value> {
+    let [index] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+
+    let Some(index) = integer_arg(index) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    match usize::try_from(index).ok().filter(|index| *index < count) {
+        Some(index) => {
+            *selected = index;
+            Ok(Value::SimpleString("OK".to_owned()))
+        }
+        None => Ok(Value::SimpleError(
+            "ERR DB index is out of range".to_owned(),
+        )),
+    }
+}
+
+pub async fn swapdb_value(args: &[Value], databases: &[Arc<ShardedDb>]) -> Result<Value> {
+    let [first, second] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+
+    let Some(first) = db_index(first, databases.len()) else {
+        return Ok(Value::SimpleError("ERR invalid first DB index".to_owned()));
+    };
+    let Some(second) = db_index(second, databases.len()) else {
+        return Ok(Value::SimpleError("ERR invalid second DB index".to_owned()));
+    };
+
+    // Always lock the lower index first so concurrent swaps cannot deadlock
+    if first != second {
+        let (low, high) = (first.min(second), first.max(second));
+        let mut low = databases[low].write().await;
+        let mut high = databases[high].write().await;
+        low.swap_entries(&mut high);
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+pub async fn dbsize_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    Ok(Value::Integer(db_instance.read().await.len() as i64))
+}
+
+pub async fn randomkey_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    match db_instance.read().await.random_key() {
+        Some(key) => Ok(Value::BulkString(key.clone().into())),
+        None => Ok(Value::Null),
+    }
+}
+
+pub async fn type_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    let name = instance.get(&key).map_or("none", DataType::type_name);
+    Ok(Value::SimpleString(name.to_owned()))
+}
+
+/// OBJECT ENCODING | IDLETIME | FREQ | REFCOUNT key inspects how a value is stored
+/// without counting as an access to it. Like in Redis, IDLETIME is only tracked under
+/// the LRU policies and FREQ under the LFU ones.
+pub async fn object_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    policy: MaxMemoryPolicy,
+) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    match subcommand.as_str() {
+        "HELP" if args.len() == 1 => return Ok(help_value(UserCommand::Object)),
+        "ENCODING" | "IDLETIME" | "FREQ" | "REFCOUNT" => {}
+        other => return Ok(unknown_subcommand("OBJECT", other)),
+    }
+    let [_, key] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    let Some((entry, accessed_at)) = instance.peek_entry(&key) else {
+        return Ok(Value::Null);
+    };
+    Ok(match subcommand.as_str() {
+        "ENCODING" => Value::BulkString(entry.value.encoding().into()),
+        "IDLETIME" if policy.is_lfu() => Value::SimpleError(
+            "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."
+                .to_owned(),
+        ),
+        "IDLETIME" => Value::Integer((db_instance.now_millis().saturating_sub(accessed_at) / 1000) as i64),
+        "FREQ" if !policy.is_lfu() => Value::SimpleError(
+            "ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."
+                .to_owned(),
+        ),
+        "FREQ" => Value::Integer(instance.frequency(&key).unwrap_or_default() as i64),
+        // Values are never shared between keys
+        _ => Value::Integer(1),
+    })
+}
+
+/// RENAME key newkey moves the value and TTL of a key, replacing any destination.
+/// With `nx` (RENAMENX) nothing happens when the destination exists.
+pub async fn rename_value(args: &[Value], db_instance: &Arc<ShardedDb>, nx: bool) -> Result<Value> {
+    let [source, destination] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let source = unpack_bulk_string(source.clone())?;
+    let destination = unpack_bulk_string(destination.clone())?;
+
+    let mut instance = db_instance.write_keys(&[&source, &destination]).await;
+    if instance.get(&source).is_none() {
+        return Ok(Value::SimpleError("ERR no such key".to_owned()));
+    }
+    if nx && instance.get(&destination).is_some() {
+        return Ok(Value::Integer(0));
+    }
+    if source != destination {
+        if let Some(entry) = instance.remove_entry(&source) {
+            instance.insert_entry(destination.clone(), entry);
+        }
+        // Clients blocked on the destination may now find a list or stream there
+        instance.wake_blocked(&destination, usize::MAX);
+    }
+
+    Ok(if nx {
+        Value::Integer(1)
+    } else {
+        Value::SimpleString("OK".to_owned())
+    })
+}
+
+/// The options SORT takes after the key.
+#[derive(Debug, Default)]
+struct SortOptions {
+    by: Option<String>,
+    // Offset and count, a negative count meaning every element after the offset
+    limit: Option<(i64, i64)>,
+    get: Vec<String>,
+    desc: bool,
+    alpha: bool,
+    store: Option<String>,
+}
+
+/// SORT key [BY pattern] [LIMIT offset count] [GET pattern ...] [ASC|DESC] [ALPHA]
+/// [STORE destination] sorts the elements of a list, set or sorted set, as numbers
+/// unless ALPHA is given. In BY and GET patterns the first `*` is replaced with the
+/// element and a `->field` suffix reads a hash field; `GET #` is the element itself.
+/// A BY pattern without `*` skips sorting. With STORE the result replaces the
+/// destination as a list and its length is returned.
+pub async fn sort_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let Some(key) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+    let options = match parse_sort_options(&args[1..])? {
+        Ok(options) => options,
+        Err(reply) => return Ok(reply),
+    };
+
+    // BY and GET patterns may name any key, so the whole database is locked
+    let Some(destination) = &options.store else {
+        let instance = db_instance.read().await;
+        return Ok(match sort_elements(&instance, &key, &options) {
+            Ok(rows) => Value::Array(
+                rows.into_iter()
+                    .map(|row| row.map_or(Value::Null, Value::BulkString))
+                    .collect(),
+            ),
+            Err(reply) => reply,
+        });
+    };
+    let mut instance = db_instance.write().await;
+    let rows = match sort_elements(&instance, &key, &options) {
+        Ok(rows) => rows,
+        Err(reply) => return Ok(reply),
+    };
+    // Missing values are stored as empty strings
+    let list: VecDeque<Bytes> = rows.into_iter().map(Option::unwrap_or_default).collect();
+    let len = list.len();
+    if list.is_empty() {
+        instance.remove(destination);
+    } else {
+        instance.insert_entry(destination.clone(), Entry::new(DataType::List(list)));
+        instance.wake_blocked(destination, usize::MAX);
+    }
+    Ok(Value::Integer(len as i64))
+}
+
+// The options are checked before the key is looked at, so an error reply comes back
+// as Err
+fn parse_sort_options(args: &[Value]) -> Result<std::result::Result<SortOptions, Value>> {
+    let args = args
+        .iter()
+        .map(|arg| unpack_bulk_string(arg.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let mut options = SortOptions::default();
+    let mut rest = args.as_slice();
+    while let Some((option, after)) = rest.split_first() {
+        rest = match (option.to_uppercase().as_str(), after) {
+            ("ASC", after) => {
+                options.desc = false;
+                after
+            }
+            ("DESC", after) => {
+                options.desc = true;
+                after
+            }
+            ("ALPHA", after) => {
+                options.alpha = true;
+                after
+            }
+            ("BY", [pattern, after @ ..]) => {
+                options.by = Some(pattern.clone());
+                after
+            }
+            ("GET", [pattern, after @ ..]) => {
+                options.get.push(pattern.clone());
+                after
+            }
+            ("STORE", [destination, after @ ..]) => {
+                options.store = Some(destination.clone());
+                after
+            }
+            ("LIMIT", [offset, count, after @ ..]) => {
+                let (Ok(offset), Ok(count)) = (offset.parse(), count.parse()) else {
+                    return Ok(Err(CommandError::NotInteger.into()));
+                };
+                options.limit = Some((offset, count));
+                after
+            }
+            _ => return Ok(Err(CommandError::Syntax.into())),
+        };
+    }
+    Ok(Ok(options))
+}
+
+// The rows SORT returns: the sorted elements, or for each of them the value of every
+// GET pattern, None where a pattern names nothing
+fn sort_elements(
+    instance: &impl Storage,
+    key: &str,
+    options: &SortOptions,
+) -> std::result::Result<Vec<Option<Bytes>>, Value> {
+    let (mut elements, is_set): (Vec<Bytes>, bool) = match instance.get(key) {
+        None => (Vec::new(), false),
+        Some(DataType::List(list)) => (list.iter().cloned().collect(), false),
+        Some(DataType::Set(set)) => (set.iter().cloned().collect(), true),
+        Some(DataType::SortedSet(zset)) => (
+            zset.iter().map(|(member, _)| member.clone()).collect(),
+            false,
+        ),
+        Some(_) => return Err(CommandError::WrongType.into()),
+    };
+
+    let sort = match &options.by {
+        Some(pattern) => pattern.contains('*'),
+        None => true,
+    };
+    if sort {
+        let weight = |element: &Bytes| match &options.by {
+            Some(pattern) => sort_lookup(instance, pattern, element),
+            None => Some(element.clone()),
+        };
+        if options.alpha {
+            let mut weighted: Vec<(Option<Bytes>, Bytes)> = elements
+                .into_iter()
+                .map(|element| (weight(&element), element))
+                .collect();
+            weighted.sort_by(|(a, _), (b, _)| a.cmp(b));
+            elements = weighted.into_iter().map(|(_, element)| element).collect();
+        } else {
+            // Missing weights count as 0
+            let mut scored = Vec::with_capacity(elements.len());
+            for element in elements {
+                let score = match weight(&element) {
+                    Some(weight) => parse_score(&weight).ok_or_else(|| {
+                        Value::SimpleError(
+                            "ERR One or more scores can't be converted into double".to_owned(),
+                        )
+                    })?,
+                    None => 0.0,
+                };
+                scored.push((score, element));
+            }
+            scored.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            elements = scored.into_iter().map(|(_, element)| element).collect();
+        }
+        if options.desc {
+            elements.reverse();
+        }
+    } else if is_set && options.store.is_some() {
+        // Set order is arbitrary, and what is stored must come out the same when the
+        // AOF or a replica runs it again
+        elements.sort();
+    }
+
+    if let Some((offset, count)) = options.limit {
+        let offset = offset.max(0) as usize;
+        let count = if count < 0 {
+            usize::MAX
+        } else {
+            count as usize
+        };
+        elements = elements.into_iter().skip(offset).take(count).collect();
+    }
+
+    if options.get.is_empty() {
+        return Ok(elements.into_iter().map(Some).collect());
+    }
+    Ok(elements
+        .iter()
+        .flat_map(|element| {
+            options
+                .get
+                .iter()
+                .map(move |pattern| sort_lookup(instance, pattern, element))
+        })
+        .collect())
+}
+
+// The value a BY or GET pattern names for an element: a string, or a hash field after
+// `->`. `#` is the element itself.
+fn sort_lookup(instance: &impl Storage, pattern: &str, element: &Bytes) -> Option<Bytes> {
+    if pattern == "#" {
+        return Some(element.clone());
+    }
+    let (key_pattern, field) = match pattern.rsplit_once("->") {
+        Some((key_pattern, field)) if !field.is_empty() => (key_pattern, Some(field)),
+        _ => (pattern, None),
+    };
+    let key = key_pattern.replacen('*', &String::from_utf8_lossy(element), 1);
+    if key == key_pattern {
+        return None;
+    }
+    match (instance.get(&key)?, field) {
+        (DataType::String(value), None) => Some(value.to_bytes()),
+        (DataType::Hash(hash), Some(field)) => hash.get(field).cloned(),
+        _ => None,
+    }
+}
+
+/// DUMP key serializes a value so RESTORE can recreate it here or on another instance
+/// of this server. The TTL is not included.
+pub async fn dump_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    Ok(instance
+        .get(&key)
+        .map_or(Value::Null, |value| Value::BulkString(dump(value))))
+}
+
+/// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] creates a key from a DUMP
+/// payload. The TTL is in milliseconds, 0 for none, and with ABSTTL it is a Unix
+/// timestamp instead; one already in the past leaves the key absent.
+pub async fn restore_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Value::BulkString(payload) = &args[2] else {
+        return Err(Error::InvalidArgument("Invalid payload type"));
+    };
+
+    let (mut replace, mut absolute) = (false, false);
+    for option in &args[3..] {
+        match unpack_bulk_string(option.clone())?.to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absolute = true,
+            _ => return Ok(CommandError::Syntax.into()),
+        }
+    }
+    let ttl = match integer_arg(&args[1]) {
+        Some(ttl) if ttl >= 0 => ttl as u64,
+        _ => {
+            return Ok(Value::SimpleError(
+                "ERR Invalid TTL value, must be >= 0".to_owned(),
+            ))
+        }
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    if !replace && instance.get(&key).is_some() {
+        return Ok(Value::SimpleError(
+            "BUSYKEY Target key name already exists.".to_owned(),
+        ));
+    }
+    let Ok(value) = undump(payload) else {
+        return Ok(Value::SimpleError(
+            "ERR DUMP payload version or checksum are wrong".to_owned(),
+        ));
+    };
+
+    let expires_at = match (ttl, absolute) {
+        (0, _) => None,
+        (ttl, true) => Some(ttl),
+        (ttl, false) => Some(db_instance.now_millis() + ttl),
+    };
+    if expires_at.is_some_and(|expires_at| expires_at <= db_instance.now_millis()) {
+        instance.remove(&key);
+    } else {
+        instance.insert_entry(key.clone(), Entry { value, expires_at });
+        // Clients blocked on the key may now find a list or sorted set there
+        instance.wake_blocked(&key, usize::MAX);
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+/// MIGRATE host port key destination-db timeout [COPY] [REPLACE] moves a key to
+/// another server with DUMP and RESTORE, then deletes it here unless COPY is given.
+/// The timeout, in milliseconds, applies to connecting and to each reply. No lock is
+/// held while talking to the target; the local delete runs as a plain DEL, which is
+/// what the AOF and replicas see.
+pub async fn migrate_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+) -> Result<Value> {
+    if args.len() < 5 {
+        return Ok(CommandError::WrongArity.into());
+    }
+    let host = unpack_bulk_string(args[0].clone())?;
+    let port = unpack_bulk_string(args[1].clone())?;
+    let key = unpack_bulk_string(args[2].clone())?;
+    let (Some(db), Some(timeout)) = (integer_arg(&args[3]), integer_arg(&args[4])) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    // Like Redis, a timeout that is not positive means one second
+    let timeout = Duration::from_millis(if timeout > 0 { timeout as u64 } else { 1000 });
+
+    let (mut copy, mut replace) = (false, false);
+    for option in &args[5..] {
+        match unpack_bulk_string(option.clone())?.to_uppercase().as_str() {
+            "COPY" => copy = true,
+            "REPLACE" => replace = true,
+            _ => return Ok(CommandError::Syntax.into()),
+        }
+    }
+
+    let (payload, ttl) = {
+        let instance = state.databases[*selected].read_key(&key).await;
+        let (Some(value), Some(ttl)) = (instance.get(&key), instance.ttl_millis(&key)) else {
+            return Ok(Value::SimpleString("NOKEY".to_owned()));
+        };
+        // A key about to expire keeps the shortest TTL RESTORE accepts
+        (dump(value), ttl.map_or(0, |ttl| ttl.max(1)))
+    };
+
+    let Ok(Ok(mut target)) =
+        tokio::time::timeout(timeout, Client::connect(format!("{}:{}", host, port))).await
+    else {
+        return Ok(Value::SimpleError(
+            "IOERR error or timeout connecting to the client".to_owned(),
+        ));
+    };
+    let ttl = ttl.to_string();
+    let db = db.to_string();
+    let mut restore: Vec<&[u8]> = vec![b"RESTORE", key.as_bytes(), ttl.as_bytes(), &payload];
+    if replace {
+        restore.push(b"REPLACE");
+    }
+    for command in [vec![b"SELECT".as_slice(), db.as_bytes()], restore] {
+        match tokio::time::timeout(timeout, target.command(&command)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(ClientError::Reply(message))) => {
+                return Ok(Value::SimpleError(format!(
+                    "ERR Target instance replied with error: {}",
+                    message
+                )))
+            }
+            _ => {
+                return Ok(Value::SimpleError(
+                    "IOERR error or timeout reading to target instance".to_owned(),
+                ))
+            }
+        }
+    }
+
+    if !copy {
+        let _shared = state.exec_lock.read().await;
+        run_command(
+            UserCommand::Del,
+            &[Value::BulkString(key.into())],
+            state,
+            selected,
+            caller,
+        )
+        .await?;
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+// FLUSHDB and FLUSHALL take an optional SYNC or ASYNC mode, returning whether to
+// free the old keys in the background
+fn parse_flush_mode(args: &[Value]) -> Result<Option<bool>> {
+    match args {
+        [] => Ok(Some(false)),
+        [mode] => match unpack_bulk_string(mode.clone())?.to_uppercase().as_str() {
+            "SYNC" => Ok(Some(false)),
+            "ASYNC" => Ok(Some(true)),
+            _ => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+// Empties one database. With ASYNC the old map is swapped out under the lock and
+// dropped on a blocking task, so freeing a large keyspace does not stall other clients.
+async fn flush(db_instance: &Arc<ShardedDb>, lazy: bool) {
+    let mut instance = db_instance.write().await;
+    if lazy {
+        let entries = instance.take_entries();
+        tokio::task::spawn_blocking(move || drop(entries));
+    } else {
+        instance.clear();
+    }
+}
+
+pub async fn flushdb_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let Some(lazy) = parse_flush_mode(args)? else {
+        return Ok(CommandError::Syntax.into());
+    };
+
+    flush(db_instance, lazy).await;
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+pub async fn flushall_value(args: &[Value], databases: &[Arc<ShardedDb>]) -> Result<Value> {
+    let Some(lazy) = parse_flush_mode(args)? else {
+        return Ok(CommandError::Syntax.into());
+    };
+
+    for db_instance in databases {
+        flush(db_instance, lazy).await;
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
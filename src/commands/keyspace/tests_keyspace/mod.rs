@@ -0,0 +1,422 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn databases(count: usize) -> Vec<Arc<ShardedDb>> {
+        (0..count).map(|_| Arc::new(ShardedDb::new())).collect()
+    }
+
+    async fn insert(db: &Arc<ShardedDb>, key: &str) {
+        db.write().await.insert_entry(
+            key.to_owned(),
+            Entry::new(DataType::String(key.to_owned().into())),
+        );
+    }
+
+    #[test]
+    fn test_select() -> Result<()> {
+        let mut selected = 0;
+        let ok = Value::SimpleString("OK".to_owned());
+        assert_eq!(select_value(&args(&["3"]), 16, &mut selected)?, ok);
+        assert_eq!(selected, 3);
+
+        for index in ["16", "-1", "one"] {
+            assert!(matches!(
+                select_value(&args(&[index]), 16, &mut selected)?,
+                Value::SimpleError(_)
+            ));
+        }
+        assert_eq!(selected, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_swapdb() -> Result<()> {
+        let databases = databases(3);
+        insert(&databases[0], "zero").await;
+        insert(&databases[2], "two").await;
+
+        assert_eq!(
+            swapdb_value(&args(&["2", "0"]), &databases).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert!(databases[0].read().await.get("two").is_some());
+        assert!(databases[2].read().await.get("zero").is_some());
+        assert!(databases[0].read().await.get("zero").is_none());
+
+        // Swapping a database with itself is a no-op
+        swapdb_value(&args(&["1", "1"]), &databases).await?;
+        assert_eq!(
+            swapdb_value(&args(&["0", "3"]), &databases).await?,
+            Value::SimpleError("ERR invalid second DB index".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flushdb_and_flushall() -> Result<()> {
+        let databases = databases(2);
+        insert(&databases[0], "a").await;
+        insert(&databases[1], "b").await;
+
+        flushdb_value(&args(&[]), &databases[1]).await?;
+        assert!(databases[0].read().await.get("a").is_some());
+        assert!(databases[1].read().await.get("b").is_none());
+
+        insert(&databases[1], "b").await;
+        flushall_value(&args(&[]), &databases).await?;
+        for db in &databases {
+            assert_eq!(db.read().await.keys().count(), 0);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flush_modes() -> Result<()> {
+        let databases = databases(2);
+        insert(&databases[0], "a").await;
+        insert(&databases[1], "b").await;
+
+        let ok = Value::SimpleString("OK".to_owned());
+        assert_eq!(flushdb_value(&args(&["async"]), &databases[0]).await?, ok);
+        assert_eq!(databases[0].read().await.keys().count(), 0);
+        assert_eq!(flushall_value(&args(&["SYNC"]), &databases).await?, ok);
+        assert_eq!(databases[1].read().await.keys().count(), 0);
+
+        // A watched key counts as written by an asynchronous flush too
+        let version = databases[1].write().await.watch("b");
+        insert(&databases[1], "b").await;
+        let version_before_flush = databases[1].read().await.version("b");
+        assert_ne!(version_before_flush, version);
+        flushall_value(&args(&["ASYNC"]), &databases).await?;
+        assert_ne!(databases[1].read().await.version("b"), version_before_flush);
+
+        for invalid in [vec!["LATER"], vec!["ASYNC", "SYNC"]] {
+            assert_eq!(
+                flushdb_value(&args(&invalid), &databases[0]).await?,
+                Value::SimpleError("ERR syntax error".to_owned())
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dbsize_and_randomkey() -> Result<()> {
+        let db = Arc::new(ShardedDb::new());
+        assert_eq!(dbsize_value(&[], &db).await?, Value::Integer(0));
+        assert_eq!(randomkey_value(&[], &db).await?, Value::Null);
+
+        for key in ["a", "b", "c"] {
+            insert(&db, key).await;
+        }
+        assert_eq!(dbsize_value(&[], &db).await?, Value::Integer(3));
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..200 {
+            if let Value::BulkString(key) = randomkey_value(&[], &db).await? {
+                seen.insert(key);
+            }
+        }
+        assert_eq!(seen.len(), 3);
+
+        // Expired keys are never picked
+        db.write().await.set_expiry("a", Some(1));
+        db.write().await.set_expiry("b", Some(1));
+        for _ in 0..20 {
+            assert_eq!(
+                randomkey_value(&[], &db).await?,
+                Value::BulkString("c".into())
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_type_and_object() -> Result<()> {
+        let db = Arc::new(ShardedDb::new());
+        insert(&db, "string").await;
+        db.write().await.insert_entry(
+            "number".to_owned(),
+            Entry::new(DataType::String("42".into())),
+        );
+
+        assert_eq!(
+            type_value(&args(&["string"]), &db).await?,
+            Value::SimpleString("string".to_owned())
+        );
+        assert_eq!(
+            type_value(&args(&["missing"]), &db).await?,
+            Value::SimpleString("none".to_owned())
+        );
+        assert_eq!(
+            object_value(
+                &args(&["ENCODING", "number"]),
+                &db,
+                MaxMemoryPolicy::NoEviction
+            )
+            .await?,
+            Value::BulkString("int".into())
+        );
+        assert_eq!(
+            object_value(
+                &args(&["encoding", "string"]),
+                &db,
+                MaxMemoryPolicy::NoEviction
+            )
+            .await?,
+            Value::BulkString("embstr".into())
+        );
+        assert_eq!(
+            object_value(
+                &args(&["IDLETIME", "string"]),
+                &db,
+                MaxMemoryPolicy::NoEviction
+            )
+            .await?,
+            Value::Integer(0)
+        );
+        // Access frequency is only tracked under an LFU policy, and idle time only
+        // under the others
+        assert!(matches!(
+            object_value(&args(&["FREQ", "number"]), &db, MaxMemoryPolicy::NoEviction).await?,
+            Value::SimpleError(message) if message.starts_with("ERR An LFU maxmemory policy is not selected")
+        ));
+        assert_eq!(
+            object_value(&args(&["FREQ", "number"]), &db, MaxMemoryPolicy::AllKeysLfu).await?,
+            Value::Integer(5)
+        );
+        assert!(matches!(
+            object_value(&args(&["IDLETIME", "number"]), &db, MaxMemoryPolicy::VolatileLfu).await?,
+            Value::SimpleError(message) if message.starts_with("ERR An LFU maxmemory policy is selected")
+        ));
+        assert_eq!(
+            object_value(
+                &args(&["REFCOUNT", "string"]),
+                &db,
+                MaxMemoryPolicy::NoEviction
+            )
+            .await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            object_value(
+                &args(&["ENCODING", "missing"]),
+                &db,
+                MaxMemoryPolicy::NoEviction
+            )
+            .await?,
+            Value::Null
+        );
+        assert_eq!(
+            object_value(&args(&["SIZE", "string"]), &db, MaxMemoryPolicy::NoEviction).await?,
+            Value::SimpleError("ERR unknown subcommand 'size'. Try OBJECT HELP.".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_renamenx() -> Result<()> {
+        let db = Arc::new(ShardedDb::new());
+        let mut expiring = Entry::new(DataType::String("value".into()));
+        expiring.expires_at = Some(SystemClock.now_millis() + 60_000);
+        db.write()
+            .await
+            .insert_entry("source".to_owned(), expiring.clone());
+        insert(&db, "taken").await;
+
+        // The TTL moves along with the value
+        assert_eq!(
+            rename_value(&args(&["source", "taken"]), &db, false).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert!(db.read().await.get("source").is_none());
+        assert_eq!(db.read().await.get_entry("taken"), Some(&expiring));
+
+        insert(&db, "other").await;
+        assert_eq!(
+            rename_value(&args(&["taken", "other"]), &db, true).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            rename_value(&args(&["taken", "free"]), &db, true).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            rename_value(&args(&["free", "free"]), &db, false).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(db.read().await.get_entry("free"), Some(&expiring));
+
+        assert_eq!(
+            rename_value(&args(&["missing", "other"]), &db, false).await?,
+            Value::SimpleError("ERR no such key".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sort() -> Result<()> {
+        let db = Arc::new(ShardedDb::new());
+        let bulk = |text: &str| Value::BulkString(text.to_owned().into());
+        let list = |items: &[&str]| Value::Array(items.iter().map(|item| bulk(item)).collect());
+        {
+            let mut instance = db.write().await;
+            instance.insert_entry(
+                "ids".to_owned(),
+                Entry::new(DataType::List(
+                    ["3", "1", "10", "2"].map(Bytes::from).into(),
+                )),
+            );
+            for (id, weight, name) in [("1", "40", "one"), ("2", "30", "two"), ("3", "20", "three")]
+            {
+                instance.insert_entry(
+                    format!("weight_{}", id),
+                    Entry::new(DataType::String(weight.to_owned().into())),
+                );
+                instance.insert_entry(
+                    format!("user:{}", id),
+                    Entry::new(DataType::Hash(
+                        [("name".to_owned(), Bytes::from(name))].into(),
+                    )),
+                );
+            }
+            instance.insert_entry(
+                "text".to_owned(),
+                Entry::new(DataType::String("value".into())),
+            );
+        }
+
+        assert_eq!(
+            sort_value(&args(&["ids"]), &db).await?,
+            list(&["1", "2", "3", "10"])
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "ALPHA", "DESC"]), &db).await?,
+            list(&["3", "2", "10", "1"])
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "LIMIT", "1", "2"]), &db).await?,
+            list(&["2", "3"])
+        );
+        // 10 has no weight, so it counts as 0
+        assert_eq!(
+            sort_value(&args(&["ids", "BY", "weight_*"]), &db).await?,
+            list(&["10", "3", "2", "1"])
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "BY", "nosort"]), &db).await?,
+            list(&["3", "1", "10", "2"])
+        );
+        assert_eq!(
+            sort_value(
+                &args(&["ids", "LIMIT", "0", "2", "GET", "#", "GET", "user:*->name"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![bulk("1"), bulk("one"), bulk("2"), bulk("two")])
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "DESC", "GET", "user:*->name"]), &db).await?,
+            Value::Array(vec![Value::Null, bulk("three"), bulk("two"), bulk("one")])
+        );
+
+        // STORE replaces the destination with a list, missing values as empty strings
+        assert_eq!(
+            sort_value(
+                &args(&["ids", "GET", "user:*->name", "STORE", "names"]),
+                &db
+            )
+            .await?,
+            Value::Integer(4)
+        );
+        assert_eq!(
+            db.read().await.get("names"),
+            Some(&DataType::List(
+                ["one", "two", "three", ""].map(Bytes::from).into()
+            ))
+        );
+        assert_eq!(
+            sort_value(&args(&["missing", "STORE", "names"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert!(db.read().await.get("names").is_none());
+
+        assert_eq!(
+            sort_value(&args(&["user:1", "ALPHA"]), &db).await?,
+            CommandError::WrongType.into()
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "BY", "user:*"]), &db).await?,
+            list(&["3", "1", "10", "2"])
+        );
+        db.write().await.insert_entry(
+            "words".to_owned(),
+            Entry::new(DataType::List(["b", "a"].map(Bytes::from).into())),
+        );
+        assert_eq!(
+            sort_value(&args(&["words"]), &db).await?,
+            Value::SimpleError("ERR One or more scores can't be converted into double".to_owned())
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "LIMIT", "one", "2"]), &db).await?,
+            CommandError::NotInteger.into()
+        );
+        assert_eq!(
+            sort_value(&args(&["ids", "SIDEWAYS"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dump_and_restore() -> Result<()> {
+        let db = Arc::new(ShardedDb::new());
+        insert(&db, "source").await;
+        let ok = Value::SimpleString("OK".to_owned());
+
+        let Value::BulkString(payload) = dump_value(&args(&["source"]), &db).await? else {
+            panic!("expected a payload");
+        };
+        assert_eq!(dump_value(&args(&["missing"]), &db).await?, Value::Null);
+
+        let restore = |key: &str, ttl: &str, options: &[&str]| {
+            let mut restore_args = args(&[key, ttl]);
+            restore_args.push(Value::BulkString(payload.clone()));
+            restore_args.extend(args(options));
+            restore_args
+        };
+        assert_eq!(restore_value(&restore("copy", "0", &[]), &db).await?, ok);
+        assert_eq!(
+            db.read().await.get("copy"),
+            Some(&DataType::String("source".into()))
+        );
+        assert_eq!(
+            restore_value(&restore("copy", "0", &[]), &db).await?,
+            Value::SimpleError("BUSYKEY Target key name already exists.".to_owned())
+        );
+        assert_eq!(
+            restore_value(&restore("copy", "60000", &["REPLACE"]), &db).await?,
+            ok
+        );
+        assert!(db.read().await.ttl_millis("copy").unwrap().is_some());
+
+        // An absolute TTL in the past leaves no key behind
+        assert_eq!(
+            restore_value(&restore("copy", "1", &["REPLACE", "ABSTTL"]), &db).await?,
+            ok
+        );
+        assert!(db.read().await.get("copy").is_none());
+
+        assert_eq!(
+            restore_value(&args(&["copy", "0", "garbage"]), &db).await?,
+            Value::SimpleError("ERR DUMP payload version or checksum are wrong".to_owned())
+        );
+        assert_eq!(
+            restore_value(&restore("copy", "-1", &[]), &db).await?,
+            Value::SimpleError("ERR Invalid TTL value, must be >= 0".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,709 @@
+use bytes::Bytes;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::commands::zset::normalize_range;
+use crate::connection::dispatch::Caller;
+use crate::connection::{integer_arg, run_command, unpack_bulk_string};
+use crate::error::{CommandError, Error, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+
+pub mod tests_list;
+
+/// The end of a list a command pushes to or pops from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+// Pops up to `count` elements, removing the key once the list is empty
+fn pop_elements(
+    instance: &mut impl StorageMut,
+    key: &str,
+    end: ListEnd,
+    count: usize,
+) -> Vec<Bytes> {
+    let Ok(Some(list)) = instance.get_list_mut(key) else {
+        return Vec::new();
+    };
+
+    let popped = (0..count)
+        .map_while(|_| match end {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        })
+        .collect();
+    // A list never outlives its last element
+    if list.is_empty() {
+        instance.remove(key);
+    }
+    popped
+}
+
+// LPUSH and RPUSH. Each element is pushed in turn, so LPUSH reverses their order
+pub async fn push_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    end: ListEnd,
+) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let elements = args[1..]
+        .iter()
+        .map(|element| match element {
+            Value::BulkString(element) => Ok(element.clone()),
+            _ => Err(Error::InvalidArgument("Invalid element type")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Acquire a write lock on the database instance, creating the list on first write
+    let mut instance = db_instance.write_key(&key).await;
+    let list = match instance.get_or_create_list(&key) {
+        Ok(list) => list,
+        Err(err) => return Ok(err.into()),
+    };
+
+    for element in elements {
+        match end {
+            ListEnd::Left => list.push_front(element),
+            ListEnd::Right => list.push_back(element),
+        }
+    }
+    let len = list.len();
+    // Give clients blocked on the key a chance at every element
+    instance.wake_blocked(&key, len);
+
+    Ok(Value::Integer(len as i64))
+}
+
+// LPOP and RPOP key [count]. With a count the reply is an array
+pub async fn pop_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    end: ListEnd,
+) -> Result<Value> {
+    if args.is_empty() || args.len() > 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let count = match args.get(1).map(integer_arg) {
+        None => None,
+        Some(Some(count)) if count >= 0 => Some(count as usize),
+        Some(_) => {
+            return Ok(Value::SimpleError(
+                "ERR value is out of range, must be positive".to_owned(),
+            ))
+        }
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    match instance.get_list(&key) {
+        Ok(Some(_)) => {}
+        Ok(None) if count.is_some() => return Ok(Value::NullArray),
+        Ok(None) => return Ok(Value::Null),
+        Err(err) => return Ok(err.into()),
+    }
+
+    let popped = pop_elements(&mut instance, &key, end, count.unwrap_or(1));
+    match count {
+        Some(_) => Ok(Value::Array(
+            popped.into_iter().map(Value::BulkString).collect(),
+        )),
+        None => Ok(popped
+            .into_iter()
+            .next()
+            .map_or(Value::Null, Value::BulkString)),
+    }
+}
+
+pub async fn llen_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_list(&key) {
+        Ok(list) => Ok(Value::Integer(list.map_or(0, |list| list.len()) as i64)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn lrange_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Some(start), Some(stop)) = (integer_arg(&args[1]), integer_arg(&args[2])) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let list = match instance.get_list(&key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Ok(Value::Array(vec![])),
+        Err(err) => return Ok(err.into()),
+    };
+
+    let Some((start, stop)) = normalize_range(start, stop, list.len()) else {
+        return Ok(Value::Array(vec![]));
+    };
+    Ok(Value::Array(
+        list.range(start..=stop)
+            .map(|element| Value::BulkString(element.clone()))
+            .collect(),
+    ))
+}
+
+/// LPOS key element [RANK rank] [COUNT count] [MAXLEN len] finds where an element
+/// occurs. RANK picks the nth match, counting from the tail when negative; COUNT
+/// returns that many matches as an array, all of them for 0; MAXLEN stops after
+/// comparing that many elements.
+pub async fn lpos_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, element, options @ ..] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    if options.len() % 2 != 0 {
+        return Ok(CommandError::Syntax.into());
+    }
+
+    let (mut rank, mut count, mut maxlen) = (1, None, 0);
+    for pair in options.chunks(2) {
+        let Some(value) = integer_arg(&pair[1]) else {
+            return Ok(CommandError::NotInteger.into());
+        };
+        match unpack_bulk_string(pair[0].clone())?.to_uppercase().as_str() {
+            "RANK" if value == 0 || value == i64::MIN => {
+                return Ok(Value::SimpleError(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list"
+                        .to_owned(),
+                ))
+            }
+            "RANK" => rank = value,
+            "COUNT" if value < 0 => {
+                return Ok(Value::SimpleError("ERR COUNT can't be negative".to_owned()))
+            }
+            "COUNT" => count = Some(value as usize),
+            "MAXLEN" if value < 0 => {
+                return Ok(Value::SimpleError("ERR MAXLEN can't be negative".to_owned()))
+            }
+            "MAXLEN" => maxlen = value as usize,
+            _ => return Ok(CommandError::Syntax.into()),
+        }
+    }
+    let key = unpack_bulk_string(key.clone())?;
+    let Value::BulkString(element) = element else {
+        return Ok(CommandError::Syntax.into());
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let list = match instance.get_list(&key) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return Ok(Value::Array(vec![])),
+        Ok(None) => return Ok(Value::Null),
+        Err(err) => return Ok(err.into()),
+    };
+    let len = list.len();
+    let compared = match maxlen {
+        0 => len,
+        maxlen => maxlen.min(len),
+    };
+    // Positions in scanning order, skipping the matches before the requested rank
+    let indexes: Box<dyn Iterator<Item = usize>> = match rank > 0 {
+        true => Box::new(0..compared),
+        false => Box::new((len - compared..len).rev()),
+    };
+    let matches = indexes
+        .filter(|&index| list[index] == *element)
+        .skip(rank.unsigned_abs() as usize - 1);
+
+    Ok(match count {
+        None => matches
+            .map(|index| Value::Integer(index as i64))
+            .next()
+            .unwrap_or(Value::Null),
+        Some(count) => Value::Array(
+            matches
+                .take(if count == 0 { usize::MAX } else { count })
+                .map(|index| Value::Integer(index as i64))
+                .collect(),
+        ),
+    })
+}
+
+/// LINSERT key BEFORE|AFTER pivot element inserts next to the first occurrence of the
+/// pivot. Replies with the new length, -1 when the pivot is missing and 0 when the
+/// key is.
+pub async fn linsert_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, position, Value::BulkString(pivot), Value::BulkString(element)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let after = match unpack_bulk_string(position.clone())?
+        .to_uppercase()
+        .as_str()
+    {
+        "BEFORE" => false,
+        "AFTER" => true,
+        _ => return Ok(CommandError::Syntax.into()),
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    match instance.get_list(&key) {
+        Ok(Some(list)) if !list.contains(pivot) => return Ok(Value::Integer(-1)),
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(Value::Integer(0)),
+        Err(err) => return Ok(err.into()),
+    }
+    let Ok(Some(list)) = instance.get_list_mut(&key) else {
+        return Ok(Value::Integer(0));
+    };
+    if let Some(index) = list.iter().position(|current| current == pivot) {
+        list.insert(index + after as usize, element.clone());
+    }
+    Ok(Value::Integer(list.len() as i64))
+}
+
+/// LSET key index element replaces the element at an index, negative ones counting
+/// from the tail.
+pub async fn lset_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, index, Value::BulkString(element)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let Some(index) = integer_arg(index) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    match instance.get_list(&key) {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(Value::SimpleError("ERR no such key".to_owned())),
+        Err(err) => return Ok(err.into()),
+    }
+    let Ok(Some(list)) = instance.get_list_mut(&key) else {
+        return Ok(Value::SimpleError("ERR no such key".to_owned()));
+    };
+    let index = match index < 0 {
+        true => list.len() as i64 + index,
+        false => index,
+    };
+    match usize::try_from(index)
+        .ok()
+        .and_then(|index| list.get_mut(index))
+    {
+        Some(current) => {
+            *current = element.clone();
+            Ok(Value::SimpleString("OK".to_owned()))
+        }
+        None => Ok(Value::SimpleError("ERR index out of range".to_owned())),
+    }
+}
+
+/// LREM key count element removes occurrences of an element: the first `count` from
+/// the head when positive, from the tail when negative, and all of them for 0.
+/// Replies with how many were removed.
+pub async fn lrem_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, count, Value::BulkString(element)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let Some(count) = integer_arg(count) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    match instance.get_list(&key) {
+        Ok(Some(list)) if !list.contains(element) => return Ok(Value::Integer(0)),
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(Value::Integer(0)),
+        Err(err) => return Ok(err.into()),
+    }
+    let Ok(Some(list)) = instance.get_list_mut(&key) else {
+        return Ok(Value::Integer(0));
+    };
+
+    // The positions to remove, counted from the end the count starts at
+    let mut matches: Vec<usize> = (0..list.len())
+        .filter(|&index| list[index] == *element)
+        .collect();
+    if count < 0 {
+        matches.reverse();
+    }
+    if count != 0 {
+        matches.truncate(count.unsigned_abs() as usize);
+    }
+    matches.sort_unstable();
+    let mut index = 0;
+    list.retain(|_| {
+        index += 1;
+        matches.binary_search(&(index - 1)).is_err()
+    });
+    let removed = matches.len();
+    if list.is_empty() {
+        instance.remove(&key);
+    }
+    Ok(Value::Integer(removed as i64))
+}
+
+/// LTRIM key start stop keeps only the elements in the range, with LRANGE's inclusive and
+/// negative indexes. A range that keeps nothing deletes the list.
+pub async fn ltrim_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Some(start), Some(stop)) = (integer_arg(&args[1]), integer_arg(&args[2])) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    let len = match instance.get_list(&key) {
+        Ok(Some(list)) => list.len(),
+        Ok(None) => return Ok(Value::SimpleString("OK".to_owned())),
+        Err(err) => return Ok(err.into()),
+    };
+    match normalize_range(start, stop, len) {
+        Some((start, stop)) => {
+            if let Ok(Some(list)) = instance.get_list_mut(&key) {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+        }
+        None => {
+            instance.remove(&key);
+        }
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+// LEFT or RIGHT, as LMOVE takes them
+fn parse_end(arg: &Value) -> Result<Option<ListEnd>> {
+    Ok(
+        match unpack_bulk_string(arg.clone())?.to_uppercase().as_str() {
+            "LEFT" => Some(ListEnd::Left),
+            "RIGHT" => Some(ListEnd::Right),
+            _ => None,
+        },
+    )
+}
+
+// Pops from one end of `source` and pushes to one end of `destination`, which may be
+// the same list to rotate it. Both keys are locked by the caller. None when the source
+// is empty; the error is the WRONGTYPE reply
+fn move_element(
+    instance: &mut impl StorageMut,
+    source: &str,
+    destination: &str,
+    from: ListEnd,
+    to: ListEnd,
+) -> std::result::Result<Option<Bytes>, Value> {
+    if instance.get_list(source)?.is_none() {
+        return Ok(None);
+    }
+    instance.get_list(destination)?;
+
+    let Some(element) = pop_elements(instance, source, from, 1).pop() else {
+        return Ok(None);
+    };
+    let list = instance.get_or_create_list(destination)?;
+    match to {
+        ListEnd::Left => list.push_front(element.clone()),
+        ListEnd::Right => list.push_back(element.clone()),
+    }
+    instance.wake_blocked(destination, 1);
+    Ok(Some(element))
+}
+
+/// LMOVE source destination LEFT|RIGHT LEFT|RIGHT, and RPOPLPUSH source destination
+/// as LMOVE with RIGHT LEFT. Replies with the moved element, or null when the source
+/// is empty.
+pub async fn lmove_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [source, destination, from, to] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let (Some(from), Some(to)) = (parse_end(from)?, parse_end(to)?) else {
+        return Ok(CommandError::Syntax.into());
+    };
+    move_value(source, destination, from, to, db_instance).await
+}
+
+pub async fn rpoplpush_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [source, destination] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    move_value(
+        source,
+        destination,
+        ListEnd::Right,
+        ListEnd::Left,
+        db_instance,
+    )
+    .await
+}
+
+async fn move_value(
+    source: &Value,
+    destination: &Value,
+    from: ListEnd,
+    to: ListEnd,
+    db_instance: &Arc<ShardedDb>,
+) -> Result<Value> {
+    let source = unpack_bulk_string(source.clone())?;
+    let destination = unpack_bulk_string(destination.clone())?;
+
+    // One acquisition covers both lists, so no client sees the element in neither
+    let mut instance = db_instance.write_keys(&[&source, &destination]).await;
+    Ok(
+        match move_element(&mut instance, &source, &destination, from, to) {
+            Ok(Some(element)) => Value::BulkString(element),
+            Ok(None) => Value::Null,
+            Err(reply) => reply,
+        },
+    )
+}
+
+// Checks BLMOVE's arguments, the first four being LMOVE's, and returns the timeout;
+// the error is the reply to send
+fn parse_blmove_args(args: &[Value]) -> Result<std::result::Result<Option<Duration>, Value>> {
+    let [source, _, from, to, timeout] = args else {
+        return Ok(Err(CommandError::WrongArity.into()));
+    };
+    if parse_end(from)?.is_none() || parse_end(to)?.is_none() {
+        return Ok(Err(CommandError::Syntax.into()));
+    }
+    Ok(parse_blocking_args(&[source.clone(), timeout.clone()])?.map(|parsed| parsed.timeout))
+}
+
+/// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout without blocking, as
+/// inside EXEC: the same as LMOVE.
+pub async fn blmove_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    match parse_blmove_args(args)? {
+        Ok(_) => lmove_value(&args[..4], db_instance).await,
+        Err(reply) => Ok(reply),
+    }
+}
+
+/// BLMOVE for a client: waits like BLPOP for the source list to get an element, then
+/// moves it with a plain LMOVE, which is what the AOF and replicas see.
+pub async fn blocking_lmove_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+) -> Result<Value> {
+    let timeout = match parse_blmove_args(args)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let keys = vec![unpack_bulk_string(args[0].clone())?];
+    let deadline = deadline_after(timeout);
+    let db_instance = Arc::clone(&state.databases[*selected]);
+    let waiter = Arc::new(Notify::new());
+
+    loop {
+        // Queue up before looking, so a push in between still wakes us
+        let mut instance = db_instance.write_keys(&keys).await;
+        instance.block(&keys, &waiter);
+        let ready = first_ready_key(&instance, &keys);
+        drop(instance);
+
+        let moved = match ready {
+            Ok(Some(_)) => {
+                let _shared = state.exec_lock.read().await;
+                run_command(UserCommand::LMove, &args[..4], state, selected, caller).await?
+            }
+            Ok(None) => Value::Null,
+            Err(reply) => reply,
+        };
+        // Another client may have emptied the list first, in which case we wait again
+        if moved != Value::Null {
+            db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+            return Ok(moved);
+        }
+
+        let woken = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, waiter.notified())
+                .await
+                .is_ok(),
+            None => {
+                waiter.notified().await;
+                true
+            }
+        };
+        db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+        if !woken {
+            return Ok(Value::Null);
+        }
+    }
+}
+
+/// The arguments of BLPOP, BRPOP and the other commands that take keys and a timeout.
+pub struct BlockingArgs {
+    pub keys: Vec<String>,
+    /// None when given as 0, meaning forever
+    pub timeout: Option<Duration>,
+}
+
+/// Splits the arguments into the keys and the timeout; the error is the reply to send.
+pub fn parse_blocking_args(args: &[Value]) -> Result<std::result::Result<BlockingArgs, Value>> {
+    let Some((timeout, keys)) = args.split_last().filter(|(_, keys)| !keys.is_empty()) else {
+        return Ok(Err(CommandError::WrongArity.into()));
+    };
+
+    let timeout = match unpack_bulk_string(timeout.clone())?.parse::<f64>() {
+        Ok(timeout) if timeout < 0.0 => {
+            return Ok(Err(Value::SimpleError(
+                "ERR timeout is negative".to_owned(),
+            )))
+        }
+        Ok(timeout) if timeout.is_finite() => timeout,
+        _ => {
+            return Ok(Err(Value::SimpleError(
+                "ERR timeout is not a float or out of range".to_owned(),
+            )))
+        }
+    };
+    let timeout = match Duration::try_from_secs_f64(timeout) {
+        Ok(duration) => (timeout > 0.0).then_some(duration),
+        Err(_) => {
+            return Ok(Err(Value::SimpleError(
+                "ERR timeout is out of range".to_owned(),
+            )))
+        }
+    };
+    let keys = keys
+        .iter()
+        .map(|key| unpack_bulk_string(key.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Ok(BlockingArgs { keys, timeout }))
+}
+
+/// When a client blocking for `timeout` gives up. None, waiting forever, without a
+/// timeout or for one reaching past any time an `Instant` can hold.
+pub fn deadline_after(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.and_then(|timeout| Instant::now().checked_add(timeout))
+}
+
+// The first key holding a non-empty list; the error is the WRONGTYPE reply
+fn first_ready_key(
+    instance: &impl Storage,
+    keys: &[String],
+) -> std::result::Result<Option<String>, Value> {
+    for key in keys {
+        if instance.get_list(key)?.is_some() {
+            return Ok(Some(key.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// BLPOP and BRPOP key [key ...] timeout without blocking, as inside EXEC: pops from
+/// the first non-empty list and replies with its key and the element, or a null array.
+pub async fn bpop_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    end: ListEnd,
+) -> Result<Value> {
+    let keys = match parse_blocking_args(args)? {
+        Ok(parsed) => parsed.keys,
+        Err(reply) => return Ok(reply),
+    };
+
+    let mut instance = db_instance.write_keys(&keys).await;
+    let key = match first_ready_key(&instance, &keys) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(Value::NullArray),
+        Err(reply) => return Ok(reply),
+    };
+    let popped = pop_elements(&mut instance, &key, end, 1);
+    Ok(Value::Array(
+        std::iter::once(Value::BulkString(key.into()))
+            .chain(popped.into_iter().map(Value::BulkString))
+            .collect(),
+    ))
+}
+
+/// BLPOP and BRPOP for a client: when every list is empty the connection waits, queued
+/// behind clients that blocked on the same keys earlier, until a push wakes it or the
+/// timeout passes. Elements are taken with a plain LPOP or RPOP so that is what the AOF
+/// and replicas see.
+pub async fn blocking_pop_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+    end: ListEnd,
+) -> Result<Value> {
+    let BlockingArgs { keys, timeout } = match parse_blocking_args(args)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let pop = match end {
+        ListEnd::Left => UserCommand::LPop,
+        ListEnd::Right => UserCommand::RPop,
+    };
+    let deadline = deadline_after(timeout);
+    let db_instance = Arc::clone(&state.databases[*selected]);
+    let waiter = Arc::new(Notify::new());
+
+    loop {
+        // Queue up before looking, so a push in between still wakes us
+        let mut instance = db_instance.write_keys(&keys).await;
+        instance.block(&keys, &waiter);
+        let ready = first_ready_key(&instance, &keys);
+        drop(instance);
+
+        let popped = match ready {
+            Ok(Some(key)) => {
+                let _shared = state.exec_lock.read().await;
+                let element = run_command(
+                    pop,
+                    &[Value::BulkString(key.clone().into())],
+                    state,
+                    selected,
+                    caller,
+                )
+                .await?;
+                Some((key, element))
+            }
+            Ok(None) => None,
+            Err(reply) => {
+                db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+                return Ok(reply);
+            }
+        };
+        // Another client may have emptied the list first, in which case we wait again
+        if let Some((key, Value::BulkString(element))) = popped {
+            db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+            return Ok(Value::Array(vec![
+                Value::BulkString(key.into()),
+                Value::BulkString(element),
+            ]));
+        }
+
+        let woken = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, waiter.notified())
+                .await
+                .is_ok(),
+            None => {
+                waiter.notified().await;
+                true
+            }
+        };
+        db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+        if !woken {
+            return Ok(Value::NullArray);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::connection::wrong_type;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    fn bulks(parts: &[&str]) -> Value {
+        Value::Array(
+            parts
+                .iter()
+                .map(|part| Value::BulkString(part.to_string().into()))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_push_and_range() -> Result<()> {
+        let db = db();
+        assert_eq!(
+            push_value(&args(&["list", "b", "c"]), &db, ListEnd::Right).await?,
+            Value::Integer(2)
+        );
+        // LPUSH pushes one element at a time, reversing them
+        assert_eq!(
+            push_value(&args(&["list", "a", "z"]), &db, ListEnd::Left).await?,
+            Value::Integer(4)
+        );
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["z", "a", "b", "c"])
+        );
+        assert_eq!(
+            lrange_value(&args(&["list", "-2", "10"]), &db).await?,
+            bulks(&["b", "c"])
+        );
+        assert_eq!(
+            lrange_value(&args(&["list", "3", "1"]), &db).await?,
+            bulks(&[])
+        );
+        assert_eq!(llen_value(&args(&["list"]), &db).await?, Value::Integer(4));
+        assert_eq!(
+            llen_value(&args(&["missing"]), &db).await?,
+            Value::Integer(0)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pop() -> Result<()> {
+        let db = db();
+        push_value(&args(&["list", "a", "b", "c"]), &db, ListEnd::Right).await?;
+
+        assert_eq!(
+            pop_value(&args(&["list"]), &db, ListEnd::Left).await?,
+            Value::BulkString("a".into())
+        );
+        assert_eq!(
+            pop_value(&args(&["list", "5"]), &db, ListEnd::Right).await?,
+            bulks(&["c", "b"])
+        );
+        // The emptied list is gone
+        assert!(db.read().await.get("list").is_none());
+        assert_eq!(
+            pop_value(&args(&["list"]), &db, ListEnd::Left).await?,
+            Value::Null
+        );
+        assert_eq!(
+            pop_value(&args(&["list", "1"]), &db, ListEnd::Left).await?,
+            Value::NullArray
+        );
+        assert_eq!(
+            pop_value(&args(&["list", "-1"]), &db, ListEnd::Left).await?,
+            Value::SimpleError("ERR value is out of range, must be positive".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lpos() -> Result<()> {
+        let db = db();
+        push_value(
+            &args(&["list", "a", "b", "c", "b", "b"]),
+            &db,
+            ListEnd::Right,
+        )
+        .await?;
+        let ints =
+            |values: &[i64]| Value::Array(values.iter().copied().map(Value::Integer).collect());
+
+        assert_eq!(
+            lpos_value(&args(&["list", "b"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(lpos_value(&args(&["list", "z"]), &db).await?, Value::Null);
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "RANK", "2"]), &db).await?,
+            Value::Integer(3)
+        );
+        // A negative rank scans from the tail but reports positions from the head
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "RANK", "-1"]), &db).await?,
+            Value::Integer(4)
+        );
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "COUNT", "0"]), &db).await?,
+            ints(&[1, 3, 4])
+        );
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "RANK", "-1", "COUNT", "2"]), &db).await?,
+            ints(&[4, 3])
+        );
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "COUNT", "0", "MAXLEN", "4"]), &db).await?,
+            ints(&[1, 3])
+        );
+        assert_eq!(
+            lpos_value(&args(&["missing", "b", "COUNT", "1"]), &db).await?,
+            ints(&[])
+        );
+
+        assert!(matches!(
+            lpos_value(&args(&["list", "b", "RANK", "0"]), &db).await?,
+            Value::SimpleError(message) if message.starts_with("ERR RANK can't be zero")
+        ));
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "COUNT", "-1"]), &db).await?,
+            Value::SimpleError("ERR COUNT can't be negative".to_owned())
+        );
+        assert_eq!(
+            lpos_value(&args(&["list", "b", "SOON", "1"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_linsert_and_lset() -> Result<()> {
+        let db = db();
+        push_value(&args(&["list", "a", "c"]), &db, ListEnd::Right).await?;
+
+        assert_eq!(
+            linsert_value(&args(&["list", "BEFORE", "c", "b"]), &db).await?,
+            Value::Integer(3)
+        );
+        assert_eq!(
+            linsert_value(&args(&["list", "after", "c", "d"]), &db).await?,
+            Value::Integer(4)
+        );
+        assert_eq!(
+            linsert_value(&args(&["list", "BEFORE", "z", "y"]), &db).await?,
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            linsert_value(&args(&["missing", "BEFORE", "a", "b"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            linsert_value(&args(&["list", "NEAR", "a", "b"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+
+        let ok = Value::SimpleString("OK".to_owned());
+        assert_eq!(lset_value(&args(&["list", "0", "A"]), &db).await?, ok);
+        assert_eq!(lset_value(&args(&["list", "-1", "D"]), &db).await?, ok);
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["A", "b", "c", "D"])
+        );
+        assert_eq!(
+            lset_value(&args(&["list", "4", "x"]), &db).await?,
+            Value::SimpleError("ERR index out of range".to_owned())
+        );
+        assert_eq!(
+            lset_value(&args(&["list", "-5", "x"]), &db).await?,
+            Value::SimpleError("ERR index out of range".to_owned())
+        );
+        assert_eq!(
+            lset_value(&args(&["missing", "0", "x"]), &db).await?,
+            Value::SimpleError("ERR no such key".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lrem() -> Result<()> {
+        let db = db();
+        push_value(
+            &args(&["list", "x", "a", "x", "b", "x"]),
+            &db,
+            ListEnd::Right,
+        )
+        .await?;
+
+        assert_eq!(
+            lrem_value(&args(&["list", "-1", "x"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["x", "a", "x", "b"])
+        );
+        assert_eq!(
+            lrem_value(&args(&["list", "1", "x"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["a", "x", "b"])
+        );
+        assert_eq!(
+            lrem_value(&args(&["list", "0", "z"]), &db).await?,
+            Value::Integer(0)
+        );
+
+        // Removing every element removes the key
+        push_value(&args(&["other", "y", "y"]), &db, ListEnd::Right).await?;
+        assert_eq!(
+            lrem_value(&args(&["other", "0", "y"]), &db).await?,
+            Value::Integer(2)
+        );
+        assert!(db.read().await.get("other").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ltrim() -> Result<()> {
+        let db = db();
+        push_value(
+            &args(&["list", "a", "b", "c", "d", "e"]),
+            &db,
+            ListEnd::Right,
+        )
+        .await?;
+        let ok = Value::SimpleString("OK".to_owned());
+
+        assert_eq!(ltrim_value(&args(&["list", "1", "-1"]), &db).await?, ok);
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["b", "c", "d", "e"])
+        );
+        // Indexes past either end are clamped
+        assert_eq!(ltrim_value(&args(&["list", "-100", "-2"]), &db).await?, ok);
+        assert_eq!(
+            lrange_value(&args(&["list", "0", "-1"]), &db).await?,
+            bulks(&["b", "c", "d"])
+        );
+        assert_eq!(ltrim_value(&args(&["list", "1", "100"]), &db).await?, ok);
+        assert_eq!(
+            lrange_value(&args(&["list", "-2", "-1"]), &db).await?,
+            bulks(&["c", "d"])
+        );
+
+        // An empty range removes the key
+        assert_eq!(ltrim_value(&args(&["list", "5", "10"]), &db).await?, ok);
+        assert!(db.read().await.get("list").is_none());
+        assert_eq!(ltrim_value(&args(&["list", "0", "1"]), &db).await?, ok);
+        assert_eq!(
+            ltrim_value(&args(&["list", "a", "1"]), &db).await?,
+            CommandError::NotInteger.into()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lmove_and_rpoplpush() -> Result<()> {
+        let db = db();
+        push_value(&args(&["list", "a", "b", "c"]), &db, ListEnd::Right).await?;
+
+        assert_eq!(
+            rpoplpush_value(&args(&["list", "other"]), &db).await?,
+            Value::BulkString("c".into())
+        );
+        assert_eq!(
+            lmove_value(&args(&["list", "other", "LEFT", "RIGHT"]), &db).await?,
+            Value::BulkString("a".into())
+        );
+        assert_eq!(
+            lrange_value(&args(&["other", "0", "-1"]), &db).await?,
+            bulks(&["c", "a"])
+        );
+        // Moving the last element removes the source
+        assert_eq!(
+            lmove_value(&args(&["list", "other", "right", "left"]), &db).await?,
+            Value::BulkString("b".into())
+        );
+        assert!(db.read().await.get("list").is_none());
+        assert_eq!(
+            rpoplpush_value(&args(&["list", "other"]), &db).await?,
+            Value::Null
+        );
+
+        // The same list rotates, even with a single element
+        assert_eq!(
+            rpoplpush_value(&args(&["other", "other"]), &db).await?,
+            Value::BulkString("a".into())
+        );
+        assert_eq!(
+            lrange_value(&args(&["other", "0", "-1"]), &db).await?,
+            bulks(&["a", "b", "c"])
+        );
+        push_value(&args(&["single", "x"]), &db, ListEnd::Right).await?;
+        lmove_value(&args(&["single", "single", "LEFT", "LEFT"]), &db).await?;
+        assert_eq!(
+            lrange_value(&args(&["single", "0", "-1"]), &db).await?,
+            bulks(&["x"])
+        );
+
+        assert_eq!(
+            lmove_value(&args(&["other", "list", "UP", "LEFT"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            blmove_value(&args(&["missing", "list", "LEFT", "LEFT", "0"]), &db).await?,
+            Value::Null
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() -> Result<()> {
+        let db = db();
+        db.write().await.insert_entry(
+            "string".to_owned(),
+            Entry::new(DataType::String("value".into())),
+        );
+        for reply in [
+            push_value(&args(&["string", "a"]), &db, ListEnd::Left).await?,
+            pop_value(&args(&["string"]), &db, ListEnd::Left).await?,
+            llen_value(&args(&["string"]), &db).await?,
+            bpop_value(&args(&["missing", "string", "0"]), &db, ListEnd::Left).await?,
+            lpos_value(&args(&["string", "a"]), &db).await?,
+            lset_value(&args(&["string", "0", "a"]), &db).await?,
+            rpoplpush_value(&args(&["string", "list"]), &db).await?,
+        ] {
+            assert_eq!(reply, wrong_type());
+        }
+        // The destination is checked too, and nothing moves
+        push_value(&args(&["list", "a"]), &db, ListEnd::Left).await?;
+        assert_eq!(
+            rpoplpush_value(&args(&["list", "string"]), &db).await?,
+            wrong_type()
+        );
+        assert_eq!(llen_value(&args(&["list"]), &db).await?, Value::Integer(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bpop_without_blocking() -> Result<()> {
+        let db = db();
+        push_value(&args(&["second", "a", "b"]), &db, ListEnd::Right).await?;
+
+        assert_eq!(
+            bpop_value(&args(&["first", "second", "0"]), &db, ListEnd::Right).await?,
+            bulks(&["second", "b"])
+        );
+        assert_eq!(
+            bpop_value(&args(&["first", "0"]), &db, ListEnd::Left).await?,
+            Value::NullArray
+        );
+        assert_eq!(
+            bpop_value(&args(&["first", "-1"]), &db, ListEnd::Left).await?,
+            Value::SimpleError("ERR timeout is negative".to_owned())
+        );
+        assert_eq!(
+            bpop_value(&args(&["first", "soon"]), &db, ListEnd::Left).await?,
+            Value::SimpleError("ERR timeout is not a float or out of range".to_owned())
+        );
+        assert_eq!(
+            bpop_value(&args(&["0"]), &db, ListEnd::Left).await?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        // Too long to be a duration at all
+        assert_eq!(
+            bpop_value(&args(&["first", "1e30"]), &db, ListEnd::Left).await?,
+            Value::SimpleError("ERR timeout is out of range".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_lmove_past_any_deadline_waits_forever() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let blocked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let mut selected = 0;
+                let args = args(&["source", "target", "LEFT", "LEFT", "9223372036854775807"]);
+                blocking_lmove_value(&args, &state, &mut selected, None).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        push_value(&args(&["source", "a"]), &state.databases[0], ListEnd::Right).await?;
+        assert_eq!(blocked.await??, Value::BulkString("a".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_pop_past_any_deadline_waits_forever() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let blocked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let mut selected = 0;
+                let timeout = args(&["list", "9223372036854775807"]);
+                blocking_pop_value(&timeout, &state, &mut selected, None, ListEnd::Left).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        push_value(&args(&["list", "a"]), &state.databases[0], ListEnd::Right).await?;
+        assert_eq!(blocked.await??, bulks(&["list", "a"]));
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+pub mod acl;
+pub mod bitmap;
+pub mod client;
+pub mod cluster;
+pub mod config;
+pub mod geo;
+pub mod hash;
+pub mod keyspace;
+pub mod list;
+pub mod replication;
+pub mod server;
+pub mod set;
+pub mod stream;
+pub mod zset;
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::commands::list::deadline_after;
+use crate::connection::{integer_arg, unpack_bulk_string};
+use crate::error::{CommandError, Result};
+use crate::parser::Value;
+use crate::server::ServerState;
+
+pub mod tests_replication;
+
+/// REPLICAOF host port makes the server a replica of that master, dropping its own data
+/// once the first synchronization arrives. REPLICAOF NO ONE turns it back into a master
+/// that keeps the data it has, under a new replication id.
+pub async fn replicaof_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let [host, port] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let (host, port) = (
+        unpack_bulk_string(host.clone())?,
+        unpack_bulk_string(port.clone())?,
+    );
+
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        state.replication.promote().await;
+        return Ok(Value::SimpleString("OK".to_owned()));
+    }
+
+    let Ok(port) = port.parse::<u16>() else {
+        return Ok(Value::SimpleError("ERR Invalid master port".to_owned()));
+    };
+    if state.replication.master() == Some((host.clone(), port)) {
+        return Ok(Value::SimpleString(
+            "OK Already connected to specified master".to_owned(),
+        ));
+    }
+    state.replication.replicate(state, host, port);
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+/// The port of a `REPLCONF listening-port <port>`, which a replica sends so its master
+/// knows where it accepts connections.
+pub fn listening_port(args: &[Value]) -> Option<u16> {
+    args.chunks(2).find_map(|pair| match pair {
+        [option, port]
+            if unpack_bulk_string(option.clone())
+                .ok()?
+                .eq_ignore_ascii_case("listening-port") =>
+        {
+            unpack_bulk_string(port.clone()).ok()?.parse().ok()
+        }
+        _ => None,
+    })
+}
+
+/// FAILOVER [TO host port] [TIMEOUT milliseconds] hands the master role over to a
+/// replica: the one at host and port, or else the one furthest along. Clients are held
+/// off until the replica caught up with every write, or the timeout passed, which
+/// aborts the failover. The replica is then promoted with REPLICAOF NO ONE and this
+/// server follows it, continuing its stream. Unlike in Redis the reply waits for all
+/// of it.
+pub async fn failover_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let mut target = None;
+    let mut timeout = None;
+    let mut rest = args;
+    while let Some(option) = rest.first() {
+        match (
+            unpack_bulk_string(option.clone())?.to_uppercase().as_str(),
+            &rest[1..],
+        ) {
+            ("TO", [host, port, ..]) => {
+                let Ok(port) = unpack_bulk_string(port.clone())?.parse::<u16>() else {
+                    return Ok(Value::SimpleError("ERR Invalid target port".to_owned()));
+                };
+                target = Some((unpack_bulk_string(host.clone())?, port));
+                rest = &rest[3..];
+            }
+            ("TIMEOUT", [milliseconds, ..]) => {
+                match integer_arg(milliseconds) {
+                    Some(milliseconds) if milliseconds > 0 => {
+                        timeout = Some(Duration::from_millis(milliseconds as u64))
+                    }
+                    _ => {
+                        return Ok(Value::SimpleError(
+                            "ERR FAILOVER timeout must be greater than 0".to_owned(),
+                        ))
+                    }
+                }
+                rest = &rest[2..];
+            }
+            _ => return Ok(CommandError::Syntax.into()),
+        }
+    }
+
+    let replication = &state.replication;
+    if replication.master().is_some() {
+        return Ok(Value::SimpleError(
+            "ERR FAILOVER is not valid when server is a replica.".to_owned(),
+        ));
+    }
+    let deadline = deadline_after(timeout);
+    // Nothing runs until the failover is over, so no write can slip in after the
+    // replica caught up
+    let _exclusive = state.exec_lock.write().await;
+    let (id, (host, port), offset) = {
+        let mut feed = replication.lock_feed().await;
+        let Some(chosen) = feed.failover_target(target.as_ref()) else {
+            return Ok(Value::SimpleError(match target {
+                Some(_) => "ERR FAILOVER target HOST and PORT is not a replica.".to_owned(),
+                None => "ERR FAILOVER requires connected replicas.".to_owned(),
+            }));
+        };
+        let offset = feed.offset();
+        feed.request_acks();
+        (chosen.0, chosen.1, offset)
+    };
+    if !replication.wait_for_replica(id, offset, deadline).await {
+        return Ok(Value::SimpleError(
+            "ERR FAILOVER target replica did not catch up in time".to_owned(),
+        ));
+    }
+
+    let promoted = async {
+        Client::connect((host.as_str(), port))
+            .await?
+            .command(&["REPLICAOF", "NO", "ONE"])
+            .await
+    };
+    if let Err(err) = promoted.await {
+        return Ok(Value::SimpleError(format!(
+            "ERR FAILOVER could not promote {}:{}: {}",
+            host, port, err
+        )));
+    }
+    replication.demote(state, host, port).await;
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+/// REPLCONF option value [option value ...], sent by replicas during the handshake.
+pub fn replconf_value(args: &[Value]) -> Result<Value> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Ok(CommandError::WrongArity.into());
+    }
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+/// WAIT numreplicas timeout blocks until `numreplicas` replicas have acknowledged every
+/// write made so far, or `timeout` milliseconds pass (0 waits forever), and replies with
+/// how many did. Without `blocking`, as inside EXEC, it only counts them.
+pub async fn wait_value(args: &[Value], state: &ServerState, blocking: bool) -> Result<Value> {
+    let [needed, timeout] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let (Some(needed), Some(timeout)) = (integer_arg(needed), integer_arg(timeout)) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    if timeout < 0 {
+        return Ok(Value::SimpleError("ERR timeout is negative".to_owned()));
+    }
+
+    let replication = &state.replication;
+    let offset = {
+        let mut feed = replication.lock_feed().await;
+        let offset = feed.offset();
+        if !blocking || feed.acked_replicas(offset) >= needed.max(0) as usize {
+            return Ok(Value::Integer(feed.acked_replicas(offset) as i64));
+        }
+        feed.request_acks();
+        offset
+    };
+
+    let deadline = deadline_after((timeout > 0).then(|| Duration::from_millis(timeout as u64)));
+    let acked = replication
+        .wait_for_acks(offset, needed as usize, deadline)
+        .await;
+    Ok(Value::Integer(acked as i64))
+}
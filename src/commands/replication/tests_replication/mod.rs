@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::testing::args;
+
+    #[tokio::test]
+    async fn test_replicaof_and_no_one() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let ok = Value::SimpleString("OK".to_owned());
+
+        // Nothing listens on port 1, so the link fails but the role is still recorded
+        assert_eq!(
+            replicaof_value(&args(&["127.0.0.1", "1"]), &state).await?,
+            ok
+        );
+        assert_eq!(
+            state.replication.master(),
+            Some(("127.0.0.1".to_owned(), 1))
+        );
+        assert_eq!(
+            replicaof_value(&args(&["127.0.0.1", "1"]), &state).await?,
+            Value::SimpleString("OK Already connected to specified master".to_owned())
+        );
+
+        assert_eq!(replicaof_value(&args(&["no", "ONE"]), &state).await?, ok);
+        assert_eq!(state.replication.master(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replicaof_errors() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        assert_eq!(
+            replicaof_value(&args(&["127.0.0.1", "port"]), &state).await?,
+            Value::SimpleError("ERR Invalid master port".to_owned())
+        );
+        assert_eq!(
+            replicaof_value(&args(&["127.0.0.1"]), &state).await?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        assert_eq!(state.replication.master(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replconf() -> Result<()> {
+        assert_eq!(
+            replconf_value(&args(&["listening-port", "6380"]))?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            replconf_value(&args(&["capa"]))?,
+            Value::SimpleError("ERR wrong number of arguments".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_without_replicas() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        assert_eq!(
+            wait_value(&args(&["0", "0"]), &state, true).await?,
+            Value::Integer(0)
+        );
+        // Inside EXEC it never blocks, even for replicas that do not exist
+        assert_eq!(
+            wait_value(&args(&["1", "0"]), &state, false).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            wait_value(&args(&["1", "10"]), &state, true).await?,
+            Value::Integer(0)
+        );
+
+        assert_eq!(
+            wait_value(&args(&["1", "-1"]), &state, true).await?,
+            Value::SimpleError("ERR timeout is negative".to_owned())
+        );
+        assert_eq!(
+            wait_value(&args(&["one", "0"]), &state, true).await?,
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_past_any_deadline_waits_forever() {
+        let state = ServerState::new(Config::new());
+        let waiting = tokio::spawn({
+            let state = state.clone();
+            async move { wait_value(&args(&["1", "9223372036854775807"]), &state, true).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        waiting.abort();
+    }
+}
@@ -0,0 +1,606 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::connection::{integer_arg, unpack_bulk_string};
+use crate::error::{unknown_subcommand, CommandError, Result};
+use crate::parser::{CommandSpec, UserCommand, Value, COMMANDS};
+use crate::persistence::{json, rdb};
+use crate::server::ServerState;
+use crate::storage::sharded::{Storage, StorageMut};
+use crate::storage::{KeyspaceStats, MemoryStats};
+use crate::{notice, warning};
+
+pub mod tests_server;
+
+pub async fn save_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let path = state.config.read().await.rdb_path();
+    let keyspace = rdb::snapshot(&state.databases).await;
+    match rdb::save(&keyspace, &path).await {
+        Ok(()) => Ok(Value::SimpleString("OK".to_owned())),
+        Err(err) => Ok(Value::SimpleError(format!("ERR {:#}", err))),
+    }
+}
+
+pub async fn bgsave_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    if state.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+        return Ok(Value::SimpleError(
+            "ERR Background save already in progress".to_owned(),
+        ));
+    }
+
+    // Copy the keyspace now; encoding and writing happen without holding any lock
+    let path = state.config.read().await.rdb_path();
+    let keyspace = rdb::snapshot(&state.databases).await;
+    let in_progress = Arc::clone(&state.bgsave_in_progress);
+    tokio::spawn(async move {
+        match rdb::save(&keyspace, &path).await {
+            Ok(()) => notice!("Background saving terminated with success"),
+            Err(err) => warning!("Background saving failed: {:#}", err),
+        }
+        in_progress.store(false, Ordering::SeqCst);
+    });
+
+    Ok(Value::SimpleString("Background saving started".to_owned()))
+}
+
+pub async fn bgrewriteaof_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let Some(aof) = state.aof.as_ref() else {
+        return Ok(Value::SimpleError(
+            "ERR Append only file is not enabled".to_owned(),
+        ));
+    };
+    let _ordered = state.write_order.lock_all().await;
+    if !aof.start_rewrite(&state.databases).await {
+        return Ok(Value::SimpleError(
+            "ERR Background append only file rewriting already in progress".to_owned(),
+        ));
+    }
+    Ok(Value::SimpleString(
+        "Background append only file rewriting started".to_owned(),
+    ))
+}
+
+/// SHUTDOWN [NOSAVE|SAVE] stops the server the way a signal does. Without an option
+/// a snapshot is saved unless the AOF is enabled. Returns None once the shutdown is
+/// under way, as the connection closes without a reply.
+pub async fn shutdown_value(args: &[Value], state: &ServerState) -> Result<Option<Value>> {
+    let save = match args {
+        [] => !state.config.read().await.appendonly,
+        [option] => match unpack_bulk_string(option.clone())?.to_uppercase().as_str() {
+            "SAVE" => true,
+            "NOSAVE" => false,
+            _ => return Ok(Some(CommandError::Syntax.into())),
+        },
+        _ => return Ok(Some(CommandError::Syntax.into())),
+    };
+    state.shutdown.request(save);
+    Ok(None)
+}
+
+/// DEBUG SLEEP seconds stalls every client for that long, as a slow command would,
+/// which is handy for testing timeouts. With `exclusive` the execution lock is taken
+/// here; inside EXEC the transaction already holds it. DEBUG OBJECT key describes how
+/// a key is stored, DEBUG SET-ACTIVE-EXPIRE 0|1 pauses or resumes the background
+/// expiry of keys, and DEBUG QUICKLIST-PACKED-THRESHOLD size is accepted for test
+/// suites that set it, though lists have no packed nodes here. DEBUG JSON-DUMP reports
+/// every database as a JSON document, and DEBUG JSON-LOAD document replaces them with
+/// one, under the execution lock like SLEEP. Neither the AOF nor replicas see the load.
+pub async fn debug_value(
+    args: &[Value],
+    state: &ServerState,
+    db: usize,
+    exclusive: bool,
+) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let ok = Value::SimpleString("OK".to_owned());
+    match unpack_bulk_string(subcommand.clone())?
+        .to_uppercase()
+        .as_str()
+    {
+        "SLEEP" => {
+            let [seconds] = &args[1..] else {
+                return Ok(CommandError::WrongArity.into());
+            };
+            let Some(seconds) = unpack_bulk_string(seconds.clone())?
+                .parse::<f64>()
+                .ok()
+                .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            else {
+                return Ok(CommandError::NotFloat.into());
+            };
+            let Ok(duration) = Duration::try_from_secs_f64(seconds) else {
+                return Ok(Value::SimpleError("ERR value is out of range".to_owned()));
+            };
+            let _exclusive = match exclusive {
+                true => Some(state.exec_lock.write().await),
+                false => None,
+            };
+            tokio::time::sleep(duration).await;
+            Ok(ok)
+        }
+        "OBJECT" => {
+            let [key] = &args[1..] else {
+                return Ok(CommandError::WrongArity.into());
+            };
+            let key = unpack_bulk_string(key.clone())?;
+            let instance = state.databases[db].read_key(&key).await;
+            let Some((entry, accessed_at)) = instance.peek_entry(&key) else {
+                return Ok(Value::SimpleError("ERR no such key".to_owned()));
+            };
+            // The LRU clock counts seconds in 24 bits, like Redis
+            Ok(Value::SimpleString(format!(
+                "Value at:{:p} refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+                &entry.value,
+                entry.value.encoding(),
+                rdb::serialized_length(&entry.value),
+                (accessed_at / 1000) & ((1 << 24) - 1),
+                state.clock.now_millis().saturating_sub(accessed_at) / 1000
+            )))
+        }
+        "SET-ACTIVE-EXPIRE" => {
+            let [enabled] = &args[1..] else {
+                return Ok(CommandError::WrongArity.into());
+            };
+            match integer_arg(enabled) {
+                Some(enabled @ (0 | 1)) => {
+                    state.active_expire.store(enabled == 1, Ordering::Relaxed);
+                    Ok(ok)
+                }
+                _ => Ok(CommandError::Syntax.into()),
+            }
+        }
+        "JSON-DUMP" => {
+            if args.len() != 1 {
+                return Ok(CommandError::WrongArity.into());
+            }
+            let keyspace = rdb::snapshot(&state.databases).await;
+            Ok(Value::BulkString(
+                json::encode(&keyspace, state.clock.now_millis()).into(),
+            ))
+        }
+        "JSON-LOAD" => {
+            let [document] = &args[1..] else {
+                return Ok(CommandError::WrongArity.into());
+            };
+            let keyspace = match json::decode(
+                &unpack_bulk_string(document.clone())?,
+                state.databases.len(),
+                state.clock.now_millis(),
+            ) {
+                Ok(keyspace) => keyspace,
+                Err(err) => return Ok(Value::SimpleError(format!("ERR {:#}", err))),
+            };
+            let _exclusive = match exclusive {
+                true => Some(state.exec_lock.write().await),
+                false => None,
+            };
+            match rdb::restore(keyspace, &state.databases).await {
+                Ok(_) => Ok(ok),
+                Err(err) => Ok(Value::SimpleError(format!("ERR {:#}", err))),
+            }
+        }
+        "QUICKLIST-PACKED-THRESHOLD" => match &args[1..] {
+            [_] => Ok(ok),
+            _ => Ok(CommandError::WrongArity.into()),
+        },
+        other => Ok(Value::SimpleError(format!(
+            "ERR unknown subcommand '{}'",
+            other.to_lowercase()
+        ))),
+    }
+}
+
+// INFO sections in the order they are reported
+const INFO_SECTIONS: [&str; 5] = ["memory", "stats", "replication", "commandstats", "keyspace"];
+
+// The sections reported when none are named
+const DEFAULT_SECTIONS: [&str; 4] = ["memory", "stats", "replication", "keyspace"];
+
+/// INFO [section ...] reports `field:value` lines grouped under `# Section` headers.
+/// Without arguments, or with `default`, every section but commandstats is included;
+/// `all` and `everything` add it. Unknown sections are left out.
+pub async fn info_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let mut requested = Vec::new();
+    for arg in args {
+        match unpack_bulk_string(arg.clone())?.to_lowercase().as_str() {
+            "all" | "everything" => requested.extend(INFO_SECTIONS),
+            "default" => requested.extend(DEFAULT_SECTIONS),
+            name => requested.extend(INFO_SECTIONS.iter().filter(|section| **section == name)),
+        }
+    }
+    if args.is_empty() {
+        requested.extend(DEFAULT_SECTIONS);
+    }
+
+    let mut sections = Vec::new();
+    for name in INFO_SECTIONS {
+        if !requested.contains(&name) {
+            continue;
+        }
+        let lines = match name {
+            "memory" => memory_info(state).await,
+            "stats" => stats_info(state),
+            "replication" => state.replication.info().await,
+            "commandstats" => state.stats.command_lines(),
+            _ => keyspace_info(state).await,
+        };
+        let title = name[..1].to_uppercase() + &name[1..];
+        let lines: String = lines.iter().map(|line| format!("{}\r\n", line)).collect();
+        sections.push(format!("# {}\r\n{}", title, lines));
+    }
+    Ok(Value::BulkString(sections.join("\r\n").into()))
+}
+
+async fn memory_info(state: &ServerState) -> Vec<String> {
+    let mut used = 0;
+    for db in &state.databases {
+        used += db.write().await.used_memory() as u64;
+    }
+    let config = state.config.read().await;
+    vec![
+        format!("used_memory:{}", used),
+        format!("used_memory_human:{}", human_bytes(used)),
+        format!("maxmemory:{}", config.maxmemory),
+        format!("maxmemory_human:{}", human_bytes(config.maxmemory)),
+        format!("maxmemory_policy:{}", config.maxmemory_policy.name()),
+    ]
+}
+
+fn stats_info(state: &ServerState) -> Vec<String> {
+    let stats = &state.stats;
+    // Summed over every database
+    let keyspace = |counter: fn(&KeyspaceStats) -> &AtomicU64| -> u64 {
+        state
+            .databases
+            .iter()
+            .map(|db| counter(db.stats()).load(Ordering::Relaxed))
+            .sum()
+    };
+    vec![
+        format!(
+            "total_commands_processed:{}",
+            stats.total_commands_processed()
+        ),
+        format!("expired_keys:{}", keyspace(|stats| &stats.expired_keys)),
+        format!("evicted_keys:{}", keyspace(|stats| &stats.evicted_keys)),
+        format!("keyspace_hits:{}", keyspace(|stats| &stats.keyspace_hits)),
+        format!(
+            "keyspace_misses:{}",
+            keyspace(|stats| &stats.keyspace_misses)
+        ),
+        format!(
+            "total_error_replies:{}",
+            stats.total_error_replies.load(Ordering::Relaxed)
+        ),
+    ]
+}
+
+// One line per non-empty database
+async fn keyspace_info(state: &ServerState) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (index, db) in state.databases.iter().enumerate() {
+        let db = db.read().await;
+        let keys = db.keys().count();
+        if keys > 0 {
+            lines.push(format!(
+                "db{}:keys={},expires={}",
+                index,
+                keys,
+                db.expires()
+            ));
+        }
+    }
+    lines
+}
+
+// A size the way INFO prints it, such as 1.50M
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", size, UNITS[unit])
+}
+
+/// LATENCY LATEST | HISTORY event | RESET [event ...] | DOCTOR reports the spikes the
+/// latency monitor recorded, see `LatencyMonitor`.
+pub fn latency_value(args: &[Value], state: &ServerState) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let args = args[1..]
+        .iter()
+        .map(|arg| unpack_bulk_string(arg.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let latency = &state.latency;
+
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    let response = match (subcommand.as_str(), args.as_slice()) {
+        ("HELP", []) => help_value(UserCommand::Latency),
+        ("LATEST", []) => Value::Array(
+            latency
+                .latest()
+                .into_iter()
+                .map(|(event, sample, max)| {
+                    Value::Array(vec![
+                        Value::BulkString(event.into()),
+                        Value::Integer(sample.time as i64),
+                        Value::Integer(sample.latency as i64),
+                        Value::Integer(max as i64),
+                    ])
+                })
+                .collect(),
+        ),
+        ("HISTORY", [event]) => Value::Array(
+            latency
+                .history(event)
+                .into_iter()
+                .map(|sample| {
+                    Value::Array(vec![
+                        Value::Integer(sample.time as i64),
+                        Value::Integer(sample.latency as i64),
+                    ])
+                })
+                .collect(),
+        ),
+        ("RESET", events) => Value::Integer(latency.reset(events) as i64),
+        ("DOCTOR", []) => Value::BulkString(latency.doctor().into()),
+        ("LATEST" | "HISTORY" | "DOCTOR", _) => CommandError::WrongArity.into(),
+        (other, _) => unknown_subcommand("LATENCY", other),
+    };
+    Ok(response)
+}
+
+/// MEMORY USAGE key [SAMPLES count] estimates the bytes a key takes with its value,
+/// and MEMORY STATS breaks down the estimate for the whole dataset. Values are
+/// measured in full, so SAMPLES is accepted but has no effect.
+pub async fn memory_value(args: &[Value], state: &ServerState, db: usize) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    match unpack_bulk_string(subcommand.clone())?
+        .to_uppercase()
+        .as_str()
+    {
+        "HELP" if args.len() == 1 => Ok(help_value(UserCommand::Memory)),
+        "USAGE" => memory_usage(&args[1..], state, db).await,
+        "STATS" if args.len() == 1 => Ok(memory_stats(state).await),
+        "STATS" => Ok(CommandError::WrongArity.into()),
+        other => Ok(unknown_subcommand("MEMORY", other)),
+    }
+}
+
+async fn memory_usage(args: &[Value], state: &ServerState, db: usize) -> Result<Value> {
+    let (key, options) = match args {
+        [key, options @ ..] if options.is_empty() || options.len() == 2 => (key, options),
+        _ => return Ok(CommandError::WrongArity.into()),
+    };
+    if let [option, count] = options {
+        if unpack_bulk_string(option.clone())?.to_uppercase() != "SAMPLES" {
+            return Ok(CommandError::Syntax.into());
+        }
+        if integer_arg(count).is_none_or(|count| count < 0) {
+            return Ok(CommandError::NotInteger.into());
+        }
+    }
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = state.databases[db].read_key(&key).await;
+    Ok(match instance.peek_entry(&key) {
+        Some((entry, _)) => Value::Integer(entry.memory_usage(&key) as i64),
+        None => Value::Null,
+    })
+}
+
+// Totals first, then the bookkeeping of each database that has keys, as a flattened
+// field/value array
+async fn memory_stats(state: &ServerState) -> Value {
+    let mut total = MemoryStats::default();
+    let mut databases = Vec::new();
+    for (index, db) in state.databases.iter().enumerate() {
+        let stats = db.read().await.memory_stats();
+        total.keys += stats.keys;
+        total.overhead += stats.overhead;
+        total.expires_overhead += stats.expires_overhead;
+        total.dataset += stats.dataset;
+        if stats.keys > 0 {
+            databases.push((index, stats));
+        }
+    }
+
+    let overhead = total.overhead + total.expires_overhead;
+    let allocated = overhead + total.dataset;
+    let percentage = match allocated {
+        0 => 0.0,
+        _ => total.dataset as f64 * 100.0 / allocated as f64,
+    };
+    let field = |name: &str| Value::BulkString(name.to_owned().into());
+    let mut reply = vec![
+        field("total.allocated"),
+        Value::Integer(allocated as i64),
+        field("keys.count"),
+        Value::Integer(total.keys as i64),
+        field("keys.bytes-per-key"),
+        Value::Integer(allocated.checked_div(total.keys).unwrap_or(0) as i64),
+        field("dataset.bytes"),
+        Value::Integer(total.dataset as i64),
+        field("dataset.percentage"),
+        Value::BulkString(format!("{:.2}", percentage).into()),
+        field("overhead.total"),
+        Value::Integer(overhead as i64),
+    ];
+    for (index, stats) in databases {
+        reply.push(field(&format!("db.{}", index)));
+        reply.push(Value::Array(vec![
+            field("overhead.hashtable.main"),
+            Value::Integer(stats.overhead as i64),
+            field("overhead.hashtable.expires"),
+            Value::Integer(stats.expires_overhead as i64),
+        ]));
+    }
+    Value::Array(reply)
+}
+
+/// LOLWUT [VERSION version] draws a maze of slashes, a different one for each version,
+/// above the server version. Clients call it to check they are talking to a server.
+/// TIME replies with the current Unix time, as whole seconds and the microseconds
+/// elapsed within the second.
+pub fn time_value(args: &[Value], clock: &dyn Clock) -> Result<Value> {
+    if !args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+    let now = clock.now_micros();
+    Ok(Value::Array(vec![
+        Value::BulkString((now / 1_000_000).to_string().into()),
+        Value::BulkString((now % 1_000_000).to_string().into()),
+    ]))
+}
+
+pub fn lolwut_value(args: &[Value]) -> Result<Value> {
+    let version = match args {
+        [] => 0,
+        [option, version]
+            if unpack_bulk_string(option.clone())?.eq_ignore_ascii_case("VERSION") =>
+        {
+            match integer_arg(version) {
+                Some(version) => version,
+                None => {
+                    return Ok(Value::SimpleError(
+                        "ERR value is not an integer or out of range".to_owned(),
+                    ))
+                }
+            }
+        }
+        _ => return Ok(CommandError::Syntax.into()),
+    };
+
+    // The same small linear congruential generator draws the same maze every time
+    let mut seed = version as u64;
+    let mut art = String::new();
+    for _ in 0..8 {
+        for _ in 0..40 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            art.push(if seed >> 63 == 0 { '/' } else { '\\' });
+        }
+        art.push('\n');
+    }
+    art.push_str(&format!("\nRedis ver. {}\n", env!("CARGO_PKG_VERSION")));
+    Ok(Value::BulkString(art.into()))
+}
+
+/// COMMAND [COUNT | INFO [name ...] | DOCS [name ...]] describes the commands the
+/// server implements, for clients that complete or route them.
+pub fn command_value(args: &[Value]) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(Value::Array(COMMANDS.iter().map(command_info).collect()));
+    };
+    let names = args[1..]
+        .iter()
+        .map(|name| unpack_bulk_string(name.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    match unpack_bulk_string(subcommand.clone())?
+        .to_uppercase()
+        .as_str()
+    {
+        "HELP" if names.is_empty() => Ok(help_value(UserCommand::Command)),
+        "COUNT" if names.is_empty() => Ok(Value::Integer(COMMANDS.len() as i64)),
+        "INFO" if names.is_empty() => Ok(Value::Array(COMMANDS.iter().map(command_info).collect())),
+        // Unknown names get a null in their place
+        "INFO" => Ok(Value::Array(
+            names
+                .iter()
+                .map(|name| CommandSpec::lookup(name).map_or(Value::Null, command_info))
+                .collect(),
+        )),
+        "DOCS" if names.is_empty() => Ok(Value::Array(
+            COMMANDS.iter().flat_map(command_docs).collect(),
+        )),
+        // Unknown names are left out
+        "DOCS" => Ok(Value::Array(
+            names
+                .iter()
+                .filter_map(|name| CommandSpec::lookup(name))
+                .flat_map(command_docs)
+                .collect(),
+        )),
+        "COUNT" => Ok(CommandError::WrongArity.into()),
+        other => Ok(unknown_subcommand("COMMAND", other)),
+    }
+}
+
+/// The reply to HELP for a container command, generated from its subcommands in
+/// `SUBCOMMANDS`: a header, then each subcommand with its arguments followed by an
+/// indented summary.
+pub fn help_value(command: UserCommand) -> Value {
+    let line = |text: String| Value::SimpleString(text);
+    let mut lines = vec![line(format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        command.name()
+    ))];
+    for subcommand in command.subcommands() {
+        lines.push(line(
+            format!("{} {}", subcommand.name, subcommand.arguments)
+                .trim_end()
+                .to_owned(),
+        ));
+        lines.push(line(format!("    {}", subcommand.summary)));
+    }
+    lines.push(line("HELP".to_owned()));
+    lines.push(line("    Prints this help.".to_owned()));
+    Value::Array(lines)
+}
+
+// name, arity, flags, first key, last key, key step
+fn command_info(spec: &CommandSpec) -> Value {
+    Value::Array(vec![
+        Value::BulkString(spec.name.to_lowercase().into()),
+        Value::Integer(spec.arity),
+        Value::Array(
+            spec.flags
+                .iter()
+                .map(|flag| Value::SimpleString((*flag).to_owned()))
+                .collect(),
+        ),
+        Value::Integer(spec.first_key),
+        Value::Integer(spec.last_key),
+        Value::Integer(spec.step),
+    ])
+}
+
+// The name followed by its documentation as a flattened field/value array
+fn command_docs(spec: &CommandSpec) -> [Value; 2] {
+    [
+        Value::BulkString(spec.name.to_lowercase().into()),
+        Value::Array(vec![
+            Value::BulkString("summary".into()),
+            Value::BulkString(spec.summary.into()),
+        ]),
+    ]
+}
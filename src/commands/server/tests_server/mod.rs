@@ -0,0 +1,438 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, MockClock, SystemClock};
+    use crate::config::Config;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    fn state(name: &str) -> ServerState {
+        ServerState::new(Config {
+            dir: std::env::temp_dir(),
+            dbfilename: format!("redis-rust-{}-{}.rdb", name, std::process::id()),
+            ..Config::new()
+        })
+    }
+
+    async fn insert(state: &ServerState, key: &str) {
+        state.databases[0].write().await.insert_entry(
+            key.to_owned(),
+            Entry::new(DataType::String(key.to_owned().into())),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save() -> Result<()> {
+        let state = state("save");
+        insert(&state, "key").await;
+        assert_eq!(
+            save_value(&args(&[]), &state).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+
+        let path = state.config.read().await.rdb_path();
+        let restored = ServerState::new(Config::new());
+        assert_eq!(rdb::load(&path, &restored.databases).await?, 1);
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bgsave_uses_point_in_time_copy() -> Result<()> {
+        let state = state("bgsave");
+        insert(&state, "before").await;
+        assert_eq!(
+            bgsave_value(&args(&[]), &state).await?,
+            Value::SimpleString("Background saving started".to_owned())
+        );
+        // Writes after BGSAVE returns are not part of the snapshot
+        insert(&state, "after").await;
+
+        while state.bgsave_in_progress.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        let path = state.config.read().await.rdb_path();
+        let restored = ServerState::new(Config::new());
+        assert_eq!(rdb::load(&path, &restored.databases).await?, 1);
+        assert!(restored.databases[0].read().await.get("before").is_some());
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bgsave_rejects_concurrent_save() -> Result<()> {
+        let state = state("concurrent");
+        state.bgsave_in_progress.store(true, Ordering::SeqCst);
+        assert_eq!(
+            bgsave_value(&args(&[]), &state).await?,
+            Value::SimpleError("ERR Background save already in progress".to_owned())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_count_and_info() -> Result<()> {
+        let Value::Integer(count) = command_value(&args(&["COUNT"]))? else {
+            panic!("COMMAND COUNT must reply with an integer");
+        };
+        assert_eq!(command_value(&args(&[]))?, command_value(&args(&["INFO"]))?);
+        let Value::Array(all) = command_value(&args(&[]))? else {
+            panic!("COMMAND must reply with an array");
+        };
+        assert_eq!(all.len() as i64, count);
+
+        assert_eq!(
+            command_value(&args(&["info", "mget", "nosuch"]))?,
+            Value::Array(vec![
+                Value::Array(vec![
+                    Value::BulkString("mget".into()),
+                    Value::Integer(-2),
+                    Value::Array(vec![
+                        Value::SimpleString("readonly".to_owned()),
+                        Value::SimpleString("fast".to_owned()),
+                    ]),
+                    Value::Integer(1),
+                    Value::Integer(-1),
+                    Value::Integer(1),
+                ]),
+                Value::Null,
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_time() -> Result<()> {
+        let clock = MockClock::new(1_700_000_000_123);
+        assert_eq!(
+            time_value(&[], &clock)?,
+            Value::Array(vec![
+                Value::BulkString("1700000000".into()),
+                Value::BulkString("123000".into()),
+            ])
+        );
+        assert_eq!(
+            time_value(&args(&["now"]), &clock)?,
+            CommandError::WrongArity.into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lolwut() -> Result<()> {
+        let Value::BulkString(art) = lolwut_value(&args(&[]))? else {
+            panic!("LOLWUT must reply with a bulk string");
+        };
+        let art = String::from_utf8(art.to_vec())?;
+        assert!(art.ends_with(&format!("Redis ver. {}\n", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(
+            lolwut_value(&args(&[]))?,
+            Value::BulkString(art.clone().into())
+        );
+
+        // Each version draws its own maze
+        assert_ne!(
+            lolwut_value(&args(&["VERSION", "5"]))?,
+            Value::BulkString(art.into())
+        );
+        assert_eq!(
+            lolwut_value(&args(&["VERSION", "five"]))?,
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+        assert_eq!(lolwut_value(&args(&["FAST"]))?, CommandError::Syntax.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_docs() -> Result<()> {
+        assert_eq!(
+            command_value(&args(&["DOCS", "Get", "nosuch"]))?,
+            Value::Array(vec![
+                Value::BulkString("get".into()),
+                Value::Array(vec![
+                    Value::BulkString("summary".into()),
+                    Value::BulkString("Returns the string value of a key.".into()),
+                ]),
+            ])
+        );
+        assert_eq!(
+            command_value(&args(&["HELPME"]))?,
+            Value::SimpleError("ERR unknown subcommand 'helpme'. Try COMMAND HELP.".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_help() -> Result<()> {
+        let state = state("help");
+        let Value::Array(lines) = command_value(&args(&["help"]))? else {
+            panic!("HELP replies with an array");
+        };
+        let line = |text: &str| Value::SimpleString(text.to_owned());
+        assert_eq!(
+            lines[0],
+            line("COMMAND <subcommand> [<arg> [value] [opt] ...]. Subcommands are:")
+        );
+        assert_eq!(lines[1], line("COUNT"));
+        assert_eq!(lines[3], line("INFO [<command-name> ...]"));
+        assert_eq!(
+            lines[lines.len() - 2..],
+            [line("HELP"), line("    Prints this help.")]
+        );
+        assert_eq!(
+            lines.len(),
+            2 * UserCommand::Command.subcommands().len() + 3
+        );
+
+        // Every subcommand HELP lists is one the command knows
+        for subcommand in UserCommand::Memory.subcommands() {
+            let reply = memory_value(&args(&[subcommand.name]), &state, 0).await?;
+            assert!(!matches!(reply, Value::SimpleError(err) if err.contains("unknown")));
+        }
+        for subcommand in UserCommand::Latency.subcommands() {
+            let reply = latency_value(&args(&[subcommand.name]), &state)?;
+            assert!(!matches!(reply, Value::SimpleError(err) if err.contains("unknown")));
+        }
+        assert!(matches!(
+            memory_value(&args(&["HELP"]), &state, 0).await?,
+            Value::Array(lines) if lines.len() == 7
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_info_sections() -> Result<()> {
+        let state = state("info");
+        insert(&state, "key").await;
+        let Value::BulkString(all) = info_value(&args(&[]), &state).await? else {
+            panic!("INFO replies with a bulk string");
+        };
+        let all = String::from_utf8_lossy(&all).into_owned();
+        assert!(all.starts_with("# Memory\r\nused_memory:"));
+        assert!(all.contains("maxmemory_policy:noeviction\r\n"));
+        assert!(all.contains(
+            "\r\n\r\n# Stats\r\ntotal_commands_processed:0\r\nexpired_keys:0\r\nevicted_keys:0\r\n"
+        ));
+        assert!(!all.contains("# Commandstats"));
+        assert!(all.ends_with("# Keyspace\r\ndb0:keys=1,expires=0\r\n"));
+
+        assert_eq!(
+            info_value(&args(&["KEYSPACE", "nosuch"]), &state).await?,
+            Value::BulkString("# Keyspace\r\ndb0:keys=1,expires=0\r\n".into())
+        );
+        state
+            .stats
+            .record_call("GET", std::time::Duration::from_micros(4), false);
+        assert_eq!(
+            info_value(&args(&["commandstats"]), &state).await?,
+            Value::BulkString(
+                "# Commandstats\r\ncmdstat_get:calls=1,usec=4,usec_per_call=4.00,failed_calls=0\r\n"
+                    .into()
+            )
+        );
+        assert_eq!(human_bytes(1023), "1023B");
+        assert_eq!(human_bytes(1536 * 1024), "1.50M");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_usage_and_stats() -> Result<()> {
+        let state = state("memory");
+        insert(&state, "key").await;
+        let mut expiring = Entry::new(DataType::Set(
+            ["a", "b"].into_iter().map(Bytes::from).collect(),
+        ));
+        expiring.expires_at = Some(SystemClock.now_millis() + 60_000);
+        state.databases[2]
+            .write()
+            .await
+            .insert_entry("set".to_owned(), expiring.clone());
+
+        let usage = expiring.memory_usage("set") as i64;
+        assert_eq!(
+            memory_value(&args(&["USAGE", "set", "SAMPLES", "0"]), &state, 2).await?,
+            Value::Integer(usage)
+        );
+        assert_eq!(
+            memory_value(&args(&["usage", "set"]), &state, 0).await?,
+            Value::Null
+        );
+        assert_eq!(
+            memory_value(&args(&["USAGE", "set", "SAMPLES", "x"]), &state, 2).await?,
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+        assert_eq!(
+            memory_value(&args(&["USAGE", "set", "COUNT", "1"]), &state, 2).await?,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+
+        let Value::Array(stats) = memory_value(&args(&["STATS"]), &state, 0).await? else {
+            panic!("MEMORY STATS replies with an array");
+        };
+        let field = |name: &str| {
+            let position = stats
+                .iter()
+                .position(|value| *value == Value::BulkString(name.to_owned().into()))
+                .unwrap();
+            stats[position + 1].clone()
+        };
+        let Value::Integer(allocated) = field("total.allocated") else {
+            panic!("total.allocated is an integer");
+        };
+        assert!(allocated > usage);
+        assert_eq!(field("keys.count"), Value::Integer(2));
+        assert!(matches!(
+            field("db.2"),
+            Value::Array(fields) if fields[3] != Value::Integer(0)
+        ));
+        assert_eq!(
+            memory_value(&args(&["DOCTOR"]), &state, 0).await?,
+            Value::SimpleError("ERR unknown subcommand 'doctor'. Try MEMORY HELP.".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_options() -> Result<()> {
+        let saving = state("shutdown-default");
+        let state = state("shutdown");
+        assert_eq!(
+            shutdown_value(&args(&["LATER"]), &state).await?,
+            Some(CommandError::Syntax.into())
+        );
+        assert_eq!(
+            shutdown_value(&args(&["SAVE", "NOSAVE"]), &state).await?,
+            Some(CommandError::Syntax.into())
+        );
+        assert_eq!(shutdown_value(&args(&["nosave"]), &state).await?, None);
+        assert!(!state.shutdown.requested().await);
+
+        // Without an option the snapshot is saved since the AOF is off
+        assert_eq!(shutdown_value(&args(&[]), &saving).await?, None);
+        assert!(saving.shutdown.requested().await);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_debug_sleep() -> Result<()> {
+        let state = Arc::new(state("debug"));
+        assert_eq!(
+            debug_value(&args(&["SLEEP", "soon"]), &state, 0, true).await?,
+            CommandError::NotFloat.into()
+        );
+        assert_eq!(
+            debug_value(&args(&["SLEEP"]), &state, 0, true).await?,
+            CommandError::WrongArity.into()
+        );
+        assert_eq!(
+            debug_value(&args(&["SLEEP", "1e30"]), &state, 0, true).await?,
+            Value::SimpleError("ERR value is out of range".to_owned())
+        );
+        assert_eq!(
+            debug_value(&args(&["NAP", "1"]), &state, 0, true).await?,
+            Value::SimpleError("ERR unknown subcommand 'nap'".to_owned())
+        );
+
+        let sleeper = {
+            let state = Arc::clone(&state);
+            tokio::spawn(
+                async move { debug_value(&args(&["SLEEP", "0.2"]), &state, 0, true).await },
+            )
+        };
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        // Other commands wait for the sleep to end
+        assert!(state.exec_lock.try_read().is_err());
+        assert_eq!(sleeper.await??, Value::SimpleString("OK".to_owned()));
+        assert!(state.exec_lock.try_read().is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_debug_object_and_active_expire() -> Result<()> {
+        let state = state("debug-object");
+        insert(&state, "key").await;
+        let Value::SimpleString(description) =
+            debug_value(&args(&["OBJECT", "key"]), &state, 0, false).await?
+        else {
+            panic!("DEBUG OBJECT replies with a simple string");
+        };
+        assert!(description.starts_with("Value at:0x"));
+        assert!(description.contains(" refcount:1 encoding:embstr serializedlength:4 lru:"));
+        assert!(description.ends_with(" lru_seconds_idle:0"));
+        assert_eq!(
+            debug_value(&args(&["OBJECT", "missing"]), &state, 0, false).await?,
+            Value::SimpleError("ERR no such key".to_owned())
+        );
+
+        let ok = Value::SimpleString("OK".to_owned());
+        assert_eq!(
+            debug_value(&args(&["SET-ACTIVE-EXPIRE", "0"]), &state, 0, false).await?,
+            ok
+        );
+        assert!(!state.active_expire.load(Ordering::Relaxed));
+        assert_eq!(
+            debug_value(&args(&["set-active-expire", "2"]), &state, 0, false).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            debug_value(
+                &args(&["QUICKLIST-PACKED-THRESHOLD", "1K"]),
+                &state,
+                0,
+                false
+            )
+            .await?,
+            ok
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_debug_json_dump_and_load() -> Result<()> {
+        let state = state("debug-json");
+        insert(&state, "key").await;
+        let dump = debug_value(&args(&["JSON-DUMP"]), &state, 0, false).await?;
+        assert_eq!(
+            dump,
+            Value::BulkString(
+                "{\n  \"0\": {\n    \"key\": {\"type\": \"string\", \"value\": \"key\"}\n  }\n}\n"
+                    .into()
+            )
+        );
+
+        // Loading replaces every database
+        let ok = Value::SimpleString("OK".to_owned());
+        let document = r#"{"3": {"other": {"type": "set", "value": ["member"]}}}"#;
+        assert_eq!(
+            debug_value(&args(&["JSON-LOAD", document]), &state, 0, false).await?,
+            ok
+        );
+        assert!(state.databases[0].read().await.get("key").is_none());
+        assert!(state.databases[3].read().await.get("other").is_some());
+        let Value::SimpleError(err) =
+            debug_value(&args(&["JSON-LOAD", "{\"0\": []}"]), &state, 0, false).await?
+        else {
+            panic!("an invalid document is refused");
+        };
+        assert_eq!(err, "ERR Expected an object, got an array");
+        assert!(matches!(
+            debug_value(&args(&["JSON-LOAD", "{\"99\": {}}"]), &state, 0, false).await?,
+            Value::SimpleError(_)
+        ));
+
+        // What was dumped loads back
+        let Value::BulkString(dump) = dump else {
+            unreachable!();
+        };
+        let dump = String::from_utf8(dump.to_vec())?;
+        assert_eq!(
+            debug_value(&args(&["JSON-LOAD", &dump]), &state, 0, false).await?,
+            ok
+        );
+        assert!(state.databases[0].read().await.get("key").is_some());
+        assert!(state.databases[3].read().await.get("other").is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use bytes::Bytes;
+use std::{
+    collections::{hash_map::RandomState, HashSet},
+    hash::BuildHasher,
+    sync::Arc,
+};
+
+use crate::connection::{integer_arg, parse_scan_options, scan_reply, unpack_bulk_string};
+use crate::error::{CommandError, Error, Result};
+use crate::parser::Value;
+use crate::storage::scan::ScanSet;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+
+pub mod tests_set;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+fn members_from(args: &[Value]) -> Result<Vec<Bytes>> {
+    args.iter()
+        .map(|member| match member {
+            Value::BulkString(member) => Ok(member.clone()),
+            _ => Err(Error::InvalidArgument("Invalid member type")),
+        })
+        .collect()
+}
+
+pub async fn sadd_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let members = members_from(&args[1..])?;
+
+    // Acquire a write lock on the database instance, creating the set on first write
+    let mut instance = db_instance.write_key(&key).await;
+    let set = match instance.get_or_create_set(&key) {
+        Ok(set) => set,
+        Err(err) => return Ok(err.into()),
+    };
+
+    // Number of members that were not already present
+    let added = members
+        .into_iter()
+        .filter(|member| set.insert(member.clone()))
+        .count();
+
+    Ok(Value::Integer(added as i64))
+}
+
+pub async fn srem_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let members = members_from(&args[1..])?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    let (removed, now_empty) = match instance.get_set_mut(&key) {
+        Ok(Some(set)) => {
+            let removed = members.iter().filter(|member| set.remove(*member)).count();
+            (removed, set.is_empty())
+        }
+        Ok(None) => (0, false),
+        Err(err) => return Ok(err.into()),
+    };
+
+    // A set never outlives its last member
+    if now_empty {
+        instance.remove(&key);
+    }
+
+    Ok(Value::Integer(removed as i64))
+}
+
+pub async fn smembers_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_set(&key) {
+        Ok(set) => Ok(members_reply(set.into_iter().flatten())),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn sismember_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Value::BulkString(member) = &args[1] else {
+        return Err(Error::InvalidArgument("Invalid member type"));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_set(&key) {
+        Ok(set) => Ok(Value::Integer(
+            set.is_some_and(|set| set.contains(member)) as i64
+        )),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn scard_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_set(&key) {
+        Ok(set) => Ok(Value::Integer(set.map_or(0, |set| set.len()) as i64)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count] iterates over the members of a set.
+pub async fn sscan_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let key = unpack_bulk_string(args[0].clone())?;
+    let options = match parse_scan_options(&args[1..]) {
+        Ok(options) if options.novalues => return Ok(CommandError::Syntax.into()),
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get_set(&key) {
+        Ok(set) => set,
+        Err(err) => return Ok(err.into()),
+    };
+    let (cursor, members) = match set {
+        Some(set) => set.scan(options.cursor, options.count),
+        None => (0, Vec::new()),
+    };
+    let members = members
+        .into_iter()
+        .filter(|member| options.matches(member))
+        .map(|member| Value::BulkString(member.clone()))
+        .collect();
+    Ok(scan_reply(cursor, members))
+}
+
+// SINTER, SUNION and SDIFF over one or more keys
+pub async fn set_operation_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    operation: SetOperation,
+) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let keys = args
+        .iter()
+        .map(|key| unpack_bulk_string(key.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // All keys are read under one lock so the result is a consistent snapshot.
+    // Every key is type checked, even ones that cannot affect the result
+    let instance = db_instance.read_keys(&keys).await;
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in &keys {
+        match instance.get_set(key) {
+            Ok(set) => sets.push(set),
+            Err(err) => return Ok(err.into()),
+        }
+    }
+
+    let empty = ScanSet::new();
+    let first = sets[0].unwrap_or(&empty);
+    let rest = &sets[1..];
+
+    let result: Vec<&Bytes> = match operation {
+        SetOperation::Inter => first
+            .iter()
+            .filter(|member| {
+                rest.iter()
+                    .all(|set| set.is_some_and(|set| set.contains(*member)))
+            })
+            .collect(),
+        SetOperation::Diff => first
+            .iter()
+            .filter(|member| {
+                !rest
+                    .iter()
+                    .any(|set| set.is_some_and(|set| set.contains(*member)))
+            })
+            .collect(),
+        SetOperation::Union => sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.iter())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect(),
+    };
+
+    Ok(members_reply(result))
+}
+
+/// SRANDMEMBER key [count] picks random members. Without a count it replies with
+/// one member; a positive count returns that many distinct members, or the whole set
+/// when it is smaller, and a negative one that many members that may repeat.
+pub async fn srandmember_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let (key, count) = match args {
+        [key] => (key, None),
+        [key, count] => match integer_arg(count) {
+            // Like Redis, a count must have a magnitude that fits
+            Some(i64::MIN) => return Ok(out_of_range()),
+            Some(count) => (key, Some(count)),
+            None => return Ok(CommandError::NotInteger.into()),
+        },
+        _ => return Ok(CommandError::WrongArity.into()),
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get_set(&key) {
+        Ok(Some(set)) => set,
+        Ok(None) if count.is_some() => return Ok(Value::Array(vec![])),
+        Ok(None) => return Ok(Value::Null),
+        Err(err) => return Ok(err.into()),
+    };
+    let members: Vec<&Bytes> = set.iter().collect();
+    let picked: Vec<&Bytes> = match count {
+        None => vec![members[random_below(members.len())]],
+        Some(count) if count < 0 => repeated_picks(&members, count.unsigned_abs()),
+        Some(count) => shuffled_prefix(members, count as usize),
+    };
+    Ok(match count {
+        None => Value::BulkString(picked[0].clone()),
+        Some(_) => Value::Array(
+            picked
+                .into_iter()
+                .map(|member| Value::BulkString(member.clone()))
+                .collect(),
+        ),
+    })
+}
+
+/// SPOP key [count] removes and returns random members: one without a count, or up
+/// to `count` distinct members as an array.
+pub async fn spop_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let (key, count) = match args {
+        [key] => (key, None),
+        [key, count] => match integer_arg(count) {
+            Some(count) if count >= 0 => (key, Some(count as usize)),
+            _ => {
+                return Ok(Value::SimpleError(
+                    "ERR value is out of range, must be positive".to_owned(),
+                ))
+            }
+        },
+        _ => return Ok(CommandError::WrongArity.into()),
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    let (popped, now_empty) = match instance.get_set_mut(&key) {
+        Ok(Some(set)) => {
+            let popped: Vec<Bytes> = shuffled_prefix(set.iter().collect(), count.unwrap_or(1))
+                .into_iter()
+                .cloned()
+                .collect();
+            for member in &popped {
+                set.remove(member);
+            }
+            (popped, set.is_empty())
+        }
+        Ok(None) => (Vec::new(), false),
+        Err(err) => return Ok(err.into()),
+    };
+    if now_empty {
+        instance.remove(&key);
+    }
+
+    Ok(match count {
+        None => popped
+            .into_iter()
+            .next()
+            .map_or(Value::Null, Value::BulkString),
+        Some(_) => Value::Array(popped.into_iter().map(Value::BulkString).collect()),
+    })
+}
+
+/// `count` distinct items picked at random, by shuffling only as far as needed.
+pub fn shuffled_prefix<T>(mut items: Vec<T>, count: usize) -> Vec<T> {
+    let count = count.min(items.len());
+    for index in 0..count {
+        let other = index + random_below(items.len() - index);
+        items.swap(index, other);
+    }
+    items.truncate(count);
+    items
+}
+
+/// `count` items picked at random from a non-empty slice, each pick independent of
+/// the others, so items may repeat. The result grows as the picks are made rather
+/// than being allocated for `count` up front.
+pub fn repeated_picks<T: Copy>(items: &[T], count: u64) -> Vec<T> {
+    let mut picked = Vec::new();
+    for _ in 0..count {
+        picked.push(items[random_below(items.len())]);
+    }
+    picked
+}
+
+/// The reply to a count whose magnitude is too large to honour.
+pub fn out_of_range() -> Value {
+    Value::SimpleError("ERR value is out of range".to_owned())
+}
+
+/// A random number below `bound`, which must not be 0. Every RandomState is keyed
+/// differently, which is all the randomness the server needs.
+pub fn random_below(bound: usize) -> usize {
+    RandomState::new().hash_one(bound) as usize % bound
+}
+
+fn members_reply<'a>(members: impl IntoIterator<Item = &'a Bytes>) -> Value {
+    Value::Set(
+        members
+            .into_iter()
+            .map(|member| Value::BulkString(member.clone()))
+            .collect(),
+    )
+}
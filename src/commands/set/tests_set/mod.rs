@@ -0,0 +1,232 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::connection::wrong_type;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    // Set replies, and random picks from a set, are unordered, so compare them sorted
+    fn sorted(value: Value) -> Vec<String> {
+        let (Value::Set(items) | Value::Array(items)) = value else {
+            panic!("expected a set or array reply, got {:?}", value);
+        };
+        let mut members: Vec<String> = items
+            .into_iter()
+            .map(|item| match item {
+                Value::BulkString(member) => String::from_utf8(member.to_vec()).unwrap(),
+                other => panic!("unexpected member {:?}", other),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[tokio::test]
+    async fn test_sadd_srem_and_scard() -> Result<()> {
+        let db = db();
+        assert_eq!(
+            sadd_value(&args(&["tags", "a", "b", "a"]), &db).await?,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            sadd_value(&args(&["tags", "b", "c"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(scard_value(&args(&["tags"]), &db).await?, Value::Integer(3));
+        assert_eq!(
+            srem_value(&args(&["tags", "a", "missing"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            sorted(smembers_value(&args(&["tags"]), &db).await?),
+            vec!["b", "c"]
+        );
+
+        // Removing the last member deletes the key
+        srem_value(&args(&["tags", "b", "c"]), &db).await?;
+        assert!(db.read().await.get("tags").is_none());
+        assert_eq!(scard_value(&args(&["tags"]), &db).await?, Value::Integer(0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sismember() -> Result<()> {
+        let db = db();
+        sadd_value(&args(&["tags", "a"]), &db).await?;
+        assert_eq!(
+            sismember_value(&args(&["tags", "a"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            sismember_value(&args(&["tags", "z"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            sismember_value(&args(&["missing", "a"]), &db).await?,
+            Value::Integer(0)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_operations() -> Result<()> {
+        let db = db();
+        sadd_value(&args(&["k1", "a", "b", "c", "d"]), &db).await?;
+        sadd_value(&args(&["k2", "c"]), &db).await?;
+        sadd_value(&args(&["k3", "a", "c", "e"]), &db).await?;
+
+        let inter =
+            set_operation_value(&args(&["k1", "k2", "k3"]), &db, SetOperation::Inter).await?;
+        assert_eq!(sorted(inter), vec!["c"]);
+        let union =
+            set_operation_value(&args(&["k1", "k2", "k3"]), &db, SetOperation::Union).await?;
+        assert_eq!(sorted(union), vec!["a", "b", "c", "d", "e"]);
+        let diff = set_operation_value(&args(&["k1", "k2", "k3"]), &db, SetOperation::Diff).await?;
+        assert_eq!(sorted(diff), vec!["b", "d"]);
+
+        // A missing key is an empty set
+        let inter =
+            set_operation_value(&args(&["k1", "missing"]), &db, SetOperation::Inter).await?;
+        assert!(sorted(inter).is_empty());
+        let diff = set_operation_value(&args(&["missing", "k1"]), &db, SetOperation::Diff).await?;
+        assert!(sorted(diff).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_srandmember() -> Result<()> {
+        let db = db();
+        sadd_value(&args(&["tags", "a", "b", "c"]), &db).await?;
+        let all = vec!["a", "b", "c"];
+
+        let Value::BulkString(member) = srandmember_value(&args(&["tags"]), &db).await? else {
+            panic!("expected a member");
+        };
+        assert!(all.iter().any(|m| m.as_bytes() == member));
+        // A positive count never repeats and stops at the size of the set
+        let two = sorted(srandmember_value(&args(&["tags", "2"]), &db).await?);
+        assert_eq!(two.len(), 2);
+        assert_ne!(two[0], two[1]);
+        assert_eq!(
+            sorted(srandmember_value(&args(&["tags", "10"]), &db).await?),
+            all
+        );
+        // A negative count may repeat members
+        let many = sorted(srandmember_value(&args(&["tags", "-10"]), &db).await?);
+        assert_eq!(many.len(), 10);
+        assert!(many.iter().all(|m| all.contains(&m.as_str())));
+
+        assert_eq!(
+            srandmember_value(&args(&["missing"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(
+            srandmember_value(&args(&["missing", "-3"]), &db).await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            srandmember_value(&args(&["tags", "x"]), &db).await?,
+            CommandError::NotInteger.into()
+        );
+        // The one count whose magnitude does not fit is refused, not allocated for
+        assert_eq!(
+            srandmember_value(&args(&["tags", "-9223372036854775808"]), &db).await?,
+            Value::SimpleError("ERR value is out of range".to_owned())
+        );
+        assert_eq!(scard_value(&args(&["tags"]), &db).await?, Value::Integer(3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spop() -> Result<()> {
+        let db = db();
+        sadd_value(&args(&["tags", "a", "b", "c"]), &db).await?;
+
+        let Value::BulkString(first) = spop_value(&args(&["tags"]), &db).await? else {
+            panic!("expected a member");
+        };
+        let rest = sorted(spop_value(&args(&["tags", "5"]), &db).await?);
+        assert_eq!(rest.len(), 2);
+        assert!(!rest.iter().any(|m| m.as_bytes() == first));
+        // Popping the last member removes the key
+        assert!(db.read().await.get("tags").is_none());
+
+        assert_eq!(spop_value(&args(&["tags"]), &db).await?, Value::Null);
+        assert_eq!(
+            spop_value(&args(&["tags", "1"]), &db).await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            spop_value(&args(&["tags", "-1"]), &db).await?,
+            Value::SimpleError("ERR value is out of range, must be positive".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() -> Result<()> {
+        let db = db();
+        sadd_value(&args(&["tags", "a"]), &db).await?;
+        db.write()
+            .await
+            .insert_entry("text".to_owned(), Entry::new(DataType::String("v".into())));
+
+        assert_eq!(sadd_value(&args(&["text", "a"]), &db).await?, wrong_type());
+        assert_eq!(smembers_value(&args(&["text"]), &db).await?, wrong_type());
+        assert_eq!(spop_value(&args(&["text"]), &db).await?, wrong_type());
+        assert_eq!(
+            srandmember_value(&args(&["text"]), &db).await?,
+            wrong_type()
+        );
+        assert_eq!(
+            set_operation_value(&args(&["tags", "text"]), &db, SetOperation::Union).await?,
+            wrong_type()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sscan() -> Result<()> {
+        let db = db();
+        for i in 0..20 {
+            sadd_value(&args(&["tags", &format!("tag:{}", i)]), &db).await?;
+        }
+        sadd_value(&args(&["tags", "other"]), &db).await?;
+
+        let mut cursor = "0".to_owned();
+        let mut members = Vec::new();
+        loop {
+            let reply = sscan_value(
+                &args(&["tags", &cursor, "MATCH", "tag:*", "COUNT", "4"]),
+                &db,
+            )
+            .await?;
+            let Value::Array(mut reply) = reply else {
+                panic!("expected an array reply, got {:?}", reply);
+            };
+            members.extend(sorted(reply.pop().unwrap()));
+            let Some(Value::BulkString(next)) = reply.pop() else {
+                panic!("expected a cursor");
+            };
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        members.sort();
+        members.dedup();
+        assert_eq!(members.len(), 20);
+        assert!(!members.contains(&"other".to_owned()));
+
+        // Sets have no values to leave out
+        assert_eq!(
+            sscan_value(&args(&["tags", "0", "NOVALUES"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,817 @@
+use bytes::Bytes;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Notify;
+
+use crate::commands::list::deadline_after;
+use crate::commands::server::help_value;
+use crate::connection::dispatch::Caller;
+use crate::connection::{integer_arg, run_command, unpack_bulk_string};
+use crate::error::{unknown_subcommand, wrong_arity, CommandError, Error, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::stream::{Fields, IdRequest, Stream, StreamId};
+use crate::storage::{DataType, Entry};
+
+pub mod tests_stream;
+
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+fn entry_reply(id: &StreamId, fields: &Fields) -> Value {
+    Value::Array(vec![
+        Value::BulkString(id.to_string().into()),
+        Value::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| {
+                    [
+                        Value::BulkString(field.clone()),
+                        Value::BulkString(value.clone()),
+                    ]
+                })
+                .collect(),
+        ),
+    ])
+}
+
+/// XADD key <* | ms-* | ms-seq> field value [field value ...] replies with the ID of
+/// the new entry.
+pub async fn xadd_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 4 || !args.len().is_multiple_of(2) {
+        return Ok(wrong_arity("xadd"));
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some(request) = IdRequest::parse(&unpack_bulk_string(args[1].clone())?) else {
+        return Ok(Value::SimpleError(INVALID_ID.to_owned()));
+    };
+    if request == IdRequest::Explicit(StreamId::MIN) {
+        return Ok(Value::SimpleError(
+            "ERR The ID specified in XADD must be greater than 0-0".to_owned(),
+        ));
+    }
+    let fields = args[2..]
+        .chunks(2)
+        .map(|pair| match pair {
+            [Value::BulkString(field), Value::BulkString(value)] => {
+                Ok((field.clone(), value.clone()))
+            }
+            _ => Err(Error::InvalidArgument("Invalid field type")),
+        })
+        .collect::<Result<Fields>>()?;
+
+    // Acquire a write lock on the database instance, creating the stream on first write
+    let mut instance = db_instance.write_key(&key).await;
+    let stream = match instance.get_or_create_stream(&key) {
+        Ok(stream) => stream,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let Some(id) = stream.next_id(request, db_instance.now_millis()) else {
+        // Nothing was added, so a stream created just now must not stay behind
+        if stream.entries().is_empty() {
+            instance.remove(&key);
+        }
+        return Ok(Value::SimpleError(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_owned(),
+        ));
+    };
+    stream.insert(id, fields);
+    // Every XREAD waiting on the stream sees the new entry
+    instance.wake_blocked(&key, usize::MAX);
+
+    Ok(Value::BulkString(id.to_string().into()))
+}
+
+pub async fn xlen_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_stream(&key) {
+        Ok(stream) => Ok(Value::Integer(
+            stream.map_or(0, |stream| stream.entries().len()) as i64,
+        )),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+// A range bound: `-` and `+` are the smallest and greatest IDs, and a bare time
+// covers every sequence number in that millisecond
+fn parse_bound(value: &Value, default_seq: u64) -> Result<Option<StreamId>> {
+    Ok(match unpack_bulk_string(value.clone())?.as_str() {
+        "-" => Some(StreamId::MIN),
+        "+" => Some(StreamId::MAX),
+        bound => StreamId::parse(bound, default_seq),
+    })
+}
+
+/// XRANGE key start end [COUNT count] and XREVRANGE key end start [COUNT count].
+pub async fn xrange_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    reverse: bool,
+) -> Result<Value> {
+    let count = match args {
+        [_, _, _] => None,
+        [_, _, _, option, count]
+            if unpack_bulk_string(option.clone())?.eq_ignore_ascii_case("COUNT") =>
+        {
+            match integer_arg(count) {
+                Some(count) => Some(count.max(0) as usize),
+                None => return Ok(CommandError::NotInteger.into()),
+            }
+        }
+        [_, _, _, ..] => return Ok(CommandError::Syntax.into()),
+        _ => return Ok(CommandError::WrongArity.into()),
+    };
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (low, high) = if reverse {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let (Some(start), Some(end)) = (parse_bound(low, 0)?, parse_bound(high, u64::MAX)?) else {
+        return Ok(Value::SimpleError(INVALID_ID.to_owned()));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let stream = match instance.get_stream(&key) {
+        Ok(Some(stream)) if start <= end => stream,
+        Ok(_) => return Ok(Value::Array(vec![])),
+        Err(err) => return Ok(err.into()),
+    };
+
+    let range = stream.entries().range(start..=end);
+    let entries: Box<dyn Iterator<Item = (&StreamId, &Fields)>> = if reverse {
+        Box::new(range.rev())
+    } else {
+        Box::new(range)
+    };
+    Ok(Value::Array(
+        entries
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect(),
+    ))
+}
+
+// Where XREAD starts reading a stream: after an ID, or `$` for only new entries.
+// XREADGROUP reads a consumer's pending entries after an ID, or with `>` the entries
+// the group has not delivered yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadFrom {
+    After(StreamId),
+    Last,
+    Undelivered,
+}
+
+#[derive(Debug)]
+struct XreadArgs {
+    // The group and consumer of XREADGROUP
+    group: Option<(String, String)>,
+    noack: bool,
+    count: Option<usize>,
+    // BLOCK in milliseconds, 0 meaning forever
+    block: Option<u64>,
+    streams: Vec<(String, ReadFrom)>,
+}
+
+impl XreadArgs {
+    fn keys(&self) -> Vec<String> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+}
+
+// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...], or with
+// `grouped` XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+// STREAMS key [key ...] id [id ...]. The error is the reply to send
+fn parse_xread_args(
+    args: &[Value],
+    grouped: bool,
+) -> Result<std::result::Result<XreadArgs, Value>> {
+    let mut parsed = XreadArgs {
+        group: None,
+        noack: false,
+        count: None,
+        block: None,
+        streams: Vec::new(),
+    };
+    let mut index = 0;
+    loop {
+        let Some(option) = args.get(index) else {
+            return Ok(Err(CommandError::Syntax.into()));
+        };
+        let option = unpack_bulk_string(option.clone())?.to_uppercase();
+        match option.as_str() {
+            "STREAMS" => {
+                index += 1;
+                break;
+            }
+            "NOACK" if grouped => {
+                parsed.noack = true;
+                index += 1;
+                continue;
+            }
+            "GROUP" if grouped => {
+                let (Some(group), Some(consumer)) = (args.get(index + 1), args.get(index + 2))
+                else {
+                    return Ok(Err(CommandError::Syntax.into()));
+                };
+                parsed.group = Some((
+                    unpack_bulk_string(group.clone())?,
+                    unpack_bulk_string(consumer.clone())?,
+                ));
+                index += 3;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args.get(index + 1).and_then(integer_arg);
+        match (option.as_str(), value) {
+            ("COUNT", Some(count)) => parsed.count = Some(count.max(0) as usize),
+            ("BLOCK", Some(block)) if block >= 0 => parsed.block = Some(block as u64),
+            ("BLOCK", Some(_)) => {
+                return Ok(Err(Value::SimpleError(
+                    "ERR timeout is negative".to_owned(),
+                )))
+            }
+            ("COUNT" | "BLOCK", None) => return Ok(Err(CommandError::NotInteger.into())),
+            _ => return Ok(Err(CommandError::Syntax.into())),
+        }
+        index += 2;
+    }
+
+    if grouped && parsed.group.is_none() {
+        return Ok(Err(Value::SimpleError(
+            "ERR Missing GROUP option for XREADGROUP".to_owned(),
+        )));
+    }
+    let rest = &args[index..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        let (name, newest) = if grouped {
+            ("xreadgroup", '>')
+        } else {
+            ("xread", '$')
+        };
+        return Ok(Err(Value::SimpleError(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.",
+            name, newest
+        ))));
+    }
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    for (key, id) in keys.iter().zip(ids) {
+        let id = match unpack_bulk_string(id.clone())?.as_str() {
+            "$" if !grouped => ReadFrom::Last,
+            ">" if grouped => ReadFrom::Undelivered,
+            "$" => {
+                return Ok(Err(Value::SimpleError(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP".to_owned(),
+                )))
+            }
+            id => match StreamId::parse(id, 0) {
+                Some(id) => ReadFrom::After(id),
+                None => return Ok(Err(Value::SimpleError(INVALID_ID.to_owned()))),
+            },
+        };
+        parsed.streams.push((unpack_bulk_string(key.clone())?, id));
+    }
+    Ok(Ok(parsed))
+}
+
+// The entries after each stream's position, for the streams that have any. `$` is
+// resolved to the stream's current last ID, so it never returns anything here
+fn read_streams(
+    instance: &impl Storage,
+    streams: &[(String, ReadFrom)],
+    count: Option<usize>,
+) -> std::result::Result<Value, Value> {
+    let mut replies = Vec::new();
+    for (key, from) in streams {
+        let Some(stream) = instance.get_stream(key)? else {
+            continue;
+        };
+        let ReadFrom::After(after) = *from else {
+            continue;
+        };
+        let entries: Vec<Value> = stream
+            .entries()
+            .range(after..)
+            .skip_while(|(id, _)| **id == after)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect();
+        if !entries.is_empty() {
+            replies.push(Value::Array(vec![
+                Value::BulkString(Bytes::from(key.clone())),
+                Value::Array(entries),
+            ]));
+        }
+    }
+    Ok(if replies.is_empty() {
+        Value::NullArray
+    } else {
+        Value::Array(replies)
+    })
+}
+
+/// XREAD without blocking, as inside EXEC: BLOCK is ignored.
+pub async fn xread_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let parsed = match parse_xread_args(args, false)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let instance = db_instance.read_keys(&parsed.keys()).await;
+    Ok(read_streams(&instance, &parsed.streams, parsed.count).unwrap_or_else(|reply| reply))
+}
+
+/// XREAD for a client. With BLOCK and nothing to read yet, the connection waits until an
+/// XADD to one of the streams or the timeout, and then replies with a null array.
+pub async fn blocking_xread_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: usize,
+) -> Result<Value> {
+    let mut parsed = match parse_xread_args(args, false)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let db_instance = &state.databases[selected];
+    let Some(block) = parsed.block else {
+        let _shared = state.exec_lock.read().await;
+        return xread_value(args, db_instance).await;
+    };
+    let deadline = deadline_after((block > 0).then(|| Duration::from_millis(block)));
+    let keys = parsed.keys();
+    let waiter = Arc::new(Notify::new());
+
+    // `$` means entries added from now on
+    {
+        let _shared = state.exec_lock.read().await;
+        let instance = db_instance.read_keys(&keys).await;
+        for (key, from) in &mut parsed.streams {
+            if *from == ReadFrom::Last {
+                let last = match instance.get_stream(key) {
+                    Ok(stream) => stream.map_or(StreamId::MIN, |stream| stream.last_id()),
+                    Err(err) => return Ok(err.into()),
+                };
+                *from = ReadFrom::After(last);
+            }
+        }
+    }
+
+    loop {
+        // Queue up before looking, so an XADD in between still wakes us
+        let reply = {
+            let _shared = state.exec_lock.read().await;
+            let mut instance = db_instance.write_keys(&keys).await;
+            instance.block(&keys, &waiter);
+            read_streams(&instance, &parsed.streams, parsed.count)
+        };
+
+        match reply {
+            Ok(Value::NullArray) => {}
+            Ok(reply) | Err(reply) => {
+                db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+                return Ok(reply);
+            }
+        }
+
+        let woken = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, waiter.notified())
+                .await
+                .is_ok(),
+            None => {
+                waiter.notified().await;
+                true
+            }
+        };
+        db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+        if !woken {
+            return Ok(Value::NullArray);
+        }
+    }
+}
+
+fn no_group(key: &str, group: &str) -> Value {
+    Value::SimpleError(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        key, group
+    ))
+}
+
+// Checks that every stream has the group, then reports whether any of them has
+// entries the group has not delivered yet
+fn group_ready(
+    instance: &impl Storage,
+    group: &str,
+    streams: &[(String, ReadFrom)],
+) -> std::result::Result<bool, Value> {
+    let mut ready = false;
+    for (key, _) in streams {
+        let Some(found) = instance.get_stream(key)?.and_then(|stream| {
+            let last_delivered = stream.group(group)?.last_delivered();
+            Some(
+                stream
+                    .entries()
+                    .last_key_value()
+                    .is_some_and(|(id, _)| *id > last_delivered),
+            )
+        }) else {
+            return Err(Value::SimpleError(format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                key, group
+            )));
+        };
+        ready |= found;
+    }
+    Ok(ready)
+}
+
+// Runs XREADGROUP against the streams. `>` hands out new entries and every other ID
+// replays the consumer's own pending entries after it
+fn read_group_streams(
+    instance: &mut impl StorageMut,
+    parsed: &XreadArgs,
+    now: u64,
+) -> std::result::Result<Value, Value> {
+    let Some((group, consumer)) = &parsed.group else {
+        return Ok(Value::NullArray);
+    };
+    group_ready(instance, group, &parsed.streams)?;
+    let count = parsed
+        .count
+        .filter(|count| *count > 0)
+        .unwrap_or(usize::MAX);
+
+    let mut replies = Vec::new();
+    for (key, from) in &parsed.streams {
+        let Some(stream) = instance.get_stream_mut(key)? else {
+            continue;
+        };
+        let entries: Vec<Value> = match *from {
+            ReadFrom::Undelivered => {
+                let delivered = stream
+                    .read_group(group, consumer, count, parsed.noack, now)
+                    .unwrap_or_default();
+                if delivered.is_empty() {
+                    continue;
+                }
+                delivered
+                    .iter()
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            }
+            ReadFrom::After(after) => {
+                let Some(group) = stream.group_mut(group) else {
+                    continue;
+                };
+                group.create_consumer(consumer);
+                let history: Vec<StreamId> = group.consumers()[consumer.as_str()]
+                    .pending()
+                    .range(after..)
+                    .filter(|id| **id != after)
+                    .take(count)
+                    .copied()
+                    .collect();
+                history
+                    .iter()
+                    .map(|id| match stream.entries().get(id) {
+                        Some(fields) => entry_reply(id, fields),
+                        None => Value::Array(vec![
+                            Value::BulkString(id.to_string().into()),
+                            Value::Null,
+                        ]),
+                    })
+                    .collect()
+            }
+            ReadFrom::Last => continue,
+        };
+        replies.push(Value::Array(vec![
+            Value::BulkString(Bytes::from(key.clone())),
+            Value::Array(entries),
+        ]));
+    }
+    Ok(if replies.is_empty() {
+        Value::NullArray
+    } else {
+        Value::Array(replies)
+    })
+}
+
+/// XREADGROUP without blocking, as inside EXEC or when replayed: BLOCK is ignored.
+pub async fn xreadgroup_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let parsed = match parse_xread_args(args, true)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let mut instance = db_instance.write_keys(&parsed.keys()).await;
+    Ok(
+        read_group_streams(&mut instance, &parsed, db_instance.now_millis())
+            .unwrap_or_else(|reply| reply),
+    )
+}
+
+/// XREADGROUP for a client. With BLOCK and only `>` IDs, the connection waits until one
+/// of the streams has an entry the group has not delivered, or the timeout. The read
+/// itself goes through `run_command` so it is logged and replicated like any write.
+pub async fn blocking_xreadgroup_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+) -> Result<Value> {
+    let parsed = match parse_xread_args(args, true)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let blocking = parsed
+        .streams
+        .iter()
+        .all(|(_, from)| *from == ReadFrom::Undelivered);
+    let (Some(block), true, Some((group, _))) = (parsed.block, blocking, &parsed.group) else {
+        let _shared = state.exec_lock.read().await;
+        return run_command(UserCommand::XReadGroup, args, state, selected, caller).await;
+    };
+    let deadline = deadline_after((block > 0).then(|| Duration::from_millis(block)));
+    let keys = parsed.keys();
+    let db_instance = Arc::clone(&state.databases[*selected]);
+    let waiter = Arc::new(Notify::new());
+
+    loop {
+        // Queue up before looking, so an XADD in between still wakes us
+        let mut instance = db_instance.write_keys(&keys).await;
+        instance.block(&keys, &waiter);
+        let ready = group_ready(&instance, group, &parsed.streams);
+        drop(instance);
+
+        match ready {
+            Ok(true) => {
+                let reply = {
+                    let _shared = state.exec_lock.read().await;
+                    run_command(UserCommand::XReadGroup, args, state, selected, caller).await?
+                };
+                // Another consumer may have taken the entries first, in which case we
+                // wait again
+                if reply != Value::NullArray {
+                    db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+                    return Ok(reply);
+                }
+            }
+            Ok(false) => {}
+            Err(reply) => {
+                db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+                return Ok(reply);
+            }
+        }
+
+        let woken = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, waiter.notified())
+                .await
+                .is_ok(),
+            None => {
+                waiter.notified().await;
+                true
+            }
+        };
+        db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+        if !woken {
+            return Ok(Value::NullArray);
+        }
+    }
+}
+
+/// XACK key group id [id ...] removes entries from the group's pending list and
+/// replies with how many were pending.
+pub async fn xack_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let group = unpack_bulk_string(args[1].clone())?;
+    let mut ids = Vec::with_capacity(args.len() - 2);
+    for id in &args[2..] {
+        match StreamId::parse(&unpack_bulk_string(id.clone())?, 0) {
+            Some(id) => ids.push(id),
+            None => return Ok(Value::SimpleError(INVALID_ID.to_owned())),
+        }
+    }
+
+    let mut instance = db_instance.write_key(&key).await;
+    let group = match instance.get_stream_mut(&key) {
+        Ok(stream) => stream.and_then(|stream| stream.group_mut(&group)),
+        Err(err) => return Ok(err.into()),
+    };
+    let Some(group) = group else {
+        return Ok(Value::Integer(0));
+    };
+    let acknowledged = ids.into_iter().filter(|id| group.acknowledge(*id)).count();
+    Ok(Value::Integer(acknowledged as i64))
+}
+
+/// XPENDING key group summarizes the group's pending entries, and
+/// XPENDING key group [IDLE min-idle-time] start end count [consumer] lists them.
+pub async fn xpending_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let group_name = unpack_bulk_string(args[1].clone())?;
+    let mut rest = &args[2..];
+    let mut min_idle = 0;
+    if rest.len() > 2 && unpack_bulk_string(rest[0].clone())?.eq_ignore_ascii_case("IDLE") {
+        let Some(idle) = integer_arg(&rest[1]) else {
+            return Ok(CommandError::NotInteger.into());
+        };
+        min_idle = idle.max(0) as u64;
+        rest = &rest[2..];
+    }
+    let range = match rest {
+        [] if args.len() == 2 => None,
+        [start, end, count, consumer @ ..] if consumer.len() <= 1 => {
+            let (Some(start), Some(end)) = (parse_bound(start, 0)?, parse_bound(end, u64::MAX)?)
+            else {
+                return Ok(Value::SimpleError(INVALID_ID.to_owned()));
+            };
+            let Some(count) = integer_arg(count) else {
+                return Ok(CommandError::NotInteger.into());
+            };
+            let consumer = match consumer.first() {
+                Some(consumer) => Some(unpack_bulk_string(consumer.clone())?),
+                None => None,
+            };
+            Some((start, end, count.max(0) as usize, consumer))
+        }
+        _ => return Ok(CommandError::Syntax.into()),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let group = match instance.get_stream(&key) {
+        Ok(stream) => stream.and_then(|stream| stream.group(&group_name)),
+        Err(err) => return Ok(err.into()),
+    };
+    let Some(group) = group else {
+        return Ok(no_group(&key, &group_name));
+    };
+    let pending = group.pending();
+
+    let Some((start, end, count, consumer)) = range else {
+        // The count, the smallest and greatest pending IDs and each consumer's share
+        let (Some((first, _)), Some((last, _))) =
+            (pending.first_key_value(), pending.last_key_value())
+        else {
+            return Ok(Value::Array(vec![
+                Value::Integer(0),
+                Value::Null,
+                Value::Null,
+                Value::NullArray,
+            ]));
+        };
+        let consumers = group
+            .consumers()
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending().is_empty())
+            .map(|(name, consumer)| {
+                Value::Array(vec![
+                    Value::BulkString(Bytes::from(name.clone())),
+                    Value::BulkString(consumer.pending().len().to_string().into()),
+                ])
+            })
+            .collect();
+        return Ok(Value::Array(vec![
+            Value::Integer(pending.len() as i64),
+            Value::BulkString(first.to_string().into()),
+            Value::BulkString(last.to_string().into()),
+            Value::Array(consumers),
+        ]));
+    };
+
+    if start > end {
+        return Ok(Value::Array(vec![]));
+    }
+    let now = db_instance.now_millis();
+    Ok(Value::Array(
+        pending
+            .range(start..=end)
+            .filter(|(_, entry)| consumer.as_ref().is_none_or(|name| entry.consumer == *name))
+            .filter(|(_, entry)| now.saturating_sub(entry.delivered_at) >= min_idle)
+            .take(count)
+            .map(|(id, entry)| {
+                Value::Array(vec![
+                    Value::BulkString(id.to_string().into()),
+                    Value::BulkString(Bytes::from(entry.consumer.clone())),
+                    Value::Integer(now.saturating_sub(entry.delivered_at) as i64),
+                    Value::Integer(entry.deliveries as i64),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+// An XGROUP ID argument: `$` is the stream's last ID, resolved once it is locked
+fn parse_group_id(value: &Value) -> Result<std::result::Result<Option<StreamId>, Value>> {
+    Ok(match unpack_bulk_string(value.clone())?.as_str() {
+        "$" => Ok(None),
+        id => StreamId::parse(id, 0)
+            .map(Some)
+            .ok_or_else(|| Value::SimpleError(INVALID_ID.to_owned())),
+    })
+}
+
+/// XGROUP CREATE key group <id | $> [MKSTREAM], SETID key group <id | $>,
+/// DESTROY key group, CREATECONSUMER key group consumer and DELCONSUMER key group
+/// consumer.
+pub async fn xgroup_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let Some(subcommand) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let subcommand = unpack_bulk_string(subcommand.clone())?.to_uppercase();
+    let arity_matches = match subcommand.as_str() {
+        "HELP" if args.len() == 1 => return Ok(help_value(UserCommand::XGroup)),
+        "CREATE" => (4..=5).contains(&args.len()),
+        "SETID" | "CREATECONSUMER" | "DELCONSUMER" => args.len() == 4,
+        "DESTROY" => args.len() == 3,
+        other => return Ok(unknown_subcommand("XGROUP", other)),
+    };
+    if !arity_matches {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[1].clone())?;
+    let group = unpack_bulk_string(args[2].clone())?;
+    let id = match subcommand.as_str() {
+        "CREATE" | "SETID" => match parse_group_id(&args[3])? {
+            Ok(id) => id,
+            Err(reply) => return Ok(reply),
+        },
+        _ => None,
+    };
+    let mkstream = match args.get(4) {
+        Some(option) if unpack_bulk_string(option.clone())?.eq_ignore_ascii_case("MKSTREAM") => {
+            true
+        }
+        Some(_) => return Ok(CommandError::Syntax.into()),
+        None => false,
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    if mkstream && instance.get(&key).is_none() {
+        instance.insert_entry(key.clone(), Entry::new(DataType::Stream(Stream::new())));
+    }
+    let stream = match instance.get_stream_mut(&key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => {
+            return Ok(Value::SimpleError(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                    .to_owned(),
+            ))
+        }
+        Err(err) => return Ok(err.into()),
+    };
+    let id = id.unwrap_or(stream.last_id());
+    let missing_group = || {
+        Value::SimpleError(format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            group, key
+        ))
+    };
+
+    let reply = match subcommand.as_str() {
+        "CREATE" if stream.create_group(&group, id).is_some() => {
+            Value::SimpleString("OK".to_owned())
+        }
+        "CREATE" => Value::SimpleError("BUSYGROUP Consumer Group name already exists".to_owned()),
+        "SETID" => match stream.group_mut(&group) {
+            Some(consumer_group) => {
+                consumer_group.set_last_delivered(id);
+                Value::SimpleString("OK".to_owned())
+            }
+            None => return Ok(missing_group()),
+        },
+        "DESTROY" => Value::Integer(stream.destroy_group(&group) as i64),
+        _ => {
+            let Some(consumer_group) = stream.group_mut(&group) else {
+                return Ok(missing_group());
+            };
+            let consumer = unpack_bulk_string(args[3].clone())?;
+            match subcommand.as_str() {
+                "CREATECONSUMER" => {
+                    Value::Integer(consumer_group.create_consumer(&consumer) as i64)
+                }
+                _ => Value::Integer(consumer_group.delete_consumer(&consumer).unwrap_or(0) as i64),
+            }
+        }
+    };
+    // Readers blocked on the group see the new position, or that it is gone
+    if matches!(subcommand.as_str(), "SETID" | "DESTROY") {
+        instance.wake_blocked(&key, usize::MAX);
+    }
+    Ok(reply)
+}
@@ -0,0 +1,450 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::testing::args;
+    use anyhow::Result;
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> Value {
+        Value::Array(vec![
+            Value::BulkString(id.to_owned().into()),
+            Value::Array(
+                fields
+                    .iter()
+                    .map(|part| Value::BulkString(part.to_string().into()))
+                    .collect(),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_xadd_ids() -> Result<()> {
+        let db = db();
+        assert_eq!(
+            xadd_value(&args(&["stream", "1-1", "a", "1"]), &db).await?,
+            Value::BulkString("1-1".into())
+        );
+        assert_eq!(
+            xadd_value(&args(&["stream", "1-*", "b", "2"]), &db).await?,
+            Value::BulkString("1-2".into())
+        );
+        let Value::BulkString(generated) =
+            xadd_value(&args(&["stream", "*", "c", "3"]), &db).await?
+        else {
+            panic!("XADD replies with the new ID");
+        };
+        let generated = StreamId::parse(std::str::from_utf8(&generated)?, 0).unwrap();
+        assert!(generated > StreamId::new(1, 2));
+
+        assert_eq!(
+            xadd_value(&args(&["stream", "1-1", "d", "4"]), &db).await?,
+            Value::SimpleError(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            xadd_value(&args(&["other", "0-0", "d", "4"]), &db).await?,
+            Value::SimpleError("ERR The ID specified in XADD must be greater than 0-0".to_owned())
+        );
+        assert_eq!(
+            xadd_value(&args(&["stream", "1-x", "d", "4"]), &db).await?,
+            Value::SimpleError(INVALID_ID.to_owned())
+        );
+        assert_eq!(
+            xadd_value(&args(&["stream", "*", "field"]), &db).await?,
+            Value::SimpleError("ERR wrong number of arguments for 'xadd' command".to_owned())
+        );
+        assert_eq!(
+            xlen_value(&args(&["stream"]), &db).await?,
+            Value::Integer(3)
+        );
+        assert_eq!(xlen_value(&args(&["other"]), &db).await?, Value::Integer(0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xrange() -> Result<()> {
+        let db = db();
+        for id in ["1-1", "1-2", "2-0", "3-5"] {
+            xadd_value(&args(&["stream", id, "id", id]), &db).await?;
+        }
+
+        assert_eq!(
+            xrange_value(&args(&["stream", "-", "+", "COUNT", "2"]), &db, false).await?,
+            Value::Array(vec![
+                entry("1-1", &["id", "1-1"]),
+                entry("1-2", &["id", "1-2"])
+            ])
+        );
+        // A bare time covers the whole millisecond
+        assert_eq!(
+            xrange_value(&args(&["stream", "1", "2"]), &db, false).await?,
+            Value::Array(vec![
+                entry("1-1", &["id", "1-1"]),
+                entry("1-2", &["id", "1-2"]),
+                entry("2-0", &["id", "2-0"]),
+            ])
+        );
+        assert_eq!(
+            xrange_value(&args(&["stream", "+", "2", "COUNT", "2"]), &db, true).await?,
+            Value::Array(vec![
+                entry("3-5", &["id", "3-5"]),
+                entry("2-0", &["id", "2-0"])
+            ])
+        );
+        assert_eq!(
+            xrange_value(&args(&["stream", "3", "1"]), &db, false).await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            xrange_value(&args(&["stream", "-", "+", "LIMIT", "2"]), &db, false).await?,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xread() -> Result<()> {
+        let db = db();
+        xadd_value(&args(&["first", "1-1", "a", "1"]), &db).await?;
+        xadd_value(&args(&["first", "1-2", "b", "2"]), &db).await?;
+        xadd_value(&args(&["second", "5-0", "c", "3"]), &db).await?;
+
+        assert_eq!(
+            xread_value(
+                &args(&["COUNT", "1", "STREAMS", "first", "second", "1-1", "0"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![
+                Value::Array(vec![
+                    Value::BulkString("first".into()),
+                    Value::Array(vec![entry("1-2", &["b", "2"])]),
+                ]),
+                Value::Array(vec![
+                    Value::BulkString("second".into()),
+                    Value::Array(vec![entry("5-0", &["c", "3"])]),
+                ]),
+            ])
+        );
+        // Nothing newer, and `$` never has anything without blocking
+        assert_eq!(
+            xread_value(&args(&["STREAMS", "first", "second", "1-2", "$"]), &db).await?,
+            Value::NullArray
+        );
+        assert_eq!(
+            xread_value(&args(&["STREAMS", "first", "second", "0"]), &db).await?,
+            Value::SimpleError(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            xread_value(&args(&["BLOCK", "-5", "STREAMS", "first", "0"]), &db).await?,
+            Value::SimpleError("ERR timeout is negative".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_xread_past_any_deadline_waits_forever() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let blocked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let args = args(&["BLOCK", "9223372036854775807", "STREAMS", "events", "$"]);
+                blocking_xread_value(&args, &state, 0).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        xadd_value(&args(&["events", "1-1", "a", "1"]), &state.databases[0]).await?;
+        assert_eq!(
+            blocked.await??,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("events".into()),
+                Value::Array(vec![entry("1-1", &["a", "1"])]),
+            ])])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_xreadgroup_past_any_deadline_waits_forever() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let db = &state.databases[0];
+        xgroup_value(&args(&["CREATE", "events", "group", "$", "MKSTREAM"]), db).await?;
+        let blocked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let mut selected = 0;
+                let args = args(&[
+                    "GROUP",
+                    "group",
+                    "alice",
+                    "BLOCK",
+                    "9223372036854775807",
+                    "STREAMS",
+                    "events",
+                    ">",
+                ]);
+                blocking_xreadgroup_value(&args, &state, &mut selected, None).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        xadd_value(&args(&["events", "1-1", "a", "1"]), db).await?;
+        assert_eq!(
+            blocked.await??,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("events".into()),
+                Value::Array(vec![entry("1-1", &["a", "1"])]),
+            ])])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xgroup() -> Result<()> {
+        let db = db();
+        assert_eq!(
+            xgroup_value(&args(&["CREATE", "stream", "group", "$"]), &db).await?,
+            Value::SimpleError("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".to_owned())
+        );
+        assert_eq!(
+            xgroup_value(&args(&["CREATE", "stream", "group", "$", "MKSTREAM"]), &db).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            xgroup_value(&args(&["CREATE", "stream", "group", "0"]), &db).await?,
+            Value::SimpleError("BUSYGROUP Consumer Group name already exists".to_owned())
+        );
+        assert_eq!(
+            xgroup_value(&args(&["CREATECONSUMER", "stream", "group", "alice"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            xgroup_value(&args(&["CREATECONSUMER", "stream", "group", "alice"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            xgroup_value(&args(&["SETID", "stream", "missing", "0"]), &db).await?,
+            Value::SimpleError(
+                "NOGROUP No such consumer group 'missing' for key name 'stream'".to_owned()
+            )
+        );
+        assert_eq!(
+            xgroup_value(&args(&["SETID", "stream", "group", "5-5"]), &db).await?,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            xgroup_value(&args(&["DELCONSUMER", "stream", "group", "alice"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            xgroup_value(&args(&["DESTROY", "stream", "group"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            xgroup_value(&args(&["DESTROY", "stream", "group"]), &db).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            xgroup_value(&args(&["NOPE", "stream"]), &db).await?,
+            Value::SimpleError("ERR unknown subcommand 'nope'. Try XGROUP HELP.".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xreadgroup_and_xack() -> Result<()> {
+        let db = db();
+        for id in ["1-1", "1-2", "1-3"] {
+            xadd_value(&args(&["stream", id, "f", id]), &db).await?;
+        }
+        xgroup_value(&args(&["CREATE", "stream", "group", "0"]), &db).await?;
+
+        // New entries are split between consumers
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "alice", "COUNT", "2", "STREAMS", "stream", ">"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("stream".into()),
+                Value::Array(vec![
+                    entry("1-1", &["f", "1-1"]),
+                    entry("1-2", &["f", "1-2"])
+                ]),
+            ])])
+        );
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "bob", "NOACK", "STREAMS", "stream", ">"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("stream".into()),
+                Value::Array(vec![entry("1-3", &["f", "1-3"])]),
+            ])])
+        );
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "bob", "STREAMS", "stream", ">"]),
+                &db
+            )
+            .await?,
+            Value::NullArray
+        );
+
+        // An ID instead of `>` replays the consumer's own pending entries
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "alice", "STREAMS", "stream", "1-1"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("stream".into()),
+                Value::Array(vec![entry("1-2", &["f", "1-2"])]),
+            ])])
+        );
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "bob", "STREAMS", "stream", "0"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("stream".into()),
+                Value::Array(vec![]),
+            ])])
+        );
+
+        assert_eq!(
+            xack_value(&args(&["stream", "group", "1-1", "1-3", "9-9"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            xack_value(&args(&["stream", "missing", "1-2"]), &db).await?,
+            Value::Integer(0)
+        );
+
+        assert_eq!(
+            xreadgroup_value(&args(&["GROUP", "group", "bob", "STREAMS", "other", ">"]), &db)
+                .await?,
+            Value::SimpleError(
+                "NOGROUP No such key 'other' or consumer group 'group' in XREADGROUP with GROUP option"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            xreadgroup_value(
+                &args(&["GROUP", "group", "bob", "STREAMS", "stream", "$"]),
+                &db
+            )
+            .await?,
+            Value::SimpleError(
+                "ERR The $ ID is meaningless in the context of XREADGROUP".to_owned()
+            )
+        );
+        assert_eq!(
+            xreadgroup_value(&args(&["STREAMS", "stream", ">"]), &db).await?,
+            Value::SimpleError("ERR Missing GROUP option for XREADGROUP".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xpending() -> Result<()> {
+        let db = db();
+        xgroup_value(&args(&["CREATE", "stream", "group", "$", "MKSTREAM"]), &db).await?;
+        assert_eq!(
+            xpending_value(&args(&["stream", "group"]), &db).await?,
+            Value::Array(vec![
+                Value::Integer(0),
+                Value::Null,
+                Value::Null,
+                Value::NullArray
+            ])
+        );
+
+        for id in ["1-1", "1-2", "1-3"] {
+            xadd_value(&args(&["stream", id, "f", "v"]), &db).await?;
+        }
+        xreadgroup_value(
+            &args(&[
+                "GROUP", "group", "alice", "COUNT", "1", "STREAMS", "stream", ">",
+            ]),
+            &db,
+        )
+        .await?;
+        xreadgroup_value(
+            &args(&["GROUP", "group", "bob", "STREAMS", "stream", ">"]),
+            &db,
+        )
+        .await?;
+
+        assert_eq!(
+            xpending_value(&args(&["stream", "group"]), &db).await?,
+            Value::Array(vec![
+                Value::Integer(3),
+                Value::BulkString("1-1".into()),
+                Value::BulkString("1-3".into()),
+                Value::Array(vec![
+                    Value::Array(vec![
+                        Value::BulkString("alice".into()),
+                        Value::BulkString("1".into())
+                    ]),
+                    Value::Array(vec![
+                        Value::BulkString("bob".into()),
+                        Value::BulkString("2".into())
+                    ]),
+                ]),
+            ])
+        );
+
+        let Value::Array(entries) =
+            xpending_value(&args(&["stream", "group", "-", "+", "10", "bob"]), &db).await?
+        else {
+            panic!("XPENDING with a range replies with an array");
+        };
+        assert_eq!(entries.len(), 2);
+        let Value::Array(first) = &entries[0] else {
+            panic!("Each pending entry is an array");
+        };
+        assert_eq!(first[0], Value::BulkString("1-2".into()));
+        assert_eq!(first[1], Value::BulkString("bob".into()));
+        assert_eq!(first[3], Value::Integer(1));
+
+        // Nothing has been pending for an hour yet
+        assert_eq!(
+            xpending_value(
+                &args(&["stream", "group", "IDLE", "3600000", "-", "+", "10"]),
+                &db
+            )
+            .await?,
+            Value::Array(vec![])
+        );
+        assert_eq!(
+            xpending_value(&args(&["stream", "missing"]), &db).await?,
+            Value::SimpleError(
+                "NOGROUP No such key 'stream' or consumer group 'missing'".to_owned()
+            )
+        );
+        assert_eq!(
+            xpending_value(&args(&["stream", "group", "-", "+"]), &db).await?,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,711 @@
+use bytes::Bytes;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::commands::list::{deadline_after, parse_blocking_args, BlockingArgs};
+use crate::connection::dispatch::Caller;
+use crate::connection::{
+    integer_arg, parse_scan_options, run_command, scan_reply, unpack_bulk_string,
+};
+use crate::error::{CommandError, Error, Result};
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::sorted_set::{format_score, parse_score, LexBound, ScoreBound};
+
+pub mod tests_zset;
+
+/// Flags accepted by ZADD before the score/member pairs.
+#[derive(Debug, Default, PartialEq)]
+pub struct ZaddOptions {
+    pub only_new: bool,
+    pub only_existing: bool,
+    pub greater_than: bool,
+    pub less_than: bool,
+    pub changed: bool,
+}
+
+/// Parses the leading ZADD flags and returns them with the index of the first score.
+pub fn parse_zadd_options(args: &[Value]) -> std::result::Result<(ZaddOptions, usize), String> {
+    let mut options = ZaddOptions::default();
+    let mut index = 0;
+
+    while let Some(Value::BulkString(flag)) = args.get(index) {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" => options.only_new = true,
+            b"XX" => options.only_existing = true,
+            b"GT" => options.greater_than = true,
+            b"LT" => options.less_than = true,
+            b"CH" => options.changed = true,
+            _ => break,
+        }
+        index += 1;
+    }
+
+    if options.only_new && options.only_existing {
+        return Err("ERR XX and NX options at the same time are not compatible".to_owned());
+    }
+    if (options.greater_than && options.less_than)
+        || (options.only_new && (options.greater_than || options.less_than))
+    {
+        return Err("ERR GT, LT, and/or NX options at the same time are not compatible".to_owned());
+    }
+
+    Ok((options, index))
+}
+
+pub async fn zadd_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (options, first_pair) = match parse_zadd_options(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    let pairs = &args[1 + first_pair..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Ok(CommandError::Syntax.into());
+    }
+
+    // Validate every score before touching the set so a bad pair changes nothing
+    let mut members = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        let (Value::BulkString(score), Value::BulkString(member)) = (&pair[0], &pair[1]) else {
+            return Err(Error::InvalidArgument("Invalid score or member type"));
+        };
+        let Some(score) = parse_score(score) else {
+            return Ok(CommandError::NotFloat.into());
+        };
+        members.push((member.clone(), score));
+    }
+
+    // Acquire a write lock on the database instance, creating the set on first write
+    let mut instance = db_instance.write_key(&key).await;
+    if options.only_existing && instance.get(&key).is_none() {
+        return Ok(Value::Integer(0));
+    }
+    let set = match instance.get_or_create_sorted_set(&key) {
+        Ok(set) => set,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let (mut added, mut updated) = (0, 0);
+    for (member, score) in members {
+        match set.score(&member) {
+            None if options.only_existing => {}
+            None => {
+                set.insert(member, score);
+                added += 1;
+            }
+            Some(_) if options.only_new => {}
+            Some(current) => {
+                let allowed = (!options.greater_than || score > current)
+                    && (!options.less_than || score < current);
+                if allowed && score != current {
+                    set.insert(member, score);
+                    updated += 1;
+                }
+            }
+        }
+    }
+
+    // Give clients blocked on the key a chance at every new member
+    instance.wake_blocked(&key, added as usize);
+
+    // CH counts updated scores too
+    let count = if options.changed {
+        added + updated
+    } else {
+        added
+    };
+    Ok(Value::Integer(count))
+}
+
+pub async fn zrem_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    let (removed, now_empty) = match instance.get_sorted_set_mut(&key) {
+        Ok(Some(set)) => {
+            let removed = args[1..]
+                .iter()
+                .filter(|member| matches!(member, Value::BulkString(member) if set.remove(member)))
+                .count();
+            (removed, set.is_empty())
+        }
+        Ok(None) => (0, false),
+        Err(err) => return Ok(err.into()),
+    };
+
+    // A sorted set never outlives its last member
+    if now_empty {
+        instance.remove(&key);
+    }
+
+    Ok(Value::Integer(removed as i64))
+}
+
+pub async fn zscore_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Value::BulkString(member) = &args[1] else {
+        return Err(Error::InvalidArgument("Invalid member type"));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_sorted_set(&key) {
+        Ok(set) => Ok(set
+            .and_then(|set| set.score(member))
+            .map_or(Value::Null, Value::Double)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+/// ZSCAN key cursor [MATCH pattern] [COUNT count] iterates over the members of a sorted
+/// set, each followed by its score.
+pub async fn zscan_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let key = unpack_bulk_string(args[0].clone())?;
+    let options = match parse_scan_options(&args[1..]) {
+        Ok(options) if options.novalues => return Ok(CommandError::Syntax.into()),
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get_sorted_set(&key) {
+        Ok(set) => set,
+        Err(err) => return Ok(err.into()),
+    };
+    let (cursor, members) = match set {
+        Some(set) => set.scan(options.cursor, options.count),
+        None => (0, Vec::new()),
+    };
+    let mut elements = Vec::new();
+    for (member, score) in members {
+        if options.matches(member) {
+            elements.push(Value::BulkString(member.clone()));
+            elements.push(Value::BulkString(format_score(score).into()));
+        }
+    }
+    Ok(scan_reply(cursor, elements))
+}
+
+pub async fn zcard_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_sorted_set(&key) {
+        Ok(set) => Ok(Value::Integer(set.map_or(0, |set| set.len()) as i64)),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+// ZRANK and ZREVRANK, with the optional WITHSCORE flag
+pub async fn zrank_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    reverse: bool,
+) -> Result<Value> {
+    let with_score = match args.get(2) {
+        None => false,
+        Some(Value::BulkString(flag)) if flag.eq_ignore_ascii_case(b"WITHSCORE") => true,
+        Some(_) => return Ok(CommandError::Syntax.into()),
+    };
+    if args.len() < 2 || args.len() > 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Value::BulkString(member) = &args[1] else {
+        return Err(Error::InvalidArgument("Invalid member type"));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get_sorted_set(&key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Ok(Value::Null),
+        Err(err) => return Ok(err.into()),
+    };
+
+    let (Some(rank), Some(score)) = (set.rank(member), set.score(member)) else {
+        return Ok(Value::Null);
+    };
+    let rank = if reverse { set.len() - 1 - rank } else { rank };
+
+    if with_score {
+        Ok(Value::Array(vec![
+            Value::Integer(rank as i64),
+            Value::BulkString(format_score(score).into()),
+        ]))
+    } else {
+        Ok(Value::Integer(rank as i64))
+    }
+}
+
+// ZRANGE and ZREVRANGE by rank, with the optional WITHSCORES flag
+pub async fn zrange_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    reverse: bool,
+) -> Result<Value> {
+    let with_scores = match args.get(3) {
+        None => false,
+        Some(Value::BulkString(flag)) if flag.eq_ignore_ascii_case(b"WITHSCORES") => true,
+        Some(_) => return Ok(CommandError::Syntax.into()),
+    };
+    if args.len() < 3 || args.len() > 4 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Some(start), Some(stop)) = (integer_arg(&args[1]), integer_arg(&args[2])) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let set = match instance.get_sorted_set(&key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Ok(Value::Array(vec![])),
+        Err(err) => return Ok(err.into()),
+    };
+
+    let Some((start, stop)) = normalize_range(start, stop, set.len()) else {
+        return Ok(Value::Array(vec![]));
+    };
+
+    let members: Box<dyn Iterator<Item = (&Bytes, f64)>> = if reverse {
+        Box::new(set.iter().rev())
+    } else {
+        Box::new(set.iter())
+    };
+
+    Ok(Value::Array(
+        members
+            .skip(start)
+            .take(stop - start + 1)
+            .flat_map(|(member, score)| {
+                let mut reply = vec![Value::BulkString(member.clone())];
+                if with_scores {
+                    reply.push(Value::BulkString(format_score(score).into()));
+                }
+                reply
+            })
+            .collect(),
+    ))
+}
+
+pub async fn zincrby_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Value::BulkString(increment), Value::BulkString(member)) = (&args[1], &args[2]) else {
+        return Err(Error::InvalidArgument("Invalid increment or member type"));
+    };
+    let Some(increment) = parse_score(increment) else {
+        return Ok(CommandError::NotFloat.into());
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    let current = match instance.get_sorted_set(&key) {
+        Ok(set) => set.and_then(|set| set.score(member)),
+        Err(err) => return Ok(err.into()),
+    };
+    // Only adding opposite infinities gives NaN
+    let score = current.unwrap_or(0.0) + increment;
+    if score.is_nan() {
+        return Ok(Value::SimpleError(
+            "ERR resulting score is not a number (NaN)".to_owned(),
+        ));
+    }
+
+    if let Ok(set) = instance.get_or_create_sorted_set(&key) {
+        if set.insert(member.clone(), score) {
+            instance.wake_blocked(&key, 1);
+        }
+    }
+    Ok(Value::Double(score))
+}
+
+// Parses the min and max of ZCOUNT and ZRANGEBYSCORE
+fn score_range(min: &Value, max: &Value) -> Option<(ScoreBound, ScoreBound)> {
+    let (Value::BulkString(min), Value::BulkString(max)) = (min, max) else {
+        return None;
+    };
+    Some((ScoreBound::parse(min)?, ScoreBound::parse(max)?))
+}
+
+pub async fn zcount_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some((min, max)) = score_range(&args[1], &args[2]) else {
+        return Ok(Value::SimpleError(
+            "ERR min or max is not a float".to_owned(),
+        ));
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_sorted_set(&key) {
+        Ok(set) => Ok(Value::Integer(
+            set.map_or(0, |set| set.range_by_score(min, max).count()) as i64,
+        )),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+/// Options that follow the bounds of ZRANGEBYSCORE and ZRANGEBYLEX. A negative
+/// LIMIT count means no limit.
+#[derive(Debug, PartialEq)]
+pub struct RangeOptions {
+    pub with_scores: bool,
+    pub offset: usize,
+    pub count: Option<usize>,
+}
+
+/// Parses WITHSCORES (when allowed) and LIMIT offset count.
+pub fn parse_range_options(
+    args: &[Value],
+    allow_scores: bool,
+) -> std::result::Result<RangeOptions, CommandError> {
+    let mut options = RangeOptions {
+        with_scores: false,
+        offset: 0,
+        count: None,
+    };
+    let mut index = 0;
+    while let Some(Value::BulkString(option)) = args.get(index) {
+        match option.to_ascii_uppercase().as_slice() {
+            b"WITHSCORES" if allow_scores => {
+                options.with_scores = true;
+                index += 1;
+            }
+            b"LIMIT" => {
+                let (Some(offset), Some(count)) = (
+                    args.get(index + 1).and_then(integer_arg),
+                    args.get(index + 2).and_then(integer_arg),
+                ) else {
+                    return Err(if args.len() < index + 3 {
+                        CommandError::Syntax
+                    } else {
+                        CommandError::NotInteger
+                    });
+                };
+                // A negative offset selects nothing, like an offset past the end
+                options.offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                options.count = usize::try_from(count).ok();
+                index += 3;
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    if index < args.len() {
+        return Err(CommandError::Syntax);
+    }
+    Ok(options)
+}
+
+// The reply of a range query: members, each followed by its score when asked for
+fn range_reply<'a>(
+    members: impl Iterator<Item = (&'a Bytes, f64)>,
+    options: &RangeOptions,
+) -> Value {
+    Value::Array(
+        members
+            .skip(options.offset)
+            .take(options.count.unwrap_or(usize::MAX))
+            .flat_map(|(member, score)| {
+                let mut reply = vec![Value::BulkString(member.clone())];
+                if options.with_scores {
+                    reply.push(Value::BulkString(format_score(score).into()));
+                }
+                reply
+            })
+            .collect(),
+    )
+}
+
+pub async fn zrangebyscore_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some((min, max)) = score_range(&args[1], &args[2]) else {
+        return Ok(Value::SimpleError(
+            "ERR min or max is not a float".to_owned(),
+        ));
+    };
+    let options = match parse_range_options(&args[3..], true) {
+        Ok(options) => options,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_sorted_set(&key) {
+        Ok(Some(set)) => Ok(range_reply(set.range_by_score(min, max), &options)),
+        Ok(None) => Ok(Value::Array(vec![])),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+pub async fn zrangebylex_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() < 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Value::BulkString(min), Value::BulkString(max)) = (&args[1], &args[2]) else {
+        return Err(Error::InvalidArgument("Invalid min or max type"));
+    };
+    let (Some(min), Some(max)) = (LexBound::parse(min), LexBound::parse(max)) else {
+        return Ok(Value::SimpleError(
+            "ERR min or max not valid string range item".to_owned(),
+        ));
+    };
+    let options = match parse_range_options(&args[3..], false) {
+        Ok(options) => options,
+        Err(err) => return Ok(err.into()),
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    match instance.get_sorted_set(&key) {
+        Ok(Some(set)) => Ok(range_reply(set.range_by_lex(&min, &max), &options)),
+        Ok(None) => Ok(Value::Array(vec![])),
+        Err(err) => Ok(err.into()),
+    }
+}
+
+// Which end of a sorted set ZPOPMIN, ZPOPMAX and their blocking variants take from
+fn pop_command(max: bool) -> UserCommand {
+    if max {
+        UserCommand::ZPopMax
+    } else {
+        UserCommand::ZPopMin
+    }
+}
+
+/// ZPOPMIN and ZPOPMAX key [count]: removes up to count members with the lowest or
+/// highest scores and replies with each member followed by its score.
+pub async fn zpop_value(args: &[Value], db_instance: &Arc<ShardedDb>, max: bool) -> Result<Value> {
+    if args.is_empty() || args.len() > 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let count = match args.get(1).map(integer_arg) {
+        None => 1,
+        Some(Some(count)) if count >= 0 => count as usize,
+        Some(Some(_)) => {
+            return Ok(Value::SimpleError(
+                "ERR value is out of range, must be positive".to_owned(),
+            ))
+        }
+        Some(None) => return Ok(CommandError::NotInteger.into()),
+    };
+
+    let mut instance = db_instance.write_key(&key).await;
+    match pop_members(&mut instance, &key, max, count) {
+        Ok(popped) => Ok(Value::Array(
+            popped
+                .into_iter()
+                .flat_map(|(member, score)| {
+                    [
+                        Value::BulkString(member),
+                        Value::BulkString(format_score(score).into()),
+                    ]
+                })
+                .collect(),
+        )),
+        Err(reply) => Ok(reply),
+    }
+}
+
+// Removes up to `count` members from one end of a sorted set; the error is the
+// WRONGTYPE reply
+fn pop_members(
+    instance: &mut impl StorageMut,
+    key: &str,
+    max: bool,
+    count: usize,
+) -> std::result::Result<Vec<(Bytes, f64)>, Value> {
+    let (popped, now_empty) = match instance.get_sorted_set_mut(key) {
+        Ok(Some(set)) => {
+            let popped: Vec<_> =
+                std::iter::from_fn(|| if max { set.pop_max() } else { set.pop_min() })
+                    .take(count)
+                    .collect();
+            (popped, set.is_empty())
+        }
+        Ok(None) => (Vec::new(), false),
+        Err(err) => return Err(err.into()),
+    };
+
+    // A sorted set never outlives its last member
+    if now_empty {
+        instance.remove(key);
+    }
+    Ok(popped)
+}
+
+// The first key holding a sorted set; the error is the WRONGTYPE reply
+fn first_ready_key(
+    instance: &impl Storage,
+    keys: &[String],
+) -> std::result::Result<Option<String>, Value> {
+    for key in keys {
+        if instance.get_sorted_set(key)?.is_some() {
+            return Ok(Some(key.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// BZPOPMIN and BZPOPMAX key [key ...] timeout without blocking, as inside EXEC: pops
+/// from the first non-empty sorted set and replies with its key, the member and its
+/// score, or a null array.
+pub async fn bzpop_value(args: &[Value], db_instance: &Arc<ShardedDb>, max: bool) -> Result<Value> {
+    let keys = match parse_blocking_args(args)? {
+        Ok(parsed) => parsed.keys,
+        Err(reply) => return Ok(reply),
+    };
+
+    let mut instance = db_instance.write_keys(&keys).await;
+    let key = match first_ready_key(&instance, &keys) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(Value::NullArray),
+        Err(reply) => return Ok(reply),
+    };
+    Ok(match pop_members(&mut instance, &key, max, 1) {
+        Ok(popped) => with_key(
+            key,
+            popped
+                .into_iter()
+                .flat_map(|(member, score)| {
+                    [
+                        Value::BulkString(member),
+                        Value::BulkString(format_score(score).into()),
+                    ]
+                })
+                .collect(),
+        ),
+        Err(reply) => reply,
+    })
+}
+
+// Puts the key in front of a single member and its score, the reply of BZPOPMIN and
+// BZPOPMAX. Nothing popped is a null array
+fn with_key(key: String, member: Vec<Value>) -> Value {
+    if member.is_empty() {
+        return Value::NullArray;
+    }
+    Value::Array(
+        std::iter::once(Value::BulkString(key.into()))
+            .chain(member)
+            .collect(),
+    )
+}
+
+/// BZPOPMIN and BZPOPMAX for a client: waits like BLPOP until a member is added to one
+/// of the sorted sets or the timeout passes. Members are taken with a plain ZPOPMIN or
+/// ZPOPMAX so that is what the AOF and replicas see.
+pub async fn blocking_zpop_value(
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+    max: bool,
+) -> Result<Value> {
+    let BlockingArgs { keys, timeout } = match parse_blocking_args(args)? {
+        Ok(parsed) => parsed,
+        Err(reply) => return Ok(reply),
+    };
+    let deadline = deadline_after(timeout);
+    let db_instance = Arc::clone(&state.databases[*selected]);
+    let waiter = Arc::new(Notify::new());
+
+    loop {
+        // Queue up before looking, so an add in between still wakes us
+        let mut instance = db_instance.write_keys(&keys).await;
+        instance.block(&keys, &waiter);
+        let ready = first_ready_key(&instance, &keys);
+        drop(instance);
+
+        let popped = match ready {
+            Ok(Some(key)) => {
+                let _shared = state.exec_lock.read().await;
+                let popped = run_command(
+                    pop_command(max),
+                    &[Value::BulkString(key.clone().into())],
+                    state,
+                    selected,
+                    caller,
+                )
+                .await?;
+                match popped {
+                    Value::Array(member) => with_key(key, member),
+                    reply => reply,
+                }
+            }
+            Ok(None) => Value::NullArray,
+            Err(reply) => reply,
+        };
+        // Another client may have emptied the set first, in which case we wait again
+        if popped != Value::NullArray {
+            db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+            return Ok(popped);
+        }
+
+        let woken = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, waiter.notified())
+                .await
+                .is_ok(),
+            None => {
+                waiter.notified().await;
+                true
+            }
+        };
+        db_instance.write_keys(&keys).await.unblock(&keys, &waiter);
+        if !woken {
+            return Ok(Value::NullArray);
+        }
+    }
+}
+
+/// Resolves inclusive start/stop ranks, where negative values count from the end,
+/// into in-bounds indexes. Returns None when the range is empty.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
@@ -0,0 +1,387 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::connection::wrong_type;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    fn bulk(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|value| Value::BulkString(value.to_string().into()))
+                .collect(),
+        )
+    }
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    async fn leaderboard() -> Result<Arc<ShardedDb>> {
+        let db = db();
+        zadd_value(&args(&["board", "1", "one", "2", "two", "3", "three"]), &db).await?;
+        Ok(db)
+    }
+
+    #[tokio::test]
+    async fn test_zadd_and_zscore() -> Result<()> {
+        let db = leaderboard().await?;
+        assert_eq!(
+            zadd_value(&args(&["board", "4", "four", "2.5", "two"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            zscore_value(&args(&["board", "two"]), &db).await?,
+            Value::Double(2.5)
+        );
+        assert_eq!(
+            zscore_value(&args(&["board", "missing"]), &db).await?,
+            Value::Null
+        );
+        assert_eq!(
+            zcard_value(&args(&["board"]), &db).await?,
+            Value::Integer(4)
+        );
+        assert_eq!(
+            zadd_value(&args(&["board", "abc", "one"]), &db).await?,
+            Value::SimpleError("ERR value is not a valid float".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zadd_flags() -> Result<()> {
+        let db = leaderboard().await?;
+
+        // NX only adds, XX only updates, CH counts updates
+        assert_eq!(
+            zadd_value(&args(&["board", "NX", "10", "one", "5", "five"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            zadd_value(&args(&["board", "XX", "CH", "10", "one", "6", "six"]), &db).await?,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            zscore_value(&args(&["board", "six"]), &db).await?,
+            Value::Null
+        );
+
+        // GT/LT only move scores in one direction
+        zadd_value(&args(&["board", "GT", "1", "one"]), &db).await?;
+        assert_eq!(
+            zscore_value(&args(&["board", "one"]), &db).await?,
+            Value::Double(10.0)
+        );
+        zadd_value(&args(&["board", "LT", "1", "one"]), &db).await?;
+        assert_eq!(
+            zscore_value(&args(&["board", "one"]), &db).await?,
+            Value::Double(1.0)
+        );
+
+        assert_eq!(
+            zadd_value(&args(&["board", "NX", "XX", "1", "one"]), &db).await?,
+            Value::SimpleError("ERR XX and NX options at the same time are not compatible".into())
+        );
+        assert_eq!(
+            zadd_value(&args(&["board", "GT", "LT", "1", "one"]), &db).await?,
+            Value::SimpleError(
+                "ERR GT, LT, and/or NX options at the same time are not compatible".into()
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zrange_and_zrevrange() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            zrange_value(&args(&["board", "0", "-1"]), &db, false).await?,
+            bulk(&["one", "two", "three"])
+        );
+        assert_eq!(
+            zrange_value(&args(&["board", "-2", "-1", "WITHSCORES"]), &db, false).await?,
+            bulk(&["two", "2", "three", "3"])
+        );
+        assert_eq!(
+            zrange_value(&args(&["board", "0", "0"]), &db, true).await?,
+            bulk(&["three"])
+        );
+        assert_eq!(
+            zrange_value(&args(&["board", "5", "10"]), &db, false).await?,
+            bulk(&[])
+        );
+        assert_eq!(
+            zrange_value(&args(&["missing", "0", "-1"]), &db, false).await?,
+            bulk(&[])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zrank_and_zrem() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            zrank_value(&args(&["board", "three"]), &db, false).await?,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            zrank_value(&args(&["board", "three"]), &db, true).await?,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            zrank_value(&args(&["board", "two", "WITHSCORE"]), &db, false).await?,
+            Value::Array(vec![Value::Integer(1), Value::BulkString("2".into())])
+        );
+        assert_eq!(
+            zrank_value(&args(&["board", "missing"]), &db, false).await?,
+            Value::Null
+        );
+
+        assert_eq!(
+            zrem_value(&args(&["board", "one", "missing"]), &db).await?,
+            Value::Integer(1)
+        );
+        zrem_value(&args(&["board", "two", "three"]), &db).await?;
+        assert!(db.read().await.get("board").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() -> Result<()> {
+        let db = db();
+        db.write()
+            .await
+            .insert_entry("text".to_owned(), Entry::new(DataType::String("v".into())));
+
+        assert_eq!(
+            zadd_value(&args(&["text", "1", "a"]), &db).await?,
+            wrong_type()
+        );
+        assert_eq!(
+            zrange_value(&args(&["text", "0", "-1"]), &db, false).await?,
+            wrong_type()
+        );
+        assert_eq!(
+            zscore_value(&args(&["text", "a"]), &db).await?,
+            wrong_type()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zincrby() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            zincrby_value(&args(&["board", "2.5", "one"]), &db).await?,
+            Value::Double(3.5)
+        );
+        assert_eq!(
+            zrange_value(&args(&["board", "-1", "-1"]), &db, false).await?,
+            bulk(&["one"])
+        );
+        assert_eq!(
+            zincrby_value(&args(&["new", "-1", "member"]), &db).await?,
+            Value::Double(-1.0)
+        );
+        assert_eq!(
+            zincrby_value(&args(&["board", "x", "one"]), &db).await?,
+            CommandError::NotFloat.into()
+        );
+
+        zincrby_value(&args(&["board", "inf", "two"]), &db).await?;
+        assert_eq!(
+            zincrby_value(&args(&["board", "-inf", "two"]), &db).await?,
+            Value::SimpleError("ERR resulting score is not a number (NaN)".to_owned())
+        );
+        assert_eq!(
+            zscore_value(&args(&["board", "two"]), &db).await?,
+            Value::Double(f64::INFINITY)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zcount_and_zrangebyscore() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            zcount_value(&args(&["board", "-inf", "+inf"]), &db).await?,
+            Value::Integer(3)
+        );
+        assert_eq!(
+            zcount_value(&args(&["board", "(1", "3"]), &db).await?,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            zcount_value(&args(&["board", "one", "3"]), &db).await?,
+            Value::SimpleError("ERR min or max is not a float".to_owned())
+        );
+
+        assert_eq!(
+            zrangebyscore_value(&args(&["board", "2", "+inf", "WITHSCORES"]), &db).await?,
+            bulk(&["two", "2", "three", "3"])
+        );
+        assert_eq!(
+            zrangebyscore_value(&args(&["board", "-inf", "(3", "LIMIT", "1", "5"]), &db).await?,
+            bulk(&["two"])
+        );
+        assert_eq!(
+            zrangebyscore_value(&args(&["board", "-inf", "inf", "LIMIT", "1", "-1"]), &db).await?,
+            bulk(&["two", "three"])
+        );
+        assert_eq!(
+            zrangebyscore_value(&args(&["board", "0", "1", "LIMIT", "0"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            zrangebyscore_value(&args(&["missing", "0", "1"]), &db).await?,
+            bulk(&[])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zrangebylex() -> Result<()> {
+        let db = db();
+        zadd_value(
+            &args(&["words", "0", "apple", "0", "banana", "0", "cherry"]),
+            &db,
+        )
+        .await?;
+
+        assert_eq!(
+            zrangebylex_value(&args(&["words", "-", "+"]), &db).await?,
+            bulk(&["apple", "banana", "cherry"])
+        );
+        assert_eq!(
+            zrangebylex_value(&args(&["words", "(apple", "[cherry"]), &db).await?,
+            bulk(&["banana", "cherry"])
+        );
+        assert_eq!(
+            zrangebylex_value(&args(&["words", "[b", "+", "LIMIT", "1", "1"]), &db).await?,
+            bulk(&["cherry"])
+        );
+        assert_eq!(
+            zrangebylex_value(&args(&["words", "apple", "+"]), &db).await?,
+            Value::SimpleError("ERR min or max not valid string range item".to_owned())
+        );
+        assert_eq!(
+            zrangebylex_value(&args(&["words", "-", "+", "WITHSCORES"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zpopmin_and_zpopmax() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            zpop_value(&args(&["board"]), &db, false).await?,
+            bulk(&["one", "1"])
+        );
+        assert_eq!(
+            zpop_value(&args(&["board", "5"]), &db, true).await?,
+            bulk(&["three", "3", "two", "2"])
+        );
+        assert!(db.read().await.get("board").is_none());
+        assert_eq!(zpop_value(&args(&["board"]), &db, true).await?, bulk(&[]));
+        assert_eq!(
+            zpop_value(&args(&["board", "-1"]), &db, false).await?,
+            Value::SimpleError("ERR value is out of range, must be positive".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bzpop_without_blocking() -> Result<()> {
+        let db = leaderboard().await?;
+
+        assert_eq!(
+            bzpop_value(&args(&["missing", "board", "0"]), &db, true).await?,
+            bulk(&["board", "three", "3"])
+        );
+        assert_eq!(
+            bzpop_value(&args(&["missing", "0"]), &db, false).await?,
+            Value::NullArray
+        );
+        assert_eq!(
+            bzpop_value(&args(&["board", "-1"]), &db, false).await?,
+            Value::SimpleError("ERR timeout is negative".to_owned())
+        );
+        assert_eq!(
+            bzpop_value(&args(&["board", "1e300"]), &db, false).await?,
+            Value::SimpleError("ERR timeout is out of range".to_owned())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_zpop_timeouts_out_of_range() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let mut selected = 0;
+        assert_eq!(
+            blocking_zpop_value(
+                &args(&["board", "1e300"]),
+                &state,
+                &mut selected,
+                None,
+                false
+            )
+            .await?,
+            Value::SimpleError("ERR timeout is out of range".to_owned())
+        );
+
+        // Past any deadline, it waits until a member is added
+        let blocked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let mut selected = 0;
+                let args = args(&["board", "9223372036854775807"]);
+                blocking_zpop_value(&args, &state, &mut selected, None, false).await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        zadd_value(&args(&["board", "1", "one"]), &state.databases[0]).await?;
+        assert_eq!(blocked.await??, bulk(&["board", "one", "1"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zscan() -> Result<()> {
+        let db = leaderboard().await?;
+        // A small sorted set fits in one page, each member followed by its score
+        let reply = zscan_value(&args(&["board", "0", "MATCH", "t*"]), &db).await?;
+        let Value::Array(reply) = reply else {
+            panic!("expected an array reply, got {:?}", reply);
+        };
+        assert_eq!(reply[0], Value::BulkString("0".into()));
+        let Value::Array(elements) = &reply[1] else {
+            panic!("expected the elements");
+        };
+        let mut pairs: Vec<&[Value]> = elements.chunks(2).collect();
+        pairs.sort_by_key(|pair| format!("{:?}", pair[0]));
+        assert_eq!(
+            Value::Array(pairs.concat()),
+            bulk(&["three", "3", "two", "2"])
+        );
+
+        assert_eq!(
+            zscan_value(&args(&["board", "0", "NOVALUES"]), &db).await?,
+            CommandError::Syntax.into()
+        );
+        assert_eq!(
+            zscan_value(&args(&["missing", "0"]), &db).await?,
+            Value::Array(vec![Value::BulkString("0".into()), bulk(&[])])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,558 @@
+use std::{fs, path::PathBuf};
+
+use crate::logging::{Level, LogFormat};
+use crate::parser::ProtocolLimits;
+use crate::propagation::KeyspaceEvents;
+use crate::replication::DEFAULT_BACKLOG_SIZE;
+
+pub mod tests_config;
+
+/// Server-wide settings shared by every connection. They come from an optional
+/// redis.conf-style file followed by `--name value` command-line flags, which win.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    // Password clients must send with AUTH before running other commands
+    pub requirepass: Option<String>,
+    // Connections beyond this many are turned away
+    pub maxclients: usize,
+    // Seconds a client may stay silent before it is disconnected, 0 meaning never
+    pub timeout: u64,
+    // Seconds between keepalive probes on an idle client connection, 0 meaning none
+    // are sent
+    pub tcp_keepalive: u64,
+    // Whether replies are sent right away rather than coalesced by Nagle's algorithm
+    pub tcp_nodelay: bool,
+    // How many connections the kernel queues until they are accepted
+    pub tcp_backlog: u32,
+    // Memory limit in bytes, 0 meaning unlimited
+    pub maxmemory: u64,
+    // Which keys make room once maxmemory is reached
+    pub maxmemory_policy: MaxMemoryPolicy,
+    // How many keys eviction compares to pick each victim
+    pub maxmemory_samples: usize,
+    pub databases: usize,
+    // Persistence: the working directory plus the snapshot and append-only file names
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    // Events taking at least this many milliseconds are recorded by LATENCY, 0 meaning
+    // none are
+    pub latency_monitor_threshold: u64,
+    // Whether keys are spread over a cluster by hash slot
+    pub cluster_enabled: bool,
+    // How far each class of client may fall behind reading its replies
+    pub client_output_buffer_limit: OutputBufferLimits,
+    // The longest bulk string and the most aggregate elements a client may send
+    pub proto_max_bulk_len: u64,
+    pub proto_max_multibulk_len: u64,
+    // As a replica, whether clients' writes are refused and whether reads are served
+    // while the link to the master is down
+    pub replica_read_only: bool,
+    pub replica_serve_stale_data: bool,
+    // Bytes of the command stream kept for replicas that reconnect
+    pub repl_backlog_size: u64,
+    // Seconds of silence after which either end of a replication link gives up on it,
+    // and how often a master pings its replicas so that a quiet one is not taken for
+    // gone
+    pub repl_timeout: u64,
+    pub repl_ping_replica_period: u64,
+    // What the server logs, and whether as text or JSON lines
+    pub loglevel: Level,
+    pub log_format: LogFormat,
+    // Which keyspace events are published to subscribers
+    pub notify_keyspace_events: KeyspaceEvents,
+}
+
+/// Every parameter name, in the order CONFIG GET reports them.
+pub const PARAMETERS: [&str; 30] = [
+    "bind",
+    "port",
+    "requirepass",
+    "maxclients",
+    "timeout",
+    "tcp-keepalive",
+    "tcp-nodelay",
+    "tcp-backlog",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "databases",
+    "dir",
+    "dbfilename",
+    "appendonly",
+    "appendfilename",
+    "appendfsync",
+    "latency-monitor-threshold",
+    "cluster-enabled",
+    "client-output-buffer-limit",
+    "proto-max-bulk-len",
+    "proto-max-multibulk-len",
+    "replica-read-only",
+    "replica-serve-stale-data",
+    "repl-backlog-size",
+    "repl-timeout",
+    "repl-ping-replica-period",
+    "loglevel",
+    "log-format",
+    "notify-keyspace-events",
+];
+
+// Parameters that only take effect at startup, so CONFIG SET refuses them
+const IMMUTABLE: [&str; 8] = [
+    "bind",
+    "port",
+    "tcp-backlog",
+    "databases",
+    "appendonly",
+    "appendfilename",
+    "appendfsync",
+    "cluster-enabled",
+];
+
+/// When the append-only file is flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    Always,
+    EverySec,
+    No,
+}
+
+/// What happens when a write would go over maxmemory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaxMemoryPolicy {
+    // Writes that may add data are refused
+    NoEviction,
+    // The least recently used keys are evicted first
+    AllKeysLru,
+    // Like AllKeysLru among the keys with a TTL
+    VolatileLru,
+    // The least frequently used keys are evicted first
+    AllKeysLfu,
+    // Like AllKeysLfu among the keys with a TTL
+    VolatileLfu,
+    AllKeysRandom,
+    // Keys with a TTL, the nearest expiry first
+    VolatileTtl,
+}
+
+impl MaxMemoryPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "noeviction" => Some(Self::NoEviction),
+            "allkeys-lru" => Some(Self::AllKeysLru),
+            "volatile-lru" => Some(Self::VolatileLru),
+            "allkeys-lfu" => Some(Self::AllKeysLfu),
+            "volatile-lfu" => Some(Self::VolatileLfu),
+            "allkeys-random" => Some(Self::AllKeysRandom),
+            "volatile-ttl" => Some(Self::VolatileTtl),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Whether only keys with a TTL may be evicted.
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileTtl
+        )
+    }
+
+    /// Whether keys are picked by access frequency rather than recency.
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+}
+
+/// A client-output-buffer-limit. A connection whose unwritten replies pass `hard` bytes,
+/// or stay above `soft` bytes for more than `soft_seconds`, is closed. 0 disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferLimit {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+/// The output buffer limit of each class of client: replicas, clients subscribed to
+/// pub/sub channels, and everyone else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputBufferLimits {
+    pub normal: BufferLimit,
+    pub replica: BufferLimit,
+    pub pubsub: BufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: BufferLimit::default(),
+            replica: BufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+            pubsub: BufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    /// Applies `<class> <hard> <soft> <soft seconds>` groups, each replacing the limit
+    /// of one class. Classes left out keep theirs.
+    pub fn set(&mut self, value: &str) -> Result<(), String> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        if words.is_empty() || !words.len().is_multiple_of(4) {
+            return Err("Wrong number of arguments in buffer limit configuration".to_owned());
+        }
+        // Every group is checked before any is applied
+        let mut limits = *self;
+        for group in words.chunks(4) {
+            let limit = match group[0].to_lowercase().as_str() {
+                "normal" => &mut limits.normal,
+                "replica" | "slave" => &mut limits.replica,
+                "pubsub" => &mut limits.pubsub,
+                _ => return Err(format!("Invalid client class '{}'", group[0])),
+            };
+            *limit = BufferLimit {
+                hard: parse_memory(group[1])?,
+                soft: parse_memory(group[2])?,
+                soft_seconds: parse_number("client-output-buffer-limit", group[3])?,
+            };
+        }
+        *self = limits;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let classes = [
+            ("normal", self.normal),
+            ("replica", self.replica),
+            ("pubsub", self.pubsub),
+        ];
+        for (index, (class, limit)) in classes.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(
+                f,
+                "{} {} {} {}",
+                class, limit.hard, limit.soft, limit.soft_seconds
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_owned(),
+            port: 6379,
+            requirepass: None,
+            maxclients: 10000,
+            timeout: 0,
+            tcp_keepalive: 300,
+            tcp_nodelay: true,
+            tcp_backlog: 511,
+            maxmemory: 0,
+            maxmemory_policy: MaxMemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            databases: 16,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_owned(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_owned(),
+            appendfsync: AppendFsync::EverySec,
+            latency_monitor_threshold: 0,
+            cluster_enabled: false,
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            proto_max_bulk_len: ProtocolLimits::default().max_bulk_len,
+            proto_max_multibulk_len: ProtocolLimits::default().max_multibulk_len,
+            replica_read_only: true,
+            replica_serve_stale_data: true,
+            repl_backlog_size: DEFAULT_BACKLOG_SIZE,
+            repl_timeout: 60,
+            repl_ping_replica_period: 10,
+            loglevel: Level::Notice,
+            log_format: LogFormat::Text,
+            notify_keyspace_events: KeyspaceEvents::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the configuration from the process arguments (without the program name):
+    /// an optional leading config file path, then any number of `--name value` flags.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter().peekable();
+        let mut config = Self::new();
+
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            let contents = fs::read_to_string(&path)
+                .map_err(|err| format!("Can't open config file '{}': {}", path, err))?;
+            config.load_str(&contents)?;
+        }
+
+        while let Some(flag) = args.next() {
+            let Some(name) = flag.strip_prefix("--") else {
+                return Err(format!("Unexpected argument '{}'", flag));
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for '--{}'", name))?;
+            config.set(name, &value)?;
+        }
+        Ok(config)
+    }
+
+    /// Applies every directive of a redis.conf-style file: one `name value` pair per
+    /// line, with blank lines and `#` comments ignored and optionally quoted values.
+    pub fn load_str(&mut self, contents: &str) -> Result<(), String> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = unquote(value.trim());
+            self.set(name, value)
+                .map_err(|err| format!("Config file line {}: {}", number + 1, err))?;
+        }
+        Ok(())
+    }
+
+    /// Sets one parameter by its redis.conf name, validating the value.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_lowercase().as_str() {
+            "bind" => self.bind = value.to_owned(),
+            "port" => self.port = parse_number(name, value)?,
+            "requirepass" => {
+                self.requirepass = (!value.is_empty()).then(|| value.to_owned());
+            }
+            "maxclients" => match parse_number(name, value)? {
+                0 => return Err("maxclients must be positive".to_owned()),
+                maxclients => self.maxclients = maxclients,
+            },
+            "timeout" => self.timeout = parse_number(name, value)?,
+            "tcp-keepalive" => self.tcp_keepalive = parse_number(name, value)?,
+            "tcp-nodelay" => self.tcp_nodelay = parse_yes_no(name, value)?,
+            "tcp-backlog" => self.tcp_backlog = parse_number(name, value)?,
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => {
+                self.maxmemory_policy = MaxMemoryPolicy::parse(value)
+                    .ok_or_else(|| format!("Invalid maxmemory-policy '{}'", value))?
+            }
+            "maxmemory-samples" => match parse_number(name, value)? {
+                0 => return Err("maxmemory-samples must be positive".to_owned()),
+                samples => self.maxmemory_samples = samples,
+            },
+            "databases" => match parse_number(name, value)? {
+                0 => return Err("Invalid number of databases".to_owned()),
+                databases => self.databases = databases,
+            },
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.to_owned(),
+            "appendonly" => self.appendonly = parse_yes_no(name, value)?,
+            "appendfilename" => self.appendfilename = value.to_owned(),
+            "appendfsync" => {
+                self.appendfsync = match value.to_lowercase().as_str() {
+                    "always" => AppendFsync::Always,
+                    "everysec" => AppendFsync::EverySec,
+                    "no" => AppendFsync::No,
+                    _ => return Err(format!("Invalid appendfsync '{}'", value)),
+                }
+            }
+            "latency-monitor-threshold" => {
+                self.latency_monitor_threshold = parse_number(name, value)?
+            }
+            "cluster-enabled" => self.cluster_enabled = parse_yes_no(name, value)?,
+            "client-output-buffer-limit" => self.client_output_buffer_limit.set(value)?,
+            // Redis refuses anything under a megabyte too
+            "proto-max-bulk-len" => match parse_memory(value)? {
+                length if length < 1024 * 1024 => {
+                    return Err("proto-max-bulk-len must be at least 1mb".to_owned())
+                }
+                length => self.proto_max_bulk_len = length,
+            },
+            "proto-max-multibulk-len" => match parse_number(name, value)? {
+                0 => return Err("proto-max-multibulk-len must be positive".to_owned()),
+                length => self.proto_max_multibulk_len = length,
+            },
+            "replica-read-only" => self.replica_read_only = parse_yes_no(name, value)?,
+            "replica-serve-stale-data" => {
+                self.replica_serve_stale_data = parse_yes_no(name, value)?
+            }
+            // Redis keeps at least 16kb
+            "repl-backlog-size" => match parse_memory(value)? {
+                size if size < 16 * 1024 => {
+                    return Err("repl-backlog-size must be at least 16kb".to_owned())
+                }
+                size => self.repl_backlog_size = size,
+            },
+            "repl-timeout" => match parse_number(name, value)? {
+                0 => return Err("repl-timeout must be positive".to_owned()),
+                seconds => self.repl_timeout = seconds,
+            },
+            "repl-ping-replica-period" => match parse_number(name, value)? {
+                0 => return Err("repl-ping-replica-period must be positive".to_owned()),
+                seconds => self.repl_ping_replica_period = seconds,
+            },
+            "loglevel" => {
+                self.loglevel =
+                    Level::parse(value).ok_or_else(|| format!("Invalid loglevel '{}'", value))?
+            }
+            "log-format" => {
+                self.log_format = LogFormat::parse(value)
+                    .ok_or_else(|| format!("Invalid log-format '{}'", value))?
+            }
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = KeyspaceEvents::parse(value)
+                    .ok_or_else(|| format!("Invalid notify-keyspace-events '{}'", value))?
+            }
+            _ => return Err(format!("Unknown config parameter '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// The current value of a parameter in its redis.conf form.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "bind" => self.bind.clone(),
+            "port" => self.port.to_string(),
+            "requirepass" => self.requirepass.clone().unwrap_or_default(),
+            "maxclients" => self.maxclients.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "tcp-nodelay" => yes_no(self.tcp_nodelay),
+            "tcp-backlog" => self.tcp_backlog.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.name().to_owned(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "databases" => self.databases.to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "appendonly" => yes_no(self.appendonly),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => match self.appendfsync {
+                AppendFsync::Always => "always",
+                AppendFsync::EverySec => "everysec",
+                AppendFsync::No => "no",
+            }
+            .to_owned(),
+            "latency-monitor-threshold" => self.latency_monitor_threshold.to_string(),
+            "cluster-enabled" => yes_no(self.cluster_enabled),
+            "client-output-buffer-limit" => self.client_output_buffer_limit.to_string(),
+            "proto-max-bulk-len" => self.proto_max_bulk_len.to_string(),
+            "proto-max-multibulk-len" => self.proto_max_multibulk_len.to_string(),
+            "replica-read-only" => yes_no(self.replica_read_only),
+            "replica-serve-stale-data" => yes_no(self.replica_serve_stale_data),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "repl-timeout" => self.repl_timeout.to_string(),
+            "repl-ping-replica-period" => self.repl_ping_replica_period.to_string(),
+            "loglevel" => self.loglevel.name().to_owned(),
+            "log-format" => self.log_format.name().to_owned(),
+            "notify-keyspace-events" => self.notify_keyspace_events.name(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Whether a parameter may be changed while the server runs.
+    pub fn is_mutable(name: &str) -> bool {
+        PARAMETERS.contains(&name) && !IMMUTABLE.contains(&name)
+    }
+
+    /// Where SAVE and BGSAVE write the snapshot and startup loads it from.
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    /// What frames clients may send.
+    pub fn protocol_limits(&self) -> ProtocolLimits {
+        ProtocolLimits {
+            max_bulk_len: self.proto_max_bulk_len,
+            max_multibulk_len: self.proto_max_multibulk_len,
+        }
+    }
+
+    /// The address the listener binds to.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, name))
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("'{}' must be yes or no", name)),
+    }
+}
+
+/// Parses a memory size such as `1048576`, `100mb` or `1gb`. As in redis.conf, `k`,
+/// `m` and `g` are powers of 1000 while `kb`, `mb` and `gb` are powers of 1024.
+pub fn parse_memory(value: &str) -> Result<u64, String> {
+    let lower = value.to_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid memory size '{}'", value)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid memory size '{}'", value))
+}
@@ -0,0 +1,172 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| part.to_string()).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_args(Vec::new()).unwrap();
+        assert_eq!(config, Config::new());
+        assert_eq!(config.address(), "127.0.0.1:6379");
+        assert_eq!(config.databases, 16);
+        assert_eq!(config.appendfsync, AppendFsync::EverySec);
+    }
+
+    #[test]
+    fn test_command_line_flags() {
+        let config = Config::from_args(args(&[
+            "--port",
+            "7000",
+            "--bind",
+            "0.0.0.0",
+            "--requirepass",
+            "secret",
+            "--maxmemory",
+            "100mb",
+            "--maxmemory-policy",
+            "allkeys-lru",
+            "--appendonly",
+            "yes",
+            "--maxclients",
+            "2",
+            "--timeout",
+            "30",
+            "--tcp-keepalive",
+            "60",
+            "--tcp-nodelay",
+            "no",
+            "--tcp-backlog",
+            "128",
+            "--proto-max-bulk-len",
+            "2mb",
+            "--proto-max-multibulk-len",
+            "100",
+            "--repl-timeout",
+            "5",
+            "--loglevel",
+            "debug",
+            "--log-format",
+            "json",
+            "--notify-keyspace-events",
+            "Elz",
+        ]))
+        .unwrap();
+        assert_eq!(config.address(), "0.0.0.0:7000");
+        assert_eq!(config.requirepass, Some("secret".to_owned()));
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.maxmemory_policy, MaxMemoryPolicy::AllKeysLru);
+        assert!(config.appendonly);
+        assert_eq!(config.maxclients, 2);
+        assert_eq!(config.timeout, 30);
+        assert_eq!(
+            (config.tcp_keepalive, config.tcp_nodelay, config.tcp_backlog),
+            (60, false, 128)
+        );
+        assert_eq!(config.repl_timeout, 5);
+        assert_eq!(config.loglevel, Level::Debug);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.get("notify-keyspace-events").unwrap(), "lzE");
+        assert_eq!(
+            config.protocol_limits(),
+            ProtocolLimits {
+                max_bulk_len: 2 * 1024 * 1024,
+                max_multibulk_len: 100,
+            }
+        );
+
+        assert!(Config::from_args(args(&["--port"])).is_err());
+        assert!(Config::from_args(args(&["--port", "not-a-port"])).is_err());
+        assert!(Config::from_args(args(&["--nonsense", "1"])).is_err());
+        assert!(Config::from_args(args(&["--maxmemory-policy", "sometimes"])).is_err());
+        assert!(Config::from_args(args(&["--maxmemory-samples", "0"])).is_err());
+        assert!(Config::from_args(args(&["--storage-engine", "single"])).is_err());
+        assert!(Config::from_args(args(&["--maxclients", "0"])).is_err());
+        assert!(Config::from_args(args(&["--timeout", "-1"])).is_err());
+        assert!(Config::from_args(args(&["--tcp-nodelay", "1"])).is_err());
+        assert!(Config::from_args(args(&["--proto-max-bulk-len", "1kb"])).is_err());
+        assert!(Config::from_args(args(&["--proto-max-multibulk-len", "0"])).is_err());
+        assert!(Config::from_args(args(&["--repl-timeout", "0"])).is_err());
+        assert!(Config::from_args(args(&["--repl-ping-replica-period", "0"])).is_err());
+        assert!(Config::from_args(args(&["--loglevel", "loud"])).is_err());
+        assert!(Config::from_args(args(&["--log-format", "xml"])).is_err());
+        assert!(Config::from_args(args(&["--notify-keyspace-events", "KEq"])).is_err());
+    }
+
+    #[test]
+    fn test_config_file_then_flags() {
+        let path = std::env::temp_dir().join(format!("redis-rust-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# Example\n\nport 7001\ndir \"/tmp/data dir\"\nappendfsync always\ndatabases 4\n",
+        )
+        .unwrap();
+
+        let config = Config::from_args(args(&[path.to_str().unwrap(), "--port", "7002"])).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Flags override the file
+        assert_eq!(config.port, 7002);
+        assert_eq!(config.dir, PathBuf::from("/tmp/data dir"));
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert_eq!(config.databases, 4);
+
+        assert!(Config::from_args(args(&["/nonexistent/redis.conf"])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_file_line_is_reported() {
+        let mut config = Config::new();
+        let err = config.load_str("port 7000\ndatabases 0\n").unwrap_err();
+        assert!(err.contains("line 2"));
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("1024"), Ok(1024));
+        assert_eq!(parse_memory("1k"), Ok(1000));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("2GB"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(parse_memory("12xb").is_err());
+        assert!(parse_memory("mb").is_err());
+    }
+
+    #[test]
+    fn test_client_output_buffer_limit() {
+        let mut config = Config::new();
+        config
+            .load_str(
+                "client-output-buffer-limit normal 0 0 0\n\
+                 client-output-buffer-limit pubsub 1mb 256kb 10\n",
+            )
+            .unwrap();
+        assert_eq!(
+            config.client_output_buffer_limit.pubsub,
+            BufferLimit {
+                hard: 1024 * 1024,
+                soft: 256 * 1024,
+                soft_seconds: 10,
+            }
+        );
+        config
+            .set("client-output-buffer-limit", "slave 0 0 0 normal 10 5 1")
+            .unwrap();
+        assert_eq!(
+            config.get("client-output-buffer-limit").unwrap(),
+            "normal 10 5 1 replica 0 0 0 pubsub 1048576 262144 10"
+        );
+
+        // A bad group leaves every class untouched
+        for value in [
+            "pubsub 1 2",
+            "normal 1 2 3 monitor 1 2 3",
+            "normal x 0 0",
+            "",
+        ] {
+            assert!(config.set("client-output-buffer-limit", value).is_err());
+        }
+        assert_eq!(config.client_output_buffer_limit.normal.hard, 10);
+    }
+}
@@ -0,0 +1,725 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+};
+use tokio::sync::RwLock;
+
+use super::{
+    append_value, del_value, exists_value, expire_value, get_value, getdel_value, getex_value,
+    getrange_value, getset_value, incr_by_value, incr_value, integer_arg, keys_value, mget_value,
+    persist_value, ping_value, publish_value, scan_value, set_value, setex_value, setnx_value,
+    setrange_value, strlen_value, ttl_value,
+};
+use crate::clients::Client;
+use crate::commands::acl::acl_value;
+use crate::commands::bitmap::{
+    bitcount_value, bitop_value, bitpos_value, getbit_value, setbit_value,
+};
+use crate::commands::client::client_value;
+use crate::commands::cluster::cluster_value;
+use crate::commands::config::config_value;
+use crate::commands::geo::{geoadd_value, geodist_value, geosearch_value};
+use crate::commands::hash::{
+    hdel_value, hexists_value, hget_value, hgetall_value, hlen_value, hmget_value,
+    hrandfield_value, hscan_value, hset_value,
+};
+use crate::commands::keyspace::{
+    dbsize_value, dump_value, flushall_value, flushdb_value, object_value, randomkey_value,
+    rename_value, restore_value, select_value, sort_value, swapdb_value, type_value,
+};
+use crate::commands::list::{
+    blmove_value, bpop_value, linsert_value, llen_value, lmove_value, lpos_value, lrange_value,
+    lrem_value, lset_value, ltrim_value, pop_value, push_value, rpoplpush_value, ListEnd,
+};
+use crate::commands::replication::{replconf_value, replicaof_value, wait_value};
+use crate::commands::server::{
+    bgrewriteaof_value, bgsave_value, command_value, debug_value, info_value, latency_value,
+    lolwut_value, memory_value, save_value, time_value,
+};
+use crate::commands::set::{
+    sadd_value, scard_value, set_operation_value, sismember_value, smembers_value, spop_value,
+    srandmember_value, srem_value, sscan_value, SetOperation,
+};
+use crate::commands::stream::{
+    xack_value, xadd_value, xgroup_value, xlen_value, xpending_value, xrange_value, xread_value,
+    xreadgroup_value,
+};
+use crate::commands::zset::{
+    bzpop_value, zadd_value, zcard_value, zcount_value, zincrby_value, zpop_value, zrange_value,
+    zrangebylex_value, zrangebyscore_value, zrank_value, zrem_value, zscan_value, zscore_value,
+};
+use crate::config::Config;
+use crate::error::Result;
+use crate::parser::{CommandSpec, COMMANDS};
+use crate::parser::{UserCommand, Value};
+use crate::pubsub::PubSub;
+use crate::replication::Replication;
+use crate::server::ServerState;
+use crate::storage::sharded::ShardedDb;
+
+pub mod tests_dispatch;
+
+/// What a handler may know of the connection a command came from.
+#[derive(Debug, Clone, Copy)]
+pub struct Caller<'a> {
+    pub client: &'a Client,
+    // The ACL user the connection is logged in as
+    pub user: &'a str,
+}
+
+/// A command being run by its handler, with the arguments after its name, the server,
+/// the database the connection has selected, which SELECT changes, and the connection
+/// itself. It is the one thing every handler gets, so what a command needs to know about
+/// where it runs is added here rather than to each handler's signature.
+pub struct CommandContext<'a> {
+    pub args: &'a [Value],
+    pub state: &'a ServerState,
+    pub selected: &'a mut usize,
+    // None when the server runs the command on its own, as when it replays the AOF or
+    // applies its master's stream
+    pub caller: Option<Caller<'a>>,
+}
+
+impl<'a> CommandContext<'a> {
+    pub fn new(
+        args: &'a [Value],
+        state: &'a ServerState,
+        selected: &'a mut usize,
+        caller: Option<Caller<'a>>,
+    ) -> Self {
+        Self {
+            args,
+            state,
+            selected,
+            caller,
+        }
+    }
+
+    /// The selected database.
+    pub fn storage(&self) -> &Arc<ShardedDb> {
+        &self.state.databases[*self.selected]
+    }
+
+    pub fn config(&self) -> &RwLock<Config> {
+        &self.state.config
+    }
+
+    pub fn pubsub(&self) -> &PubSub {
+        &self.state.pubsub
+    }
+
+    pub fn replication(&self) -> &Replication {
+        &self.state.replication
+    }
+}
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<Value>> + Send + 'a>>;
+
+/// Runs one command once its arity has been checked.
+pub type Handler = for<'a> fn(CommandContext<'a>) -> HandlerFuture<'a>;
+
+/// A command as the server runs it: how it is called, and the handler running it.
+/// Commands the connection deals with itself, such as MULTI, SUBSCRIBE or AUTH, have
+/// no handler.
+#[derive(Debug)]
+pub struct Command {
+    pub spec: &'static CommandSpec,
+    pub handler: Option<Handler>,
+}
+
+/// Every command and alias by name, as listed in `COMMANDS`. A new command only needs
+/// its spec there and its handler in `HANDLERS`.
+pub fn registry() -> &'static HashMap<&'static str, Command> {
+    static REGISTRY: OnceLock<HashMap<&'static str, Command>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        COMMANDS
+            .iter()
+            .map(|spec| {
+                let handler = HANDLERS
+                    .iter()
+                    .find(|(command, _)| *command == spec.command)
+                    .map(|(_, handler)| *handler);
+                (spec.name, Command { spec, handler })
+            })
+            .collect()
+    })
+}
+
+// The reply to a command that acts on the connection when the server runs it on its own
+fn connection_only(command: &str) -> Value {
+    Value::SimpleError(format!("ERR {} needs a client connection", command))
+}
+
+// A handler running `body` in an async block, with the context bound to the pattern
+macro_rules! handler {
+    (|$ctx:pat_param| $body:expr) => {
+        |$ctx| Box::pin(async move { $body })
+    };
+}
+
+const HANDLERS: &[(UserCommand, Handler)] = &[
+    (UserCommand::Ping, handler!(|ctx| Ok(ping_value(ctx.args)))),
+    (UserCommand::Echo, handler!(|ctx| Ok(ctx.args[0].clone()))),
+    (
+        UserCommand::Get,
+        handler!(|ctx| get_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Mget,
+        handler!(|ctx| mget_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Expire,
+        handler!(|ctx| expire_value(ctx.args, ctx.storage(), UserCommand::Expire).await),
+    ),
+    (
+        UserCommand::ExpireAt,
+        handler!(|ctx| expire_value(ctx.args, ctx.storage(), UserCommand::ExpireAt).await),
+    ),
+    (
+        UserCommand::PExpireAt,
+        handler!(|ctx| expire_value(ctx.args, ctx.storage(), UserCommand::PExpireAt).await),
+    ),
+    (
+        UserCommand::Persist,
+        handler!(|ctx| persist_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Ttl,
+        handler!(|ctx| ttl_value(ctx.args, ctx.storage(), 1000).await),
+    ),
+    (
+        UserCommand::Pttl,
+        handler!(|ctx| ttl_value(ctx.args, ctx.storage(), 1).await),
+    ),
+    (
+        UserCommand::Incr,
+        handler!(|ctx| incr_value(ctx.args, ctx.storage(), 1).await),
+    ),
+    (
+        UserCommand::Decr,
+        handler!(|ctx| incr_value(ctx.args, ctx.storage(), -1).await),
+    ),
+    (
+        UserCommand::IncrBy,
+        handler!(|ctx| incr_by_value(ctx.args, ctx.storage(), 1).await),
+    ),
+    (
+        UserCommand::DecrBy,
+        handler!(|ctx| incr_by_value(ctx.args, ctx.storage(), -1).await),
+    ),
+    (
+        UserCommand::Append,
+        handler!(|ctx| append_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Strlen,
+        handler!(|ctx| strlen_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GetRange,
+        handler!(|ctx| getrange_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SetRange,
+        handler!(|ctx| setrange_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GetDel,
+        handler!(|ctx| getdel_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GetEx,
+        handler!(|ctx| getex_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GetSet,
+        handler!(|ctx| getset_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SetNx,
+        handler!(|ctx| setnx_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SetEx,
+        handler!(|ctx| setex_value(ctx.args, ctx.storage(), UserCommand::SetEx, 1000).await),
+    ),
+    (
+        UserCommand::PSetEx,
+        handler!(|ctx| setex_value(ctx.args, ctx.storage(), UserCommand::PSetEx, 1).await),
+    ),
+    (
+        UserCommand::SetBit,
+        handler!(|ctx| setbit_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GetBit,
+        handler!(|ctx| getbit_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::BitCount,
+        handler!(|ctx| bitcount_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::BitOp,
+        handler!(|ctx| bitop_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::BitPos,
+        handler!(|ctx| bitpos_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Keys,
+        handler!(|ctx| keys_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Scan,
+        handler!(|ctx| scan_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HSet,
+        handler!(|ctx| hset_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HGet,
+        handler!(|ctx| hget_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HMGet,
+        handler!(|ctx| hmget_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HDel,
+        handler!(|ctx| hdel_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HGetAll,
+        handler!(|ctx| hgetall_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HScan,
+        handler!(|ctx| hscan_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HExists,
+        handler!(|ctx| hexists_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HLen,
+        handler!(|ctx| hlen_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::HRandField,
+        handler!(|ctx| hrandfield_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SAdd,
+        handler!(|ctx| sadd_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SRem,
+        handler!(|ctx| srem_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SMembers,
+        handler!(|ctx| smembers_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SIsMember,
+        handler!(|ctx| sismember_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SCard,
+        handler!(|ctx| scard_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SPop,
+        handler!(|ctx| spop_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SRandMember,
+        handler!(|ctx| srandmember_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SScan,
+        handler!(|ctx| sscan_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::SInter,
+        handler!(|ctx| set_operation_value(ctx.args, ctx.storage(), SetOperation::Inter).await),
+    ),
+    (
+        UserCommand::SUnion,
+        handler!(|ctx| set_operation_value(ctx.args, ctx.storage(), SetOperation::Union).await),
+    ),
+    (
+        UserCommand::SDiff,
+        handler!(|ctx| set_operation_value(ctx.args, ctx.storage(), SetOperation::Diff).await),
+    ),
+    (
+        UserCommand::ZAdd,
+        handler!(|ctx| zadd_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZRem,
+        handler!(|ctx| zrem_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZScore,
+        handler!(|ctx| zscore_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZCard,
+        handler!(|ctx| zcard_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZScan,
+        handler!(|ctx| zscan_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZRank,
+        handler!(|ctx| zrank_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::ZRevRank,
+        handler!(|ctx| zrank_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::ZRange,
+        handler!(|ctx| zrange_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::ZRevRange,
+        handler!(|ctx| zrange_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::ZIncrBy,
+        handler!(|ctx| zincrby_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZCount,
+        handler!(|ctx| zcount_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZRangeByScore,
+        handler!(|ctx| zrangebyscore_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZRangeByLex,
+        handler!(|ctx| zrangebylex_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::ZPopMin,
+        handler!(|ctx| zpop_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::ZPopMax,
+        handler!(|ctx| zpop_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::GeoAdd,
+        handler!(|ctx| geoadd_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GeoDist,
+        handler!(|ctx| geodist_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::GeoSearch,
+        handler!(|ctx| geosearch_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LPush,
+        handler!(|ctx| push_value(ctx.args, ctx.storage(), ListEnd::Left).await),
+    ),
+    (
+        UserCommand::RPush,
+        handler!(|ctx| push_value(ctx.args, ctx.storage(), ListEnd::Right).await),
+    ),
+    (
+        UserCommand::LPop,
+        handler!(|ctx| pop_value(ctx.args, ctx.storage(), ListEnd::Left).await),
+    ),
+    (
+        UserCommand::RPop,
+        handler!(|ctx| pop_value(ctx.args, ctx.storage(), ListEnd::Right).await),
+    ),
+    (
+        UserCommand::LLen,
+        handler!(|ctx| llen_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LRange,
+        handler!(|ctx| lrange_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LPos,
+        handler!(|ctx| lpos_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LInsert,
+        handler!(|ctx| linsert_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LSet,
+        handler!(|ctx| lset_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LRem,
+        handler!(|ctx| lrem_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LTrim,
+        handler!(|ctx| ltrim_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::RPopLPush,
+        handler!(|ctx| rpoplpush_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::LMove,
+        handler!(|ctx| lmove_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::BLMove,
+        handler!(|ctx| blmove_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::BLPop,
+        handler!(|ctx| bpop_value(ctx.args, ctx.storage(), ListEnd::Left).await),
+    ),
+    (
+        UserCommand::BRPop,
+        handler!(|ctx| bpop_value(ctx.args, ctx.storage(), ListEnd::Right).await),
+    ),
+    (
+        UserCommand::BZPopMin,
+        handler!(|ctx| bzpop_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::BZPopMax,
+        handler!(|ctx| bzpop_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::XAdd,
+        handler!(|ctx| xadd_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XLen,
+        handler!(|ctx| xlen_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XRange,
+        handler!(|ctx| xrange_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::XRevRange,
+        handler!(|ctx| xrange_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::XRead,
+        handler!(|ctx| xread_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XGroup,
+        handler!(|ctx| xgroup_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XReadGroup,
+        handler!(|ctx| xreadgroup_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XAck,
+        handler!(|ctx| xack_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::XPending,
+        handler!(|ctx| xpending_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Select,
+        handler!(|ctx| {
+            if ctx.state.cluster.is_some()
+                && matches!(ctx.args, [index] if integer_arg(index).is_some_and(|index| index != 0))
+            {
+                return Ok(Value::SimpleError(
+                    "ERR SELECT is not allowed in cluster mode".to_owned(),
+                ));
+            }
+            select_value(ctx.args, ctx.state.databases.len(), ctx.selected)
+        }),
+    ),
+    (
+        UserCommand::SwapDb,
+        handler!(|ctx| swapdb_value(ctx.args, &ctx.state.databases).await),
+    ),
+    (
+        UserCommand::FlushDb,
+        handler!(|ctx| flushdb_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::DbSize,
+        handler!(|ctx| dbsize_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::RandomKey,
+        handler!(|ctx| randomkey_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Type,
+        handler!(|ctx| type_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Sort,
+        handler!(|ctx| sort_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Object,
+        handler!(|ctx| {
+            let policy = ctx.config().read().await.maxmemory_policy;
+            object_value(ctx.args, ctx.storage(), policy).await
+        }),
+    ),
+    (
+        UserCommand::Memory,
+        handler!(|ctx| memory_value(ctx.args, ctx.state, *ctx.selected).await),
+    ),
+    (
+        UserCommand::FlushAll,
+        handler!(|ctx| flushall_value(ctx.args, &ctx.state.databases).await),
+    ),
+    (
+        UserCommand::Save,
+        handler!(|ctx| save_value(ctx.args, ctx.state).await),
+    ),
+    (
+        UserCommand::BgSave,
+        handler!(|ctx| bgsave_value(ctx.args, ctx.state).await),
+    ),
+    (
+        UserCommand::BgRewriteAof,
+        handler!(|ctx| bgrewriteaof_value(ctx.args, ctx.state).await),
+    ),
+    (
+        UserCommand::Publish,
+        handler!(|ctx| publish_value(ctx.args, ctx.pubsub())),
+    ),
+    (
+        UserCommand::Config,
+        handler!(|ctx| config_value(ctx.args, ctx.state).await),
+    ),
+    (
+        UserCommand::ReplicaOf,
+        handler!(|ctx| replicaof_value(ctx.args, ctx.state).await),
+    ),
+    (
+        UserCommand::ReplConf,
+        handler!(|ctx| replconf_value(ctx.args)),
+    ),
+    (
+        UserCommand::Wait,
+        handler!(|ctx| wait_value(ctx.args, ctx.state, false).await),
+    ),
+    (
+        UserCommand::Debug,
+        handler!(|ctx| debug_value(ctx.args, ctx.state, *ctx.selected, false).await),
+    ),
+    (
+        UserCommand::Latency,
+        handler!(|ctx| latency_value(ctx.args, ctx.state)),
+    ),
+    (
+        UserCommand::Command,
+        handler!(|ctx| command_value(ctx.args)),
+    ),
+    (
+        UserCommand::Info,
+        handler!(|ctx| info_value(ctx.args, ctx.state).await),
+    ),
+    (UserCommand::Lolwut, handler!(|ctx| lolwut_value(ctx.args))),
+    (
+        UserCommand::Time,
+        handler!(|ctx| time_value(ctx.args, ctx.state.clock.as_ref())),
+    ),
+    (
+        UserCommand::Cluster,
+        handler!(|ctx| cluster_value(ctx.args, ctx.state).await),
+    ),
+    // The flag itself lives on the connection, which already set it
+    (
+        UserCommand::Asking,
+        handler!(|ctx| match ctx.state.cluster {
+            Some(_) => Ok(Value::SimpleString("OK".to_owned())),
+            None => Ok(Value::SimpleError(
+                "ERR This instance has cluster support disabled".to_owned(),
+            )),
+        }),
+    ),
+    // Queued inside MULTI; EXEC has already released the watches by then
+    (
+        UserCommand::Unwatch,
+        handler!(|_| Ok(Value::SimpleString("OK".to_owned()))),
+    ),
+    (
+        UserCommand::Set,
+        handler!(|ctx| set_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Del,
+        handler!(|ctx| del_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::Exists,
+        handler!(|ctx| exists_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Unlink,
+        handler!(|ctx| del_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::Rename,
+        handler!(|ctx| rename_value(ctx.args, ctx.storage(), false).await),
+    ),
+    (
+        UserCommand::RenameNx,
+        handler!(|ctx| rename_value(ctx.args, ctx.storage(), true).await),
+    ),
+    (
+        UserCommand::Dump,
+        handler!(|ctx| dump_value(ctx.args, ctx.storage()).await),
+    ),
+    (
+        UserCommand::Restore,
+        handler!(|ctx| restore_value(ctx.args, ctx.storage()).await),
+    ),
+    // CLIENT and ACL act on the connection, which the server has none of on its own
+    (
+        UserCommand::Client,
+        handler!(|ctx| match ctx.caller {
+            Some(caller) => client_value(ctx.args, ctx.state, caller.client),
+            None => Ok(connection_only("CLIENT")),
+        }),
+    ),
+    (
+        UserCommand::Acl,
+        handler!(|ctx| match ctx.caller {
+            Some(caller) => acl_value(ctx.args, ctx.state, caller.user),
+            None => Ok(connection_only("ACL")),
+        }),
+    ),
+    // Talking to another server while EXEC holds everyone else off is not supported
+    (
+        UserCommand::Migrate,
+        handler!(|_| Ok(Value::SimpleError(
+            "ERR MIGRATE is not allowed inside MULTI".to_owned()
+        ))),
+    ),
+    (
+        UserCommand::Failover,
+        handler!(|_| Ok(Value::SimpleError(
+            "ERR FAILOVER is not allowed inside MULTI".to_owned()
+        ))),
+    ),
+];
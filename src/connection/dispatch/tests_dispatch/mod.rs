@@ -0,0 +1,115 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::connection::execute_command;
+    use crate::error::wrong_arity;
+    use crate::testing::args;
+    use anyhow::Result;
+
+    #[test]
+    fn test_registry_covers_the_table() {
+        let registry = registry();
+        assert_eq!(registry.len(), COMMANDS.len());
+        for spec in COMMANDS {
+            assert_eq!(registry[spec.name].spec, spec);
+        }
+        // Each handler is registered once, for a command in the table
+        for (index, (command, _)) in HANDLERS.iter().enumerate() {
+            assert!(command.spec().is_some(), "{:?} has no spec", command);
+            assert!(
+                HANDLERS[..index].iter().all(|(other, _)| other != command),
+                "{:?} has two handlers",
+                command
+            );
+        }
+        assert!(registry["GET"].handler.is_some());
+        assert!(registry["MULTI"].handler.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_arity_is_checked_before_the_handler() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let mut selected = 0;
+        for (command, request, name) in [
+            (UserCommand::Get, vec![], "get"),
+            (UserCommand::Get, vec!["a", "b"], "get"),
+            (UserCommand::Echo, vec![], "echo"),
+            (UserCommand::HSet, vec!["hash", "field"], "hset"),
+        ] {
+            assert_eq!(
+                execute_command(command, &args(&request), &state, &mut selected, None).await?,
+                Value::SimpleError(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    name
+                ))
+            );
+        }
+        // Arity errors found by a handler get the name of the command too
+        assert_eq!(
+            execute_command(
+                UserCommand::HSet,
+                &args(&["hash", "a", "1", "b"]),
+                &state,
+                &mut selected,
+                None
+            )
+            .await?,
+            wrong_arity("hset")
+        );
+        assert_eq!(
+            execute_command(
+                UserCommand::Echo,
+                &args(&["hi"]),
+                &state,
+                &mut selected,
+                None
+            )
+            .await?,
+            Value::BulkString("hi".into())
+        );
+        assert_eq!(
+            execute_command(UserCommand::Multi, &[], &state, &mut selected, None).await?,
+            Value::SimpleError(
+                "ERR unknown command 'multi', with args beginning with: ".to_owned()
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handlers_see_the_caller() -> Result<()> {
+        let state = ServerState::new(Config::new());
+        let client = state.clients.register("127.0.0.1:6000".parse()?);
+        let caller = Caller {
+            client: &client,
+            user: "default",
+        };
+        let mut selected = 0;
+        for (command, request, own, alone) in [
+            (
+                UserCommand::Client,
+                "ID",
+                Value::Integer(client.id as i64),
+                "ERR CLIENT needs a client connection",
+            ),
+            (
+                UserCommand::Acl,
+                "WHOAMI",
+                Value::BulkString("default".into()),
+                "ERR ACL needs a client connection",
+            ),
+        ] {
+            let request = args(&[request]);
+            assert_eq!(
+                execute_command(command, &request, &state, &mut selected, Some(caller)).await?,
+                own
+            );
+            assert_eq!(
+                execute_command(command, &request, &state, &mut selected, None).await?,
+                Value::SimpleError(alone.to_owned())
+            );
+        }
+        Ok(())
+    }
+}
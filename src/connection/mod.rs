@@ -1,117 +1,1048 @@
-use anyhow::Result;
+use bytes::{Bytes, BytesMut};
 
-use tokio::time::{self, sleep};
+use crate::acl::Acl;
+use crate::cluster::{command_keys, key_hash_slot};
+use crate::commands::client::client_value;
+use crate::commands::keyspace::migrate_value;
+use crate::commands::list::{blocking_lmove_value, blocking_pop_value, ListEnd};
+use crate::commands::replication::{failover_value, listening_port, wait_value};
+use crate::commands::server::{debug_value, shutdown_value};
+use crate::commands::stream::{blocking_xread_value, blocking_xreadgroup_value};
+use crate::commands::zset::blocking_zpop_value;
+use crate::error::{unknown_command, wrong_arity, CommandError, Error, RespError, Result};
+use crate::glob::glob_match;
+use crate::parser::{Protocol, RespHandler, UserCommand, Value};
+use crate::plugin::plugin_value;
+use crate::propagation::Propagation;
+use crate::pubsub::{PubSub, Subscriber};
+use crate::replication::serve_replica;
+use crate::server::ServerState;
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::string::StringValue;
+use crate::storage::{eviction, DataType, Entry};
+use crate::{debug, logging, verbose, warning};
+use dispatch::{registry, Caller, CommandContext};
+use state::ConnectionState;
 
-use crate::parser::{RespHandler, UserCommand, Value};
-
-use std::{collections::HashMap, sync::Arc};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::RwLock;
+use tokio::sync::broadcast;
 
+pub mod dispatch;
+pub mod state;
 pub mod tests_connection;
 
-pub async fn handle_connection(
-    socket: TcpStream,
-    db_instance: Arc<RwLock<HashMap<String, String>>>,
+pub async fn handle_connection(socket: TcpStream, state: Arc<ServerState>) -> Result<()> {
+    verbose!("Accepted new connection: {:?}", socket);
+    let addr = socket.peer_addr()?;
+    serve_client(socket, addr, state).await
+}
+
+/// Serves one client over any stream, `addr` being the peer it is reported as.
+pub async fn serve_client<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    addr: SocketAddr,
+    state: Arc<ServerState>,
 ) -> Result<()> {
-    println!("Accepted new connection: {:?}", socket);
     let mut client_handler = RespHandler::new(socket);
-    // In a loop, read data from the socket and write the data back.
+    if state.clients.count() >= state.config.read().await.maxclients {
+        let reply = Value::SimpleError("ERR max clients reached".to_owned());
+        client_handler.write_value(&reply).await?;
+        return Ok(());
+    }
+    let connection = ConnectionState::new(&state, state.clients.register(addr));
+    let client = connection.client.id;
+    let served = serve_commands(client_handler, connection, addr, state);
+    logging::client_span(client, served).await
+}
+
+// In a loop, read every complete frame from the socket and write all replies back at once.
+// Messages for subscribed channels are forwarded as soon as they are published.
+async fn serve_commands<S: AsyncRead + AsyncWrite + Unpin>(
+    mut client_handler: RespHandler<S>,
+    mut connection: ConnectionState,
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+) -> Result<()> {
     loop {
-        let value = client_handler.read_value().await?;
-        // dbg!(&value);
+        // Published messages, counted against the pubsub client-output-buffer-limit. RESET
+        // swaps the subscriber, so this is taken anew every time.
+        let messages_output = connection.subscriber.output();
+        let config = state.config.read().await;
+        // Subscribers and monitors only listen, so they are never idle
+        let timeout = match connection.subscriber.is_subscribed() || connection.monitor.is_some() {
+            true => 0,
+            false => config.timeout,
+        };
+        client_handler.limits = config.protocol_limits();
+        drop(config);
+        let values = tokio::select! {
+            values = read_values_within(&mut client_handler, timeout) => match values {
+                // As in Redis, a client that breaks the protocol is told why and let go
+                Some(Err(err)) if err.is_protocol() => {
+                    verbose!("Client {} sent an invalid request: {}", connection.client.id, err);
+                    let reply = Value::SimpleError(format!("ERR {}", err));
+                    client_handler.write_value(&reply).await?;
+                    break;
+                }
+                Some(values) => values?,
+                None => {
+                    verbose!("Client {} timed out.", connection.client.id);
+                    break;
+                }
+            },
+            Some(message) = connection.subscriber.receiver.recv() => {
+                let mut messages = vec![message];
+                while let Ok(message) = connection.subscriber.receiver.try_recv() {
+                    messages.push(message);
+                }
+                client_handler.queue_values(&messages);
+                let written = client_handler.pending_output();
+                // A subscriber too slow to read what is published is let go of rather
+                // than buffered for without bound
+                let flushed = tokio::select! {
+                    flushed = client_handler.flush() => flushed,
+                    _ = messages_output.overflowed() => {
+                        warning!("Client {} closed for overcoming of output buffer limits.", connection.client.id);
+                        break;
+                    }
+                };
+                if let Err(err) = flushed {
+                    verbose!("Error writing to socket: {}", err);
+                    break;
+                }
+                messages_output.drain(written);
+                continue;
+            }
+            _ = messages_output.overflowed() => {
+                warning!("Client {} closed for overcoming of output buffer limits.", connection.client.id);
+                break;
+            }
+            Some(line) = next_monitor_line(&mut connection.monitor) => {
+                if let Err(err) = client_handler.write_value(&line).await {
+                    verbose!("Error writing to socket: {}", err);
+                    break;
+                }
+                continue;
+            }
+            _ = connection.client.killed() => {
+                verbose!("Client {} was killed.", connection.client.id);
+                break;
+            }
+            // Commands already read have been answered, so the connection can close
+            _ = state.shutdown.requested() => break,
+        };
+
+        let Some(values) = values else {
+            verbose!("Client requested to quit.");
+            break;
+        };
 
-        let response = if let Some(value) = value {
-            let (command, args) = extract_command(value)?;
+        let mut responses = Vec::with_capacity(values.len());
+        let mut quit = false;
 
+        for value in values {
+            if is_empty_command(&value) {
+                continue;
+            }
+            let (command, args) = match extract_command(value) {
+                Ok(extracted) => extracted,
+                Err(err) => {
+                    if connection.transaction.is_active() {
+                        connection.transaction.fail();
+                    }
+                    responses.push(error_reply(err));
+                    continue;
+                }
+            };
+            connection
+                .client
+                .record_command(command.name(), connection.selected);
+            // Passwords are kept out of the monitor
+            if !matches!(command, UserCommand::Invalid | UserCommand::Auth) {
+                state
+                    .monitor
+                    .feed(connection.selected, addr, command.name(), &args);
+            }
+            let asking = std::mem::replace(&mut connection.asking, command == UserCommand::Asking);
+            // Arity comes from the command table, so a queued command fails right away
+            if let Some(spec) = command.spec() {
+                if !spec.accepts(args.len() + 1) {
+                    if connection.transaction.is_active() {
+                        connection.transaction.fail();
+                    }
+                    responses.push(wrong_arity(spec.name));
+                    continue;
+                }
+            }
+
+            if connection.authenticated {
+                if let Some(redirect) = cluster_redirect(command, &args, &state, asking)
+                    .await
+                    .unwrap_or_else(|err| Some(error_reply(err)))
+                {
+                    if connection.transaction.is_active() {
+                        connection.transaction.fail();
+                    }
+                    responses.push(redirect);
+                    continue;
+                }
+                // AUTH, HELLO, QUIT and RESET are how a connection changes user, so
+                // every user may run them
+                let always_allowed = matches!(
+                    command,
+                    UserCommand::Auth | UserCommand::Hello | UserCommand::Quit | UserCommand::Reset
+                );
+                if !always_allowed {
+                    if let Some(denied) = state.acl.check(&connection.user, command, &args) {
+                        if connection.transaction.is_active() {
+                            connection.transaction.fail();
+                        }
+                        responses.push(denied);
+                        continue;
+                    }
+                }
+                if let Some(refused) = replica_refusal(command, &args, &state).await {
+                    if connection.transaction.is_active() {
+                        connection.transaction.fail();
+                    }
+                    responses.push(refused);
+                    continue;
+                }
+            }
+
+            // Remembered for when the replica sends PSYNC
+            if command == UserCommand::ReplConf {
+                if let Some(port) = listening_port(&args) {
+                    connection.listening_port = Some(port);
+                }
+            }
+
+            let _command = logging::command_span(command.name());
+            let started = Instant::now();
+            let mut answered = responses.len();
             match command {
-                UserCommand::Ping => Value::SimpleString("PONG".to_owned()),
-                UserCommand::Echo => args.first().unwrap().clone(),
-                UserCommand::Get => get_value(&args, &db_instance).await?,
-                UserCommand::Mget => mget_value(&args, &db_instance).await?,
-                UserCommand::Expire => expire_value(&args, &db_instance).await?,
-                UserCommand::Set => set_value(&args, &db_instance).await?,
-                UserCommand::Del => del_value(&args, &db_instance).await?,
                 UserCommand::Quit => {
-                    println!("Client requested to quit.");
+                    verbose!("Client requested to quit.");
+                    quit = true;
                     break;
                 }
-                _ => Value::SimpleError("Invalid command".to_owned()),
+                // RESET goes back to RESP2 as well, so like HELLO the replies before it
+                // go out first
+                UserCommand::Reset => {
+                    client_handler.write_values(&responses).await?;
+                    responses.clear();
+                    answered = 0;
+                    connection.reset(&state).await;
+                    client_handler.protocol = Protocol::Resp2;
+                    responses.push(Value::SimpleString("RESET".to_owned()));
+                }
+                UserCommand::Auth => {
+                    let (reply, user) = auth_value(&args, &state.acl)
+                        .unwrap_or_else(|err| (error_reply(err), None));
+                    if let Some(user) = user {
+                        connection.user = user;
+                        connection.authenticated = true;
+                    }
+                    responses.push(reply);
+                }
+                // The reply to HELLO already uses the protocol it picks, so earlier
+                // replies go out first in the old one
+                UserCommand::Hello => {
+                    client_handler.write_values(&responses).await?;
+                    responses.clear();
+                    answered = 0;
+                    let reply =
+                        hello_value(&args, &state, &mut connection, &mut client_handler.protocol)
+                            .await
+                            .unwrap_or_else(error_reply);
+                    responses.push(reply);
+                }
+                _ if !connection.authenticated => {
+                    responses.push(Value::SimpleError(
+                        "NOAUTH Authentication required.".to_owned(),
+                    ));
+                }
+                // The connection belongs to a replica from now on, which accepts
+                // connections at the port it gave, for FAILOVER
+                UserCommand::Psync => {
+                    client_handler.write_values(&responses).await?;
+                    let address = connection
+                        .listening_port
+                        .map(|port| (addr.ip().to_string(), port));
+                    return serve_replica(client_handler, &state, &args, address).await;
+                }
+                UserCommand::Multi
+                | UserCommand::Exec
+                | UserCommand::Discard
+                | UserCommand::Watch => {
+                    responses.push(
+                        transaction_command(command, &args, &mut connection, &state)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                // MONITOR and SHUTDOWN act on the connection or the server itself, so they run
+                // straight away
+                UserCommand::Monitor => {
+                    connection
+                        .monitor
+                        .get_or_insert_with(|| state.monitor.subscribe());
+                    responses.push(Value::SimpleString("OK".to_owned()));
+                }
+                UserCommand::Shutdown => match shutdown_value(&args, &state).await {
+                    Ok(Some(reply)) => responses.push(reply),
+                    Err(err) => responses.push(error_reply(err)),
+                    Ok(None) => {
+                        quit = true;
+                        break;
+                    }
+                },
+                // Inside MULTI everything else is queued for EXEC
+                UserCommand::Invalid
+                    if connection.transaction.is_active()
+                        && state.plugins.find(command, &args).is_none() =>
+                {
+                    connection.transaction.fail();
+                    responses.push(unknown_command(&args));
+                }
+                _ if connection.transaction.is_active() => {
+                    connection.transaction.queue(command, args);
+                    responses.push(Value::SimpleString("QUEUED".to_owned()));
+                }
+                UserCommand::Unwatch => {
+                    connection.transaction.unwatch().await;
+                    responses.push(Value::SimpleString("OK".to_owned()));
+                }
+                UserCommand::Subscribe
+                | UserCommand::Unsubscribe
+                | UserCommand::PSubscribe
+                | UserCommand::PUnsubscribe => {
+                    match subscription_command(command, &args, &mut connection.subscriber) {
+                        Ok(replies) => responses.extend(replies),
+                        Err(err) => responses.push(error_reply(err)),
+                    }
+                }
+                // A subscribed RESP2 client may only manage subscriptions and PING. RESP3
+                // keeps messages apart as push frames, so there anything goes.
+                UserCommand::Ping
+                    if connection.subscriber.is_subscribed()
+                        && client_handler.protocol == Protocol::Resp2 =>
+                {
+                    let reply = match args.as_slice() {
+                        [] => Value::BulkString(Bytes::new()),
+                        [message] => message.clone(),
+                        _ => wrong_arity("PING"),
+                    };
+                    responses.push(match reply {
+                        Value::SimpleError(_) => reply,
+                        message => Value::Array(vec![Value::BulkString("pong".into()), message]),
+                    });
+                }
+                _ if connection.subscriber.is_subscribed()
+                    && client_handler.protocol == Protocol::Resp2 =>
+                {
+                    responses.push(Value::SimpleError(
+                        "ERR only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are allowed in this context"
+                            .to_owned(),
+                    ));
+                }
+                // Blocking commands must not hold up EXEC while they wait
+                UserCommand::Wait => {
+                    responses.push(
+                        wait_value(&args, &state, true)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                // DEBUG SLEEP takes the execution lock for itself to stall everyone
+                UserCommand::Debug => {
+                    responses.push(
+                        debug_value(&args, &state, connection.selected, true)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::BLPop => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_pop_value(&args, &state, selected, Some(caller), ListEnd::Left)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::BRPop => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_pop_value(&args, &state, selected, Some(caller), ListEnd::Right)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::BLMove => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_lmove_value(&args, &state, selected, Some(caller))
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::BZPopMin => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_zpop_value(&args, &state, selected, Some(caller), false)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::BZPopMax => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_zpop_value(&args, &state, selected, Some(caller), true)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::XRead => {
+                    responses.push(
+                        blocking_xread_value(&args, &state, connection.selected)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                UserCommand::XReadGroup => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        blocking_xreadgroup_value(&args, &state, selected, Some(caller))
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                // FAILOVER holds everyone off by itself while the replica catches up
+                UserCommand::Failover => {
+                    responses.push(
+                        failover_value(&args, &state)
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                // MIGRATE waits on another server, so it must not hold up EXEC either
+                UserCommand::Migrate => {
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        migrate_value(&args, &state, selected, Some(caller))
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
+                _ => {
+                    let _shared = state.exec_lock.read().await;
+                    let (selected, caller) = connection.caller();
+                    responses.push(
+                        run_command(command, &args, &state, selected, Some(caller))
+                            .await
+                            .unwrap_or_else(error_reply),
+                    );
+                }
             }
-        } else {
-            println!("Client requested to quit.");
+            for reply in &mut responses[answered..] {
+                *reply = name_arity_error(std::mem::replace(reply, Value::Null), command.name());
+            }
+            let failed = responses[answered..]
+                .iter()
+                .any(|reply| matches!(reply, Value::SimpleError(_)));
+            state
+                .stats
+                .record_call(command.name(), started.elapsed(), failed);
+            debug!("Ran in {:?}", started.elapsed());
+            // Blocking commands spend their time waiting, not running
+            if let Some(spec) = command.spec() {
+                if !spec.flags.contains(&"blocking") {
+                    let event = match spec.flags.contains(&"fast") {
+                        true => "fast-command",
+                        false => "command",
+                    };
+                    state.latency.record(event, started.elapsed());
+                }
+            }
+        }
+
+        // The replies to the whole batch go out with a single flush
+        client_handler.queue_values(&responses);
+        let written = client_handler.pending_output();
+        let limit = state.config.read().await.client_output_buffer_limit.normal;
+        if !connection.output.push(written, &limit) {
+            warning!(
+                "Client {} closed for overcoming of output buffer limits.",
+                connection.client.id
+            );
             break;
-        };
+        }
+        if let Err(err) = client_handler.flush().await {
+            verbose!("Error writing to socket: {}", err);
+            break;
+        }
+        connection.output.drain(written);
 
-        if let Err(err) = client_handler.write_value(response).await {
-            eprintln!("Error writing to socket: {}", err);
+        if quit {
             break;
         }
     }
     Ok(())
 }
 
-async fn get_value(
-    args: &Vec<Value>,
-    db_instance: &Arc<RwLock<HashMap<String, String>>>,
+// Counts a keyspace hit or miss for every key a read-only command is about to look up
+async fn count_lookups(
+    command: UserCommand,
+    args: &[Value],
+    state: &ServerState,
+    selected: usize,
+) -> Result<()> {
+    let keys = command_keys(command, args)
+        .into_iter()
+        .map(|key| unpack_bulk_string(key.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let instance = state.databases[selected].read_keys(&keys).await;
+    for key in &keys {
+        instance.lookup(key);
+    }
+    Ok(())
+}
+
+// In cluster mode, the error sending a command elsewhere when this node does not serve
+// the slot of its keys. Cluster nodes only have database 0.
+async fn cluster_redirect(
+    command: UserCommand,
+    args: &[Value],
+    state: &ServerState,
+    asking: bool,
+) -> Result<Option<Value>> {
+    let Some(cluster) = state.cluster.as_ref() else {
+        return Ok(None);
+    };
+    let keys = command_keys(command, args)
+        .into_iter()
+        .map(|key| unpack_bulk_string(key.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let Some(first) = keys.first() else {
+        return Ok(None);
+    };
+    let slot = key_hash_slot(first.as_bytes());
+    if keys.iter().any(|key| key_hash_slot(key.as_bytes()) != slot) {
+        return Ok(Some(Value::SimpleError(
+            "CROSSSLOT Keys in request don't hash to the same slot".to_owned(),
+        )));
+    }
+    let instance = state.databases[0].read_keys(&keys).await;
+    let present = keys.iter().all(|key| instance.get(key).is_some());
+    Ok(cluster.redirect(slot, asking, present))
+}
+
+// On a replica, the error refusing a client's write when replica-read-only is on, or
+// any command but a few while the master is down and replica-serve-stale-data is off.
+// Writes from the master arrive over the replication link, not through here.
+async fn replica_refusal(
+    command: UserCommand,
+    args: &[Value],
+    state: &ServerState,
+) -> Option<Value> {
+    state.replication.master()?;
+    let config = state.config.read().await;
+    if config.replica_read_only && (command.is_write() || state.plugins.is_write(command, args)) {
+        return Some(Value::SimpleError(
+            "READONLY You can't write against a read only replica.".to_owned(),
+        ));
+    }
+    if !config.replica_serve_stale_data && state.replication.is_stale() && !command.is_stale() {
+        return Some(Value::SimpleError(
+            "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'."
+                .to_owned(),
+        ));
+    }
+    None
+}
+
+// The next line for a MONITOR connection; other connections never get one
+async fn next_monitor_line(monitor: &mut Option<broadcast::Receiver<Value>>) -> Option<Value> {
+    let receiver = monitor.as_mut()?;
+    loop {
+        match receiver.recv().await {
+            Ok(line) => return Some(line),
+            // Lines missed by a client that fell behind are simply skipped
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+// AUTH [username] password, checked against the ACL users. Returns the reply and the
+// user the connection is now logged in as, if any.
+fn auth_value(args: &[Value], acl: &Acl) -> Result<(Value, Option<String>)> {
+    let (username, password) = match args {
+        [password] => ("default".to_owned(), unpack_bulk_string(password.clone())?),
+        [username, password] => (
+            unpack_bulk_string(username.clone())?,
+            unpack_bulk_string(password.clone())?,
+        ),
+        _ => return Ok((CommandError::WrongArity.into(), None)),
+    };
+
+    if args.len() == 1 && acl.is_nopass("default") {
+        return Ok((
+            Value::SimpleError(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                    .to_owned(),
+            ),
+            None,
+        ));
+    }
+
+    if acl.authenticate(&username, &password) {
+        Ok((Value::SimpleString("OK".to_owned()), Some(username)))
+    } else {
+        Ok((
+            Value::SimpleError(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_owned(),
+            ),
+            None,
+        ))
+    }
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]] switches the
+// connection to `protover`, authenticating and naming it on the way, and replies with
+// a map describing the server. Nothing changes when any part of it fails.
+async fn hello_value(
+    args: &[Value],
+    state: &ServerState,
+    connection: &mut ConnectionState,
+    protocol: &mut Protocol,
 ) -> Result<Value> {
-    // Ensure there is at least one argument
-    if args.is_empty() {
-        return Err(anyhow::anyhow!("Missing argument for GET command"));
+    let args = args
+        .iter()
+        .map(|arg| unpack_bulk_string(arg.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut requested = *protocol;
+    let mut options = args.as_slice();
+    if let Some((version, rest)) = options.split_first() {
+        let Ok(version) = version.parse::<i64>() else {
+            return Ok(Value::SimpleError(
+                "ERR Protocol version is not an integer or out of range".to_owned(),
+            ));
+        };
+        let Some(version) = Protocol::from_version(version) else {
+            return Ok(Value::SimpleError(
+                "NOPROTO unsupported protocol version".to_owned(),
+            ));
+        };
+        requested = version;
+        options = rest;
     }
 
-    // Extract the first argument
-    let item = match args.first() {
-        Some(arg) => arg.clone(),
-        None => return Err(anyhow::anyhow!("Missing argument for GET command")),
+    let (mut credentials, mut name) = (None, None);
+    loop {
+        match options {
+            [option, username, password, rest @ ..] if option.eq_ignore_ascii_case("AUTH") => {
+                credentials =
+                    Some([username, password].map(|arg| Value::BulkString(arg.clone().into())));
+                options = rest;
+            }
+            [option, client_name, rest @ ..] if option.eq_ignore_ascii_case("SETNAME") => {
+                name = Some(client_name);
+                options = rest;
+            }
+            [option, ..] => {
+                return Ok(Value::SimpleError(format!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    option
+                )))
+            }
+            [] => break,
+        }
+    }
+
+    if let Some(credentials) = credentials {
+        let (reply, user) = auth_value(&credentials, &state.acl)?;
+        let Some(user) = user else {
+            return Ok(reply);
+        };
+        connection.user = user;
+        connection.authenticated = true;
+    }
+    if !connection.authenticated {
+        return Ok(Value::SimpleError(
+            "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+                .to_owned(),
+        ));
+    }
+    if let Some(name) = name {
+        let args = ["SETNAME", name].map(|arg| Value::BulkString(arg.to_owned().into()));
+        let reply = client_value(&args, state, &connection.client)?;
+        if matches!(reply, Value::SimpleError(_)) {
+            return Ok(reply);
+        }
+    }
+
+    *protocol = requested;
+    let role = if state.replication.master().is_some() {
+        "replica"
+    } else {
+        "master"
     };
+    let mode = match state.cluster {
+        Some(_) => "cluster",
+        None => "standalone",
+    };
+    let field = |name: &str| Value::BulkString(name.to_owned().into());
+    Ok(Value::Map(vec![
+        (field("server"), field("redis")),
+        (field("version"), field(env!("CARGO_PKG_VERSION"))),
+        (field("proto"), Value::Integer(requested.version())),
+        (field("id"), Value::Integer(connection.client.id as i64)),
+        (field("mode"), field(mode)),
+        (field("role"), field(role)),
+        (field("modules"), Value::Array(vec![])),
+    ]))
+}
 
-    // Acquire a read lock on the database instance
-    let instance = db_instance.read().await;
+// MULTI, EXEC, DISCARD and WATCH
+async fn transaction_command(
+    command: UserCommand,
+    args: &[Value],
+    connection: &mut ConnectionState,
+    state: &ServerState,
+) -> Result<Value> {
+    let transaction = &mut connection.transaction;
+    let response = match command {
+        UserCommand::Multi if !transaction.begin() => {
+            Value::SimpleError("ERR MULTI calls can not be nested".to_owned())
+        }
+        UserCommand::Multi => Value::SimpleString("OK".to_owned()),
+        UserCommand::Watch if transaction.is_active() => {
+            Value::SimpleError("ERR WATCH inside MULTI is not allowed".to_owned())
+        }
+        UserCommand::Watch if args.is_empty() => CommandError::WrongArity.into(),
+        UserCommand::Watch => {
+            let keys = args
+                .iter()
+                .map(|key| unpack_bulk_string(key.clone()))
+                .collect::<Result<Vec<_>>>()?;
+            transaction
+                .watch(&state.databases[connection.selected], keys)
+                .await;
+            Value::SimpleString("OK".to_owned())
+        }
+        UserCommand::Exec if !transaction.is_active() => {
+            Value::SimpleError("ERR EXEC without MULTI".to_owned())
+        }
+        UserCommand::Discard if !transaction.is_active() => {
+            Value::SimpleError("ERR DISCARD without MULTI".to_owned())
+        }
+        UserCommand::Discard => {
+            transaction.finish();
+            transaction.unwatch().await;
+            Value::SimpleString("OK".to_owned())
+        }
+        _ => {
+            let Some(queued) = transaction.finish() else {
+                transaction.unwatch().await;
+                return Ok(Value::SimpleError(
+                    "EXECABORT Transaction discarded because of previous errors.".to_owned(),
+                ));
+            };
 
-    // Match the item to ensure it's a BulkString and get the corresponding value from the database
-    let value = match item {
-        Value::BulkString(key) => instance.get(&key).cloned(),
-        _ => return Err(anyhow::anyhow!("Invalid key type")),
+            // Keep every other command out until the whole transaction has run
+            let _exclusive = state.exec_lock.write().await;
+            let dirty = transaction.is_dirty().await;
+            transaction.unwatch().await;
+            // A watched key changed since WATCH, so nothing is executed
+            if dirty {
+                return Ok(Value::NullArray);
+            }
+
+            let mut replies = Vec::with_capacity(queued.len());
+            for (command, args) in queued {
+                let (selected, caller) = connection.caller();
+                // A failing command does not abort the rest of the transaction
+                replies.push(
+                    run_command(command, &args, state, selected, Some(caller))
+                        .await
+                        .unwrap_or_else(error_reply),
+                );
+            }
+            Value::Array(replies)
+        }
     };
+    Ok(response)
+}
 
-    // Return the found value or an error if the key has no associated value
-    match value {
-        Some(string) => Ok(Value::BulkString(string)),
-        None => Ok(Value::SimpleError("key has no associated value".to_owned())),
+// Reads the next batch of frames, or None once the client stayed silent for `timeout`
+// seconds. A timeout of 0 waits forever
+async fn read_values_within<S: AsyncRead + AsyncWrite + Unpin>(
+    client_handler: &mut RespHandler<S>,
+    timeout: u64,
+) -> Option<Result<Option<Vec<Value>>, RespError>> {
+    if timeout == 0 {
+        return Some(client_handler.read_values().await);
     }
+    tokio::time::timeout(Duration::from_secs(timeout), client_handler.read_values())
+        .await
+        .ok()
 }
 
-async fn mget_value(
-    args: &Vec<Value>,
-    db_instance: &Arc<RwLock<HashMap<String, String>>>,
+/// Runs a command and, when it is a write that succeeded, propagates it: to the AOF,
+/// to replicas and as keyspace notifications.
+pub async fn run_command(
+    command: UserCommand,
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
 ) -> Result<Value> {
-    // Ensure there is at least one argument
-    if args.is_empty() {
+    if !command.is_write() && !state.plugins.is_write(command, args) {
+        return execute_command(command, args, state, selected, caller).await;
+    }
+
+    // Room is made before the write runs, each evicted key logged as a DEL
+    let fits = evict_keys(state).await?;
+    if !fits && command.is_denyoom() {
         return Ok(Value::SimpleError(
-            "Missing argument for MGET command".to_owned(),
+            "OOM command not allowed when used memory > 'maxmemory'.".to_owned(),
         ));
     }
 
-    // Acquire a read lock on the database instance
-    let instance = db_instance.read().await;
+    let keys = command_keys(command, args);
+    let mut propagation = Propagation::lock(state, &keys).await;
+    let db = *selected;
+    let response = execute_command(command, args, state, selected, caller).await?;
+    if !matches!(response, Value::SimpleError(_)) {
+        propagation.command(db, command, args, &response).await?;
+    }
+    drop(propagation);
+    if evicting(state).await {
+        measure_keys(state, db, &keys).await;
+    }
+    Ok(response)
+}
 
-    let mut result = Vec::new();
+// With maxmemory set, evicts keys by the configured policy until the dataset fits,
+// propagating each as a DEL. Returns whether it fits now. Every key is locked only
+// once the published estimate is over the limit. Replicas leave this to their master
+// and apply the DELs it sends.
+async fn evict_keys(state: &ServerState) -> Result<bool> {
+    let (maxmemory, policy, samples) = {
+        let config = state.config.read().await;
+        (
+            config.maxmemory,
+            config.maxmemory_policy,
+            config.maxmemory_samples,
+        )
+    };
+    if maxmemory == 0 || state.replication.master().is_some() {
+        return Ok(true);
+    }
+    let used: usize = state.databases.iter().map(|db| db.used_memory()).sum();
+    if used as u64 <= maxmemory {
+        return Ok(true);
+    }
+
+    let mut propagation = Propagation::lock(state, &[]).await;
+    let mut databases = Vec::with_capacity(state.databases.len());
+    for db in &state.databases {
+        databases.push(db.write().await);
+    }
+    let (evicted, fits) = eviction::evict(&mut databases, maxmemory, policy, samples);
+    drop(databases);
+    for (db, key) in evicted {
+        propagation.evicted(db, key).await?;
+    }
+    Ok(fits)
+}
+
+// Whether writes may evict, so the memory estimate must keep up with them
+async fn evicting(state: &ServerState) -> bool {
+    state.config.read().await.maxmemory > 0 && state.replication.master().is_none()
+}
+
+// Measures again what a write may have changed in place: the shards of its keys, or
+// every shard of every database for a write without keys
+async fn measure_keys(state: &ServerState, db: usize, keys: &[&Value]) {
+    if keys.is_empty() {
+        for db in &state.databases {
+            db.measure().await;
+        }
+        return;
+    }
+    let keys: Vec<_> = keys
+        .iter()
+        .filter_map(|key| match key {
+            Value::BulkString(key) => Some(String::from_utf8_lossy(key)),
+            _ => None,
+        })
+        .collect();
+    state.databases[db].measure_keys(&keys).await;
+}
 
-    // Match the item to ensure it's a BulkString and get the corresponding value from the database
+// SUBSCRIBE, UNSUBSCRIBE, PSUBSCRIBE and PUNSUBSCRIBE reply with one frame per
+// channel or pattern, each carrying the connection's total subscription count
+fn subscription_command(
+    command: UserCommand,
+    args: &[Value],
+    subscriber: &mut Subscriber,
+) -> Result<Vec<Value>> {
+    let names = args
+        .iter()
+        .map(|name| match name {
+            Value::BulkString(name) => Ok(name.clone()),
+            _ => Err(Error::InvalidArgument("Invalid channel type")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (kind, names) = match command {
+        UserCommand::Subscribe | UserCommand::PSubscribe if names.is_empty() => {
+            return Ok(vec![CommandError::WrongArity.into()])
+        }
+        UserCommand::Subscribe => ("subscribe", names),
+        UserCommand::PSubscribe => ("psubscribe", names),
+        // Without arguments every channel (or pattern) is dropped
+        UserCommand::Unsubscribe if names.is_empty() => ("unsubscribe", subscriber.channels()),
+        UserCommand::Unsubscribe => ("unsubscribe", names),
+        UserCommand::PUnsubscribe if names.is_empty() => ("punsubscribe", subscriber.patterns()),
+        _ => ("punsubscribe", names),
+    };
+
+    if names.is_empty() {
+        return Ok(vec![Value::Push(vec![
+            Value::BulkString(kind.into()),
+            Value::Null,
+            Value::Integer(subscriber.count() as i64),
+        ])]);
+    }
+
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let count = match command {
+                UserCommand::Subscribe => subscriber.subscribe(name.clone()),
+                UserCommand::PSubscribe => subscriber.psubscribe(name.clone()),
+                UserCommand::Unsubscribe => subscriber.unsubscribe(&name),
+                _ => subscriber.punsubscribe(&name),
+            };
+            Value::Push(vec![
+                Value::BulkString(kind.into()),
+                Value::BulkString(name),
+                Value::Integer(count as i64),
+            ])
+        })
+        .collect())
+}
+
+/// Runs a command through its handler in the registry, once its arity is checked against
+/// the command table. `caller` is the connection it came from, None when the server
+/// runs it on its own.
+pub async fn execute_command(
+    command: UserCommand,
+    args: &[Value],
+    state: &ServerState,
+    selected: &mut usize,
+    caller: Option<Caller<'_>>,
+) -> Result<Value> {
+    let Some(command) = registry().get(command.name()) else {
+        // Anything the table does not know may still be a plugin
+        return Ok(match state.plugins.find(command, args) {
+            Some(plugin) => plugin_value(plugin.as_ref(), args, &state.databases[*selected]).await,
+            None => unknown_command(args),
+        });
+    };
+    // Commands only a connection runs, such as MULTI, are unknown to the dispatcher
+    let Some(handler) = command.handler else {
+        let name = Value::BulkString(command.spec.name.to_lowercase().into());
+        let request: Vec<Value> = std::iter::once(name).chain(args.iter().cloned()).collect();
+        return Ok(unknown_command(&request));
+    };
+    if !command.spec.accepts(args.len() + 1) {
+        return Ok(wrong_arity(command.spec.name));
+    }
+    if command.spec.flags.contains(&"readonly") {
+        count_lookups(command.spec.command, args, state, *selected).await?;
+    }
+    let reply = handler(CommandContext::new(args, state, selected, caller)).await?;
+    Ok(name_arity_error(reply, command.spec.name))
+}
+
+// Handlers that find too few or too many arguments themselves reply with the bare arity
+// error, which is given the name of the command here
+fn name_arity_error(reply: Value, command: &str) -> Value {
+    match reply == CommandError::WrongArity.into() {
+        true => wrong_arity(command),
+        false => reply,
+    }
+}
+
+// A command that fails is answered with an error reply, so a bad request never closes
+// the connection; only failing to read or write the socket does
+fn error_reply(err: Error) -> Value {
+    Value::SimpleError(format!("ERR {}", err))
+}
+
+async fn get_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    // Extract the first argument
+    let Some(item) = args.first().cloned() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+
+    // Match the item to ensure it's a BulkString
+    let key = match item {
+        Value::BulkString(_) => unpack_bulk_string(item)?,
+        _ => return Err(Error::InvalidArgument("Invalid key type")),
+    };
+
+    // Acquire a read lock on the shard holding the key and get its value
+    let instance = db_instance.read_key(&key).await;
+    let value = instance.get(&key);
+
+    // Return the found value or a null bulk string if the key has no associated value
+    match value {
+        Some(DataType::String(string)) => Ok(Value::BulkString(string.to_bytes())),
+        Some(_) => Ok(wrong_type()),
+        None => Ok(Value::Null),
+    }
+}
+
+async fn mget_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    // Ensure there is at least one argument
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    // Match every item to ensure it's a BulkString
+    let mut keys = Vec::with_capacity(args.len());
     for value in args.iter() {
-        let key = match value {
-            Value::BulkString(key) => key.clone(),
+        match value {
+            Value::BulkString(_) => keys.push(unpack_bulk_string(value.clone())?),
             _ => {
                 return Ok(Value::SimpleError(
                     "One or more keys are invalid".to_owned(),
                 ))
             }
-        };
+        }
+    }
+
+    // Acquire a read lock on the shards holding the keys
+    let instance = db_instance.read_keys(&keys).await;
+
+    let mut result = Vec::new();
 
-        let value = match instance.get(&key) {
-            Some(string) => Value::BulkString(string.clone()),
-            None => Value::SimpleError(format!("The key {} has no associated value", key)),
+    // Get the corresponding value of each key from the database
+    for key in &keys {
+        // Keys holding other data types read as missing, like Redis
+        let value = match instance.get(key) {
+            Some(DataType::String(string)) => Value::BulkString(string.to_bytes()),
+            _ => Value::Null,
         };
 
         result.push(value);
@@ -120,124 +1051,863 @@ async fn mget_value(
     Ok(Value::Array(result))
 }
 
-async fn set_value(
-    args: &Vec<Value>,
-    db_instance: &Arc<RwLock<HashMap<String, String>>>,
-) -> Result<Value> {
+async fn getdel_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    // Reading and removing under one write lock, so no other client sees the value after
+    let mut instance = db_instance.write_key(&key).await;
+    let value = match instance.get(&key) {
+        Some(DataType::String(string)) => string.to_bytes(),
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(Value::Null),
+    };
+    instance.remove(&key);
+    Ok(Value::BulkString(value))
+}
+
+// GETEX key [EX s | PX ms | EXAT ts | PXAT ts | PERSIST]
+async fn getex_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let Some(key) = args.first() else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    // The new absolute expiry: None leaves the TTL alone and Some(None) removes it
+    let expiry = match &args[1..] {
+        [] => None,
+        [option] if unpack_bulk_string(option.clone())?.eq_ignore_ascii_case("PERSIST") => {
+            Some(None)
+        }
+        [option, amount] => {
+            let expiry: fn(u64) -> SetExpiry =
+                match unpack_bulk_string(option.clone())?.to_uppercase().as_str() {
+                    "EX" => SetExpiry::Seconds,
+                    "PX" => SetExpiry::Milliseconds,
+                    "EXAT" => SetExpiry::UnixSeconds,
+                    "PXAT" => SetExpiry::UnixMilliseconds,
+                    _ => return Ok(CommandError::Syntax.into()),
+                };
+            match integer_arg(amount) {
+                Some(amount) if amount > 0 => {
+                    Some(expiry(amount as u64).expires_at(None, db_instance.now_millis()))
+                }
+                Some(_) => {
+                    return Ok(Value::SimpleError(
+                        "ERR invalid expire time in 'getex' command".to_owned(),
+                    ))
+                }
+                None => return Ok(CommandError::NotInteger.into()),
+            }
+        }
+        _ => return Ok(CommandError::Syntax.into()),
+    };
+
+    // The TTL changes under the same write lock the value is read with
+    let mut instance = db_instance.write_key(&key).await;
+    let value = match instance.get(&key) {
+        Some(DataType::String(string)) => string.to_bytes(),
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(Value::Null),
+    };
+    if let Some(expires_at) = expiry {
+        instance.set_expiry(&key, expires_at);
+    }
+    Ok(Value::BulkString(value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetExpiry {
+    Seconds(u64),
+    Milliseconds(u64),
+    UnixSeconds(u64),
+    UnixMilliseconds(u64),
+    KeepTtl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetCondition {
+    IfNotExists,
+    IfExists,
+}
+
+/// Options accepted after `SET key value`.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetOptions {
+    pub expiry: Option<SetExpiry>,
+    pub condition: Option<SetCondition>,
+    pub get: bool,
+}
+
+impl SetExpiry {
+    // Absolute expiry in unix milliseconds, `now` being the current time; KEEPTTL
+    // carries over the previous one
+    fn expires_at(self, previous: Option<u64>, now: u64) -> Option<u64> {
+        match self {
+            SetExpiry::Seconds(s) => Some(now.saturating_add(s.saturating_mul(1000))),
+            SetExpiry::Milliseconds(ms) => Some(now.saturating_add(ms)),
+            SetExpiry::UnixSeconds(s) => Some(s.saturating_mul(1000)),
+            SetExpiry::UnixMilliseconds(ms) => Some(ms),
+            SetExpiry::KeepTtl => previous,
+        }
+    }
+}
+
+/// Parses `[NX | XX] [GET] [EX s | PX ms | EXAT ts | PXAT ts | KEEPTTL]`.
+/// The error is the message to reply with.
+pub fn parse_set_options(args: &[Value]) -> std::result::Result<SetOptions, String> {
+    let mut options = SetOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let option = unpack_bulk_string(arg.clone())
+            .map_err(|_| "ERR syntax error".to_owned())?
+            .to_uppercase();
+
+        match option.as_str() {
+            "NX" | "XX" if options.condition.is_none() => {
+                options.condition = Some(if option == "NX" {
+                    SetCondition::IfNotExists
+                } else {
+                    SetCondition::IfExists
+                });
+            }
+            "GET" => options.get = true,
+            "KEEPTTL" if options.expiry.is_none() => options.expiry = Some(SetExpiry::KeepTtl),
+            "EX" | "PX" | "EXAT" | "PXAT" if options.expiry.is_none() => {
+                let amount = args
+                    .next()
+                    .ok_or_else(|| "ERR syntax error".to_owned())
+                    .and_then(|value| {
+                        unpack_bulk_string(value.clone()).map_err(|_| "ERR syntax error".to_owned())
+                    })?
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|amount| *amount > 0)
+                    .ok_or_else(|| "ERR invalid expire time in 'set' command".to_owned())?;
+
+                options.expiry = Some(match option.as_str() {
+                    "EX" => SetExpiry::Seconds(amount),
+                    "PX" => SetExpiry::Milliseconds(amount),
+                    "EXAT" => SetExpiry::UnixSeconds(amount),
+                    _ => SetExpiry::UnixMilliseconds(amount),
+                });
+            }
+            _ => return Err("ERR syntax error".to_owned()),
+        }
+    }
+
+    Ok(options)
+}
+
+async fn set_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
     // Ensure there are enough arguments for setting a value
     if args.len() < 2 {
-        return Err(anyhow::anyhow!("Not enough arguments for SET command"));
+        return Ok(CommandError::WrongArity.into());
     }
 
     // Extract the key and value from arguments
     let key = match args.first() {
-        Some(Value::BulkString(key)) => key.clone(),
-        _ => return Err(anyhow::anyhow!("Invalid key type")),
+        Some(value @ Value::BulkString(_)) => unpack_bulk_string(value.clone())?,
+        _ => return Err(Error::InvalidArgument("Invalid key type")),
     };
 
     let value = match args.get(1) {
         Some(Value::BulkString(value)) => value.clone(),
-        _ => return Err(anyhow::anyhow!("Invalid value type")),
+        _ => return Err(Error::InvalidArgument("Invalid value type")),
     };
 
-    let result;
+    let options = match parse_set_options(&args[2..]) {
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
 
-    // Acquire a write lock on the database instance
+    // Acquire a write lock on the database instance; the condition check, the write
+    // and reading the old value for GET all happen under it
+    let mut instance = db_instance.write_key(&key).await;
+    let previous = instance.get_entry(&key).cloned();
+
+    // SET overwrites any type, but GET can only report a previous string
+    if options.get
+        && !matches!(
+            &previous,
+            None | Some(Entry {
+                value: DataType::String(_),
+                ..
+            })
+        )
     {
-        let mut instance = db_instance.write().await;
-        result = instance.insert(key, value);
-    } // The write lock is dropped here
+        return Ok(wrong_type());
+    }
+
+    let should_set = match options.condition {
+        Some(SetCondition::IfNotExists) => previous.is_none(),
+        Some(SetCondition::IfExists) => previous.is_some(),
+        None => true,
+    };
+
+    if should_set {
+        let previous_expiry = previous.as_ref().and_then(|entry| entry.expires_at);
+        let expires_at = options
+            .expiry
+            .and_then(|expiry| expiry.expires_at(previous_expiry, db_instance.now_millis()));
+        instance.insert_entry(
+            key,
+            Entry {
+                value: DataType::String(value.into()),
+                expires_at,
+            },
+        );
+    }
+    drop(instance);
+
+    if options.get {
+        return Ok(match previous {
+            Some(Entry {
+                value: DataType::String(value),
+                ..
+            }) => Value::BulkString(value.to_bytes()),
+            _ => Value::Null,
+        });
+    }
 
-    match result {
-        Some(_) => Ok(Value::BulkString(
-            "Scucessfully updated value in database".to_owned(),
-        )),
-        None => Ok(Value::BulkString(
-            "Scucessfully inserted value in database".to_owned(),
-        )),
+    match should_set {
+        true => Ok(Value::SimpleString("OK".to_owned())),
+        false => Ok(Value::Null),
     }
 }
 
-async fn del_value(
-    args: &Vec<Value>,
-    db_instance: &Arc<RwLock<HashMap<String, String>>>,
+// GETSET key value, the older spelling of SET key value GET: the value is replaced, its
+// TTL dropped and the previous string returned under the same write lock
+async fn getset_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, value] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let args = [
+        key.clone(),
+        value.clone(),
+        Value::BulkString(Bytes::from_static(b"GET")),
+    ];
+    set_value(&args, db_instance).await
+}
+
+async fn setnx_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key, Value::BulkString(value)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    // 1 when the key was set, 0 when it already existed
+    let mut instance = db_instance.write_key(&key).await;
+    if instance.get(&key).is_some() {
+        return Ok(Value::Integer(0));
+    }
+    instance.insert_entry(key, Entry::new(DataType::String(value.clone().into())));
+    Ok(Value::Integer(1))
+}
+
+// SETEX key seconds value (unit = 1000) and PSETEX key milliseconds value (unit = 1)
+async fn setex_value(
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    command: UserCommand,
+    unit: u64,
 ) -> Result<Value> {
-    // Ensure there are enough arguments for setting a value
-    if args.len() < 1 {
-        return Err(anyhow::anyhow!("Not enough arguments for DEL command"));
+    let [key, amount, Value::BulkString(value)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+    let expires_at = match integer_arg(amount) {
+        Some(amount) if amount > 0 => db_instance
+            .now_millis()
+            .saturating_add((amount as u64).saturating_mul(unit)),
+        Some(_) => {
+            return Ok(Value::SimpleError(format!(
+                "ERR invalid expire time in '{}' command",
+                command.name().to_lowercase()
+            )))
+        }
+        None => return Ok(CommandError::NotInteger.into()),
+    };
+
+    db_instance.write_key(&key).await.insert_entry(
+        key,
+        Entry {
+            value: DataType::String(value.clone().into()),
+            expires_at: Some(expires_at),
+        },
+    );
+    Ok(Value::SimpleString("OK".to_owned()))
+}
+
+// The key arguments of a variadic command, such as DEL, EXISTS and UNLINK
+fn key_args(args: &[Value]) -> Result<Vec<String>> {
+    args.iter()
+        .map(|key| match key {
+            Value::BulkString(_) => unpack_bulk_string(key.clone()),
+            _ => Err(Error::InvalidArgument("Invalid key type")),
+        })
+        .collect()
+}
+
+/// DEL and UNLINK key [key ...], replying with how many keys were removed. UNLINK
+/// (`lazy`) takes the values out under the lock and drops them on a blocking task,
+/// so freeing a large value does not stall other clients.
+async fn del_value(args: &[Value], db_instance: &Arc<ShardedDb>, lazy: bool) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
     }
+    let keys = key_args(args)?;
 
-    // Extract the key and value from arguments
-    let key = match args.first() {
-        Some(Value::BulkString(key)) => key.clone(),
-        _ => return Err(anyhow::anyhow!("Invalid key type")),
+    let removed: Vec<DataType> = {
+        let mut instance = db_instance.write_keys(&keys).await;
+        keys.iter().filter_map(|key| instance.remove(key)).collect()
     };
 
-    let value;
+    // Number of keys that were removed
+    let count = removed.len() as i64;
+    if lazy {
+        tokio::task::spawn_blocking(move || drop(removed));
+    }
+    Ok(Value::Integer(count))
+}
 
-    {
-        let mut instace = db_instance.write().await;
-        value = instace.remove(&key);
+/// EXISTS key [key ...]. A key named more than once is counted each time.
+async fn exists_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(CommandError::WrongArity.into());
     }
+    let keys = key_args(args)?;
 
-    match value {
-        Some(_) => Ok(Value::BulkString(
-            "Removed the key from database".to_owned(),
-        )),
-        None => Ok(Value::SimpleError(
-            "Value doesnt exist in database".to_owned(),
-        )),
+    let instance = db_instance.read_keys(&keys).await;
+    let count = keys
+        .iter()
+        .filter(|key| instance.get(key).is_some())
+        .count();
+    Ok(Value::Integer(count as i64))
+}
+
+/// The NX, XX, GT and LT options of EXPIRE, EXPIREAT and PEXPIREAT. A key without a
+/// TTL counts as never expiring when comparing.
+#[derive(Debug, Default, PartialEq)]
+struct ExpireOptions {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireOptions {
+    // The error is the message to reply with
+    fn parse(args: &[Value]) -> std::result::Result<Self, String> {
+        let mut options = Self::default();
+        for arg in args {
+            let option = unpack_bulk_string(arg.clone())
+                .map_err(|_| "ERR syntax error".to_owned())?
+                .to_uppercase();
+            match option.as_str() {
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                _ => return Err(format!("ERR Unsupported option {}", option)),
+            }
+        }
+        if options.nx && (options.xx || options.gt || options.lt) {
+            return Err(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".to_owned(),
+            );
+        }
+        if options.gt && options.lt {
+            return Err("ERR GT and LT options at the same time are not compatible".to_owned());
+        }
+        Ok(options)
+    }
+
+    fn allows(&self, current: Option<u64>, expires_at: u64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => {
+                !self.nx && (!self.gt || expires_at > current) && (!self.lt || expires_at < current)
+            }
+        }
     }
 }
 
+// EXPIRE key seconds, EXPIREAT key unix-time-seconds and PEXPIREAT key
+// unix-time-milliseconds, each followed by the options. A time in the past expires the
+// key at once. Replies 1 when the TTL was set and 0 when the key does not exist or an
+// option prevented it
 async fn expire_value(
-    args: &Vec<Value>,
-    db_instance: &Arc<RwLock<HashMap<String, String>>>,
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+    command: UserCommand,
 ) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Some(amount) = integer_arg(&args[1]) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+    let options = match ExpireOptions::parse(&args[2..]) {
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    let (unit, absolute) = match command {
+        UserCommand::Expire => (1000, false),
+        UserCommand::ExpireAt => (1000, true),
+        _ => (1, true),
+    };
+    let expires_at = amount.checked_mul(unit).and_then(|millis| {
+        if absolute {
+            Some(millis)
+        } else {
+            millis.checked_add(db_instance.now_millis() as i64)
+        }
+    });
+    let Some(expires_at) = expires_at else {
+        return Ok(Value::SimpleError(format!(
+            "ERR invalid expire time in '{}' command",
+            command.name().to_lowercase()
+        )));
+    };
+    let expires_at = u64::try_from(expires_at).unwrap_or(0);
+
+    // The check against the current TTL and the update share one write lock
+    let mut instance = db_instance.write_key(&key).await;
+    let Some(current) = instance.get_entry(&key).map(|entry| entry.expires_at) else {
+        return Ok(Value::Integer(0));
+    };
+    if !options.allows(current, expires_at) {
+        return Ok(Value::Integer(0));
+    }
+    instance.set_expiry(&key, Some(expires_at));
+    Ok(Value::Integer(1))
+}
+
+// PERSIST key replies 1 when it removed a TTL and 0 when the key is missing or has none
+async fn persist_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let [key] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+    let key = unpack_bulk_string(key.clone())?;
+
+    let mut instance = db_instance.write_key(&key).await;
+    match instance.get_entry(&key) {
+        Some(Entry {
+            expires_at: Some(_),
+            ..
+        }) => {
+            instance.set_expiry(&key, None);
+            Ok(Value::Integer(1))
+        }
+        _ => Ok(Value::Integer(0)),
+    }
+}
+
+// Shared by TTL (unit = 1000) and PTTL (unit = 1)
+async fn ttl_value(args: &[Value], db_instance: &Arc<ShardedDb>, unit: u64) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+
+    // -2 when the key does not exist, -1 when it has no associated expire
+    match db_instance.read_key(&key).await.ttl_millis(&key) {
+        None => Ok(Value::Integer(-2)),
+        Some(None) => Ok(Value::Integer(-1)),
+        Some(Some(millis)) => Ok(Value::Integer(millis.div_ceil(unit) as i64)),
+    }
+}
+
+// INCR (delta = 1) and DECR (delta = -1)
+async fn incr_value(args: &[Value], db_instance: &Arc<ShardedDb>, delta: i64) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let mut instance = db_instance.write_key(&key).await;
+    Ok(apply_increment(&mut instance, key, delta))
+}
+
+// INCRBY (sign = 1) and DECRBY (sign = -1)
+async fn incr_by_value(args: &[Value], db_instance: &Arc<ShardedDb>, sign: i64) -> Result<Value> {
     if args.len() != 2 {
-        return Ok(Value::SimpleError("Invalid number of arguments".to_owned()));
+        return Ok(CommandError::WrongArity.into());
     }
 
-    if let (Some(Value::BulkString(key)), Some(Value::BulkString(seconds))) =
-        (args.get(0), args.get(1))
+    let key = unpack_bulk_string(args[0].clone())?;
+    let delta = match unpack_bulk_string(args[1].clone())?
+        .parse::<i64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(sign))
     {
-        let seconds = seconds.parse();
-        let seconds = match seconds {
-            Ok(s) => s,
-            Err(_) => return Ok(Value::SimpleError("Invalid number of seconds".to_owned())),
-        };
-        let seconds = time::Duration::from_secs(seconds);
+        Some(delta) => delta,
+        None => return Ok(CommandError::NotInteger.into()),
+    };
 
-        let key_clone = key.clone();
-        let db_instance_clone = Arc::clone(db_instance);
+    let mut instance = db_instance.write_key(&key).await;
+    Ok(apply_increment(&mut instance, key, delta))
+}
 
-        tokio::spawn(async move {
-            sleep(seconds).await;
-            let mut instance = db_instance_clone.write().await;
-            instance.remove(&key_clone);
-            println!("removed: {} after {:?} seconds", key_clone, seconds);
-        });
-        Ok(Value::SimpleString("OK".to_owned()))
+// The read-modify-write runs entirely under the caller's write lock so concurrent
+// increments never lose updates. The key keeps its TTL, a missing key starts at 0.
+fn apply_increment(instance: &mut impl StorageMut, key: String, delta: i64) -> Value {
+    let current = match instance.get(&key) {
+        Some(DataType::String(value)) => match value.as_integer() {
+            Some(number) => number,
+            None => return CommandError::NotInteger.into(),
+        },
+        Some(_) => return wrong_type(),
+        None => 0,
+    };
+
+    let Some(updated) = current.checked_add(delta) else {
+        return Value::SimpleError("ERR increment or decrement would overflow".to_owned());
+    };
+
+    let value = DataType::String(StringValue::Int(updated));
+    match instance.get_entry_mut(&key) {
+        Some(entry) => entry.value = value,
+        None => {
+            instance.insert_entry(key, Entry::new(value));
+        }
+    }
+    Value::Integer(updated)
+}
+
+fn parse_integer_value(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+pub fn integer_arg(value: &Value) -> Option<i64> {
+    match value {
+        Value::BulkString(bytes) => parse_integer_value(bytes),
+        _ => None,
+    }
+}
+
+// Largest string SETRANGE may grow a value to, same as Redis' proto-max-bulk-len
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+async fn append_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 2 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let Value::BulkString(suffix) = &args[1] else {
+        return Err(Error::InvalidArgument("Invalid value type"));
+    };
+
+    // Appending keeps the TTL of an existing key
+    let mut instance = db_instance.write_key(&key).await;
+    let length = match instance.get_entry_mut(&key) {
+        Some(Entry {
+            value: DataType::String(value),
+            ..
+        }) => {
+            let mut appended = BytesMut::from(&value.to_bytes()[..]);
+            appended.extend_from_slice(suffix);
+            *value = StringValue::Raw(appended.freeze());
+            value.len()
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            instance.insert_entry(key, Entry::new(DataType::String(suffix.clone().into())));
+            suffix.len()
+        }
+    };
+
+    Ok(Value::Integer(length as i64))
+}
+
+async fn strlen_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let instance = db_instance.read_key(&key).await;
+    match instance.get(&key) {
+        Some(DataType::String(value)) => Ok(Value::Integer(value.len() as i64)),
+        Some(_) => Ok(wrong_type()),
+        None => Ok(Value::Integer(0)),
+    }
+}
+
+async fn getrange_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let (Some(start), Some(end)) = (integer_arg(&args[1]), integer_arg(&args[2])) else {
+        return Ok(CommandError::NotInteger.into());
+    };
+
+    let instance = db_instance.read_key(&key).await;
+    let value = match instance.get(&key) {
+        Some(DataType::String(value)) => value.to_bytes(),
+        Some(_) => return Ok(wrong_type()),
+        None => Bytes::new(),
+    };
+
+    // Negative indexes count from the end; both ends are inclusive and clamped
+    let length = value.len() as i64;
+    let start = if start < 0 {
+        (length + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        length + end
     } else {
-        Ok(Value::SimpleError("Invalid arguments".to_owned()))
+        end.min(length - 1)
+    };
+
+    if length == 0 || start > end {
+        return Ok(Value::BulkString(Bytes::new()));
     }
+
+    Ok(Value::BulkString(
+        value.slice(start as usize..=end as usize),
+    ))
+}
+
+async fn setrange_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 3 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let key = unpack_bulk_string(args[0].clone())?;
+    let offset = match integer_arg(&args[1]) {
+        Some(offset) if offset >= 0 => offset as usize,
+        _ => return Ok(Value::SimpleError("ERR offset is out of range".to_owned())),
+    };
+    let Value::BulkString(patch) = &args[2] else {
+        return Err(Error::InvalidArgument("Invalid value type"));
+    };
+
+    if offset + patch.len() > MAX_STRING_LENGTH {
+        return Ok(Value::SimpleError(
+            "ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_owned(),
+        ));
+    }
+
+    let mut instance = db_instance.write_key(&key).await;
+    let current = match instance.get(&key) {
+        Some(DataType::String(value)) => Some(value.to_bytes()),
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+
+    // An empty patch never creates the key or pads an existing one
+    if patch.is_empty() {
+        return Ok(Value::Integer(current.map_or(0, |value| value.len()) as i64));
+    }
+
+    // Grow with zero bytes when the offset lies past the end of the current value
+    let mut value = BytesMut::from(&current.unwrap_or_default()[..]);
+    if value.len() < offset + patch.len() {
+        value.resize(offset + patch.len(), 0);
+    }
+    value[offset..offset + patch.len()].copy_from_slice(patch);
+    let length = value.len();
+
+    let value = DataType::String(StringValue::Raw(value.freeze()));
+    match instance.get_entry_mut(&key) {
+        Some(entry) => entry.value = value,
+        None => {
+            instance.insert_entry(key, Entry::new(value));
+        }
+    }
+
+    Ok(Value::Integer(length as i64))
+}
+
+/// PING [message] answers PONG, or the message as a bulk string when there is one.
+fn ping_value(args: &[Value]) -> Value {
+    match args {
+        [] => Value::SimpleString("PONG".to_owned()),
+        [message] => message.clone(),
+        _ => CommandError::WrongArity.into(),
+    }
+}
+
+fn publish_value(args: &[Value], pubsub: &PubSub) -> Result<Value> {
+    let [Value::BulkString(channel), Value::BulkString(message)] = args else {
+        return Ok(CommandError::WrongArity.into());
+    };
+
+    // Number of clients that received the message
+    Ok(Value::Integer(pubsub.publish(channel, message) as i64))
+}
+
+async fn keys_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    if args.len() != 1 {
+        return Ok(CommandError::WrongArity.into());
+    }
+
+    let Value::BulkString(pattern) = &args[0] else {
+        return Err(Error::InvalidArgument("Invalid pattern type"));
+    };
+
+    // Acquire a read lock on the database instance and collect every matching key
+    let instance = db_instance.read().await;
+    let keys = instance
+        .keys()
+        .filter(|key| glob_match(pattern, key.as_bytes()))
+        .map(|key| Value::BulkString(Bytes::copy_from_slice(key.as_bytes())))
+        .collect();
+
+    Ok(Value::Array(keys))
+}
+
+/// Options shared by the cursor-based iteration commands.
+#[derive(Debug, PartialEq)]
+pub struct ScanOptions {
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    // HSCAN only: fields without their values
+    pub novalues: bool,
+}
+
+impl ScanOptions {
+    /// Whether a key, field or member passes the MATCH pattern.
+    pub fn matches(&self, name: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// The reply of every SCAN variant: the next cursor and the page found.
+pub fn scan_reply(cursor: u64, elements: Vec<Value>) -> Value {
+    Value::Array(vec![
+        Value::BulkString(Bytes::from(cursor.to_string())),
+        Value::Array(elements),
+    ])
+}
+
+/// Parses `cursor [MATCH pattern] [COUNT count] [NOVALUES]`. The error is the message
+/// to reply with.
+pub fn parse_scan_options(args: &[Value]) -> std::result::Result<ScanOptions, String> {
+    let cursor = match args.first() {
+        Some(Value::BulkString(cursor)) => std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse::<u64>().ok())
+            .ok_or_else(|| "ERR invalid cursor".to_owned())?,
+        _ => return Err("ERR wrong number of arguments for 'scan' command".to_owned()),
+    };
+
+    // Same default page size as Redis
+    let mut options = ScanOptions {
+        cursor,
+        pattern: None,
+        count: 10,
+        novalues: false,
+    };
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        let option = unpack_bulk_string(arg.clone())
+            .map_err(|_| "ERR syntax error".to_owned())?
+            .to_uppercase();
+
+        if option == "NOVALUES" {
+            options.novalues = true;
+            continue;
+        }
+        match (option.as_str(), args.next()) {
+            ("MATCH", Some(Value::BulkString(pattern))) => options.pattern = Some(pattern.clone()),
+            ("COUNT", Some(count)) => {
+                options.count = match integer_arg(count) {
+                    Some(count) if count >= 1 => count as usize,
+                    Some(_) => return Err("ERR syntax error".to_owned()),
+                    None => return Err("ERR value is not an integer or out of range".to_owned()),
+                };
+            }
+            _ => return Err("ERR syntax error".to_owned()),
+        }
+    }
+
+    Ok(options)
+}
+
+async fn scan_value(args: &[Value], db_instance: &Arc<ShardedDb>) -> Result<Value> {
+    let options = match parse_scan_options(args) {
+        Ok(options) if options.novalues => return Ok(CommandError::Syntax.into()),
+        Ok(options) => options,
+        Err(message) => return Ok(Value::SimpleError(message)),
+    };
+
+    // Only this page is read, one shard locked at a time; like Redis, MATCH filters
+    // the page after it is selected, so a page may come back empty with a non-zero
+    // cursor
+    let (cursor, keys) = db_instance.scan(options.cursor, options.count).await;
+
+    let keys = keys
+        .into_iter()
+        .filter(|key| options.matches(key.as_bytes()))
+        .map(|key| Value::BulkString(Bytes::copy_from_slice(key.as_bytes())))
+        .collect();
+
+    Ok(scan_reply(cursor, keys))
 }
 
 //"*2\r\n$4\r\nECHO\r\n$3\r\nHEY\r\n"
 // return (command, Vec<Argumets>)
+// A command no built-in claims comes back as Invalid with its name still the first
+// argument, so a plugin can pick it up. A name that is not UTF-8 names no command.
+// Callers skip empty arrays, see `is_empty_command`
 pub fn extract_command(value: Value) -> Result<(UserCommand, Vec<Value>)> {
     match value {
-        Value::Array(array) => Ok((
-            UserCommand::from(unpack_bulk_string(array.first().unwrap().clone())?),
-            array.into_iter().skip(1).collect(),
-        )),
-        _ => return Err(anyhow::anyhow!("Invalid command")),
+        Value::Array(array) => {
+            let command = match array.first() {
+                Some(Value::BulkString(name)) => {
+                    UserCommand::from(String::from_utf8_lossy(name).into_owned())
+                }
+                Some(_) => {
+                    return Err(Error::Protocol(
+                        "expected a bulk string as the command name",
+                    ))
+                }
+                None => return Err(Error::Protocol("empty command")),
+            };
+            let args = match command {
+                UserCommand::Invalid => array,
+                _ => array.into_iter().skip(1).collect(),
+            };
+            Ok((command, args))
+        }
+        _ => Err(Error::Protocol("expected an array of bulk strings")),
     }
 }
 
+/// Whether a request is an empty array, which Redis neither runs nor answers.
+pub fn is_empty_command(value: &Value) -> bool {
+    matches!(value, Value::Array(array) if array.is_empty())
+}
+
+pub fn wrong_type() -> Value {
+    CommandError::WrongType.into()
+}
+
 pub fn unpack_bulk_string(value: Value) -> Result<String> {
     match value {
-        Value::BulkString(string) => Ok(string),
-        _ => return Err(anyhow::anyhow!("Invalid bulk string")),
+        Value::BulkString(bytes) => {
+            String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidUtf8)
+        }
+        _ => Err(Error::InvalidArgument("Invalid bulk string")),
     }
 }
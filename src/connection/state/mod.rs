@@ -0,0 +1,79 @@
+use tokio::sync::broadcast;
+
+use crate::clients::{Client, OutputBuffer};
+use crate::connection::dispatch::Caller;
+use crate::parser::Value;
+use crate::pubsub::Subscriber;
+use crate::server::ServerState;
+use crate::transaction::Transaction;
+
+pub mod tests_state;
+
+/// Everything a connection remembers between commands. It starts out the way RESET
+/// leaves it, apart from the entry in the client registry, which lives as long as the
+/// connection does.
+#[derive(Debug)]
+pub struct ConnectionState {
+    pub client: Client,
+    // The ACL user commands run as, which only counts once authenticated
+    pub user: String,
+    pub authenticated: bool,
+    // Index of the database the connection works on, changed with SELECT
+    pub selected: usize,
+    // The MULTI queue and the keys under WATCH
+    pub transaction: Transaction,
+    pub subscriber: Subscriber,
+    // Set once the connection sent MONITOR
+    pub monitor: Option<broadcast::Receiver<Value>>,
+    // Set by ASKING for the command after it
+    pub asking: bool,
+    // Replies to commands, counted against the normal client-output-buffer-limit
+    pub output: OutputBuffer,
+    // The port a replica gave with REPLCONF listening-port before PSYNC
+    pub listening_port: Option<u16>,
+}
+
+impl ConnectionState {
+    pub fn new(state: &ServerState, client: Client) -> Self {
+        Self {
+            client,
+            user: "default".to_owned(),
+            // While the default user has no password every connection starts out
+            // authenticated
+            authenticated: state.acl.is_nopass("default"),
+            selected: 0,
+            transaction: Transaction::new(),
+            subscriber: state.pubsub.subscriber(),
+            monitor: None,
+            asking: false,
+            output: OutputBuffer::new(),
+            listening_port: None,
+        }
+    }
+
+    /// The database the connection has selected, which a command may change, and the
+    /// connection as the command's handler sees it.
+    pub fn caller(&mut self) -> (&mut usize, Caller<'_>) {
+        let caller = Caller {
+            client: &self.client,
+            user: &self.user,
+        };
+        (&mut self.selected, caller)
+    }
+
+    /// RESET: discards MULTI and every WATCH, leaves all channels and MONITOR,
+    /// selects database 0, forgets the client name and logs the connection back in
+    /// as the default user, which takes AUTH when it has a password.
+    pub async fn reset(&mut self, state: &ServerState) {
+        self.transaction.finish();
+        self.transaction.unwatch().await;
+        // Messages still queued for the old subscriptions are dropped with it
+        self.subscriber = state.pubsub.subscriber();
+        self.monitor = None;
+        self.selected = 0;
+        self.asking = false;
+        self.client.set_name(None);
+        self.user = "default".to_owned();
+        self.authenticated = state.acl.is_nopass("default");
+    }
+}
@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::parser::UserCommand;
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn test_reset_restores_a_new_connection() {
+        let state = ServerState::new(Config {
+            requirepass: Some("secret".to_owned()),
+            ..Config::new()
+        });
+        let client = state
+            .clients
+            .register(SocketAddr::from(([127, 0, 0, 1], 5000)));
+        let mut connection = ConnectionState::new(&state, client);
+        assert!(!connection.authenticated);
+
+        connection.authenticated = true;
+        connection.selected = 3;
+        connection.asking = true;
+        connection.client.set_name(Some("worker".to_owned()));
+        connection.monitor = Some(state.monitor.subscribe());
+        connection.transaction.begin();
+        connection.transaction.queue(UserCommand::Ping, Vec::new());
+        connection.subscriber.subscribe("news".into());
+
+        connection.reset(&state).await;
+        assert!(!connection.authenticated);
+        assert_eq!(connection.selected, 0);
+        assert!(!connection.asking);
+        assert_eq!(connection.client.name(), None);
+        assert!(connection.monitor.is_none());
+        assert!(!connection.transaction.is_active());
+        assert!(!connection.subscriber.is_subscribed());
+        // The old subscriptions are gone from the registry too
+        assert_eq!(state.pubsub.publish(&"news".into(), &"hello".into()), 0);
+    }
+}
@@ -1,29 +1,87 @@
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use std::{collections::HashMap, sync::Arc};
+    use crate::clock::{Clock, MockClock, SystemClock};
+    use crate::config::{AppendFsync, Config, MaxMemoryPolicy, OutputBufferLimits};
+    use crate::persistence::aof::{self, Aof};
+    use bytes::Bytes;
+    use std::sync::{atomic::Ordering, Arc};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpListener;
-    use tokio::sync::RwLock;
 
-    async fn setup() -> (TcpStream, Arc<RwLock<HashMap<String, String>>>) {
+    async fn setup() -> (TcpStream, Arc<ShardedDb>) {
+        let (addr, db_instance) = spawn_server().await;
+
+        // Connect to the listener
+        let socket = TcpStream::connect(addr).await.unwrap();
+
+        (socket, db_instance)
+    }
+
+    // Starts a server that accepts any number of clients sharing one database
+    async fn spawn_server() -> (std::net::SocketAddr, Arc<ShardedDb>) {
+        spawn_server_with_config(Config::new()).await
+    }
+
+    async fn spawn_server_with_config(config: Config) -> (std::net::SocketAddr, Arc<ShardedDb>) {
+        spawn_server_with_state(ServerState::new(config)).await
+    }
+
+    async fn spawn_server_with_state(state: ServerState) -> (std::net::SocketAddr, Arc<ShardedDb>) {
         // Start a TCP listener
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        spawn_server_on(listener, state)
+    }
+
+    // A server whose port setting is the one it listens on, as replicas announce it
+    async fn spawn_announcing_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::new();
+        config.port = listener.local_addr().unwrap().port();
+        spawn_server_on(listener, ServerState::new(config)).0
+    }
+
+    fn spawn_server_on(
+        listener: TcpListener,
+        state: ServerState,
+    ) -> (std::net::SocketAddr, Arc<ShardedDb>) {
         let addr = listener.local_addr().unwrap();
 
-        // Create a shared database instance
-        let db_instance = Arc::new(RwLock::new(HashMap::new()));
+        // Share the server state; tests inspect database 0
+        let state = Arc::new(state);
+        let db_instance = Arc::clone(&state.databases[0]);
 
         // Spawn a task to accept connections
-        let db_instance_clone = Arc::clone(&db_instance);
         tokio::spawn(async move {
-            let (socket, _) = listener.accept().await.unwrap();
-            handle_connection(socket, db_instance_clone).await.unwrap();
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    handle_connection(socket, state).await.unwrap();
+                });
+            }
         });
 
-        // Connect to the listener
-        let socket = TcpStream::connect(addr).await.unwrap();
+        (addr, db_instance)
+    }
 
-        (socket, db_instance)
+    // Builds a RESP command array from plain string parts
+    fn command(parts: &[&str]) -> Value {
+        Value::Array(
+            parts
+                .iter()
+                .map(|part| Value::BulkString(Bytes::copy_from_slice(part.as_bytes())))
+                .collect(),
+        )
+    }
+
+    // Sends a command and waits for its reply
+    async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+        client_handler: &mut RespHandler<S>,
+        parts: &[&str],
+    ) -> Value {
+        client_handler.write_value(&command(parts)).await.unwrap();
+        client_handler.read_value().await.unwrap().unwrap()
     }
 
     #[tokio::test]
@@ -33,13 +91,23 @@ mod tests {
 
         // Send the PING command
         client_handler
-            .write_value(Value::Array(vec![Value::BulkString("PING".to_owned())]))
+            .write_value(&Value::Array(vec![Value::BulkString("PING".into())]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
         assert_eq!(response, Value::SimpleString("PONG".to_owned()));
+
+        // With a message PING echoes it back
+        assert_eq!(
+            send(&mut client_handler, &["PING", "hello"]).await,
+            Value::BulkString("hello".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PING", "a", "b"]).await,
+            Value::SimpleError("ERR wrong number of arguments for 'ping' command".to_owned())
+        );
     }
 
     #[tokio::test]
@@ -49,16 +117,16 @@ mod tests {
 
         // Send the ECHO command
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("ECHO".to_owned()),
-                Value::BulkString("Hello, World!".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("ECHO".into()),
+                Value::BulkString("Hello, World!".into()),
             ]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
-        assert_eq!(response, Value::BulkString("Hello, World!".to_owned()));
+        assert_eq!(response, Value::BulkString("Hello, World!".into()));
     }
 
     #[tokio::test]
@@ -68,33 +136,30 @@ mod tests {
 
         // Send the SET command
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("SET".to_owned()),
-                Value::BulkString("key".to_owned()),
-                Value::BulkString("value".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("SET".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("value".into()),
             ]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
-        assert_eq!(
-            response,
-            Value::BulkString("Scucessfully inserted value in database".to_owned())
-        );
+        assert_eq!(response, Value::SimpleString("OK".to_owned()));
 
         // Send the GET command
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("GET".to_owned()),
-                Value::BulkString("key".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("GET".into()),
+                Value::BulkString("key".into()),
             ]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
-        assert_eq!(response, Value::BulkString("value".to_owned()));
+        assert_eq!(response, Value::BulkString("value".into()));
     }
 
     #[tokio::test]
@@ -104,10 +169,10 @@ mod tests {
 
         // First, set a key
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("SET".to_owned()),
-                Value::BulkString("key".to_owned()),
-                Value::BulkString("value".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("SET".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("value".into()),
             ]))
             .await
             .unwrap();
@@ -115,36 +180,37 @@ mod tests {
 
         // Send the DEL command
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("DEL".to_owned()),
-                Value::BulkString("key".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("DEL".into()),
+                Value::BulkString("key".into()),
             ]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
-        assert_eq!(
-            response,
-            Value::BulkString("Removed the key from database".to_owned())
-        );
+        assert_eq!(response, Value::Integer(1));
 
         // Verify the key is deleted
         let db_instance = db_instance.read().await;
         assert!(db_instance.get("key").is_none());
     }
 
-    #[tokio::test]
+    // The connection tasks run on other threads than the test, and still read the time
+    // from the server's mock clock
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_expire_command() {
-        let (socket, db_instance) = setup().await;
-        let mut client_handler = RespHandler::new(socket);
+        let clock = Arc::new(MockClock::starting_now());
+        let (addr, db_instance) =
+            spawn_server_with_state(ServerState::with_clock(Config::new(), clock.clone())).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
 
         // First, set a key
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("SET".to_owned()),
-                Value::BulkString("key".to_owned()),
-                Value::BulkString("value".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("SET".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("value".into()),
             ]))
             .await
             .unwrap();
@@ -152,23 +218,3239 @@ mod tests {
 
         // Send the EXPIRE command
         client_handler
-            .write_value(Value::Array(vec![
-                Value::BulkString("EXPIRE".to_owned()),
-                Value::BulkString("key".to_owned()),
-                Value::BulkString("1".to_owned()),
+            .write_value(&Value::Array(vec![
+                Value::BulkString("EXPIRE".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("1".into()),
             ]))
             .await
             .unwrap();
 
         // Read the response
         let response = client_handler.read_value().await.unwrap().unwrap();
-        assert_eq!(response, Value::SimpleString("OK".to_owned()));
+        assert_eq!(response, Value::Integer(1));
+
+        clock.advance(Duration::from_millis(999));
+        assert!(db_instance.read().await.get("key").is_some());
+        clock.advance(Duration::from_millis(1));
+        assert!(db_instance.read().await.get("key").is_none());
+        assert_eq!(
+            send(&mut client_handler, &["EXISTS", "key"]).await,
+            Value::Integer(0)
+        );
+    }
 
-        // Wait for more than 1 second to ensure the key expires
-        sleep(tokio::time::Duration::from_secs(2)).await;
+    #[tokio::test]
+    async fn test_del_and_expire_missing_key() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
 
-        // Verify the key is deleted
-        let db_instance = db_instance.read().await;
-        assert!(db_instance.get("key").is_none());
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("DEL".into()),
+                Value::BulkString("missing".into()),
+            ]))
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::Integer(0));
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("EXPIRE".into()),
+                Value::BulkString("missing".into()),
+                Value::BulkString("10".into()),
+            ]))
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn test_misuse_replies_without_closing_the_connection() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        let error = |message: &str| Value::SimpleError(message.to_owned());
+
+        for (request, reply) in [
+            (
+                vec!["GET"],
+                error("ERR wrong number of arguments for 'get' command"),
+            ),
+            (
+                vec!["HSET", "hash", "field"],
+                error("ERR wrong number of arguments for 'hset' command"),
+            ),
+            // Caught by the handler rather than the command table
+            (
+                vec!["HSET", "hash", "a", "1", "b"],
+                error("ERR wrong number of arguments for 'hset' command"),
+            ),
+            (
+                vec!["SET", "key", "value"],
+                Value::SimpleString("OK".to_owned()),
+            ),
+            (
+                vec!["LPUSH", "key", "a"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+            (vec!["MULTI"], Value::SimpleString("OK".to_owned())),
+            (
+                vec!["EXISTS"],
+                error("ERR wrong number of arguments for 'exists' command"),
+            ),
+            (
+                vec!["EXEC"],
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &request).await,
+                reply,
+                "{:?}",
+                request
+            );
+        }
+
+        // A key that is not a bulk string fails the command, not the connection
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("GET".into()),
+                Value::Integer(1),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(
+            client_handler.read_value().await.unwrap(),
+            Some(error("ERR Invalid bulk string"))
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_variadic_del_exists_and_unlink() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        for key in ["a", "b", "c"] {
+            send(&mut client_handler, &["SET", key, "value"]).await;
+        }
+
+        for (request, reply) in [
+            (vec!["RPUSH", "list", "a", "b", "c"], Value::Integer(3)),
+            (vec!["LPUSH", "list", "d", "e"], Value::Integer(5)),
+            (
+                vec!["EXISTS", "a", "a", "missing", "list"],
+                Value::Integer(3),
+            ),
+            (vec!["DEL", "a", "b", "missing", "a"], Value::Integer(2)),
+            (vec!["UNLINK", "c", "list", "missing"], Value::Integer(2)),
+            (vec!["EXISTS", "a", "b", "c", "list"], Value::Integer(0)),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &request).await,
+                reply,
+                "{:?}",
+                request
+            );
+        }
+        assert!(db_instance.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_and_mget_missing_keys_return_null() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("GET".into()),
+                Value::BulkString("missing".into()),
+            ]))
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::Null);
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("SET".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("value".into()),
+            ]))
+            .await
+            .unwrap();
+        client_handler.read_value().await.unwrap().unwrap();
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("MGET".into()),
+                Value::BulkString("key".into()),
+                Value::BulkString("missing".into()),
+            ]))
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(
+            response,
+            Value::Array(vec![Value::BulkString("value".into()), Value::Null])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get_binary_value() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        let payload = Bytes::from_static(&[0xff, 0x00, 0xfe, b'\r', b'\n', 0x80]);
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("SET".into()),
+                Value::BulkString("blob".into()),
+                Value::BulkString(payload.clone()),
+            ]))
+            .await
+            .unwrap();
+        client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(
+            db_instance.read().await.get("blob"),
+            Some(&DataType::String(payload.clone().into()))
+        );
+
+        client_handler
+            .write_value(&Value::Array(vec![
+                Value::BulkString("GET".into()),
+                Value::BulkString("blob".into()),
+            ]))
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::BulkString(payload));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_commands() {
+        let (mut socket, _) = setup().await;
+
+        // Three commands in a single write, like redis-benchmark -P 3
+        socket
+            .write_all(
+                b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n\
+                  *2\r\n$3\r\nGET\r\n$3\r\nkey\r\n\
+                  *1\r\n$4\r\nPING\r\n",
+            )
+            .await
+            .unwrap();
+
+        let expected: &[u8] = b"+OK\r\n\
+                                $5\r\nvalue\r\n\
+                                +PONG\r\n";
+        let mut buffer = vec![0; expected.len()];
+        socket.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, expected);
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_commands_with_trailing_partial_frame() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        client_handler
+            .socket
+            .write_all(b"*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$2\r\nhi")
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::SimpleString("PONG".to_owned()));
+
+        client_handler.socket.write_all(b"\r\n").await.unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::BulkString("hi".into()));
+    }
+
+    #[tokio::test]
+    async fn test_empty_and_malformed_requests_keep_the_connection() {
+        let (mut socket, _) = setup().await;
+
+        // An empty array is skipped unanswered and a name that is not a bulk string
+        // gets an error, without either closing the connection
+        socket
+            .write_all(b"*0\r\n*1\r\n:1\r\n*0\r\n*1\r\n$4\r\nPING\r\n")
+            .await
+            .unwrap();
+        let expected: &[u8] =
+            b"-ERR Protocol error: expected a bulk string as the command name\r\n\
+                                +PONG\r\n";
+        let mut buffer = vec![0; expected.len()];
+        socket.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, expected);
+    }
+
+    #[tokio::test]
+    async fn test_inline_commands() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        // Telnet sends CRLF, netcat a bare LF, and both mix with RESP commands
+        client_handler
+            .socket
+            .write_all(b"PING\r\n\r\nSET greeting \"hello world\"\n*2\r\n$3\r\nGET\r\n$8\r\ngreeting\r\nECHO 'it\\'s'")
+            .await
+            .unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::SimpleString("PONG".to_owned()));
+        client_handler.read_value().await.unwrap().unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::BulkString("hello world".into()));
+
+        // The last line only counts once it is complete
+        client_handler.socket.write_all(b"\r\n").await.unwrap();
+        let response = client_handler.read_value().await.unwrap().unwrap();
+        assert_eq!(response, Value::BulkString("it's".into()));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_limits() {
+        let mut config = Config::new();
+        config.set("proto-max-multibulk-len", "4").unwrap();
+        let (addr, _) = spawn_server_with_config(config).await;
+
+        // An array claiming too many elements is refused before they arrive
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        client_handler.socket.write_all(b"*5\r\n").await.unwrap();
+        assert_eq!(
+            client_handler.read_value().await.unwrap(),
+            Some(Value::SimpleError(
+                "ERR Protocol error: invalid multibulk length".to_owned()
+            ))
+        );
+        assert_eq!(client_handler.read_value().await.unwrap(), None);
+
+        // Likewise a bulk string longer than proto-max-bulk-len, which CONFIG SET lowers
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let reply = send(
+            &mut client_handler,
+            &["CONFIG", "SET", "proto-max-bulk-len", "1mb"],
+        )
+        .await;
+        assert_eq!(reply, Value::SimpleString("OK".to_owned()));
+        client_handler
+            .socket
+            .write_all(b"*2\r\n$4\r\nECHO\r\n$1048577\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            client_handler.read_value().await.unwrap(),
+            Some(Value::SimpleError(
+                "ERR Protocol error: invalid bulk length".to_owned()
+            ))
+        );
+        assert_eq!(client_handler.read_value().await.unwrap(), None);
+
+        // Requests within the limits are served as usual
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let reply = send(&mut client_handler, &["ECHO", "hi"]).await;
+        assert_eq!(reply, Value::BulkString("hi".into()));
+    }
+
+    // Large MGET replies, end to end and encoding alone. Run with
+    // `cargo test --release bench_large_mget -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_large_mget() {
+        const KEYS: usize = 10_000;
+        const ROUNDS: usize = 200;
+
+        let (socket, db_instance) = setup().await;
+        let keys: Vec<String> = (0..KEYS).map(|i| format!("key:{}", i)).collect();
+        {
+            let mut instance = db_instance.write().await;
+            for key in &keys {
+                let value = DataType::String(vec![b'x'; 100].into());
+                instance.insert_entry(key.clone(), Entry::new(value));
+            }
+        }
+        let mut parts = vec!["MGET"];
+        parts.extend(keys.iter().map(String::as_str));
+        let request = command(&parts);
+
+        let mut client_handler = RespHandler::new(socket);
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            client_handler.write_value(&request).await.unwrap();
+            client_handler.read_value().await.unwrap().unwrap();
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "MGET of {} keys: {:>8.0} replies/s",
+            KEYS,
+            ROUNDS as f64 / elapsed
+        );
+
+        let reply = Value::Array(
+            (0..KEYS)
+                .map(|_| Value::BulkString(vec![b'x'; 100].into()))
+                .collect(),
+        );
+        let mut out = BytesMut::new();
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            reply.encode(&mut out);
+            out.clear();
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "encoding alone:     {:>8.0} replies/s",
+            ROUNDS as f64 / elapsed
+        );
+    }
+
+    // Writes from several tasks through `run_command`, each to keys of its own, with
+    // and without a maxmemory limit to check against. Run with
+    // `cargo test --release bench_concurrent_writes -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn bench_concurrent_writes() {
+        const TASKS: usize = 8;
+        const WRITES: usize = 20_000;
+
+        for maxmemory in [0, 1 << 30] {
+            let state = ServerState::new(Config {
+                maxmemory,
+                ..Config::new()
+            });
+            let start = std::time::Instant::now();
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let mut selected = 0;
+                        for i in 0..WRITES {
+                            let key = bulk(&format!("list:{}:{}", task, i % 64));
+                            let item = bulk(&i.to_string());
+                            run_command(
+                                UserCommand::RPush,
+                                &[key, item],
+                                &state,
+                                &mut selected,
+                                None,
+                            )
+                            .await
+                            .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            let rate = (TASKS * WRITES) as f64 / start.elapsed().as_secs_f64();
+            println!("maxmemory {:>10}: {:>10.0} writes/s", maxmemory, rate);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_connections() {
+        let state = ServerState::new(Config::new());
+        let shutdown = Arc::clone(&state.shutdown);
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            send(&mut client_handler, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+
+        shutdown.request(false);
+        assert!(client_handler.read_value().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_command() {
+        let state = ServerState::new(Config::new());
+        let shutdown = Arc::clone(&state.shutdown);
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            send(&mut client_handler, &["SHUTDOWN", "NOW"]).await,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+
+        // The connection closes without a reply
+        client_handler
+            .write_value(&command(&["SHUTDOWN", "NOSAVE"]))
+            .await
+            .unwrap();
+        assert!(client_handler.read_value().await.unwrap().is_none());
+        assert!(!shutdown.requested().await);
+    }
+
+    #[tokio::test]
+    async fn test_debug_sleep_stalls_other_clients() {
+        let (addr, _) = spawn_server().await;
+        let mut sleeper = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        sleeper
+            .write_value(&command(&["DEBUG", "SLEEP", "0.2"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        let start = std::time::Instant::now();
+        assert_eq!(
+            send(&mut other, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+        assert_eq!(
+            sleeper.read_value().await.unwrap().unwrap(),
+            Value::SimpleString("OK".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_over_in_memory_stream() {
+        let (client, server) = tokio::io::duplex(4096);
+        let state = Arc::new(ServerState::new(Config::new()));
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        tokio::spawn(serve_client(server, addr, Arc::clone(&state)));
+
+        let mut client_handler = RespHandler::new(client);
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::BulkString("value".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["CLIENT", "LIST"]).await,
+            Value::BulkString(
+                "id=1 addr=127.0.0.1:5000 name= age=0 idle=0 db=0 cmd=client\n".into()
+            )
+        );
+        assert!(state.databases[0].read().await.get("key").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_maxclients_turns_away_extra_connections() {
+        let (addr, _) = spawn_server_with_config(Config {
+            maxclients: 1,
+            ..Config::new()
+        })
+        .await;
+        let mut first = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            send(&mut first, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+
+        let mut second = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            second.read_value().await.unwrap(),
+            Some(Value::SimpleError("ERR max clients reached".to_owned()))
+        );
+        assert!(second.read_value().await.unwrap().is_none());
+
+        // The slot frees up once the first client leaves
+        drop(first);
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        let mut third = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            send(&mut third, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_clients_time_out() {
+        let (addr, _) = spawn_server_with_config(Config {
+            timeout: 1,
+            ..Config::new()
+        })
+        .await;
+        let mut idle = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+
+        let start = std::time::Instant::now();
+        assert!(idle.read_value().await.unwrap().is_none());
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+
+        // Subscribers are left alone
+        let mut publisher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+        assert!(matches!(
+            subscriber.read_value().await.unwrap(),
+            Some(Value::Array(_) | Value::Push(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ttl_and_pttl_commands() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(-2)
+        );
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(-1)
+        );
+
+        send(&mut client_handler, &["EXPIRE", "key", "100"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(100)
+        );
+
+        let Value::Integer(millis) = send(&mut client_handler, &["PTTL", "key"]).await else {
+            panic!("expected an integer reply");
+        };
+        assert!(millis > 99_000 && millis <= 100_000);
+    }
+
+    fn options(parts: &[&str]) -> std::result::Result<SetOptions, String> {
+        let Value::Array(args) = command(parts) else {
+            unreachable!()
+        };
+        parse_set_options(&args)
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        assert_eq!(options(&[]), Ok(SetOptions::default()));
+        assert_eq!(
+            options(&["nx", "EX", "10"]),
+            Ok(SetOptions {
+                expiry: Some(SetExpiry::Seconds(10)),
+                condition: Some(SetCondition::IfNotExists),
+                get: false,
+            })
+        );
+        assert_eq!(
+            options(&["XX", "GET", "KEEPTTL"]),
+            Ok(SetOptions {
+                expiry: Some(SetExpiry::KeepTtl),
+                condition: Some(SetCondition::IfExists),
+                get: true,
+            })
+        );
+        assert_eq!(
+            options(&["PXAT", "1700000000000"]).unwrap().expiry,
+            Some(SetExpiry::UnixMilliseconds(1_700_000_000_000))
+        );
+        assert_eq!(
+            options(&["EXAT", "1700000000"]).unwrap().expiry,
+            Some(SetExpiry::UnixSeconds(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn test_parse_set_options_rejects_invalid_combinations() {
+        let syntax = Err("ERR syntax error".to_owned());
+        assert_eq!(options(&["NX", "XX"]), syntax);
+        assert_eq!(options(&["EX", "10", "PX", "100"]), syntax);
+        assert_eq!(options(&["EX", "10", "KEEPTTL"]), syntax);
+        assert_eq!(options(&["EX"]), syntax);
+        assert_eq!(options(&["BOGUS"]), syntax);
+        assert_eq!(
+            options(&["EX", "0"]),
+            Err("ERR invalid expire time in 'set' command".to_owned())
+        );
+        assert_eq!(
+            options(&["PX", "abc"]),
+            Err("ERR invalid expire time in 'set' command".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_nx_and_xx() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v1", "XX"]).await,
+            Value::Null
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v1", "NX"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v2", "NX"]).await,
+            Value::Null
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v3", "XX"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::BulkString("v3".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_setnx_setex_and_psetex() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["SETNX", "lock", "first"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SETNX", "lock", "second"]).await,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "lock"]).await,
+            Value::BulkString("first".into())
+        );
+
+        // SETEX replaces the value and any previous TTL
+        assert_eq!(
+            send(&mut client_handler, &["SETEX", "lock", "100", "renewed"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "lock"]).await,
+            Value::Integer(100)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PSETEX", "short", "1500", "value"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "short"]).await,
+            Value::Integer(2)
+        );
+
+        assert_eq!(
+            send(&mut client_handler, &["SETEX", "key", "0", "value"]).await,
+            Value::SimpleError("ERR invalid expire time in 'setex' command".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PSETEX", "key", "-5", "value"]).await,
+            Value::SimpleError("ERR invalid expire time in 'psetex' command".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SETEX", "key", "soon", "value"]).await,
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+        assert!(db_instance.read().await.get("key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_set_with_expiry_options() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "value", "EX", "100"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(100)
+        );
+
+        // KEEPTTL retains the expiry, a plain SET clears it
+        send(&mut client_handler, &["SET", "key", "other", "KEEPTTL"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(100)
+        );
+        send(&mut client_handler, &["SET", "key", "other"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(-1)
+        );
+
+        send(&mut client_handler, &["SET", "key", "value", "PX", "50"]).await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::Null
+        );
+
+        // An absolute timestamp in the past expires the key immediately
+        send(&mut client_handler, &["SET", "key", "value", "EXAT", "1"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_get_option() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v1", "GET"]).await,
+            Value::Null
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v2", "GET"]).await,
+            Value::BulkString("v1".into())
+        );
+        // GET together with a failed NX still reports the current value
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v3", "NX", "GET"]).await,
+            Value::BulkString("v2".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::BulkString("v2".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SET", "key", "v3", "EX", "-5"]).await,
+            Value::SimpleError("ERR invalid expire time in 'set' command".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_getset_command() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["GETSET", "token", "first"]).await,
+            Value::Null
+        );
+        send(&mut client_handler, &["EXPIRE", "token", "100"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GETSET", "token", "second"]).await,
+            Value::BulkString("first".into())
+        );
+        // Like SET, the new value does not keep the old TTL
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "token"]).await,
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "token"]).await,
+            Value::BulkString("second".into())
+        );
+
+        send(&mut client_handler, &["LPUSH", "list", "item"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GETSET", "list", "value"]).await,
+            wrong_type()
+        );
+        assert_eq!(
+            send(&mut client_handler, &["LLEN", "list"]).await,
+            Value::Integer(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incr_and_decr_commands() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCRBY", "counter", "40"]).await,
+            Value::Integer(42)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["DECR", "counter"]).await,
+            Value::Integer(41)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["DECRBY", "counter", "-9"]).await,
+            Value::Integer(50)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "counter"]).await,
+            Value::BulkString("50".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incr_errors_and_ttl() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        let not_an_integer =
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned());
+
+        send(&mut client_handler, &["SET", "text", "abc"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "text"]).await,
+            not_an_integer
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCRBY", "counter", "1.5"]).await,
+            not_an_integer
+        );
+
+        send(&mut client_handler, &["SET", "max", &i64::MAX.to_string()]).await;
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "max"]).await,
+            Value::SimpleError("ERR increment or decrement would overflow".to_owned())
+        );
+
+        // Incrementing keeps the existing expiry
+        send(&mut client_handler, &["SET", "counter", "1", "EX", "100"]).await;
+        send(&mut client_handler, &["INCR", "counter"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "counter"]).await,
+            Value::Integer(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_incr_does_not_lose_updates() {
+        let db_instance = Arc::new(ShardedDb::new());
+        let mut tasks = Vec::new();
+        for _ in 0..8 {
+            let db_instance = Arc::clone(&db_instance);
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..100 {
+                    incr_value(&[Value::BulkString("counter".into())], &db_instance, 1)
+                        .await
+                        .unwrap();
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(
+            db_instance.read().await.get("counter"),
+            Some(&DataType::String(Bytes::from("800").into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_and_strlen_commands() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["STRLEN", "key"]).await,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["APPEND", "key", "Hello"]).await,
+            Value::Integer(5)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["APPEND", "key", " World"]).await,
+            Value::Integer(11)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["STRLEN", "key"]).await,
+            Value::Integer(11)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::BulkString("Hello World".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_int_encoded_strings() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        let encoding = |key: &'static str| ["OBJECT", "ENCODING", key];
+
+        send(&mut client_handler, &["SET", "counter", "-10"]).await;
+        assert_eq!(
+            send(&mut client_handler, &encoding("counter")).await,
+            Value::BulkString("int".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCRBY", "counter", "25"]).await,
+            Value::Integer(15)
+        );
+        assert!(matches!(
+            db_instance.read().await.get("counter"),
+            Some(DataType::String(StringValue::Int(15)))
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["STRLEN", "counter"]).await,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GETRANGE", "counter", "-1", "-1"]).await,
+            Value::BulkString("5".into())
+        );
+
+        // Writing by byte offset turns the number into raw bytes, which INCR still reads
+        assert_eq!(
+            send(&mut client_handler, &["APPEND", "counter", "0"]).await,
+            Value::Integer(3)
+        );
+        assert!(matches!(
+            db_instance.read().await.get("counter"),
+            Some(DataType::String(StringValue::Raw(_)))
+        ));
+        assert_eq!(
+            send(&mut client_handler, &encoding("counter")).await,
+            Value::BulkString("embstr".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            Value::Integer(151)
+        );
+        assert_eq!(
+            send(&mut client_handler, &encoding("counter")).await,
+            Value::BulkString("int".into())
+        );
+
+        // Only the canonical spelling of a number is stored as one
+        send(&mut client_handler, &["SET", "padded", "007"]).await;
+        assert_eq!(
+            send(&mut client_handler, &encoding("padded")).await,
+            Value::BulkString("embstr".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "padded"]).await,
+            Value::BulkString("007".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_getrange_command() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        send(&mut client_handler, &["SET", "key", "This is a string"]).await;
+
+        for (start, end, expected) in [
+            ("0", "3", "This"),
+            ("-3", "-1", "ing"),
+            ("0", "-1", "This is a string"),
+            ("10", "100", "string"),
+            ("-100", "3", "This"),
+            ("5", "2", ""),
+            ("100", "200", ""),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &["GETRANGE", "key", start, end]).await,
+                Value::BulkString(Bytes::copy_from_slice(expected.as_bytes())),
+                "GETRANGE key {} {}",
+                start,
+                end
+            );
+        }
+
+        assert_eq!(
+            send(&mut client_handler, &["GETRANGE", "missing", "0", "-1"]).await,
+            Value::BulkString(Bytes::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_setrange_command() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "Hello World"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["SETRANGE", "key", "6", "Redis"]).await,
+            Value::Integer(11)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::BulkString("Hello Redis".into())
+        );
+
+        // Writing past the end pads with zero bytes
+        assert_eq!(
+            send(&mut client_handler, &["SETRANGE", "padded", "3", "ab"]).await,
+            Value::Integer(5)
+        );
+        assert_eq!(
+            db_instance.read().await.get("padded"),
+            Some(&DataType::String(Bytes::from_static(b"\0\0\0ab").into()))
+        );
+
+        assert_eq!(
+            send(&mut client_handler, &["SETRANGE", "empty", "10", ""]).await,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "empty"]).await,
+            Value::Null
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SETRANGE", "key", "-1", "x"]).await,
+            Value::SimpleError("ERR offset is out of range".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_getdel_command() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GETDEL", "key"]).await,
+            Value::BulkString("value".into())
+        );
+        assert!(db_instance.read().await.get("key").is_none());
+        assert_eq!(
+            send(&mut client_handler, &["GETDEL", "key"]).await,
+            Value::Null
+        );
+
+        send(&mut client_handler, &["HSET", "hash", "field", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GETDEL", "hash"]).await,
+            wrong_type()
+        );
+        assert!(db_instance.read().await.get("hash").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_getex_command() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GETEX", "key", "EX", "100"]).await,
+            Value::BulkString("value".into())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(100)
+        );
+
+        // Without options the TTL stays as it is
+        send(&mut client_handler, &["GETEX", "key"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(100)
+        );
+
+        let at = (SystemClock.now_millis() + 50_000).to_string();
+        send(&mut client_handler, &["GETEX", "key", "PXAT", &at]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(50)
+        );
+
+        send(&mut client_handler, &["GETEX", "key", "PERSIST"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(-1)
+        );
+
+        assert_eq!(
+            send(&mut client_handler, &["GETEX", "missing", "EX", "10"]).await,
+            Value::Null
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GETEX", "key", "EX", "0"]).await,
+            Value::SimpleError("ERR invalid expire time in 'getex' command".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GETEX", "key", "EX", "ten"]).await,
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GETEX", "key", "KEEPTTL"]).await,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_command() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        for key in ["firstname", "lastname", "age", "expired"] {
+            send(&mut client_handler, &["SET", key, "value"]).await;
+        }
+        send(
+            &mut client_handler,
+            &["SET", "expired", "value", "PXAT", "1"],
+        )
+        .await;
+
+        let keys = |value: Value| {
+            let Value::Array(mut keys) = value else {
+                panic!("expected an array reply");
+            };
+            keys.sort_by_key(|key| format!("{:?}", key));
+            keys
+        };
+
+        assert_eq!(
+            keys(send(&mut client_handler, &["KEYS", "*name"]).await),
+            vec![
+                Value::BulkString("firstname".into()),
+                Value::BulkString("lastname".into())
+            ]
+        );
+        assert_eq!(
+            keys(send(&mut client_handler, &["KEYS", "a??"]).await),
+            vec![Value::BulkString("age".into())]
+        );
+        assert_eq!(
+            keys(send(&mut client_handler, &["KEYS", "*"]).await).len(),
+            3
+        );
+        assert!(keys(send(&mut client_handler, &["KEYS", "[xyz]*"]).await).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_command() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        for i in 0..25 {
+            send(
+                &mut client_handler,
+                &["SET", &format!("user:{}", i), "value"],
+            )
+            .await;
+            send(
+                &mut client_handler,
+                &["SET", &format!("session:{}", i), "value"],
+            )
+            .await;
+        }
+
+        let mut cursor = "0".to_owned();
+        let mut users = Vec::new();
+        let mut calls = 0;
+        loop {
+            let reply = send(
+                &mut client_handler,
+                &["SCAN", &cursor, "MATCH", "user:*", "COUNT", "5"],
+            )
+            .await;
+            let Value::Array(parts) = reply else {
+                panic!("expected an array reply");
+            };
+            let [Value::BulkString(next), Value::Array(keys)] = &parts[..] else {
+                panic!("unexpected SCAN reply {:?}", parts);
+            };
+            users.extend(keys.iter().cloned());
+            calls += 1;
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        assert!(calls >= 10);
+        users.sort_by_key(|key| format!("{:?}", key));
+        users.dedup();
+        assert_eq!(users.len(), 25);
+    }
+
+    #[tokio::test]
+    async fn test_scan_command_errors() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert_eq!(
+            send(&mut client_handler, &["SCAN", "abc"]).await,
+            Value::SimpleError("ERR invalid cursor".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SCAN", "0", "COUNT", "0"]).await,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SCAN", "0", "MATCH"]).await,
+            Value::SimpleError("ERR syntax error".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["SCAN", "0"]).await,
+            Value::Array(vec![Value::BulkString("0".into()), Value::Array(vec![])])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_string_commands_on_hash_reply_wrongtype() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["HSET", "user", "name", "ada"]).await;
+        for parts in [
+            &["GET", "user"][..],
+            &["INCR", "user"],
+            &["APPEND", "user", "x"],
+            &["STRLEN", "user"],
+            &["GETRANGE", "user", "0", "1"],
+            &["SETRANGE", "user", "0", "x"],
+            &["SET", "user", "value", "GET"],
+        ] {
+            assert_eq!(
+                send(&mut client_handler, parts).await,
+                wrong_type(),
+                "{:?}",
+                parts
+            );
+        }
+
+        // MGET reports other types as missing and a plain SET overwrites them
+        assert_eq!(
+            send(&mut client_handler, &["MGET", "user"]).await,
+            Value::Array(vec![Value::Null])
+        );
+        send(&mut client_handler, &["SET", "user", "value"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["GET", "user"]).await,
+            Value::BulkString("value".into())
+        );
+    }
+
+    fn frame(parts: &[Value]) -> Value {
+        Value::Array(parts.to_vec())
+    }
+
+    fn bulk(text: &str) -> Value {
+        Value::BulkString(Bytes::copy_from_slice(text.as_bytes()))
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_publish() {
+        let (addr, _) = spawn_server().await;
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut publisher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        subscriber
+            .write_value(&command(&["SUBSCRIBE", "news", "sports"]))
+            .await
+            .unwrap();
+        for (channel, count) in [("news", 1), ("sports", 2)] {
+            assert_eq!(
+                subscriber.read_value().await.unwrap().unwrap(),
+                frame(&[bulk("subscribe"), bulk(channel), Value::Integer(count)])
+            );
+        }
+
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "news", "hello"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            subscriber.read_value().await.unwrap().unwrap(),
+            frame(&[bulk("message"), bulk("news"), bulk("hello")])
+        );
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "weather", "rain"]).await,
+            Value::Integer(0)
+        );
+
+        // Regular commands are refused while subscribed, PING still works
+        assert!(matches!(
+            send(&mut subscriber, &["GET", "key"]).await,
+            Value::SimpleError(_)
+        ));
+        assert_eq!(
+            send(&mut subscriber, &["PING"]).await,
+            frame(&[bulk("pong"), bulk("")])
+        );
+        assert_eq!(
+            send(&mut subscriber, &["PING", "hi"]).await,
+            frame(&[bulk("pong"), bulk("hi")])
+        );
+
+        subscriber
+            .write_value(&command(&["UNSUBSCRIBE"]))
+            .await
+            .unwrap();
+        let mut remaining = Vec::new();
+        for _ in 0..2 {
+            let Value::Array(parts) = subscriber.read_value().await.unwrap().unwrap() else {
+                panic!("expected an unsubscribe frame");
+            };
+            remaining.push(parts[2].clone());
+        }
+        remaining.sort_by_key(|count| format!("{:?}", count));
+        assert_eq!(remaining, vec![Value::Integer(0), Value::Integer(1)]);
+        assert_eq!(
+            send(&mut subscriber, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_psubscribe_with_exact_subscription() {
+        let (addr, _) = spawn_server().await;
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut publisher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut subscriber, &["SUBSCRIBE", "news.tech"]).await,
+            frame(&[bulk("subscribe"), bulk("news.tech"), Value::Integer(1)])
+        );
+        assert_eq!(
+            send(&mut subscriber, &["PSUBSCRIBE", "news.*"]).await,
+            frame(&[bulk("psubscribe"), bulk("news.*"), Value::Integer(2)])
+        );
+
+        // Both the exact and the pattern subscription receive the message
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "news.tech", "rust"]).await,
+            Value::Integer(2)
+        );
+        assert_eq!(
+            subscriber.read_value().await.unwrap().unwrap(),
+            frame(&[bulk("message"), bulk("news.tech"), bulk("rust")])
+        );
+        assert_eq!(
+            subscriber.read_value().await.unwrap().unwrap(),
+            frame(&[
+                bulk("pmessage"),
+                bulk("news.*"),
+                bulk("news.tech"),
+                bulk("rust")
+            ])
+        );
+
+        assert_eq!(
+            send(&mut subscriber, &["PUNSUBSCRIBE"]).await,
+            frame(&[bulk("punsubscribe"), bulk("news.*"), Value::Integer(1)])
+        );
+        // Still in subscribe mode thanks to the exact subscription
+        assert!(matches!(
+            send(&mut subscriber, &["GET", "key"]).await,
+            Value::SimpleError(_)
+        ));
+        assert_eq!(
+            send(&mut subscriber, &["PUNSUBSCRIBE"]).await,
+            frame(&[bulk("punsubscribe"), Value::Null, Value::Integer(1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multi_exec() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        let ok = Value::SimpleString("OK".to_owned());
+        let queued = Value::SimpleString("QUEUED".to_owned());
+        assert_eq!(send(&mut client_handler, &["MULTI"]).await, ok);
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            queued
+        );
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            queued
+        );
+        assert_eq!(send(&mut client_handler, &["GET", "counter"]).await, queued);
+        assert_eq!(
+            send(&mut client_handler, &["EXEC"]).await,
+            frame(&[Value::Integer(1), Value::Integer(2), bulk("2")])
+        );
+
+        // DISCARD drops the queue
+        assert_eq!(send(&mut client_handler, &["MULTI"]).await, ok);
+        assert_eq!(
+            send(&mut client_handler, &["INCR", "counter"]).await,
+            queued
+        );
+        assert_eq!(send(&mut client_handler, &["DISCARD"]).await, ok);
+        assert_eq!(
+            send(&mut client_handler, &["GET", "counter"]).await,
+            bulk("2")
+        );
+
+        assert!(matches!(
+            send(&mut client_handler, &["EXEC"]).await,
+            Value::SimpleError(message) if message == "ERR EXEC without MULTI"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_exec_aborts_after_queuing_error() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["MULTI"]).await;
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert!(matches!(
+            send(&mut client_handler, &["NOSUCHCOMMAND"]).await,
+            Value::SimpleError(_)
+        ));
+        assert!(matches!(
+            send(&mut client_handler, &["EXEC"]).await,
+            Value::SimpleError(message) if message.starts_with("EXECABORT")
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_aborts_exec_when_key_changes() {
+        let (addr, _) = spawn_server().await;
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut client, &["SET", "key", "1"]).await;
+        assert_eq!(
+            send(&mut client, &["WATCH", "key"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        send(&mut client, &["MULTI"]).await;
+        send(&mut client, &["SET", "key", "from-transaction"]).await;
+
+        // Another connection modifies the watched key before EXEC
+        send(&mut other, &["SET", "key", "from-other"]).await;
+
+        assert_eq!(send(&mut client, &["EXEC"]).await, Value::NullArray);
+        assert_eq!(send(&mut client, &["GET", "key"]).await, bulk("from-other"));
+
+        // EXEC released the watch, so the next transaction goes through
+        send(&mut client, &["MULTI"]).await;
+        send(&mut client, &["GET", "key"]).await;
+        assert_eq!(
+            send(&mut client, &["EXEC"]).await,
+            frame(&[bulk("from-other")])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_untouched_and_unwatch() {
+        let (addr, _) = spawn_server().await;
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        // Watching a missing key that nobody touches lets EXEC run
+        send(&mut client, &["WATCH", "missing"]).await;
+        send(&mut other, &["SET", "unrelated", "value"]).await;
+        send(&mut client, &["MULTI"]).await;
+        send(&mut client, &["SET", "missing", "now-set"]).await;
+        assert_eq!(
+            send(&mut client, &["EXEC"]).await,
+            frame(&[Value::SimpleString("OK".to_owned())])
+        );
+
+        // Nor does a DEL of it that finds nothing to delete
+        send(&mut client, &["WATCH", "absent"]).await;
+        assert_eq!(
+            send(&mut other, &["DEL", "absent"]).await,
+            Value::Integer(0)
+        );
+        send(&mut client, &["MULTI"]).await;
+        send(&mut client, &["GET", "absent"]).await;
+        assert_eq!(send(&mut client, &["EXEC"]).await, frame(&[Value::Null]));
+
+        // After UNWATCH a concurrent write no longer aborts the transaction
+        send(&mut client, &["WATCH", "missing"]).await;
+        assert_eq!(
+            send(&mut client, &["UNWATCH"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        send(&mut other, &["DEL", "missing"]).await;
+        send(&mut client, &["MULTI"]).await;
+        assert!(matches!(
+            send(&mut client, &["WATCH", "missing"]).await,
+            Value::SimpleError(_)
+        ));
+        send(&mut client, &["GET", "missing"]).await;
+        assert_eq!(send(&mut client, &["EXEC"]).await, frame(&[Value::Null]));
+    }
+
+    #[tokio::test]
+    async fn test_auth_required() {
+        let config = Config {
+            requirepass: Some("secret".to_owned()),
+            ..Config::new()
+        };
+        let (addr, _) = spawn_server_with_config(config).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::SimpleError("NOAUTH Authentication required.".to_owned())
+        );
+        assert!(matches!(
+            send(&mut client_handler, &["AUTH", "wrong"]).await,
+            Value::SimpleError(message) if message.starts_with("WRONGPASS")
+        ));
+        assert!(matches!(
+            send(&mut client_handler, &["AUTH", "someone", "secret"]).await,
+            Value::SimpleError(message) if message.starts_with("WRONGPASS")
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["AUTH", "default", "secret"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::Null
+        );
+
+        // Authentication is per connection
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert!(matches!(
+            send(&mut other, &["PING"]).await,
+            Value::SimpleError(message) if message.starts_with("NOAUTH")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_auth_without_password() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        assert!(matches!(
+            send(&mut client_handler, &["AUTH", "secret"]).await,
+            Value::SimpleError(message) if message.starts_with("ERR AUTH")
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+    }
+
+    // The value of one field of a HELLO reply
+    fn hello_field(reply: &Value, name: &str) -> Value {
+        let Value::Map(fields) = reply else {
+            panic!("expected a map reply, got {:?}", reply);
+        };
+        fields
+            .iter()
+            .find(|(field, _)| *field == bulk(name))
+            .map(|(_, value)| value.clone())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hello_switches_protocol() {
+        let (addr, _) = spawn_server().await;
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut client, &["HELLO", "4"]).await,
+            Value::SimpleError("NOPROTO unsupported protocol version".to_owned())
+        );
+        assert!(matches!(
+            send(&mut client, &["HELLO", "3", "SETNAME"]).await,
+            Value::SimpleError(message) if message.starts_with("ERR Syntax error")
+        ));
+
+        let reply = send(&mut client, &["HELLO", "3", "SETNAME", "app"]).await;
+        assert_eq!(hello_field(&reply, "proto"), Value::Integer(3));
+        assert_eq!(hello_field(&reply, "role"), bulk("master"));
+        assert_eq!(send(&mut client, &["CLIENT", "GETNAME"]).await, bulk("app"));
+
+        // Replies use the RESP3 types from now on
+        send(&mut client, &["HSET", "user", "name", "ada"]).await;
+        assert_eq!(
+            send(&mut client, &["HGETALL", "user"]).await,
+            Value::Map(vec![(bulk("name"), bulk("ada"))])
+        );
+        send(&mut client, &["ZADD", "board", "1.5", "one"]).await;
+        assert_eq!(
+            send(&mut client, &["ZSCORE", "board", "one"]).await,
+            Value::Double(1.5)
+        );
+        assert_eq!(send(&mut client, &["GET", "missing"]).await, Value::Null);
+
+        // And back, with maps flattened again
+        let Value::Array(fields) = send(&mut client, &["HELLO", "2"]).await else {
+            panic!("expected a flat array reply");
+        };
+        assert_eq!(fields[4..6], [bulk("proto"), Value::Integer(2)]);
+        assert_eq!(
+            send(&mut client, &["HGETALL", "user"]).await,
+            frame(&[bulk("name"), bulk("ada")])
+        );
+        assert_eq!(
+            send(&mut client, &["ZSCORE", "board", "one"]).await,
+            bulk("1.5")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hello_authenticates() {
+        let config = Config {
+            requirepass: Some("secret".to_owned()),
+            ..Config::new()
+        };
+        let (addr, _) = spawn_server_with_config(config).await;
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert!(matches!(
+            send(&mut client, &["HELLO", "3"]).await,
+            Value::SimpleError(message) if message.starts_with("NOAUTH")
+        ));
+        assert!(matches!(
+            send(&mut client, &["HELLO", "3", "AUTH", "default", "wrong"]).await,
+            Value::SimpleError(message) if message.starts_with("WRONGPASS")
+        ));
+        let reply = send(&mut client, &["HELLO", "3", "AUTH", "default", "secret"]).await;
+        assert_eq!(hello_field(&reply, "proto"), Value::Integer(3));
+        assert_eq!(send(&mut client, &["GET", "key"]).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_resp3_subscriber_gets_push_frames() {
+        let (addr, _) = spawn_server().await;
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut publisher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut subscriber, &["HELLO", "3"]).await;
+        assert_eq!(
+            send(&mut subscriber, &["SUBSCRIBE", "news"]).await,
+            Value::Push(vec![bulk("subscribe"), bulk("news"), Value::Integer(1)])
+        );
+        // Other commands keep working while subscribed
+        assert_eq!(
+            send(&mut subscriber, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+        send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+        assert_eq!(
+            subscriber.read_value().await.unwrap().unwrap(),
+            Value::Push(vec![bulk("message"), bulk("news"), bulk("hello")])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_set_requirepass() {
+        let (addr, _) = spawn_server().await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["CONFIG", "SET", "requirepass", "secret"]
+            )
+            .await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(&mut client_handler, &["CONFIG", "GET", "requirepass"]).await,
+            frame(&[bulk("requirepass"), bulk("secret")])
+        );
+
+        // New connections now have to authenticate
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        assert!(matches!(
+            send(&mut other, &["CONFIG", "GET", "*"]).await,
+            Value::SimpleError(message) if message.starts_with("NOAUTH")
+        ));
+        assert_eq!(
+            send(&mut other, &["AUTH", "secret"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_select_and_swapdb() {
+        let (addr, db_instance) = spawn_server().await;
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut other = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let ok = Value::SimpleString("OK".to_owned());
+
+        send(&mut client, &["SET", "key", "in-zero"]).await;
+        assert_eq!(send(&mut client, &["SELECT", "1"]).await, ok);
+        assert_eq!(send(&mut client, &["GET", "key"]).await, Value::Null);
+        send(&mut client, &["SET", "key", "in-one"]).await;
+
+        // The selected database is per connection
+        assert_eq!(send(&mut other, &["GET", "key"]).await, bulk("in-zero"));
+        assert!(matches!(
+            send(&mut other, &["SELECT", "16"]).await,
+            Value::SimpleError(message) if message == "ERR DB index is out of range"
+        ));
+
+        assert_eq!(send(&mut other, &["SWAPDB", "0", "1"]).await, ok);
+        assert_eq!(send(&mut other, &["GET", "key"]).await, bulk("in-one"));
+        assert_eq!(send(&mut client, &["GET", "key"]).await, bulk("in-zero"));
+        assert_eq!(
+            db_instance.read().await.get("key"),
+            Some(&DataType::String("in-one".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flushdb_and_flushall_target_databases() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "a", "1"]).await;
+        send(&mut client_handler, &["SELECT", "2"]).await;
+        send(&mut client_handler, &["SET", "b", "2"]).await;
+        send(&mut client_handler, &["SET", "c", "3"]).await;
+
+        // FLUSHDB only clears the selected database
+        send(&mut client_handler, &["FLUSHDB"]).await;
+        assert_eq!(send(&mut client_handler, &["KEYS", "*"]).await, frame(&[]));
+        send(&mut client_handler, &["SELECT", "0"]).await;
+        assert_eq!(send(&mut client_handler, &["GET", "a"]).await, bulk("1"));
+
+        send(&mut client_handler, &["SELECT", "2"]).await;
+        send(&mut client_handler, &["SET", "b", "2"]).await;
+        send(&mut client_handler, &["FLUSHALL"]).await;
+        assert_eq!(send(&mut client_handler, &["GET", "b"]).await, Value::Null);
+        send(&mut client_handler, &["SELECT", "0"]).await;
+        assert_eq!(send(&mut client_handler, &["GET", "a"]).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_select_inside_transaction() {
+        let (socket, db_instance) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["MULTI"]).await;
+        send(&mut client_handler, &["SELECT", "5"]).await;
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        send(&mut client_handler, &["EXEC"]).await;
+
+        // Commands after SELECT in the transaction ran against the new database
+        assert_eq!(db_instance.read().await.get("key"), None);
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            bulk("value")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aof_logs_successful_writes() {
+        let path = std::env::temp_dir().join(format!("redis-rust-conn-{}.aof", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let mut state = ServerState::new(Config::new());
+        state.aof = Some(Arc::new(
+            Aof::open(&path, AppendFsync::Always).await.unwrap(),
+        ));
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut client_handler, &["SET", "counter", "10"]).await;
+        send(&mut client_handler, &["GET", "counter"]).await;
+        send(&mut client_handler, &["HSET", "counter", "field", "value"]).await;
+        send(&mut client_handler, &["MULTI"]).await;
+        send(&mut client_handler, &["INCRBY", "counter", "5"]).await;
+        send(&mut client_handler, &["SELECT", "3"]).await;
+        send(&mut client_handler, &["SET", "other", "db"]).await;
+        send(&mut client_handler, &["EXEC"]).await;
+
+        // Reads and failed writes are left out of the log
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(!contents.contains("GET"));
+        assert!(!contents.contains("HSET"));
+
+        let restored = ServerState::new(Config::new());
+        assert_eq!(aof::replay(&path, &restored).await.unwrap(), 5);
+        assert_eq!(
+            restored.databases[0].read().await.get("counter"),
+            Some(&DataType::String("15".into()))
+        );
+        assert_eq!(
+            restored.databases[3].read().await.get("other"),
+            Some(&DataType::String("db".into()))
+        );
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maxmemory_evicts_and_refuses_writes() {
+        let path =
+            std::env::temp_dir().join(format!("redis-rust-eviction-{}.aof", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let mut state = ServerState::new(Config {
+            maxmemory: 1000,
+            maxmemory_policy: MaxMemoryPolicy::AllKeysLru,
+            ..Config::new()
+        });
+        state.aof = Some(Arc::new(
+            Aof::open(&path, AppendFsync::Always).await.unwrap(),
+        ));
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        let value = "v".repeat(100);
+        for index in 0..20 {
+            let key = format!("key{}", index);
+            send(&mut client_handler, &["SET", &key, &value]).await;
+        }
+        let Value::Integer(keys) = send(&mut client_handler, &["DBSIZE"]).await else {
+            panic!("DBSIZE replies with an integer");
+        };
+        assert!(keys < 20);
+        let Value::BulkString(info) = send(&mut client_handler, &["INFO", "stats"]).await else {
+            panic!("INFO replies with a bulk string");
+        };
+        assert!(String::from_utf8_lossy(&info).contains("evicted_keys:"));
+        assert!(!String::from_utf8_lossy(&info).contains("evicted_keys:0\r\n"));
+
+        // Evictions are logged, so replaying the file ends with the same keys
+        let restored = ServerState::new(Config::new());
+        aof::replay(&path, &restored).await.unwrap();
+        assert_eq!(restored.databases[0].read().await.len() as i64, keys);
+
+        // Without a policy to make room, writes that add data are refused
+        send(
+            &mut client_handler,
+            &[
+                "CONFIG",
+                "SET",
+                "maxmemory-policy",
+                "noeviction",
+                "maxmemory",
+                "100",
+            ],
+        )
+        .await;
+        assert_eq!(
+            send(&mut client_handler, &["SET", "another", &value]).await,
+            Value::SimpleError(
+                "OOM command not allowed when used memory > 'maxmemory'.".to_owned()
+            )
+        );
+        let Value::Integer(1) = send(&mut client_handler, &["DEL", "key19"]).await else {
+            panic!("DEL is allowed and the newest key is still there");
+        };
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maxmemory_counts_values_grown_in_place() {
+        let clock = Arc::new(MockClock::starting_now());
+        let config = Config {
+            maxmemory: 2000,
+            maxmemory_policy: MaxMemoryPolicy::AllKeysLru,
+            // Enough samples that both keys are all but certain to be among them
+            maxmemory_samples: 64,
+            ..Config::new()
+        };
+        let state = ServerState::with_clock(config, clock.clone());
+        let mut selected = 0;
+        let item = bulk(&"v".repeat(100));
+        for list in ["first", "second"] {
+            // Each list on its own fits, both together do not
+            clock.advance(Duration::from_secs(1));
+            for _ in 0..15 {
+                run_command(
+                    UserCommand::RPush,
+                    &[bulk(list), item.clone()],
+                    &state,
+                    &mut selected,
+                    None,
+                )
+                .await
+                .unwrap();
+            }
+        }
+        // The lists only ever grew in place, yet the older one made room
+        let stats = state.databases[0].stats();
+        assert_eq!(stats.evicted_keys.load(Ordering::Relaxed), 1);
+        assert!(state.databases[0].read().await.get("first").is_none());
+        assert!(state.databases[0].used_memory() <= 2000);
+    }
+
+    #[tokio::test]
+    async fn test_pexpireat() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        let deadline = (SystemClock.now_millis() + 60_000).to_string();
+        assert_eq!(
+            send(&mut client_handler, &["PEXPIREAT", "key", &deadline]).await,
+            Value::Integer(1)
+        );
+        assert!(matches!(
+            send(&mut client_handler, &["PTTL", "key"]).await,
+            Value::Integer(ttl) if ttl > 59_000 && ttl <= 60_000
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["PEXPIREAT", "missing", &deadline]).await,
+            Value::Integer(0)
+        );
+
+        // A deadline in the past removes the key straight away
+        assert_eq!(
+            send(&mut client_handler, &["PEXPIREAT", "key", "1000"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["GET", "key"]).await,
+            Value::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expireat_and_persist() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        let deadline = (SystemClock.now_millis() / 1000 + 100).to_string();
+        assert_eq!(
+            send(&mut client_handler, &["EXPIREAT", "key", &deadline]).await,
+            Value::Integer(1)
+        );
+        assert!(matches!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(ttl) if ttl > 98 && ttl <= 100
+        ));
+
+        assert_eq!(
+            send(&mut client_handler, &["PERSIST", "key"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["TTL", "key"]).await,
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PERSIST", "key"]).await,
+            Value::Integer(0)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["PERSIST", "missing"]).await,
+            Value::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expire_options() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+
+        // A key without a TTL counts as never expiring
+        for (options, expected) in [(&["XX"][..], 0), (&["GT"], 0), (&["NX"], 1), (&["NX"], 0)] {
+            let mut parts = vec!["EXPIRE", "key", "100"];
+            parts.extend_from_slice(options);
+            assert_eq!(
+                send(&mut client_handler, &parts).await,
+                Value::Integer(expected),
+                "{:?}",
+                options
+            );
+        }
+        for (seconds, option, expected, ttl) in [
+            ("50", "GT", 0, 100),
+            ("200", "GT", 1, 200),
+            ("300", "LT", 0, 200),
+            ("150", "LT", 1, 150),
+            ("120", "XX", 1, 120),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &["EXPIRE", "key", seconds, option]).await,
+                Value::Integer(expected),
+                "{} {}",
+                option,
+                seconds
+            );
+            assert_eq!(
+                send(&mut client_handler, &["TTL", "key"]).await,
+                Value::Integer(ttl)
+            );
+        }
+
+        send(&mut client_handler, &["PERSIST", "key"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["EXPIRE", "key", "100", "LT"]).await,
+            Value::Integer(1)
+        );
+
+        assert_eq!(
+            send(&mut client_handler, &["EXPIRE", "key", "100", "NX", "GT"]).await,
+            Value::SimpleError(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".to_owned()
+            )
+        );
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["PEXPIREAT", "key", "100", "GT", "LT"]
+            )
+            .await,
+            Value::SimpleError(
+                "ERR GT and LT options at the same time are not compatible".to_owned()
+            )
+        );
+        assert_eq!(
+            send(&mut client_handler, &["EXPIREAT", "key", "100", "SOON"]).await,
+            Value::SimpleError("ERR Unsupported option SOON".to_owned())
+        );
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["EXPIRE", "key", "9223372036854775807"]
+            )
+            .await,
+            Value::SimpleError("ERR invalid expire time in 'expire' command".to_owned())
+        );
+    }
+
+    // Polls GET on a server until it returns the expected value, failing after a second
+    async fn wait_for_value(client_handler: &mut RespHandler, key: &str, expected: Value) {
+        for _ in 0..100 {
+            if send(client_handler, &["GET", key]).await == expected {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("{} never became {:?}", key, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replication() {
+        let (master_addr, _) = spawn_server().await;
+        let (replica_addr, _) = spawn_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut replica = RespHandler::new(TcpStream::connect(replica_addr).await.unwrap());
+
+        send(&mut master, &["SET", "before", "snapshot"]).await;
+        send(&mut replica, &["SET", "stale", "value"]).await;
+        let port = master_addr.port().to_string();
+        assert_eq!(
+            send(&mut replica, &["SLAVEOF", "127.0.0.1", &port]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+
+        // The full synchronization replaces the replica's own data
+        wait_for_value(&mut replica, "before", Value::BulkString("snapshot".into())).await;
+        assert_eq!(send(&mut replica, &["GET", "stale"]).await, Value::Null);
+
+        // Later writes are streamed, including the database they were made in
+        send(&mut master, &["SET", "after", "stream"]).await;
+        send(&mut master, &["SELECT", "2"]).await;
+        send(&mut master, &["INCR", "counter"]).await;
+        send(&mut replica, &["SELECT", "2"]).await;
+        wait_for_value(&mut replica, "counter", Value::BulkString("1".into())).await;
+        send(&mut replica, &["SELECT", "0"]).await;
+        assert_eq!(
+            send(&mut replica, &["GET", "after"]).await,
+            Value::BulkString("stream".into())
+        );
+
+        // Once promoted the replica stops following the master
+        assert_eq!(
+            send(&mut replica, &["REPLICAOF", "NO", "ONE"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        send(&mut master, &["SELECT", "0"]).await;
+        send(&mut master, &["SET", "after", "ignored"]).await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(
+            send(&mut replica, &["GET", "after"]).await,
+            Value::BulkString("stream".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_replicas() {
+        let (master_addr, _) = spawn_server().await;
+        let (replica_addr, _) = spawn_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut replica = RespHandler::new(TcpStream::connect(replica_addr).await.unwrap());
+
+        let port = master_addr.port().to_string();
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut master, &["SET", "key", "value"]).await;
+        wait_for_value(&mut replica, "key", Value::BulkString("value".into())).await;
+
+        // The replica acknowledges the write as soon as the master asks
+        send(&mut master, &["INCR", "counter"]).await;
+        assert_eq!(
+            send(&mut master, &["WAIT", "1", "0"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut replica, &["GET", "counter"]).await,
+            Value::BulkString("1".into())
+        );
+
+        // Asking for more replicas than exist waits out the timeout
+        let started = std::time::Instant::now();
+        assert_eq!(
+            send(&mut master, &["WAIT", "2", "50"]).await,
+            Value::Integer(1)
+        );
+        assert!(started.elapsed() >= std::time::Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_partial_resynchronization() {
+        let (master_addr, _) = spawn_server().await;
+        let (replica_addr, _) = spawn_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut replica = RespHandler::new(TcpStream::connect(replica_addr).await.unwrap());
+
+        let port = master_addr.port().to_string();
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut master, &["SELECT", "3"]).await;
+        send(&mut master, &["SET", "first", "1"]).await;
+        send(&mut replica, &["SELECT", "3"]).await;
+        wait_for_value(&mut replica, "first", Value::BulkString("1".into())).await;
+
+        // A key of the replica's own, which a full synchronization would wipe out
+        send(&mut replica, &["CONFIG", "SET", "replica-read-only", "no"]).await;
+        send(&mut replica, &["SET", "local", "kept"]).await;
+
+        // The same master under another name: a new link that continues the stream,
+        // still in the database it selected
+        send(&mut replica, &["REPLICAOF", "localhost", &port]).await;
+        send(&mut master, &["SET", "second", "2"]).await;
+        wait_for_value(&mut replica, "second", Value::BulkString("2".into())).await;
+        assert_eq!(
+            send(&mut replica, &["GET", "local"]).await,
+            Value::BulkString("kept".into())
+        );
+
+        let Value::BulkString(info) = send(&mut master, &["INFO", "replication"]).await else {
+            panic!("INFO must reply with a bulk string");
+        };
+        let info = String::from_utf8(info.to_vec()).unwrap();
+        assert!(info.contains("role:master\r\nconnected_slaves:1\r\n"));
+        assert!(info.contains("repl_backlog_active:1\r\n"));
+        let Value::BulkString(info) = send(&mut replica, &["INFO", "replication"]).await else {
+            panic!("INFO must reply with a bulk string");
+        };
+        let info = String::from_utf8(info.to_vec()).unwrap();
+        assert!(info.contains(&format!(
+            "role:slave\r\nmaster_host:localhost\r\nmaster_port:{}\r\n",
+            port
+        )));
+    }
+
+    // The value of a field of INFO replication
+    async fn replication_info(client_handler: &mut RespHandler, field: &str) -> String {
+        let Value::BulkString(info) = send(client_handler, &["INFO", "replication"]).await else {
+            panic!("INFO must reply with a bulk string");
+        };
+        let info = String::from_utf8(info.to_vec()).unwrap();
+        info.lines()
+            .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+            .unwrap_or_else(|| panic!("no {} in {}", field, info))
+            .to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_promotion_keeps_the_stream() {
+        let (master_addr, _) = spawn_server().await;
+        let (promoted_addr, _) = spawn_server().await;
+        let (other_addr, _) = spawn_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut promoted = RespHandler::new(TcpStream::connect(promoted_addr).await.unwrap());
+        let mut other = RespHandler::new(TcpStream::connect(other_addr).await.unwrap());
+
+        let port = master_addr.port().to_string();
+        send(&mut promoted, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut other, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut master, &["SET", "key", "value"]).await;
+        wait_for_value(&mut promoted, "key", Value::BulkString("value".into())).await;
+        wait_for_value(&mut other, "key", Value::BulkString("value".into())).await;
+
+        // The promoted replica gets a new replication id and keeps the master's
+        let master_replid = replication_info(&mut master, "master_replid").await;
+        send(&mut promoted, &["REPLICAOF", "NO", "ONE"]).await;
+        assert_eq!(replication_info(&mut promoted, "role").await, "master");
+        assert_ne!(
+            replication_info(&mut promoted, "master_replid").await,
+            master_replid
+        );
+        assert_eq!(
+            replication_info(&mut promoted, "master_replid2").await,
+            master_replid
+        );
+        assert_eq!(
+            replication_info(&mut promoted, "master_repl_offset").await,
+            replication_info(&mut master, "master_repl_offset").await
+        );
+
+        // The other replica moves over to it without a full synchronization, which
+        // would wipe out a key of its own
+        send(&mut other, &["CONFIG", "SET", "replica-read-only", "no"]).await;
+        send(&mut other, &["SET", "local", "kept"]).await;
+        let port = promoted_addr.port().to_string();
+        send(&mut other, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut promoted, &["SET", "key", "new"]).await;
+        wait_for_value(&mut other, "key", Value::BulkString("new".into())).await;
+        assert_eq!(
+            send(&mut other, &["GET", "local"]).await,
+            Value::BulkString("kept".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let master_addr = spawn_announcing_server().await;
+        let replica_addr = spawn_announcing_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut replica = RespHandler::new(TcpStream::connect(replica_addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut master, &["FAILOVER"]).await,
+            Value::SimpleError("ERR FAILOVER requires connected replicas.".to_owned())
+        );
+        // A timeout past any instant means no deadline
+        assert_eq!(
+            send(&mut master, &["FAILOVER", "TIMEOUT", "9223372036854775807"]).await,
+            Value::SimpleError("ERR FAILOVER requires connected replicas.".to_owned())
+        );
+        let port = master_addr.port().to_string();
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut master, &["SET", "key", "value"]).await;
+        wait_for_value(&mut replica, "key", Value::BulkString("value".into())).await;
+        assert_eq!(
+            send(&mut master, &["FAILOVER", "TO", "127.0.0.1", "1"]).await,
+            Value::SimpleError("ERR FAILOVER target HOST and PORT is not a replica.".to_owned())
+        );
+        assert_eq!(
+            send(&mut replica, &["FAILOVER"]).await,
+            Value::SimpleError("ERR FAILOVER is not valid when server is a replica.".to_owned())
+        );
+
+        // The roles swap, and writes now flow from the former replica
+        let replica_port = replica_addr.port().to_string();
+        assert_eq!(
+            send(
+                &mut master,
+                &[
+                    "FAILOVER",
+                    "TO",
+                    "127.0.0.1",
+                    &replica_port,
+                    "TIMEOUT",
+                    "1000"
+                ]
+            )
+            .await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(replication_info(&mut replica, "role").await, "master");
+        assert_eq!(replication_info(&mut master, "role").await, "slave");
+        assert_eq!(
+            replication_info(&mut master, "master_port").await,
+            replica_port
+        );
+        send(&mut replica, &["SET", "key", "after"]).await;
+        wait_for_value(&mut master, "key", Value::BulkString("after".into())).await;
+        assert!(matches!(
+            send(&mut master, &["SET", "key", "refused"]).await,
+            Value::SimpleError(err) if err.starts_with("READONLY")
+        ));
+    }
+
+    async fn wait_for_link_status(client_handler: &mut RespHandler, expected: &str) {
+        for _ in 0..300 {
+            if replication_info(client_handler, "master_link_status").await == expected {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("the master link never went {}", expected);
+    }
+
+    #[tokio::test]
+    async fn test_master_link_reconnects() {
+        // A master that synchronizes the replica and then falls silent
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let (addr, _) = spawn_server().await;
+        let mut replica = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        send(&mut replica, &["CONFIG", "SET", "repl-timeout", "1"]).await;
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+        assert_eq!(
+            replication_info(&mut replica, "master_link_status").await,
+            "down"
+        );
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut master = RespHandler::new(socket);
+        for reply in ["PONG", "OK", "OK"] {
+            master.read_value().await.unwrap();
+            master
+                .write_value(&Value::SimpleString(reply.to_owned()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            master.read_value().await.unwrap(),
+            Some(command(&["PSYNC", "?", "-1"]))
+        );
+        let replid = "0123456789012345678901234567890123456789";
+        let payload = crate::persistence::rdb::encode(&Vec::new());
+        let header = format!("+FULLRESYNC {} 0\r\n${}\r\n", replid, payload.len());
+        master.socket.write_all(header.as_bytes()).await.unwrap();
+        master.socket.write_all(&payload).await.unwrap();
+        wait_for_link_status(&mut replica, "up").await;
+        assert_eq!(
+            replication_info(&mut replica, "master_last_io_seconds_ago").await,
+            "0"
+        );
+
+        // Only acknowledgements come back, until the replica gives up on the link and
+        // makes a new one, asking to continue where it was
+        wait_for_link_status(&mut replica, "down").await;
+        assert_eq!(
+            replication_info(&mut replica, "master_last_io_seconds_ago").await,
+            "-1"
+        );
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut master = RespHandler::new(socket);
+        for reply in ["PONG", "OK", "OK"] {
+            master.read_value().await.unwrap();
+            master
+                .write_value(&Value::SimpleString(reply.to_owned()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            master.read_value().await.unwrap(),
+            Some(command(&["PSYNC", replid, "1"]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_read_only() {
+        let (master_addr, _) = spawn_server().await;
+        let (replica_addr, _) = spawn_server().await;
+        let mut master = RespHandler::new(TcpStream::connect(master_addr).await.unwrap());
+        let mut replica = RespHandler::new(TcpStream::connect(replica_addr).await.unwrap());
+
+        let port = master_addr.port().to_string();
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+        send(&mut master, &["SET", "key", "value"]).await;
+        wait_for_value(&mut replica, "key", Value::BulkString("value".into())).await;
+
+        // Clients may only read, inside a transaction too
+        let readonly =
+            Value::SimpleError("READONLY You can't write against a read only replica.".to_owned());
+        assert_eq!(send(&mut replica, &["SET", "key", "other"]).await, readonly);
+        send(&mut replica, &["MULTI"]).await;
+        assert_eq!(send(&mut replica, &["DEL", "key"]).await, readonly);
+        assert!(matches!(
+            send(&mut replica, &["EXEC"]).await,
+            Value::SimpleError(err) if err.starts_with("EXECABORT")
+        ));
+
+        send(&mut replica, &["CONFIG", "SET", "replica-read-only", "no"]).await;
+        send(&mut replica, &["SET", "local", "write"]).await;
+        assert_eq!(
+            send(&mut replica, &["GET", "local"]).await,
+            Value::BulkString("write".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_serve_stale_data() {
+        // A port nothing listens on, so the link to the master never comes up
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        drop(listener);
+        let (addr, _) = spawn_server().await;
+        let mut replica = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await;
+
+        // Stale data is served by default
+        assert_eq!(send(&mut replica, &["GET", "key"]).await, Value::Null);
+
+        send(
+            &mut replica,
+            &["CONFIG", "SET", "replica-serve-stale-data", "no"],
+        )
+        .await;
+        assert_eq!(
+            send(&mut replica, &["GET", "key"]).await,
+            Value::SimpleError(
+                "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'."
+                    .to_owned()
+            )
+        );
+        // A few commands still run, such as those looking into or ending the outage
+        assert_eq!(
+            send(&mut replica, &["PING"]).await,
+            Value::SimpleString("PONG".to_owned())
+        );
+        assert!(matches!(
+            send(&mut replica, &["INFO", "replication"]).await,
+            Value::BulkString(_)
+        ));
+        send(&mut replica, &["REPLICAOF", "NO", "ONE"]).await;
+        assert_eq!(send(&mut replica, &["GET", "key"]).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_client_list_and_kill() {
+        let (addr, _) = spawn_server().await;
+        let mut first = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut second = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut first, &["CLIENT", "SETNAME", "first"]).await;
+        let Value::Integer(id) = send(&mut second, &["CLIENT", "ID"]).await else {
+            panic!("CLIENT ID must reply with an integer");
+        };
+        let Value::BulkString(list) = send(&mut first, &["CLIENT", "LIST"]).await else {
+            panic!("CLIENT LIST must reply with a bulk string");
+        };
+        let list = String::from_utf8(list.to_vec()).unwrap();
+        assert_eq!(list.lines().count(), 2);
+        assert!(list.contains("name=first"));
+        assert!(list.contains(&format!("id={} ", id)));
+
+        assert_eq!(
+            send(&mut first, &["CLIENT", "KILL", "ID", &id.to_string()]).await,
+            Value::Integer(1)
+        );
+        // The killed connection is closed by the server
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(1), second.read_value())
+            .await
+            .unwrap();
+        assert!(matches!(closed, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let (addr, _) = spawn_server().await;
+        let mut monitor = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut monitor, &["MONITOR"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        send(&mut client_handler, &["SELECT", "1"]).await;
+        send(&mut client_handler, &["GET", "key"]).await;
+        send(&mut client_handler, &["AUTH", "secret"]).await;
+        send(&mut client_handler, &["PING"]).await;
+
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            let Some(Value::SimpleString(line)) = monitor.read_value().await.unwrap() else {
+                panic!("monitor lines are simple strings");
+            };
+            lines.push(line.split_once(' ').unwrap().1.to_owned());
+        }
+        let client_addr = client_handler.socket.local_addr().unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                format!("[0 {}] \"select\" \"1\"", client_addr),
+                format!("[1 {}] \"get\" \"key\"", client_addr),
+                format!("[1 {}] \"ping\"", client_addr),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blpop_blocks_until_push() {
+        let (addr, _) = spawn_server().await;
+        let mut pusher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut first = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut second = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        first
+            .write_value(&command(&["BLPOP", "other", "list", "0"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        second
+            .write_value(&command(&["BRPOP", "list", "0"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        // Each waiter gets one element, the one that blocked first being served first
+        assert_eq!(
+            send(&mut pusher, &["RPUSH", "list", "a", "b"]).await,
+            Value::Integer(2)
+        );
+        let expected = |element: &str| {
+            Value::Array(vec![
+                Value::BulkString("list".into()),
+                Value::BulkString(element.to_owned().into()),
+            ])
+        };
+        assert_eq!(first.read_value().await.unwrap().unwrap(), expected("a"));
+        assert_eq!(second.read_value().await.unwrap().unwrap(), expected("b"));
+        assert_eq!(
+            send(&mut pusher, &["LLEN", "list"]).await,
+            Value::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blmove_blocks_until_push() {
+        let (addr, db) = spawn_server().await;
+        let mut pusher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut mover = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(
+                &mut mover,
+                &["BLMOVE", "source", "dest", "LEFT", "RIGHT", "0.05"]
+            )
+            .await,
+            Value::Null
+        );
+        mover
+            .write_value(&command(&[
+                "BLMOVE", "source", "dest", "RIGHT", "LEFT", "0",
+            ]))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        send(&mut pusher, &["RPUSH", "source", "a", "b"]).await;
+        assert_eq!(
+            mover.read_value().await.unwrap().unwrap(),
+            Value::BulkString("b".into())
+        );
+        assert_eq!(
+            send(&mut pusher, &["LRANGE", "dest", "0", "-1"]).await,
+            Value::Array(vec![Value::BulkString("b".into())])
+        );
+        assert!(db.read().await.get("source").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_bzpopmin_blocks_until_zadd() {
+        let (addr, db) = spawn_server().await;
+        let mut adder = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut popper = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            send(&mut popper, &["BZPOPMAX", "queue", "0.05"]).await,
+            Value::NullArray
+        );
+        popper
+            .write_value(&command(&["BZPOPMIN", "other", "queue", "0"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        send(&mut adder, &["ZADD", "queue", "5", "low", "9", "high"]).await;
+        assert_eq!(
+            popper.read_value().await.unwrap().unwrap(),
+            Value::Array(vec![
+                Value::BulkString("queue".into()),
+                Value::BulkString("low".into()),
+                Value::BulkString("5".into()),
+            ])
+        );
+        assert_eq!(
+            send(&mut adder, &["ZCARD", "queue"]).await,
+            Value::Integer(1)
+        );
+        assert!(db.read().await.get("other").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blpop_timeout() {
+        let (socket, _) = setup().await;
+        let mut client_handler = RespHandler::new(socket);
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            send(&mut client_handler, &["BLPOP", "list", "0.05"]).await,
+            Value::NullArray
+        );
+        assert!(started.elapsed() >= std::time::Duration::from_millis(50));
+
+        // An element already there is popped straight away
+        send(&mut client_handler, &["LPUSH", "list", "a"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["BLPOP", "list", "1"]).await,
+            Value::Array(vec![
+                Value::BulkString("list".into()),
+                Value::BulkString("a".into()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xread_block() {
+        let (addr, _) = spawn_server().await;
+        let mut writer = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut reader = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut writer, &["XADD", "stream", "1-1", "old", "entry"]).await;
+        reader
+            .write_value(&command(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"]))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        // Only the entry added after XREAD started is returned
+        send(&mut writer, &["XADD", "stream", "2-0", "new", "entry"]).await;
+        assert_eq!(
+            reader.read_value().await.unwrap().unwrap(),
+            Value::Array(vec![Value::Array(vec![
+                Value::BulkString("stream".into()),
+                Value::Array(vec![Value::Array(vec![
+                    Value::BulkString("2-0".into()),
+                    Value::Array(vec![
+                        Value::BulkString("new".into()),
+                        Value::BulkString("entry".into()),
+                    ]),
+                ])]),
+            ])])
+        );
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            send(
+                &mut reader,
+                &["XREAD", "BLOCK", "50", "STREAMS", "stream", "2-0"]
+            )
+            .await,
+            Value::NullArray
+        );
+        assert!(started.elapsed() >= std::time::Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_xadd_logs_generated_id() {
+        let path = std::env::temp_dir().join(format!("redis-rust-xadd-{}.aof", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let mut state = ServerState::new(Config::new());
+        state.aof = Some(Arc::new(
+            Aof::open(&path, AppendFsync::Always).await.unwrap(),
+        ));
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        let Value::BulkString(id) =
+            send(&mut client_handler, &["XADD", "stream", "*", "a", "1"]).await
+        else {
+            panic!("XADD replies with the new ID");
+        };
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(contents.contains(std::str::from_utf8(&id).unwrap()));
+        assert!(!contents.contains("$1\r\n*\r\n"));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spop_logs_srem() {
+        let path = std::env::temp_dir().join(format!("redis-rust-spop-{}.aof", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let mut state = ServerState::new(Config::new());
+        state.aof = Some(Arc::new(
+            Aof::open(&path, AppendFsync::Always).await.unwrap(),
+        ));
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut client_handler, &["SADD", "tags", "a", "b", "c"]).await;
+        let Value::BulkString(member) = send(&mut client_handler, &["SPOP", "tags"]).await else {
+            panic!("SPOP replies with the member");
+        };
+        let member = std::str::from_utf8(&member).unwrap().to_owned();
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(contents.ends_with(&format!(
+            "*3\r\n$4\r\nSREM\r\n$4\r\ntags\r\n$1\r\n{}\r\n",
+            member
+        )));
+        assert!(!contents.contains("SPOP"));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_relative_expiry_logs_absolute_time() {
+        let path = std::env::temp_dir().join(format!("redis-rust-pxat-{}.aof", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let mut state = ServerState::new(Config::new());
+        state.aof = Some(Arc::new(
+            Aof::open(&path, AppendFsync::Always).await.unwrap(),
+        ));
+        let (addr, db_instance) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(
+            &mut client_handler,
+            &["SET", "session", "token", "EX", "100"],
+        )
+        .await;
+        send(&mut client_handler, &["EXPIRE", "session", "200"]).await;
+        // Nothing to expire, so nothing to log
+        send(&mut client_handler, &["EXPIRE", "missing", "200"]).await;
+
+        let expires_at = db_instance
+            .read()
+            .await
+            .get_entry("session")
+            .unwrap()
+            .expires_at
+            .unwrap()
+            .to_string();
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(contents.contains("$5\r\ntoken\r\n$4\r\nPXAT\r\n"));
+        assert!(contents.ends_with(&format!(
+            "*3\r\n$9\r\nPEXPIREAT\r\n$7\r\nsession\r\n${}\r\n{}\r\n",
+            expires_at.len(),
+            expires_at
+        )));
+        assert!(!contents.contains("EXPIRE\r\n"));
+        assert!(!contents.contains("missing"));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_notifications() {
+        let (addr, _) = spawn_server().await;
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut subscriber, &["PSUBSCRIBE", "__key*__:*"]).await;
+        // Off by default
+        send(&mut client_handler, &["SET", "quiet", "value"]).await;
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["CONFIG", "SET", "notify-keyspace-events", "KEA"]
+            )
+            .await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["CONFIG", "GET", "notify-keyspace-events"]
+            )
+            .await,
+            Value::Array(vec![bulk("notify-keyspace-events"), bulk("AKE")])
+        );
+
+        send(&mut client_handler, &["LPUSH", "queue", "job"]).await;
+        // Writes that change nothing give no events
+        send(&mut client_handler, &["SREM", "queue-ids", "job"]).await;
+        send(&mut client_handler, &["DEL", "queue"]).await;
+        for (channel, message) in [
+            ("__keyspace@0__:queue", "lpush"),
+            ("__keyevent@0__:lpush", "queue"),
+            ("__keyspace@0__:queue", "del"),
+            ("__keyevent@0__:del", "queue"),
+        ] {
+            assert_eq!(
+                subscriber.read_value().await.unwrap().unwrap(),
+                frame(&[
+                    bulk("pmessage"),
+                    bulk("__key*__:*"),
+                    bulk(channel),
+                    bulk(message)
+                ])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_xreadgroup_block() {
+        let (addr, _) = spawn_server().await;
+        let mut writer = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut first = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut second = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(
+            &mut writer,
+            &["XGROUP", "CREATE", "stream", "group", "$", "MKSTREAM"],
+        )
+        .await;
+        for (client_handler, consumer) in [(&mut first, "alice"), (&mut second, "bob")] {
+            client_handler
+                .write_value(&command(&[
+                    "XREADGROUP",
+                    "GROUP",
+                    "group",
+                    consumer,
+                    "BLOCK",
+                    "0",
+                    "STREAMS",
+                    "stream",
+                    ">",
+                ]))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        // Each entry wakes one of the consumers
+        send(&mut writer, &["XADD", "stream", "1-1", "a", "1"]).await;
+        send(&mut writer, &["XADD", "stream", "1-2", "b", "2"]).await;
+        first.read_value().await.unwrap().unwrap();
+        second.read_value().await.unwrap().unwrap();
+        assert_eq!(
+            send(&mut writer, &["XPENDING", "stream", "group"]).await,
+            Value::Array(vec![
+                Value::Integer(2),
+                Value::BulkString("1-1".into()),
+                Value::BulkString("1-2".into()),
+                Value::Array(vec![
+                    Value::Array(vec![
+                        Value::BulkString("alice".into()),
+                        Value::BulkString("1".into()),
+                    ]),
+                    Value::Array(vec![
+                        Value::BulkString("bob".into()),
+                        Value::BulkString("1".into()),
+                    ]),
+                ]),
+            ])
+        );
+
+        assert_eq!(
+            send(
+                &mut first,
+                &[
+                    "XREADGROUP",
+                    "GROUP",
+                    "group",
+                    "alice",
+                    "BLOCK",
+                    "20",
+                    "STREAMS",
+                    "stream",
+                    ">"
+                ]
+            )
+            .await,
+            Value::NullArray
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_key_to_another_server() {
+        let (source_addr, source_db) = spawn_server().await;
+        let (target_addr, target_db) = spawn_server().await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(source_addr).await.unwrap());
+        let port = target_addr.port().to_string();
+        let ok = Value::SimpleString("OK".to_owned());
+
+        send(&mut client_handler, &["RPUSH", "list", "a", "b"]).await;
+        send(&mut client_handler, &["SET", "kept", "value", "EX", "100"]).await;
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["MIGRATE", "127.0.0.1", &port, "list", "0", "1000"]
+            )
+            .await,
+            ok
+        );
+        assert!(source_db.read().await.get("list").is_none());
+        assert_eq!(
+            target_db.read().await.get("list"),
+            Some(&DataType::List(std::collections::VecDeque::from([
+                Bytes::from("a"),
+                Bytes::from("b")
+            ])))
+        );
+
+        // COPY leaves the key here, and the TTL travels with it
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["MIGRATE", "127.0.0.1", &port, "kept", "0", "1000", "COPY"]
+            )
+            .await,
+            ok
+        );
+        assert!(source_db.read().await.get("kept").is_some());
+        assert!(target_db.read().await.ttl_millis("kept").unwrap().is_some());
+
+        // The target refuses to overwrite without REPLACE
+        assert!(matches!(
+            send(
+                &mut client_handler,
+                &["MIGRATE", "127.0.0.1", &port, "kept", "0", "1000"]
+            )
+            .await,
+            Value::SimpleError(message) if message.contains("BUSYKEY")
+        ));
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &[
+                    "MIGRATE",
+                    "127.0.0.1",
+                    &port,
+                    "kept",
+                    "0",
+                    "1000",
+                    "REPLACE"
+                ]
+            )
+            .await,
+            ok
+        );
+        assert!(source_db.read().await.get("kept").is_none());
+
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["MIGRATE", "127.0.0.1", &port, "missing", "0", "1000"]
+            )
+            .await,
+            Value::SimpleString("NOKEY".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_command_and_keyspace_stats() {
+        let state = ServerState::new(Config::new());
+        let stats = Arc::clone(&state.stats);
+        let db = Arc::clone(&state.databases[0]);
+        let (addr, _) = spawn_server_with_state(state).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+
+        send(&mut client_handler, &["GET", "key"]).await;
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        send(&mut client_handler, &["MGET", "key", "other"]).await;
+        send(&mut client_handler, &["INCR", "key"]).await;
+
+        assert_eq!(stats.total_commands_processed(), 4);
+        assert_eq!(db.stats().keyspace_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(db.stats().keyspace_misses.load(Ordering::Relaxed), 2);
+        let incr = stats.command("INCR").unwrap();
+        assert_eq!(incr.failed_calls.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.total_error_replies.load(Ordering::Relaxed), 1);
+
+        assert_eq!(
+            send(&mut client_handler, &["CONFIG", "RESETSTAT"]).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        // CONFIG RESETSTAT itself is counted after the reset
+        assert_eq!(stats.total_commands_processed(), 1);
+        assert_eq!(db.stats().keyspace_misses.load(Ordering::Relaxed), 0);
+
+        // Keys expiring count once they are removed
+        send(&mut client_handler, &["SET", "short", "value", "PX", "1"]).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        send(&mut client_handler, &["SET", "short", "again"]).await;
+        let Value::BulkString(info) = send(&mut client_handler, &["INFO", "stats"]).await else {
+            panic!("INFO must reply with a bulk string");
+        };
+        assert!(String::from_utf8_lossy(&info).contains("\r\nexpired_keys:1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_latency_monitor_records_slow_commands() {
+        let (addr, _) = spawn_server().await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let ok = Value::SimpleString("OK".to_owned());
+
+        // Off by default
+        send(&mut client_handler, &["DEBUG", "SLEEP", "0.02"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["LATENCY", "LATEST"]).await,
+            Value::Array(vec![])
+        );
+
+        assert_eq!(
+            send(
+                &mut client_handler,
+                &["CONFIG", "SET", "latency-monitor-threshold", "10"]
+            )
+            .await,
+            ok
+        );
+        send(&mut client_handler, &["DEBUG", "SLEEP", "0.02"]).await;
+        let Value::Array(latest) = send(&mut client_handler, &["LATENCY", "LATEST"]).await else {
+            panic!("LATENCY LATEST replies with an array");
+        };
+        let [Value::Array(event)] = &latest[..] else {
+            panic!("one event was recorded: {:?}", latest);
+        };
+        assert_eq!(event[0], Value::BulkString("command".into()));
+        assert!(matches!(event[2], Value::Integer(latency) if latency >= 20));
+        assert!(matches!(
+            send(&mut client_handler, &["LATENCY", "HISTORY", "command"]).await,
+            Value::Array(samples) if samples.len() == 1
+        ));
+        assert_eq!(
+            send(&mut client_handler, &["LATENCY", "RESET"]).await,
+            Value::Integer(1)
+        );
+        assert_eq!(
+            send(&mut client_handler, &["LATENCY", "HISTORY", "command"]).await,
+            Value::Array(vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limits_close_slow_clients() {
+        let mut limits = OutputBufferLimits::default();
+        limits.set("normal 1kb 0 0 pubsub 64kb 0 0").unwrap();
+        let (addr, _) = spawn_server_with_config(Config {
+            client_output_buffer_limit: limits,
+            ..Config::new()
+        })
+        .await;
+
+        // A reply bigger than the normal limit closes the connection instead
+        let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let value = "x".repeat(2000);
+        send(&mut client, &["SET", "big", &value]).await;
+        client.write_value(&command(&["GET", "big"])).await.unwrap();
+        assert!(client.read_value().await.unwrap().is_none());
+
+        // A subscriber that never reads is dropped once the kernel buffers are full and
+        // its queue passes the pubsub limit, so publishing stops reaching it
+        let mut subscriber = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+        let mut publisher = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let message = "x".repeat(64 * 1024);
+        let mut published = 0;
+        while send(&mut publisher, &["PUBLISH", "news", &message]).await == Value::Integer(1) {
+            published += 1;
+            assert!(published < 10_000, "the subscriber was never dropped");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_and_acl_are_queued_in_multi() {
+        let (addr, _) = spawn_server_with_config(Config::new()).await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let queued = || Value::SimpleString("QUEUED".to_owned());
+
+        for (request, reply) in [
+            (vec!["MULTI"], Value::SimpleString("OK".to_owned())),
+            (vec!["CLIENT", "SETNAME", "worker"], queued()),
+            (vec!["CLIENT", "GETNAME"], queued()),
+            (vec!["ACL", "WHOAMI"], queued()),
+            (
+                vec!["EXEC"],
+                Value::Array(vec![
+                    Value::SimpleString("OK".to_owned()),
+                    bulk("worker"),
+                    bulk("default"),
+                ]),
+            ),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &request).await,
+                reply,
+                "{:?}",
+                request
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reset_command() {
+        let (addr, db_instance) = spawn_server_with_config(Config {
+            requirepass: Some("secret".to_owned()),
+            ..Config::new()
+        })
+        .await;
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let ok = || Value::SimpleString("OK".to_owned());
+        let error = |message: &str| Value::SimpleError(message.to_owned());
+
+        for (request, reply) in [
+            (vec!["AUTH", "secret"], ok()),
+            (vec!["CLIENT", "SETNAME", "worker"], ok()),
+            (vec!["SELECT", "1"], ok()),
+            (vec!["MULTI"], ok()),
+            (
+                vec!["SET", "key", "value"],
+                Value::SimpleString("QUEUED".to_owned()),
+            ),
+            (vec!["RESET"], Value::SimpleString("RESET".to_owned())),
+            (vec!["GET", "key"], error("NOAUTH Authentication required.")),
+            (vec!["AUTH", "secret"], ok()),
+            (vec!["EXEC"], error("ERR EXEC without MULTI")),
+            (vec!["CLIENT", "GETNAME"], Value::Null),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &request).await,
+                reply,
+                "{:?}",
+                request
+            );
+        }
+        // Back on database 0
+        send(&mut client_handler, &["SET", "key", "value"]).await;
+        assert!(db_instance.read().await.get("key").is_some());
+
+        // Replies pipelined before RESET are still sent
+        let mut pipeline = command(&["PING"]).serialize().to_vec();
+        pipeline.extend_from_slice(&command(&["RESET"]).serialize());
+        client_handler.socket.write_all(&pipeline).await.unwrap();
+        assert_eq!(
+            client_handler.read_value().await.unwrap(),
+            Some(Value::SimpleString("PONG".to_owned()))
+        );
+        assert_eq!(
+            client_handler.read_value().await.unwrap(),
+            Some(Value::SimpleString("RESET".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acl_users() {
+        let (addr, _) = spawn_server().await;
+        let mut admin = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let mut client_handler = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+        let ok = || Value::SimpleString("OK".to_owned());
+        let error = |message: &str| Value::SimpleError(message.to_owned());
+
+        assert_eq!(
+            send(
+                &mut admin,
+                &[
+                    "ACL", "SETUSER", "reader", "on", ">pw", "~cache:*", "+@read", "+acl",
+                    "+multi", "+exec"
+                ]
+            )
+            .await,
+            ok()
+        );
+        send(&mut admin, &["SET", "cache:a", "1"]).await;
+
+        for (request, reply) in [
+            (
+                vec!["AUTH", "reader", "wrong"],
+                error("WRONGPASS invalid username-password pair or user is disabled."),
+            ),
+            (vec!["AUTH", "reader", "pw"], ok()),
+            (vec!["ACL", "WHOAMI"], bulk("reader")),
+            (vec!["GET", "cache:a"], bulk("1")),
+            (
+                vec!["GET", "secret"],
+                error("NOPERM No permissions to access a key"),
+            ),
+            (
+                vec!["DEL", "cache:a"],
+                error("NOPERM User reader has no permissions to run the 'del' command"),
+            ),
+            (vec!["MULTI"], ok()),
+            (
+                vec!["DEL", "cache:a"],
+                error("NOPERM User reader has no permissions to run the 'del' command"),
+            ),
+            (
+                vec!["EXEC"],
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+            (vec!["AUTH", "default", "anything"], ok()),
+            (vec!["DEL", "cache:a"], Value::Integer(1)),
+        ] {
+            assert_eq!(
+                send(&mut client_handler, &request).await,
+                reply,
+                "{:?}",
+                request
+            );
+        }
+
+        // Disabled users cannot log in any more
+        send(&mut admin, &["ACL", "SETUSER", "reader", "off"]).await;
+        assert_eq!(
+            send(&mut client_handler, &["AUTH", "reader", "pw"]).await,
+            error("WRONGPASS invalid username-password pair or user is disabled.")
+        );
     }
 }
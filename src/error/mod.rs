@@ -0,0 +1,294 @@
+use std::{fmt, io};
+use tokio::task::JoinError;
+
+use crate::parser::Value;
+
+pub mod tests_error;
+
+/// Why a connection could not be read from or written to. These close the connection,
+/// unlike `CommandError`s, which are sent back as error replies.
+#[derive(Debug)]
+pub enum RespError {
+    Io(io::Error),
+    // The peer went away in the middle of a frame
+    UnexpectedEof,
+    // A frame started with a byte that is not a RESP type
+    InvalidPrefix(u8),
+    // A length or integer that is not a number
+    InvalidInteger,
+    InvalidLength(i64),
+    InvalidUtf8,
+    // A null, boolean, double or big number frame that does not hold one
+    InvalidValue(&'static str),
+    InvalidPayloadHeader,
+    UnbalancedQuotes,
+    InlineTooLong,
+    // Aggregates nested deeper than the parser follows
+    TooDeep,
+    // Bulk strings and aggregates past the configured protocol limits
+    BulkTooLong,
+    MultibulkTooLong,
+}
+
+/// Errors a command replies with, in the form clients match on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandError {
+    WrongType,
+    WrongArity,
+    NotInteger,
+    NotFloat,
+    Syntax,
+}
+
+/// Why the server, or something it ran, failed instead of answering. A command failing
+/// with one is answered with it as an error reply, while starting the server, loading its
+/// files or following a master stops with it.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Resp(RespError),
+    Client(ClientError),
+    // A request that is not an array of bulk strings
+    Protocol(&'static str),
+    // An argument of a type the command cannot take, such as an array where a key goes
+    InvalidArgument(&'static str),
+    InvalidUtf8,
+    // A snapshot, AOF, dump payload, JSON document or replay script that does not decode
+    Corrupt(String),
+    // The master answered the handshake with something unexpected, or went silent
+    Master(String),
+    // A setting the server cannot start with
+    Config(String),
+    // The server's task panicked
+    Join(JoinError),
+    // What was being done when `source` happened, such as "Failed to read dump.rdb"
+    Context { context: String, source: Box<Error> },
+}
+
+/// The result of anything in the crate that can fail with an `Error`.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Says what was being done when an error happened, like "Failed to read dump.rdb".
+pub trait Context<T> {
+    fn context(self, context: &str) -> Result<T>;
+
+    fn with_context(self, context: impl FnOnce() -> String) -> Result<T>;
+}
+
+/// Why a request made through `client::Client` failed.
+#[derive(Debug)]
+pub enum ClientError {
+    Resp(RespError),
+    // The server closed the connection before replying
+    Closed,
+    // The server answered with an error reply
+    Reply(String),
+    // A reply of another type than the command returns
+    UnexpectedReply(Value),
+}
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::UnexpectedEof => write!(f, "Connection closed in the middle of a frame"),
+            Self::InvalidPrefix(prefix) => {
+                write!(f, "Protocol error: invalid type {:?}", *prefix as char)
+            }
+            Self::InvalidInteger => write!(f, "Protocol error: invalid integer"),
+            Self::InvalidLength(length) => write!(f, "Protocol error: invalid length {}", length),
+            Self::InvalidUtf8 => write!(f, "Protocol error: invalid UTF-8"),
+            Self::InvalidValue(kind) => write!(f, "Protocol error: invalid {}", kind),
+            Self::InvalidPayloadHeader => write!(f, "Protocol error: invalid payload header"),
+            Self::UnbalancedQuotes => write!(f, "Protocol error: unbalanced quotes in request"),
+            Self::InlineTooLong => write!(f, "Protocol error: too big inline request"),
+            Self::TooDeep => write!(f, "Protocol error: too deeply nested aggregate"),
+            Self::BulkTooLong => write!(f, "Protocol error: invalid bulk length"),
+            Self::MultibulkTooLong => write!(f, "Protocol error: invalid multibulk length"),
+        }
+    }
+}
+
+impl RespError {
+    /// Whether the peer sent something that is not valid RESP, as opposed to the
+    /// connection failing. Clients are told about these before being disconnected.
+    pub fn is_protocol(&self) -> bool {
+        !matches!(self, Self::Io(_) | Self::UnexpectedEof)
+    }
+}
+
+impl std::error::Error for RespError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RespError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::WrongType => "WRONGTYPE Operation against a key holding the wrong kind of value",
+            Self::WrongArity => "ERR wrong number of arguments",
+            Self::NotInteger => "ERR value is not an integer or out of range",
+            Self::NotFloat => "ERR value is not a valid float",
+            Self::Syntax => "ERR syntax error",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Resp(err) => write!(f, "{}", err),
+            Self::Client(err) => write!(f, "{}", err),
+            Self::Protocol(message) => write!(f, "Protocol error: {}", message),
+            Self::InvalidArgument(message) => f.write_str(message),
+            Self::InvalidUtf8 => write!(f, "Invalid UTF-8 in bulk string"),
+            Self::Corrupt(message) | Self::Master(message) | Self::Config(message) => {
+                f.write_str(message)
+            }
+            Self::Join(err) => write!(f, "{}", err),
+            Self::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Resp(err) => Some(err),
+            Self::Client(err) => Some(err),
+            Self::Join(err) => Some(err),
+            Self::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<RespError> for Error {
+    fn from(err: RespError) -> Self {
+        Self::Resp(err)
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(err: ClientError) -> Self {
+        Self::Client(err)
+    }
+}
+
+impl From<JoinError> for Error {
+    fn from(err: JoinError) -> Self {
+        Self::Join(err)
+    }
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, context: &str) -> Result<T> {
+        self.with_context(|| context.to_owned())
+    }
+
+    fn with_context(self, context: impl FnOnce() -> String) -> Result<T> {
+        self.map_err(|err| Error::Context {
+            context: context(),
+            source: Box::new(err.into()),
+        })
+    }
+}
+
+impl From<CommandError> for Value {
+    fn from(err: CommandError) -> Self {
+        Value::SimpleError(err.to_string())
+    }
+}
+
+/// The arity error naming the command, such as
+/// "ERR wrong number of arguments for 'get' command".
+pub fn wrong_arity(command: &str) -> Value {
+    Value::SimpleError(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command.to_ascii_lowercase()
+    ))
+}
+
+/// The error for a subcommand `command` does not have, pointing at its HELP, such as
+/// "ERR unknown subcommand 'foo'. Try OBJECT HELP."
+pub fn unknown_subcommand(command: &str, subcommand: &str) -> Value {
+    Value::SimpleError(format!(
+        "ERR unknown subcommand '{}'. Try {} HELP.",
+        subcommand.to_ascii_lowercase(),
+        command.to_ascii_uppercase()
+    ))
+}
+
+/// The error for a command nobody implements, quoting the name and the start of its
+/// arguments as Redis does: "ERR unknown command 'foo', with args beginning with: 'a' ".
+/// `request` starts with the name.
+pub fn unknown_command(request: &[Value]) -> Value {
+    let text = |value: &Value| match value {
+        Value::BulkString(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        _ => String::new(),
+    };
+    let truncated = |text: String, limit: usize| text.chars().take(limit).collect::<String>();
+    let name = request.first().map(text).unwrap_or_default();
+    let mut args = String::new();
+    for arg in request.iter().skip(1) {
+        let length = args.chars().count();
+        if length >= 128 {
+            break;
+        }
+        args.push_str(&format!("'{}' ", truncated(text(arg), 128 - length)));
+    }
+    let message = format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        truncated(name, 128),
+        args
+    );
+    // An error reply is a single line
+    Value::SimpleError(message.replace(['\r', '\n'], " "))
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Resp(err) => write!(f, "{}", err),
+            Self::Closed => write!(f, "Connection closed by the server"),
+            Self::Reply(message) => write!(f, "{}", message),
+            Self::UnexpectedReply(reply) => write!(f, "Unexpected reply {:?}", reply),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Resp(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<RespError> for ClientError {
+    fn from(err: RespError) -> Self {
+        Self::Resp(err)
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_command_errors_become_replies() {
+        assert_eq!(
+            Value::from(CommandError::WrongType),
+            Value::SimpleError(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_owned()
+            )
+        );
+        assert_eq!(
+            Value::from(CommandError::NotInteger),
+            Value::SimpleError("ERR value is not an integer or out of range".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_unknown_command_quotes_the_request() {
+        let request = |parts: &[&str]| -> Vec<Value> {
+            parts
+                .iter()
+                .map(|part| Value::BulkString(part.to_string().into()))
+                .collect()
+        };
+        assert_eq!(
+            unknown_command(&request(&["foo"])),
+            Value::SimpleError("ERR unknown command 'foo', with args beginning with: ".to_owned())
+        );
+        assert_eq!(
+            unknown_command(&request(&["Foo", "a", "b\r\nc"])),
+            Value::SimpleError(
+                "ERR unknown command 'Foo', with args beginning with: 'a' 'b  c' ".to_owned()
+            )
+        );
+        let long = "x".repeat(200);
+        let Value::SimpleError(message) = unknown_command(&request(&["foo", &long, "next"])) else {
+            unreachable!()
+        };
+        assert!(message.ends_with(&format!("'{}' ", "x".repeat(128))));
+    }
+
+    #[test]
+    fn test_resp_errors_keep_their_cause() {
+        let err = RespError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(matches!(err, RespError::Io(_)));
+        assert_eq!(
+            RespError::InvalidPrefix(b'!').to_string(),
+            "Protocol error: invalid type '!'"
+        );
+    }
+
+    #[test]
+    fn test_errors_can_be_matched_and_keep_their_context() {
+        let read: Result<()> = Err(io::Error::from(io::ErrorKind::NotFound).into());
+        let err = read.context("Failed to read dump.rdb").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Failed to read dump.rdb: {}",
+                io::Error::from(io::ErrorKind::NotFound)
+            )
+        );
+        let Error::Context { source, .. } = &err else {
+            panic!("expected a context, got {:?}", err);
+        };
+        assert!(matches!(**source, Error::Io(_)));
+        assert!(std::error::Error::source(&err).is_some());
+
+        let err = Error::from(RespError::UnexpectedEof);
+        assert!(matches!(err, Error::Resp(RespError::UnexpectedEof)));
+        assert_eq!(
+            Error::Protocol("empty command").to_string(),
+            "Protocol error: empty command"
+        );
+    }
+}
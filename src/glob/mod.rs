@@ -0,0 +1,81 @@
+pub mod tests_glob;
+
+/// Redis-style glob matching over raw bytes.
+///
+/// Supports `*` (any run of bytes), `?` (exactly one byte), `[...]` classes with
+/// ranges (`[a-z]`) and negation (`[^abc]`), and `\` to escape the next byte.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last `*`: (pattern index after it, string index)
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            if pattern[p] == b'*' {
+                p += 1;
+                backtrack = Some((p, s));
+                continue;
+            }
+            if let Some(next) = match_token(pattern, p, string[s]) {
+                p = next;
+                s += 1;
+                continue;
+            }
+        }
+
+        // Let the last `*` swallow one more byte and retry from there
+        match backtrack {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                backtrack = Some((star_p, s));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+// Matches the single-byte token starting at pattern[p] and returns where the next token starts
+fn match_token(pattern: &[u8], p: usize, byte: u8) -> Option<usize> {
+    match pattern[p] {
+        b'?' => Some(p + 1),
+        b'[' => match_class(pattern, p + 1, byte),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == byte).then_some(p + 2),
+        literal => (literal == byte).then_some(p + 1),
+    }
+}
+
+fn match_class(pattern: &[u8], mut p: usize, byte: u8) -> Option<usize> {
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            matched |= pattern[p] == byte;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+            let (low, high) = if pattern[p] <= pattern[p + 2] {
+                (pattern[p], pattern[p + 2])
+            } else {
+                (pattern[p + 2], pattern[p])
+            };
+            matched |= low <= byte && byte <= high;
+            p += 2;
+        } else {
+            matched |= pattern[p] == byte;
+        }
+        p += 1;
+    }
+
+    // Step over the closing bracket; an unterminated class simply ends the pattern
+    if p < pattern.len() {
+        p += 1;
+    }
+
+    (matched != negate).then_some(p)
+}
@@ -0,0 +1,52 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn test_literal_and_wildcards() {
+        assert!(matches("hello", "hello"));
+        assert!(!matches("hello", "hell"));
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("h?llo", "hallo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("*llo*", "hello world"));
+        assert!(!matches("h*x", "hello"));
+        assert!(matches("a*b*c", "aXXbYYbZZc"));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+        // Reversed ranges behave like Redis and are normalised
+        assert!(matches("h[z-x]llo", "hyllo"));
+        assert!(matches("key:[0-9]*", "key:42"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("what\\?", "what?"));
+        assert!(!matches("what\\?", "whatx"));
+        assert!(matches("star\\*", "star*"));
+        assert!(!matches("star\\*", "starfish"));
+        assert!(matches("[\\]]", "]"));
+    }
+
+    #[test]
+    fn test_binary_input() {
+        assert!(glob_match(b"\xff*", b"\xff\x00\x01"));
+        assert!(!glob_match(b"\xfe*", b"\xff"));
+    }
+}
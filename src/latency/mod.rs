@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::clock::Clock;
+
+pub mod tests_latency;
+
+/// How many samples each event keeps, the oldest dropped first.
+pub const HISTORY_LEN: usize = 160;
+
+/// A latency spike: when it happened, in Unix seconds, and how long it took in
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub time: u64,
+    pub latency: u64,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    samples: VecDeque<Sample>,
+    // The highest latency seen since the last reset, even if its sample was dropped
+    max: u64,
+}
+
+/// Records events that took at least `latency-monitor-threshold` milliseconds, per
+/// event class such as `command` or `expire-cycle`, for LATENCY to report. The
+/// threshold is kept here as well as in `Config` so background tasks can check it
+/// without the config lock.
+#[derive(Debug)]
+pub struct LatencyMonitor {
+    threshold: AtomicU64,
+    events: Mutex<HashMap<&'static str, History>>,
+    // Dates the samples, the server's clock
+    clock: Arc<dyn Clock>,
+}
+
+impl LatencyMonitor {
+    pub fn new(threshold: u64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            threshold: AtomicU64::new(threshold),
+            events: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    pub fn set_threshold(&self, threshold: u64) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Records `event` when it took at least the threshold and monitoring is on. Spikes
+    /// within the same second are merged, keeping the longest.
+    pub fn record(&self, event: &'static str, elapsed: Duration) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        let latency = elapsed.as_millis() as u64;
+        if threshold == 0 || latency < threshold {
+            return;
+        }
+        let time = self.clock.now_millis() / 1000;
+        let mut events = self.events.lock().unwrap();
+        let history = events.entry(event).or_default();
+        history.max = history.max.max(latency);
+        match history.samples.back_mut() {
+            Some(last) if last.time == time => last.latency = last.latency.max(latency),
+            _ => {
+                if history.samples.len() == HISTORY_LEN {
+                    history.samples.pop_front();
+                }
+                history.samples.push_back(Sample { time, latency });
+            }
+        }
+    }
+
+    /// The latest sample and the all-time maximum of every event, by event name.
+    pub fn latest(&self) -> Vec<(&'static str, Sample, u64)> {
+        let events = self.events.lock().unwrap();
+        let mut latest: Vec<_> = events
+            .iter()
+            .filter_map(|(event, history)| Some((*event, *history.samples.back()?, history.max)))
+            .collect();
+        latest.sort_by_key(|(event, _, _)| *event);
+        latest
+    }
+
+    /// Every sample kept for `event`, oldest first.
+    pub fn history(&self, event: &str) -> Vec<Sample> {
+        let events = self.events.lock().unwrap();
+        events
+            .get(event)
+            .map(|history| history.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the named events, or all of them when none are named. Returns how many
+    /// had samples.
+    pub fn reset(&self, names: &[String]) -> usize {
+        let mut events = self.events.lock().unwrap();
+        if names.is_empty() {
+            let count = events.len();
+            events.clear();
+            return count;
+        }
+        names
+            .iter()
+            .filter(|name| events.remove(name.as_str()).is_some())
+            .count()
+    }
+
+    /// A human-readable report of what was recorded, for LATENCY DOCTOR.
+    pub fn doctor(&self) -> String {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        if threshold == 0 {
+            return "Latency monitoring is disabled. Enable it with CONFIG SET latency-monitor-threshold <milliseconds>.\n"
+                .to_owned();
+        }
+        let latest = self.latest();
+        if latest.is_empty() {
+            return format!(
+                "No latency spikes of {} milliseconds or more were observed.\n",
+                threshold
+            );
+        }
+        let mut report = format!(
+            "Latency spikes of {} milliseconds or more were observed:\n\n",
+            threshold
+        );
+        for (number, (event, _, max)) in latest.iter().enumerate() {
+            let samples = self.history(event);
+            let average =
+                samples.iter().map(|sample| sample.latency).sum::<u64>() / samples.len() as u64;
+            report.push_str(&format!(
+                "{}. {}: {} latency spikes (average {}ms, worst {}ms).\n",
+                number + 1,
+                event,
+                samples.len(),
+                average,
+                max
+            ));
+        }
+        let advice = [
+            ("command", "Some commands are slow; KEYS and large range queries walk many keys at once."),
+            ("fast-command", "Even O(1) commands are slow, which hints at a host that is overloaded or swapping."),
+            ("expire-cycle", "Many keys expire at once; spreading their TTLs out avoids the bursts."),
+            ("aof-write", "Writing the AOF is slow; with appendfsync always every write waits for the disk."),
+            ("aof-fsync", "Syncing the AOF is slow, so the disk is likely saturated."),
+        ];
+        let advice: Vec<&str> = advice
+            .iter()
+            .filter(|(event, _)| latest.iter().any(|(seen, _, _)| seen == event))
+            .map(|(_, advice)| *advice)
+            .collect();
+        if !advice.is_empty() {
+            report.push_str("\nAdvice:\n");
+            for line in advice {
+                report.push_str(&format!("- {}\n", line));
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{MockClock, SystemClock};
+
+    #[test]
+    fn test_threshold() {
+        let latency = LatencyMonitor::new(0, Arc::new(SystemClock));
+        latency.record("command", Duration::from_millis(500));
+        assert!(latency.latest().is_empty());
+
+        latency.set_threshold(100);
+        latency.record("command", Duration::from_millis(99));
+        assert!(latency.latest().is_empty());
+        latency.record("command", Duration::from_millis(100));
+        assert_eq!(latency.history("command").len(), 1);
+    }
+
+    #[test]
+    fn test_samples_merge_within_a_second() {
+        let clock = Arc::new(MockClock::new(1_700_000_000_000));
+        let latency = LatencyMonitor::new(1, clock.clone());
+        latency.record("expire-cycle", Duration::from_millis(20));
+        latency.record("expire-cycle", Duration::from_millis(50));
+        clock.advance(Duration::from_millis(999));
+        latency.record("expire-cycle", Duration::from_millis(30));
+
+        let history = latency.history("expire-cycle");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].time, 1_700_000_000);
+        assert_eq!(history.iter().map(|sample| sample.latency).max(), Some(50));
+        let [(event, sample, max)] = latency.latest()[..] else {
+            panic!("one event was recorded");
+        };
+        assert_eq!((event, max), ("expire-cycle", 50));
+        assert_eq!(sample, *history.last().unwrap());
+    }
+
+    #[test]
+    fn test_history_is_bounded_and_reset() {
+        let latency = LatencyMonitor::new(1, Arc::new(SystemClock));
+        {
+            let mut events = latency.events.lock().unwrap();
+            let history = events.entry("command").or_default();
+            history.samples = (0..HISTORY_LEN as u64)
+                .map(|time| Sample { time, latency: 5 })
+                .collect();
+            history.max = 5;
+        }
+        latency.record("command", Duration::from_millis(7));
+        let history = latency.history("command");
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history[0].time, 1);
+        assert_eq!(history.last().unwrap().latency, 7);
+
+        latency.record("aof-write", Duration::from_millis(3));
+        assert!(latency.doctor().contains("2. command: 160 latency spikes"));
+        assert_eq!(
+            latency.reset(&["aof-write".to_owned(), "nothing".to_owned()]),
+            1
+        );
+        assert_eq!(latency.reset(&[]), 1);
+        assert!(latency.latest().is_empty());
+    }
+}
@@ -0,0 +1,33 @@
+//! A Redis-compatible server. The `redis_rust` binary runs it, and `Server` starts one
+//! in-process, for instance inside another project's tests. The modules are exposed so
+//! the other binaries, such as the `cli` client, can reuse the protocol code.
+
+pub mod acl;
+pub mod client;
+pub mod clients;
+pub mod clock;
+pub mod cluster;
+pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod error;
+pub mod glob;
+pub mod latency;
+pub mod logging;
+pub mod monitor;
+pub mod parser;
+pub mod persistence;
+pub mod plugin;
+pub mod propagation;
+pub mod pubsub;
+pub mod replay;
+pub mod replication;
+pub mod server;
+pub mod shutdown;
+pub mod stats;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod transaction;
+
+pub use server::{Server, ServerBuilder, ServerHandle};
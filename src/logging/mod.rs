@@ -0,0 +1,275 @@
+use std::cell::Cell;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::clock::{Clock, SystemClock};
+use crate::persistence::json::put_string;
+
+pub mod tests_logging;
+
+/// How much the server logs, from everything to warnings only, named as in Redis's
+/// `loglevel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "debug" => Some(Self::Debug),
+            "verbose" => Some(Self::Verbose),
+            "notice" => Some(Self::Notice),
+            "warning" => Some(Self::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Verbose => "verbose",
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+        }
+    }
+
+    // The mark Redis puts before messages of the level
+    fn mark(&self) -> char {
+        match self {
+            Self::Debug => '.',
+            Self::Verbose => '-',
+            Self::Notice => '*',
+            Self::Warning => '#',
+        }
+    }
+}
+
+/// How log lines are written: for people to read, or one JSON object per line for log
+/// aggregators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
+
+// The logger is process-wide, like stdout it writes to. The server sets it from its
+// config at startup and on CONFIG SET.
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Notice as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    // The client a connection task serves, and the command it is running
+    static SPAN: Span;
+}
+
+#[derive(Debug)]
+struct Span {
+    client: u64,
+    command: Cell<Option<&'static str>>,
+}
+
+/// Where a message was logged from, which every line it gives carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Context {
+    pub client: Option<u64>,
+    pub command: Option<&'static str>,
+}
+
+/// Sets what is logged from now on.
+pub fn configure(level: Level, format: LogFormat) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Runs a connection task, tagging what it logs with the client's id.
+pub async fn client_span<F: Future>(client: u64, future: F) -> F::Output {
+    let span = Span {
+        client,
+        command: Cell::new(None),
+    };
+    SPAN.scope(span, future).await
+}
+
+/// Tags what is logged with the command until the guard is dropped. Outside a client
+/// span it does nothing.
+pub fn command_span(name: &'static str) -> CommandSpan {
+    let name = Some(name).filter(|name| !name.is_empty());
+    let _ = SPAN.try_with(|span| span.command.set(name));
+    CommandSpan
+}
+
+#[derive(Debug)]
+pub struct CommandSpan;
+
+impl Drop for CommandSpan {
+    fn drop(&mut self) {
+        let _ = SPAN.try_with(|span| span.command.set(None));
+    }
+}
+
+/// The client and command of the current task, if any.
+pub fn context() -> Context {
+    SPAN.try_with(|span| Context {
+        client: Some(span.client),
+        command: span.command.get(),
+    })
+    .unwrap_or_default()
+}
+
+/// Writes a message of the level, if it is enabled. Warnings go to stderr and the rest
+/// to stdout. The `warning!`, `notice!`, `verbose!` and `debug!` macros call this.
+pub fn log(level: Level, message: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let format = match JSON.load(Ordering::Relaxed) {
+        true => LogFormat::Json,
+        false => LogFormat::Text,
+    };
+    let line = format_line(
+        level,
+        format,
+        context(),
+        &message.to_string(),
+        SystemClock.now_millis(),
+    );
+    match level {
+        Level::Warning => eprintln!("{}", line),
+        _ => println!("{}", line),
+    }
+}
+
+/// One log line: `<time> <mark> [client:command] message` as text, or an object with
+/// the same fields as JSON.
+pub fn format_line(
+    level: Level,
+    format: LogFormat,
+    context: Context,
+    message: &str,
+    millis: u64,
+) -> String {
+    let mut line = String::new();
+    let time = timestamp(millis);
+    match format {
+        LogFormat::Text => {
+            write!(line, "{} {} ", time, level.mark()).unwrap();
+            match context {
+                Context {
+                    client: Some(client),
+                    command: Some(command),
+                } => write!(line, "[{}:{}] ", client, command).unwrap(),
+                Context {
+                    client: Some(client),
+                    ..
+                } => write!(line, "[{}] ", client).unwrap(),
+                _ => {}
+            }
+            line.push_str(message);
+        }
+        LogFormat::Json => {
+            write!(
+                line,
+                "{{\"time\": \"{}\", \"level\": \"{}\"",
+                time,
+                level.name()
+            )
+            .unwrap();
+            if let Some(client) = context.client {
+                write!(line, ", \"client\": {}", client).unwrap();
+            }
+            if let Some(command) = context.command {
+                line.push_str(", \"command\": ");
+                put_string(&mut line, command);
+            }
+            line.push_str(", \"message\": ");
+            put_string(&mut line, message);
+            line.push('}');
+        }
+    }
+    line
+}
+
+// Milliseconds since the epoch as an RFC 3339 UTC time, such as
+// 2024-05-01T12:30:00.250Z
+fn timestamp(millis: u64) -> String {
+    let (days, rest) = (millis / 86_400_000, millis % 86_400_000);
+    // Howard Hinnant's days-to-civil algorithm, on eras of 400 years
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rest / 3_600_000,
+        rest / 60_000 % 60,
+        rest / 1_000 % 60,
+        rest % 1_000
+    )
+}
+
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Warning, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Notice, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Verbose, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Debug, format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(Level::parse("VERBOSE"), Some(Level::Verbose));
+        assert_eq!(Level::parse("loud"), None);
+        assert!(Level::Debug < Level::Verbose && Level::Notice < Level::Warning);
+        assert_eq!(LogFormat::parse("json").unwrap().name(), "json");
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(timestamp(951_782_400_000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(timestamp(1_700_000_000_123), "2023-11-14T22:13:20.123Z");
+    }
+
+    #[test]
+    fn test_format_line() {
+        let context = Context {
+            client: Some(7),
+            command: Some("get"),
+        };
+        assert_eq!(
+            format_line(
+                Level::Notice,
+                LogFormat::Text,
+                Context::default(),
+                "Ready",
+                0
+            ),
+            "1970-01-01T00:00:00.000Z * Ready"
+        );
+        assert_eq!(
+            format_line(Level::Warning, LogFormat::Text, context, "Failed", 0),
+            "1970-01-01T00:00:00.000Z # [7:get] Failed"
+        );
+        assert_eq!(
+            format_line(Level::Verbose, LogFormat::Json, context, "Said \"hi\"", 0),
+            concat!(
+                "{\"time\": \"1970-01-01T00:00:00.000Z\", \"level\": \"verbose\", ",
+                "\"client\": 7, \"command\": \"get\", \"message\": \"Said \\\"hi\\\"\"}"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spans() {
+        assert_eq!(context(), Context::default());
+        client_span(3, async {
+            assert_eq!(context().client, Some(3));
+            {
+                let _command = command_span("set");
+                tokio::task::yield_now().await;
+                assert_eq!(context().command, Some("set"));
+            }
+            assert_eq!(context().command, None);
+        })
+        .await;
+        // Outside a client span commands are not recorded
+        let _command = command_span("set");
+        assert_eq!(context(), Context::default());
+    }
+}
@@ -1,24 +1,16 @@
-mod connection;
-mod parser;
-
-use std::{collections::HashMap, sync::Arc};
-
-use connection::handle_connection;
-
-use anyhow::Result;
-use tokio::{net::TcpListener, sync::RwLock};
+use anyhow::{anyhow, Result};
+use redis_rust::config::Config;
+use redis_rust::shutdown::spawn_signal_handler;
+use redis_rust::Server;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:6379").await?;
-    let db: Arc<RwLock<HashMap<String, String>>> = Arc::new(RwLock::new(HashMap::new()));
-    loop {
-        let (socket, _) = listener.accept().await?;
-        let instance = Arc::clone(&db);
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(socket, instance).await {
-                eprintln!("Failed to handle connection: {}", e);
-            }
-        });
-    }
+async fn main() -> Result<()> {
+    let config = Config::from_args(std::env::args().skip(1)).map_err(|err| anyhow!(err))?;
+    let appendonly = config.appendonly;
+    let server = Server::builder().config(config).spawn().await?;
+    // Without the AOF the snapshot is the only copy of the data, so a signal saves it
+    spawn_signal_handler(server.state(), !appendonly)?;
+    server.wait().await?;
+    redis_rust::notice!("Ready to exit, bye bye...");
+    Ok(())
 }
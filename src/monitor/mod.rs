@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::clock::{Clock, SystemClock};
+use crate::parser::Value;
+
+pub mod tests_monitor;
+
+// Lines a slow MONITOR client may fall behind by before it starts missing some
+const CAPACITY: usize = 1024;
+
+/// Broadcasts a line for every command the server receives to the MONITOR clients.
+#[derive(Debug)]
+pub struct Monitor {
+    sender: broadcast::Sender<Value>,
+    // Stamps the lines, the server's clock
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl Monitor {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            clock,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Value> {
+        self.sender.subscribe()
+    }
+
+    /// Sends `+<time> [<db> <addr>] "<command>" "<arg>" ...` to every MONITOR client.
+    pub fn feed(&self, db: usize, addr: SocketAddr, name: &str, args: &[Value]) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let now = self.clock.now_micros();
+        let mut line = format!(
+            "{}.{:06} [{} {}] {}",
+            now / 1_000_000,
+            now % 1_000_000,
+            db,
+            addr,
+            quote(name.to_lowercase().as_bytes())
+        );
+        for arg in args {
+            if let Value::BulkString(bytes) = arg {
+                line.push(' ');
+                line.push_str(&quote(bytes));
+            }
+        }
+        // Nobody may be listening any more, which is fine
+        let _ = self.sender.send(Value::SimpleString(line));
+    }
+}
+
+// Quotes an argument the way redis-cli prints it, escaping anything unprintable
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::with_capacity(bytes.len() + 2);
+    quoted.push('"');
+    for &byte in bytes {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => quoted.push(byte as char),
+            byte => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
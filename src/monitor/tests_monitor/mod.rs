@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(b"plain text"), "\"plain text\"");
+        assert_eq!(quote(b"a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(quote(b"line\r\n\x01"), "\"line\\r\\n\\x01\"");
+    }
+
+    #[tokio::test]
+    async fn test_feed() {
+        let monitor = Monitor::default();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 5000));
+
+        // Without listeners nothing is formatted or queued
+        monitor.feed(0, addr, "PING", &[]);
+        let mut receiver = monitor.subscribe();
+        monitor.feed(
+            2,
+            addr,
+            "SET",
+            &[
+                Value::BulkString("key".into()),
+                Value::BulkString("two words".into()),
+            ],
+        );
+
+        let Value::SimpleString(line) = receiver.recv().await.unwrap() else {
+            panic!("monitor lines are simple strings");
+        };
+        let (time, rest) = line.split_once(' ').unwrap();
+        assert!(time.parse::<f64>().is_ok());
+        assert_eq!(rest, "[2 127.0.0.1:5000] \"set\" \"key\" \"two words\"");
+        assert!(receiver.try_recv().is_err());
+    }
+}
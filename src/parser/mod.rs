@@ -1,11 +1,15 @@
-use anyhow::{Context, Result};
-use bytes::BytesMut;
+use crate::error::RespError;
+
+// Parsing and socket errors are all protocol level
+type Result<T> = std::result::Result<T, RespError>;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
 };
 
 pub mod tests_parser;
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UserCommand {
     Ping,
     Echo,
@@ -13,8 +17,150 @@ pub enum UserCommand {
     Mget,
     Set,
     Del,
+    Exists,
+    Unlink,
+    Rename,
+    RenameNx,
+    Dump,
+    Restore,
+    Migrate,
     Expire,
+    ExpireAt,
+    PExpireAt,
+    Persist,
+    Ttl,
+    Pttl,
+    Incr,
+    Decr,
+    IncrBy,
+    DecrBy,
+    Append,
+    Strlen,
+    GetRange,
+    SetRange,
+    GetDel,
+    GetEx,
+    GetSet,
+    SetNx,
+    SetEx,
+    PSetEx,
+    SetBit,
+    GetBit,
+    BitCount,
+    BitOp,
+    BitPos,
+    Keys,
+    Scan,
+    HSet,
+    HGet,
+    HMGet,
+    HDel,
+    HGetAll,
+    HScan,
+    HExists,
+    HLen,
+    HRandField,
+    SAdd,
+    SRem,
+    SMembers,
+    SIsMember,
+    SCard,
+    SPop,
+    SRandMember,
+    SScan,
+    SInter,
+    SUnion,
+    SDiff,
+    ZAdd,
+    ZRem,
+    ZScore,
+    ZCard,
+    ZScan,
+    ZRank,
+    ZRevRank,
+    ZRange,
+    ZRevRange,
+    ZIncrBy,
+    ZCount,
+    ZRangeByScore,
+    ZRangeByLex,
+    ZPopMin,
+    ZPopMax,
+    GeoAdd,
+    GeoDist,
+    GeoSearch,
+    LPush,
+    RPush,
+    LPop,
+    RPop,
+    LLen,
+    LRange,
+    LPos,
+    LInsert,
+    LSet,
+    LRem,
+    LTrim,
+    RPopLPush,
+    LMove,
+    BLPop,
+    BRPop,
+    BLMove,
+    BZPopMin,
+    BZPopMax,
+    XAdd,
+    XLen,
+    XRange,
+    XRevRange,
+    XRead,
+    XGroup,
+    XReadGroup,
+    XAck,
+    XPending,
+    Subscribe,
+    Unsubscribe,
+    PSubscribe,
+    PUnsubscribe,
+    Publish,
+    Multi,
+    Exec,
+    Discard,
+    Watch,
+    Unwatch,
+    Auth,
+    Hello,
+    Config,
+    Select,
+    SwapDb,
+    FlushDb,
+    FlushAll,
+    DbSize,
+    RandomKey,
+    Type,
+    Sort,
+    Object,
+    Memory,
+    Save,
+    BgSave,
+    BgRewriteAof,
+    ReplicaOf,
+    ReplConf,
+    Psync,
+    Wait,
+    Failover,
+    Command,
+    Info,
+    Lolwut,
+    Time,
+    Client,
+    Acl,
+    Cluster,
+    Asking,
+    Monitor,
+    Shutdown,
+    Debug,
+    Latency,
     Quit,
+    Reset,
     Invalid,
 }
 
@@ -22,143 +168,2258 @@ pub enum UserCommand {
 pub enum Value {
     SimpleString(String),
     SimpleError(String),
-    BulkString(String),
+    Integer(i64),
+    BulkString(Bytes),
     Array(Vec<Value>),
-    InvalidValue,
+    Null,
+    NullArray,
+    // RESP3 types. RESP2 clients get them as the closest RESP2 type instead: maps
+    // as flat key/value arrays, sets and pushes as arrays, booleans as 0 or 1, and
+    // doubles and big numbers as bulk strings.
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    // Out-of-band data such as Pub/Sub messages
+    Push(Vec<Value>),
+}
+
+/// The protocol version a connection speaks, chosen with HELLO.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Self> {
+        match version {
+            2 => Some(Self::Resp2),
+            3 => Some(Self::Resp3),
+            _ => None,
+        }
+    }
+
+    pub fn version(&self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
 }
 
+/// Bounds on the frames a peer may send. Frames past them are protocol errors, so a
+/// client cannot make the server buffer without limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProtocolLimits {
+    // The longest bulk string, proto-max-bulk-len
+    pub max_bulk_len: u64,
+    // The most elements an array, map, set or push may claim
+    pub max_multibulk_len: u64,
+}
+
+impl Default for ProtocolLimits {
+    // The defaults of Redis
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: i32::MAX as u64,
+        }
+    }
+}
+
+/// Outcome of parsing the front of a receive buffer. `NeedMoreData` means the
+/// bytes seen so far are a valid prefix of a frame but the frame is not complete
+/// yet, so the caller should read more from the socket and try again.
+#[derive(Debug, PartialEq)]
+pub enum ParseStatus {
+    Complete(Value, usize),
+    NeedMoreData,
+}
+
+/// Reads frames from and writes replies to a stream, a TCP socket unless another is
+/// given, such as an in-memory duplex in tests.
 #[derive(Debug)]
-pub struct RespHandler {
-    pub socket: TcpStream,
+pub struct RespHandler<S = TcpStream> {
+    pub socket: S,
     pub buffer: BytesMut,
-    pub value: Value,
+    // Replies are encoded here before being written
+    output: BytesMut,
+    // How replies are encoded
+    pub protocol: Protocol,
+    // What frames read are allowed to claim
+    pub limits: ProtocolLimits,
 }
 
+/// How a command is called, as reported by COMMAND.
+#[derive(Debug, PartialEq)]
+pub struct CommandSpec {
+    pub command: UserCommand,
+    pub name: &'static str,
+    // Number of arguments including the name; negative means at least that many
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    // Position of the first and last key argument and the step between keys, all 0
+    // when the command takes no keys. A negative last key counts from the end.
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+    pub summary: &'static str,
+}
+
+const fn spec(
+    command: UserCommand,
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    (first_key, last_key, step): (i64, i64, i64),
+    summary: &'static str,
+) -> CommandSpec {
+    CommandSpec {
+        command,
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        summary,
+    }
+}
+
+/// A subcommand of a container command such as OBJECT or CONFIG, as listed by its HELP.
+#[derive(Debug, PartialEq)]
+pub struct SubcommandSpec {
+    pub name: &'static str,
+    // The arguments after the subcommand name, in the HELP notation
+    pub arguments: &'static str,
+    pub summary: &'static str,
+}
+
+const fn sub(name: &'static str, arguments: &'static str, summary: &'static str) -> SubcommandSpec {
+    SubcommandSpec {
+        name,
+        arguments,
+        summary,
+    }
+}
+
+const READONLY: &[&str] = &["readonly"];
+const READONLY_FAST: &[&str] = &["readonly", "fast"];
+const WRITE: &[&str] = &["write"];
+const WRITE_FAST: &[&str] = &["write", "fast"];
+const WRITE_DENYOOM: &[&str] = &["write", "denyoom"];
+const WRITE_DENYOOM_FAST: &[&str] = &["write", "denyoom", "fast"];
+const WRITE_BLOCKING: &[&str] = &["write", "noscript", "blocking"];
+const READONLY_BLOCKING: &[&str] = &["readonly", "blocking"];
+const FAST: &[&str] = &["fast"];
+const PUBSUB: &[&str] = &["pubsub", "noscript", "loading", "stale"];
+const PUBSUB_FAST: &[&str] = &["pubsub", "loading", "stale", "fast"];
+const CONNECTION: &[&str] = &["noscript", "loading", "stale", "fast"];
+const TRANSACTION: &[&str] = &["noscript", "loading", "stale"];
+const STALE: &[&str] = &["loading", "stale"];
+const STALE_FAST: &[&str] = &["loading", "stale", "fast"];
+const ADMIN: &[&str] = &["admin", "noscript"];
+const ADMIN_STALE: &[&str] = &["admin", "noscript", "loading", "stale"];
+const NOSCRIPT: &[&str] = &["noscript"];
+
+/// Every command with its canonical name, aliases after it; lookups ignore case.
+pub const COMMANDS: &[CommandSpec] = &[
+    spec(
+        UserCommand::Ping,
+        "PING",
+        -1,
+        CONNECTION,
+        (0, 0, 0),
+        "Returns the server's liveliness response.",
+    ),
+    spec(
+        UserCommand::Echo,
+        "ECHO",
+        2,
+        FAST,
+        (0, 0, 0),
+        "Returns the given string.",
+    ),
+    spec(
+        UserCommand::Get,
+        "GET",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the string value of a key.",
+    ),
+    spec(
+        UserCommand::Mget,
+        "MGET",
+        -2,
+        READONLY_FAST,
+        (1, -1, 1),
+        "Atomically returns the string values of one or more keys.",
+    ),
+    spec(
+        UserCommand::Set,
+        "SET",
+        -3,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sets the string value of a key, ignoring its type.",
+    ),
+    spec(
+        UserCommand::Del,
+        "DEL",
+        -2,
+        WRITE,
+        (1, -1, 1),
+        "Deletes one or more keys.",
+    ),
+    spec(
+        UserCommand::Exists,
+        "EXISTS",
+        -2,
+        READONLY_FAST,
+        (1, -1, 1),
+        "Determines whether one or more keys exist.",
+    ),
+    spec(
+        UserCommand::Unlink,
+        "UNLINK",
+        -2,
+        WRITE_FAST,
+        (1, -1, 1),
+        "Asynchronously deletes one or more keys.",
+    ),
+    spec(
+        UserCommand::Rename,
+        "RENAME",
+        3,
+        WRITE,
+        (1, 2, 1),
+        "Renames a key and overwrites the destination.",
+    ),
+    spec(
+        UserCommand::RenameNx,
+        "RENAMENX",
+        3,
+        WRITE_FAST,
+        (1, 2, 1),
+        "Renames a key only when the target key name doesn't exist.",
+    ),
+    spec(
+        UserCommand::Dump,
+        "DUMP",
+        2,
+        READONLY,
+        (1, 1, 1),
+        "Returns a serialized representation of the value stored at a key.",
+    ),
+    spec(
+        UserCommand::Restore,
+        "RESTORE",
+        -4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Creates a key from the serialized representation of a value.",
+    ),
+    spec(
+        UserCommand::Migrate,
+        "MIGRATE",
+        -6,
+        WRITE,
+        (3, 3, 1),
+        "Atomically transfers a key from one Redis instance to another.",
+    ),
+    spec(
+        UserCommand::Expire,
+        "EXPIRE",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Sets the expiration time of a key in seconds.",
+    ),
+    spec(
+        UserCommand::ExpireAt,
+        "EXPIREAT",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Sets the expiration time of a key to a Unix timestamp.",
+    ),
+    spec(
+        UserCommand::PExpireAt,
+        "PEXPIREAT",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+    ),
+    spec(
+        UserCommand::Persist,
+        "PERSIST",
+        2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Removes the expiration time of a key.",
+    ),
+    spec(
+        UserCommand::Ttl,
+        "TTL",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the expiration time in seconds of a key.",
+    ),
+    spec(
+        UserCommand::Pttl,
+        "PTTL",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the expiration time in milliseconds of a key.",
+    ),
+    spec(
+        UserCommand::Incr,
+        "INCR",
+        2,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Increments the integer value of a key by one.",
+    ),
+    spec(
+        UserCommand::Decr,
+        "DECR",
+        2,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Decrements the integer value of a key by one.",
+    ),
+    spec(
+        UserCommand::IncrBy,
+        "INCRBY",
+        3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Increments the integer value of a key by a number.",
+    ),
+    spec(
+        UserCommand::DecrBy,
+        "DECRBY",
+        3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Decrements a number from the integer value of a key.",
+    ),
+    spec(
+        UserCommand::Append,
+        "APPEND",
+        3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Appends a string to the value of a key.",
+    ),
+    spec(
+        UserCommand::Strlen,
+        "STRLEN",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the length of a string value.",
+    ),
+    spec(
+        UserCommand::GetRange,
+        "GETRANGE",
+        4,
+        READONLY,
+        (1, 1, 1),
+        "Returns a substring of the string stored at a key.",
+    ),
+    spec(
+        UserCommand::SetRange,
+        "SETRANGE",
+        4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Overwrites a part of a string value from an offset.",
+    ),
+    spec(
+        UserCommand::GetDel,
+        "GETDEL",
+        2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the string value of a key after deleting the key.",
+    ),
+    spec(
+        UserCommand::GetEx,
+        "GETEX",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the string value of a key after setting its expiration time.",
+    ),
+    spec(
+        UserCommand::GetSet,
+        "GETSET",
+        3,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Returns the previous string value of a key after setting it to a new value.",
+    ),
+    spec(
+        UserCommand::SetNx,
+        "SETNX",
+        3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Set the string value of a key only when the key doesn't exist.",
+    ),
+    spec(
+        UserCommand::SetEx,
+        "SETEX",
+        4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.",
+    ),
+    spec(
+        UserCommand::PSetEx,
+        "PSETEX",
+        4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist.",
+    ),
+    spec(
+        UserCommand::SetBit,
+        "SETBIT",
+        4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist.",
+    ),
+    spec(
+        UserCommand::GetBit,
+        "GETBIT",
+        3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns a bit value by offset.",
+    ),
+    spec(
+        UserCommand::BitCount,
+        "BITCOUNT",
+        -2,
+        READONLY,
+        (1, 1, 1),
+        "Counts the number of set bits (population counting) in a string.",
+    ),
+    spec(
+        UserCommand::BitOp,
+        "BITOP",
+        -4,
+        WRITE_DENYOOM,
+        (2, -1, 1),
+        "Performs bitwise operations on multiple strings, and stores the result.",
+    ),
+    spec(
+        UserCommand::BitPos,
+        "BITPOS",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Finds the first set (1) or clear (0) bit in a string.",
+    ),
+    spec(
+        UserCommand::Keys,
+        "KEYS",
+        2,
+        READONLY,
+        (0, 0, 0),
+        "Returns all key names that match a pattern.",
+    ),
+    spec(
+        UserCommand::Scan,
+        "SCAN",
+        -2,
+        READONLY,
+        (0, 0, 0),
+        "Iterates over the key names in the database.",
+    ),
+    spec(
+        UserCommand::HSet,
+        "HSET",
+        -4,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Creates or modifies the value of fields in a hash.",
+    ),
+    spec(
+        UserCommand::HGet,
+        "HGET",
+        3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the value of a field in a hash.",
+    ),
+    spec(
+        UserCommand::HMGet,
+        "HMGET",
+        -3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the values of all fields in a hash.",
+    ),
+    spec(
+        UserCommand::HDel,
+        "HDEL",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Deletes one or more fields from a hash.",
+    ),
+    spec(
+        UserCommand::HGetAll,
+        "HGETALL",
+        2,
+        READONLY,
+        (1, 1, 1),
+        "Returns all fields and values in a hash.",
+    ),
+    spec(
+        UserCommand::HScan,
+        "HSCAN",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Iterates over fields and values of a hash.",
+    ),
+    spec(
+        UserCommand::HExists,
+        "HEXISTS",
+        3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Determines whether a field exists in a hash.",
+    ),
+    spec(
+        UserCommand::HLen,
+        "HLEN",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the number of fields in a hash.",
+    ),
+    spec(
+        UserCommand::HRandField,
+        "HRANDFIELD",
+        -2,
+        READONLY,
+        (1, 1, 1),
+        "Returns one or more random fields from a hash.",
+    ),
+    spec(
+        UserCommand::SAdd,
+        "SADD",
+        -3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Adds one or more members to a set.",
+    ),
+    spec(
+        UserCommand::SRem,
+        "SREM",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Removes one or more members from a set.",
+    ),
+    spec(
+        UserCommand::SMembers,
+        "SMEMBERS",
+        2,
+        READONLY,
+        (1, 1, 1),
+        "Returns all members of a set.",
+    ),
+    spec(
+        UserCommand::SIsMember,
+        "SISMEMBER",
+        3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Determines whether a member belongs to a set.",
+    ),
+    spec(
+        UserCommand::SCard,
+        "SCARD",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the number of members in a set.",
+    ),
+    spec(
+        UserCommand::SPop,
+        "SPOP",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns one or more random members from a set after removing them.",
+    ),
+    spec(
+        UserCommand::SRandMember,
+        "SRANDMEMBER",
+        -2,
+        READONLY,
+        (1, 1, 1),
+        "Get one or multiple random members from a set.",
+    ),
+    spec(
+        UserCommand::SScan,
+        "SSCAN",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Iterates over members of a set.",
+    ),
+    spec(
+        UserCommand::SInter,
+        "SINTER",
+        -2,
+        READONLY,
+        (1, -1, 1),
+        "Returns the intersection of multiple sets.",
+    ),
+    spec(
+        UserCommand::SUnion,
+        "SUNION",
+        -2,
+        READONLY,
+        (1, -1, 1),
+        "Returns the union of multiple sets.",
+    ),
+    spec(
+        UserCommand::SDiff,
+        "SDIFF",
+        -2,
+        READONLY,
+        (1, -1, 1),
+        "Returns the difference of multiple sets.",
+    ),
+    spec(
+        UserCommand::ZAdd,
+        "ZADD",
+        -4,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Adds one or more members to a sorted set, or updates their scores.",
+    ),
+    spec(
+        UserCommand::ZRem,
+        "ZREM",
+        -3,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Removes one or more members from a sorted set.",
+    ),
+    spec(
+        UserCommand::ZScore,
+        "ZSCORE",
+        3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the score of a member in a sorted set.",
+    ),
+    spec(
+        UserCommand::ZCard,
+        "ZCARD",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the number of members in a sorted set.",
+    ),
+    spec(
+        UserCommand::ZScan,
+        "ZSCAN",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Iterates over members and scores of a sorted set.",
+    ),
+    spec(
+        UserCommand::ZRank,
+        "ZRANK",
+        -3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the index of a member in a sorted set ordered by ascending scores.",
+    ),
+    spec(
+        UserCommand::ZRevRank,
+        "ZREVRANK",
+        -3,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the index of a member in a sorted set ordered by descending scores.",
+    ),
+    spec(
+        UserCommand::ZRange,
+        "ZRANGE",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns members in a sorted set within a range of indexes.",
+    ),
+    spec(
+        UserCommand::ZRevRange,
+        "ZREVRANGE",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns members in a sorted set within a range of indexes in reverse order.",
+    ),
+    spec(
+        UserCommand::ZIncrBy,
+        "ZINCRBY",
+        4,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Increments the score of a member in a sorted set.",
+    ),
+    spec(
+        UserCommand::ZCount,
+        "ZCOUNT",
+        4,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the count of members in a sorted set that have scores within a range.",
+    ),
+    spec(
+        UserCommand::ZRangeByScore,
+        "ZRANGEBYSCORE",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns members in a sorted set within a range of scores.",
+    ),
+    spec(
+        UserCommand::ZRangeByLex,
+        "ZRANGEBYLEX",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns members in a sorted set within a lexicographical range.",
+    ),
+    spec(
+        UserCommand::ZPopMin,
+        "ZPOPMIN",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    ),
+    spec(
+        UserCommand::ZPopMax,
+        "ZPOPMAX",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    ),
+    spec(
+        UserCommand::GeoAdd,
+        "GEOADD",
+        -5,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Adds one or more members to a geospatial index. The key is created if it doesn't exist.",
+    ),
+    spec(
+        UserCommand::GeoDist,
+        "GEODIST",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns the distance between two members of a geospatial index.",
+    ),
+    spec(
+        UserCommand::GeoSearch,
+        "GEOSEARCH",
+        -7,
+        READONLY,
+        (1, 1, 1),
+        "Queries a geospatial index for members inside an area of a box or a circle.",
+    ),
+    spec(
+        UserCommand::LPush,
+        "LPUSH",
+        -3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Prepends one or more elements to a list.",
+    ),
+    spec(
+        UserCommand::RPush,
+        "RPUSH",
+        -3,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Appends one or more elements to a list.",
+    ),
+    spec(
+        UserCommand::LPop,
+        "LPOP",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the first elements in a list after removing it.",
+    ),
+    spec(
+        UserCommand::RPop,
+        "RPOP",
+        -2,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns and removes the last elements of a list.",
+    ),
+    spec(
+        UserCommand::LLen,
+        "LLEN",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Returns the length of a list.",
+    ),
+    spec(
+        UserCommand::LRange,
+        "LRANGE",
+        4,
+        READONLY,
+        (1, 1, 1),
+        "Returns a range of elements from a list.",
+    ),
+    spec(
+        UserCommand::LPos,
+        "LPOS",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Returns the index of matching elements in a list.",
+    ),
+    spec(
+        UserCommand::LInsert,
+        "LINSERT",
+        5,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Inserts an element before or after another element in a list.",
+    ),
+    spec(
+        UserCommand::LSet,
+        "LSET",
+        4,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sets the value of an element in a list by its index.",
+    ),
+    spec(
+        UserCommand::LRem,
+        "LREM",
+        4,
+        WRITE,
+        (1, 1, 1),
+        "Removes elements from a list.",
+    ),
+    spec(
+        UserCommand::LTrim,
+        "LTRIM",
+        4,
+        WRITE,
+        (1, 1, 1),
+        "Removes elements from both ends of a list. Deletes the list if all elements were trimmed.",
+    ),
+    spec(
+        UserCommand::RPopLPush,
+        "RPOPLPUSH",
+        3,
+        WRITE_DENYOOM,
+        (1, 2, 1),
+        "Returns the last element of a list after removing and pushing it to another list.",
+    ),
+    spec(
+        UserCommand::LMove,
+        "LMOVE",
+        5,
+        WRITE_DENYOOM,
+        (1, 2, 1),
+        "Returns an element after popping it from one list and pushing it to another.",
+    ),
+    spec(
+        UserCommand::BLPop,
+        "BLPOP",
+        -3,
+        WRITE_BLOCKING,
+        (1, -2, 1),
+        "Removes and returns the first element in a list. Blocks until an element is available otherwise.",
+    ),
+    spec(
+        UserCommand::BRPop,
+        "BRPOP",
+        -3,
+        WRITE_BLOCKING,
+        (1, -2, 1),
+        "Removes and returns the last element in a list. Blocks until an element is available otherwise.",
+    ),
+    spec(
+        UserCommand::BLMove,
+        "BLMOVE",
+        6,
+        WRITE_BLOCKING,
+        (1, 2, 1),
+        "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise.",
+    ),
+    spec(
+        UserCommand::BZPopMin,
+        "BZPOPMIN",
+        -3,
+        WRITE_BLOCKING,
+        (1, -2, 1),
+        "Removes and returns the member with the lowest score from one or more sorted sets. Blocks until a member is available otherwise.",
+    ),
+    spec(
+        UserCommand::BZPopMax,
+        "BZPOPMAX",
+        -3,
+        WRITE_BLOCKING,
+        (1, -2, 1),
+        "Removes and returns the member with the highest score from one or more sorted sets. Blocks until a member is available otherwise.",
+    ),
+    spec(
+        UserCommand::XAdd,
+        "XADD",
+        -5,
+        WRITE_DENYOOM_FAST,
+        (1, 1, 1),
+        "Appends a new message to a stream. Creates the key if it doesn't exist.",
+    ),
+    spec(
+        UserCommand::XLen,
+        "XLEN",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Return the number of messages in a stream.",
+    ),
+    spec(
+        UserCommand::XRange,
+        "XRANGE",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns the messages from a stream within a range of IDs.",
+    ),
+    spec(
+        UserCommand::XRevRange,
+        "XREVRANGE",
+        -4,
+        READONLY,
+        (1, 1, 1),
+        "Returns the messages from a stream within a range of IDs in reverse order.",
+    ),
+    spec(
+        UserCommand::XRead,
+        "XREAD",
+        -4,
+        READONLY_BLOCKING,
+        (0, 0, 0),
+        "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.",
+    ),
+    spec(
+        UserCommand::XGroup,
+        "XGROUP",
+        -2,
+        WRITE,
+        (2, 2, 1),
+        "Creates, destroys or changes a consumer group, or its consumers.",
+    ),
+    spec(
+        UserCommand::XReadGroup,
+        "XREADGROUP",
+        -7,
+        WRITE_BLOCKING,
+        (0, 0, 0),
+        "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.",
+    ),
+    spec(
+        UserCommand::XAck,
+        "XACK",
+        -4,
+        WRITE_FAST,
+        (1, 1, 1),
+        "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+    ),
+    spec(
+        UserCommand::XPending,
+        "XPENDING",
+        -3,
+        READONLY,
+        (1, 1, 1),
+        "Returns the information and entries from a stream consumer group's pending entries list.",
+    ),
+    spec(
+        UserCommand::Subscribe,
+        "SUBSCRIBE",
+        -2,
+        PUBSUB,
+        (0, 0, 0),
+        "Listens for messages published to channels.",
+    ),
+    spec(
+        UserCommand::Unsubscribe,
+        "UNSUBSCRIBE",
+        -1,
+        PUBSUB,
+        (0, 0, 0),
+        "Stops listening to messages posted to channels.",
+    ),
+    spec(
+        UserCommand::PSubscribe,
+        "PSUBSCRIBE",
+        -2,
+        PUBSUB,
+        (0, 0, 0),
+        "Listens for messages published to channels that match one or more patterns.",
+    ),
+    spec(
+        UserCommand::PUnsubscribe,
+        "PUNSUBSCRIBE",
+        -1,
+        PUBSUB,
+        (0, 0, 0),
+        "Stops listening to messages published to channels that match one or more patterns.",
+    ),
+    spec(
+        UserCommand::Publish,
+        "PUBLISH",
+        3,
+        PUBSUB_FAST,
+        (0, 0, 0),
+        "Posts a message to a channel.",
+    ),
+    spec(
+        UserCommand::Multi,
+        "MULTI",
+        1,
+        CONNECTION,
+        (0, 0, 0),
+        "Starts a transaction.",
+    ),
+    spec(
+        UserCommand::Exec,
+        "EXEC",
+        1,
+        TRANSACTION,
+        (0, 0, 0),
+        "Executes all commands in a transaction.",
+    ),
+    spec(
+        UserCommand::Discard,
+        "DISCARD",
+        1,
+        CONNECTION,
+        (0, 0, 0),
+        "Discards a transaction.",
+    ),
+    spec(
+        UserCommand::Watch,
+        "WATCH",
+        -2,
+        CONNECTION,
+        (1, -1, 1),
+        "Monitors changes to keys to determine the execution of a transaction.",
+    ),
+    spec(
+        UserCommand::Unwatch,
+        "UNWATCH",
+        1,
+        CONNECTION,
+        (0, 0, 0),
+        "Forgets about watched keys of a transaction.",
+    ),
+    spec(
+        UserCommand::Auth,
+        "AUTH",
+        -2,
+        CONNECTION,
+        (0, 0, 0),
+        "Authenticates the connection.",
+    ),
+    spec(
+        UserCommand::Hello,
+        "HELLO",
+        -1,
+        CONNECTION,
+        (0, 0, 0),
+        "Handshakes with the server, optionally switching the protocol version.",
+    ),
+    spec(
+        UserCommand::Config,
+        "CONFIG",
+        -2,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Gets or sets configuration parameters.",
+    ),
+    spec(
+        UserCommand::Select,
+        "SELECT",
+        2,
+        CONNECTION,
+        (0, 0, 0),
+        "Changes the selected database.",
+    ),
+    spec(
+        UserCommand::SwapDb,
+        "SWAPDB",
+        3,
+        WRITE_FAST,
+        (0, 0, 0),
+        "Swaps two Redis databases.",
+    ),
+    spec(
+        UserCommand::FlushDb,
+        "FLUSHDB",
+        -1,
+        WRITE,
+        (0, 0, 0),
+        "Removes all keys from the current database.",
+    ),
+    spec(
+        UserCommand::FlushAll,
+        "FLUSHALL",
+        -1,
+        WRITE,
+        (0, 0, 0),
+        "Removes all keys from all databases.",
+    ),
+    spec(
+        UserCommand::DbSize,
+        "DBSIZE",
+        1,
+        READONLY_FAST,
+        (0, 0, 0),
+        "Returns the number of keys in the database.",
+    ),
+    spec(
+        UserCommand::RandomKey,
+        "RANDOMKEY",
+        1,
+        READONLY,
+        (0, 0, 0),
+        "Returns a random key name from the database.",
+    ),
+    spec(
+        UserCommand::Type,
+        "TYPE",
+        2,
+        READONLY_FAST,
+        (1, 1, 1),
+        "Determines the type of value stored at a key.",
+    ),
+    spec(
+        UserCommand::Sort,
+        "SORT",
+        -2,
+        WRITE_DENYOOM,
+        (1, 1, 1),
+        "Sorts the elements in a list, a set, or a sorted set, optionally storing the result.",
+    ),
+    spec(
+        UserCommand::Object,
+        "OBJECT",
+        -2,
+        READONLY,
+        (2, 2, 1),
+        "Returns the internal encoding, idle time or reference count of a key's value.",
+    ),
+    spec(
+        UserCommand::Memory,
+        "MEMORY",
+        -2,
+        READONLY,
+        (2, 2, 1),
+        "Reports the memory used by a key or by the whole dataset.",
+    ),
+    spec(
+        UserCommand::Save,
+        "SAVE",
+        1,
+        ADMIN,
+        (0, 0, 0),
+        "Synchronously saves the database(s) to disk.",
+    ),
+    spec(
+        UserCommand::BgSave,
+        "BGSAVE",
+        1,
+        ADMIN,
+        (0, 0, 0),
+        "Asynchronously saves the database(s) to disk.",
+    ),
+    spec(
+        UserCommand::BgRewriteAof,
+        "BGREWRITEAOF",
+        1,
+        ADMIN,
+        (0, 0, 0),
+        "Asynchronously rewrites the append-only file to disk.",
+    ),
+    spec(
+        UserCommand::ReplicaOf,
+        "REPLICAOF",
+        3,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Configures a server as replica of another, or promotes it to a master.",
+    ),
+    spec(
+        UserCommand::ReplicaOf,
+        "SLAVEOF",
+        3,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Sets a Redis server as a replica of another, or promotes it to being a master.",
+    ),
+    spec(
+        UserCommand::ReplConf,
+        "REPLCONF",
+        -1,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "An internal command for configuring the replication stream.",
+    ),
+    spec(
+        UserCommand::Psync,
+        "PSYNC",
+        -3,
+        ADMIN,
+        (0, 0, 0),
+        "An internal command used in replication.",
+    ),
+    spec(
+        UserCommand::Wait,
+        "WAIT",
+        3,
+        NOSCRIPT,
+        (0, 0, 0),
+        "Blocks until the asynchronous replication of all preceding write commands is acknowledged.",
+    ),
+    spec(
+        UserCommand::Failover,
+        "FAILOVER",
+        -1,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Starts a coordinated failover from a server to one of its replicas.",
+    ),
+    spec(
+        UserCommand::Command,
+        "COMMAND",
+        -1,
+        CONNECTION,
+        (0, 0, 0),
+        "Returns detailed information about all commands.",
+    ),
+    spec(
+        UserCommand::Info,
+        "INFO",
+        -1,
+        STALE,
+        (0, 0, 0),
+        "Returns information and statistics about the server.",
+    ),
+    spec(
+        UserCommand::Lolwut,
+        "LOLWUT",
+        -1,
+        READONLY_FAST,
+        (0, 0, 0),
+        "Displays computer art and the server version.",
+    ),
+    spec(
+        UserCommand::Time,
+        "TIME",
+        1,
+        STALE_FAST,
+        (0, 0, 0),
+        "Returns the server time.",
+    ),
+    spec(
+        UserCommand::Client,
+        "CLIENT",
+        -2,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Manages the connections of the server.",
+    ),
+    spec(
+        UserCommand::Acl,
+        "ACL",
+        -2,
+        NOSCRIPT,
+        (0, 0, 0),
+        "Manages the users and what each of them may run.",
+    ),
+    spec(
+        UserCommand::Cluster,
+        "CLUSTER",
+        -2,
+        ADMIN,
+        (0, 0, 0),
+        "Manages the slots and nodes of a cluster.",
+    ),
+    spec(
+        UserCommand::Asking,
+        "ASKING",
+        1,
+        CONNECTION,
+        (0, 0, 0),
+        "Signals that a cluster client is following an -ASK redirect.",
+    ),
+    spec(
+        UserCommand::Monitor,
+        "MONITOR",
+        1,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Listens for all requests received by the server in real-time.",
+    ),
+    spec(
+        UserCommand::Shutdown,
+        "SHUTDOWN",
+        -1,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Saves the data set if asked to and shuts down the server.",
+    ),
+    spec(
+        UserCommand::Debug,
+        "DEBUG",
+        -2,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Debugging helpers such as DEBUG SLEEP.",
+    ),
+    spec(
+        UserCommand::Latency,
+        "LATENCY",
+        -2,
+        ADMIN_STALE,
+        (0, 0, 0),
+        "Reports latency spikes recorded by the latency monitor.",
+    ),
+    spec(
+        UserCommand::Quit,
+        "QUIT",
+        -1,
+        CONNECTION,
+        (0, 0, 0),
+        "Closes the connection.",
+    ),
+    spec(
+        UserCommand::Reset,
+        "RESET",
+        1,
+        CONNECTION,
+        (0, 0, 0),
+        "Resets the connection.",
+    ),
+];
+
+/// The subcommands of every container command, in the order HELP lists them. HELP
+/// itself is left out, since every command here answers it.
+pub const SUBCOMMANDS: &[(UserCommand, &[SubcommandSpec])] = &[
+    (
+        UserCommand::Object,
+        &[
+            sub(
+                "ENCODING",
+                "<key>",
+                "Returns the internal encoding of the value stored at <key>.",
+            ),
+            sub(
+                "FREQ",
+                "<key>",
+                "Returns the access frequency counter of <key>, under an LFU policy.",
+            ),
+            sub(
+                "IDLETIME",
+                "<key>",
+                "Returns the seconds since <key> was last accessed, under an LRU policy.",
+            ),
+            sub(
+                "REFCOUNT",
+                "<key>",
+                "Returns the reference count of the value stored at <key>.",
+            ),
+        ],
+    ),
+    (
+        UserCommand::Client,
+        &[
+            sub("ID", "", "Returns the unique id of the connection."),
+            sub("GETNAME", "", "Returns the name of the connection."),
+            sub(
+                "SETNAME",
+                "<name>",
+                "Names the connection, or clears its name.",
+            ),
+            sub("LIST", "", "Lists the open connections."),
+            sub(
+                "KILL",
+                "<ip:port> | ID <id> | ADDR <ip:port>",
+                "Closes the matching connections.",
+            ),
+        ],
+    ),
+    (
+        UserCommand::Config,
+        &[
+            sub(
+                "GET",
+                "<pattern> [<pattern> ...]",
+                "Returns the parameters matching the glob-style patterns and their values.",
+            ),
+            sub(
+                "SET",
+                "<parameter> <value> [<parameter> <value> ...]",
+                "Sets parameters at runtime.",
+            ),
+            sub("RESETSTAT", "", "Resets the statistics reported by INFO."),
+        ],
+    ),
+    (
+        UserCommand::Cluster,
+        &[
+            sub(
+                "INFO",
+                "",
+                "Returns information about the state of the cluster.",
+            ),
+            sub("MYID", "", "Returns the id of this node."),
+            sub("KEYSLOT", "<key>", "Returns the hash slot of <key>."),
+            sub("SLOTS", "", "Returns the mapping of slot ranges to nodes."),
+            sub(
+                "SHARDS",
+                "",
+                "Returns the shards of the cluster with their nodes.",
+            ),
+            sub(
+                "ADDSLOTS",
+                "<slot> [<slot> ...]",
+                "Assigns slots to this node.",
+            ),
+            sub(
+                "ADDSLOTSRANGE",
+                "<start> <end> [<start> <end> ...]",
+                "Assigns ranges of slots to this node.",
+            ),
+            sub(
+                "DELSLOTS",
+                "<slot> [<slot> ...]",
+                "Removes slots from this node.",
+            ),
+            sub("MEET", "<ip> <port>", "Adds a node to the cluster."),
+            sub(
+                "SETSLOT",
+                "<slot> STABLE | NODE <id> | MIGRATING <id> | IMPORTING <id>",
+                "Binds a slot to a node or changes its migration state.",
+            ),
+            sub(
+                "COUNTKEYSINSLOT",
+                "<slot>",
+                "Returns the number of keys in <slot>.",
+            ),
+            sub(
+                "GETKEYSINSLOT",
+                "<slot> <count>",
+                "Returns up to <count> key names in <slot>.",
+            ),
+        ],
+    ),
+    (
+        UserCommand::Command,
+        &[
+            sub("COUNT", "", "Returns the number of commands."),
+            sub(
+                "INFO",
+                "[<command-name> ...]",
+                "Returns details about the given commands, or all of them.",
+            ),
+            sub(
+                "DOCS",
+                "[<command-name> ...]",
+                "Returns documentary information about the given commands, or all of them.",
+            ),
+        ],
+    ),
+    (
+        UserCommand::Memory,
+        &[
+            sub(
+                "USAGE",
+                "<key> [SAMPLES <count>]",
+                "Estimates the memory used by <key> and its value.",
+            ),
+            sub("STATS", "", "Breaks down the memory used by the dataset."),
+        ],
+    ),
+    (
+        UserCommand::Latency,
+        &[
+            sub("LATEST", "", "Returns the latest spike of every event."),
+            sub(
+                "HISTORY",
+                "<event>",
+                "Returns the spikes recorded for <event>.",
+            ),
+            sub(
+                "RESET",
+                "[<event> ...]",
+                "Forgets the spikes of the given events, or all of them.",
+            ),
+            sub("DOCTOR", "", "Returns a report on the recorded spikes."),
+        ],
+    ),
+    (
+        UserCommand::Acl,
+        &[
+            sub(
+                "WHOAMI",
+                "",
+                "Returns the user the connection is authenticated as.",
+            ),
+            sub("USERS", "", "Lists the usernames."),
+            sub("LIST", "", "Lists the users with their rules."),
+            sub(
+                "SETUSER",
+                "<username> [<rule> ...]",
+                "Creates a user or changes its rules.",
+            ),
+            sub("DELUSER", "<username> [<username> ...]", "Deletes users."),
+            sub("GETUSER", "<username>", "Returns the rules of a user."),
+            sub(
+                "CAT",
+                "[<category>]",
+                "Lists the categories, or the commands in <category>.",
+            ),
+        ],
+    ),
+    (
+        UserCommand::XGroup,
+        &[
+            sub(
+                "CREATE",
+                "<key> <group> <id | $> [MKSTREAM]",
+                "Creates a consumer group.",
+            ),
+            sub(
+                "SETID",
+                "<key> <group> <id | $>",
+                "Sets the last delivered id of a consumer group.",
+            ),
+            sub("DESTROY", "<key> <group>", "Deletes a consumer group."),
+            sub(
+                "CREATECONSUMER",
+                "<key> <group> <consumer>",
+                "Creates a consumer in a consumer group.",
+            ),
+            sub(
+                "DELCONSUMER",
+                "<key> <group> <consumer>",
+                "Deletes a consumer from a consumer group.",
+            ),
+        ],
+    ),
+];
+
 impl UserCommand {
     pub fn from(command: String) -> Self {
-        match command.to_uppercase().as_str() {
-            "PING" => Self::Ping,
-            "ECHO" => Self::Echo,
-            "GET" => Self::Get,
-            "MGET" => Self::Mget,
-            "SET" => Self::Set,
-            "DEL" => Self::Del,
-            "EXPIRE" => Self::Expire,
-            "QUIT" => Self::Quit,
+        CommandSpec::lookup(&command).map_or(Self::Invalid, |spec| spec.command)
+    }
+
+    /// The canonical upper-case name, empty for `Invalid`.
+    pub fn name(&self) -> &'static str {
+        self.spec().map_or("", |spec| spec.name)
+    }
+
+    pub fn spec(&self) -> Option<&'static CommandSpec> {
+        COMMANDS.iter().find(|spec| spec.command == *self)
+    }
+
+    /// The subcommands HELP lists, empty for commands without any.
+    pub fn subcommands(&self) -> &'static [SubcommandSpec] {
+        SUBCOMMANDS
+            .iter()
+            .find(|(command, _)| command == self)
+            .map_or(&[], |(_, subcommands)| subcommands)
+    }
+
+    /// Whether the command can modify the keyspace, so it must be persisted.
+    pub fn is_write(&self) -> bool {
+        self.spec()
+            .is_some_and(|spec| spec.flags.contains(&"write"))
+    }
+
+    /// Whether the command may run on a replica that lost its master while
+    /// replica-serve-stale-data is off.
+    pub fn is_stale(&self) -> bool {
+        self.spec()
+            .is_some_and(|spec| spec.flags.contains(&"stale"))
+    }
+
+    /// Whether the command may grow the dataset, so it is refused when over maxmemory.
+    pub fn is_denyoom(&self) -> bool {
+        self.spec()
+            .is_some_and(|spec| spec.flags.contains(&"denyoom"))
+    }
+}
+
+impl CommandSpec {
+    /// Finds a command or alias by name, ignoring case.
+    pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|spec| spec.name.eq_ignore_ascii_case(name))
+    }
 
-            _ => Self::Invalid,
+    /// Whether the command can be called with `count` arguments, its name included.
+    pub fn accepts(&self, count: usize) -> bool {
+        match self.arity {
+            arity if arity < 0 => count as i64 >= -arity,
+            arity => count as i64 == arity,
         }
     }
 }
 
 impl Value {
-    pub fn serialize(self) -> String {
+    /// Encodes the value as RESP2, the protocol every connection starts with and the
+    /// one used for the AOF and the replication stream.
+    pub fn serialize(&self) -> Bytes {
+        self.serialize_as(Protocol::Resp2)
+    }
+
+    pub fn serialize_as(&self, protocol: Protocol) -> Bytes {
+        let mut serialized = BytesMut::new();
+        self.encode_as(protocol, &mut serialized);
+        serialized.freeze()
+    }
+
+    /// Appends the RESP2 encoding of the value to `out`. Payloads are copied straight
+    /// from the value, so replies can be encoded without cloning or consuming them.
+    pub fn encode(&self, out: &mut BytesMut) {
+        self.encode_as(Protocol::Resp2, out)
+    }
+
+    pub fn encode_as(&self, protocol: Protocol, out: &mut BytesMut) {
+        let resp3 = protocol == Protocol::Resp3;
         match self {
-            Value::SimpleString(s) => format!("+{}\r\n", s),
-            Value::BulkString(s) => format!("${}\r\n{}\r\n", s.chars().count(), s),
-            Value::Array(arr) => Value::serialize_array(arr),
-            Value::SimpleError(s) => format!("-{}\r\n", s),
-            _ => panic!("Unsupported value"),
+            Value::SimpleString(s) => encode_line(b'+', s, out),
+            Value::BulkString(s) => encode_bulk_string(s, out),
+            Value::Array(arr) => encode_aggregate(b'*', arr.len(), arr, protocol, out),
+            Value::SimpleError(s) => encode_line(b'-', s, out),
+            Value::Integer(n) => encode_header(b':', *n, out),
+            // RESP3 has a single null for both
+            Value::Null | Value::NullArray if resp3 => out.extend_from_slice(b"_\r\n"),
+            Value::Null => out.extend_from_slice(b"$-1\r\n"),
+            Value::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            Value::Map(pairs) if resp3 => {
+                encode_header(b'%', pairs.len(), out);
+                for (key, value) in pairs {
+                    key.encode_as(protocol, out);
+                    value.encode_as(protocol, out);
+                }
+            }
+            Value::Map(pairs) => {
+                let flat = pairs.iter().flat_map(|(key, value)| [key, value]);
+                encode_aggregate(b'*', pairs.len() * 2, flat, protocol, out)
+            }
+            Value::Set(items) => {
+                let prefix = if resp3 { b'~' } else { b'*' };
+                encode_aggregate(prefix, items.len(), items, protocol, out)
+            }
+            Value::Push(items) => {
+                let prefix = if resp3 { b'>' } else { b'*' };
+                encode_aggregate(prefix, items.len(), items, protocol, out)
+            }
+            Value::Double(n) if resp3 => encode_line(b',', &format_double(*n), out),
+            Value::Double(n) => encode_bulk_string(format_double(*n).as_bytes(), out),
+            Value::Boolean(b) if resp3 => {
+                out.extend_from_slice(if *b { b"#t\r\n" } else { b"#f\r\n" })
+            }
+            Value::Boolean(b) => encode_header(b':', *b as i64, out),
+            Value::BigNumber(n) if resp3 => encode_line(b'(', n, out),
+            Value::BigNumber(n) => encode_bulk_string(n.as_bytes(), out),
         }
     }
+}
 
-    fn serialize_array(arr: Vec<Value>) -> String {
-        let mut serialized = format!("*{}\r\n", arr.len());
-        for value in arr {
-            serialized.push_str(&value.serialize());
-        }
-        serialized
+// A type prefix followed by a number, the header of most frames
+fn encode_header(prefix: u8, number: impl std::fmt::Display, out: &mut BytesMut) {
+    use std::fmt::Write;
+    out.put_u8(prefix);
+    // Formatting straight into the buffer cannot fail
+    let _ = write!(out, "{}\r\n", number);
+}
+
+fn encode_line(prefix: u8, line: &str, out: &mut BytesMut) {
+    out.put_u8(prefix);
+    out.extend_from_slice(line.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+// Bulk strings are length-prefixed by their byte count so any payload round-trips
+fn encode_bulk_string(bytes: &[u8], out: &mut BytesMut) {
+    encode_header(b'$', bytes.len(), out);
+    out.extend_from_slice(bytes);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn encode_aggregate<'a>(
+    prefix: u8,
+    len: usize,
+    items: impl IntoIterator<Item = &'a Value>,
+    protocol: Protocol,
+    out: &mut BytesMut,
+) {
+    encode_header(prefix, len, out);
+    for item in items {
+        item.encode_as(protocol, out);
     }
 }
 
-impl RespHandler {
-    pub fn new(socket: TcpStream) -> Self {
+// Infinities are spelled out as RESP3 expects
+fn format_double(n: f64) -> String {
+    if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else if n.is_nan() {
+        "nan".to_owned()
+    } else {
+        n.to_string()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RespHandler<S> {
+    pub fn new(socket: S) -> Self {
         Self {
             socket,
             buffer: BytesMut::with_capacity(512),
-            value: Value::InvalidValue,
+            output: BytesMut::with_capacity(512),
+            protocol: Protocol::default(),
+            limits: ProtocolLimits::default(),
         }
     }
 
+    /// Reads the next complete frame from the socket. Bytes are accumulated in
+    /// `buffer` until a whole frame is available, and only the bytes belonging to
+    /// that frame are consumed, so frames split across TCP segments are handled.
     pub async fn read_value(&mut self) -> Result<Option<Value>> {
-        let bytes_read = self.socket.read_buf(&mut self.buffer).await?;
-        if bytes_read == 0 {
+        self.read_frame(parse_message_within).await
+    }
+
+    async fn read_frame(
+        &mut self,
+        parse: fn(&[u8], &ProtocolLimits) -> Result<ParseStatus>,
+    ) -> Result<Option<Value>> {
+        loop {
+            if !self.buffer.is_empty() {
+                if let ParseStatus::Complete(value, consumed) = parse(&self.buffer, &self.limits)? {
+                    self.buffer.advance(consumed);
+                    return Ok(Some(value));
+                }
+            }
+
+            let bytes_read = self.socket.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(RespError::UnexpectedEof);
+            }
+        }
+    }
+
+    /// Waits for at least one complete command, then drains every other command that
+    /// is already buffered so pipelined commands are handled in a single pass.
+    /// Commands are parsed with `parse_request`, so inline ones are accepted too.
+    pub async fn read_values(&mut self) -> Result<Option<Vec<Value>>> {
+        let Some(first) = self.read_frame(parse_request_within).await? else {
             return Ok(None);
+        };
+
+        let mut values = vec![first];
+        while !self.buffer.is_empty() {
+            match parse_request_within(&self.buffer, &self.limits)? {
+                ParseStatus::Complete(value, consumed) => {
+                    self.buffer.advance(consumed);
+                    values.push(value);
+                }
+                ParseStatus::NeedMoreData => break,
+            }
+        }
+        Ok(Some(values))
+    }
+
+    /// Reads a `$<length>\r\n` header followed by exactly that many raw bytes without a
+    /// trailing CRLF, the framing a master uses to send its snapshot to a replica.
+    pub async fn read_payload(&mut self) -> Result<Bytes> {
+        let length = loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let length = self.buffer[..end]
+                    .strip_prefix(b"$")
+                    .and_then(|digits| std::str::from_utf8(digits).ok()?.parse::<usize>().ok())
+                    .ok_or(RespError::InvalidPayloadHeader)?;
+                self.buffer.advance(end + 2);
+                break length;
+            }
+            self.fill_buffer().await?;
+        };
+        while self.buffer.len() < length {
+            self.fill_buffer().await?;
         }
-        // dbg!(&self.buffer);
-        let (value, _) = parse_message(self.buffer.split())?;
-        Ok(Some(value))
+        Ok(self.buffer.split_to(length).freeze())
     }
 
-    pub async fn write_value(&mut self, value: Value) -> Result<()> {
-        // dbg!(&value);
-        self.socket.write_all(value.serialize().as_bytes()).await?;
+    async fn fill_buffer(&mut self) -> Result<()> {
+        if self.socket.read_buf(&mut self.buffer).await? == 0 {
+            return Err(RespError::UnexpectedEof);
+        }
         Ok(())
     }
+
+    pub async fn write_value(&mut self, value: &Value) -> Result<()> {
+        self.write_values(std::slice::from_ref(value)).await
+    }
+
+    /// Encodes all replies into the output buffer and sends them with a single write.
+    pub async fn write_values(&mut self, values: &[Value]) -> Result<()> {
+        self.queue_values(values);
+        self.flush().await
+    }
+
+    /// Encodes replies into the output buffer without sending them, so a whole batch
+    /// goes out with one `flush`.
+    pub fn queue_values(&mut self, values: &[Value]) {
+        for value in values {
+            value.encode_as(self.protocol, &mut self.output);
+        }
+    }
+
+    /// Bytes queued in the output buffer since the last flush.
+    pub fn pending_output(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Writes out everything queued. The buffer is kept between writes, so steady
+    /// traffic allocates nothing.
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        let result = self.socket.write_all(&self.output).await;
+        self.output.clear();
+        Ok(result?)
+    }
+}
+
+/// Parses a command sent by a client. As in Redis, anything that does not start like a
+/// RESP array is an inline command: one line of space-separated arguments, the way
+/// they are typed into telnet or netcat. Blank lines are skipped.
+pub fn parse_request(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_request_within(buffer, &ProtocolLimits::default())
+}
+
+/// Like `parse_request`, refusing frames past `limits`.
+pub fn parse_request_within(buffer: &[u8], limits: &ProtocolLimits) -> Result<ParseStatus> {
+    match buffer.first() {
+        None => Ok(ParseStatus::NeedMoreData),
+        Some(b'*') => parse_message_within(buffer, limits),
+        Some(_) => parse_inline_within(buffer, limits),
+    }
+}
+
+pub fn parse_inline(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_inline_within(buffer, &ProtocolLimits::default())
 }
 
-pub fn parse_message(buffer: BytesMut) -> Result<(Value, usize)> {
-    match buffer[0] as char {
+fn parse_inline_within(buffer: &[u8], limits: &ProtocolLimits) -> Result<ParseStatus> {
+    let Some(end) = buffer.iter().position(|&byte| byte == b'\n') else {
+        if buffer.len() > MAX_INLINE_LENGTH {
+            return Err(RespError::InlineTooLong);
+        }
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    // Netcat sends a bare LF, telnet CRLF
+    let line = &buffer[..end];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    let args = split_inline(line)?;
+    if args.is_empty() {
+        return skip_blank_line(buffer, end + 1, limits);
+    }
+    let args = args
+        .into_iter()
+        .map(|arg| Value::BulkString(arg.into()))
+        .collect();
+    Ok(ParseStatus::Complete(Value::Array(args), end + 1))
+}
+
+// Parses the request after the blank line ending at `skipped`. Runs of blank lines
+// are stepped over in a loop rather than by recursion, so a flood of them cannot
+// exhaust the stack.
+fn skip_blank_line(
+    buffer: &[u8],
+    mut skipped: usize,
+    limits: &ProtocolLimits,
+) -> Result<ParseStatus> {
+    loop {
+        let rest = &buffer[skipped..];
+        let blank = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(end) if rest[..end].iter().all(u8::is_ascii_whitespace) => end + 1,
+            _ => 0,
+        };
+        if blank == 0 {
+            break;
+        }
+        skipped += blank;
+    }
+    Ok(match parse_request_within(&buffer[skipped..], limits)? {
+        ParseStatus::Complete(value, consumed) => ParseStatus::Complete(value, skipped + consumed),
+        ParseStatus::NeedMoreData => ParseStatus::NeedMoreData,
+    })
+}
+
+// Longest inline command accepted without a line ending, as in Redis
+const MAX_INLINE_LENGTH: usize = 64 * 1024;
+
+// Splits an inline command into arguments. Arguments are separated by whitespace and
+// may be quoted: double quotes understand \n, \r, \t, \b, \a, \xHH and escaped
+// characters, single quotes only an escaped quote.
+fn split_inline(line: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut bytes = line.iter().copied().peekable();
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        let Some(first) = bytes.next() else {
+            return Ok(args);
+        };
+
+        let mut arg = Vec::new();
+        match first {
+            b'"' => loop {
+                match bytes.next().ok_or(RespError::UnbalancedQuotes)? {
+                    b'"' => break,
+                    b'\\' => match bytes.next().ok_or(RespError::UnbalancedQuotes)? {
+                        b'n' => arg.push(b'\n'),
+                        b'r' => arg.push(b'\r'),
+                        b't' => arg.push(b'\t'),
+                        b'b' => arg.push(0x08),
+                        b'a' => arg.push(0x07),
+                        b'x' => {
+                            let mut ahead = bytes.clone();
+                            let hex = |digit: Option<u8>| (digit? as char).to_digit(16);
+                            match (hex(ahead.next()), hex(ahead.next())) {
+                                (Some(high), Some(low)) => {
+                                    arg.push((high * 16 + low) as u8);
+                                    bytes = ahead;
+                                }
+                                // Not a hex escape, so just an escaped x
+                                _ => arg.push(b'x'),
+                            }
+                        }
+                        escaped => arg.push(escaped),
+                    },
+                    byte => arg.push(byte),
+                }
+            },
+            b'\'' => loop {
+                match bytes.next().ok_or(RespError::UnbalancedQuotes)? {
+                    b'\'' => break,
+                    b'\\' if bytes.peek() == Some(&b'\'') => arg.push(bytes.next().unwrap()),
+                    byte => arg.push(byte),
+                }
+            },
+            byte => {
+                arg.push(byte);
+                while let Some(byte) = bytes.next_if(|byte| !byte.is_ascii_whitespace()) {
+                    arg.push(byte);
+                }
+            }
+        }
+
+        // A closing quote must end the argument
+        if matches!(first, b'"' | b'\'')
+            && bytes.peek().is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            return Err(RespError::UnbalancedQuotes);
+        }
+        args.push(arg);
+    }
+}
+
+// Aggregates may hold aggregates this many levels deep, so a frame of nested arrays
+// cannot run the parser out of stack
+const MAX_DEPTH: usize = 128;
+
+/// Parses the frame at the front of `buffer`, returning it with the number of bytes it
+/// took, or None when the buffer holds only the start of a frame. Any input, however
+/// malformed, gives an error rather than a panic, which makes this the entry point for
+/// fuzzing.
+pub fn parse_frame(buffer: &[u8]) -> Result<Option<(Value, usize)>> {
+    Ok(match parse_message(buffer)? {
+        ParseStatus::Complete(value, consumed) => Some((value, consumed)),
+        ParseStatus::NeedMoreData => None,
+    })
+}
+
+pub fn parse_message(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_message_within(buffer, &ProtocolLimits::default())
+}
+
+/// Like `parse_message`, refusing frames past `limits`.
+pub fn parse_message_within(buffer: &[u8], limits: &ProtocolLimits) -> Result<ParseStatus> {
+    parse_nested(buffer, limits, 0)
+}
+
+// Parses a frame inside `depth` enclosing aggregates
+fn parse_nested(buffer: &[u8], limits: &ProtocolLimits, depth: usize) -> Result<ParseStatus> {
+    let Some(&prefix) = buffer.first() else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    match prefix as char {
         '+' => parse_simple_string(buffer),
-        '$' => parse_bulk_string(buffer),
-        '*' => parse_array(buffer),
+        '$' => parse_bulk_string_within(buffer, limits),
+        '*' => parse_array_nested(buffer, limits, depth),
         '-' => parse_simple_error(buffer),
-        _ => Err(anyhow::anyhow!("Invalid type {:?}", buffer)),
+        ':' => parse_integer(buffer),
+        '_' => parse_null(buffer),
+        '#' => parse_boolean(buffer),
+        ',' => parse_double(buffer),
+        '(' => parse_big_number(buffer),
+        '%' | '~' | '>' => parse_aggregate_nested(buffer, limits, depth),
+        _ => Err(RespError::InvalidPrefix(prefix)),
     }
 }
 
-pub fn parse_simple_error(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
-        Ok((Value::SimpleError(string), len + 1))
+// The line after a frame's type byte, with the bytes up to and including its CRLF
+fn frame_line(buffer: &[u8]) -> Option<(&[u8], usize)> {
+    let (line, len) = read_until_crlf(buffer.get(1..)?)?;
+    Some((line, len + 1))
+}
+
+pub fn parse_simple_error(buffer: &[u8]) -> Result<ParseStatus> {
+    if let Some((line, len)) = frame_line(buffer) {
+        let string = String::from_utf8(line.to_vec()).map_err(|_| RespError::InvalidUtf8)?;
+        Ok(ParseStatus::Complete(Value::SimpleError(string), len))
     } else {
-        Err(anyhow::anyhow!("Invalid string {:?}", buffer))
+        Ok(ParseStatus::NeedMoreData)
     }
 }
 
-pub fn parse_simple_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
-        Ok((Value::SimpleString(string), len + 1))
+pub fn parse_simple_string(buffer: &[u8]) -> Result<ParseStatus> {
+    if let Some((line, len)) = frame_line(buffer) {
+        let string = String::from_utf8(line.to_vec()).map_err(|_| RespError::InvalidUtf8)?;
+        Ok(ParseStatus::Complete(Value::SimpleString(string), len))
     } else {
-        Err(anyhow::anyhow!("Invalid string {:?}", buffer))
+        Ok(ParseStatus::NeedMoreData)
     }
 }
 
-pub fn parse_array(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (array_length, mut bytes_consumed) =
-        if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-            let array_length = parse_int(line).unwrap();
-            (array_length, len + 1)
-        } else {
-            return Err(anyhow::anyhow!("Invalid array {:?}", buffer));
-        };
+pub fn parse_integer(buffer: &[u8]) -> Result<ParseStatus> {
+    if let Some((line, len)) = frame_line(buffer) {
+        Ok(ParseStatus::Complete(Value::Integer(parse_int(line)?), len))
+    } else {
+        Ok(ParseStatus::NeedMoreData)
+    }
+}
 
-    let mut items = vec![];
+pub fn parse_null(buffer: &[u8]) -> Result<ParseStatus> {
+    match frame_line(buffer) {
+        Some((b"", len)) => Ok(ParseStatus::Complete(Value::Null, len)),
+        Some(_) => Err(RespError::InvalidValue("null")),
+        None => Ok(ParseStatus::NeedMoreData),
+    }
+}
+
+pub fn parse_boolean(buffer: &[u8]) -> Result<ParseStatus> {
+    let Some((line, len)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    let value = match line {
+        b"t" => true,
+        b"f" => false,
+        _ => return Err(RespError::InvalidValue("boolean")),
+    };
+    Ok(ParseStatus::Complete(Value::Boolean(value), len))
+}
 
-    for _ in 0..array_length {
-        let (array_item, length) = parse_message(BytesMut::from(&buffer[bytes_consumed..]))?;
-        bytes_consumed += length;
-        items.push(array_item);
+pub fn parse_double(buffer: &[u8]) -> Result<ParseStatus> {
+    let Some((line, len)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    let number = std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse::<f64>().ok())
+        .ok_or(RespError::InvalidValue("double"))?;
+    Ok(ParseStatus::Complete(Value::Double(number), len))
+}
+
+pub fn parse_big_number(buffer: &[u8]) -> Result<ParseStatus> {
+    let Some((line, len)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    let digits = line.strip_prefix(b"-").unwrap_or(line);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(RespError::InvalidValue("big number"));
     }
+    let number = String::from_utf8(line.to_vec()).map_err(|_| RespError::InvalidUtf8)?;
+    Ok(ParseStatus::Complete(Value::BigNumber(number), len))
+}
 
-    Ok((Value::Array(items), bytes_consumed))
+// Maps, sets and pushes: a count followed by that many elements, or twice as many
+// for the key/value pairs of a map
+pub fn parse_aggregate(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_aggregate_nested(buffer, &ProtocolLimits::default(), 0)
 }
 
-pub fn parse_bulk_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (string_length, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string_length = parse_int(line).unwrap();
-        (string_length, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid bulk string {:?}", buffer));
+fn parse_aggregate_nested(
+    buffer: &[u8],
+    limits: &ProtocolLimits,
+    depth: usize,
+) -> Result<ParseStatus> {
+    let Some((line, mut bytes_consumed)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    let count = parse_int(line)?;
+    if count > 0 && count as u64 > limits.max_multibulk_len {
+        return Err(RespError::MultibulkTooLong);
+    }
+    let is_map = buffer[0] == b'%';
+    let elements = usize::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(if is_map { 2 } else { 1 }))
+        .ok_or(RespError::InvalidLength(count))?;
+    let items = match parse_elements(buffer, &mut bytes_consumed, elements, limits, depth)? {
+        Some(items) => items,
+        None => return Ok(ParseStatus::NeedMoreData),
+    };
+
+    let value = match buffer[0] {
+        b'%' => {
+            let mut items = items.into_iter();
+            let mut pairs = Vec::with_capacity(elements / 2);
+            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                pairs.push((key, value));
+            }
+            Value::Map(pairs)
+        }
+        b'~' => Value::Set(items),
+        _ => Value::Push(items),
+    };
+    Ok(ParseStatus::Complete(value, bytes_consumed))
+}
+
+pub fn parse_array(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_array_nested(buffer, &ProtocolLimits::default(), 0)
+}
+
+fn parse_array_nested(buffer: &[u8], limits: &ProtocolLimits, depth: usize) -> Result<ParseStatus> {
+    let Some((line, mut bytes_consumed)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
+    };
+    let array_length = parse_int(line)?;
+    if array_length == -1 {
+        return Ok(ParseStatus::Complete(Value::NullArray, bytes_consumed));
+    }
+    let elements =
+        usize::try_from(array_length).map_err(|_| RespError::InvalidLength(array_length))?;
+    if elements as u64 > limits.max_multibulk_len {
+        return Err(RespError::MultibulkTooLong);
+    }
+
+    match parse_elements(buffer, &mut bytes_consumed, elements, limits, depth)? {
+        Some(items) => Ok(ParseStatus::Complete(Value::Array(items), bytes_consumed)),
+        None => Ok(ParseStatus::NeedMoreData),
+    }
+}
+
+// The elements of an aggregate at `depth`, starting `bytes_consumed` into the buffer
+// and advancing it past them. None until all of them arrived.
+fn parse_elements(
+    buffer: &[u8],
+    bytes_consumed: &mut usize,
+    count: usize,
+    limits: &ProtocolLimits,
+    depth: usize,
+) -> Result<Option<Vec<Value>>> {
+    if count > 0 && depth >= MAX_DEPTH {
+        return Err(RespError::TooDeep);
+    }
+    // The count is only a claim, so room is made as elements actually arrive
+    let mut items = vec![];
+    for _ in 0..count {
+        match parse_nested(&buffer[*bytes_consumed..], limits, depth + 1)? {
+            ParseStatus::Complete(item, length) => {
+                *bytes_consumed += length;
+                items.push(item);
+            }
+            ParseStatus::NeedMoreData => return Ok(None),
+        }
+    }
+    Ok(Some(items))
+}
+
+pub fn parse_bulk_string(buffer: &[u8]) -> Result<ParseStatus> {
+    parse_bulk_string_within(buffer, &ProtocolLimits::default())
+}
+
+fn parse_bulk_string_within(buffer: &[u8], limits: &ProtocolLimits) -> Result<ParseStatus> {
+    let Some((line, bytes_consumed)) = frame_line(buffer) else {
+        return Ok(ParseStatus::NeedMoreData);
     };
+    let string_length = parse_int(line)?;
 
-    let end_of_bulk_string = bytes_consumed + string_length as usize;
+    if string_length == -1 {
+        return Ok(ParseStatus::Complete(Value::Null, bytes_consumed));
+    }
+
+    if string_length < 0 {
+        return Err(RespError::InvalidLength(string_length));
+    }
+    if string_length as u64 > limits.max_bulk_len {
+        return Err(RespError::BulkTooLong);
+    }
+
+    let end_of_bulk_string = usize::try_from(string_length)
+        .ok()
+        .and_then(|length| bytes_consumed.checked_add(length))
+        .ok_or(RespError::BulkTooLong)?;
     let total_parsed = end_of_bulk_string + 2;
 
-    let string = String::from_utf8(buffer[bytes_consumed..end_of_bulk_string].to_vec())
-        .context("Invalid bulk string")?;
-    Ok((Value::BulkString(string), total_parsed))
+    // The payload and its trailing CRLF may still be in flight
+    if buffer.len() < total_parsed {
+        return Ok(ParseStatus::NeedMoreData);
+    }
+    if &buffer[end_of_bulk_string..total_parsed] != b"\r\n" {
+        return Err(RespError::InvalidValue("bulk string"));
+    }
+
+    let bytes = Bytes::copy_from_slice(&buffer[bytes_consumed..end_of_bulk_string]);
+    Ok(ParseStatus::Complete(
+        Value::BulkString(bytes),
+        total_parsed,
+    ))
 }
 
 pub fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
@@ -171,10 +2432,8 @@ pub fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
 }
 
 pub fn parse_int(buffer: &[u8]) -> Result<i64> {
-    let string =
-        String::from_utf8(buffer.to_vec()).context("Failed to convert buffer to UTF-8 string")?;
-    let number = string
-        .parse::<i64>()
-        .context("Failed to parse string as i64")?;
-    Ok(number)
+    std::str::from_utf8(buffer)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(RespError::InvalidInteger)
 }
@@ -2,7 +2,7 @@
 mod tests {
     use super::super::*;
     use anyhow::Result;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
 
     #[test]
     fn test_serialize_simple_string() -> Result<()> {
@@ -13,11 +13,20 @@ mod tests {
 
     #[test]
     fn test_serialize_bulk_string() -> Result<()> {
-        let value = Value::BulkString("foobar".to_string());
+        let value = Value::BulkString("foobar".into());
         assert_eq!(value.serialize(), "$6\r\nfoobar\r\n");
         Ok(())
     }
 
+    #[test]
+    fn test_serialize_bulk_string_counts_bytes() -> Result<()> {
+        let value = Value::BulkString(Bytes::from_static(&[0xff, 0xfe, 0x00]));
+        assert_eq!(value.serialize(), &b"$3\r\n\xff\xfe\x00\r\n"[..]);
+        let value = Value::BulkString("héllo".into());
+        assert_eq!(value.serialize(), "$6\r\nhéllo\r\n");
+        Ok(())
+    }
+
     #[test]
     fn test_serialize_simple_error() -> Result<()> {
         let value = Value::SimpleError("Error message".to_string());
@@ -29,16 +38,177 @@ mod tests {
     fn test_serialize_array() -> Result<()> {
         let value = Value::Array(vec![
             Value::SimpleString("foo".to_string()),
-            Value::BulkString("bar".to_string()),
+            Value::BulkString("bar".into()),
         ]);
         assert_eq!(value.serialize(), "*2\r\n+foo\r\n$3\r\nbar\r\n");
         Ok(())
     }
 
+    #[test]
+    fn test_serialize_integer() -> Result<()> {
+        assert_eq!(Value::Integer(42).serialize(), ":42\r\n");
+        assert_eq!(Value::Integer(-7).serialize(), ":-7\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_resp3_types() -> Result<()> {
+        let map = Value::Map(vec![(Value::BulkString("a".into()), Value::Integer(1))]);
+        assert_eq!(
+            map.clone().serialize_as(Protocol::Resp3),
+            "%1\r\n$1\r\na\r\n:1\r\n"
+        );
+        assert_eq!(map.serialize(), "*2\r\n$1\r\na\r\n:1\r\n");
+
+        let set = Value::Set(vec![Value::Integer(1)]);
+        assert_eq!(set.clone().serialize_as(Protocol::Resp3), "~1\r\n:1\r\n");
+        assert_eq!(set.serialize(), "*1\r\n:1\r\n");
+        let push = Value::Push(vec![Value::Integer(1)]);
+        assert_eq!(push.clone().serialize_as(Protocol::Resp3), ">1\r\n:1\r\n");
+        assert_eq!(push.serialize(), "*1\r\n:1\r\n");
+
+        assert_eq!(Value::Double(1.5).serialize_as(Protocol::Resp3), ",1.5\r\n");
+        assert_eq!(
+            Value::Double(f64::NEG_INFINITY).serialize_as(Protocol::Resp3),
+            ",-inf\r\n"
+        );
+        assert_eq!(Value::Double(3.0).serialize(), "$1\r\n3\r\n");
+        assert_eq!(Value::Boolean(true).serialize_as(Protocol::Resp3), "#t\r\n");
+        assert_eq!(Value::Boolean(false).serialize(), ":0\r\n");
+        let big = Value::BigNumber("12345678901234567890".to_owned());
+        assert_eq!(
+            big.clone().serialize_as(Protocol::Resp3),
+            "(12345678901234567890\r\n"
+        );
+        assert_eq!(big.serialize(), "$20\r\n12345678901234567890\r\n");
+
+        assert_eq!(Value::Null.serialize_as(Protocol::Resp3), "_\r\n");
+        assert_eq!(Value::NullArray.serialize_as(Protocol::Resp3), "_\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_resp3_types() -> Result<()> {
+        let frames: [(&str, Value); 7] = [
+            ("_\r\n", Value::Null),
+            ("#f\r\n", Value::Boolean(false)),
+            (",-2.5\r\n", Value::Double(-2.5)),
+            ("(-123\r\n", Value::BigNumber("-123".to_owned())),
+            (
+                "%1\r\n+a\r\n:1\r\n",
+                Value::Map(vec![(
+                    Value::SimpleString("a".to_owned()),
+                    Value::Integer(1),
+                )]),
+            ),
+            ("~1\r\n:1\r\n", Value::Set(vec![Value::Integer(1)])),
+            (">1\r\n:1\r\n", Value::Push(vec![Value::Integer(1)])),
+        ];
+        for (frame, value) in frames {
+            assert_eq!(
+                parse_message(frame.as_bytes())?,
+                ParseStatus::Complete(value, frame.len())
+            );
+        }
+
+        assert_eq!(parse_message(b"%1\r\n+a\r\n")?, ParseStatus::NeedMoreData);
+        assert!(matches!(
+            parse_message(b"#x\r\n"),
+            Err(RespError::InvalidValue("boolean"))
+        ));
+        assert!(matches!(
+            parse_message(b"!3\r\n"),
+            Err(RespError::InvalidPrefix(b'!'))
+        ));
+        assert!(matches!(
+            parse_message(b"$-2\r\n"),
+            Err(RespError::InvalidLength(-2))
+        ));
+        assert!(parse_message(b"(12a\r\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_inline_request() -> Result<()> {
+        let command = |parts: &[&[u8]]| {
+            Value::Array(
+                parts
+                    .iter()
+                    .map(|part| Value::BulkString(Bytes::copy_from_slice(part)))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            parse_request(b"SET  foo bar\r\n")?,
+            ParseStatus::Complete(command(&[b"SET", b"foo", b"bar"]), 14)
+        );
+        assert_eq!(
+            parse_request(b"\r\n\nPING\n")?,
+            ParseStatus::Complete(command(&[b"PING"]), 8)
+        );
+        assert_eq!(
+            parse_request(b"ECHO \"a\\tb\\x41\\xZ\" 'c\\'d' \"\"\r\n")?,
+            ParseStatus::Complete(command(&[b"ECHO", b"a\tbAxZ", b"c'd", b""]), 30)
+        );
+        // RESP arrays are still understood
+        assert_eq!(
+            parse_request(b"*1\r\n$4\r\nPING\r\n")?,
+            ParseStatus::Complete(command(&[b"PING"]), 14)
+        );
+
+        assert_eq!(parse_request(b"PING")?, ParseStatus::NeedMoreData);
+        assert_eq!(parse_request(b"\r\n")?, ParseStatus::NeedMoreData);
+        assert!(matches!(
+            parse_request(b"ECHO \"open\r\n"),
+            Err(RespError::UnbalancedQuotes)
+        ));
+        assert!(parse_request(b"ECHO \"a\"b\r\n").is_err());
+        assert!(matches!(
+            parse_request(&vec![b'a'; MAX_INLINE_LENGTH + 1]),
+            Err(RespError::InlineTooLong)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_integer() -> Result<()> {
+        let buffer = BytesMut::from(":1000\r\n");
+        let ParseStatus::Complete(value, consumed) = parse_integer(&buffer)? else {
+            panic!("expected a complete frame");
+        };
+        assert_eq!(value, Value::Integer(1000));
+        assert_eq!(consumed, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_nulls() -> Result<()> {
+        assert_eq!(Value::Null.serialize(), "$-1\r\n");
+        assert_eq!(Value::NullArray.serialize(), "*-1\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_nulls() -> Result<()> {
+        let buffer = BytesMut::from("$-1\r\n");
+        assert_eq!(
+            parse_message(&buffer)?,
+            ParseStatus::Complete(Value::Null, 5)
+        );
+        let buffer = BytesMut::from("*-1\r\n");
+        assert_eq!(
+            parse_message(&buffer)?,
+            ParseStatus::Complete(Value::NullArray, 5)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_parse_simple_string() -> Result<()> {
         let buffer = BytesMut::from("+OK\r\n");
-        let (value, _) = parse_simple_string(buffer)?;
+        let ParseStatus::Complete(value, _) = parse_simple_string(&buffer)? else {
+            panic!("expected a complete frame");
+        };
         assert_eq!(value, Value::SimpleString("OK".to_string()));
         Ok(())
     }
@@ -46,15 +216,33 @@ mod tests {
     #[test]
     fn test_parse_bulk_string() -> Result<()> {
         let buffer = BytesMut::from("$6\r\nfoobar\r\n");
-        let (value, _) = parse_bulk_string(buffer)?;
-        assert_eq!(value, Value::BulkString("foobar".to_string()));
+        let ParseStatus::Complete(value, _) = parse_bulk_string(&buffer)? else {
+            panic!("expected a complete frame");
+        };
+        assert_eq!(value, Value::BulkString("foobar".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_binary_bulk_string() -> Result<()> {
+        let buffer = BytesMut::from(&b"$4\r\n\x00\xc3\x28\xff\r\n"[..]);
+        let ParseStatus::Complete(value, consumed) = parse_bulk_string(&buffer)? else {
+            panic!("expected a complete frame");
+        };
+        assert_eq!(
+            value,
+            Value::BulkString(Bytes::from_static(&[0x00, 0xc3, 0x28, 0xff]))
+        );
+        assert_eq!(consumed, 10);
         Ok(())
     }
 
     #[test]
     fn test_parse_simple_error() -> Result<()> {
         let buffer = BytesMut::from("-Error message\r\n");
-        let (value, _) = parse_simple_error(buffer)?;
+        let ParseStatus::Complete(value, _) = parse_simple_error(&buffer)? else {
+            panic!("expected a complete frame");
+        };
         assert_eq!(value, Value::SimpleError("Error message".to_string()));
         Ok(())
     }
@@ -62,24 +250,169 @@ mod tests {
     #[test]
     fn test_parse_array() -> Result<()> {
         let buffer = BytesMut::from("*2\r\n+foo\r\n$3\r\nbar\r\n");
-        let (value, _) = parse_array(buffer)?;
+        let ParseStatus::Complete(value, _) = parse_array(&buffer)? else {
+            panic!("expected a complete frame");
+        };
         assert_eq!(
             value,
             Value::Array(vec![
                 Value::SimpleString("foo".to_string()),
-                Value::BulkString("bar".to_string()),
+                Value::BulkString("bar".into()),
             ])
         );
         Ok(())
     }
+
+    #[test]
+    fn test_parse_partial_bulk_string() -> Result<()> {
+        let buffer = BytesMut::from("$6\r\nfoo");
+        assert_eq!(parse_message(&buffer)?, ParseStatus::NeedMoreData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_partial_array() -> Result<()> {
+        let buffer = BytesMut::from("*2\r\n$4\r\nECHO\r\n$3\r");
+        assert_eq!(parse_message(&buffer)?, ParseStatus::NeedMoreData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_reports_consumed_bytes() -> Result<()> {
+        let buffer = BytesMut::from("+OK\r\n+NEXT\r\n");
+        assert_eq!(
+            parse_message(&buffer)?,
+            ParseStatus::Complete(Value::SimpleString("OK".to_string()), 5)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_frame() -> Result<()> {
+        assert_eq!(parse_frame(b":1\r\n:2\r\n")?, Some((Value::Integer(1), 4)));
+        assert_eq!(parse_frame(b"*2\r\n:1\r\n")?, None);
+        assert_eq!(parse_frame(b"")?, None);
+        assert!(parse_frame(b"?\r\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_lengths() {
+        // Lengths that would overflow, negative ones and ones past the limit
+        for frame in [
+            "$-2\r\n",
+            "*-5\r\n",
+            "%-1\r\n",
+            "~-3\r\n",
+            "$9223372036854775807\r\n",
+            "$99999999999999999999\r\n",
+            "$536870913\r\n",
+        ] {
+            assert!(parse_frame(frame.as_bytes()).is_err(), "{:?}", frame);
+        }
+        // A huge count allocates nothing up front and just waits for elements
+        assert_eq!(parse_frame(b"*2147483647\r\n").unwrap(), None);
+        // The payload must be followed by a CRLF
+        assert!(parse_frame(b"$3\r\nfooXY").is_err());
+        assert_eq!(parse_frame(b"$3\r\nfoo\r").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_within_limits() {
+        let limits = ProtocolLimits {
+            max_bulk_len: 3,
+            max_multibulk_len: 2,
+        };
+        assert!(parse_message_within(b"$3\r\nfoo\r\n", &limits).is_ok());
+        assert!(matches!(
+            parse_message_within(b"$4\r\n", &limits),
+            Err(RespError::BulkTooLong)
+        ));
+        assert!(parse_request_within(b"*2\r\n:1\r\n:2\r\n", &limits).is_ok());
+        for frame in ["*3\r\n", "\r\n*3\r\n"] {
+            assert!(matches!(
+                parse_request_within(frame.as_bytes(), &limits),
+                Err(RespError::MultibulkTooLong)
+            ));
+        }
+        for frame in ["%3\r\n", "~3\r\n", ">3\r\n"] {
+            assert!(matches!(
+                parse_message_within(frame.as_bytes(), &limits),
+                Err(RespError::MultibulkTooLong)
+            ));
+        }
+        // Nested aggregates are held to the same limits
+        assert!(matches!(
+            parse_message_within(b"*1\r\n*1\r\n$9\r\n", &limits),
+            Err(RespError::BulkTooLong)
+        ));
+    }
+
+    #[test]
+    fn test_parse_limits_nesting() {
+        let nested = |depth: usize| format!("{}:1\r\n", "*1\r\n".repeat(depth));
+        assert!(parse_frame(nested(128).as_bytes()).unwrap().is_some());
+        assert!(matches!(
+            parse_frame(nested(100_000).as_bytes()),
+            Err(RespError::TooDeep)
+        ));
+        // Likewise for maps, sets and pushes
+        let nested = "%1\r\n~1\r\n>1\r\n".repeat(50);
+        assert!(matches!(
+            parse_frame(nested.as_bytes()),
+            Err(RespError::TooDeep)
+        ));
+    }
+
+    #[test]
+    fn test_parse_skips_many_blank_lines() -> Result<()> {
+        let buffer = format!("{}PING\r\n", "\r\n".repeat(100_000));
+        assert_eq!(
+            parse_request(buffer.as_bytes())?,
+            ParseStatus::Complete(
+                Value::Array(vec![Value::BulkString("PING".into())]),
+                buffer.len()
+            )
+        );
+        Ok(())
+    }
+
+    // Every prefix of a sample of frames, and each of them with every byte replaced by
+    // a few troublesome ones, parses or errors but never panics
+    #[test]
+    fn test_parse_never_panics() {
+        let frames: [&[u8]; 8] = [
+            b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n",
+            b"%2\r\n+a\r\n:1\r\n+b\r\n#t\r\n",
+            b"~2\r\n,3.14\r\n(12345678901234567890\r\n",
+            b">2\r\n$7\r\nmessage\r\n_\r\n",
+            b"-ERR oops\r\n",
+            b"*-1\r\n",
+            b"$-1\r\n",
+            b"GET \"a\\x41\" 'b'\r\n",
+        ];
+        for frame in frames {
+            for end in 0..=frame.len() {
+                let _ = parse_frame(&frame[..end]);
+                let _ = parse_request(&frame[..end]);
+            }
+            for position in 0..frame.len() {
+                for byte in [b'\0', b'\r', b'\n', b'-', b'9', b'*', b'$', 0xff] {
+                    let mut mutated = frame.to_vec();
+                    mutated[position] = byte;
+                    let _ = parse_frame(&mutated);
+                    let _ = parse_request(&mutated);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod client_server_tests {
     use super::super::*;
     use anyhow::Result;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
-    use tokio::net::{TcpListener, TcpStream};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
 
     #[tokio::test]
     async fn test_simple_string_serialization() {
@@ -89,7 +422,7 @@ mod client_server_tests {
 
     #[tokio::test]
     async fn test_bulk_string_serialization() {
-        let value = Value::BulkString("foobar".to_string());
+        let value = Value::BulkString("foobar".into());
         assert_eq!(value.serialize(), "$6\r\nfoobar\r\n");
     }
 
@@ -116,7 +449,7 @@ mod client_server_tests {
         server.write_all(b"$6\r\nfoobar\r\n").await?;
         let mut handler = RespHandler::new(client);
         let value = handler.read_value().await?.unwrap();
-        assert_eq!(value, Value::BulkString("foobar".to_string()));
+        assert_eq!(value, Value::BulkString("foobar".into()));
         Ok(())
     }
 
@@ -135,7 +468,7 @@ mod client_server_tests {
         let (client, mut server) = create_client_server().await?;
         let mut handler = RespHandler::new(client);
         handler
-            .write_value(Value::SimpleString("OK".to_string()))
+            .write_value(&Value::SimpleString("OK".to_string()))
             .await?;
         let mut buffer = vec![0; 5];
         server.read_exact(&mut buffer).await?;
@@ -148,7 +481,7 @@ mod client_server_tests {
         let (client, mut server) = create_client_server().await?;
         let mut handler = RespHandler::new(client);
         handler
-            .write_value(Value::BulkString("foobar".to_string()))
+            .write_value(&Value::BulkString("foobar".into()))
             .await?;
 
         let mut buffer = vec![0; 12];
@@ -164,7 +497,7 @@ mod client_server_tests {
         let (client, mut server) = create_client_server().await?;
         let mut handler = RespHandler::new(client);
         handler
-            .write_value(Value::SimpleError("Error message".to_string()))
+            .write_value(&Value::SimpleError("Error message".to_string()))
             .await?;
         let mut buffer = vec![0; 16];
         server.read_exact(&mut buffer).await?;
@@ -172,11 +505,66 @@ mod client_server_tests {
         Ok(())
     }
 
-    async fn create_client_server() -> Result<(TcpStream, TcpStream)> {
-        let listener = TcpListener::bind("127.0.0.1:0").await?;
-        let addr = listener.local_addr()?;
-        let client = TcpStream::connect(addr).await?;
-        let (server, _) = listener.accept().await?;
-        Ok((client, server))
+    #[tokio::test]
+    async fn test_read_frame_split_across_writes() -> Result<()> {
+        let (client, mut server) = create_client_server().await?;
+        let mut handler = RespHandler::new(client);
+
+        let writer = tokio::spawn(async move {
+            server.write_all(b"*2\r\n$4\r\nECHO\r\n$3").await?;
+            server.flush().await?;
+            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+            server.write_all(b"\r\nHEY\r\n").await?;
+            Ok::<_, anyhow::Error>(server)
+        });
+
+        let value = handler.read_value().await?.unwrap();
+        assert_eq!(
+            value,
+            Value::Array(vec![
+                Value::BulkString("ECHO".into()),
+                Value::BulkString("HEY".into()),
+            ])
+        );
+        assert!(handler.buffer.is_empty());
+        writer.await??;
+        Ok(())
+    }
+
+    // Both ends of an in-memory stream, standing in for a socket
+    async fn create_client_server() -> Result<(DuplexStream, DuplexStream)> {
+        Ok(duplex(4096))
+    }
+
+    #[test]
+    fn test_command_table() {
+        assert_eq!(
+            UserCommand::from("slaveof".to_owned()),
+            UserCommand::ReplicaOf
+        );
+        assert_eq!(UserCommand::ReplicaOf.name(), "REPLICAOF");
+        assert_eq!(CommandSpec::lookup("SlaveOf").unwrap().name, "SLAVEOF");
+        assert_eq!(UserCommand::from("nosuch".to_owned()), UserCommand::Invalid);
+
+        assert!(UserCommand::Set.is_write());
+        assert!(!UserCommand::Get.is_write());
+        assert!(!UserCommand::Invalid.is_write());
+
+        // Keyless commands report no key positions
+        for spec in COMMANDS {
+            assert_eq!(spec.first_key == 0, spec.step == 0, "{}", spec.name);
+        }
+
+        // Container commands take their subcommand as an argument, each listed once
+        for (command, subcommands) in SUBCOMMANDS {
+            assert!(command.spec().is_some_and(|spec| spec.arity < 0));
+            for (i, subcommand) in subcommands.iter().enumerate() {
+                assert!(subcommands[i + 1..]
+                    .iter()
+                    .all(|other| other.name != subcommand.name));
+            }
+        }
+        assert_eq!(UserCommand::Object.subcommands()[0].name, "ENCODING");
+        assert!(UserCommand::Get.subcommands().is_empty());
     }
 }
@@ -0,0 +1,341 @@
+use bytes::{Bytes, BytesMut};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{Mutex, MutexGuard},
+    task::JoinHandle,
+};
+
+use super::encode_command;
+use super::rdb::{self, Keyspace};
+use crate::config::AppendFsync;
+use crate::connection::{execute_command, extract_command};
+use crate::error::{Context, Result};
+use crate::latency::LatencyMonitor;
+use crate::parser::{parse_message, ParseStatus, UserCommand, Value};
+use crate::server::ServerState;
+use crate::shutdown::Shutdown;
+use crate::storage::sharded::ShardedDb;
+use crate::storage::{
+    sorted_set::format_score,
+    stream::{Stream, StreamId},
+    DataType,
+};
+use crate::{notice, warning};
+
+pub mod tests_aof;
+
+/// The append-only file. Every write command that succeeded is appended as a RESP
+/// array, preceded by a SELECT whenever it targets another database than the previous
+/// one, so replaying the file from the start rebuilds the keyspace.
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+    writer: Mutex<AofWriter>,
+}
+
+#[derive(Debug)]
+pub struct AofWriter {
+    file: File,
+    fsync: AppendFsync,
+    // Database of the last logged command, None until the first SELECT is written
+    selected: Option<usize>,
+    // While a rewrite runs, everything appended is also kept here to be added to the
+    // rewritten file before it replaces the current one
+    rewrite_buffer: Option<Vec<Bytes>>,
+}
+
+// Collections are rewritten in commands of at most this many items
+const ITEMS_PER_COMMAND: usize = 64;
+
+impl Aof {
+    pub async fn open(path: &Path, fsync: AppendFsync) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self {
+            path: path.to_owned(),
+            writer: Mutex::new(AofWriter {
+                file,
+                fsync,
+                selected: None,
+                rewrite_buffer: None,
+            }),
+        })
+    }
+
+    /// Locks the log, to append a write or to sync it.
+    pub async fn lock(&self) -> MutexGuard<'_, AofWriter> {
+        self.writer.lock().await
+    }
+
+    /// Starts compacting the log in the background: the current dataset is written as
+    /// the shortest command stream that rebuilds it, then replaces the file. Returns
+    /// false when a rewrite is already running. The caller holds every stripe of
+    /// `WriteOrder`, so no write runs while the dataset is copied.
+    pub async fn start_rewrite(self: &Arc<Self>, databases: &[Arc<ShardedDb>]) -> bool {
+        let mut writer = self.lock().await;
+        if writer.rewrite_buffer.is_some() {
+            return false;
+        }
+        // No write runs while the stripes are held, so the copy matches the end of the log
+        let keyspace = rdb::snapshot(databases).await;
+        writer.rewrite_buffer = Some(Vec::new());
+        // The buffered commands must start with their own SELECT
+        writer.selected = None;
+        drop(writer);
+
+        let aof = Arc::clone(self);
+        tokio::spawn(async move {
+            match aof.finish_rewrite(&keyspace).await {
+                Ok(()) => notice!("Background AOF rewrite finished successfully"),
+                Err(err) => {
+                    warning!("Background AOF rewrite failed: {:#}", err);
+                    aof.lock().await.rewrite_buffer = None;
+                }
+            }
+        });
+        true
+    }
+
+    async fn finish_rewrite(&self, keyspace: &Keyspace) -> Result<()> {
+        let temp = self
+            .path
+            .with_extension(format!("rewrite-{}", std::process::id()));
+        let mut file = File::create(&temp)
+            .await
+            .with_context(|| format!("Failed to create {}", temp.display()))?;
+        file.write_all(&rewrite_commands(keyspace)).await?;
+
+        // Add the writes made during the rewrite and swap files with the log locked
+        let mut writer = self.lock().await;
+        for frame in writer.rewrite_buffer.take().unwrap_or_default() {
+            file.write_all(&frame).await?;
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&temp, &self.path).await?;
+        writer.file = OpenOptions::new().append(true).open(&self.path).await?;
+        Ok(())
+    }
+}
+
+impl AofWriter {
+    pub async fn append(&mut self, db: usize, command: UserCommand, args: &[Value]) -> Result<()> {
+        let frame = encode_command(&mut self.selected, db, command, args);
+        self.file.write_all(&frame).await?;
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.push(frame);
+        }
+        if self.fsync == AppendFsync::Always {
+            self.file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn sync(&mut self) -> Result<()> {
+        self.file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// The commands that rebuild a keyspace from nothing: a SELECT per non-empty database,
+/// one SET, HSET, SADD, ZADD or RPUSH per key (split every `ITEMS_PER_COMMAND` items),
+/// one XADD per stream entry followed by its consumer groups, and a PEXPIREAT for keys
+/// with an expiry.
+pub fn rewrite_commands(keyspace: &Keyspace) -> BytesMut {
+    let mut buffer = BytesMut::new();
+    let mut push = |parts: Vec<Bytes>| {
+        let frame = Value::Array(parts.into_iter().map(Value::BulkString).collect());
+        frame.encode(&mut buffer);
+    };
+
+    for (index, entries) in keyspace.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        push(vec!["SELECT".into(), index.to_string().into()]);
+
+        for (key, entry) in entries {
+            let key = Bytes::from(key.clone());
+            let (name, items): (&'static str, Vec<Vec<Bytes>>) = match &entry.value {
+                DataType::String(string) => ("SET", vec![vec![string.to_bytes()]]),
+                DataType::Hash(hash) => (
+                    "HSET",
+                    hash.iter()
+                        .map(|(field, value)| vec![field.clone().into(), value.clone()])
+                        .collect(),
+                ),
+                DataType::Set(set) => (
+                    "SADD",
+                    set.iter().map(|member| vec![member.clone()]).collect(),
+                ),
+                DataType::SortedSet(sorted_set) => (
+                    "ZADD",
+                    sorted_set
+                        .iter()
+                        .map(|(member, score)| vec![format_score(score).into(), member.clone()])
+                        .collect(),
+                ),
+                DataType::List(list) => (
+                    "RPUSH",
+                    list.iter().map(|element| vec![element.clone()]).collect(),
+                ),
+                DataType::Stream(stream) => (
+                    "XADD",
+                    stream
+                        .entries()
+                        .iter()
+                        .map(|(id, fields)| {
+                            let mut item = vec![Bytes::from(id.to_string())];
+                            for (field, value) in fields {
+                                item.extend([field.clone(), value.clone()]);
+                            }
+                            item
+                        })
+                        .collect(),
+                ),
+            };
+            // XADD takes a single entry
+            let per_command = if name == "XADD" { 1 } else { ITEMS_PER_COMMAND };
+            for chunk in items.chunks(per_command) {
+                let mut parts = vec![Bytes::from_static(name.as_bytes()), key.clone()];
+                parts.extend(chunk.iter().flatten().cloned());
+                push(parts);
+            }
+            if let DataType::Stream(stream) = &entry.value {
+                for parts in group_commands(&key, stream) {
+                    push(parts);
+                }
+            }
+            if let Some(expires_at) = entry.expires_at {
+                push(vec!["PEXPIREAT".into(), key, expires_at.to_string().into()]);
+            }
+        }
+    }
+    buffer
+}
+
+// Recreates a stream's consumer groups. MKSTREAM brings back streams that have
+// groups but no entries. Each pending entry is handed to its consumer again by moving
+// the group just before it and reading one entry, so delivery counts and times start
+// over
+fn group_commands(key: &Bytes, stream: &Stream) -> Vec<Vec<Bytes>> {
+    let mut commands = Vec::new();
+    for (name, group) in stream.groups() {
+        let name = Bytes::from(name.clone());
+        let xgroup = |subcommand: &'static str, arg: Bytes| {
+            vec![
+                Bytes::from_static(b"XGROUP"),
+                Bytes::from_static(subcommand.as_bytes()),
+                key.clone(),
+                name.clone(),
+                arg,
+            ]
+        };
+        let last_delivered = Bytes::from(group.last_delivered().to_string());
+        let mut create = xgroup("CREATE", last_delivered.clone());
+        create.push(Bytes::from_static(b"MKSTREAM"));
+        commands.push(create);
+
+        for (id, entry) in group.pending() {
+            if !stream.entries().contains_key(id) {
+                continue;
+            }
+            let before = stream
+                .entries()
+                .range(..id)
+                .next_back()
+                .map_or(StreamId::MIN, |(before, _)| *before);
+            commands.push(xgroup("SETID", before.to_string().into()));
+            commands.push(vec![
+                Bytes::from_static(b"XREADGROUP"),
+                Bytes::from_static(b"GROUP"),
+                name.clone(),
+                Bytes::from(entry.consumer.clone()),
+                Bytes::from_static(b"COUNT"),
+                Bytes::from_static(b"1"),
+                Bytes::from_static(b"STREAMS"),
+                key.clone(),
+                Bytes::from_static(b">"),
+            ]);
+        }
+        if !group.pending().is_empty() {
+            commands.push(xgroup("SETID", last_delivered));
+        }
+        for (consumer, state) in group.consumers() {
+            if state.pending().is_empty() {
+                commands.push(xgroup("CREATECONSUMER", Bytes::from(consumer.clone())));
+            }
+        }
+    }
+    commands
+}
+
+/// With `appendfsync everysec` the file is synced to disk once a second, until the
+/// server shuts down.
+pub fn spawn_fsync_task(
+    aof: Arc<Aof>,
+    latency: Arc<LatencyMonitor>,
+    shutdown: Arc<Shutdown>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.requested() => return,
+            }
+            let mut writer = aof.lock().await;
+            if writer.fsync == AppendFsync::EverySec {
+                let started = Instant::now();
+                if let Err(err) = writer.sync().await {
+                    warning!("Failed to fsync the AOF: {:#}", err);
+                }
+                latency.record("aof-fsync", started.elapsed());
+            }
+        }
+    })
+}
+
+/// Runs every command of the AOF at `path` against the server's databases and returns
+/// how many were replayed. A missing file replays nothing. A command cut short by a
+/// crash is dropped and the file truncated before it, so new appends stay readable.
+pub async fn replay(path: &Path, state: &ServerState) -> Result<usize> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let mut offset = 0;
+    let mut selected = 0;
+    let mut replayed = 0;
+    while offset < data.len() {
+        match parse_message(&data[offset..]).context("Corrupt AOF")? {
+            ParseStatus::Complete(value, consumed) => {
+                offset += consumed;
+                let (command, args) = extract_command(value)?;
+                execute_command(command, &args, state, &mut selected, None).await?;
+                replayed += 1;
+            }
+            ParseStatus::NeedMoreData => {
+                warning!(
+                    "AOF ends with a truncated command, dropping its last {} bytes",
+                    data.len() - offset
+                );
+                let file = OpenOptions::new().write(true).open(path).await?;
+                file.set_len(offset as u64).await?;
+                break;
+            }
+        }
+    }
+    Ok(replayed)
+}
@@ -0,0 +1,227 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::config::Config;
+    use crate::storage::scan::ScanSet;
+    use crate::storage::sharded::{Storage, StorageMut};
+    use crate::storage::{
+        sorted_set::SortedSet,
+        stream::{Stream, StreamId},
+        Entry,
+    };
+    use crate::testing::args;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("redis-rust-{}-{}.aof", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_append_and_replay() -> Result<()> {
+        let path = temp_path("replay");
+        let _ = tokio::fs::remove_file(&path).await;
+        {
+            let aof = Aof::open(&path, AppendFsync::Always).await?;
+            let mut writer = aof.lock().await;
+            writer
+                .append(0, UserCommand::Set, &args(&["a", "1"]))
+                .await?;
+            writer.append(0, UserCommand::Incr, &args(&["a"])).await?;
+            writer
+                .append(2, UserCommand::SAdd, &args(&["s", "x", "y"]))
+                .await?;
+            writer
+                .append(0, UserCommand::Del, &args(&["missing"]))
+                .await?;
+        }
+
+        // A SELECT is only written when the database changes
+        let contents = tokio::fs::read_to_string(&path).await?;
+        assert_eq!(contents.matches("SELECT").count(), 3);
+        assert!(contents.starts_with("*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n"));
+
+        let state = ServerState::new(Config::new());
+        assert_eq!(replay(&path, &state).await?, 7);
+        assert_eq!(
+            state.databases[0].read().await.get("a"),
+            Some(&DataType::String("2".into()))
+        );
+        assert!(matches!(
+            state.databases[2].read().await.get("s"),
+            Some(DataType::Set(set)) if set.len() == 2
+        ));
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_drops_truncated_command() -> Result<()> {
+        let path = temp_path("truncated");
+        let complete = Value::Array(args(&["SET", "a", "1"])).serialize();
+        let partial = Value::Array(args(&["SET", "b", "2"])).serialize();
+        let mut contents = complete.to_vec();
+        contents.extend_from_slice(&partial[..partial.len() - 3]);
+        tokio::fs::write(&path, &contents).await?;
+
+        let state = ServerState::new(Config::new());
+        assert_eq!(replay(&path, &state).await?, 1);
+        assert!(state.databases[0].read().await.get("b").is_none());
+        // The partial command is cut off so later appends start on a frame boundary
+        assert_eq!(tokio::fs::read(&path).await?, complete.to_vec());
+
+        tokio::fs::remove_file(&path).await?;
+        assert_eq!(replay(&path, &state).await?, 0);
+        Ok(())
+    }
+
+    // Fills database 0 with one key of each type and database 1 with a large set
+    async fn populate(state: &ServerState) {
+        let mut first = state.databases[0].write().await;
+        let mut expiring = Entry::new(DataType::String("value".into()));
+        expiring.expires_at = Some(SystemClock.now_millis() + 60_000);
+        first.insert_entry("string".to_owned(), expiring);
+        first.insert_entry(
+            "hash".to_owned(),
+            Entry::new(DataType::Hash(
+                [("field".to_owned(), Bytes::from("value"))]
+                    .into_iter()
+                    .collect(),
+            )),
+        );
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("low".into(), f64::NEG_INFINITY);
+        sorted_set.insert("mid".into(), 0.1);
+        first.insert_entry(
+            "zset".to_owned(),
+            Entry::new(DataType::SortedSet(sorted_set)),
+        );
+        let mut stream = Stream::new();
+        for seq in 1..3 {
+            stream.insert(
+                StreamId::new(5, seq),
+                vec![("field".into(), seq.to_string().into())],
+            );
+        }
+        first.insert_entry("stream".to_owned(), Entry::new(DataType::Stream(stream)));
+        first.insert_entry(
+            "list".to_owned(),
+            Entry::new(DataType::List(
+                ["b", "a", "b"].into_iter().map(Bytes::from).collect(),
+            )),
+        );
+
+        let members: ScanSet<Bytes> = (0..150).map(|i| Bytes::from(i.to_string())).collect();
+        state.databases[1]
+            .write()
+            .await
+            .insert_entry("set".to_owned(), Entry::new(DataType::Set(members)));
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_commands_rebuild_keyspace() -> Result<()> {
+        let source = ServerState::new(Config::new());
+        populate(&source).await;
+        let keyspace = rdb::snapshot(&source.databases).await;
+
+        let commands = rewrite_commands(&keyspace);
+        // 150 members need three SADD commands
+        assert_eq!(
+            String::from_utf8_lossy(&commands).matches("SADD").count(),
+            3
+        );
+
+        let path = temp_path("rewrite-commands");
+        tokio::fs::write(&path, &commands).await?;
+        let restored = ServerState::new(Config::new());
+        replay(&path, &restored).await?;
+        tokio::fs::remove_file(&path).await?;
+
+        for (index, entries) in keyspace.into_iter().enumerate() {
+            let instance = restored.databases[index].read().await;
+            for (key, entry) in entries {
+                assert_eq!(instance.get_entry(&key), Some(&entry));
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_commands_rebuild_consumer_groups() -> Result<()> {
+        let mut stream = Stream::new();
+        for seq in 1..=4 {
+            stream.insert(StreamId::new(1, seq), vec![("f".into(), "v".into())]);
+        }
+        let group = stream.create_group("group", StreamId::MIN).unwrap();
+        for (seq, consumer) in [(1, "alice"), (3, "bob"), (4, "alice")] {
+            group.deliver(StreamId::new(1, seq), consumer, SystemClock.now_millis());
+        }
+        group.set_last_delivered(StreamId::new(1, 4));
+        group.create_consumer("idle");
+        stream.create_group("empty", StreamId::new(1, 2));
+        let keyspace = vec![vec![(
+            "stream".to_owned(),
+            Entry::new(DataType::Stream(stream.clone())),
+        )]];
+
+        let path = temp_path("rewrite-groups");
+        tokio::fs::write(&path, rewrite_commands(&keyspace)).await?;
+        let restored = ServerState::new(Config::new());
+        replay(&path, &restored).await?;
+        tokio::fs::remove_file(&path).await?;
+
+        let instance = restored.databases[0].read().await;
+        let Some(DataType::Stream(restored)) = instance.get("stream") else {
+            panic!("The stream is rebuilt");
+        };
+        for (name, group) in stream.groups() {
+            let restored = restored.group(name).unwrap();
+            assert_eq!(restored.last_delivered(), group.last_delivered());
+            assert_eq!(restored.consumers(), group.consumers());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_keeps_concurrent_writes() -> Result<()> {
+        let path = temp_path("rewrite");
+        let _ = tokio::fs::remove_file(&path).await;
+        let state = ServerState::new(Config::new());
+        let aof = Arc::new(Aof::open(&path, AppendFsync::No).await?);
+        for _ in 0..100 {
+            aof.lock()
+                .await
+                .append(0, UserCommand::Incr, &args(&["counter"]))
+                .await?;
+            execute_command(UserCommand::Incr, &args(&["counter"]), &state, &mut 0, None).await?;
+        }
+
+        assert!(aof.start_rewrite(&state.databases).await);
+        assert!(!aof.start_rewrite(&state.databases).await);
+        // A write made while the rewrite runs ends up in the new file
+        aof.lock()
+            .await
+            .append(4, UserCommand::Set, &args(&["late", "write"]))
+            .await?;
+        while aof.lock().await.rewrite_buffer.is_some() {
+            tokio::task::yield_now().await;
+        }
+        aof.lock()
+            .await
+            .append(4, UserCommand::Set, &args(&["after", "swap"]))
+            .await?;
+
+        let contents = tokio::fs::read_to_string(&path).await?;
+        assert!(!contents.contains("INCR"));
+        let restored = ServerState::new(Config::new());
+        // SELECT 0, SET counter, SELECT 4 and the two late writes
+        assert_eq!(replay(&path, &restored).await?, 5);
+        assert_eq!(
+            restored.databases[0].read().await.get("counter"),
+            Some(&DataType::String("100".into()))
+        );
+        assert!(restored.databases[4].read().await.get("late").is_some());
+        assert!(restored.databases[4].read().await.get("after").is_some());
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,682 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use super::rdb::Keyspace;
+use crate::error::{Context, Error, Result};
+use crate::storage::scan::{ScanMap, ScanSet};
+use crate::storage::{
+    sorted_set::SortedSet,
+    stream::{PendingEntry, Stream, StreamId},
+    DataType, Entry,
+};
+
+pub mod tests_json;
+
+// Deeper documents than any keyspace produces are refused rather than recursed into
+const MAX_DEPTH: usize = 32;
+
+// Document layout: an object from database number to an object from key to entry, one
+// key per line and everything sorted so that two dumps diff cleanly. An entry is
+// {"type": ..., "value": ...} with a "pttl" in milliseconds when the key expires, so
+// fixtures loaded later keep their keys alive for as long. Strings that are not UTF-8
+// are written as {"hex": "..."}, and infinite scores as "inf" and "-inf".
+
+/// The keyspace as a JSON document, as DEBUG JSON-DUMP reports it, with TTLs counted
+/// from `now` in Unix milliseconds.
+pub fn encode(keyspace: &Keyspace, now: u64) -> String {
+    let databases: Vec<String> = keyspace
+        .iter()
+        .enumerate()
+        .filter(|(_, entries)| !entries.is_empty())
+        .map(|(index, entries)| {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let keys: Vec<String> = entries
+                .into_iter()
+                .map(|(key, entry)| {
+                    let mut line = String::from("    ");
+                    put_string(&mut line, key);
+                    line.push_str(": ");
+                    put_entry(&mut line, entry, now);
+                    line
+                })
+                .collect();
+            format!("  \"{}\": {{\n{}\n  }}", index, keys.join(",\n"))
+        })
+        .collect();
+    match databases.is_empty() {
+        true => "{}\n".to_owned(),
+        false => format!("{{\n{}\n}}\n", databases.join(",\n")),
+    }
+}
+
+fn put_entry(out: &mut String, entry: &Entry, now: u64) {
+    let name = match entry.value {
+        DataType::String(_) => "string",
+        DataType::Hash(_) => "hash",
+        DataType::Set(_) => "set",
+        DataType::SortedSet(_) => "zset",
+        DataType::List(_) => "list",
+        DataType::Stream(_) => "stream",
+    };
+    write!(out, "{{\"type\": \"{}\", \"value\": ", name).unwrap();
+    put_value(out, &entry.value);
+    if let Some(expires_at) = entry.expires_at {
+        write!(out, ", \"pttl\": {}", expires_at.saturating_sub(now)).unwrap();
+    }
+    out.push('}');
+}
+
+fn put_value(out: &mut String, value: &DataType) {
+    match value {
+        DataType::String(string) => put_bytes(out, &string.to_bytes()),
+        DataType::Hash(hash) => {
+            let mut fields: Vec<_> = hash.iter().collect();
+            fields.sort();
+            put_object(out, fields, |out, value| put_bytes(out, value));
+        }
+        DataType::Set(set) => {
+            let mut members: Vec<_> = set.iter().collect();
+            members.sort();
+            put_array(out, members, |out, member| put_bytes(out, member));
+        }
+        DataType::SortedSet(sorted_set) => {
+            put_array(out, sorted_set.iter(), |out, (member, score)| {
+                out.push('[');
+                put_bytes(out, member);
+                out.push_str(", ");
+                put_score(out, score);
+                out.push(']');
+            });
+        }
+        DataType::List(list) => put_array(out, list, |out, element| put_bytes(out, element)),
+        DataType::Stream(stream) => put_stream(out, stream),
+    }
+}
+
+// A stream's entries go in order with their fields as a flat list, which may repeat a
+// field, and its consumer groups with every consumer's pending entries
+fn put_stream(out: &mut String, stream: &Stream) {
+    write!(
+        out,
+        "{{\"last_id\": \"{}\", \"entries\": ",
+        stream.last_id()
+    )
+    .unwrap();
+    put_array(out, stream.entries(), |out, (id, fields)| {
+        write!(out, "[\"{}\", ", id).unwrap();
+        let flat = fields.iter().flat_map(|(field, value)| [field, value]);
+        put_array(out, flat, |out, part| put_bytes(out, part));
+        out.push(']');
+    });
+    out.push_str(", \"groups\": ");
+    put_object(out, stream.groups(), |out, group| {
+        write!(
+            out,
+            "{{\"last_delivered\": \"{}\", \"consumers\": ",
+            group.last_delivered()
+        )
+        .unwrap();
+        put_object(out, group.consumers(), |out, consumer| {
+            put_array(out, consumer.pending(), |out, id| {
+                let entry = &group.pending()[id];
+                write!(
+                    out,
+                    "{{\"id\": \"{}\", \"delivered_at\": {}, \"deliveries\": {}}}",
+                    id, entry.delivered_at, entry.deliveries
+                )
+                .unwrap();
+            });
+        });
+        out.push('}');
+    });
+    out.push('}');
+}
+
+fn put_array<T>(
+    out: &mut String,
+    items: impl IntoIterator<Item = T>,
+    put: impl Fn(&mut String, T),
+) {
+    out.push('[');
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        put(out, item);
+    }
+    out.push(']');
+}
+
+fn put_object<'a, K: AsRef<str> + 'a, T: 'a>(
+    out: &mut String,
+    members: impl IntoIterator<Item = (&'a K, &'a T)>,
+    put: impl Fn(&mut String, &T),
+) {
+    out.push('{');
+    for (index, (name, member)) in members.into_iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        put_string(out, name.as_ref());
+        out.push_str(": ");
+        put(out, member);
+    }
+    out.push('}');
+}
+
+fn put_bytes(out: &mut String, bytes: &[u8]) {
+    match std::str::from_utf8(bytes) {
+        Ok(string) => put_string(out, string),
+        Err(_) => {
+            out.push_str("{\"hex\": \"");
+            for byte in bytes {
+                write!(out, "{:02x}", byte).unwrap();
+            }
+            out.push_str("\"}");
+        }
+    }
+}
+
+/// Appends a string as a quoted JSON string.
+pub fn put_string(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn put_score(out: &mut String, score: f64) {
+    match score {
+        f64::INFINITY => out.push_str("\"inf\""),
+        f64::NEG_INFINITY => out.push_str("\"-inf\""),
+        score => write!(out, "{}", score).unwrap(),
+    }
+}
+
+/// Reads back a document `encode` wrote, or one written by hand in the same layout,
+/// for a server with `databases` databases, its TTLs counting from `now`.
+pub fn decode(text: &str, databases: usize, now: u64) -> Result<Keyspace> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+    };
+    let document = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.position < parser.text.len() {
+        return Err(Error::Corrupt(format!(
+            "Trailing data after the document at byte {}",
+            parser.position
+        )));
+    }
+
+    let mut keyspace: Keyspace = Vec::new();
+    for (index, entries) in as_object(&document)? {
+        let index: usize = index
+            .parse()
+            .ok()
+            .filter(|index| *index < databases)
+            .ok_or_else(|| Error::Corrupt(format!("Invalid database number '{}'", index)))?;
+        if keyspace.len() <= index {
+            keyspace.resize_with(index + 1, Vec::new);
+        }
+        for (key, entry) in as_object(entries)? {
+            let entry =
+                decode_entry(entry, now).with_context(|| format!("Invalid key '{}'", key))?;
+            keyspace[index].push((key.clone(), entry));
+        }
+    }
+    Ok(keyspace)
+}
+
+fn decode_entry(entry: &Json, now: u64) -> Result<Entry> {
+    let entry = as_object(entry)?;
+    let expires_at = match field(entry, "pttl") {
+        Some(pttl) => Some(now.saturating_add(as_u64(pttl)?)),
+        None => None,
+    };
+    let value = || field(entry, "value").ok_or_else(|| Error::Corrupt("Missing value".to_owned()));
+    let value = match as_str(
+        field(entry, "type").ok_or_else(|| Error::Corrupt("Missing type".to_owned()))?,
+    )? {
+        "string" => DataType::String(as_bytes(value()?)?.into()),
+        "hash" => {
+            let mut hash = ScanMap::new();
+            for (name, value) in as_object(value()?)? {
+                hash.insert(name.clone(), as_bytes(value)?);
+            }
+            DataType::Hash(hash)
+        }
+        "set" => DataType::Set(
+            as_array(value()?)?
+                .iter()
+                .map(as_bytes)
+                .collect::<Result<ScanSet<_>>>()?,
+        ),
+        "zset" => {
+            let mut sorted_set = SortedSet::new();
+            for pair in as_array(value()?)? {
+                let [member, score] = as_array(pair)? else {
+                    return Err(Error::Corrupt(
+                        "Sorted set members must be [member, score] pairs".to_owned(),
+                    ));
+                };
+                sorted_set.insert(as_bytes(member)?, as_score(score)?);
+            }
+            DataType::SortedSet(sorted_set)
+        }
+        "list" => DataType::List(
+            as_array(value()?)?
+                .iter()
+                .map(as_bytes)
+                .collect::<Result<VecDeque<_>>>()?,
+        ),
+        "stream" => DataType::Stream(decode_stream(as_object(value()?)?)?),
+        name => return Err(Error::Corrupt(format!("Unknown type '{}'", name))),
+    };
+    Ok(Entry { value, expires_at })
+}
+
+fn decode_stream(value: &[(String, Json)]) -> Result<Stream> {
+    let mut stream = Stream::new();
+    if let Some(entries) = field(value, "entries") {
+        for pair in as_array(entries)? {
+            let [id, fields] = as_array(pair)? else {
+                return Err(Error::Corrupt(
+                    "Stream entries must be [id, fields] pairs".to_owned(),
+                ));
+            };
+            let fields = as_array(fields)?;
+            if fields.len() % 2 != 0 {
+                return Err(Error::Corrupt(
+                    "Stream entry fields must come in field and value pairs".to_owned(),
+                ));
+            }
+            let fields = fields
+                .chunks(2)
+                .map(|pair| Ok((as_bytes(&pair[0])?, as_bytes(&pair[1])?)))
+                .collect::<Result<_>>()?;
+            stream.insert(as_stream_id(id)?, fields);
+        }
+    }
+    if let Some(last_id) = field(value, "last_id") {
+        stream.set_last_id(as_stream_id(last_id)?);
+    }
+    if let Some(last_id) = stream.entries().keys().next_back().copied() {
+        stream.set_last_id(last_id);
+    }
+    for (name, group) in field(value, "groups").map_or(Ok(&[][..]), as_object)? {
+        let group = as_object(group)?;
+        let last_delivered = match field(group, "last_delivered") {
+            Some(id) => as_stream_id(id)?,
+            None => StreamId::MIN,
+        };
+        let Some(created) = stream.create_group(name, last_delivered) else {
+            return Err(Error::Corrupt(format!(
+                "Duplicate consumer group '{}'",
+                name
+            )));
+        };
+        for (consumer, pending) in field(group, "consumers").map_or(Ok(&[][..]), as_object)? {
+            created.create_consumer(consumer);
+            for pending in as_array(pending)? {
+                let pending = as_object(pending)?;
+                let number = |name| match field(pending, name) {
+                    Some(number) => as_u64(number),
+                    None => Err(Error::Corrupt(format!("Missing {}", name))),
+                };
+                let id =
+                    field(pending, "id").ok_or_else(|| Error::Corrupt("Missing id".to_owned()))?;
+                let entry = PendingEntry {
+                    consumer: consumer.clone(),
+                    delivered_at: number("delivered_at")?,
+                    deliveries: number("deliveries")?,
+                };
+                created.insert_pending(as_stream_id(id)?, entry);
+            }
+        }
+    }
+    Ok(stream)
+}
+
+fn field<'a>(object: &'a [(String, Json)], name: &str) -> Option<&'a Json> {
+    object
+        .iter()
+        .find(|(member, _)| member == name)
+        .map(|(_, value)| value)
+}
+
+fn as_object(json: &Json) -> Result<&[(String, Json)]> {
+    match json {
+        Json::Object(members) => Ok(members),
+        other => Err(Error::Corrupt(format!(
+            "Expected an object, got {}",
+            other.kind()
+        ))),
+    }
+}
+
+fn as_array(json: &Json) -> Result<&[Json]> {
+    match json {
+        Json::Array(items) => Ok(items),
+        other => Err(Error::Corrupt(format!(
+            "Expected an array, got {}",
+            other.kind()
+        ))),
+    }
+}
+
+fn as_str(json: &Json) -> Result<&str> {
+    match json {
+        Json::String(string) => Ok(string),
+        other => Err(Error::Corrupt(format!(
+            "Expected a string, got {}",
+            other.kind()
+        ))),
+    }
+}
+
+fn as_u64(json: &Json) -> Result<u64> {
+    match json {
+        Json::Number(number) => number.parse().map_err(|_| {
+            Error::Corrupt(format!("Expected a non-negative integer, got {}", number))
+        }),
+        other => Err(Error::Corrupt(format!(
+            "Expected a number, got {}",
+            other.kind()
+        ))),
+    }
+}
+
+fn as_score(json: &Json) -> Result<f64> {
+    let score = match json {
+        Json::Number(number) => number
+            .parse()
+            .map_err(|_| Error::Corrupt(format!("Invalid score '{}'", number)))?,
+        Json::String(string) => match string.as_str() {
+            "inf" | "+inf" => f64::INFINITY,
+            "-inf" => f64::NEG_INFINITY,
+            _ => return Err(Error::Corrupt(format!("Invalid score '{}'", string))),
+        },
+        other => {
+            return Err(Error::Corrupt(format!(
+                "Expected a score, got {}",
+                other.kind()
+            )))
+        }
+    };
+    Ok(score)
+}
+
+fn as_stream_id(json: &Json) -> Result<StreamId> {
+    let id = as_str(json)?;
+    StreamId::parse(id, 0).ok_or_else(|| Error::Corrupt(format!("Invalid stream ID '{}'", id)))
+}
+
+// A string, or {"hex": "..."} for bytes that are not UTF-8
+fn as_bytes(json: &Json) -> Result<Bytes> {
+    match json {
+        Json::String(string) => Ok(Bytes::copy_from_slice(string.as_bytes())),
+        Json::Object(members) => {
+            let [(name, Json::String(hex))] = members.as_slice() else {
+                return Err(Error::Corrupt(
+                    "Expected a string or {\"hex\": ...}".to_owned(),
+                ));
+            };
+            if name != "hex" || hex.len() % 2 != 0 {
+                return Err(Error::Corrupt(
+                    "Expected a string or {\"hex\": ...}".to_owned(),
+                ));
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|at| {
+                    u8::from_str_radix(hex.get(at..at + 2).unwrap_or("?"), 16)
+                        .map_err(|_| Error::Corrupt(format!("Invalid hex '{}'", hex)))
+                })
+                .collect::<Result<Vec<u8>>>()
+                .map(Bytes::from)
+        }
+        other => Err(Error::Corrupt(format!(
+            "Expected a string, got {}",
+            other.kind()
+        ))),
+    }
+}
+
+/// A parsed JSON value. Numbers keep their text, so integers past 2^53 survive.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Corrupt("Document nested too deeply".to_owned()));
+        }
+        self.skip_whitespace();
+        let value = match self.peek()? {
+            b'{' => {
+                self.position += 1;
+                let mut members = Vec::new();
+                if !self.consume(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        self.skip_whitespace();
+                        self.expect(b':')?;
+                        members.push((name, self.value(depth + 1)?));
+                        if !self.separator(b'}')? {
+                            break;
+                        }
+                    }
+                }
+                Json::Object(members)
+            }
+            b'[' => {
+                self.position += 1;
+                let mut items = Vec::new();
+                if !self.consume(b']') {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        if !self.separator(b']')? {
+                            break;
+                        }
+                    }
+                }
+                Json::Array(items)
+            }
+            b'"' => Json::String(self.string()?),
+            b't' => self.literal("true", Json::Bool(true))?,
+            b'f' => self.literal("false", Json::Bool(false))?,
+            b'n' => self.literal("null", Json::Null)?,
+            _ => self.number()?,
+        };
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.text
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| Error::Corrupt("Unexpected end of the document".to_owned()))
+    }
+
+    // Skips the byte if it comes next, past any whitespace
+    fn consume(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.get(self.position) == Some(&byte);
+        self.position += found as usize;
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if !self.consume(byte) {
+            return Err(Error::Corrupt(format!(
+                "Expected '{}' at byte {}",
+                byte as char, self.position
+            )));
+        }
+        Ok(())
+    }
+
+    // After an element: true when a comma says another follows, false at the end
+    fn separator(&mut self, end: u8) -> Result<bool> {
+        if self.consume(b',') {
+            return Ok(true);
+        }
+        self.expect(end)?;
+        Ok(false)
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        if !self.text[self.position..].starts_with(word.as_bytes()) {
+            return Err(Error::Corrupt(format!(
+                "Unexpected token at byte {}",
+                self.position
+            )));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.position;
+        while self
+            .text
+            .get(self.position)
+            .is_some_and(|byte| matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.position += 1;
+        }
+        // The text is a str and only ASCII was taken, so this slice is one too
+        let number = std::str::from_utf8(&self.text[start..self.position])
+            .map_err(|_| Error::Corrupt(format!("Unexpected token at byte {}", start)))?;
+        if number.parse::<f64>().is_err() || number.starts_with('+') {
+            return Err(Error::Corrupt(format!(
+                "Unexpected token at byte {}",
+                start
+            )));
+        }
+        Ok(Json::Number(number.to_owned()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.peek()?;
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek()?;
+                    self.position += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => {
+                            return Err(Error::Corrupt(format!(
+                                "Invalid escape at byte {}",
+                                self.position - 1
+                            )))
+                        }
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte if byte < b' ' => {
+                    return Err(Error::Corrupt(
+                        "Unescaped control character in a string".to_owned(),
+                    ))
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        // Only whole characters of the str and encoded escapes went in
+        String::from_utf8(bytes).map_err(|_| Error::Corrupt("Invalid UTF-8 in a string".to_owned()))
+    }
+
+    // The character of a \uXXXX escape, the 'u' already consumed, joining the two
+    // halves of a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if !self.text[self.position..].starts_with(b"\\u") {
+                    return Err(Error::Corrupt("Unpaired surrogate in a string".to_owned()));
+                }
+                self.position += 2;
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(Error::Corrupt("Unpaired surrogate in a string".to_owned()));
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            code => code,
+        };
+        char::from_u32(code)
+            .ok_or_else(|| Error::Corrupt("Invalid \\u escape in a string".to_owned()))
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .text
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| Error::Corrupt("Invalid \\u escape in a string".to_owned()))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
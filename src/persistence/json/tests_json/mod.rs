@@ -0,0 +1,153 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    // The time the tests encode and decode at, in Unix milliseconds
+    const NOW: u64 = 1_700_000_000_000;
+
+    // One entry of every type, one of them with an expiry, spread over two databases
+    fn keyspace() -> Keyspace {
+        let mut hash = ScanMap::new();
+        hash.insert("field".to_owned(), Bytes::from("value"));
+        hash.insert("quoted \"field\"".to_owned(), Bytes::from("line\nbreak"));
+        let set: ScanSet<Bytes> = [Bytes::from("a"), Bytes::from(vec![0u8, 255])]
+            .into_iter()
+            .collect();
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert(Bytes::from("low"), -1.5);
+        sorted_set.insert(Bytes::from("high"), f64::INFINITY);
+
+        let mut stream = Stream::new();
+        stream.insert(
+            StreamId::new(1, 1),
+            vec![
+                (Bytes::from("field"), Bytes::from("value")),
+                (Bytes::from("field"), Bytes::from("again")),
+            ],
+        );
+        stream.set_last_id(StreamId::new(9, 0));
+        let group = stream.create_group("group", StreamId::new(1, 1)).unwrap();
+        group.deliver(StreamId::new(1, 1), "consumer", 1_000);
+        group.create_consumer("idle");
+
+        let mut expiring = Entry::new(DataType::String(Bytes::from("snowman ☃").into()));
+        expiring.expires_at = Some(NOW + 60_000);
+
+        vec![
+            vec![
+                ("string".to_owned(), expiring),
+                ("hash".to_owned(), Entry::new(DataType::Hash(hash))),
+                ("stream".to_owned(), Entry::new(DataType::Stream(stream))),
+            ],
+            Vec::new(),
+            vec![
+                ("set".to_owned(), Entry::new(DataType::Set(set))),
+                (
+                    "zset".to_owned(),
+                    Entry::new(DataType::SortedSet(sorted_set)),
+                ),
+                (
+                    "list".to_owned(),
+                    Entry::new(DataType::List(
+                        [Bytes::from("first"), Bytes::new()].into_iter().collect(),
+                    )),
+                ),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() -> Result<()> {
+        let mut keyspace = keyspace();
+        // Keys come back sorted, and expiry times as far off as they were
+        let decoded = decode(&encode(&keyspace, NOW), 16, NOW + 500)?;
+        keyspace[0][0].1.expires_at = Some(NOW + 60_500);
+        for entries in keyspace.iter_mut() {
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        assert_eq!(decoded, keyspace);
+
+        assert_eq!(encode(&vec![Vec::new(); 4], NOW), "{}\n");
+        assert_eq!(decode("{}", 16, NOW)?, Keyspace::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout() -> Result<()> {
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert(Bytes::from("member"), f64::NEG_INFINITY);
+        let keyspace = vec![
+            Vec::new(),
+            vec![
+                ("b".to_owned(), Entry::new(DataType::SortedSet(sorted_set))),
+                (
+                    "a".to_owned(),
+                    Entry::new(DataType::String(Bytes::from(vec![0xff, 0x00]).into())),
+                ),
+            ],
+        ];
+        assert_eq!(
+            encode(&keyspace, NOW),
+            concat!(
+                "{\n",
+                "  \"1\": {\n",
+                "    \"a\": {\"type\": \"string\", \"value\": {\"hex\": \"ff00\"}},\n",
+                "    \"b\": {\"type\": \"zset\", \"value\": [[\"member\", \"-inf\"]]}\n",
+                "  }\n",
+                "}\n"
+            )
+        );
+
+        // Written by hand, with escapes and a key that expires
+        let keyspace = decode(
+            r#"{"0": {"key": {"type": "list", "value": ["é😀", "\/"], "pttl": 5000}}}"#,
+            16,
+            NOW,
+        )?;
+        let [(key, entry)] = keyspace[0].as_slice() else {
+            panic!("one key expected");
+        };
+        assert_eq!(key, "key");
+        assert_eq!(
+            entry.value,
+            DataType::List(VecDeque::from([Bytes::from("é😀"), Bytes::from("/")]))
+        );
+        assert_eq!(entry.expires_at, Some(NOW + 5000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_documents() {
+        let encoded = encode(&keyspace(), NOW);
+        // Every truncation is detected rather than read as a shorter keyspace
+        for length in 0..encoded.trim_end().len() {
+            if encoded.is_char_boundary(length) {
+                assert!(decode(&encoded[..length], 16, NOW).is_err());
+            }
+        }
+        for document in [
+            "",
+            "[]",
+            "{} {}",
+            r#"{"x": {}}"#,
+            r#"{"16": {}}"#,
+            r#"{"0": {"key": {"value": "no type"}}}"#,
+            r#"{"0": {"key": {"type": "blob", "value": ""}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": 1}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": {"hex": "f"}}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": "", "pttl": -1}}}"#,
+            r#"{"0": {"key": {"type": "zset", "value": [["member"]]}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": "\ud83d"}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": "tab	inside"}}}"#,
+            r#"{"0": {"key": {"type": "string", "value": "x",}}}"#,
+        ] {
+            assert!(
+                decode(document, 16, NOW).is_err(),
+                "{} was accepted",
+                document
+            );
+        }
+        // Nesting is bounded rather than recursed into
+        assert!(decode(&"[".repeat(100_000), 16, NOW).is_err());
+    }
+}
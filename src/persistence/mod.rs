@@ -0,0 +1,54 @@
+use bytes::{Bytes, BytesMut};
+use std::path::Path;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::error::{Context, Result};
+use crate::parser::{UserCommand, Value};
+
+pub mod aof;
+pub mod json;
+pub mod rdb;
+
+/// Replaces a file so that readers see either the old or the new contents, never a
+/// partial write: the data is synced to a temporary file next to the target, which is
+/// then renamed over it.
+pub async fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let temp = path.with_extension(format!("tmp-{}", std::process::id()));
+    let mut file = fs::File::create(&temp)
+        .await
+        .with_context(|| format!("Failed to create {}", temp.display()))?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    fs::rename(&temp, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Serializes a write command for a command stream (the AOF or the replication feed).
+/// `selected` is the stream's current database: a SELECT is emitted first whenever the
+/// command targets another one.
+pub fn encode_command(
+    selected: &mut Option<usize>,
+    db: usize,
+    command: UserCommand,
+    args: &[Value],
+) -> Bytes {
+    let mut encoded = BytesMut::new();
+    if *selected != Some(db) {
+        let select = Value::Array(vec![
+            Value::BulkString("SELECT".into()),
+            Value::BulkString(db.to_string().into()),
+        ]);
+        select.encode(&mut encoded);
+        *selected = Some(db);
+    }
+    let mut frame = Vec::with_capacity(args.len() + 1);
+    // Plugin commands carry their own name as the first argument
+    if command != UserCommand::Invalid {
+        frame.push(Value::BulkString(command.name().into()));
+    }
+    frame.extend_from_slice(args);
+    Value::Array(frame).encode(&mut encoded);
+    encoded.freeze()
+}
@@ -0,0 +1,469 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{collections::VecDeque, path::Path, sync::Arc};
+
+use super::write_atomically;
+use crate::error::{Context, Error, Result};
+use crate::storage::scan::{ScanMap, ScanSet};
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+use crate::storage::{
+    sorted_set::SortedSet,
+    stream::{PendingEntry, Stream, StreamId},
+    DataType, Entry,
+};
+
+pub mod tests_rdb;
+
+// File layout: MAGIC, VERSION, then for every non-empty database OP_SELECT_DB and its
+// index followed by its entries, and finally OP_EOF. An entry is an optional
+// OP_EXPIRE_MS with the absolute expiry, a type tag, the key and the value. Lengths
+// and counts are LEB128 varints, scores are little-endian f64 bits.
+const MAGIC: &[u8] = b"REDISRS";
+const VERSION: u8 = 1;
+
+const OP_EXPIRE_MS: u8 = 0xFC;
+const OP_SELECT_DB: u8 = 0xFE;
+const OP_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_HASH: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_SORTED_SET: u8 = 3;
+const TYPE_LIST: u8 = 4;
+const TYPE_STREAM: u8 = 5;
+
+/// The live entries of every database at one point in time, indexed by database number.
+pub type Keyspace = Vec<Vec<(String, Entry)>>;
+
+/// Copies every database. All read locks are held together so the copy is consistent
+/// across databases.
+pub async fn snapshot(databases: &[Arc<ShardedDb>]) -> Keyspace {
+    let mut instances = Vec::with_capacity(databases.len());
+    for db_instance in databases {
+        instances.push(db_instance.read().await);
+    }
+    instances
+        .iter()
+        .map(|instance| {
+            instance
+                .entries()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes a snapshot to `path`, replacing any previous file.
+pub async fn save(keyspace: &Keyspace, path: &Path) -> Result<()> {
+    write_atomically(path, &encode(keyspace)).await
+}
+
+/// Loads the snapshot at `path` into the databases, skipping keys that expired while
+/// the server was down. A missing file is an empty keyspace. Returns the keys loaded.
+pub async fn load(path: &Path, databases: &[Arc<ShardedDb>]) -> Result<usize> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let keyspace = decode(&data).with_context(|| format!("Corrupt snapshot {}", path.display()))?;
+    restore(keyspace, databases).await
+}
+
+/// Replaces the contents of the databases with a decoded snapshot, skipping keys that
+/// have already expired. Returns the keys loaded.
+pub async fn restore(keyspace: Keyspace, databases: &[Arc<ShardedDb>]) -> Result<usize> {
+    if keyspace.len() > databases.len() {
+        return Err(Error::Corrupt(format!(
+            "Snapshot uses database {} but only {} are configured",
+            keyspace.len() - 1,
+            databases.len()
+        )));
+    }
+
+    let mut loaded = 0;
+    let mut keyspace = keyspace.into_iter();
+    for db_instance in databases {
+        let now = db_instance.now_millis();
+        let mut instance = db_instance.write().await;
+        instance.clear();
+        for (key, entry) in keyspace
+            .next()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+        {
+            instance.insert_entry(key, entry);
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+pub fn encode(keyspace: &Keyspace) -> Bytes {
+    let mut buffer = BytesMut::new();
+    buffer.put_slice(MAGIC);
+    buffer.put_u8(VERSION);
+
+    for (index, entries) in keyspace.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        buffer.put_u8(OP_SELECT_DB);
+        put_length(&mut buffer, index);
+        for (key, entry) in entries {
+            if let Some(expires_at) = entry.expires_at {
+                buffer.put_u8(OP_EXPIRE_MS);
+                buffer.put_u64_le(expires_at);
+            }
+            encode_value(&mut buffer, key, &entry.value);
+        }
+    }
+
+    buffer.put_u8(OP_EOF);
+    buffer.freeze()
+}
+
+fn encode_value(buffer: &mut BytesMut, key: &str, value: &DataType) {
+    buffer.put_u8(value_tag(value));
+    put_bytes(buffer, key.as_bytes());
+    put_payload(buffer, value);
+}
+
+fn value_tag(value: &DataType) -> u8 {
+    match value {
+        DataType::String(_) => TYPE_STRING,
+        DataType::Hash(_) => TYPE_HASH,
+        DataType::Set(_) => TYPE_SET,
+        DataType::SortedSet(_) => TYPE_SORTED_SET,
+        DataType::List(_) => TYPE_LIST,
+        DataType::Stream(_) => TYPE_STREAM,
+    }
+}
+
+fn put_payload(buffer: &mut BytesMut, value: &DataType) {
+    match value {
+        DataType::String(string) => put_bytes(buffer, &string.to_bytes()),
+        DataType::Hash(hash) => {
+            put_length(buffer, hash.len());
+            for (field, value) in hash {
+                put_bytes(buffer, field.as_bytes());
+                put_bytes(buffer, value);
+            }
+        }
+        DataType::Set(set) => {
+            put_length(buffer, set.len());
+            for member in set {
+                put_bytes(buffer, member);
+            }
+        }
+        DataType::SortedSet(sorted_set) => {
+            put_length(buffer, sorted_set.len());
+            for (member, score) in sorted_set.iter() {
+                put_bytes(buffer, member);
+                buffer.put_f64_le(score);
+            }
+        }
+        DataType::List(list) => {
+            put_length(buffer, list.len());
+            for element in list {
+                put_bytes(buffer, element);
+            }
+        }
+        DataType::Stream(stream) => {
+            put_stream_id(buffer, stream.last_id());
+            put_length(buffer, stream.entries().len());
+            for (id, fields) in stream.entries() {
+                put_stream_id(buffer, *id);
+                put_length(buffer, fields.len());
+                for (field, value) in fields {
+                    put_bytes(buffer, field);
+                    put_bytes(buffer, value);
+                }
+            }
+            // Consumer groups with each consumer's pending entries
+            put_length(buffer, stream.groups().len());
+            for (name, group) in stream.groups() {
+                put_bytes(buffer, name.as_bytes());
+                put_stream_id(buffer, group.last_delivered());
+                put_length(buffer, group.consumers().len());
+                for (consumer, state) in group.consumers() {
+                    put_bytes(buffer, consumer.as_bytes());
+                    put_length(buffer, state.pending().len());
+                    for id in state.pending() {
+                        let entry = &group.pending()[id];
+                        put_stream_id(buffer, *id);
+                        buffer.put_u64_le(entry.delivered_at);
+                        buffer.put_u64_le(entry.deliveries);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The DUMP serialization of one value: its type tag and payload as in a snapshot,
+/// then VERSION as a little-endian u16 and a CRC-64 of everything before it.
+pub fn dump(value: &DataType) -> Bytes {
+    let mut buffer = BytesMut::new();
+    buffer.put_u8(value_tag(value));
+    put_payload(&mut buffer, value);
+    buffer.put_u16_le(VERSION.into());
+    let checksum = crc64(&buffer);
+    buffer.put_u64_le(checksum);
+    buffer.freeze()
+}
+
+/// How many bytes the value takes in a snapshot, as DEBUG OBJECT reports it.
+pub fn serialized_length(value: &DataType) -> usize {
+    let mut buffer = BytesMut::new();
+    put_payload(&mut buffer, value);
+    buffer.len()
+}
+
+/// Reads back what `dump` wrote, failing when the version or checksum do not match.
+pub fn undump(data: &[u8]) -> Result<DataType> {
+    let Some(body_length) = data.len().checked_sub(8) else {
+        return Err(Error::Corrupt("Dump payload too short".to_owned()));
+    };
+    let (body, checksum) = data.split_at(body_length);
+    if crc64(body).to_le_bytes() != checksum {
+        return Err(Error::Corrupt("Dump payload checksum mismatch".to_owned()));
+    }
+    let Some(value_length) = body.len().checked_sub(2) else {
+        return Err(Error::Corrupt("Dump payload too short".to_owned()));
+    };
+    let (mut value, version) = body.split_at(value_length);
+    if version != u16::from(VERSION).to_le_bytes() {
+        return Err(Error::Corrupt("Unsupported dump version".to_owned()));
+    }
+
+    let data = &mut value;
+    let tag = take_u8(data)?;
+    let value = decode_value(data, tag)?;
+    if !data.is_empty() {
+        return Err(Error::Corrupt(
+            "Trailing data after the dumped value".to_owned(),
+        ));
+    }
+    Ok(value)
+}
+
+// CRC-64 with the Jones polynomial, the checksum Redis puts in DUMP payloads
+fn crc64(data: &[u8]) -> u64 {
+    const POLYNOMIAL: u64 = 0x95AC_9329_AC4B_C9B5;
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u64::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+pub fn decode(mut data: &[u8]) -> Result<Keyspace> {
+    let data = &mut data;
+    if take(data, MAGIC.len())? != MAGIC {
+        return Err(Error::Corrupt("Not a snapshot file".to_owned()));
+    }
+    let version = take_u8(data)?;
+    if version != VERSION {
+        return Err(Error::Corrupt(format!(
+            "Unsupported snapshot version {}",
+            version
+        )));
+    }
+
+    let mut keyspace: Keyspace = Vec::new();
+    let mut expires_at = None;
+    loop {
+        match take_u8(data)? {
+            OP_EOF => break,
+            OP_SELECT_DB => {
+                let index = take_length(data)?;
+                if index < keyspace.len() {
+                    return Err(Error::Corrupt(format!("Database {} appears twice", index)));
+                }
+                keyspace.resize_with(index + 1, Vec::new);
+            }
+            OP_EXPIRE_MS => expires_at = Some(take_u64(data)?),
+            tag => {
+                let entries = keyspace
+                    .last_mut()
+                    .ok_or_else(|| Error::Corrupt("Entry before any database".to_owned()))?;
+                let key = take_string(data)?;
+                let value = decode_value(data, tag)?;
+                entries.push((
+                    key,
+                    Entry {
+                        value,
+                        expires_at: expires_at.take(),
+                    },
+                ));
+            }
+        }
+    }
+
+    if !data.is_empty() {
+        return Err(Error::Corrupt(
+            "Trailing data after the end of the snapshot".to_owned(),
+        ));
+    }
+    Ok(keyspace)
+}
+
+fn decode_value(data: &mut &[u8], tag: u8) -> Result<DataType> {
+    let value = match tag {
+        TYPE_STRING => DataType::String(take_bytes(data)?.into()),
+        TYPE_HASH => {
+            let count = take_length(data)?;
+            let mut hash = ScanMap::new();
+            for _ in 0..count {
+                let field = take_string(data)?;
+                hash.insert(field, take_bytes(data)?);
+            }
+            DataType::Hash(hash)
+        }
+        TYPE_SET => {
+            let count = take_length(data)?;
+            let mut set = ScanSet::new();
+            for _ in 0..count {
+                set.insert(take_bytes(data)?);
+            }
+            DataType::Set(set)
+        }
+        TYPE_SORTED_SET => {
+            let count = take_length(data)?;
+            let mut sorted_set = SortedSet::new();
+            for _ in 0..count {
+                let member = take_bytes(data)?;
+                let score = f64::from_le_bytes(take_array(data)?);
+                if score.is_nan() {
+                    return Err(Error::Corrupt("NaN score in sorted set".to_owned()));
+                }
+                sorted_set.insert(member, score);
+            }
+            DataType::SortedSet(sorted_set)
+        }
+        TYPE_LIST => {
+            let count = take_length(data)?;
+            let mut list = VecDeque::with_capacity(count.min(data.len()));
+            for _ in 0..count {
+                list.push_back(take_bytes(data)?);
+            }
+            DataType::List(list)
+        }
+        TYPE_STREAM => {
+            let last_id = take_stream_id(data)?;
+            let count = take_length(data)?;
+            let mut stream = Stream::new();
+            for _ in 0..count {
+                let id = take_stream_id(data)?;
+                let field_count = take_length(data)?;
+                let mut fields = Vec::with_capacity(field_count.min(data.len()));
+                for _ in 0..field_count {
+                    fields.push((take_bytes(data)?, take_bytes(data)?));
+                }
+                stream.insert(id, fields);
+            }
+            stream.set_last_id(last_id);
+            for _ in 0..take_length(data)? {
+                let name = take_string(data)?;
+                let Some(group) = stream.create_group(&name, take_stream_id(data)?) else {
+                    return Err(Error::Corrupt(format!(
+                        "Duplicate consumer group '{}'",
+                        name
+                    )));
+                };
+                for _ in 0..take_length(data)? {
+                    let consumer = take_string(data)?;
+                    group.create_consumer(&consumer);
+                    for _ in 0..take_length(data)? {
+                        let id = take_stream_id(data)?;
+                        let entry = PendingEntry {
+                            consumer: consumer.clone(),
+                            delivered_at: take_u64(data)?,
+                            deliveries: take_u64(data)?,
+                        };
+                        group.insert_pending(id, entry);
+                    }
+                }
+            }
+            DataType::Stream(stream)
+        }
+        tag => return Err(Error::Corrupt(format!("Unknown value type {}", tag))),
+    };
+    Ok(value)
+}
+
+fn put_stream_id(buffer: &mut BytesMut, id: StreamId) {
+    buffer.put_u64_le(id.ms);
+    buffer.put_u64_le(id.seq);
+}
+
+fn take_stream_id(data: &mut &[u8]) -> Result<StreamId> {
+    Ok(StreamId::new(take_u64(data)?, take_u64(data)?))
+}
+
+fn put_length(buffer: &mut BytesMut, length: usize) {
+    let mut length = length as u64;
+    while length >= 0x80 {
+        buffer.put_u8((length as u8 & 0x7F) | 0x80);
+        length >>= 7;
+    }
+    buffer.put_u8(length as u8);
+}
+
+fn put_bytes(buffer: &mut BytesMut, bytes: &[u8]) {
+    put_length(buffer, bytes.len());
+    buffer.put_slice(bytes);
+}
+
+// Splits `count` bytes off the front of the input
+fn take<'a>(data: &mut &'a [u8], count: usize) -> Result<&'a [u8]> {
+    if data.len() < count {
+        return Err(Error::Corrupt("Unexpected end of snapshot".to_owned()));
+    }
+    let (head, tail) = data.split_at(count);
+    *data = tail;
+    Ok(head)
+}
+
+fn take_u8(data: &mut &[u8]) -> Result<u8> {
+    Ok(take(data, 1)?[0])
+}
+
+fn take_u64(data: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(take_array(data)?))
+}
+
+fn take_array<const N: usize>(data: &mut &[u8]) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(data, N)?);
+    Ok(array)
+}
+
+fn take_length(data: &mut &[u8]) -> Result<usize> {
+    let mut length: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take_u8(data)?;
+        length |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return usize::try_from(length)
+                .map_err(|_| Error::Corrupt("Length out of range".to_owned()));
+        }
+    }
+    Err(Error::Corrupt("Length varint too long".to_owned()))
+}
+
+fn take_bytes(data: &mut &[u8]) -> Result<Bytes> {
+    let length = take_length(data)?;
+    Ok(Bytes::copy_from_slice(take(data, length)?))
+}
+
+fn take_string(data: &mut &[u8]) -> Result<String> {
+    let length = take_length(data)?;
+    String::from_utf8(take(data, length)?.to_vec())
+        .map_err(|_| Error::Corrupt("Invalid UTF-8 in a string".to_owned()))
+}
@@ -0,0 +1,149 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    fn databases(count: usize) -> Vec<Arc<ShardedDb>> {
+        (0..count).map(|_| Arc::new(ShardedDb::new())).collect()
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("redis-rust-{}-{}.rdb", name, std::process::id()))
+    }
+
+    // One entry of every type, one of them with an expiry, spread over two databases
+    fn keyspace() -> Keyspace {
+        let mut hash = ScanMap::new();
+        hash.insert("field".to_owned(), Bytes::from("value"));
+        let set: ScanSet<Bytes> = [Bytes::from("a"), Bytes::from(vec![0u8, 255])]
+            .into_iter()
+            .collect();
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert(Bytes::from("low"), -1.5);
+        sorted_set.insert(Bytes::from("high"), f64::INFINITY);
+
+        let mut stream = Stream::new();
+        stream.insert(
+            StreamId::new(1, 1),
+            vec![(Bytes::from("field"), Bytes::from("value"))],
+        );
+        stream.set_last_id(StreamId::new(9, 0));
+        let group = stream.create_group("group", StreamId::new(1, 1)).unwrap();
+        group.deliver(StreamId::new(1, 1), "consumer", 1_000);
+        group.create_consumer("idle");
+
+        let mut expiring = Entry::new(DataType::String(Bytes::from("x".repeat(300)).into()));
+        expiring.expires_at = Some(SystemClock.now_millis() + 60_000);
+
+        vec![
+            vec![
+                ("string".to_owned(), expiring),
+                ("hash".to_owned(), Entry::new(DataType::Hash(hash))),
+                ("stream".to_owned(), Entry::new(DataType::Stream(stream))),
+            ],
+            Vec::new(),
+            vec![
+                ("set".to_owned(), Entry::new(DataType::Set(set))),
+                (
+                    "zset".to_owned(),
+                    Entry::new(DataType::SortedSet(sorted_set)),
+                ),
+                (
+                    "list".to_owned(),
+                    Entry::new(DataType::List(
+                        [Bytes::from("first"), Bytes::new()].into_iter().collect(),
+                    )),
+                ),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() -> Result<()> {
+        let keyspace = keyspace();
+        assert_eq!(decode(&encode(&keyspace))?, keyspace);
+
+        // Trailing empty databases are not written at all
+        assert_eq!(decode(&encode(&vec![Vec::new(); 4]))?, Keyspace::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_rejects_corrupt_data() {
+        let encoded = encode(&keyspace());
+        assert!(decode(b"NOTRDB\x01\xFF").is_err());
+        // Every truncation is detected rather than read as a shorter keyspace
+        for length in 0..encoded.len() {
+            assert!(decode(&encoded[..length]).is_err());
+        }
+        let mut trailing = encoded.to_vec();
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() -> Result<()> {
+        let source = databases(3);
+        for (index, entries) in keyspace().into_iter().enumerate() {
+            let mut instance = source[index].write().await;
+            for (key, entry) in entries {
+                instance.insert_entry(key, entry);
+            }
+        }
+        // Already expired keys are left out of the snapshot
+        let mut expired = Entry::new(DataType::String(Bytes::from("gone").into()));
+        expired.expires_at = Some(SystemClock.now_millis() - 1);
+        source[0]
+            .write()
+            .await
+            .insert_entry("expired".to_owned(), expired);
+
+        let path = temp_path("save-load");
+        save(&snapshot(&source).await, &path).await?;
+
+        let target = databases(3);
+        assert_eq!(load(&path, &target).await?, 6);
+        assert!(target[0].read().await.get("expired").is_none());
+        assert_eq!(
+            target[2].read().await.get("zset"),
+            source[2].read().await.get("zset")
+        );
+        assert!(target[0]
+            .read()
+            .await
+            .ttl_millis("string")
+            .unwrap()
+            .is_some());
+
+        // A server with fewer databases refuses the snapshot
+        assert!(load(&path, &databases(2)).await.is_err());
+        tokio::fs::remove_file(&path).await?;
+
+        // A missing file is an empty keyspace
+        assert_eq!(load(&path, &target).await?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_and_undump() -> Result<()> {
+        // The check value of CRC-64/Jones as Redis implements it
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("member".into(), 1.5);
+        let values = [
+            DataType::String("value".into()),
+            DataType::List(VecDeque::from([Bytes::from("a"), Bytes::from("b")])),
+            DataType::SortedSet(sorted_set),
+        ];
+        for value in values {
+            assert_eq!(undump(&dump(&value))?, value);
+        }
+
+        let mut corrupted = dump(&DataType::String("value".into())).to_vec();
+        corrupted[1] ^= 1;
+        assert!(undump(&corrupted).is_err());
+        assert!(undump(b"short").is_err());
+        Ok(())
+    }
+}
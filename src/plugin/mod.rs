@@ -0,0 +1,93 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::error::wrong_arity;
+use crate::parser::{UserCommand, Value};
+use crate::storage::sharded::{ShardedDb, WriteShards};
+
+pub mod tests_plugin;
+
+/// A command added by an embedder with `ServerBuilder::plugin`, for names no built-in
+/// command claims. It runs like any other command: inside transactions, through the
+/// AOF on startup and, when it writes, on replicas that register it too.
+pub trait CommandPlugin: Send + Sync + 'static {
+    /// The command name, matched case-insensitively.
+    fn name(&self) -> &str;
+
+    /// The arity as COMMAND reports it: counting the name, positive for an exact number
+    /// of arguments and negative for a minimum.
+    fn arity(&self) -> i64;
+
+    /// Whether the command modifies the keyspace, so it is logged to the AOF and sent to
+    /// replicas.
+    fn is_write(&self) -> bool {
+        false
+    }
+
+    /// Runs the command with every shard of the selected database locked. `args`
+    /// excludes the name and has already been checked against `arity`.
+    fn call(&self, storage: &mut WriteShards<'_>, args: &[Value]) -> Value;
+}
+
+/// The registered plugins by lower-case name.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    commands: HashMap<String, Arc<dyn CommandPlugin>>,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.commands.keys()).finish()
+    }
+}
+
+impl Plugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a plugin, replacing any earlier one with the same name.
+    pub fn register(&mut self, plugin: impl CommandPlugin) {
+        self.commands
+            .insert(plugin.name().to_ascii_lowercase(), Arc::new(plugin));
+    }
+
+    /// The plugin for a command no built-in claims. Such commands keep their name as
+    /// the first argument, see `extract_command`.
+    pub fn find(&self, command: UserCommand, args: &[Value]) -> Option<&Arc<dyn CommandPlugin>> {
+        if command != UserCommand::Invalid || self.commands.is_empty() {
+            return None;
+        }
+        let Some(Value::BulkString(name)) = args.first() else {
+            return None;
+        };
+        let name = std::str::from_utf8(name).ok()?.to_ascii_lowercase();
+        self.commands.get(&name)
+    }
+
+    /// The lower-case names of the registered plugins.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(String::as_str)
+    }
+
+    /// Whether the command is a plugin that writes.
+    pub fn is_write(&self, command: UserCommand, args: &[Value]) -> bool {
+        self.find(command, args)
+            .is_some_and(|plugin| plugin.is_write())
+    }
+}
+
+/// Checks the arity and calls the plugin. `args` starts with the command name.
+pub async fn plugin_value(
+    plugin: &dyn CommandPlugin,
+    args: &[Value],
+    db_instance: &Arc<ShardedDb>,
+) -> Value {
+    let arity = plugin.arity();
+    let count = args.len() as i64;
+    if (arity >= 0 && count != arity) || (arity < 0 && count < -arity) {
+        return wrong_arity(plugin.name());
+    }
+
+    let mut storage = db_instance.write().await;
+    plugin.call(&mut storage, &args[1..])
+}
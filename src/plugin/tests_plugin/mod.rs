@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::storage::sharded::{Storage, StorageMut};
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    struct Hello;
+
+    impl CommandPlugin for Hello {
+        fn name(&self) -> &str {
+            "HELLO.SET"
+        }
+
+        fn arity(&self) -> i64 {
+            2
+        }
+
+        fn is_write(&self) -> bool {
+            true
+        }
+
+        fn call(&self, storage: &mut WriteShards<'_>, args: &[Value]) -> Value {
+            let Value::BulkString(key) = &args[0] else {
+                return Value::SimpleError("ERR invalid key".to_owned());
+            };
+            storage.insert_entry(
+                String::from_utf8_lossy(key).into_owned(),
+                Entry::new(DataType::String("hello".into())),
+            );
+            Value::SimpleString("OK".to_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_and_call() {
+        let mut plugins = Plugins::new();
+        plugins.register(Hello);
+        let db = Arc::new(ShardedDb::new());
+
+        let call = args(&["hello.set", "key"]);
+        assert!(plugins.is_write(UserCommand::Invalid, &call));
+        assert!(plugins.find(UserCommand::Get, &call).is_none());
+        assert!(plugins
+            .find(UserCommand::Invalid, &args(&["other"]))
+            .is_none());
+
+        let plugin = plugins.find(UserCommand::Invalid, &call).unwrap();
+        assert_eq!(
+            plugin_value(plugin.as_ref(), &call, &db).await,
+            Value::SimpleString("OK".to_owned())
+        );
+        assert!(db.read().await.get("key").is_some());
+        assert_eq!(
+            plugin_value(plugin.as_ref(), &args(&["HELLO.SET"]), &db).await,
+            Value::SimpleError("ERR wrong number of arguments for 'hello.set' command".to_owned())
+        );
+    }
+}
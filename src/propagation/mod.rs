@@ -0,0 +1,436 @@
+use bytes::Bytes;
+use std::{borrow::Cow, time::Instant};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::cluster::command_keys;
+use crate::error::Result;
+use crate::parser::{UserCommand, Value};
+use crate::server::ServerState;
+use crate::storage::scan::scan_hash;
+use crate::storage::sharded::{ShardedDb, Storage};
+
+pub mod tests_propagation;
+
+/// Which keyspace notifications are published, set with Redis's flag letters: K and E
+/// pick the `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>` channels, the
+/// others the classes of event, and A every class. Without K or E nothing is published.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyspaceEvents {
+    keyspace: bool,
+    keyevent: bool,
+    // One bit per letter of CLASSES
+    classes: u16,
+}
+
+// Generic, string, list, set, hash, sorted set, expired, evicted and stream events
+const CLASSES: &str = "g$lshzxet";
+const ALL_CLASSES: u16 = (1 << CLASSES.len()) - 1;
+
+impl KeyspaceEvents {
+    pub fn parse(flags: &str) -> Option<Self> {
+        let mut events = Self::default();
+        for flag in flags.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'A' => events.classes |= ALL_CLASSES,
+                _ => events.classes |= 1 << CLASSES.find(flag)?,
+            }
+        }
+        Some(events)
+    }
+
+    /// The flags as CONFIG GET gives them, with A standing for every class.
+    pub fn name(&self) -> String {
+        let mut name = match self.classes == ALL_CLASSES {
+            true => "A".to_owned(),
+            false => CLASSES
+                .chars()
+                .enumerate()
+                .filter(|(bit, _)| self.classes & 1 << bit != 0)
+                .map(|(_, class)| class)
+                .collect(),
+        };
+        if self.keyspace {
+            name.push('K');
+        }
+        if self.keyevent {
+            name.push('E');
+        }
+        name
+    }
+
+    pub fn publishes(&self, class: char) -> bool {
+        (self.keyspace || self.keyevent)
+            && CLASSES
+                .find(class)
+                .is_some_and(|bit| self.classes & 1 << bit != 0)
+    }
+}
+
+/// Keeps the writes to a key in the order they were applied on their way to the AOF
+/// and the replicas. Keys hash to one of a fixed set of stripes, and a write holds the
+/// stripes of its keys from before it runs until it is propagated, so writes to other
+/// keys run and log in parallel. Stripes go by key alone, whatever the database, so
+/// MOVE and COPY to another database are ordered with the writes on both sides.
+///
+/// Stripes are always locked in ascending order, before any shard, so two writes can
+/// never wait on each other.
+#[derive(Debug)]
+pub struct WriteOrder {
+    stripes: Vec<Mutex<()>>,
+}
+
+pub type WriteStripes<'a> = Vec<MutexGuard<'a, ()>>;
+
+// Enough that writes to unrelated keys rarely share one
+const STRIPES: usize = 64;
+
+impl Default for WriteOrder {
+    fn default() -> Self {
+        Self {
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl WriteOrder {
+    /// Locks the stripes of a write's keys, or every stripe for a write without keys,
+    /// like FLUSHALL, which may touch any of them.
+    pub async fn lock(&self, keys: &[&Value]) -> WriteStripes<'_> {
+        if keys.is_empty() {
+            return self.lock_all().await;
+        }
+        let mut indexes: Vec<usize> = keys
+            .iter()
+            .map(|key| match key {
+                Value::BulkString(key) => scan_hash(key) as usize % self.stripes.len(),
+                _ => 0,
+            })
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        let mut guards = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            guards.push(self.stripes[index].lock().await);
+        }
+        guards
+    }
+
+    /// Locks every stripe, so no write is between running and being propagated: for
+    /// eviction, and for copies of the dataset that must match a position in the AOF
+    /// or the replication stream.
+    pub async fn lock_all(&self) -> WriteStripes<'_> {
+        let mut guards = Vec::with_capacity(self.stripes.len());
+        for stripe in &self.stripes {
+            guards.push(stripe.lock().await);
+        }
+        guards
+    }
+}
+
+/// Where a write goes once it is applied: the AOF when it is on, the replication feed,
+/// and the keyspace notifications. The write's stripes of `WriteOrder` stay locked from
+/// before it runs until it is propagated, while the AOF and the feed are only locked to
+/// append to them.
+pub struct Propagation<'a> {
+    state: &'a ServerState,
+    _stripes: WriteStripes<'a>,
+    events: KeyspaceEvents,
+}
+
+impl<'a> Propagation<'a> {
+    /// Readies the propagation of a write to `keys`, every key when there are none.
+    pub async fn lock(state: &'a ServerState, keys: &[&Value]) -> Self {
+        let events = state.config.read().await.notify_keyspace_events;
+        Self {
+            state,
+            _stripes: state.write_order.lock(keys).await,
+            events,
+        }
+    }
+
+    /// Propagates a command that ran, given the reply it got: its canonical form goes to
+    /// the AOF and the replicas, and the keys it changed get their events.
+    pub async fn command(
+        &mut self,
+        db: usize,
+        command: UserCommand,
+        args: &[Value],
+        response: &Value,
+    ) -> Result<()> {
+        let Some((canonical, canonical_args)) =
+            canonical(command, args, response, &self.state.databases[db]).await
+        else {
+            return Ok(());
+        };
+        self.emit(db, canonical, &canonical_args).await?;
+        if changed(command, response) {
+            // SPOP is logged as the SREM it amounts to, but still notified as itself
+            let notified = match command {
+                UserCommand::SPop => command,
+                _ => canonical,
+            };
+            for (class, event, key) in events(notified, &canonical_args) {
+                self.notify(db, class, event, key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagates a key that was evicted to make room, as a DEL.
+    pub async fn evicted(&mut self, db: usize, key: String) -> Result<()> {
+        let key = Bytes::from(key);
+        self.emit(db, UserCommand::Del, &[Value::BulkString(key.clone())])
+            .await?;
+        self.notify(db, 'e', "evicted", &key);
+        Ok(())
+    }
+
+    /// Sends a write, as it is to be replayed, to the AOF and the replicas.
+    pub async fn emit(&mut self, db: usize, command: UserCommand, args: &[Value]) -> Result<()> {
+        if let Some(aof) = self.state.aof.as_ref() {
+            let started = Instant::now();
+            aof.lock().await.append(db, command, args).await?;
+            self.state.latency.record("aof-write", started.elapsed());
+        }
+        self.state
+            .replication
+            .lock_feed()
+            .await
+            .propagate(db, command, args);
+        Ok(())
+    }
+
+    /// Publishes an event on a key to the channels the notification flags ask for.
+    pub fn notify(&self, db: usize, class: char, event: &str, key: &Bytes) {
+        if !self.events.publishes(class) {
+            return;
+        }
+        let pubsub = &self.state.pubsub;
+        if self.events.keyspace {
+            let mut channel = format!("__keyspace@{}__:", db).into_bytes();
+            channel.extend_from_slice(key);
+            pubsub.publish(&Bytes::from(channel), &Bytes::from(event.to_owned()));
+        }
+        if self.events.keyevent {
+            let channel = format!("__keyevent@{}__:{}", db, event);
+            pubsub.publish(&Bytes::from(channel), key);
+        }
+    }
+}
+
+/// A write as it is logged: replaying it must give the result it had here, or nothing
+/// is logged when it changed nothing. An XADD with a generated ID is logged with the ID
+/// it got, SPOP as an SREM of the members it picked, and relative expiry times are made
+/// absolute: SETEX and SET with EX or PX become SET with PXAT, and EXPIRE and GETEX
+/// become PEXPIREAT, or a DEL when the key expired on the spot.
+pub async fn canonical<'a>(
+    command: UserCommand,
+    args: &'a [Value],
+    response: &Value,
+    db: &ShardedDb,
+) -> Option<(UserCommand, Cow<'a, [Value]>)> {
+    let owned = |command, args: Vec<Value>| Some((command, Cow::Owned(args)));
+    match (command, response) {
+        (UserCommand::XAdd, Value::BulkString(id)) => {
+            let mut args = args.to_vec();
+            args[1] = Value::BulkString(id.clone());
+            owned(command, args)
+        }
+        (UserCommand::SPop, Value::BulkString(member)) => owned(
+            UserCommand::SRem,
+            vec![args[0].clone(), Value::BulkString(member.clone())],
+        ),
+        (UserCommand::SPop, Value::Array(members)) if !members.is_empty() => {
+            let mut args = vec![args[0].clone()];
+            args.extend(members.iter().cloned());
+            owned(UserCommand::SRem, args)
+        }
+        (
+            UserCommand::Expire | UserCommand::ExpireAt | UserCommand::PExpireAt,
+            Value::Integer(0),
+        ) => None,
+        (UserCommand::Expire | UserCommand::ExpireAt | UserCommand::PExpireAt, _) => {
+            Some(expire_at(&args[0], db).await)
+        }
+        (UserCommand::SetEx | UserCommand::PSetEx, _) => match expiry(&args[0], db).await {
+            Some(at) => owned(
+                UserCommand::Set,
+                vec![args[0].clone(), args[2].clone(), bulk("PXAT"), bulk(&at)],
+            ),
+            None => owned(UserCommand::Del, vec![args[0].clone()]),
+        },
+        (UserCommand::Set, _) => {
+            let options = &args[2..];
+            // Not set, and nothing read with GET either
+            if *response == Value::Null && !options.iter().any(|option| is(option, "GET")) {
+                return None;
+            }
+            let relative = options
+                .iter()
+                .position(|option| ["EX", "PX", "EXAT"].iter().any(|name| is(option, name)));
+            let (Some(position), Some(at)) = (relative, expiry(&args[0], db).await) else {
+                return Some((command, Cow::Borrowed(args)));
+            };
+            let mut rewritten = args[..2].to_vec();
+            rewritten.extend(options[..position].iter().cloned());
+            rewritten.extend(options[position + 2..].iter().cloned());
+            rewritten.extend([bulk("PXAT"), bulk(&at)]);
+            owned(command, rewritten)
+        }
+        // A relative TTL would restart on every replay, so RESTORE carries the absolute
+        // one it set, or becomes a DEL when the key expired on the spot
+        (UserCommand::Restore, Value::SimpleString(_))
+            if !is(&args[1], "0") && !args[3..].iter().any(|option| is(option, "ABSTTL")) =>
+        {
+            match expiry(&args[0], db).await {
+                Some(at) => {
+                    let mut rewritten = args.to_vec();
+                    rewritten[1] = bulk(&at);
+                    rewritten.push(bulk("ABSTTL"));
+                    owned(command, rewritten)
+                }
+                None => owned(UserCommand::Del, vec![args[0].clone()]),
+            }
+        }
+        (UserCommand::GetEx, Value::Null) => None,
+        (UserCommand::GetEx, _) => match &args[1..] {
+            [] => None,
+            [_] => owned(UserCommand::Persist, vec![args[0].clone()]),
+            _ => Some(expire_at(&args[0], db).await),
+        },
+        _ => Some((command, Cow::Borrowed(args))),
+    }
+}
+
+// PEXPIREAT for the expiry a key was given, or a DEL when it is already gone
+async fn expire_at<'a>(key: &Value, db: &ShardedDb) -> (UserCommand, Cow<'a, [Value]>) {
+    match expiry(key, db).await {
+        Some(at) => (
+            UserCommand::PExpireAt,
+            Cow::Owned(vec![key.clone(), bulk(&at)]),
+        ),
+        None => (UserCommand::Del, Cow::Owned(vec![key.clone()])),
+    }
+}
+
+// When the key expires, in unix milliseconds, if it is there and has a TTL
+async fn expiry(key: &Value, db: &ShardedDb) -> Option<String> {
+    let Value::BulkString(key) = key else {
+        return None;
+    };
+    let key = String::from_utf8_lossy(key);
+    let instance = db.read_key(&key).await;
+    let at = instance.get_entry(&key)?.expires_at?;
+    Some(at.to_string())
+}
+
+fn bulk(text: &str) -> Value {
+    Value::BulkString(Bytes::from(text.to_owned()))
+}
+
+fn is(option: &Value, name: &str) -> bool {
+    matches!(option, Value::BulkString(option) if option.eq_ignore_ascii_case(name.as_bytes()))
+}
+
+// Whether a write changed anything, going by its reply: nothing was popped or set, or a
+// count of what changed came back 0
+fn changed(command: UserCommand, response: &Value) -> bool {
+    match response {
+        Value::Null | Value::NullArray => false,
+        Value::Array(items) => !items.is_empty(),
+        Value::Integer(count) => {
+            *count > 0
+                || !matches!(
+                    command,
+                    UserCommand::Del
+                        | UserCommand::Unlink
+                        | UserCommand::Persist
+                        | UserCommand::SetNx
+                        | UserCommand::RenameNx
+                        | UserCommand::SAdd
+                        | UserCommand::SRem
+                        | UserCommand::HDel
+                        | UserCommand::ZRem
+                        | UserCommand::LRem
+                        | UserCommand::LInsert
+                )
+        }
+        _ => true,
+    }
+}
+
+// The events a write gives the keys it changed, named and classed as in Redis. A DEL
+// of several keys notifies each of them, as the reply does not tell which existed.
+fn events(command: UserCommand, args: &[Value]) -> Vec<(char, &'static str, &Bytes)> {
+    use UserCommand::*;
+    let keys: Vec<&Bytes> = command_keys(command, args)
+        .into_iter()
+        .filter_map(|key| match key {
+            Value::BulkString(key) => Some(key),
+            _ => None,
+        })
+        .collect();
+    let Some(&key) = keys.first() else {
+        return Vec::new();
+    };
+    let second = keys.get(1).copied();
+    let (class, event) = match command {
+        Del | Unlink | GetDel => {
+            return keys.into_iter().map(|key| ('g', "del", key)).collect();
+        }
+        Rename | RenameNx => {
+            let mut events = vec![('g', "rename_from", key)];
+            events.extend(second.map(|to| ('g', "rename_to", to)));
+            return events;
+        }
+        RPopLPush => {
+            let mut events = vec![('l', "rpop", key)];
+            events.extend(second.map(|to| ('l', "lpush", to)));
+            return events;
+        }
+        LMove | BLMove => {
+            let end = |index: usize, left: &'static str, right: &'static str| match args
+                .get(index)
+                .is_some_and(|end| is(end, "LEFT"))
+            {
+                true => left,
+                false => right,
+            };
+            let mut events = vec![('l', end(2, "lpop", "rpop"), key)];
+            events.extend(second.map(|to| ('l', end(3, "lpush", "rpush"), to)));
+            return events;
+        }
+        PExpireAt | Expire | ExpireAt => ('g', "expire"),
+        Persist => ('g', "persist"),
+        Restore => ('g', "restore"),
+        Set | SetNx | GetSet | SetEx | PSetEx | BitOp => ('$', "set"),
+        Append => ('$', "append"),
+        SetRange => ('$', "setrange"),
+        SetBit => ('$', "setbit"),
+        Incr | IncrBy => ('$', "incrby"),
+        Decr | DecrBy => ('$', "decrby"),
+        LPush => ('l', "lpush"),
+        RPush => ('l', "rpush"),
+        LPop => ('l', "lpop"),
+        RPop => ('l', "rpop"),
+        LInsert => ('l', "linsert"),
+        LSet => ('l', "lset"),
+        LRem => ('l', "lrem"),
+        LTrim => ('l', "ltrim"),
+        HSet => ('h', "hset"),
+        HDel => ('h', "hdel"),
+        SAdd => ('s', "sadd"),
+        SRem => ('s', "srem"),
+        SPop => ('s', "spop"),
+        ZAdd | GeoAdd => ('z', "zadd"),
+        ZIncrBy => ('z', "zincr"),
+        ZRem => ('z', "zrem"),
+        ZPopMin => ('z', "zpopmin"),
+        ZPopMax => ('z', "zpopmax"),
+        XAdd => ('t', "xadd"),
+        _ => return Vec::new(),
+    };
+    vec![(class, event, key)]
+}
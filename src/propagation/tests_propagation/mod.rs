@@ -0,0 +1,224 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::storage::sharded::StorageMut;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+
+    // The canonical form of a command as text, or None when nothing is logged
+    async fn logged(
+        command: UserCommand,
+        parts: &[&str],
+        response: Value,
+        db: &ShardedDb,
+    ) -> Option<(UserCommand, Vec<String>)> {
+        let args = args(parts);
+        let (command, args) = canonical(command, &args, &response, db).await?;
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                Value::BulkString(arg) => String::from_utf8(arg.to_vec()).unwrap(),
+                other => panic!("unexpected argument {:?}", other),
+            })
+            .collect();
+        Some((command, args))
+    }
+
+    #[test]
+    fn test_keyspace_events_flags() {
+        let events = KeyspaceEvents::parse("").unwrap();
+        assert_eq!(events, KeyspaceEvents::default());
+        assert!(!events.publishes('g'));
+
+        let events = KeyspaceEvents::parse("KEA").unwrap();
+        assert_eq!(events.name(), "AKE");
+        assert!(events.publishes('$') && events.publishes('e'));
+        // Classes without a channel to publish on give nothing
+        assert!(!KeyspaceEvents::parse("A").unwrap().publishes('g'));
+
+        let events = KeyspaceEvents::parse("Kl$").unwrap();
+        assert_eq!(events.name(), "$lK");
+        assert!(events.publishes('l') && !events.publishes('h'));
+        assert_eq!(KeyspaceEvents::parse("Kq"), None);
+    }
+
+    #[tokio::test]
+    async fn test_relative_expiry_is_made_absolute() {
+        let db = ShardedDb::new();
+        let at = SystemClock.now_millis() + 10_000;
+        let mut entry = Entry::new(DataType::String("value".into()));
+        entry.expires_at = Some(at);
+        db.write().await.insert_entry("key".to_owned(), entry);
+        let at = at.to_string();
+
+        assert_eq!(
+            logged(
+                UserCommand::Set,
+                &["key", "value", "XX", "EX", "10"],
+                Value::SimpleString("OK".to_owned()),
+                &db
+            )
+            .await,
+            Some((
+                UserCommand::Set,
+                vec![
+                    "key".into(),
+                    "value".into(),
+                    "XX".into(),
+                    "PXAT".into(),
+                    at.clone()
+                ]
+            ))
+        );
+        assert_eq!(
+            logged(
+                UserCommand::PSetEx,
+                &["key", "10000", "value"],
+                Value::SimpleString("OK".to_owned()),
+                &db
+            )
+            .await,
+            Some((
+                UserCommand::Set,
+                vec!["key".into(), "value".into(), "PXAT".into(), at.clone()]
+            ))
+        );
+        for (command, parts) in [
+            (UserCommand::Expire, &["key", "10"][..]),
+            (UserCommand::GetEx, &["key", "PX", "10000"][..]),
+        ] {
+            assert_eq!(
+                logged(command, parts, Value::Integer(1), &db).await,
+                Some((UserCommand::PExpireAt, vec!["key".into(), at.clone()]))
+            );
+        }
+        assert_eq!(
+            logged(
+                UserCommand::GetEx,
+                &["key", "PERSIST"],
+                Value::Integer(1),
+                &db
+            )
+            .await,
+            Some((UserCommand::Persist, vec!["key".into()]))
+        );
+        assert_eq!(
+            logged(
+                UserCommand::Restore,
+                &["key", "10000", "payload", "REPLACE"],
+                Value::SimpleString("OK".to_owned()),
+                &db
+            )
+            .await,
+            Some((
+                UserCommand::Restore,
+                vec![
+                    "key".into(),
+                    at.clone(),
+                    "payload".into(),
+                    "REPLACE".into(),
+                    "ABSTTL".into()
+                ]
+            ))
+        );
+        assert_eq!(
+            logged(
+                UserCommand::Restore,
+                &["other", "0", "payload"],
+                Value::SimpleString("OK".to_owned()),
+                &db
+            )
+            .await,
+            Some((
+                UserCommand::Restore,
+                vec!["other".into(), "0".into(), "payload".into()]
+            ))
+        );
+        // A key expired on the spot is deleted
+        assert_eq!(
+            logged(
+                UserCommand::Expire,
+                &["missing", "-1"],
+                Value::Integer(1),
+                &db
+            )
+            .await,
+            Some((UserCommand::Del, vec!["missing".into()]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writes_that_changed_nothing_are_not_logged() {
+        let db = ShardedDb::new();
+        assert_eq!(
+            logged(
+                UserCommand::Expire,
+                &["missing", "10"],
+                Value::Integer(0),
+                &db
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            logged(
+                UserCommand::Set,
+                &["key", "value", "NX", "EX", "5"],
+                Value::Null,
+                &db
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            logged(
+                UserCommand::GetEx,
+                &["key"],
+                Value::BulkString("v".into()),
+                &db
+            )
+            .await,
+            None
+        );
+        // Other writes are logged as they ran
+        assert_eq!(
+            logged(UserCommand::LPush, &["list", "a"], Value::Integer(1), &db).await,
+            Some((UserCommand::LPush, vec!["list".into(), "a".into()]))
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let names = |command, parts: &[&str]| -> Vec<(char, &'static str, String)> {
+            events(command, &args(parts))
+                .into_iter()
+                .map(|(class, event, key)| (class, event, String::from_utf8(key.to_vec()).unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            names(UserCommand::Set, &["key", "v"]),
+            [('$', "set", "key".into())]
+        );
+        assert_eq!(
+            names(UserCommand::Del, &["a", "b"]),
+            [('g', "del", "a".into()), ('g', "del", "b".into())]
+        );
+        assert_eq!(
+            names(UserCommand::LMove, &["from", "to", "LEFT", "RIGHT"]),
+            [('l', "lpop", "from".into()), ('l', "rpush", "to".into())]
+        );
+        assert_eq!(
+            names(UserCommand::Rename, &["old", "new"]),
+            [
+                ('g', "rename_from", "old".into()),
+                ('g', "rename_to", "new".into())
+            ]
+        );
+        assert!(names(UserCommand::FlushDb, &[]).is_empty());
+
+        assert!(!changed(UserCommand::SAdd, &Value::Integer(0)));
+        assert!(changed(UserCommand::HSet, &Value::Integer(0)));
+        assert!(!changed(UserCommand::LPop, &Value::Null));
+    }
+}
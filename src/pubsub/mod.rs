@@ -0,0 +1,197 @@
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::clients::OutputBuffer;
+use crate::config::BufferLimit;
+use crate::glob::glob_match;
+use crate::parser::Value;
+
+pub mod tests_pubsub;
+
+type Subscribers = HashMap<u64, Queue>;
+
+// Where messages for one connection wait until it writes them out
+#[derive(Debug, Clone)]
+struct Queue {
+    sender: UnboundedSender<Value>,
+    output: Arc<OutputBuffer>,
+}
+
+/// Registry of channel and pattern subscriptions shared by every connection.
+/// Locks are never held across an await, so std locks are enough here.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    next_id: AtomicU64,
+    channels: RwLock<HashMap<Bytes, Subscribers>>,
+    patterns: RwLock<HashMap<Bytes, Subscribers>>,
+    // The pubsub client-output-buffer-limit
+    output_limit: RwLock<BufferLimit>,
+}
+
+/// A connection's view of pub/sub: the subscriptions it holds and the receiving end
+/// of its message queue. Dropping it removes every subscription from the registry.
+#[derive(Debug)]
+pub struct Subscriber {
+    id: u64,
+    pubsub: Arc<PubSub>,
+    queue: Queue,
+    pub receiver: UnboundedReceiver<Value>,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscriber(self: &Arc<Self>) -> Subscriber {
+        let (sender, receiver) = unbounded_channel();
+        Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            pubsub: Arc::clone(self),
+            queue: Queue {
+                sender,
+                output: Arc::new(OutputBuffer::new()),
+            },
+            receiver,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+        }
+    }
+
+    pub fn set_output_limit(&self, limit: BufferLimit) {
+        *self.output_limit.write().unwrap() = limit;
+    }
+
+    /// Delivers a message to exact subscribers of the channel and to every pattern
+    /// matching it. Returns the number of deliveries, so a client subscribed both to
+    /// the channel and to a matching pattern is counted twice, like in Redis.
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let limit = *self.output_limit.read().unwrap();
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.read().unwrap().get(channel) {
+            let frame = Value::Push(vec![
+                Value::BulkString("message".into()),
+                Value::BulkString(channel.clone()),
+                Value::BulkString(message.clone()),
+            ]);
+            receivers += deliver(subscribers, &frame, &limit);
+        }
+
+        for (pattern, subscribers) in self.patterns.read().unwrap().iter() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let frame = Value::Push(vec![
+                Value::BulkString("pmessage".into()),
+                Value::BulkString(pattern.clone()),
+                Value::BulkString(channel.clone()),
+                Value::BulkString(message.clone()),
+            ]);
+            receivers += deliver(subscribers, &frame, &limit);
+        }
+
+        receivers
+    }
+}
+
+// A closed queue means the connection is going away, and one over its output buffer
+// limit is about to be closed; neither is counted as a receiver
+fn deliver(subscribers: &Subscribers, frame: &Value, limit: &BufferLimit) -> usize {
+    let len = frame.serialize().len();
+    subscribers
+        .values()
+        .filter(|queue| queue.output.push(len, limit) && queue.sender.send(frame.clone()).is_ok())
+        .count()
+}
+
+fn register(registry: &RwLock<HashMap<Bytes, Subscribers>>, name: &Bytes, id: u64, queue: &Queue) {
+    registry
+        .write()
+        .unwrap()
+        .entry(name.clone())
+        .or_default()
+        .insert(id, queue.clone());
+}
+
+fn unregister(registry: &RwLock<HashMap<Bytes, Subscribers>>, name: &Bytes, id: u64) {
+    let mut registry = registry.write().unwrap();
+    if let Some(subscribers) = registry.get_mut(name) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            registry.remove(name);
+        }
+    }
+}
+
+impl Subscriber {
+    /// Total number of channel and pattern subscriptions, as reported in replies.
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.count() > 0
+    }
+
+    /// The messages queued for the connection, counted against the pubsub limit.
+    pub fn output(&self) -> Arc<OutputBuffer> {
+        Arc::clone(&self.queue.output)
+    }
+
+    pub fn channels(&self) -> Vec<Bytes> {
+        self.channels.iter().cloned().collect()
+    }
+
+    pub fn patterns(&self) -> Vec<Bytes> {
+        self.patterns.iter().cloned().collect()
+    }
+
+    pub fn subscribe(&mut self, channel: Bytes) -> usize {
+        if self.channels.insert(channel.clone()) {
+            register(&self.pubsub.channels, &channel, self.id, &self.queue);
+        }
+        self.count()
+    }
+
+    pub fn unsubscribe(&mut self, channel: &Bytes) -> usize {
+        if self.channels.remove(channel) {
+            unregister(&self.pubsub.channels, channel, self.id);
+        }
+        self.count()
+    }
+
+    pub fn psubscribe(&mut self, pattern: Bytes) -> usize {
+        if self.patterns.insert(pattern.clone()) {
+            register(&self.pubsub.patterns, &pattern, self.id, &self.queue);
+        }
+        self.count()
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &Bytes) -> usize {
+        if self.patterns.remove(pattern) {
+            unregister(&self.pubsub.patterns, pattern, self.id);
+        }
+        self.count()
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        for channel in std::mem::take(&mut self.channels) {
+            unregister(&self.pubsub.channels, &channel, self.id);
+        }
+        for pattern in std::mem::take(&mut self.patterns) {
+            unregister(&self.pubsub.patterns, &pattern, self.id);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[tokio::test]
+    async fn test_publish_to_channel_subscribers() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut first = pubsub.subscriber();
+        let mut second = pubsub.subscriber();
+        assert_eq!(first.subscribe("news".into()), 1);
+        assert_eq!(second.subscribe("news".into()), 1);
+
+        assert_eq!(pubsub.publish(&"news".into(), &"hello".into()), 2);
+        assert_eq!(pubsub.publish(&"sports".into(), &"goal".into()), 0);
+
+        let expected = Value::Push(vec![
+            Value::BulkString("message".into()),
+            Value::BulkString("news".into()),
+            Value::BulkString("hello".into()),
+        ]);
+        assert_eq!(first.receiver.recv().await, Some(expected.clone()));
+        assert_eq!(second.receiver.recv().await, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_pattern_and_exact_subscriptions_are_counted_separately() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut subscriber = pubsub.subscriber();
+        assert_eq!(subscriber.subscribe("news.tech".into()), 1);
+        assert_eq!(subscriber.psubscribe("news.*".into()), 2);
+        // Subscribing twice to the same pattern changes nothing
+        assert_eq!(subscriber.psubscribe("news.*".into()), 2);
+
+        assert_eq!(pubsub.publish(&"news.tech".into(), &"rust".into()), 2);
+        assert_eq!(
+            subscriber.receiver.recv().await,
+            Some(Value::Push(vec![
+                Value::BulkString("message".into()),
+                Value::BulkString("news.tech".into()),
+                Value::BulkString("rust".into()),
+            ]))
+        );
+        assert_eq!(
+            subscriber.receiver.recv().await,
+            Some(Value::Push(vec![
+                Value::BulkString("pmessage".into()),
+                Value::BulkString("news.*".into()),
+                Value::BulkString("news.tech".into()),
+                Value::BulkString("rust".into()),
+            ]))
+        );
+
+        assert_eq!(subscriber.unsubscribe(&"news.tech".into()), 1);
+        assert_eq!(pubsub.publish(&"news.tech".into(), &"rust".into()), 1);
+        assert_eq!(subscriber.punsubscribe(&"news.*".into()), 0);
+        assert!(!subscriber.is_subscribed());
+    }
+
+    #[test]
+    fn test_dropping_subscriber_unregisters_it() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut subscriber = pubsub.subscriber();
+        subscriber.subscribe("news".into());
+        subscriber.psubscribe("*".into());
+        drop(subscriber);
+
+        assert_eq!(pubsub.publish(&"news".into(), &"hello".into()), 0);
+        assert!(pubsub.channels.read().unwrap().is_empty());
+        assert!(pubsub.patterns.read().unwrap().is_empty());
+    }
+}
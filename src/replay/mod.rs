@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use crate::clock::MockClock;
+use crate::config::Config;
+use crate::connection::{extract_command, is_empty_command, run_command};
+use crate::error::{Error, Result};
+use crate::parser::{parse_request, ParseStatus, Value};
+use crate::server::ServerState;
+
+pub mod tests_replay;
+
+/// Runs scripted commands through the dispatcher against an in-memory server, with no
+/// socket in between, and collects the replies: the harness behind table-driven
+/// protocol tests and regression fixtures.
+///
+/// Commands go through `run_command`, as a client's would once past the connection,
+/// so writes are propagated and keys evicted as usual. What belongs to a connection
+/// rather than the dispatcher, such as AUTH, MULTI, CLIENT or SUBSCRIBE, is not
+/// handled. The server reads the time from a `MockClock` that stands still, so TTLs
+/// and TIME reply the same on every run; `clock` moves it on.
+#[derive(Debug)]
+pub struct Replay {
+    state: ServerState,
+    clock: Arc<MockClock>,
+    selected: usize,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Self::with_config(Config::new())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let clock = Arc::new(MockClock::starting_now());
+        Self {
+            state: ServerState::with_clock(config, clock.clone()),
+            clock,
+            selected: 0,
+        }
+    }
+
+    pub fn state(&self) -> &ServerState {
+        &self.state
+    }
+
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// The database SELECT last picked.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Runs every frame in `script`, RESP arrays or inline commands alike, and returns
+    /// one reply per command. Empty arrays are skipped unanswered, as a connection
+    /// skips them. A frame cut short is an error, as nothing would ever complete it.
+    pub async fn feed(&mut self, script: &[u8]) -> Result<Vec<Value>> {
+        let mut replies = Vec::new();
+        let mut offset = 0;
+        while offset < script.len() {
+            match parse_request(&script[offset..])? {
+                ParseStatus::Complete(value, consumed) => {
+                    offset += consumed;
+                    if !is_empty_command(&value) {
+                        replies.push(self.run_value(value).await);
+                    }
+                }
+                // Blank lines left at the end are skipped like anywhere else
+                ParseStatus::NeedMoreData
+                    if script[offset..].iter().all(u8::is_ascii_whitespace) =>
+                {
+                    break
+                }
+                ParseStatus::NeedMoreData => {
+                    return Err(Error::Corrupt(format!(
+                        "Script ends with a truncated frame at byte {}",
+                        offset
+                    )))
+                }
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Runs one command given as its arguments, the name first.
+    pub async fn run(&mut self, command: &[&str]) -> Result<Value> {
+        let args = command
+            .iter()
+            .map(|arg| Value::BulkString(arg.to_string().into()))
+            .collect();
+        Ok(self.run_value(Value::Array(args)).await)
+    }
+
+    // Failures reply the way a connection would answer them
+    async fn run_value(&mut self, value: Value) -> Value {
+        let reply = match extract_command(value) {
+            Ok((command, args)) => {
+                run_command(command, &args, &self.state, &mut self.selected, None).await
+            }
+            Err(err) => Err(err),
+        };
+        reply.unwrap_or_else(|err| Value::SimpleError(format!("ERR {}", err)))
+    }
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    fn bulk(value: &str) -> Value {
+        Value::BulkString(value.to_string().into())
+    }
+
+    fn ok() -> Value {
+        Value::SimpleString("OK".to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_replies_table() -> Result<()> {
+        let wrong_type = Value::SimpleError(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_owned(),
+        );
+        let cases: &[(&[&str], Value)] = &[
+            (&["SET", "counter", "9"], ok()),
+            (&["INCR", "counter"], Value::Integer(10)),
+            (&["GET", "counter"], bulk("10")),
+            (&["GET", "missing"], Value::Null),
+            (&["LPUSH", "list", "a", "b"], Value::Integer(2)),
+            (&["GET", "list"], wrong_type),
+            (&["EXISTS", "counter", "list", "missing"], Value::Integer(2)),
+        ];
+
+        let mut replay = Replay::new();
+        for (command, expected) in cases {
+            assert_eq!(&replay.run(command).await?, expected, "{:?}", command);
+        }
+        assert!(matches!(
+            replay.run(&["GET"]).await?,
+            Value::SimpleError(message) if message.contains("wrong number of arguments")
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_script() -> Result<()> {
+        let mut replay = Replay::new();
+        // RESP arrays and inline commands may be mixed, as a client may send them
+        let script =
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\nSELECT 2\r\n\r\nSET a 2\nGET a\r\n\r\n";
+        assert_eq!(
+            replay.feed(script).await?,
+            vec![ok(), ok(), ok(), bulk("2")]
+        );
+        assert_eq!(replay.selected(), 2);
+        assert_eq!(replay.feed(b"SELECT 0\r\nGET a\r\n").await?[1], bulk("1"));
+
+        assert!(replay.feed(b"GET a\r\n*2\r\n$3\r\nGET\r\n").await.is_err());
+
+        // Empty arrays get no reply and a name that is not a bulk string an error
+        let replies = replay.feed(b"*0\r\n*1\r\n:1\r\n*0\r\nGET a\r\n").await?;
+        assert_eq!(replies.len(), 2);
+        assert!(matches!(&replies[0], Value::SimpleError(message) if message.starts_with("ERR")));
+        assert_eq!(replies[1], bulk("1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clock_stands_still() -> Result<()> {
+        let mut replay = Replay::new();
+        replay.run(&["SET", "key", "value", "PX", "1500"]).await?;
+        assert_eq!(replay.run(&["PTTL", "key"]).await?, Value::Integer(1500));
+
+        replay.clock().advance(Duration::from_millis(1000));
+        assert_eq!(replay.run(&["PTTL", "key"]).await?, Value::Integer(500));
+        replay.clock().advance(Duration::from_millis(500));
+        assert_eq!(replay.run(&["GET", "key"]).await?, Value::Null);
+        Ok(())
+    }
+
+    // The clock belongs to the replayed server, not to the thread polling it, so the
+    // work-stealing runtime sees it too
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_clock_on_multi_thread_runtime() -> Result<()> {
+        let mut replay = Replay::new();
+        replay.run(&["SET", "key", "value", "PX", "100"]).await?;
+        let replay = tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            replay.clock().advance(Duration::from_millis(40));
+            replay.run(&["PTTL", "key"]).await
+        });
+        assert_eq!(replay.await??, Value::Integer(60));
+        Ok(())
+    }
+}
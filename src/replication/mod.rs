@@ -0,0 +1,836 @@
+use bytes::Bytes;
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex, MutexGuard, Notify,
+    },
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::clients::OutputBuffer;
+use crate::config::BufferLimit;
+use crate::connection::{extract_command, run_command, unpack_bulk_string};
+use crate::error::{Context, Error, Result};
+use crate::parser::{RespHandler, UserCommand, Value};
+use crate::persistence::{
+    encode_command,
+    rdb::{self, Keyspace},
+};
+use crate::server::ServerState;
+use crate::storage::sharded::ShardedDb;
+use crate::{notice, warning};
+
+pub mod tests_replication;
+
+// How often a replica reports its offset without being asked
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+// How long a replica waits before connecting to its master again, doubled after every
+// attempt that did not get to synchronize
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+// Bytes of the command stream kept for replicas to resume from, as in Redis
+pub const DEFAULT_BACKLOG_SIZE: u64 = 1024 * 1024;
+
+/// Replication state of the server: the feed of write commands streamed to connected
+/// replicas and, when the server is itself a replica, the link to its master.
+#[derive(Debug)]
+pub struct Replication {
+    feed: Mutex<Feed>,
+    // Only touched by REPLICAOF and never across an await, so a std lock is enough
+    master: StdMutex<Option<MasterLink>>,
+    // Whether this replica is synchronized with its master and following its stream
+    link_up: AtomicBool,
+    // When the replica last heard from its master
+    last_io: StdMutex<Option<Instant>>,
+    next_replica_id: AtomicU64,
+    // Woken whenever a replica acknowledges an offset, for WAIT
+    acked: Notify,
+    // The replica client-output-buffer-limit, copied into the feed whenever it is locked
+    output_limit: StdMutex<BufferLimit>,
+    // repl-backlog-size, copied into the feed the same way
+    backlog_size: AtomicU64,
+    // As a replica, how far into the master's command stream its data goes
+    master_offset: StdMutex<Option<MasterOffset>>,
+}
+
+/// The command stream sent to replicas. Write commands hold it locked while they run,
+/// so replicas receive writes in the order they were applied.
+#[derive(Debug, Default)]
+pub struct Feed {
+    // The id of the stream, replaced whenever the server is promoted
+    replid: String,
+    // Once promoted, the id of the stream followed before and the offset it got to,
+    // which this stream carries on from
+    previous: Option<(String, u64)>,
+    // Bytes streamed so far, the master replication offset
+    offset: u64,
+    // Database of the last streamed command, None to force a SELECT before the next
+    selected: Option<usize>,
+    replicas: Vec<Replica>,
+    output_limit: BufferLimit,
+    // Created once the first replica attaches, and kept so replicas can resume
+    backlog: Option<Backlog>,
+    backlog_size: u64,
+    // When the last frame was streamed, for pings to fill in the silences
+    last_sent: Option<Instant>,
+}
+
+/// The latest bytes of the command stream, so a replica that lost its link can pick up
+/// where it left off instead of loading a full copy again.
+#[derive(Debug)]
+struct Backlog {
+    buffer: VecDeque<u8>,
+    // The stream offset of the first byte held
+    start: u64,
+}
+
+/// Where a replica's data stands in its master's command stream, kept across links so
+/// a new one can ask to continue from there. The database the stream last selected
+/// comes along, as the master does not select it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MasterOffset {
+    pub replid: String,
+    pub offset: u64,
+    pub selected: usize,
+}
+
+#[derive(Debug)]
+struct Replica {
+    id: u64,
+    // Where the replica accepts connections: the host it connected from and the port
+    // it gave with REPLCONF listening-port
+    address: Option<(String, u16)>,
+    sender: UnboundedSender<Bytes>,
+    // The offset the replica last reported having applied with REPLCONF ACK
+    acked: u64,
+    // Frames sent but not yet written to the replica's socket
+    output: Arc<OutputBuffer>,
+}
+
+#[derive(Debug)]
+struct MasterLink {
+    host: String,
+    port: u16,
+    task: JoinHandle<()>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        Self {
+            feed: Mutex::new(Feed {
+                replid: random_id(),
+                ..Feed::default()
+            }),
+            master: StdMutex::new(None),
+            link_up: AtomicBool::new(false),
+            last_io: StdMutex::new(None),
+            next_replica_id: AtomicU64::new(0),
+            acked: Notify::new(),
+            output_limit: StdMutex::new(BufferLimit::default()),
+            backlog_size: AtomicU64::new(DEFAULT_BACKLOG_SIZE),
+            master_offset: StdMutex::new(None),
+        }
+    }
+
+    pub async fn replid(&self) -> String {
+        self.lock_feed().await.replid.clone()
+    }
+
+    pub async fn lock_feed(&self) -> MutexGuard<'_, Feed> {
+        let mut feed = self.feed.lock().await;
+        feed.output_limit = *self.output_limit.lock().unwrap();
+        feed.backlog_size = self.backlog_size.load(Ordering::Relaxed);
+        feed
+    }
+
+    pub fn set_output_limit(&self, limit: BufferLimit) {
+        *self.output_limit.lock().unwrap() = limit;
+    }
+
+    pub fn set_backlog_size(&self, size: u64) {
+        self.backlog_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Registers a new replica. Returns its id, the offset its copy of the dataset
+    /// corresponds to, that copy, and the receiver of every write made after it along
+    /// with the output buffer counting what is waiting there. The caller holds every
+    /// stripe of `WriteOrder`, so no write runs while the copy is taken.
+    pub async fn attach_replica(
+        &self,
+        databases: &[Arc<ShardedDb>],
+        address: Option<(String, u16)>,
+    ) -> (
+        u64,
+        u64,
+        Keyspace,
+        UnboundedReceiver<Bytes>,
+        Arc<OutputBuffer>,
+    ) {
+        let mut feed = self.lock_feed().await;
+        // No write runs while the stripes are held, so the copy matches the feed position
+        let keyspace = rdb::snapshot(databases).await;
+        // The replica starts out with everything up to the current offset
+        let offset = feed.offset;
+        let (id, receiver, output) = self.register(&mut feed, offset, address);
+        feed.selected = None;
+        (id, offset, keyspace, receiver, output)
+    }
+
+    /// Registers a replica that asked to continue from `offset` of the stream of
+    /// `replid`. When this is that stream, or the one it carries on from up to where
+    /// that ended, and the backlog still holds everything from there, returns the
+    /// replica's id, the bytes it missed, the receiver of every later write and its
+    /// output buffer; None when it needs a full copy instead.
+    pub async fn resume_replica(
+        &self,
+        replid: &str,
+        offset: u64,
+        address: Option<(String, u16)>,
+    ) -> Option<(u64, Bytes, UnboundedReceiver<Bytes>, Arc<OutputBuffer>)> {
+        let mut feed = self.lock_feed().await;
+        let continued = match &feed.previous {
+            Some((previous, end)) => previous == replid && offset <= *end,
+            None => false,
+        };
+        if replid != feed.replid && !continued {
+            return None;
+        }
+        let missed = feed.backlog.as_ref()?.since(offset, feed.offset)?;
+        let (id, receiver, output) = self.register(&mut feed, offset, address);
+        Some((id, missed, receiver, output))
+    }
+
+    // Adds a replica that has the stream up to `offset`, creating the backlog with the
+    // first one
+    fn register(
+        &self,
+        feed: &mut Feed,
+        offset: u64,
+        address: Option<(String, u16)>,
+    ) -> (u64, UnboundedReceiver<Bytes>, Arc<OutputBuffer>) {
+        let (sender, receiver) = unbounded_channel();
+        let id = self.next_replica_id.fetch_add(1, Ordering::Relaxed);
+        let output = Arc::new(OutputBuffer::new());
+        feed.replicas.push(Replica {
+            id,
+            address,
+            sender,
+            acked: offset,
+            output: Arc::clone(&output),
+        });
+        let start = feed.offset;
+        feed.backlog.get_or_insert_with(|| Backlog::new(start));
+        (id, receiver, output)
+    }
+
+    pub async fn detach_replica(&self, id: u64) {
+        self.lock_feed()
+            .await
+            .replicas
+            .retain(|replica| replica.id != id);
+        self.acked.notify_waiters();
+    }
+
+    /// Records that a replica has applied the command stream up to `offset`.
+    pub async fn acknowledge(&self, id: u64, offset: u64) {
+        let mut feed = self.lock_feed().await;
+        if let Some(replica) = feed.replicas.iter_mut().find(|replica| replica.id == id) {
+            replica.acked = replica.acked.max(offset);
+        }
+        self.acked.notify_waiters();
+    }
+
+    /// Waits until `needed` replicas have acknowledged `offset` or the deadline passes,
+    /// and returns how many have. Without a deadline it waits as long as it takes.
+    pub async fn wait_for_acks(
+        &self,
+        offset: u64,
+        needed: usize,
+        deadline: Option<Instant>,
+    ) -> usize {
+        let reached = self
+            .wait_on_acks(deadline, |feed| {
+                let acked = feed.acked_replicas(offset);
+                (acked >= needed).then_some(acked)
+            })
+            .await;
+        match reached {
+            Some(acked) => acked,
+            None => self.lock_feed().await.acked_replicas(offset),
+        }
+    }
+
+    /// Waits until the replica `id` has acknowledged `offset`. False once the deadline
+    /// passes or the replica disconnects first.
+    pub async fn wait_for_replica(&self, id: u64, offset: u64, deadline: Option<Instant>) -> bool {
+        self.wait_on_acks(deadline, |feed| {
+            match feed.replicas.iter().find(|replica| replica.id == id) {
+                Some(replica) => (replica.acked >= offset).then_some(true),
+                None => Some(false),
+            }
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    // Waits for `check` to give a value, checking again whenever a replica acknowledges
+    // an offset or goes away, until the deadline passes
+    async fn wait_on_acks<T>(
+        &self,
+        deadline: Option<Instant>,
+        check: impl Fn(&Feed) -> Option<T>,
+    ) -> Option<T> {
+        loop {
+            // Register for the wakeup before checking so no acknowledgement is missed
+            let notified = self.acked.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(value) = check(&*self.lock_feed().await) {
+                return Some(value);
+            }
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                        return None;
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// The master this server replicates, if any.
+    pub fn master(&self) -> Option<(String, u16)> {
+        let master = self.master.lock().unwrap();
+        master.as_ref().map(|link| (link.host.clone(), link.port))
+    }
+
+    /// As a replica, how far into its master's command stream the data goes, once it
+    /// synchronized.
+    pub fn master_offset(&self) -> Option<MasterOffset> {
+        self.master_offset.lock().unwrap().clone()
+    }
+
+    fn set_master_offset(&self, master_offset: Option<MasterOffset>) {
+        *self.master_offset.lock().unwrap() = master_offset;
+    }
+
+    /// The lines of the replication section of INFO.
+    pub async fn info(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        match self.master() {
+            Some((host, port)) => {
+                lines.push("role:slave".to_owned());
+                lines.push(format!("master_host:{}", host));
+                lines.push(format!("master_port:{}", port));
+                let link_up = self.link_up.load(Ordering::Relaxed);
+                let status = if link_up { "up" } else { "down" };
+                lines.push(format!("master_link_status:{}", status));
+                let last_io = match *self.last_io.lock().unwrap() {
+                    Some(last_io) if link_up => last_io.elapsed().as_secs() as i64,
+                    _ => -1,
+                };
+                lines.push(format!("master_last_io_seconds_ago:{}", last_io));
+                let offset = self.master_offset().map_or(0, |master| master.offset);
+                lines.push(format!("slave_repl_offset:{}", offset));
+            }
+            None => lines.push("role:master".to_owned()),
+        }
+        let feed = self.lock_feed().await;
+        lines.push(format!("connected_slaves:{}", feed.replicas.len()));
+        for (index, replica) in feed.replicas.iter().enumerate() {
+            let (ip, port) = replica.address.clone().unwrap_or_default();
+            lines.push(format!(
+                "slave{}:ip={},port={},offset={}",
+                index, ip, port, replica.acked
+            ));
+        }
+        lines.push(format!("master_replid:{}", feed.replid));
+        let (replid2, second_offset) = match &feed.previous {
+            // Counted from 1 like the backlog offset
+            Some((replid, end)) => (replid.clone(), *end as i64 + 1),
+            None => ("0".repeat(40), -1),
+        };
+        lines.push(format!("master_replid2:{}", replid2));
+        lines.push(format!("master_repl_offset:{}", feed.offset));
+        lines.push(format!("second_repl_offset:{}", second_offset));
+        let (start, histlen) = feed
+            .backlog
+            .as_ref()
+            .map_or((0, 0), |backlog| (backlog.start, backlog.buffer.len()));
+        lines.push(format!(
+            "repl_backlog_active:{}",
+            feed.backlog.is_some() as u8
+        ));
+        lines.push(format!("repl_backlog_size:{}", feed.backlog_size));
+        // Counted from 1, as Redis does
+        lines.push(format!("repl_backlog_first_byte_offset:{}", start + 1));
+        lines.push(format!("repl_backlog_histlen:{}", histlen));
+        lines
+    }
+
+    /// Whether this server is a replica whose link to the master is down, so its data
+    /// may be stale: still synchronizing, or disconnected.
+    pub fn is_stale(&self) -> bool {
+        self.master.lock().unwrap().is_some() && !self.link_up.load(Ordering::Relaxed)
+    }
+
+    // Replaces the master link, stopping the previous one
+    fn set_master(&self, link: Option<MasterLink>) {
+        let mut master = self.master.lock().unwrap();
+        if let Some(previous) = master.take() {
+            previous.task.abort();
+        }
+        self.link_up.store(false, Ordering::Relaxed);
+        *master = link;
+    }
+
+    /// REPLICAOF host port starts replicating that master in the background, replacing
+    /// any previous one.
+    pub fn replicate(&self, state: &ServerState, host: String, port: u16) {
+        let task = tokio::spawn(run_master_link(state.clone(), host.clone(), port));
+        self.set_master(Some(MasterLink { host, port, task }));
+    }
+
+    /// REPLICAOF NO ONE turns a replica into a master that keeps its data. Its stream
+    /// gets a new id, and carries on from where the master's got to, so the other
+    /// replicas of that master can continue with this one without a full copy.
+    pub async fn promote(&self) {
+        if self.master().is_none() {
+            return;
+        }
+        self.set_master(None);
+        let position = self.master_offset.lock().unwrap().take();
+        let mut feed = self.lock_feed().await;
+        feed.replid = random_id();
+        feed.previous = position.map(|position| (position.replid, position.offset));
+        if let Some((_, offset)) = feed.previous {
+            feed.offset = offset;
+            feed.backlog = Some(Backlog::new(offset));
+            feed.selected = None;
+        }
+    }
+
+    /// Turns this master into a replica of one of its replicas that was just promoted
+    /// after catching up with it, continuing the promoted one's stream from the end of
+    /// this one's.
+    pub async fn demote(&self, state: &ServerState, host: String, port: u16) {
+        let feed = self.lock_feed().await;
+        self.set_master_offset(Some(MasterOffset {
+            replid: feed.replid.clone(),
+            offset: feed.offset,
+            selected: feed.selected.unwrap_or(0),
+        }));
+        drop(feed);
+        self.replicate(state, host, port);
+    }
+}
+
+impl Feed {
+    /// The master replication offset: the number of bytes streamed to replicas so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Streams a write command that was just applied to every replica. Once there is
+    /// a backlog it is kept up even without replicas, for those coming back.
+    pub fn propagate(&mut self, db: usize, command: UserCommand, args: &[Value]) {
+        if self.replicas.is_empty() && self.backlog.is_none() {
+            return;
+        }
+        let frame = encode_command(&mut self.selected, db, command, args);
+        self.send(frame);
+    }
+
+    /// Asks every replica to report its offset with REPLCONF ACK.
+    pub fn request_acks(&mut self) {
+        if self.replicas.is_empty() {
+            return;
+        }
+        self.send(command_frame(&["REPLCONF", "GETACK", "*"]).serialize());
+    }
+
+    /// The replica to fail over to: the one at `address` or, without one, the one that
+    /// acknowledged the most. Only replicas that gave their listening port qualify.
+    pub fn failover_target(&self, address: Option<&(String, u16)>) -> Option<(u64, (String, u16))> {
+        self.replicas
+            .iter()
+            .filter_map(|replica| Some((replica, replica.address.clone()?)))
+            .filter(|(_, candidate)| address.is_none_or(|address| address == candidate))
+            .max_by_key(|(replica, _)| replica.acked)
+            .map(|(replica, candidate)| (replica.id, candidate))
+    }
+
+    /// Streams a PING when nothing was streamed for `period`, so replicas can tell a
+    /// quiet master from one they lost.
+    pub fn ping(&mut self, period: Duration) {
+        if self.replicas.is_empty()
+            || self
+                .last_sent
+                .is_some_and(|last_sent| last_sent.elapsed() < period)
+        {
+            return;
+        }
+        self.send(command_frame(&["PING"]).serialize());
+    }
+
+    /// How many replicas have acknowledged at least `offset`.
+    pub fn acked_replicas(&self, offset: u64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|replica| replica.acked >= offset)
+            .count()
+    }
+
+    // Every frame counts towards the offset. Replicas whose connection is gone are
+    // dropped, and so are those too far behind, which closes their connection.
+    fn send(&mut self, frame: Bytes) {
+        self.offset += frame.len() as u64;
+        self.last_sent = Some(Instant::now());
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.push(&frame, self.backlog_size);
+        }
+        let limit = self.output_limit;
+        self.replicas.retain(|replica| {
+            replica.output.push(frame.len(), &limit) && replica.sender.send(frame.clone()).is_ok()
+        });
+    }
+}
+
+impl Backlog {
+    fn new(start: u64) -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            start,
+        }
+    }
+
+    // Appends a frame, dropping the oldest bytes past `size`
+    fn push(&mut self, frame: &[u8], size: u64) {
+        self.buffer.extend(frame);
+        let excess = self.buffer.len().saturating_sub(size as usize);
+        self.buffer.drain(..excess);
+        self.start += excess as u64;
+    }
+
+    // The bytes from `offset` up to `end`, the current offset, if all are still held
+    fn since(&self, offset: u64, end: u64) -> Option<Bytes> {
+        if offset < self.start || offset > end {
+            return None;
+        }
+        let skip = (offset - self.start) as usize;
+        Some(self.buffer.iter().skip(skip).copied().collect())
+    }
+}
+
+fn command_frame(parts: &[&str]) -> Value {
+    Value::Array(
+        parts
+            .iter()
+            .map(|part| Value::BulkString(Bytes::copy_from_slice(part.as_bytes())))
+            .collect(),
+    )
+}
+
+/// 40 random hex characters, the form of replication and cluster node ids.
+pub fn random_id() -> String {
+    let state = RandomState::new();
+    let id: String = (0..3)
+        .map(|part| format!("{:016x}", state.hash_one(part)))
+        .collect();
+    id[..40].to_owned()
+}
+
+/// Serves a connection that sent PSYNC replid offset. A replica asking to continue a
+/// stream the backlog still holds gets `+CONTINUE <replid>` followed by the commands
+/// it missed. Any other gets a full copy of the dataset framed as
+/// `+FULLRESYNC <replid> <offset>` and `$<length>` followed by the snapshot. Either way
+/// every write command is then streamed until the replica disconnects.
+pub async fn serve_replica<S: AsyncRead + AsyncWrite + Unpin>(
+    mut replica: RespHandler<S>,
+    state: &ServerState,
+    args: &[Value],
+    address: Option<(String, u16)>,
+) -> Result<()> {
+    let replication = &state.replication;
+    // Offsets are counted from 1 in PSYNC, so "? -1" never matches
+    let resumed = match psync_position(args) {
+        Some((replid, offset)) => {
+            replication
+                .resume_replica(&replid, offset, address.clone())
+                .await
+        }
+        None => None,
+    };
+    let (id, receiver, output) = match resumed {
+        Some((id, missed, receiver, output)) => {
+            let header = format!("+CONTINUE {}\r\n", replication.replid().await);
+            replica.socket.write_all(header.as_bytes()).await?;
+            replica.socket.write_all(&missed).await?;
+            (id, receiver, output)
+        }
+        None => {
+            let ordered = state.write_order.lock_all().await;
+            let (id, offset, keyspace, receiver, output) =
+                replication.attach_replica(&state.databases, address).await;
+            drop(ordered);
+            let payload = rdb::encode(&keyspace);
+            let header = format!(
+                "+FULLRESYNC {} {}\r\n${}\r\n",
+                replication.replid().await,
+                offset,
+                payload.len()
+            );
+            replica.socket.write_all(header.as_bytes()).await?;
+            replica.socket.write_all(&payload).await?;
+            (id, receiver, output)
+        }
+    };
+
+    let ping_period = Duration::from_secs(state.config.read().await.repl_ping_replica_period);
+    let result =
+        stream_to_replica(&mut replica, replication, id, receiver, output, ping_period).await;
+    replication.detach_replica(id).await;
+    result
+}
+
+// The replid and the offset a PSYNC asks to continue from, counted from 0
+fn psync_position(args: &[Value]) -> Option<(String, u64)> {
+    let [replid, offset] = args else {
+        return None;
+    };
+    let offset = unpack_bulk_string(offset.clone())
+        .ok()?
+        .parse::<u64>()
+        .ok()?;
+    Some((
+        unpack_bulk_string(replid.clone()).ok()?,
+        offset.checked_sub(1)?,
+    ))
+}
+
+// Sends every write to the replica, and a ping when there were none for a while, and
+// records the offsets it acknowledges
+async fn stream_to_replica<S: AsyncRead + AsyncWrite + Unpin>(
+    replica: &mut RespHandler<S>,
+    replication: &Replication,
+    id: u64,
+    mut receiver: UnboundedReceiver<Bytes>,
+    output: Arc<OutputBuffer>,
+    ping_period: Duration,
+) -> Result<()> {
+    let mut pings = tokio::time::interval_at(Instant::now() + ping_period, ping_period);
+    loop {
+        tokio::select! {
+            _ = pings.tick() => replication.lock_feed().await.ping(ping_period),
+            frame = receiver.recv() => {
+                // The feed let go of a replica over its output buffer limit
+                let Some(frame) = frame else {
+                    warning!("Replica closed for overcoming of output buffer limits.");
+                    break;
+                };
+                replica.socket.write_all(&frame).await?;
+                output.drain(frame.len());
+            }
+            value = replica.read_value() => {
+                let Some(value) = value? else {
+                    break;
+                };
+                if let Some(offset) = ack_offset(value) {
+                    replication.acknowledge(id, offset).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The offset reported by a `REPLCONF ACK <offset>` frame
+fn ack_offset(value: Value) -> Option<u64> {
+    let (command, args) = extract_command(value).ok()?;
+    match (command, args.as_slice()) {
+        (UserCommand::ReplConf, [option, offset])
+            if unpack_bulk_string(option.clone())
+                .ok()?
+                .eq_ignore_ascii_case("ACK") =>
+        {
+            unpack_bulk_string(offset.clone()).ok()?.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+// Keeps the link to the master up until REPLICAOF stops it: a link that breaks or stays
+// silent for repl-timeout is made again, backing off while the master cannot be reached
+async fn run_master_link(state: ServerState, host: String, port: u16) {
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        let result = sync_with_master(&state, &host, port).await;
+        // Having synchronized, the master was reachable, so the next attempt goes soon
+        if state.replication.link_up.swap(false, Ordering::Relaxed) {
+            delay = RECONNECT_MIN_DELAY;
+        }
+        match result {
+            Ok(()) => notice!("Master {}:{} closed the replication link", host, port),
+            Err(err) => warning!("Replication from {}:{} failed: {:#}", host, port, err),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+// Synchronizes with the master, then applies its command stream
+async fn sync_with_master(state: &ServerState, host: &str, port: u16) -> Result<()> {
+    let timeout = Duration::from_secs(state.config.read().await.repl_timeout);
+    let (mut master, mut position) = tokio::time::timeout(timeout, handshake(state, host, port))
+        .await
+        .map_err(|_| Error::Master("Timed out synchronizing with the master".to_owned()))??;
+    state.replication.set_master_offset(Some(position.clone()));
+    state.replication.link_up.store(true, Ordering::Relaxed);
+    let mut last_io = Instant::now();
+    *state.replication.last_io.lock().unwrap() = Some(last_io);
+
+    // Replicated writes go through the same path as client writes, so they reach this
+    // server's AOF and its own replicas too. The offset is reported back every second
+    // and whenever the master asks for it.
+    let mut heartbeat = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        let timeout = Duration::from_secs(state.config.read().await.repl_timeout);
+        let frame = tokio::select! {
+            frame = master.read_value() => frame?,
+            _ = heartbeat.tick() => {
+                send_ack(&mut master, position.offset).await?;
+                continue;
+            }
+            _ = tokio::time::sleep_until(last_io + timeout) => {
+                return Err(Error::Master(format!("No data from the master for {} seconds", timeout.as_secs())))
+            }
+        };
+        let Some(frame) = frame else {
+            break;
+        };
+        last_io = Instant::now();
+        *state.replication.last_io.lock().unwrap() = Some(last_io);
+        // Frames are plain arrays of bulk strings, so serializing gives back their size
+        let length = frame.serialize().len() as u64;
+        let (command, args) = extract_command(frame)?;
+        if command == UserCommand::ReplConf {
+            // The offset sent excludes the GETACK itself
+            send_ack(&mut master, position.offset).await?;
+        } else {
+            let _shared = state.exec_lock.read().await;
+            run_command(command, &args, state, &mut position.selected, None).await?;
+        }
+        // Recorded right after the command applied, with no await in between, so the
+        // link being aborted cannot leave the two apart
+        position.offset += length;
+        state.replication.set_master_offset(Some(position.clone()));
+    }
+    Ok(())
+}
+
+// Connects and introduces itself to the master, then either continues the stream it
+// followed or loads a full copy of the master's data
+async fn handshake(
+    state: &ServerState,
+    host: &str,
+    port: u16,
+) -> Result<(RespHandler, MasterOffset)> {
+    let socket = TcpStream::connect((host, port))
+        .await
+        .context("Failed to connect to the master")?;
+    let mut master = RespHandler::new(socket);
+    let listening_port = state.config.read().await.port.to_string();
+
+    expect_reply(&mut master, &["PING"], "PONG").await?;
+    expect_reply(
+        &mut master,
+        &["REPLCONF", "listening-port", &listening_port],
+        "OK",
+    )
+    .await?;
+    expect_reply(&mut master, &["REPLCONF", "capa", "psync2"], "OK").await?;
+
+    // Having followed a master before, ask to continue from there
+    let cached = state.replication.master_offset();
+    let (replid, next) = match &cached {
+        Some(cached) => (cached.replid.as_str(), (cached.offset + 1).to_string()),
+        None => ("?", "-1".to_owned()),
+    };
+    let reply = request(&mut master, &["PSYNC", replid, &next]).await?;
+    let position = match (reply, cached) {
+        // The master may have taken on another replid, which the stream now continues
+        (Value::SimpleString(reply), Some(cached)) if reply.starts_with("CONTINUE") => {
+            let replid = reply.split(' ').nth(1).unwrap_or(&cached.replid).to_owned();
+            notice!("Continuing the replication stream of {}:{}", host, port);
+            MasterOffset { replid, ..cached }
+        }
+        (Value::SimpleString(reply), _) if reply.starts_with("FULLRESYNC") => {
+            let mut fields = reply.split(' ').skip(1);
+            let (Some(replid), Some(offset)) = (
+                fields.next(),
+                fields.next().and_then(|offset| offset.parse().ok()),
+            ) else {
+                return Err(Error::Master("Invalid FULLRESYNC reply".to_owned()));
+            };
+            let keyspace = rdb::decode(&master.read_payload().await?)?;
+            let loaded = rdb::restore(keyspace, &state.databases).await?;
+            notice!("Synchronized {} keys from {}:{}", loaded, host, port);
+            MasterOffset {
+                replid: replid.to_owned(),
+                offset,
+                selected: 0,
+            }
+        }
+        (reply, _) => {
+            return Err(Error::Master(format!(
+                "Unexpected reply to PSYNC: {:?}",
+                reply
+            )))
+        }
+    };
+    Ok((master, position))
+}
+
+async fn send_ack(master: &mut RespHandler, offset: u64) -> Result<()> {
+    master
+        .write_value(&command_frame(&["REPLCONF", "ACK", &offset.to_string()]))
+        .await?;
+    Ok(())
+}
+
+async fn request(master: &mut RespHandler, parts: &[&str]) -> Result<Value> {
+    master.write_value(&command_frame(parts)).await?;
+    master.read_value().await?.ok_or_else(|| {
+        Error::Master("Master closed the connection during the handshake".to_owned())
+    })
+}
+
+async fn expect_reply(master: &mut RespHandler, parts: &[&str], expected: &str) -> Result<()> {
+    match request(master, parts).await? {
+        Value::SimpleString(reply) if reply == expected => Ok(()),
+        reply => Err(Error::Master(format!(
+            "Unexpected reply to {}: {:?}",
+            parts[0], reply
+        ))),
+    }
+}
@@ -0,0 +1,274 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::storage::sharded::StorageMut;
+    use crate::storage::{DataType, Entry};
+    use crate::testing::args;
+    use std::sync::Arc;
+
+    fn databases() -> Vec<Arc<ShardedDb>> {
+        (0..2).map(|_| Arc::new(ShardedDb::new())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_replid() {
+        let replid = Replication::new().replid().await;
+        assert_eq!(replid.len(), 40);
+        assert!(replid.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(Replication::new().replid().await, replid);
+    }
+
+    #[tokio::test]
+    async fn test_attach_snapshots_and_streams() {
+        let replication = Replication::new();
+        let databases = databases();
+        databases[1].write().await.insert_entry(
+            "key".to_owned(),
+            Entry::new(DataType::String("value".into())),
+        );
+
+        let (_, offset, keyspace, mut receiver, _) =
+            replication.attach_replica(&databases, None).await;
+        assert_eq!(offset, 0);
+        assert_eq!(keyspace[1].len(), 1);
+
+        replication
+            .lock_feed()
+            .await
+            .propagate(1, UserCommand::Set, &args(&["key", "other"]));
+        replication
+            .lock_feed()
+            .await
+            .propagate(1, UserCommand::Del, &args(&["key"]));
+
+        // The first command selects its database, the second reuses it
+        let set = receiver.recv().await.unwrap();
+        assert_eq!(
+            set,
+            Bytes::from_static(
+                b"*2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nother\r\n"
+            )
+        );
+        let del = receiver.recv().await.unwrap();
+        assert_eq!(del, Bytes::from_static(b"*2\r\n$3\r\nDEL\r\n$3\r\nkey\r\n"));
+        assert_eq!(
+            replication.lock_feed().await.offset,
+            (set.len() + del.len()) as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_replicas_are_dropped() {
+        let replication = Replication::new();
+        let databases = databases();
+        let (id, _, _, receiver, _) = replication.attach_replica(&databases, None).await;
+        let (_, _, _, _kept, _) = replication.attach_replica(&databases, None).await;
+        drop(receiver);
+
+        replication
+            .lock_feed()
+            .await
+            .propagate(0, UserCommand::Del, &args(&["key"]));
+        let feed = replication.lock_feed().await;
+        assert_eq!(feed.replicas.len(), 1);
+        assert_ne!(feed.replicas[0].id, id);
+    }
+
+    #[tokio::test]
+    async fn test_replicas_over_the_output_limit_are_dropped() {
+        let replication = Replication::new();
+        replication.set_output_limit(BufferLimit {
+            hard: 60,
+            soft: 0,
+            soft_seconds: 0,
+        });
+        let databases = databases();
+        let (_, _, _, mut receiver, output) = replication.attach_replica(&databases, None).await;
+
+        let mut feed = replication.lock_feed().await;
+        // SELECT and the first DEL fit, the second DEL does not
+        feed.propagate(0, UserCommand::Del, &args(&["key"]));
+        assert_eq!(feed.replicas.len(), 1);
+        feed.propagate(0, UserCommand::Del, &args(&["key"]));
+        assert!(feed.replicas.is_empty());
+        drop(feed);
+
+        // 23 bytes of SELECT and 22 of DEL, then another DEL that was never sent
+        assert_eq!(receiver.recv().await.unwrap().len(), 45);
+        assert!(receiver.recv().await.is_none());
+        assert_eq!(output.pending(), 67);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_the_backlog() {
+        let replication = Replication::new();
+        let databases = databases();
+        let replid = replication.replid().await;
+        // No backlog until a replica attaches
+        assert!(replication.resume_replica(&replid, 0, None).await.is_none());
+
+        let (id, _, _, _, _) = replication.attach_replica(&databases, None).await;
+        replication.detach_replica(id).await;
+        // Writes are kept in the backlog with no replica attached
+        let mut feed = replication.lock_feed().await;
+        feed.propagate(0, UserCommand::Del, &args(&["a"]));
+        let seen = feed.offset();
+        feed.propagate(0, UserCommand::Del, &args(&["b"]));
+        drop(feed);
+
+        let (_, missed, mut receiver, _) = replication
+            .resume_replica(&replid, seen, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            missed,
+            Bytes::from_static(b"*2\r\n$3\r\nDEL\r\n$1\r\nb\r\n")
+        );
+        replication
+            .lock_feed()
+            .await
+            .propagate(0, UserCommand::Del, &args(&["c"]));
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            Bytes::from_static(b"*2\r\n$3\r\nDEL\r\n$1\r\nc\r\n")
+        );
+
+        // Another stream, or offsets past the current one, need a full copy
+        assert!(replication
+            .resume_replica("other", seen, None)
+            .await
+            .is_none());
+        let offset = replication.lock_feed().await.offset();
+        assert!(replication
+            .resume_replica(&replid, offset + 1, None)
+            .await
+            .is_none());
+        assert!(replication
+            .resume_replica(&replid, offset, None)
+            .await
+            .is_some());
+
+        // Bytes past the backlog size are forgotten
+        replication.set_backlog_size(30);
+        replication
+            .lock_feed()
+            .await
+            .propagate(0, UserCommand::Del, &args(&["d"]));
+        assert!(replication
+            .resume_replica(&replid, seen, None)
+            .await
+            .is_none());
+        assert!(replication
+            .resume_replica(&replid, offset, None)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resume_the_stream_carried_on() {
+        let replication = Replication::new();
+        let replid = replication.replid().await;
+        // As left by a promotion at offset 10 of the "old" stream
+        {
+            let mut feed = replication.lock_feed().await;
+            feed.previous = Some(("old".to_owned(), 10));
+            feed.offset = 10;
+            feed.backlog = Some(Backlog::new(10));
+            feed.propagate(0, UserCommand::Del, &args(&["key"]));
+        }
+
+        let (_, missed, _, _) = replication.resume_replica("old", 10, None).await.unwrap();
+        assert_eq!(
+            missed,
+            Bytes::from_static(
+                b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*2\r\n$3\r\nDEL\r\n$3\r\nkey\r\n"
+            )
+        );
+        // Past where the old stream ended it went elsewhere
+        assert!(replication.resume_replica("old", 11, None).await.is_none());
+        assert!(replication
+            .resume_replica(&replid, 10, None)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failover_target() {
+        let replication = Replication::new();
+        let databases = databases();
+        let address = |port| Some(("127.0.0.1".to_owned(), port));
+        let (first, _, _, _, _) = replication.attach_replica(&databases, address(1)).await;
+        let (second, _, _, _, _) = replication.attach_replica(&databases, address(2)).await;
+        replication.attach_replica(&databases, None).await;
+        replication.acknowledge(second, 5).await;
+
+        let feed = replication.lock_feed().await;
+        // The replica furthest along, or the one asked for
+        assert_eq!(feed.failover_target(None).unwrap().0, second);
+        let target = ("127.0.0.1".to_owned(), 1);
+        assert_eq!(feed.failover_target(Some(&target)), Some((first, target)));
+        assert!(feed
+            .failover_target(Some(&("127.0.0.1".to_owned(), 3)))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ping_fills_silences() {
+        let replication = Replication::new();
+        let period = Duration::from_secs(60);
+        let mut feed = replication.lock_feed().await;
+        // Nobody to ping
+        feed.ping(period);
+        assert_eq!(feed.offset(), 0);
+        drop(feed);
+
+        let (_, _, _, mut receiver, _) = replication.attach_replica(&databases(), None).await;
+        let mut feed = replication.lock_feed().await;
+        feed.ping(period);
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            Bytes::from_static(b"*1\r\n$4\r\nPING\r\n")
+        );
+        // Not again until the period passes without anything streamed
+        feed.ping(period);
+        feed.ping(Duration::ZERO);
+        assert_eq!(feed.offset(), 28);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_acks() {
+        let replication = Arc::new(Replication::new());
+        let databases = databases();
+        let (id, _, _, mut receiver, _) = replication.attach_replica(&databases, None).await;
+
+        let offset = {
+            let mut feed = replication.lock_feed().await;
+            feed.propagate(0, UserCommand::Del, &args(&["key"]));
+            feed.request_acks();
+            feed.offset() - 36
+        };
+        receiver.recv().await.unwrap();
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            Bytes::from_static(b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n")
+        );
+
+        // Nobody acknowledges in time
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(
+            replication.wait_for_acks(offset, 1, Some(deadline)).await,
+            0
+        );
+
+        let acknowledging = Arc::clone(&replication);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            acknowledging.acknowledge(id, offset).await;
+        });
+        assert_eq!(replication.wait_for_acks(offset, 1, None).await, 1);
+
+        // An older acknowledgement never moves a replica back
+        replication.acknowledge(id, 0).await;
+        assert_eq!(replication.lock_feed().await.acked_replicas(offset), 1);
+    }
+}
@@ -0,0 +1,360 @@
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::{
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream},
+    sync::RwLock,
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::acl::Acl;
+use crate::clients::Clients;
+use crate::clock::{Clock, SystemClock};
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::connection::handle_connection;
+use crate::error::{Error, Result};
+use crate::latency::LatencyMonitor;
+use crate::logging;
+use crate::monitor::Monitor;
+use crate::persistence::aof::{self, spawn_fsync_task, Aof};
+use crate::persistence::rdb;
+use crate::plugin::{CommandPlugin, Plugins};
+use crate::propagation::WriteOrder;
+use crate::pubsub::PubSub;
+use crate::replication::Replication;
+use crate::shutdown::{self, Shutdown};
+use crate::stats::Stats;
+use crate::storage::sharded::ShardedDb;
+use crate::storage::spawn_expiry_sweeper;
+use crate::{notice, warning};
+
+pub mod tests_server;
+
+// The pause after an accept fails for want of resources
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// State shared by every connection. `exec_lock` is held shared while a command runs
+/// and exclusively while EXEC runs a transaction, so the queued commands never
+/// interleave with others. Every field is shared, so clones handed to background
+/// tasks see the same server.
+#[derive(Debug, Clone)]
+pub struct ServerState {
+    // The logical databases selected with SELECT, numbered from 0
+    pub databases: Vec<Arc<ShardedDb>>,
+    pub pubsub: Arc<PubSub>,
+    pub clients: Arc<Clients>,
+    pub monitor: Arc<Monitor>,
+    pub config: Arc<RwLock<Config>>,
+    pub exec_lock: Arc<RwLock<()>>,
+    // Held by writes to keep each key's writes in order up to the AOF and replicas
+    pub write_order: Arc<WriteOrder>,
+    // Set while a BGSAVE task is writing the snapshot
+    pub bgsave_in_progress: Arc<AtomicBool>,
+    // The append-only file, when appendonly is enabled
+    pub aof: Option<Arc<Aof>>,
+    pub replication: Arc<Replication>,
+    pub shutdown: Arc<Shutdown>,
+    // Commands added by an embedder
+    pub plugins: Arc<Plugins>,
+    // The slots this node serves, when cluster-enabled is set
+    pub cluster: Option<Arc<Cluster>>,
+    // Command and keyspace counters, reset by CONFIG RESETSTAT
+    pub stats: Arc<Stats>,
+    pub latency: Arc<LatencyMonitor>,
+    // Cleared by DEBUG SET-ACTIVE-EXPIRE 0 to pause the expiry sweeper
+    pub active_expire: Arc<AtomicBool>,
+    // The users clients log in as, the default one protected by requirepass
+    pub acl: Arc<Acl>,
+    // Where expiry, TTLs, TIME and idle times read the time, shared with every database
+    pub clock: Arc<dyn Clock>,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// A server that reads the time from `clock`, such as a `MockClock` in tests.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> Self {
+        let latency = Arc::new(LatencyMonitor::new(
+            config.latency_monitor_threshold,
+            Arc::clone(&clock),
+        ));
+        let cluster = config
+            .cluster_enabled
+            .then(|| Arc::new(Cluster::new(&config.bind, config.port)));
+        let acl = Arc::new(Acl::new(config.requirepass.as_deref()));
+        let limits = config.client_output_buffer_limit;
+        let pubsub = Arc::new(PubSub::new());
+        pubsub.set_output_limit(limits.pubsub);
+        let replication = Arc::new(Replication::new());
+        replication.set_output_limit(limits.replica);
+        replication.set_backlog_size(config.repl_backlog_size);
+        Self {
+            databases: (0..config.databases)
+                .map(|_| Arc::new(ShardedDb::with_clock(Arc::clone(&clock))))
+                .collect(),
+            pubsub,
+            clients: Arc::new(Clients::new()),
+            monitor: Arc::new(Monitor::new(Arc::clone(&clock))),
+            config: Arc::new(RwLock::new(config)),
+            exec_lock: Arc::new(RwLock::new(())),
+            write_order: Arc::new(WriteOrder::default()),
+            bgsave_in_progress: Arc::new(AtomicBool::new(false)),
+            aof: None,
+            replication,
+            shutdown: Arc::new(Shutdown::new()),
+            plugins: Arc::new(Plugins::new()),
+            cluster,
+            stats: Arc::new(Stats::new()),
+            latency,
+            active_expire: Arc::new(AtomicBool::new(true)),
+            acl,
+            clock,
+        }
+    }
+}
+
+/// A server running inside the current process, as the binary runs it or as a test
+/// starts one next to the code it exercises:
+///
+/// ```ignore
+/// let server = Server::builder().bind("127.0.0.1:0".parse()?).spawn().await?;
+/// let client = TcpStream::connect(server.local_addr()).await?;
+/// server.shutdown().await?;
+/// ```
+#[derive(Debug)]
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+}
+
+/// Settings for a server about to be spawned, starting from the defaults of `Config`.
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    config: Config,
+    storage: Option<Vec<Arc<ShardedDb>>>,
+    plugins: Plugins,
+}
+
+impl ServerBuilder {
+    /// Replaces every setting at once, so it goes before the other setters.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The address to listen on. Port 0 picks a free one, reported by
+    /// `ServerHandle::local_addr`.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.config.bind = addr.ip().to_string();
+        self.config.port = addr.port();
+        self
+    }
+
+    /// Serves these databases instead of empty ones, so a test can seed keys before
+    /// the server starts or read them back after it stops. Their number replaces
+    /// the `databases` setting, and whatever the AOF or the snapshot holds is loaded
+    /// on top.
+    pub fn storage(mut self, databases: Vec<Arc<ShardedDb>>) -> Self {
+        self.storage = Some(databases);
+        self
+    }
+
+    /// Adds a command for clients to call, see `CommandPlugin`.
+    pub fn plugin(mut self, plugin: impl CommandPlugin) -> Self {
+        self.plugins.register(plugin);
+        self
+    }
+
+    /// Binds the listener, loads the AOF or the snapshot and starts accepting clients
+    /// in the background.
+    pub async fn spawn(self) -> Result<ServerHandle> {
+        let mut config = self.config;
+        if let Some(databases) = &self.storage {
+            if databases.is_empty() {
+                return Err(Error::Config("Invalid number of databases".to_owned()));
+            }
+            config.databases = databases.len();
+        }
+        logging::configure(config.loglevel, config.log_format);
+        let listener = bind(&config).await?;
+        let local_addr = listener.local_addr()?;
+        // The port other cluster nodes are told to redirect clients to
+        config.port = local_addr.port();
+        let (appendonly, fsync) = (config.appendonly, config.appendfsync);
+        let (rdb_path, aof_path) = (config.rdb_path(), config.aof_path());
+        let mut state = ServerState::new(config);
+        if let Some(databases) = self.storage {
+            state.databases = databases;
+        }
+        // Registered first, so the AOF can replay plugin commands
+        state.plugins = Arc::new(self.plugins);
+        for name in state.plugins.names() {
+            state.acl.register_command(name);
+        }
+        let mut background = Vec::new();
+
+        // The AOF is the more complete record, so it takes precedence over the snapshot
+        if appendonly {
+            let replayed = aof::replay(&aof_path, &state).await?;
+            notice!("Replayed {} commands from {}", replayed, aof_path.display());
+            let log = Arc::new(Aof::open(&aof_path, fsync).await?);
+            background.push(spawn_fsync_task(
+                Arc::clone(&log),
+                Arc::clone(&state.latency),
+                Arc::clone(&state.shutdown),
+            ));
+            state.aof = Some(log);
+        } else {
+            let loaded = rdb::load(&rdb_path, &state.databases).await?;
+            notice!("Loaded {} keys from {}", loaded, rdb_path.display());
+        }
+        let state = Arc::new(state);
+        background.push(spawn_expiry_sweeper(
+            state.databases.clone(),
+            Duration::from_millis(100),
+            Arc::clone(&state.active_expire),
+            Arc::clone(&state.latency),
+            Arc::clone(&state.shutdown),
+        ));
+
+        let task = tokio::spawn(serve(listener, Arc::clone(&state), background));
+        Ok(ServerHandle {
+            local_addr,
+            state,
+            task,
+        })
+    }
+}
+
+/// A spawned server. It runs until shutdown is requested, by `shutdown`, a SHUTDOWN
+/// command or a signal once a handler is installed.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    state: Arc<ServerState>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn state(&self) -> &Arc<ServerState> {
+        &self.state
+    }
+
+    /// Stops the server without saving a snapshot and waits until it has.
+    pub async fn shutdown(self) -> Result<()> {
+        self.state.shutdown.request(false);
+        self.wait().await
+    }
+
+    /// Waits for the server to stop on its own.
+    pub async fn wait(self) -> Result<()> {
+        self.task.await?
+    }
+}
+
+// Binds the listener with tcp-backlog as the length of its accept queue
+async fn bind(config: &Config) -> Result<TcpListener> {
+    let address = config.address();
+    let addr = lookup_host(&address)
+        .await?
+        .next()
+        .ok_or_else(|| Error::Config(format!("{} does not resolve to an address", address)))?;
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // As TcpListener::bind does, so a restarted server gets its port back right away
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    Ok(socket.listen(config.tcp_backlog)?)
+}
+
+// Applies tcp-nodelay and tcp-keepalive to an accepted client connection
+fn tune(socket: &TcpStream, config: &Config) -> io::Result<()> {
+    socket.set_nodelay(config.tcp_nodelay)?;
+    let socket = SockRef::from(socket);
+    match config.tcp_keepalive {
+        0 => socket.set_keepalive(false),
+        seconds => {
+            let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(seconds));
+            socket.set_tcp_keepalive(&keepalive)
+        }
+    }
+}
+
+// How long to stop accepting after a failed accept. A client that gave up while
+// queued only costs that one connection, but running out of file descriptors fails
+// every accept until connections close, so retrying at once would spin
+fn accept_backoff(error: &io::Error) -> Option<Duration> {
+    match error.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => None,
+        _ => Some(ACCEPT_BACKOFF),
+    }
+}
+
+// Accepts clients until shutdown is requested, then lets the connections and the
+// background tasks wind down before the final save
+async fn serve(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    background: Vec<JoinHandle<()>>,
+) -> Result<()> {
+    let mut connections = JoinSet::new();
+    let save = loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let socket = match accepted {
+                    Ok((socket, _)) => socket,
+                    Err(e) => {
+                        warning!("Failed to accept a connection: {}", e);
+                        if let Some(pause) = accept_backoff(&e) {
+                            tokio::time::sleep(pause).await;
+                        }
+                        continue;
+                    }
+                };
+                if let Err(e) = tune(&socket, &*state.config.read().await) {
+                    warning!("Failed to set socket options: {}", e);
+                }
+                let state = Arc::clone(&state);
+                connections.spawn(async move {
+                    if let Err(e) = handle_connection(socket, state).await {
+                        warning!("Failed to handle connection: {}", e);
+                    }
+                });
+            }
+            // Closed connections are reaped as they go
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            save = state.shutdown.requested() => break save,
+        }
+    };
+
+    // No new connections from here on
+    drop(listener);
+    notice!(
+        "Shutting down, waiting for {} connections",
+        connections.len()
+    );
+    shutdown::drain(connections).await;
+    for task in background {
+        task.await?;
+    }
+    shutdown::finish(&state, save).await
+}
@@ -0,0 +1,287 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::parser::{RespHandler, Value};
+    use crate::storage::sharded::{Storage, StorageMut, WriteShards};
+    use crate::storage::{DataType, Entry};
+    use anyhow::Result;
+    use tokio::net::TcpStream;
+
+    fn config(name: &str) -> Config {
+        Config {
+            dir: std::env::temp_dir(),
+            dbfilename: format!("redis-rust-{}-{}.rdb", name, std::process::id()),
+            ..Config::new()
+        }
+    }
+
+    fn command(parts: &[&str]) -> Value {
+        Value::Array(
+            parts
+                .iter()
+                .map(|part| Value::BulkString(part.to_string().into()))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_spawn_and_shutdown() -> Result<()> {
+        let server = Server::builder()
+            .config(config("embedded"))
+            .bind("127.0.0.1:0".parse()?)
+            .spawn()
+            .await?;
+        assert_ne!(server.local_addr().port(), 0);
+
+        let mut client = RespHandler::new(TcpStream::connect(server.local_addr()).await?);
+        client.write_value(&command(&["PING"])).await?;
+        assert_eq!(
+            client.read_value().await?,
+            Some(Value::SimpleString("PONG".to_owned()))
+        );
+
+        let addr = server.local_addr();
+        server.shutdown().await?;
+        assert!(client.read_value().await?.is_none());
+        assert!(TcpStream::connect(addr).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_storage() -> Result<()> {
+        let databases = vec![Arc::new(ShardedDb::new()), Arc::new(ShardedDb::new())];
+        databases[1].write().await.insert_entry(
+            "seeded".to_owned(),
+            Entry::new(DataType::String("value".into())),
+        );
+        let server = Server::builder()
+            .config(config("embedded-storage"))
+            .bind("127.0.0.1:0".parse()?)
+            .storage(databases.clone())
+            .spawn()
+            .await?;
+        assert_eq!(server.state().config.read().await.databases, 2);
+
+        let mut client = RespHandler::new(TcpStream::connect(server.local_addr()).await?);
+        for (request, reply) in [
+            (vec!["SELECT", "1"], Value::SimpleString("OK".to_owned())),
+            (vec!["GET", "seeded"], Value::BulkString("value".into())),
+            (
+                vec!["SET", "written", "1"],
+                Value::SimpleString("OK".to_owned()),
+            ),
+            (
+                vec!["SELECT", "2"],
+                Value::SimpleError("ERR DB index is out of range".to_owned()),
+            ),
+        ] {
+            client.write_value(&command(&request)).await?;
+            assert_eq!(client.read_value().await?, Some(reply));
+        }
+        server.shutdown().await?;
+        assert!(databases[1].read().await.get("written").is_some());
+
+        let empty = Server::builder()
+            .config(config("embedded-no-storage"))
+            .bind("127.0.0.1:0".parse()?)
+            .storage(Vec::new())
+            .spawn()
+            .await;
+        assert!(empty.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tune_accepted_sockets() -> Result<()> {
+        let listener = bind(&Config {
+            port: 0,
+            tcp_backlog: 16,
+            ..Config::new()
+        })
+        .await?;
+        let _client = TcpStream::connect(listener.local_addr()?).await?;
+        let (socket, _) = listener.accept().await?;
+
+        tune(&socket, &Config::new())?;
+        assert!(socket.nodelay()?);
+        assert!(SockRef::from(&socket).keepalive()?);
+        tune(
+            &socket,
+            &Config {
+                tcp_keepalive: 0,
+                tcp_nodelay: false,
+                ..Config::new()
+            },
+        )?;
+        assert!(!socket.nodelay()?);
+        assert!(!SockRef::from(&socket).keepalive()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_errors_back_off_only_when_out_of_resources() {
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert_eq!(accept_backoff(&aborted), None);
+        // EMFILE, too many open files
+        let exhausted = io::Error::from_raw_os_error(24);
+        assert_eq!(accept_backoff(&exhausted), Some(ACCEPT_BACKOFF));
+    }
+
+    #[tokio::test]
+    async fn test_wait_returns_after_shutdown_command() -> Result<()> {
+        let server = Server::builder()
+            .config(config("embedded-wait"))
+            .bind("127.0.0.1:0".parse()?)
+            .spawn()
+            .await?;
+        let mut client = RespHandler::new(TcpStream::connect(server.local_addr()).await?);
+        client
+            .write_value(&command(&["SHUTDOWN", "NOSAVE"]))
+            .await?;
+        tokio::time::timeout(Duration::from_secs(5), server.wait()).await??;
+        Ok(())
+    }
+
+    struct Count;
+
+    impl CommandPlugin for Count {
+        fn name(&self) -> &str {
+            "count.keys"
+        }
+
+        fn arity(&self) -> i64 {
+            1
+        }
+
+        fn call(&self, storage: &mut WriteShards<'_>, _args: &[Value]) -> Value {
+            Value::Integer(storage.len() as i64)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugin_commands() -> Result<()> {
+        let server = Server::builder()
+            .config(config("embedded-plugin"))
+            .bind("127.0.0.1:0".parse()?)
+            .plugin(Count)
+            .spawn()
+            .await?;
+        let mut client = RespHandler::new(TcpStream::connect(server.local_addr()).await?);
+
+        for (request, reply) in [
+            (vec!["SET", "key", "value"], None),
+            (vec!["COUNT.KEYS"], Some(Value::Integer(1))),
+            (
+                vec!["count.keys", "extra"],
+                Some(Value::SimpleError(
+                    "ERR wrong number of arguments for 'count.keys' command".to_owned(),
+                )),
+            ),
+            (
+                vec!["missing.command"],
+                Some(Value::SimpleError(
+                    "ERR unknown command 'missing.command', with args beginning with: ".to_owned(),
+                )),
+            ),
+            (vec!["MULTI"], None),
+            (
+                vec!["count.keys"],
+                Some(Value::SimpleString("QUEUED".to_owned())),
+            ),
+            (vec!["EXEC"], Some(Value::Array(vec![Value::Integer(1)]))),
+        ] {
+            client.write_value(&command(&request)).await?;
+            let response = client.read_value().await?;
+            if let Some(reply) = reply {
+                assert_eq!(response, Some(reply));
+            }
+        }
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cluster_redirects() -> Result<()> {
+        let mut servers = Vec::new();
+        for name in ["embedded-cluster-a", "embedded-cluster-b"] {
+            let config = Config {
+                cluster_enabled: true,
+                ..config(name)
+            };
+            servers.push(
+                Server::builder()
+                    .config(config)
+                    .bind("127.0.0.1:0".parse()?)
+                    .spawn()
+                    .await?,
+            );
+        }
+        let (a, b) = (&servers[0], &servers[1]);
+        let b_port = b.local_addr().port().to_string();
+        let b_id = b.state().cluster.as_ref().unwrap().myid();
+        let mut client = RespHandler::new(TcpStream::connect(a.local_addr()).await?);
+        let ok = || Value::SimpleString("OK".to_owned());
+        let error = |message: String| Value::SimpleError(message);
+
+        // "bar" hashes to slot 5061 and "foo" to 12182
+        for (request, reply) in [
+            (
+                vec!["GET", "bar"],
+                error("CLUSTERDOWN Hash slot not served".to_owned()),
+            ),
+            (vec!["CLUSTER", "ADDSLOTSRANGE", "0", "8191"], ok()),
+            (
+                vec!["SET", "bar", "1"],
+                Value::SimpleString("OK".to_owned()),
+            ),
+            (vec!["CLUSTER", "MEET", "127.0.0.1", &b_port], ok()),
+            (vec!["CLUSTER", "SETSLOT", "12182", "NODE", &b_id], ok()),
+            (
+                vec!["GET", "foo"],
+                error(format!("MOVED 12182 127.0.0.1:{}", b_port)),
+            ),
+            (
+                vec!["MGET", "foo", "bar"],
+                error("CROSSSLOT Keys in request don't hash to the same slot".to_owned()),
+            ),
+            (
+                vec!["SELECT", "1"],
+                error("ERR SELECT is not allowed in cluster mode".to_owned()),
+            ),
+            // Keys of a migrating slot that already left are asked for on the target
+            (vec!["CLUSTER", "SETSLOT", "5061", "MIGRATING", &b_id], ok()),
+            (vec!["GET", "bar"], Value::BulkString("1".into())),
+            (
+                vec!["GET", "{bar}.missing"],
+                error(format!("ASK 5061 127.0.0.1:{}", b_port)),
+            ),
+        ] {
+            client.write_value(&command(&request)).await?;
+            assert_eq!(client.read_value().await?, Some(reply), "{:?}", request);
+        }
+
+        // The target serves an importing slot only right after ASKING
+        let a_id = a.state().cluster.as_ref().unwrap().myid();
+        let a_port = a.local_addr().port().to_string();
+        let mut client = RespHandler::new(TcpStream::connect(b.local_addr()).await?);
+        for (request, reply) in [
+            (vec!["CLUSTER", "MEET", "127.0.0.1", &a_port], ok()),
+            (vec!["CLUSTER", "SETSLOT", "5061", "NODE", &a_id], ok()),
+            (vec!["CLUSTER", "SETSLOT", "5061", "IMPORTING", &a_id], ok()),
+            (
+                vec!["GET", "bar"],
+                error(format!("MOVED 5061 127.0.0.1:{}", a_port)),
+            ),
+            (vec!["ASKING"], ok()),
+            (vec!["GET", "bar"], Value::Null),
+        ] {
+            client.write_value(&command(&request)).await?;
+            assert_eq!(client.read_value().await?, Some(reply), "{:?}", request);
+        }
+
+        for server in servers {
+            server.shutdown().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+use tokio::{sync::watch, task::JoinSet};
+
+use crate::error::Result;
+use crate::persistence::rdb;
+use crate::server::ServerState;
+use crate::{notice, warning};
+
+pub mod tests_shutdown;
+
+// How long connections get to finish what they are doing before they are dropped
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tells every part of the server to stop: the accept loop, connections between two
+/// batches of commands and the background tasks. It is requested once, by a signal or
+/// SHUTDOWN, and carries whether a final snapshot should be saved.
+#[derive(Debug)]
+pub struct Shutdown {
+    sender: watch::Sender<Option<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: watch::Sender::new(None),
+        }
+    }
+
+    /// Asks the server to stop. Only the first request counts.
+    pub fn request(&self, save: bool) {
+        self.sender.send_if_modified(|requested| {
+            let first = requested.is_none();
+            requested.get_or_insert(save);
+            first
+        });
+    }
+
+    /// Resolves once shutdown was requested, with whether to save first.
+    pub async fn requested(&self) -> bool {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as `self`, so the channel cannot close
+        let requested = receiver
+            .wait_for(Option::is_some)
+            .await
+            .expect("the shutdown sender outlives its receivers");
+        requested.unwrap_or_default()
+    }
+}
+
+/// Requests shutdown on SIGINT or SIGTERM. `save` says whether that shutdown saves a
+/// final snapshot. The handlers are installed before this returns, so no signal
+/// arriving afterwards is missed.
+#[cfg(unix)]
+pub fn spawn_signal_handler(state: &ServerState, save: bool) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+        notice!("Received a shutdown signal");
+        state.shutdown.request(save);
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_signal_handler(state: &ServerState, save: bool) -> std::io::Result<()> {
+    let state = state.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            state.shutdown.request(save);
+        }
+    });
+    Ok(())
+}
+
+/// Waits up to `DRAIN_TIMEOUT` for every connection to finish its current commands
+/// and close, then drops the ones still running.
+pub async fn drain(mut connections: JoinSet<()>) {
+    let finished = tokio::time::timeout(DRAIN_TIMEOUT, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if finished.is_err() {
+        warning!(
+            "Dropping {} connections that did not finish",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+}
+
+/// The last steps before the process exits: waits out any command still running,
+/// saves a final snapshot when asked to and syncs the AOF.
+pub async fn finish(state: &ServerState, save: bool) -> Result<()> {
+    let _exclusive = state.exec_lock.write().await;
+    if save {
+        let path = state.config.read().await.rdb_path();
+        let keyspace = rdb::snapshot(&state.databases).await;
+        rdb::save(&keyspace, &path).await?;
+        notice!("Saved the final snapshot to {}", path.display());
+    }
+    if let Some(aof) = &state.aof {
+        aof.lock().await.sync().await?;
+    }
+    Ok(())
+}
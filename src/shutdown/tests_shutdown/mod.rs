@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::config::Config;
+    use crate::storage::sharded::StorageMut;
+    use crate::storage::{DataType, Entry};
+
+    #[tokio::test]
+    async fn test_first_request_wins() {
+        let shutdown = Shutdown::new();
+        shutdown.request(true);
+        shutdown.request(false);
+        assert!(shutdown.requested().await);
+    }
+
+    #[tokio::test]
+    async fn test_requested_waits_for_the_request() {
+        let shutdown = std::sync::Arc::new(Shutdown::new());
+        let waiter = tokio::spawn({
+            let shutdown = std::sync::Arc::clone(&shutdown);
+            async move { shutdown.requested().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        shutdown.request(false);
+        assert!(!waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_connections() {
+        let mut connections = JoinSet::new();
+        connections.spawn(tokio::time::sleep(Duration::from_millis(20)));
+        let start = std::time::Instant::now();
+        drain(connections).await;
+        assert!(start.elapsed() < DRAIN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_finish_saves_the_final_snapshot() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("redis-rust-shutdown-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let config = Config {
+            dir: dir.clone(),
+            ..Config::new()
+        };
+        let state = ServerState::new(config.clone());
+        state.databases[0].write_key("key").await.insert_entry(
+            "key".to_owned(),
+            Entry::new(DataType::String("value".into())),
+        );
+
+        finish(&state, false).await?;
+        assert!(!config.rdb_path().exists());
+        finish(&state, true).await?;
+        let restored = ServerState::new(config.clone());
+        let loaded = rdb::load(&config.rdb_path(), &restored.databases).await?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(loaded, 1);
+        Ok(())
+    }
+}
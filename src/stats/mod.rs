@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::parser::COMMANDS;
+
+pub mod tests_stats;
+
+/// Counters for one command, as INFO commandstats reports them.
+#[derive(Debug, Default)]
+pub struct CommandStats {
+    pub calls: AtomicU64,
+    // Time spent running the command, in microseconds
+    pub usec: AtomicU64,
+    // Calls that replied with an error
+    pub failed_calls: AtomicU64,
+}
+
+/// Server-wide counters. There is one `CommandStats` per entry of the command table,
+/// created up front, so recording a call never takes a lock.
+#[derive(Debug)]
+pub struct Stats {
+    commands: HashMap<&'static str, CommandStats>,
+    pub total_error_replies: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            commands: COMMANDS
+                .iter()
+                .map(|spec| (spec.name, CommandStats::default()))
+                .collect(),
+            total_error_replies: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a call of the command named `name`, which took `elapsed` and replied
+    /// with an error when `failed`. Unknown names, such as plugin commands, only count
+    /// towards the totals.
+    pub fn record_call(&self, name: &str, elapsed: Duration, failed: bool) {
+        if failed {
+            self.total_error_replies.fetch_add(1, Ordering::Relaxed);
+        }
+        let Some(command) = self.commands.get(name) else {
+            return;
+        };
+        command.calls.fetch_add(1, Ordering::Relaxed);
+        command
+            .usec
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if failed {
+            command.failed_calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn command(&self, name: &str) -> Option<&CommandStats> {
+        self.commands.get(name)
+    }
+
+    pub fn total_commands_processed(&self) -> u64 {
+        self.commands
+            .values()
+            .map(|command| command.calls.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// One `cmdstat_<name>` line per command called since the last reset, by name.
+    pub fn command_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .commands
+            .iter()
+            .filter_map(|(name, command)| {
+                let calls = command.calls.load(Ordering::Relaxed);
+                let usec = command.usec.load(Ordering::Relaxed);
+                (calls > 0).then(|| {
+                    format!(
+                        "cmdstat_{}:calls={},usec={},usec_per_call={:.2},failed_calls={}",
+                        name.to_lowercase(),
+                        calls,
+                        usec,
+                        usec as f64 / calls as f64,
+                        command.failed_calls.load(Ordering::Relaxed)
+                    )
+                })
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    /// Zeroes every counter, for CONFIG RESETSTAT.
+    pub fn reset(&self) {
+        for command in self.commands.values() {
+            command.calls.store(0, Ordering::Relaxed);
+            command.usec.store(0, Ordering::Relaxed);
+            command.failed_calls.store(0, Ordering::Relaxed);
+        }
+        self.total_error_replies.store(0, Ordering::Relaxed);
+    }
+}
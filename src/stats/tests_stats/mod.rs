@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_record_and_reset() {
+        let stats = Stats::new();
+        stats.record_call("GET", Duration::from_micros(10), false);
+        stats.record_call("GET", Duration::from_micros(5), true);
+        stats.record_call("SET", Duration::from_micros(3), false);
+        // Unknown commands only count as errors
+        stats.record_call("", Duration::from_micros(1), true);
+
+        assert_eq!(stats.total_commands_processed(), 3);
+        assert_eq!(stats.total_error_replies.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            stats.command_lines(),
+            vec![
+                "cmdstat_get:calls=2,usec=15,usec_per_call=7.50,failed_calls=1".to_owned(),
+                "cmdstat_set:calls=1,usec=3,usec_per_call=3.00,failed_calls=0".to_owned(),
+            ]
+        );
+
+        stats.reset();
+        assert_eq!(stats.total_commands_processed(), 0);
+        assert_eq!(stats.total_error_replies.load(Ordering::Relaxed), 0);
+        assert!(stats.command_lines().is_empty());
+        assert_eq!(
+            stats.command("GET").unwrap().usec.load(Ordering::Relaxed),
+            0
+        );
+    }
+}
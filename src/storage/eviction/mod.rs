@@ -0,0 +1,98 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use super::sharded::{Storage, StorageMut};
+use crate::config::MaxMemoryPolicy;
+
+pub mod tests_eviction;
+
+/// The total estimated memory of the entries in every database.
+pub fn used_memory(databases: &mut [impl StorageMut]) -> u64 {
+    databases.iter_mut().map(|db| db.used_memory() as u64).sum()
+}
+
+/// Evicts keys until the databases fit in `maxmemory` bytes, choosing each victim
+/// by `policy` among `samples` random keys of every database, the way Redis
+/// approximates LRU. Returns the database and name of every evicted key, and
+/// whether the memory now fits; it does not when the policy has nothing left to
+/// evict.
+pub fn evict(
+    databases: &mut [impl StorageMut],
+    maxmemory: u64,
+    policy: MaxMemoryPolicy,
+    samples: usize,
+) -> (Vec<(usize, String)>, bool) {
+    let mut evicted = Vec::new();
+    while used_memory(databases) > maxmemory {
+        if policy == MaxMemoryPolicy::NoEviction {
+            return (evicted, false);
+        }
+        let Some((index, key)) = victim(databases, policy, samples) else {
+            return (evicted, false);
+        };
+        databases[index].evict(&key);
+        evicted.push((index, key));
+    }
+    (evicted, true)
+}
+
+// The sampled key that best matches the policy, lowest score first
+fn victim(
+    databases: &[impl Storage],
+    policy: MaxMemoryPolicy,
+    samples: usize,
+) -> Option<(usize, String)> {
+    let mut best = None;
+    for (index, db) in databases.iter().enumerate() {
+        for _ in 0..samples {
+            let Some(key) = db.random_key() else {
+                break;
+            };
+            keep_lowest(&mut best, db, index, key, policy);
+        }
+    }
+    // Keys with a TTL may be too rare for sampling to find them
+    if best.is_none() && policy.is_volatile() {
+        for (index, db) in databases.iter().enumerate() {
+            for key in db.keys() {
+                keep_lowest(&mut best, db, index, key, policy);
+            }
+        }
+    }
+    best.map(|(_, index, key)| (index, key.clone()))
+}
+
+fn keep_lowest<'a>(
+    best: &mut Option<(u64, usize, &'a String)>,
+    db: &impl Storage,
+    index: usize,
+    key: &'a String,
+    policy: MaxMemoryPolicy,
+) {
+    if let Some(score) = score(db, key, policy) {
+        if best.is_none_or(|(lowest, _, _)| score < lowest) {
+            *best = Some((score, index, key));
+        }
+    }
+}
+
+// How eagerly a key should go, None when the policy never evicts it
+fn score(db: &impl Storage, key: &str, policy: MaxMemoryPolicy) -> Option<u64> {
+    let (entry, accessed_at) = db.peek_entry(key)?;
+    match policy {
+        MaxMemoryPolicy::NoEviction => None,
+        MaxMemoryPolicy::AllKeysLru => Some(accessed_at),
+        MaxMemoryPolicy::VolatileLru => entry.expires_at.map(|_| accessed_at),
+        // Among keys used as often, the least recently used goes first
+        MaxMemoryPolicy::AllKeysLfu => Some(lfu_score(db, key, accessed_at)),
+        MaxMemoryPolicy::VolatileLfu => entry.expires_at.map(|_| lfu_score(db, key, accessed_at)),
+        MaxMemoryPolicy::AllKeysRandom => Some(RandomState::new().hash_one(key)),
+        MaxMemoryPolicy::VolatileTtl => entry.expires_at,
+    }
+}
+
+// The access counter above the access time, which takes up well under 48 bits
+fn lfu_score(db: &impl Storage, key: &str, accessed_at: u64) -> u64 {
+    let frequency = db.frequency(key).unwrap_or_default() as u64;
+    (frequency << 48) | accessed_at
+}
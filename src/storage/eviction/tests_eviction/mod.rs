@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::storage::sharded::ShardedDb;
+    use crate::storage::{DataType, Entry};
+
+    fn insert(db: &mut impl StorageMut, key: &str, expires_in: Option<u64>) {
+        let mut entry = Entry::new(DataType::String(key.to_owned().into()));
+        entry.expires_at = expires_in.map(|millis| SystemClock.now_millis() + millis);
+        db.insert_entry(key.to_owned(), entry);
+    }
+
+    #[tokio::test]
+    async fn test_allkeys_lru_evicts_least_recently_used() {
+        let db = ShardedDb::new();
+        let mut databases = [db.write().await];
+        for key in ["a", "b", "c"] {
+            insert(&mut databases[0], key, None);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        databases[0].get("a");
+        let limit = used_memory(&mut databases) - 1;
+
+        // Sampling many times over three keys sees all of them
+        let (evicted, fits) = evict(&mut databases, limit, MaxMemoryPolicy::AllKeysLru, 100);
+        assert_eq!(evicted, vec![(0, "b".to_owned())]);
+        assert!(fits);
+        assert!(used_memory(&mut databases) <= limit);
+    }
+
+    #[tokio::test]
+    async fn test_allkeys_lfu_evicts_least_frequently_used() {
+        let db = ShardedDb::new();
+        let mut databases = [db.write().await];
+        // b is used more recently, but a more often
+        insert(&mut databases[0], "a", None);
+        databases[0].get("a");
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        insert(&mut databases[0], "b", None);
+        let limit = used_memory(&mut databases) - 1;
+
+        let (evicted, fits) = evict(&mut databases, limit, MaxMemoryPolicy::AllKeysLfu, 100);
+        assert_eq!(evicted, vec![(0, "b".to_owned())]);
+        assert!(fits);
+    }
+
+    #[tokio::test]
+    async fn test_volatile_ttl_spares_persistent_keys() {
+        let (first, second) = (ShardedDb::new(), ShardedDb::new());
+        let mut databases = [first.write().await, second.write().await];
+        insert(&mut databases[0], "persistent", None);
+        insert(&mut databases[0], "later", Some(60_000));
+        insert(&mut databases[1], "sooner", Some(30_000));
+
+        let (evicted, fits) = evict(&mut databases, 0, MaxMemoryPolicy::VolatileTtl, 5);
+        assert_eq!(
+            evicted,
+            vec![(1, "sooner".to_owned()), (0, "later".to_owned())]
+        );
+        assert!(!fits);
+        assert!(databases[0].get("persistent").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_noeviction_keeps_everything() {
+        let db = ShardedDb::new();
+        let mut databases = [db.write().await];
+        insert(&mut databases[0], "key", None);
+        assert_eq!(
+            evict(&mut databases, 0, MaxMemoryPolicy::NoEviction, 5),
+            (Vec::new(), false)
+        );
+        let (evicted, fits) = evict(&mut databases, 0, MaxMemoryPolicy::AllKeysRandom, 5);
+        assert_eq!(evicted.len(), 1);
+        assert!(fits);
+    }
+}
@@ -0,0 +1,699 @@
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::clock::{Clock, SystemClock};
+use crate::debug;
+use crate::latency::LatencyMonitor;
+use crate::shutdown::Shutdown;
+
+use scan::{ScanMap, ScanSet};
+use sharded::ShardedDb;
+use sorted_set::SortedSet;
+use stream::Stream;
+use string::{parse_integer, StringValue};
+use table::Table;
+
+pub mod eviction;
+pub mod scan;
+pub mod sharded;
+pub mod sorted_set;
+pub mod stream;
+pub mod string;
+pub mod table;
+pub mod tests_storage;
+
+/// The value stored under a key, one variant per Redis data type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataType {
+    String(StringValue),
+    Hash(ScanMap<String, Bytes>),
+    Set(ScanSet<Bytes>),
+    SortedSet(SortedSet),
+    List(VecDeque<Bytes>),
+    Stream(Stream),
+}
+
+/// How a database's estimated memory splits up, as MEMORY STATS reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryStats {
+    pub keys: usize,
+    // Bytes of keyspace bookkeeping: keys and their slots, and separately expiry times
+    pub overhead: usize,
+    pub expires_overhead: usize,
+    // Bytes of the values themselves
+    pub dataset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: DataType,
+    // Absolute expiry time in milliseconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
+// How many random picks RANDOMKEY tries before settling for any live key
+const RANDOM_KEY_ATTEMPTS: usize = 16;
+
+/// Counters of what happened to the keys of one database, for INFO stats. Every shard
+/// of a database shares them, and they are atomics so lookups under a read lock can
+/// count too.
+#[derive(Debug, Default)]
+pub struct KeyspaceStats {
+    pub expired_keys: AtomicU64,
+    pub evicted_keys: AtomicU64,
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    // The memory estimate of every shard's table, lagging behind for entries changed
+    // in place until they are measured again. Not a counter, so RESETSTAT keeps it
+    pub used_memory: AtomicUsize,
+}
+
+impl KeyspaceStats {
+    /// Counts a read of a key that existed when `hit`.
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.keyspace_hits,
+            false => &self.keyspace_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Zeroes every counter, for CONFIG RESETSTAT.
+    pub fn reset(&self) {
+        self.expired_keys.store(0, Ordering::Relaxed);
+        self.evicted_keys.store(0, Ordering::Relaxed);
+        self.keyspace_hits.store(0, Ordering::Relaxed);
+        self.keyspace_misses.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The keyspace. Every entry carries its own expiry timestamp; expired entries are
+/// invisible to readers straight away and are physically removed either when a
+/// writer touches them or by the periodic sweeper.
+///
+/// Keys that are WATCHed by at least one connection also get a version, bumped on
+/// every write, so a transaction can tell whether they changed underneath it.
+#[derive(Debug)]
+pub struct Db {
+    entries: Table,
+    // Number of connections watching each key
+    watchers: HashMap<String, usize>,
+    // Version of the last write to each watched key; absent means untouched since watched
+    versions: HashMap<String, u64>,
+    last_version: u64,
+    // Connections blocked on each key by BLPOP, BRPOP or XREAD, in the order they blocked
+    blocked: HashMap<String, VecDeque<Arc<Notify>>>,
+    stats: Arc<KeyspaceStats>,
+    // Expiry is judged by the server's clock
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Self::with_stats(Arc::default(), Arc::new(SystemClock))
+    }
+}
+
+// Up to this many elements, each no longer than SMALL_ELEMENT bytes, Redis keeps a
+// hash, list, set or sorted set in a compact listpack
+const LISTPACK_ENTRIES: usize = 128;
+const SMALL_ELEMENT: usize = 64;
+// Sets of integers stay an intset up to this size
+const INTSET_ENTRIES: usize = 512;
+// Strings up to this length are embedded with their header
+const EMBSTR_SIZE: usize = 44;
+
+// Rough allocation overheads for memory accounting: a key with its entry in the
+// keyspace, its expiry time, the header of a value and one element inside a
+// collection
+const KEY_OVERHEAD: usize = 56;
+const EXPIRY_OVERHEAD: usize = 8;
+const VALUE_HEADER: usize = 16;
+const ELEMENT_OVERHEAD: usize = 16;
+// A stream ID or a sorted set score
+const NUMBER_SIZE: usize = 16;
+
+impl DataType {
+    /// The name TYPE reports.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DataType::String(_) => "string",
+            DataType::Hash(_) => "hash",
+            DataType::Set(_) => "set",
+            DataType::SortedSet(_) => "zset",
+            DataType::List(_) => "list",
+            DataType::Stream(_) => "stream",
+        }
+    }
+
+    /// The encoding Redis would use for this value, as OBJECT ENCODING reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            DataType::String(StringValue::Int(_)) => "int",
+            DataType::String(string) if string.len() <= EMBSTR_SIZE => "embstr",
+            DataType::String(_) => "raw",
+            DataType::Hash(hash)
+                if fits_listpack(
+                    hash.len(),
+                    hash.iter()
+                        .map(|(field, value)| field.len().max(value.len())),
+                ) =>
+            {
+                "listpack"
+            }
+            DataType::Hash(_) => "hashtable",
+            DataType::Set(set)
+                if set.len() <= INTSET_ENTRIES
+                    && set.iter().all(|member| parse_integer(member).is_some()) =>
+            {
+                "intset"
+            }
+            DataType::Set(set)
+                if fits_listpack(set.len(), set.iter().map(|member| member.len())) =>
+            {
+                "listpack"
+            }
+            DataType::Set(_) => "hashtable",
+            DataType::SortedSet(sorted_set)
+                if fits_listpack(
+                    sorted_set.len(),
+                    sorted_set.iter().map(|(member, _)| member.len()),
+                ) =>
+            {
+                "listpack"
+            }
+            DataType::SortedSet(_) => "skiplist",
+            DataType::List(list)
+                if fits_listpack(list.len(), list.iter().map(|element| element.len())) =>
+            {
+                "listpack"
+            }
+            DataType::List(_) => "quicklist",
+            DataType::Stream(_) => "stream",
+        }
+    }
+
+    /// An estimate of the bytes the value takes: a header and the empty structure of
+    /// its type, then its contents plus a fixed overhead per element.
+    pub fn memory_usage(&self) -> usize {
+        VALUE_HEADER + self.structure_size() + self.contents_size()
+    }
+
+    // The empty container: a table for hashes and sets, a score map plus an ordered
+    // index for sorted sets, a ring buffer for lists, and for streams the entry index
+    // with the group table
+    fn structure_size(&self) -> usize {
+        match self {
+            DataType::String(_) => 0,
+            DataType::Hash(_) | DataType::Set(_) => 48,
+            DataType::SortedSet(_) => 96,
+            DataType::List(_) => 32,
+            DataType::Stream(_) => 80,
+        }
+    }
+
+    fn contents_size(&self) -> usize {
+        match self {
+            // An int lives in the object header itself, as in Redis
+            DataType::String(StringValue::Int(_)) => 0,
+            DataType::String(StringValue::Raw(string)) => string.len(),
+            // Fields are copied into the scan index; set and sorted set members share
+            // their buffer with it
+            DataType::Hash(hash) => hash
+                .iter()
+                .map(|(field, value)| 2 * field.len() + value.len() + 3 * ELEMENT_OVERHEAD)
+                .sum(),
+            DataType::Set(set) => set
+                .iter()
+                .map(|member| member.len() + 2 * ELEMENT_OVERHEAD)
+                .sum(),
+            // Members sit in the score map, the ordered index and the scan index
+            DataType::SortedSet(sorted_set) => sorted_set
+                .iter()
+                .map(|(member, _)| member.len() + NUMBER_SIZE + 3 * ELEMENT_OVERHEAD)
+                .sum(),
+            DataType::List(list) => list
+                .iter()
+                .map(|element| element.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            DataType::Stream(stream) => {
+                let entries: usize = stream
+                    .entries()
+                    .values()
+                    .map(|fields| {
+                        NUMBER_SIZE
+                            + ELEMENT_OVERHEAD
+                            + fields
+                                .iter()
+                                .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+                                .sum::<usize>()
+                    })
+                    .sum();
+                let groups: usize = stream
+                    .groups()
+                    .iter()
+                    .map(|(name, group)| {
+                        name.len()
+                            + ELEMENT_OVERHEAD
+                            + group.pending().len() * (NUMBER_SIZE + 2 * ELEMENT_OVERHEAD)
+                            + group
+                                .consumers()
+                                .keys()
+                                .map(|consumer| consumer.len() + ELEMENT_OVERHEAD)
+                                .sum::<usize>()
+                    })
+                    .sum();
+                entries + groups
+            }
+        }
+    }
+}
+
+fn fits_listpack(len: usize, mut sizes: impl Iterator<Item = usize>) -> bool {
+    len <= LISTPACK_ENTRIES && sizes.all(|size| size <= SMALL_ELEMENT)
+}
+
+impl Entry {
+    pub fn new(value: DataType) -> Self {
+        Self {
+            value,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(at) if at <= now)
+    }
+
+    /// An estimate of the bytes this entry takes in the keyspace under `key`.
+    pub fn memory_usage(&self, key: &str) -> usize {
+        self.overhead(key) + self.value.memory_usage()
+    }
+
+    // The keyspace's own bookkeeping for the entry
+    fn overhead(&self, key: &str) -> usize {
+        KEY_OVERHEAD + key.len() + self.expires_at.map_or(0, |_| EXPIRY_OVERHEAD)
+    }
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A shard of a database, counting into the stats of the whole database and
+    /// reading the time from its clock.
+    pub fn with_stats(stats: Arc<KeyspaceStats>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: Table::with_stats(Arc::clone(&stats), Arc::clone(&clock)),
+            watchers: HashMap::new(),
+            versions: HashMap::new(),
+            last_version: 0,
+            blocked: HashMap::new(),
+            stats,
+            clock,
+        }
+    }
+
+    /// The current time in milliseconds since the Unix epoch, by the server's clock.
+    pub fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    pub fn stats(&self) -> &KeyspaceStats {
+        &self.stats
+    }
+
+    pub fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(self.clock.now_millis()))
+    }
+
+    /// A live entry and the time it was last accessed, without counting this lookup as
+    /// an access.
+    pub fn peek_entry(&self, key: &str) -> Option<(&Entry, u64)> {
+        self.entries
+            .peek(key)
+            .filter(|(entry, _)| !entry.is_expired(self.clock.now_millis()))
+    }
+
+    /// The LFU access counter of a live key, without counting this lookup as an access.
+    pub fn frequency(&self, key: &str) -> Option<u8> {
+        self.peek_entry(key)?;
+        self.entries.frequency(key)
+    }
+
+    /// Mutable access to a live entry; an expired entry is dropped first.
+    pub fn get_entry_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.purge_if_expired(key);
+        self.touch(key);
+        self.entries.get_mut(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&DataType> {
+        self.get_entry(key).map(|entry| &entry.value)
+    }
+
+    /// Like `get`, but counted as a keyspace hit or miss. For the keys commands read.
+    pub fn lookup(&self, key: &str) -> Option<&DataType> {
+        let value = self.get(key);
+        self.stats.record_lookup(value.is_some());
+        value
+    }
+
+    /// Stores an entry, replacing any previous value and TTL, and returns the previous
+    /// live entry.
+    pub fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        let now = self.clock.now_millis();
+        self.touch(&key);
+        let previous = self.entries.insert(key, entry);
+        self.live(previous, now)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<DataType> {
+        self.remove_entry(key).map(|entry| entry.value)
+    }
+
+    /// Removes a live key along with its TTL.
+    pub fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let now = self.clock.now_millis();
+        let removed = self.entries.remove(key);
+        // Deleting a key that is not there changes nothing a watcher could see
+        if removed.is_some() {
+            self.touch(key);
+        }
+        self.live(removed, now)
+    }
+
+    /// Removes a key to make room under maxmemory, counted as evicted.
+    pub fn evict(&mut self, key: &str) -> Option<DataType> {
+        let value = self.remove(key);
+        if value.is_some() {
+            self.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Sets the absolute expiry of a live key. Returns false when the key does not exist.
+    pub fn set_expiry(&mut self, key: &str, expires_at: Option<u64>) -> bool {
+        self.purge_if_expired(key);
+        self.touch(key);
+        self.entries.set_expiry(key, expires_at)
+    }
+
+    /// Iterates over every live key.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries().map(|(key, _)| key)
+    }
+
+    /// Iterates over every live key and its entry.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        let now = self.clock.now_millis();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+    }
+
+    /// One page of live keys, see `ScanIndex::page`. Expired keys still count towards
+    /// `count`, so a page may come back short with a non-zero cursor.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&String>) {
+        let now = self.clock.now_millis();
+        let (cursor, entries) = self.entries.scan(cursor, count);
+        let keys = entries
+            .into_iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key)
+            .collect();
+        (cursor, keys)
+    }
+
+    /// The number of keys, counting expired ones the sweeper has not reclaimed yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of live keys with a TTL.
+    pub fn expires(&self) -> usize {
+        self.entries()
+            .filter(|(_, entry)| entry.expires_at.is_some())
+            .count()
+    }
+
+    /// Breaks down the estimated memory of the live entries.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for (key, entry) in self.entries() {
+            stats.keys += 1;
+            stats.overhead += KEY_OVERHEAD + key.len();
+            stats.expires_overhead += entry.expires_at.map_or(0, |_| EXPIRY_OVERHEAD);
+            stats.dataset += entry.value.memory_usage();
+        }
+        stats
+    }
+
+    /// An estimate of the bytes taken by the entries, expired ones included.
+    pub fn used_memory(&mut self) -> usize {
+        self.entries.used_memory()
+    }
+
+    /// A uniformly random live key, None when there is none.
+    pub fn random_key(&self) -> Option<&String> {
+        if self.is_empty() {
+            return None;
+        }
+        let now = self.clock.now_millis();
+        for _ in 0..RANDOM_KEY_ATTEMPTS {
+            let (key, entry) = self.entries.random()?;
+            if !entry.is_expired(now) {
+                return Some(key);
+            }
+        }
+        // Mostly expired keys that are waiting for the sweeper
+        self.keys().next()
+    }
+
+    /// Remaining time to live in milliseconds. `None` means the key does not exist and
+    /// `Some(None)` means it exists without an expiry.
+    pub fn ttl_millis(&self, key: &str) -> Option<Option<u64>> {
+        let now = self.clock.now_millis();
+        self.get_entry(key)
+            .map(|entry| entry.expires_at.map(|at| at.saturating_sub(now)))
+    }
+
+    /// Physically removes every expired entry and returns how many were dropped.
+    pub fn remove_expired(&mut self) -> usize {
+        let now = self.clock.now_millis();
+        let before = self.entries.len();
+        // Expiring a watched key counts as a write to it
+        let mut expired_watched = Vec::new();
+        let watchers = &self.watchers;
+        self.entries.retain(|key, entry| {
+            let expired = entry.is_expired(now);
+            if expired && watchers.contains_key(key) {
+                expired_watched.push(key.clone());
+            }
+            !expired
+        });
+        for key in &expired_watched {
+            self.touch(key);
+        }
+        let removed = before - self.entries.len();
+        self.stats
+            .expired_keys
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// Looks at up to `count` random keys with a TTL and removes those that expired.
+    /// Returns how many keys were looked at and how many of them were removed.
+    pub fn remove_expired_sample(&mut self, count: usize) -> (usize, usize) {
+        let now = self.clock.now_millis();
+        let (mut sampled, mut removed) = (0, 0);
+        while sampled < count {
+            let Some((key, entry)) = self.entries.random_volatile() else {
+                break;
+            };
+            sampled += 1;
+            if entry.is_expired(now) {
+                let key = key.clone();
+                self.entries.remove(&key);
+                self.touch(&key);
+                removed += 1;
+            }
+        }
+        self.stats
+            .expired_keys
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        (sampled, removed)
+    }
+
+    /// Removes every key. Watched keys count as written.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.touch_all_watched();
+    }
+
+    /// Empties the database like `clear` but hands the old entries back, so the caller
+    /// can free them somewhere that does not hold the lock.
+    pub fn take_entries(&mut self) -> Table {
+        self.touch_all_watched();
+        let empty = Table::with_stats(Arc::clone(&self.stats), Arc::clone(&self.clock));
+        std::mem::replace(&mut self.entries, empty)
+    }
+
+    /// Exchanges the contents of two databases, as SWAPDB does. Watches stay with their
+    /// database, so every watched key on both sides counts as written.
+    pub fn swap_entries(&mut self, other: &mut Db) {
+        std::mem::swap(&mut self.entries, &mut other.entries);
+        self.entries.set_stats(Arc::clone(&self.stats));
+        other.entries.set_stats(Arc::clone(&other.stats));
+        self.touch_all_watched();
+        other.touch_all_watched();
+    }
+
+    /// Starts watching a key on behalf of one connection and returns its current version.
+    pub fn watch(&mut self, key: &str) -> u64 {
+        *self.watchers.entry(key.to_owned()).or_default() += 1;
+        self.version(key)
+    }
+
+    /// Drops one connection's watch on a key, forgetting its version once nobody watches it.
+    pub fn unwatch(&mut self, key: &str) {
+        if let Some(count) = self.watchers.get_mut(key) {
+            *count -= 1;
+            if *count == 0 {
+                self.watchers.remove(key);
+                self.versions.remove(key);
+            }
+        }
+    }
+
+    /// Queues a connection blocked on some keys until something is added to one of them.
+    pub fn block(&mut self, keys: &[String], waiter: &Arc<Notify>) {
+        for key in keys {
+            self.blocked
+                .entry(key.clone())
+                .or_default()
+                .push_back(Arc::clone(waiter));
+        }
+    }
+
+    pub fn unblock(&mut self, keys: &[String], waiter: &Arc<Notify>) {
+        for key in keys {
+            if let Some(waiters) = self.blocked.get_mut(key) {
+                waiters.retain(|blocked| !Arc::ptr_eq(blocked, waiter));
+                if waiters.is_empty() {
+                    self.blocked.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Wakes up to `count` connections blocked on a key, longest waiting first. A woken
+    /// connection leaves the queue and blocks again if another client got there first.
+    pub fn wake_blocked(&mut self, key: &str, count: usize) {
+        let Some(waiters) = self.blocked.get_mut(key) else {
+            return;
+        };
+        let mut woken = 0;
+        while woken < count {
+            let Some(waiter) = waiters.pop_front() else {
+                break;
+            };
+            // Only the queue still holds a waiter whose connection has gone away
+            if Arc::strong_count(&waiter) > 1 {
+                waiter.notify_one();
+                woken += 1;
+            }
+        }
+        if waiters.is_empty() {
+            self.blocked.remove(key);
+        }
+    }
+
+    /// The version of a watched key. Any write after `watch` returns a different value.
+    pub fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn touch_all_watched(&mut self) {
+        let watched: Vec<String> = self.watchers.keys().cloned().collect();
+        for key in &watched {
+            self.touch(key);
+        }
+    }
+
+    // Records a write to a key if anybody is watching it
+    fn touch(&mut self, key: &str) {
+        if self.watchers.contains_key(key) {
+            self.last_version += 1;
+            self.versions.insert(key.to_owned(), self.last_version);
+        }
+    }
+
+    // An entry taken out of the table if it is still live; an expired one counts as
+    // expired now that it is gone
+    fn live(&self, entry: Option<Entry>, now: u64) -> Option<Entry> {
+        let entry = entry?;
+        if entry.is_expired(now) {
+            self.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(entry)
+    }
+
+    fn purge_if_expired(&mut self, key: &str) {
+        if matches!(self.entries.peek(key), Some((entry, _)) if entry.is_expired(self.clock.now_millis()))
+        {
+            self.entries.remove(key);
+            self.touch(key);
+            self.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Starts the active expiry cycle, which reclaims keys that expired without being accessed.
+/// It skips its rounds while `active` is false. A round samples keys with a TTL, see
+/// `ShardedDb::expire_cycle`, and may take up to a quarter of `interval`.
+pub fn spawn_expiry_sweeper(
+    databases: Vec<Arc<ShardedDb>>,
+    interval: Duration,
+    active: Arc<AtomicBool>,
+    latency: Arc<LatencyMonitor>,
+    shutdown: Arc<Shutdown>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.requested() => return,
+            }
+            if !active.load(Ordering::Relaxed) {
+                continue;
+            }
+            let started = Instant::now();
+            let deadline = started + interval / 4;
+            for (index, db_instance) in databases.iter().enumerate() {
+                let removed = db_instance.expire_cycle(deadline).await;
+                if removed > 0 {
+                    debug!("Expired {} keys in db {}", removed, index);
+                }
+            }
+            latency.record("expire-cycle", started.elapsed());
+        }
+    })
+}
@@ -0,0 +1,361 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+pub mod tests_scan;
+
+/// A fixed hash of a key or member, the order SCAN and its relatives walk in. Being
+/// fixed for the life of the process, a cursor taken from it stays valid however
+/// the collection changes in between.
+pub fn scan_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The keys of a collection ordered by `scan_hash`, kept next to the collection so
+/// a page of a cursor-based iteration is a range lookup: it costs the page size and
+/// a logarithm, not a pass over the whole collection.
+///
+/// The cursor is the hash to resume from, so every item present for the whole
+/// iteration is returned at least once however the collection grows or shrinks. A
+/// returned cursor of 0 ends the iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanIndex<K> {
+    order: BTreeSet<(u64, K)>,
+}
+
+impl<K> Default for ScanIndex<K> {
+    fn default() -> Self {
+        Self {
+            order: BTreeSet::new(),
+        }
+    }
+}
+
+impl<K: Ord + Clone + AsRef<[u8]> + MinKey> ScanIndex<K> {
+    pub fn insert(&mut self, key: K) {
+        self.order.insert((scan_hash(key.as_ref()), key));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.order.remove(&(scan_hash(key.as_ref()), key));
+    }
+
+    pub fn clear(&mut self) {
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// One page of at least `count` keys, fewer at the end, from `cursor` on, and the
+    /// cursor of the next page. Keys sharing a hash are never split across pages.
+    pub fn page(&self, cursor: u64, count: usize) -> (u64, Vec<&K>) {
+        let mut keys = Vec::new();
+        let mut last_hash = None;
+        for (hash, key) in self.order.range((cursor, K::min_key())..) {
+            if keys.len() >= count.max(1) && last_hash != Some(*hash) {
+                return (*hash, keys);
+            }
+            keys.push(key);
+            last_hash = Some(*hash);
+        }
+        (0, keys)
+    }
+}
+
+/// A key type `ScanIndex::page` can start a range from: the least value there is,
+/// which sorts before every key sharing its hash.
+pub trait MinKey {
+    fn min_key() -> Self;
+}
+
+impl MinKey for String {
+    fn min_key() -> Self {
+        String::new()
+    }
+}
+
+impl MinKey for bytes::Bytes {
+    fn min_key() -> Self {
+        bytes::Bytes::new()
+    }
+}
+
+/// A `HashMap` that keeps a `ScanIndex` of its keys, for the hashes HSCAN walks.
+/// Reads go through the map itself; writes go through the methods here, so the
+/// index cannot fall out of step.
+#[derive(Debug, Clone)]
+pub struct ScanMap<K, V> {
+    map: HashMap<K, V>,
+    index: ScanIndex<K>,
+}
+
+impl<K, V> Default for ScanMap<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            index: ScanIndex::default(),
+        }
+    }
+}
+
+impl<K, V> Deref for ScanMap<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for ScanMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K, V> ScanMap<K, V>
+where
+    K: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(existing) = self.map.get_mut(&key) {
+            return Some(std::mem::replace(existing, value));
+        }
+        self.index.insert(key.clone());
+        self.map.insert(key, value)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.index.remove(key);
+        Some(value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// The value under `key`, inserting the one `default` makes when there is none.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        if !self.map.contains_key(&key) {
+            self.index.insert(key.clone());
+        }
+        self.map.entry(key).or_insert_with(default)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.index.clear();
+    }
+
+    /// One page of keys and their values, see `ScanIndex::page`.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&K, &V)>) {
+        let (cursor, keys) = self.index.page(cursor, count);
+        let entries = keys
+            .into_iter()
+            .filter_map(|key| self.map.get_key_value(key))
+            .collect();
+        (cursor, entries)
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for ScanMap<K, V>
+where
+    K: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        let mut map = Self::new();
+        map.extend(entries);
+        map
+    }
+}
+
+impl<K, V> Extend<(K, V)> for ScanMap<K, V>
+where
+    K: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V> From<HashMap<K, V>> for ScanMap<K, V>
+where
+    K: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from(map: HashMap<K, V>) -> Self {
+        let mut index = ScanIndex::default();
+        for key in map.keys() {
+            index.insert(key.clone());
+        }
+        Self { map, index }
+    }
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for ScanMap<K, V>
+where
+    K: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a ScanMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::collections::hash_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+/// A `HashSet` that keeps a `ScanIndex` of its members, for the sets SSCAN walks.
+/// Like `ScanMap`, writes go through the methods here.
+#[derive(Debug, Clone)]
+pub struct ScanSet<T> {
+    set: HashSet<T>,
+    index: ScanIndex<T>,
+}
+
+impl<T> Default for ScanSet<T> {
+    fn default() -> Self {
+        Self {
+            set: HashSet::new(),
+            index: ScanIndex::default(),
+        }
+    }
+}
+
+impl<T> Deref for ScanSet<T> {
+    type Target = HashSet<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.set
+    }
+}
+
+impl<T: Hash + Eq> PartialEq for ScanSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.set == other.set
+    }
+}
+
+impl<T> ScanSet<T>
+where
+    T: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a member. Returns true when it is new.
+    pub fn insert(&mut self, member: T) -> bool {
+        if self.set.contains(&member) {
+            return false;
+        }
+        self.index.insert(member.clone());
+        self.set.insert(member)
+    }
+
+    /// Removes a member. Returns true when it was there.
+    pub fn remove<Q>(&mut self, member: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.set.take(member) {
+            Some(member) => {
+                self.index.remove(member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.set.clear();
+        self.index.clear();
+    }
+
+    /// One page of members, see `ScanIndex::page`.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&T>) {
+        self.index.page(cursor, count)
+    }
+}
+
+impl<T> FromIterator<T> for ScanSet<T>
+where
+    T: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(members: I) -> Self {
+        let mut set = Self::new();
+        set.extend(members);
+        set
+    }
+}
+
+impl<T> Extend<T> for ScanSet<T>
+where
+    T: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, members: I) {
+        for member in members {
+            self.insert(member);
+        }
+    }
+}
+
+impl<T> From<HashSet<T>> for ScanSet<T>
+where
+    T: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from(set: HashSet<T>) -> Self {
+        let mut index = ScanIndex::default();
+        for member in &set {
+            index.insert(member.clone());
+        }
+        Self { set, index }
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for ScanSet<T>
+where
+    T: Hash + Ord + Clone + AsRef<[u8]> + MinKey,
+{
+    fn from(members: [T; N]) -> Self {
+        members.into_iter().collect()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a ScanSet<T> {
+    type Item = &'a T;
+    type IntoIter = std::collections::hash_set::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.iter()
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use bytes::Bytes;
+
+    fn scan_all(index: &ScanIndex<String>, count: usize) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = index.page(cursor, count);
+            assert!(page.len() <= count);
+            seen.extend(page.into_iter().cloned());
+            if next == 0 {
+                return seen;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn test_page_visits_every_key_once() {
+        let keys: Vec<String> = (0..100).map(|i| format!("key:{}", i)).collect();
+        let mut index = ScanIndex::default();
+        for key in &keys {
+            index.insert(key.clone());
+        }
+
+        let mut seen = scan_all(&index, 7);
+        seen.sort();
+        let mut expected = keys.clone();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_page_survives_concurrent_changes() {
+        let mut index = ScanIndex::default();
+        for i in 0..50 {
+            index.insert(format!("stable:{}", i));
+        }
+        let (mut cursor, page) = index.page(0, 10);
+        let mut seen: Vec<String> = page.into_iter().cloned().collect();
+
+        // Keys added and removed mid-iteration must not hide the stable ones
+        for key in seen.iter().filter(|key| !key.ends_with('0')) {
+            index.remove(key.clone());
+        }
+        for i in 0..200 {
+            index.insert(format!("new:{}", i));
+        }
+
+        while cursor != 0 {
+            let (next, page) = index.page(cursor, 10);
+            seen.extend(page.into_iter().cloned());
+            cursor = next;
+        }
+        for i in 0..50 {
+            assert!(seen.contains(&format!("stable:{}", i)));
+        }
+    }
+
+    #[test]
+    fn test_map_and_set_keep_their_index() {
+        let mut hash: ScanMap<String, Bytes> = [("a".to_owned(), Bytes::from("1"))].into();
+        hash.insert("b".to_owned(), Bytes::from("2"));
+        hash.insert("a".to_owned(), Bytes::from("3"));
+        *hash.get_or_insert_with("c".to_owned(), Bytes::new) = Bytes::from("4");
+        assert_eq!(hash.remove("b"), Some(Bytes::from("2")));
+        let (cursor, mut page) = hash.scan(0, 10);
+        page.sort();
+        assert_eq!(cursor, 0);
+        assert_eq!(
+            page,
+            vec![
+                (&"a".to_owned(), &Bytes::from("3")),
+                (&"c".to_owned(), &Bytes::from("4"))
+            ]
+        );
+
+        let mut set: ScanSet<Bytes> = (0..20).map(|i| Bytes::from(i.to_string())).collect();
+        assert!(!set.insert(Bytes::from("3")));
+        assert!(set.remove(&Bytes::from("3")));
+        assert!(!set.remove(&Bytes::from("3")));
+        let (cursor, page) = set.scan(0, 5);
+        assert!(cursor != 0 && page.len() >= 5);
+        let mut members = page.len();
+        let mut cursor = cursor;
+        while cursor != 0 {
+            let (next, page) = set.scan(cursor, 5);
+            members += page.len();
+            cursor = next;
+        }
+        assert_eq!(members, 19);
+
+        set.clear();
+        assert_eq!(set.scan(0, 5), (0, vec![]));
+    }
+}
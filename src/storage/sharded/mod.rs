@@ -0,0 +1,492 @@
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::CommandError;
+
+use super::scan::{scan_hash, ScanMap, ScanSet};
+use super::sorted_set::SortedSet;
+use super::stream::Stream;
+use super::{table::Table, DataType, Db, Entry, KeyspaceStats, MemoryStats};
+
+pub mod tests_sharded;
+
+// Shards per database unless a caller asks for another count
+const SHARDS: usize = 16;
+// Keys with a TTL the active expiry cycle looks at per round, and the share of them
+// in percent that must have expired for another round, like Redis
+const EXPIRE_SAMPLES: usize = 20;
+const EXPIRE_STALE_PERCENT: usize = 25;
+
+/// One logical database split into shards, each a `Db` behind its own lock. A key
+/// always lives in the shard its hash picks, so a command locks only the shards of
+/// the keys it names and writes to keys in different shards run in parallel.
+///
+/// Shards are always locked in ascending order, and a command holds one set of
+/// shard locks at a time, so two commands can never wait on each other.
+#[derive(Debug)]
+pub struct ShardedDb {
+    shards: Vec<RwLock<Db>>,
+    // Shared by every shard, so reading them takes no lock
+    stats: Arc<KeyspaceStats>,
+    clock: Arc<dyn Clock>,
+}
+
+/// A set of locked shards of one database. Shards that were not locked are None,
+/// and touching a key that lives in one of them is a bug that panics.
+pub struct Shards<G> {
+    guards: Vec<Option<G>>,
+}
+
+pub type ReadShards<'a> = Shards<RwLockReadGuard<'a, Db>>;
+pub type WriteShards<'a> = Shards<RwLockWriteGuard<'a, Db>>;
+
+impl Default for ShardedDb {
+    fn default() -> Self {
+        Self::with_shards(SHARDS)
+    }
+}
+
+impl ShardedDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_shards(count: usize) -> Self {
+        Self::with_layout(count, Arc::new(SystemClock))
+    }
+
+    /// A database whose shards read the time from `clock`, the server's.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::with_layout(SHARDS, clock)
+    }
+
+    fn with_layout(count: usize, clock: Arc<dyn Clock>) -> Self {
+        let stats = Arc::new(KeyspaceStats::default());
+        Self {
+            shards: (0..count.max(1))
+                .map(|_| RwLock::new(Db::with_stats(Arc::clone(&stats), Arc::clone(&clock))))
+                .collect(),
+            stats,
+            clock,
+        }
+    }
+
+    /// The current time in milliseconds since the Unix epoch, by the server's clock.
+    pub fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    /// The expired, evicted, hit and missed key counters of this database.
+    pub fn stats(&self) -> &KeyspaceStats {
+        &self.stats
+    }
+
+    /// The estimated memory of the database's entries, read without locking. Entries
+    /// changed in place count at their old size until they are measured again.
+    pub fn used_memory(&self) -> usize {
+        self.stats.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// Measures again the entries changed in place in the shards holding `keys`, so
+    /// `used_memory` catches up with a write to them.
+    pub async fn measure_keys<K: AsRef<str>>(&self, keys: &[K]) {
+        self.write_keys(keys).await.used_memory();
+    }
+
+    /// Measures again the entries changed in place in every shard, one shard at a time.
+    pub async fn measure(&self) {
+        for shard in &self.shards {
+            shard.write().await.used_memory();
+        }
+    }
+
+    /// Locks every shard for reading, for commands that look at the whole keyspace.
+    pub async fn read(&self) -> ReadShards<'_> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            guards.push(Some(shard.read().await));
+        }
+        Shards { guards }
+    }
+
+    /// Locks every shard for writing.
+    pub async fn write(&self) -> WriteShards<'_> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            guards.push(Some(shard.write().await));
+        }
+        Shards { guards }
+    }
+
+    /// Locks for reading only the shards holding `keys`.
+    pub async fn read_keys<K: AsRef<str>>(&self, keys: &[K]) -> ReadShards<'_> {
+        let mut guards: Vec<_> = self.shards.iter().map(|_| None).collect();
+        for index in self.shard_indexes(keys) {
+            guards[index] = Some(self.shards[index].read().await);
+        }
+        Shards { guards }
+    }
+
+    /// Locks for writing only the shards holding `keys`.
+    pub async fn write_keys<K: AsRef<str>>(&self, keys: &[K]) -> WriteShards<'_> {
+        let mut guards: Vec<_> = self.shards.iter().map(|_| None).collect();
+        for index in self.shard_indexes(keys) {
+            guards[index] = Some(self.shards[index].write().await);
+        }
+        Shards { guards }
+    }
+
+    pub async fn read_key(&self, key: &str) -> ReadShards<'_> {
+        self.read_keys(&[key]).await
+    }
+
+    pub async fn write_key(&self, key: &str) -> WriteShards<'_> {
+        self.write_keys(&[key]).await
+    }
+
+    /// Physically removes every expired entry, one shard at a time so other clients
+    /// only ever wait for a single shard. Returns how many were dropped.
+    pub async fn remove_expired(&self) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            removed += shard.write().await.remove_expired();
+        }
+        removed
+    }
+
+    /// One active expiry cycle, Redis' adaptive algorithm: every shard samples some
+    /// keys with a TTL and removes the expired ones, again while more than a quarter
+    /// of a sample had expired, so the cycle costs about the same on any keyspace
+    /// size and works harder only when many keys are stale. It stops early at
+    /// `deadline`. Returns how many keys were removed.
+    pub async fn expire_cycle(&self, deadline: Instant) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            loop {
+                let (sampled, expired) = shard.write().await.remove_expired_sample(EXPIRE_SAMPLES);
+                removed += expired;
+                if expired * 100 <= sampled * EXPIRE_STALE_PERCENT || Instant::now() >= deadline {
+                    break;
+                }
+            }
+        }
+        removed
+    }
+
+    /// One page of SCAN over the whole database, see `ScanIndex::page`. Shards split
+    /// the range of `scan_hash` between them, so the cursor names the shard to resume
+    /// in and only that one is locked at a time.
+    pub async fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let count = count.max(1);
+        let mut keys = Vec::new();
+        let mut cursor = cursor;
+        let mut shard = shard_of_hash(cursor, self.shards.len());
+        loop {
+            let db = self.shards[shard].read().await;
+            let (next, page) = db.scan(cursor, count - keys.len());
+            keys.extend(page.into_iter().cloned());
+            drop(db);
+            if next != 0 {
+                return (next, keys);
+            }
+            shard += 1;
+            if shard == self.shards.len() {
+                return (0, keys);
+            }
+            cursor = first_hash_of_shard(shard, self.shards.len());
+            if keys.len() >= count {
+                return (cursor, keys);
+            }
+        }
+    }
+
+    // The distinct shards of some keys, in the order they must be locked
+    fn shard_indexes<K: AsRef<str>>(&self, keys: &[K]) -> Vec<usize> {
+        let mut indexes: Vec<usize> = keys
+            .iter()
+            .map(|key| shard_index(key.as_ref(), self.shards.len()))
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+    }
+}
+
+// A fixed hash, so a key maps to the same shard for the life of the process. It is
+// the hash SCAN walks in, and each shard takes one contiguous range of it
+fn shard_index(key: &str, count: usize) -> usize {
+    shard_of_hash(scan_hash(key.as_bytes()), count)
+}
+
+fn shard_of_hash(hash: u64, count: usize) -> usize {
+    (((hash >> 32) * count as u64) >> 32) as usize
+}
+
+// The least hash `shard_of_hash` puts in `shard`
+fn first_hash_of_shard(shard: usize, count: usize) -> u64 {
+    (((shard as u64) << 32).div_ceil(count as u64)) << 32
+}
+
+// Typed lookups for every collection type, so commands need not match on `DataType`
+// themselves. A key holding another type is the WRONGTYPE error; a missing key is
+// None for reads and created empty by the `_or_create` accessors of `StorageMut`.
+macro_rules! typed_lookups {
+    ($($get:ident => $variant:ident($type:ty)),* $(,)?) => {
+        $(
+            fn $get(&self, key: &str) -> Result<Option<&$type>, CommandError> {
+                match self.get(key) {
+                    Some(DataType::$variant(value)) => Ok(Some(value)),
+                    Some(_) => Err(CommandError::WrongType),
+                    None => Ok(None),
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! typed_lookups_mut {
+    ($($get_mut:ident, $or_create:ident => $variant:ident($type:ty)),* $(,)?) => {
+        $(
+            fn $get_mut(&mut self, key: &str) -> Result<Option<&mut $type>, CommandError> {
+                match self.get_entry_mut(key) {
+                    Some(Entry {
+                        value: DataType::$variant(value),
+                        ..
+                    }) => Ok(Some(value)),
+                    Some(_) => Err(CommandError::WrongType),
+                    None => Ok(None),
+                }
+            }
+
+            // A write command that ends up leaving the collection empty must remove
+            // the key, as no collection outlives its last element
+            fn $or_create(&mut self, key: &str) -> Result<&mut $type, CommandError> {
+                if self.get_entry(key).is_none() {
+                    let empty = DataType::$variant(Default::default());
+                    self.insert_entry(key.to_owned(), Entry::new(empty));
+                }
+                Ok(self.$get_mut(key)?.expect("the key was just created"))
+            }
+        )*
+    };
+}
+
+/// Read access to a keyspace. Lookups go to the shard that holds the key; methods
+/// that walk the keyspace cover every locked shard.
+pub trait Storage {
+    /// The shard holding `key`.
+    fn shard(&self, key: &str) -> &Db;
+
+    /// Every locked shard, in order.
+    fn shards(&self) -> impl Iterator<Item = &Db>;
+
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.shard(key).get_entry(key)
+    }
+
+    fn peek_entry(&self, key: &str) -> Option<(&Entry, u64)> {
+        self.shard(key).peek_entry(key)
+    }
+
+    fn frequency(&self, key: &str) -> Option<u8> {
+        self.shard(key).frequency(key)
+    }
+
+    fn get(&self, key: &str) -> Option<&DataType> {
+        self.shard(key).get(key)
+    }
+
+    fn lookup(&self, key: &str) -> Option<&DataType> {
+        self.shard(key).lookup(key)
+    }
+
+    fn ttl_millis(&self, key: &str) -> Option<Option<u64>> {
+        self.shard(key).ttl_millis(key)
+    }
+
+    fn version(&self, key: &str) -> u64 {
+        self.shard(key).version(key)
+    }
+
+    typed_lookups! {
+        get_hash => Hash(ScanMap<String, Bytes>),
+        get_set => Set(ScanSet<Bytes>),
+        get_sorted_set => SortedSet(SortedSet),
+        get_list => List(VecDeque<Bytes>),
+        get_stream => Stream(Stream),
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &String> {
+        self.shards().flat_map(|shard| shard.keys())
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.shards().flat_map(|shard| shard.entries())
+    }
+
+    /// The number of keys, counting expired ones the sweeper has not reclaimed yet.
+    fn len(&self) -> usize {
+        self.shards().map(Db::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shards().all(Db::is_empty)
+    }
+
+    fn expires(&self) -> usize {
+        self.shards().map(Db::expires).sum()
+    }
+
+    fn memory_stats(&self) -> MemoryStats {
+        let mut total = MemoryStats::default();
+        for stats in self.shards().map(Db::memory_stats) {
+            total.keys += stats.keys;
+            total.overhead += stats.overhead;
+            total.expires_overhead += stats.expires_overhead;
+            total.dataset += stats.dataset;
+        }
+        total
+    }
+
+    /// A random live key. The shard is picked in proportion to its size, so every key
+    /// is about as likely.
+    fn random_key(&self) -> Option<&String> {
+        if self.is_empty() {
+            return None;
+        }
+        let total = self.len();
+        let mut position = RandomState::new().hash_one(total) as usize % total;
+        for shard in self.shards() {
+            if position < shard.len() {
+                if let Some(key) = shard.random_key() {
+                    return Some(key);
+                }
+                break;
+            }
+            position -= shard.len();
+        }
+        // The picked shard only held expired keys
+        self.keys().next()
+    }
+}
+
+/// Write access to a keyspace, on top of `Storage`.
+pub trait StorageMut: Storage {
+    fn shard_mut(&mut self, key: &str) -> &mut Db;
+
+    fn shards_mut(&mut self) -> impl Iterator<Item = &mut Db>;
+
+    fn get_entry_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.shard_mut(key).get_entry_mut(key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        self.shard_mut(&key).insert_entry(key, entry)
+    }
+
+    typed_lookups_mut! {
+        get_hash_mut, get_or_create_hash => Hash(ScanMap<String, Bytes>),
+        get_set_mut, get_or_create_set => Set(ScanSet<Bytes>),
+        get_sorted_set_mut, get_or_create_sorted_set => SortedSet(SortedSet),
+        get_list_mut, get_or_create_list => List(VecDeque<Bytes>),
+        get_stream_mut, get_or_create_stream => Stream(Stream),
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DataType> {
+        self.shard_mut(key).remove(key)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        self.shard_mut(key).remove_entry(key)
+    }
+
+    fn evict(&mut self, key: &str) -> Option<DataType> {
+        self.shard_mut(key).evict(key)
+    }
+
+    fn set_expiry(&mut self, key: &str, expires_at: Option<u64>) -> bool {
+        self.shard_mut(key).set_expiry(key, expires_at)
+    }
+
+    fn watch(&mut self, key: &str) -> u64 {
+        self.shard_mut(key).watch(key)
+    }
+
+    fn unwatch(&mut self, key: &str) {
+        self.shard_mut(key).unwatch(key)
+    }
+
+    fn block(&mut self, keys: &[String], waiter: &Arc<Notify>) {
+        for key in keys {
+            self.shard_mut(key).block(std::slice::from_ref(key), waiter);
+        }
+    }
+
+    fn unblock(&mut self, keys: &[String], waiter: &Arc<Notify>) {
+        for key in keys {
+            self.shard_mut(key)
+                .unblock(std::slice::from_ref(key), waiter);
+        }
+    }
+
+    fn wake_blocked(&mut self, key: &str, count: usize) {
+        self.shard_mut(key).wake_blocked(key, count)
+    }
+
+    fn used_memory(&mut self) -> usize {
+        self.shards_mut().map(|shard| shard.used_memory()).sum()
+    }
+
+    fn clear(&mut self) {
+        self.shards_mut().for_each(Db::clear);
+    }
+
+    /// Empties every shard and hands back the old entries, as `Db::take_entries`.
+    fn take_entries(&mut self) -> Vec<Table> {
+        self.shards_mut().map(Db::take_entries).collect()
+    }
+
+    /// Exchanges the contents of two keyspaces shard by shard. Both must have every
+    /// shard locked.
+    fn swap_entries(&mut self, other: &mut Self)
+    where
+        Self: Sized,
+    {
+        for (shard, other) in self.shards_mut().zip(other.shards_mut()) {
+            shard.swap_entries(other);
+        }
+    }
+}
+
+impl<G: Deref<Target = Db>> Storage for Shards<G> {
+    fn shard(&self, key: &str) -> &Db {
+        self.guards[shard_index(key, self.guards.len())]
+            .as_deref()
+            .expect("the shard of a key is locked before it is used")
+    }
+
+    fn shards(&self) -> impl Iterator<Item = &Db> {
+        self.guards.iter().flatten().map(Deref::deref)
+    }
+}
+
+impl<G: DerefMut<Target = Db>> StorageMut for Shards<G> {
+    fn shard_mut(&mut self, key: &str) -> &mut Db {
+        let index = shard_index(key, self.guards.len());
+        self.guards[index]
+            .as_deref_mut()
+            .expect("the shard of a key is locked before it is used")
+    }
+
+    fn shards_mut(&mut self) -> impl Iterator<Item = &mut Db> {
+        self.guards.iter_mut().flatten().map(DerefMut::deref_mut)
+    }
+}
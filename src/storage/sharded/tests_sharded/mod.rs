@@ -0,0 +1,281 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::error::CommandError;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    fn entry(value: &str) -> Entry {
+        Entry::new(DataType::String(value.to_owned().into()))
+    }
+
+    // Two keys that live in different shards
+    fn keys_in_different_shards() -> (String, String) {
+        let first = "key:0".to_owned();
+        let other = (1..)
+            .map(|i| format!("key:{}", i))
+            .find(|key| shard_index(key, SHARDS) != shard_index(&first, SHARDS))
+            .unwrap();
+        (first, other)
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_found_through_any_lock() {
+        let db = ShardedDb::new();
+        for i in 0..50 {
+            let key = format!("key:{}", i);
+            db.write_key(&key)
+                .await
+                .insert_entry(key.clone(), entry("v"));
+        }
+
+        assert_eq!(db.read().await.len(), 50);
+        assert_eq!(db.read().await.keys().count(), 50);
+        let (first, other) = keys_in_different_shards();
+        let instance = db.read_keys(&[&first, &other]).await;
+        assert!(instance.get(&first).is_some());
+        assert!(instance.get(&other).is_some());
+        // Only the two shards are locked, so walking the keyspace sees only their keys
+        assert!(instance.keys().count() < 50);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "locked")]
+    async fn test_key_outside_the_locked_shards_panics() {
+        let db = ShardedDb::new();
+        let (first, other) = keys_in_different_shards();
+        db.read_key(&first).await.get(&other);
+    }
+
+    #[tokio::test]
+    async fn test_writers_on_different_shards_do_not_wait() {
+        let db = ShardedDb::new();
+        let (first, other) = keys_in_different_shards();
+        let _held = db.write_key(&first).await;
+        let acquired = tokio::time::timeout(Duration::from_millis(100), db.write_key(&other)).await;
+        assert!(acquired.is_ok());
+        // The same shard, or the whole keyspace, has to wait
+        let blocked = tokio::time::timeout(Duration::from_millis(50), db.read()).await;
+        assert!(blocked.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_random_key_and_swap_cover_every_shard() {
+        let first = ShardedDb::new();
+        let second = ShardedDb::new();
+        {
+            let mut instance = first.write().await;
+            for i in 0..20 {
+                instance.insert_entry(format!("key:{}", i), entry("v"));
+            }
+        }
+
+        let instance = first.read().await;
+        let seen: HashSet<String> = (0..2000)
+            .filter_map(|_| instance.random_key().cloned())
+            .collect();
+        assert_eq!(seen.len(), 20);
+        drop(instance);
+
+        let (mut low, mut high) = (first.write().await, second.write().await);
+        low.swap_entries(&mut high);
+        assert!(low.is_empty());
+        assert_eq!(high.len(), 20);
+        assert_eq!(high.take_entries().len(), SHARDS);
+        assert!(high.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_walks_the_shards_in_turn() {
+        let db = ShardedDb::new();
+        for i in 0..500 {
+            let key = format!("key:{}", i);
+            db.write_key(&key)
+                .await
+                .insert_entry(key.clone(), entry("v"));
+        }
+
+        // The first page only needs the first shard, so a writer elsewhere does not
+        // hold it up
+        let last = (0..500)
+            .map(|i| format!("key:{}", i))
+            .find(|key| shard_index(key, SHARDS) == SHARDS - 1)
+            .unwrap();
+        let held = db.write_key(&last).await;
+        let page = tokio::time::timeout(Duration::from_millis(100), db.scan(0, 10)).await;
+        assert!(matches!(page, Ok((cursor, keys)) if cursor != 0 && keys.len() == 10));
+        drop(held);
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, 7).await;
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 500);
+
+        for shard in 0..SHARDS {
+            let first = first_hash_of_shard(shard, SHARDS);
+            assert_eq!(shard_of_hash(first, SHARDS), shard);
+            if shard > 0 {
+                assert_eq!(shard_of_hash(first - 1, SHARDS), shard - 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typed_lookups() {
+        let db = ShardedDb::new();
+        let mut instance = db.write().await;
+        instance.insert_entry("text".to_owned(), entry("v"));
+        assert_eq!(instance.get_list("missing"), Ok(None));
+        assert_eq!(instance.get_list("text"), Err(CommandError::WrongType));
+        assert_eq!(instance.get_hash_mut("text"), Err(CommandError::WrongType));
+        assert!(instance.get_or_create_set("text").is_err());
+
+        // Creating a collection leaves it empty for the caller to fill
+        instance
+            .get_or_create_list("list")
+            .unwrap()
+            .push_back("a".into());
+        instance
+            .get_or_create_list("list")
+            .unwrap()
+            .push_back("b".into());
+        assert_eq!(instance.get_list("list").unwrap().unwrap().len(), 2);
+        assert!(instance
+            .get_or_create_stream("stream")
+            .unwrap()
+            .entries()
+            .is_empty());
+        assert_eq!(instance.get_stream_mut("missing"), Ok(None));
+        assert!(instance.get_sorted_set("list").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expire_cycle_adapts_to_stale_keys() {
+        let db = ShardedDb::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        {
+            let mut instance = db.write().await;
+            for i in 0..1000 {
+                let key = format!("stale:{}", i);
+                instance.insert_entry(key.clone(), entry("v"));
+                instance.set_expiry(&key, Some(SystemClock.now_millis() - 1));
+            }
+        }
+        // Past the deadline every shard gets a single round
+        let removed = db.expire_cycle(Instant::now()).await;
+        assert!(removed > 0 && removed <= EXPIRE_SAMPLES * SHARDS);
+        // With every sampled key expired, rounds go on until none are left
+        assert_eq!(db.expire_cycle(deadline).await, 1000 - removed);
+        assert!(db.read().await.is_empty());
+
+        {
+            let mut instance = db.write().await;
+            for i in 0..1000 {
+                let key = format!("fresh:{}", i);
+                instance.insert_entry(key.clone(), entry("v"));
+                instance.set_expiry(&key, Some(SystemClock.now_millis() + 10_000));
+            }
+            instance.insert_entry("stale".to_owned(), entry("v"));
+            instance.set_expiry("stale", Some(SystemClock.now_millis() - 1));
+        }
+        // With barely any, a cycle is one round per shard and leaves live keys alone
+        assert!(db.expire_cycle(deadline).await <= 1);
+        assert!(db.read().await.len() >= 1000);
+    }
+
+    #[tokio::test]
+    async fn test_used_memory_is_published() {
+        let first = ShardedDb::new();
+        let second = ShardedDb::new();
+        {
+            let mut instance = first.write().await;
+            for i in 0..20 {
+                instance.insert_entry(format!("key:{}", i), entry("v"));
+            }
+            assert_eq!(first.used_memory(), instance.used_memory());
+        }
+        let full = first.used_memory();
+        assert!(full > 0);
+
+        // A value grown in place counts once it is measured again
+        if let Some(Entry {
+            value: DataType::String(value),
+            ..
+        }) = first.write_key("key:0").await.get_entry_mut("key:0")
+        {
+            *value = "v".repeat(1000).into();
+        }
+        assert_eq!(first.used_memory(), full);
+        first.measure_keys(&["key:0"]).await;
+        let grown = first.used_memory();
+        assert!(grown >= full + 999);
+
+        // The memory moves with the entries, and entries taken out count until dropped
+        let (mut low, mut high) = (first.write().await, second.write().await);
+        low.swap_entries(&mut high);
+        assert_eq!((first.used_memory(), second.used_memory()), (0, grown));
+        let taken = high.take_entries();
+        assert_eq!(second.used_memory(), grown);
+        drop(taken);
+        assert_eq!(second.used_memory(), 0);
+    }
+
+    // Several tasks each append to their own list, holding the lock for as long as a
+    // real command would. Run with
+    // `cargo test --release bench_sharded_writes -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn bench_sharded_writes() {
+        const TASKS: usize = 8;
+        const WRITES: usize = 20_000;
+
+        let mut rates = Vec::new();
+        for shards in [1, SHARDS] {
+            let db = Arc::new(ShardedDb::with_shards(shards));
+            let start = Instant::now();
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let db = Arc::clone(&db);
+                    tokio::spawn(async move {
+                        for i in 0..WRITES {
+                            let key = format!("list:{}:{}", task, i % 64);
+                            let mut instance = db.write_key(&key).await;
+                            if instance.get(&key).is_none() {
+                                instance.insert_entry(
+                                    key.clone(),
+                                    Entry::new(DataType::List(Default::default())),
+                                );
+                            }
+                            if let Some(Entry {
+                                value: DataType::List(list),
+                                ..
+                            }) = instance.get_entry_mut(&key)
+                            {
+                                list.push_back(i.to_string().into());
+                                list.truncate(16);
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            let rate = (TASKS * WRITES) as f64 / start.elapsed().as_secs_f64();
+            println!("{:>2} shard(s): {:>10.0} writes/s", shards, rate);
+            rates.push(rate);
+        }
+        println!("speedup: {:.2}x", rates[1] / rates[0]);
+    }
+}
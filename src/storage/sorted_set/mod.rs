@@ -0,0 +1,224 @@
+use bytes::Bytes;
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use super::scan::ScanMap;
+
+pub mod tests_sorted_set;
+
+/// A score with a total order so it can key a BTreeSet. NaN is rejected before it
+/// gets here and -0.0 is normalised to 0.0, so the order matches numeric order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by (score, member), with a member -> score map for O(1) lookups
+/// that ZSCAN also walks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: ScanMap<Bytes, f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or updates a member. Returns true when the member is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let score = if score == 0.0 { 0.0 } else { score };
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&(Score(score), member.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the member with the lowest score.
+    pub fn pop_min(&mut self) -> Option<(Bytes, f64)> {
+        let (score, member) = self.ordered.pop_first()?;
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    /// Removes and returns the member with the highest score.
+    pub fn pop_max(&mut self) -> Option<(Bytes, f64)> {
+        let (score, member) = self.ordered.pop_last()?;
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Zero-based position of the member in ascending order.
+    pub fn rank(&self, member: &Bytes) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.ordered.range(..(Score(score), member.clone())).count())
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members in ascending (score, member) order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// One page of members and their scores, see `ScanIndex::page`.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, f64)>) {
+        let (cursor, members) = self.scores.scan(cursor, count);
+        let members = members
+            .into_iter()
+            .map(|(member, score)| (member, *score))
+            .collect();
+        (cursor, members)
+    }
+
+    /// Members with a score between `min` and `max`, in ascending order.
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        // The empty member sorts first, so the range starts at the lowest score
+        self.ordered
+            .range((Score(min.value), Bytes::new())..)
+            .map(|(score, member)| (member, score.0))
+            .skip_while(move |(_, score)| !min.allows_above(*score))
+            .take_while(move |(_, score)| max.allows_below(*score))
+    }
+
+    /// Members between `min` and `max` by byte order, walking the set in score order.
+    /// The result is only meaningful when every member has the same score.
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: &'a LexBound,
+        max: &'a LexBound,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> {
+        self.iter()
+            .skip_while(move |(member, _)| !min.allows_above(member))
+            .take_while(move |(member, _)| max.allows_below(member))
+    }
+}
+
+/// One end of a score range. A leading `(` makes it exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let (value, exclusive) = match value.strip_prefix(b"(") {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        let value = parse_score(value)?;
+        Some(Self {
+            value: if value == 0.0 { 0.0 } else { value },
+            exclusive,
+        })
+    }
+
+    // Whether `score` is on the right side of this bound as a minimum
+    fn allows_above(&self, score: f64) -> bool {
+        score > self.value || (!self.exclusive && score == self.value)
+    }
+
+    // Whether `score` is on the right side of this bound as a maximum
+    fn allows_below(&self, score: f64) -> bool {
+        score < self.value || (!self.exclusive && score == self.value)
+    }
+}
+
+/// One end of a lexicographic range: `[member` inclusive, `(member` exclusive, and
+/// `-` and `+` below and above every member.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        match value {
+            b"-" => Some(Self::Min),
+            b"+" => Some(Self::Max),
+            [b'[', member @ ..] => Some(Self::Inclusive(Bytes::copy_from_slice(member))),
+            [b'(', member @ ..] => Some(Self::Exclusive(Bytes::copy_from_slice(member))),
+            _ => None,
+        }
+    }
+
+    fn allows_above(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Min => true,
+            Self::Max => false,
+            Self::Inclusive(bound) => member >= bound.as_ref(),
+            Self::Exclusive(bound) => member > bound.as_ref(),
+        }
+    }
+
+    fn allows_below(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Min => false,
+            Self::Max => true,
+            Self::Inclusive(bound) => member <= bound.as_ref(),
+            Self::Exclusive(bound) => member < bound.as_ref(),
+        }
+    }
+}
+
+/// Parses a score the way Redis does, accepting `inf`, `+inf` and `-inf` but not NaN.
+pub fn parse_score(value: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(value).ok()?;
+    let score = match text.to_ascii_lowercase().as_str() {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        _ => text.parse::<f64>().ok()?,
+    };
+    (!score.is_nan()).then_some(score)
+}
+
+/// Formats a score for replies: integers without a fraction and infinities as `inf`.
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        score.to_string()
+    }
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn members(set: &SortedSet) -> Vec<(String, f64)> {
+        set.iter()
+            .map(|(member, score)| (String::from_utf8(member.to_vec()).unwrap(), score))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_orders_by_score_then_member() {
+        let mut set = SortedSet::new();
+        assert!(set.insert("b".into(), 2.0));
+        assert!(set.insert("a".into(), 2.0));
+        assert!(set.insert("c".into(), 1.0));
+        assert!(!set.insert("c".into(), 3.0));
+
+        assert_eq!(
+            members(&set),
+            vec![
+                ("a".to_owned(), 2.0),
+                ("b".to_owned(), 2.0),
+                ("c".to_owned(), 3.0)
+            ]
+        );
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn test_rank_score_and_remove() {
+        let mut set = SortedSet::new();
+        set.insert("low".into(), f64::NEG_INFINITY);
+        set.insert("mid".into(), -0.0);
+        set.insert("high".into(), 10.5);
+
+        assert_eq!(set.rank(&"low".into()), Some(0));
+        assert_eq!(set.rank(&"high".into()), Some(2));
+        assert_eq!(set.rank(&"missing".into()), None);
+        assert_eq!(set.score(&"mid".into()), Some(0.0));
+
+        assert!(set.remove(&"mid".into()));
+        assert!(!set.remove(&"mid".into()));
+        assert_eq!(set.rank(&"high".into()), Some(1));
+    }
+
+    #[test]
+    fn test_parse_and_format_score() {
+        assert_eq!(parse_score(b"1.5"), Some(1.5));
+        assert_eq!(parse_score(b"-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(parse_score(b"+Inf"), Some(f64::INFINITY));
+        assert_eq!(parse_score(b"nan"), None);
+        assert_eq!(parse_score(b"abc"), None);
+
+        assert_eq!(format_score(3.0), "3");
+        assert_eq!(format_score(-1.25), "-1.25");
+        assert_eq!(format_score(f64::INFINITY), "inf");
+        assert_eq!(format_score(f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn test_range_by_score_and_lex() {
+        let mut set = SortedSet::new();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 3.0)] {
+            set.insert(member.into(), score);
+        }
+        let by_score = |min: &str, max: &str| -> Vec<Bytes> {
+            let min = ScoreBound::parse(min.as_bytes()).unwrap();
+            let max = ScoreBound::parse(max.as_bytes()).unwrap();
+            set.range_by_score(min, max)
+                .map(|(member, _)| member.clone())
+                .collect()
+        };
+        assert_eq!(by_score("2", "2"), vec!["b", "c"]);
+        assert_eq!(by_score("(1", "(3"), vec!["b", "c"]);
+        assert_eq!(by_score("-inf", "+inf").len(), 4);
+        assert!(by_score("(3", "+inf").is_empty());
+        assert!(ScoreBound::parse(b"(x").is_none());
+
+        let by_lex = |min: &str, max: &str| -> Vec<Bytes> {
+            let min = LexBound::parse(min.as_bytes()).unwrap();
+            let max = LexBound::parse(max.as_bytes()).unwrap();
+            set.range_by_lex(&min, &max)
+                .map(|(member, _)| member.clone())
+                .collect()
+        };
+        assert_eq!(by_lex("-", "+").len(), 4);
+        assert_eq!(by_lex("[b", "(d"), vec!["b", "c"]);
+        assert_eq!(by_lex("(a", "[b"), vec!["b"]);
+        assert!(by_lex("+", "-").is_empty());
+        assert!(LexBound::parse(b"b").is_none());
+    }
+}
@@ -0,0 +1,293 @@
+use bytes::Bytes;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    fmt,
+};
+
+pub mod tests_stream;
+
+/// A stream entry ID: the creation time in milliseconds and a sequence number that
+/// tells apart entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+/// The field/value pairs of one entry, in the order they were given.
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// The ID XADD was asked to use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdRequest {
+    // `*`: generated from the clock
+    Auto,
+    // `<ms>-*`: the given time with the next free sequence number
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// An append-only log of entries ordered by ID.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    // The greatest ID ever added; new entries must be above it
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+/// A consumer group hands each new entry to one of its consumers and remembers it
+/// as pending until the consumer acknowledges it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerGroup {
+    // The newest entry given to any consumer
+    last_delivered: StreamId,
+    // Delivered but unacknowledged entries of every consumer
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Consumer {
+    // The IDs of this consumer's entries in the group's pending list
+    pending: BTreeSet<StreamId>,
+}
+
+/// Who an unacknowledged entry was delivered to, when, and how many times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parses `<ms>-<seq>`, or a bare `<ms>` that takes `default_seq`.
+    pub fn parse(text: &str, default_seq: u64) -> Option<Self> {
+        match text.split_once('-') {
+            Some((ms, seq)) => Some(Self::new(ms.parse().ok()?, seq.parse().ok()?)),
+            None => Some(Self::new(text.parse().ok()?, default_seq)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl IdRequest {
+    /// Parses the ID argument of XADD.
+    pub fn parse(text: &str) -> Option<Self> {
+        if text == "*" {
+            return Some(Self::Auto);
+        }
+        match text.strip_suffix("-*") {
+            Some(ms) => Some(Self::AutoSeq(ms.parse().ok()?)),
+            None => StreamId::parse(text, 0).map(Self::Explicit),
+        }
+    }
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<StreamId, Fields> {
+        &self.entries
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Raises the last ID, as when loading a stream whose newest entries were deleted.
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    /// Resolves the ID a new entry would get at time `now`. Returns None when it would
+    /// not be greater than the last ID.
+    pub fn next_id(&self, request: IdRequest, now: u64) -> Option<StreamId> {
+        let last = self.last_id;
+        let id = match request {
+            IdRequest::Auto if now > last.ms => StreamId::new(now, 0),
+            // The clock went backwards or this millisecond already has entries
+            IdRequest::Auto => match last.seq.checked_add(1) {
+                Some(seq) => StreamId::new(last.ms, seq),
+                None => StreamId::new(last.ms.checked_add(1)?, 0),
+            },
+            IdRequest::AutoSeq(ms) if ms == last.ms => StreamId::new(ms, last.seq.checked_add(1)?),
+            // 0-0 is never a valid ID, so the first entry of millisecond 0 is 0-1
+            IdRequest::AutoSeq(ms) => StreamId::new(ms, (ms == 0) as u64),
+            IdRequest::Explicit(id) => id,
+        };
+        (id > last).then_some(id)
+    }
+
+    /// Appends an entry. The ID must come from `next_id`.
+    pub fn insert(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = self.last_id.max(id);
+    }
+
+    pub fn groups(&self) -> &BTreeMap<String, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Adds a group that will deliver the entries after `last_delivered`. Returns None
+    /// when the name is taken.
+    pub fn create_group(
+        &mut self,
+        name: &str,
+        last_delivered: StreamId,
+    ) -> Option<&mut ConsumerGroup> {
+        match self.groups.entry(name.to_owned()) {
+            Entry::Occupied(_) => None,
+            Entry::Vacant(vacant) => Some(vacant.insert(ConsumerGroup::new(last_delivered))),
+        }
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Delivers up to `count` entries the group has not handed out yet to `consumer`,
+    /// adding them to its pending list unless `noack` is set. Returns None when the
+    /// group does not exist.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Fields)>> {
+        let group = self.groups.get_mut(group)?;
+        group.create_consumer(consumer);
+        let after = group.last_delivered;
+        let delivered: Vec<(StreamId, Fields)> = self
+            .entries
+            .range(after..)
+            .skip_while(|(id, _)| **id == after)
+            .take(count)
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect();
+        for (id, _) in &delivered {
+            group.last_delivered = *id;
+            if !noack {
+                group.deliver(*id, consumer, now);
+            }
+        }
+        Some(delivered)
+    }
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId) -> Self {
+        Self {
+            last_delivered,
+            ..Self::default()
+        }
+    }
+
+    pub fn last_delivered(&self) -> StreamId {
+        self.last_delivered
+    }
+
+    pub fn set_last_delivered(&mut self, id: StreamId) {
+        self.last_delivered = id;
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<String, Consumer> {
+        &self.consumers
+    }
+
+    /// Returns false when the consumer already exists.
+    pub fn create_consumer(&mut self, name: &str) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(name.to_owned(), Consumer::default());
+        true
+    }
+
+    /// Removes a consumer along with its pending entries and returns how many it had.
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Records that `consumer` was given the entry `id` at `now`. An entry that was
+    /// already pending moves to the new consumer and counts one more delivery.
+    pub fn deliver(&mut self, id: StreamId, consumer: &str, now: u64) {
+        let deliveries = self.pending.get(&id).map_or(0, |entry| entry.deliveries);
+        self.insert_pending(
+            id,
+            PendingEntry {
+                consumer: consumer.to_owned(),
+                delivered_at: now,
+                deliveries: deliveries + 1,
+            },
+        );
+    }
+
+    /// Adds an entry to the pending list as it is, as when loading a snapshot.
+    pub fn insert_pending(&mut self, id: StreamId, entry: PendingEntry) {
+        if let Some(previous) = self.pending.get(&id) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        self.consumers
+            .entry(entry.consumer.clone())
+            .or_default()
+            .pending
+            .insert(id);
+        self.pending.insert(id, entry);
+    }
+
+    /// Removes an entry from the pending list. Returns false if it was not pending.
+    pub fn acknowledge(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(&id);
+        }
+        true
+    }
+}
+
+impl Consumer {
+    pub fn pending(&self) -> &BTreeSet<StreamId> {
+        &self.pending
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn fields() -> Fields {
+        vec![(Bytes::from("field"), Bytes::from("value"))]
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(StreamId::parse("5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse("5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse("5-x", 0), None);
+        assert_eq!(StreamId::new(1, 2).to_string(), "1-2");
+
+        assert_eq!(IdRequest::parse("*"), Some(IdRequest::Auto));
+        assert_eq!(IdRequest::parse("7-*"), Some(IdRequest::AutoSeq(7)));
+        assert_eq!(
+            IdRequest::parse("7-1"),
+            Some(IdRequest::Explicit(StreamId::new(7, 1)))
+        );
+        assert_eq!(IdRequest::parse("-1"), None);
+    }
+
+    #[test]
+    fn test_generated_ids_increase() {
+        let mut stream = Stream::new();
+        let first = stream.next_id(IdRequest::Auto, 100).unwrap();
+        assert_eq!(first, StreamId::new(100, 0));
+        stream.insert(first, fields());
+
+        // Same millisecond, or a clock that went backwards, bumps the sequence
+        assert_eq!(
+            stream.next_id(IdRequest::Auto, 100),
+            Some(StreamId::new(100, 1))
+        );
+        assert_eq!(
+            stream.next_id(IdRequest::Auto, 50),
+            Some(StreamId::new(100, 1))
+        );
+        assert_eq!(
+            stream.next_id(IdRequest::AutoSeq(100), 0),
+            Some(StreamId::new(100, 1))
+        );
+        assert_eq!(
+            stream.next_id(IdRequest::AutoSeq(200), 0),
+            Some(StreamId::new(200, 0))
+        );
+        assert_eq!(stream.next_id(IdRequest::AutoSeq(99), 0), None);
+    }
+
+    #[test]
+    fn test_explicit_ids_must_increase() {
+        let mut stream = Stream::new();
+        assert_eq!(
+            stream.next_id(IdRequest::AutoSeq(0), 0),
+            Some(StreamId::new(0, 1))
+        );
+        assert_eq!(stream.next_id(IdRequest::Explicit(StreamId::MIN), 0), None);
+
+        stream.insert(StreamId::new(5, 5), fields());
+        assert_eq!(
+            stream.next_id(IdRequest::Explicit(StreamId::new(5, 5)), 0),
+            None
+        );
+        assert_eq!(
+            stream.next_id(IdRequest::Explicit(StreamId::new(5, 6)), 0),
+            Some(StreamId::new(5, 6))
+        );
+        assert_eq!(stream.entries().len(), 1);
+        assert_eq!(stream.last_id(), StreamId::new(5, 5));
+    }
+
+    #[test]
+    fn test_consumer_group_delivery() {
+        let mut stream = Stream::new();
+        for seq in 1..=3 {
+            stream.insert(StreamId::new(1, seq), fields());
+        }
+        assert!(stream.create_group("group", StreamId::new(1, 1)).is_some());
+        assert!(stream.create_group("group", StreamId::MIN).is_none());
+        assert_eq!(stream.read_group("missing", "alice", 10, false, 0), None);
+
+        // Each new entry goes to one consumer only
+        let first = stream.read_group("group", "alice", 1, false, 10).unwrap();
+        assert_eq!(first[0].0, StreamId::new(1, 2));
+        let second = stream.read_group("group", "bob", 10, false, 20).unwrap();
+        assert_eq!(second.len(), 1);
+        assert!(stream
+            .read_group("group", "bob", 10, false, 30)
+            .unwrap()
+            .is_empty());
+
+        let group = stream.group_mut("group").unwrap();
+        assert_eq!(group.last_delivered(), StreamId::new(1, 3));
+        assert_eq!(group.pending().len(), 2);
+
+        // Redelivering moves the entry and counts it
+        group.deliver(StreamId::new(1, 2), "bob", 40);
+        assert!(group.consumers()["alice"].pending().is_empty());
+        assert_eq!(group.consumers()["bob"].pending().len(), 2);
+        assert_eq!(group.pending()[&StreamId::new(1, 2)].deliveries, 2);
+
+        assert!(group.acknowledge(StreamId::new(1, 2)));
+        assert!(!group.acknowledge(StreamId::new(1, 2)));
+        assert_eq!(group.delete_consumer("bob"), Some(1));
+        assert!(group.pending().is_empty());
+    }
+}
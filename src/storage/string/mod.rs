@@ -0,0 +1,101 @@
+use bytes::{Bytes, BytesMut};
+use std::fmt::Write;
+
+pub mod tests_string;
+
+/// A string value. One that is the canonical form of a 64-bit integer is kept as the
+/// number, like Redis's int encoding, so it costs no allocation and INCR skips parsing
+/// it. Writes by byte offset, such as APPEND and SETRANGE, leave the value raw.
+#[derive(Debug, Clone)]
+pub enum StringValue {
+    Int(i64),
+    Raw(Bytes),
+}
+
+impl StringValue {
+    /// Stores the bytes as a number when they spell one exactly, and as they are
+    /// otherwise.
+    pub fn new(bytes: Bytes) -> Self {
+        match parse_integer(&bytes) {
+            Some(number) => Self::Int(number),
+            None => Self::Raw(bytes),
+        }
+    }
+
+    /// The value as the bytes a client reads.
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            Self::Int(number) => {
+                let mut digits = BytesMut::with_capacity(20);
+                write!(digits, "{}", number).unwrap();
+                digits.freeze()
+            }
+            Self::Raw(bytes) => bytes.clone(),
+        }
+    }
+
+    /// The value as a number, if it is one, for INCR and friends.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Int(number) => Some(*number),
+            Self::Raw(bytes) => std::str::from_utf8(bytes).ok()?.parse().ok(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Int(number) => {
+                let digits = number.unsigned_abs().checked_ilog10().unwrap_or(0) as usize + 1;
+                digits + (*number < 0) as usize
+            }
+            Self::Raw(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Raw(bytes) if bytes.is_empty())
+    }
+}
+
+// The encoding is how the value is kept, not what it is, so an int equals its digits
+impl PartialEq for StringValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            _ => self.to_bytes() == other.to_bytes(),
+        }
+    }
+}
+
+impl From<Bytes> for StringValue {
+    fn from(bytes: Bytes) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<&'static str> for StringValue {
+    fn from(string: &'static str) -> Self {
+        Self::new(Bytes::from(string))
+    }
+}
+
+impl From<String> for StringValue {
+    fn from(string: String) -> Self {
+        Self::new(Bytes::from(string))
+    }
+}
+
+impl From<Vec<u8>> for StringValue {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(Bytes::from(bytes))
+    }
+}
+
+/// A canonical signed 64-bit integer, the only strings Redis stores as numbers.
+pub fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() > 20 {
+        return None;
+    }
+    let number: i64 = std::str::from_utf8(bytes).ok()?.parse().ok()?;
+    (number.to_string().as_bytes() == bytes).then_some(number)
+}
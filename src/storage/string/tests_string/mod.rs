@@ -0,0 +1,51 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_only_canonical_integers_are_numbers() {
+        for (text, number) in [
+            ("0", 0),
+            ("42", 42),
+            ("-7", -7),
+            ("9223372036854775807", i64::MAX),
+            ("-9223372036854775808", i64::MIN),
+        ] {
+            let value = StringValue::from(text);
+            assert!(
+                matches!(value, StringValue::Int(n) if n == number),
+                "{}",
+                text
+            );
+            assert_eq!(value.to_bytes(), text);
+            assert_eq!(value.len(), text.len());
+        }
+        for text in [
+            "",
+            "012",
+            "+1",
+            " 1",
+            "1.0",
+            "-0",
+            "9223372036854775808",
+            "abc",
+        ] {
+            assert!(
+                matches!(StringValue::from(text), StringValue::Raw(_)),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_raw_digits_equal_the_number() {
+        let raw = StringValue::Raw(Bytes::from("12"));
+        assert_eq!(raw, StringValue::Int(12));
+        assert_eq!(raw.as_integer(), Some(12));
+        assert_ne!(StringValue::Raw(Bytes::from("012")), StringValue::Int(12));
+        assert_eq!(StringValue::Raw(Bytes::from("x")).as_integer(), None);
+        assert!(StringValue::from("").is_empty());
+        assert!(!StringValue::Int(0).is_empty());
+    }
+}
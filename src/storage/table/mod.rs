@@ -0,0 +1,344 @@
+use std::collections::{hash_map::RandomState, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use super::scan::ScanIndex;
+use super::{Entry, KeyspaceStats};
+use crate::clock::{Clock, SystemClock};
+
+pub mod tests_table;
+
+/// The keys of one database and their entries. Entries sit in a dense vector with a
+/// map from key to position, so besides lookups by key a uniformly random entry can
+/// be picked in constant time (RANDOMKEY). Removal swaps the last entry into the gap.
+///
+/// Each slot also remembers when its key was last looked up, which OBJECT IDLETIME
+/// reports, and how often it is looked up, which OBJECT FREQ reports. Lookups under a
+/// read lock update both, so they are atomic.
+///
+/// The keys are also kept in a `ScanIndex`, so SCAN reads one page at a time.
+///
+/// Keys with a TTL are also listed in a dense vector of their positions, so the
+/// active expiry cycle can sample them without walking the keys that never expire.
+/// Every TTL change goes through `insert` or `set_expiry` to keep that list in step.
+///
+/// The table keeps a running estimate of the memory its entries take for maxmemory.
+/// An entry handed out mutably may change in any way, so its size is only measured
+/// again when `used_memory` is next asked for. Every change to the estimate is also
+/// added to the database's `KeyspaceStats::used_memory`, which can be read without
+/// locking the table.
+#[derive(Debug)]
+pub struct Table {
+    slots: Vec<Slot>,
+    positions: HashMap<String, usize>,
+    // The sum of every slot's size
+    used: usize,
+    // Keys whose entries were borrowed mutably since their size was measured
+    stale: HashSet<String>,
+    // Positions of the slots whose entry has a TTL, in no particular order
+    volatile: Vec<usize>,
+    order: ScanIndex<String>,
+    // Access times are read from the database's clock
+    clock: Arc<dyn Clock>,
+    // Where `used` is published
+    stats: Arc<KeyspaceStats>,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::with_stats(Arc::default(), Arc::new(SystemClock))
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        self.resize(self.used, 0);
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    key: String,
+    entry: Entry,
+    // Milliseconds since the Unix epoch
+    accessed_at: AtomicU64,
+    // Logarithmic access counter, as the LFU policies use
+    frequency: AtomicU8,
+    // The entry's memory usage when last measured
+    size: usize,
+    // Where the slot is listed in `Table::volatile`, when its entry has a TTL
+    volatile: Option<usize>,
+}
+
+impl Slot {
+    fn new(key: String, entry: Entry, now: u64) -> Self {
+        Self {
+            size: entry.memory_usage(&key),
+            key,
+            entry,
+            accessed_at: AtomicU64::new(now),
+            frequency: AtomicU8::new(LFU_INIT),
+            volatile: None,
+        }
+    }
+
+    fn touch(&self, now: u64) {
+        let frequency = self.decayed_frequency(now);
+        self.frequency
+            .store(increment_frequency(frequency, now), Ordering::Relaxed);
+        self.accessed_at.store(now, Ordering::Relaxed);
+    }
+
+    // The counter less one for every minute since the last access, like Redis with
+    // lfu-decay-time 1
+    fn decayed_frequency(&self, now: u64) -> u8 {
+        let idle_minutes = now.saturating_sub(self.accessed_at.load(Ordering::Relaxed)) / 60_000;
+        let frequency = self.frequency.load(Ordering::Relaxed);
+        frequency.saturating_sub(idle_minutes.min(u8::MAX as u64) as u8)
+    }
+}
+
+// The counter a new key starts with, so it is not evicted before it had a chance
+const LFU_INIT: u8 = 5;
+// How much harder each step of the counter gets, Redis' lfu-log-factor
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+// Counts one access: the higher the counter, the less likely it grows, so 255 stands
+// for about a million accesses
+fn increment_frequency(frequency: u8, now: u64) -> u8 {
+    if frequency == u8::MAX {
+        return frequency;
+    }
+    let random = RandomState::new().hash_one(now) as f64 / u64::MAX as f64;
+    let base = frequency.saturating_sub(LFU_INIT) as f64;
+    if random < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+        frequency + 1
+    } else {
+        frequency
+    }
+}
+
+impl Table {
+    /// An empty table of a database, publishing its memory to the database's stats
+    /// and reading the time from its clock.
+    pub fn with_stats(stats: Arc<KeyspaceStats>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            slots: Vec::new(),
+            positions: HashMap::new(),
+            used: 0,
+            stale: HashSet::new(),
+            volatile: Vec::new(),
+            order: ScanIndex::default(),
+            clock,
+            stats,
+        }
+    }
+
+    /// Moves the table to another database's stats, as SWAPDB does, taking its memory
+    /// along.
+    pub fn set_stats(&mut self, stats: Arc<KeyspaceStats>) {
+        let used = self.used;
+        self.resize(used, 0);
+        self.stats = stats;
+        self.resize(0, used);
+    }
+
+    // Changes the estimate from `from` to `to` bytes and publishes the difference
+    fn resize(&mut self, from: usize, to: usize) {
+        self.used = self.used - from + to;
+        let memory = &self.stats.used_memory;
+        match to >= from {
+            true => memory.fetch_add(to - from, Ordering::Relaxed),
+            false => memory.fetch_sub(from - to, Ordering::Relaxed),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Looks up an entry and records the access.
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        let slot = &self.slots[*self.positions.get(key)?];
+        slot.touch(self.clock.now_millis());
+        Some(&slot.entry)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        let slot = &mut self.slots[*self.positions.get(key)?];
+        slot.touch(self.clock.now_millis());
+        if !self.stale.contains(key) {
+            self.stale.insert(key.to_owned());
+        }
+        Some(&mut slot.entry)
+    }
+
+    /// Looks up an entry without counting it as an access, along with the time of the
+    /// last access.
+    pub fn peek(&self, key: &str) -> Option<(&Entry, u64)> {
+        let slot = &self.slots[*self.positions.get(key)?];
+        Some((&slot.entry, slot.accessed_at.load(Ordering::Relaxed)))
+    }
+
+    /// The access counter of an entry, decayed for the time since its last access,
+    /// without counting this as an access.
+    pub fn frequency(&self, key: &str) -> Option<u8> {
+        let slot = &self.slots[*self.positions.get(key)?];
+        Some(slot.decayed_frequency(self.clock.now_millis()))
+    }
+
+    /// Stores an entry and returns the one it replaced.
+    pub fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        match self.positions.get(&key) {
+            Some(&position) => {
+                let slot = &mut self.slots[position];
+                slot.touch(self.clock.now_millis());
+                let size = entry.memory_usage(&key);
+                let previous_size = std::mem::replace(&mut slot.size, size);
+                let volatile = entry.expires_at.is_some();
+                let previous = std::mem::replace(&mut slot.entry, entry);
+                self.resize(previous_size, size);
+                self.stale.remove(&key);
+                self.list_volatile(position, volatile);
+                Some(previous)
+            }
+            None => {
+                let volatile = entry.expires_at.is_some();
+                let slot = Slot::new(key.clone(), entry, self.clock.now_millis());
+                self.resize(0, slot.size);
+                self.order.insert(key.clone());
+                self.positions.insert(key, self.slots.len());
+                self.slots.push(slot);
+                self.list_volatile(self.slots.len() - 1, volatile);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let position = self.positions.remove(key)?;
+        self.list_volatile(position, false);
+        let slot = self.slots.swap_remove(position);
+        if let Some(moved) = self.slots.get(position) {
+            self.positions.insert(moved.key.clone(), position);
+            if let Some(index) = moved.volatile {
+                self.volatile[index] = position;
+            }
+        }
+        self.resize(slot.size, 0);
+        self.stale.remove(key);
+        self.order.remove(slot.key);
+        Some(slot.entry)
+    }
+
+    /// Keeps only the entries for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&String, &mut Entry) -> bool) {
+        let mut position = 0;
+        while position < self.slots.len() {
+            let slot = &mut self.slots[position];
+            if keep(&slot.key, &mut slot.entry) {
+                position += 1;
+            } else {
+                let key = slot.key.clone();
+                self.remove(&key);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.slots.iter().map(|slot| (&slot.key, &slot.entry))
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.positions.clear();
+        self.resize(self.used, 0);
+        self.stale.clear();
+        self.volatile.clear();
+        self.order.clear();
+    }
+
+    /// One page of keys with their entries, see `ScanIndex::page`.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&String, &Entry)>) {
+        let (cursor, keys) = self.order.page(cursor, count);
+        let entries = keys
+            .into_iter()
+            .map(|key| (key, &self.slots[self.positions[key]].entry))
+            .collect();
+        (cursor, entries)
+    }
+
+    /// Sets or clears the TTL of an entry, counted as an access. Returns false when
+    /// the key does not exist.
+    pub fn set_expiry(&mut self, key: &str, expires_at: Option<u64>) -> bool {
+        let Some(entry) = self.get_mut(key) else {
+            return false;
+        };
+        entry.expires_at = expires_at;
+        self.list_volatile(self.positions[key], expires_at.is_some());
+        true
+    }
+
+    /// The number of entries with a TTL, expired ones included.
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    /// A uniformly random entry with a TTL, None when there is none.
+    pub fn random_volatile(&self) -> Option<(&String, &Entry)> {
+        if self.volatile.is_empty() {
+            return None;
+        }
+        let index = RandomState::new().hash_one(self.volatile.len()) as usize % self.volatile.len();
+        let slot = &self.slots[self.volatile[index]];
+        Some((&slot.key, &slot.entry))
+    }
+
+    // Adds the slot at `position` to the volatile list or takes it out, moving the
+    // last listed slot into the gap like `remove` does
+    fn list_volatile(&mut self, position: usize, volatile: bool) {
+        match (self.slots[position].volatile, volatile) {
+            (None, true) => {
+                self.slots[position].volatile = Some(self.volatile.len());
+                self.volatile.push(position);
+            }
+            (Some(index), false) => {
+                self.slots[position].volatile = None;
+                self.volatile.swap_remove(index);
+                if let Some(&moved) = self.volatile.get(index) {
+                    self.slots[moved].volatile = Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The estimated memory taken by every entry, measuring again those that may have
+    /// changed since.
+    pub fn used_memory(&mut self) -> usize {
+        for key in std::mem::take(&mut self.stale) {
+            if let Some(&position) = self.positions.get(&key) {
+                let slot = &mut self.slots[position];
+                let size = slot.entry.memory_usage(&slot.key);
+                let previous_size = std::mem::replace(&mut slot.size, size);
+                self.resize(previous_size, size);
+            }
+        }
+        self.used
+    }
+
+    /// A uniformly random entry, None when the table is empty.
+    pub fn random(&self) -> Option<(&String, &Entry)> {
+        if self.is_empty() {
+            return None;
+        }
+        let position = RandomState::new().hash_one(self.slots.len()) as usize % self.slots.len();
+        let slot = &self.slots[position];
+        Some((&slot.key, &slot.entry))
+    }
+}
@@ -0,0 +1,138 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::storage::DataType;
+    use std::collections::HashSet;
+
+    fn entry(value: &str) -> Entry {
+        Entry::new(DataType::String(value.to_owned().into()))
+    }
+
+    #[test]
+    fn test_remove_keeps_positions() {
+        let mut table = Table::default();
+        for key in ["a", "b", "c", "d"] {
+            assert_eq!(table.insert(key.to_owned(), entry(key)), None);
+        }
+        assert_eq!(table.insert("b".to_owned(), entry("new")), Some(entry("b")));
+
+        // Removing from the middle moves the last entry into the gap
+        assert_eq!(table.remove("a"), Some(entry("a")));
+        assert_eq!(table.remove("a"), None);
+        assert_eq!(table.get("d"), Some(&entry("d")));
+        assert_eq!(table.get("b"), Some(&entry("new")));
+
+        table.retain(|key, _| key != "d");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get("c"), Some(&entry("c")));
+        assert!(table.get("d").is_none());
+    }
+
+    #[test]
+    fn test_random_visits_every_entry() {
+        let mut table = Table::default();
+        assert!(table.random().is_none());
+        for key in ["a", "b", "c"] {
+            table.insert(key.to_owned(), entry(key));
+        }
+
+        let seen: HashSet<&String> = (0..200)
+            .filter_map(|_| table.random().map(|(key, _)| key))
+            .collect();
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn test_volatile_entries_are_listed() {
+        let mut table = Table::default();
+        assert!(table.random_volatile().is_none());
+        for key in ["a", "b", "c", "d"] {
+            table.insert(key.to_owned(), entry(key));
+        }
+        table.set_expiry("a", Some(1));
+        table.set_expiry("c", Some(1));
+        assert!(!table.set_expiry("missing", Some(1)));
+        let volatile = |table: &Table| -> HashSet<String> {
+            (0..200)
+                .filter_map(|_| table.random_volatile().map(|(key, _)| key.clone()))
+                .collect()
+        };
+        assert_eq!(
+            volatile(&table),
+            HashSet::from(["a".to_owned(), "c".to_owned()])
+        );
+
+        // Removing "a" moves "d" into its slot, which must not confuse the list
+        table.remove("a");
+        table.set_expiry("d", Some(1));
+        table.set_expiry("c", None);
+        table.insert(
+            "b".to_owned(),
+            Entry {
+                expires_at: Some(1),
+                ..entry("b")
+            },
+        );
+        assert_eq!(table.volatile_len(), 2);
+        assert_eq!(
+            volatile(&table),
+            HashSet::from(["b".to_owned(), "d".to_owned()])
+        );
+        table.insert("d".to_owned(), entry("d"));
+        assert_eq!(volatile(&table), HashSet::from(["b".to_owned()]));
+        table.clear();
+        assert_eq!(table.volatile_len(), 0);
+    }
+
+    #[test]
+    fn test_lookups_record_access() {
+        let mut table = Table::default();
+        table.insert("key".to_owned(), entry("value"));
+        let (_, inserted) = table.peek("key").unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        table.peek("key");
+        assert_eq!(table.peek("key").unwrap().1, inserted);
+        table.get("key");
+        assert!(table.peek("key").unwrap().1 > inserted);
+    }
+
+    #[test]
+    fn test_lookups_count_frequency() {
+        let mut table = Table::default();
+        table.insert("key".to_owned(), entry("value"));
+        assert_eq!(table.frequency("key"), Some(5));
+
+        // Only lookups count; the first one past the initial value always does
+        table.peek("key");
+        assert_eq!(table.frequency("key"), Some(5));
+        table.get("key");
+        assert_eq!(table.frequency("key"), Some(6));
+        assert_eq!(table.frequency("missing"), None);
+    }
+
+    #[test]
+    fn test_used_memory_follows_changes() {
+        let mut table = Table::default();
+        assert_eq!(table.used_memory(), 0);
+        table.insert("a".to_owned(), entry("one"));
+        table.insert("b".to_owned(), entry("two"));
+        let both = table.used_memory();
+        assert_eq!(both, 2 * entry("one").memory_usage("a"));
+
+        // A value changed in place is measured again
+        if let Some(Entry {
+            value: DataType::String(value),
+            ..
+        }) = table.get_mut("a")
+        {
+            *value = "a much longer value".into();
+        }
+        assert_eq!(table.used_memory(), both - 3 + "a much longer value".len());
+
+        table.remove("a");
+        assert_eq!(table.used_memory(), entry("two").memory_usage("b"));
+        table.clear();
+        assert_eq!(table.used_memory(), 0);
+    }
+}
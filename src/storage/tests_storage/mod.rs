@@ -0,0 +1,304 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::storage::sharded::StorageMut;
+
+    fn entry(value: &str) -> Entry {
+        Entry {
+            value: DataType::String(Bytes::copy_from_slice(value.as_bytes()).into()),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut db = Db::new();
+        assert_eq!(db.insert_entry("key".to_owned(), entry("value")), None);
+        assert_eq!(
+            db.get("key"),
+            Some(&DataType::String(Bytes::from("value").into()))
+        );
+        assert_eq!(
+            db.insert_entry("key".to_owned(), entry("other")),
+            Some(entry("value"))
+        );
+    }
+
+    #[test]
+    fn test_expired_entry_is_invisible() {
+        let mut db = Db::new();
+        db.insert_entry("key".to_owned(), entry("value"));
+        assert!(db.set_expiry("key", Some(SystemClock.now_millis() - 1)));
+
+        assert_eq!(db.get("key"), None);
+        assert_eq!(db.ttl_millis("key"), None);
+        // Removing an expired key reports nothing was there
+        assert_eq!(db.remove("key"), None);
+    }
+
+    #[test]
+    fn test_keyspace_stats() {
+        let stats = Arc::new(KeyspaceStats::default());
+        let mut db = Db::with_stats(Arc::clone(&stats), Arc::new(SystemClock));
+        db.insert_entry("key".to_owned(), entry("value"));
+        db.insert_entry("other".to_owned(), entry("value"));
+
+        // Only lookups count as hits and misses, not every read
+        db.get("missing");
+        assert!(db.lookup("key").is_some());
+        assert!(db.lookup("missing").is_none());
+        assert_eq!(stats.keyspace_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.keyspace_misses.load(Ordering::Relaxed), 1);
+
+        // Expired keys count once they are removed, by a writer or the sweeper
+        db.set_expiry("key", Some(SystemClock.now_millis() - 1));
+        db.set_expiry("other", Some(SystemClock.now_millis() - 1));
+        assert_eq!(stats.expired_keys.load(Ordering::Relaxed), 0);
+        assert_eq!(db.remove("key"), None);
+        assert_eq!(db.remove_expired(), 1);
+        assert_eq!(stats.expired_keys.load(Ordering::Relaxed), 2);
+
+        db.insert_entry("key".to_owned(), entry("value"));
+        assert!(db.evict("key").is_some());
+        assert!(db.evict("key").is_none());
+        assert_eq!(stats.evicted_keys.load(Ordering::Relaxed), 1);
+
+        stats.reset();
+        assert_eq!(stats.expired_keys.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_watched_key_versions() {
+        let mut db = Db::new();
+        db.insert_entry("key".to_owned(), entry("value"));
+
+        // Writes to unwatched keys are not tracked
+        let version = db.watch("key");
+        assert_eq!(db.version("key"), version);
+        db.insert_entry("other".to_owned(), entry("value"));
+        assert_eq!(db.version("key"), version);
+        assert_eq!(db.version("other"), 0);
+
+        db.get_entry_mut("key");
+        let written = db.version("key");
+        assert_ne!(written, version);
+        db.remove("key");
+        let removed = db.version("key");
+        assert_ne!(removed, written);
+        // Deleting it again removes nothing, so it is not a write
+        db.remove("key");
+        assert_eq!(db.version("key"), removed);
+
+        // The version is forgotten once the last watcher leaves
+        db.unwatch("key");
+        assert_eq!(db.version("key"), 0);
+    }
+
+    #[test]
+    fn test_clear_and_swap_touch_watched_keys() {
+        let mut first = Db::new();
+        let mut second = Db::new();
+        first.insert_entry("a".to_owned(), entry("1"));
+        second.insert_entry("b".to_owned(), entry("2"));
+        second.insert_entry("c".to_owned(), entry("3"));
+
+        let version = first.watch("missing");
+        first.swap_entries(&mut second);
+        assert_eq!(first.keys().count(), 2);
+        assert_eq!(
+            second.get("a"),
+            Some(&DataType::String(Bytes::from("1").into()))
+        );
+        assert_ne!(first.version("missing"), version);
+
+        let version = second.watch("a");
+        second.clear();
+        assert_eq!(second.keys().count(), 0);
+        assert_ne!(second.version("a"), version);
+    }
+
+    #[test]
+    fn test_expiring_watched_key_bumps_version() {
+        let mut db = Db::new();
+        db.insert_entry("key".to_owned(), entry("value"));
+        db.set_expiry("key", Some(SystemClock.now_millis() - 1));
+        let version = db.watch("key");
+
+        assert_eq!(db.remove_expired(), 1);
+        assert_ne!(db.version("key"), version);
+    }
+
+    #[tokio::test]
+    async fn test_wake_blocked_in_order() {
+        let mut db = Db::new();
+        let (first, second, third) = (
+            Arc::new(Notify::new()),
+            Arc::new(Notify::new()),
+            Arc::new(Notify::new()),
+        );
+        let keys = ["list".to_owned()];
+        db.block(&keys, &first);
+        db.block(&keys, &second);
+        db.block(&keys, &third);
+        db.unblock(&keys, &second);
+
+        // One element wakes only the longest waiting connection
+        db.wake_blocked("list", 1);
+        let woken = |waiter: &Arc<Notify>| {
+            let waiter = Arc::clone(waiter);
+            async move {
+                tokio::time::timeout(Duration::from_millis(10), waiter.notified())
+                    .await
+                    .is_ok()
+            }
+        };
+        assert!(woken(&first).await);
+        assert!(!woken(&third).await);
+
+        // Waiters whose connection is gone are skipped
+        let gone = Arc::new(Notify::new());
+        db.block(&keys, &gone);
+        drop(gone);
+        db.block(&keys, &second);
+        db.wake_blocked("list", 2);
+        assert!(woken(&third).await);
+        assert!(woken(&second).await);
+    }
+
+    #[test]
+    fn test_insert_clears_ttl() {
+        let mut db = Db::new();
+        db.insert_entry("key".to_owned(), entry("value"));
+        db.set_expiry("key", Some(SystemClock.now_millis() + 10_000));
+        assert!(matches!(db.ttl_millis("key"), Some(Some(_))));
+
+        db.insert_entry("key".to_owned(), entry("value"));
+        assert_eq!(db.ttl_millis("key"), Some(None));
+    }
+
+    #[test]
+    fn test_set_expiry_on_missing_key() {
+        let mut db = Db::new();
+        assert!(!db.set_expiry("missing", Some(SystemClock.now_millis() + 1000)));
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let mut db = Db::new();
+        db.insert_entry("stale".to_owned(), entry("value"));
+        db.insert_entry("fresh".to_owned(), entry("value"));
+        db.set_expiry("stale", Some(SystemClock.now_millis() - 1));
+        db.set_expiry("fresh", Some(SystemClock.now_millis() + 10_000));
+
+        assert_eq!(db.remove_expired(), 1);
+        assert_eq!(db.remove_expired(), 0);
+        assert!(db.get("fresh").is_some());
+    }
+
+    #[test]
+    fn test_remove_expired_sample() {
+        let mut db = Db::new();
+        assert_eq!(db.remove_expired_sample(20), (0, 0));
+        db.insert_entry("persistent".to_owned(), entry("value"));
+        db.insert_entry("stale".to_owned(), entry("value"));
+        db.insert_entry("fresh".to_owned(), entry("value"));
+        db.set_expiry("stale", Some(SystemClock.now_millis() - 1));
+        db.set_expiry("fresh", Some(SystemClock.now_millis() + 10_000));
+
+        // Only keys with a TTL are sampled, the same one possibly more than once
+        let (sampled, removed) = db.remove_expired_sample(50);
+        assert_eq!(sampled, 50);
+        assert!(removed <= 1);
+        while db.len() == 3 {
+            db.remove_expired_sample(20);
+        }
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.stats().expired_keys.load(Ordering::Relaxed), 1);
+        assert!(db.get("fresh").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_expiry_sweeper_reclaims_keys() {
+        let db = Arc::new(ShardedDb::new());
+        {
+            let mut instance = db.write().await;
+            instance.insert_entry("key".to_owned(), entry("value"));
+            instance.set_expiry("key", Some(SystemClock.now_millis() + 20));
+        }
+
+        let shutdown = Arc::new(Shutdown::new());
+        let sweeper = spawn_expiry_sweeper(
+            vec![Arc::clone(&db)],
+            Duration::from_millis(10),
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(LatencyMonitor::new(0, Arc::new(SystemClock))),
+            Arc::clone(&shutdown),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(db.remove_expired().await, 0);
+
+        // The sweeper stops on its own once the server shuts down
+        shutdown.request(false);
+        tokio::time::timeout(Duration::from_secs(1), sweeper)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_encodings() {
+        let string =
+            |value: &str| DataType::String(Bytes::copy_from_slice(value.as_bytes()).into());
+        assert_eq!(string("12345").encoding(), "int");
+        assert_eq!(string("012").encoding(), "embstr");
+        assert_eq!(string(&"x".repeat(45)).encoding(), "raw");
+
+        let numbers: ScanSet<Bytes> = (0..200).map(|i| Bytes::from(i.to_string())).collect();
+        assert_eq!(DataType::Set(numbers.clone()).encoding(), "intset");
+        let mut words = numbers;
+        words.insert("word".into());
+        assert_eq!(DataType::Set(words).encoding(), "hashtable");
+
+        let mut list: VecDeque<Bytes> = ["a", "b"].into_iter().map(Bytes::from).collect();
+        assert_eq!(DataType::List(list.clone()).encoding(), "listpack");
+        list.push_back(Bytes::from("x".repeat(65)));
+        assert_eq!(DataType::List(list).encoding(), "quicklist");
+        assert_eq!(DataType::SortedSet(SortedSet::new()).encoding(), "listpack");
+        assert_eq!(DataType::SortedSet(SortedSet::new()).type_name(), "zset");
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        // Every key pays for its slot and every value for its header
+        let short = entry("v");
+        assert!(short.memory_usage("k") > "kv".len());
+        assert_eq!(
+            entry("longer").memory_usage("k") - short.memory_usage("k"),
+            "longer".len() - 1
+        );
+        let mut expiring = short.clone();
+        expiring.expires_at = Some(SystemClock.now_millis() + 60_000);
+        assert!(expiring.memory_usage("k") > short.memory_usage("k"));
+
+        // An empty collection still costs its structure
+        let empty = DataType::Hash(ScanMap::new()).memory_usage();
+        assert!(empty > DataType::String(Bytes::new().into()).memory_usage());
+        let hash = DataType::Hash([("field".to_owned(), Bytes::from("value"))].into());
+        assert!(hash.memory_usage() > empty + "fieldvalue".len());
+
+        let mut db = Db::new();
+        db.insert_entry("a".to_owned(), short.clone());
+        db.insert_entry("b".to_owned(), expiring.clone());
+        let stats = db.memory_stats();
+        assert_eq!(stats.keys, 2);
+        assert_eq!(
+            stats.overhead + stats.expires_overhead + stats.dataset,
+            short.memory_usage("a") + expiring.memory_usage("b")
+        );
+        assert_eq!(
+            db.used_memory(),
+            stats.overhead + stats.expires_overhead + stats.dataset
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by the unit tests of every module.
+
+use crate::parser::Value;
+
+/// A command's arguments, after its name, as the bulk strings a client sends.
+pub fn args(parts: &[&str]) -> Vec<Value> {
+    parts
+        .iter()
+        .map(|part| Value::BulkString(part.to_string().into()))
+        .collect()
+}
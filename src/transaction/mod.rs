@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use crate::parser::{UserCommand, Value};
+use crate::storage::sharded::{ShardedDb, Storage, StorageMut};
+
+pub mod tests_transaction;
+
+/// Per-connection MULTI/EXEC state: the commands queued since MULTI and the keys
+/// WATCHed before it. Dropping it releases any watches still held.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    // Some while between MULTI and EXEC/DISCARD
+    queued: Option<Vec<(UserCommand, Vec<Value>)>>,
+    // Set when a command was rejected while queuing, so EXEC must abort
+    failed: bool,
+    watched: Vec<WatchedKey>,
+}
+
+#[derive(Debug)]
+struct WatchedKey {
+    // The database the key was watched in, which SELECT may since have changed
+    db: Arc<ShardedDb>,
+    key: String,
+    version: u64,
+    // Whether the key was live when watched, so a lazy expiry counts as a change
+    existed: bool,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// Enters MULTI. Returns false when a transaction is already open.
+    pub fn begin(&mut self) -> bool {
+        if self.is_active() {
+            return false;
+        }
+        self.queued = Some(Vec::new());
+        self.failed = false;
+        true
+    }
+
+    pub fn queue(&mut self, command: UserCommand, args: Vec<Value>) {
+        if let Some(queued) = self.queued.as_mut() {
+            queued.push((command, args));
+        }
+    }
+
+    /// Marks the open transaction as failed; EXEC will then discard it.
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// Leaves MULTI and returns the queued commands, or `None` if the transaction had
+    /// a queuing error.
+    pub fn finish(&mut self) -> Option<Vec<(UserCommand, Vec<Value>)>> {
+        let queued = self.queued.take().unwrap_or_default();
+        (!std::mem::take(&mut self.failed)).then_some(queued)
+    }
+
+    pub async fn watch(&mut self, db: &Arc<ShardedDb>, keys: Vec<String>) {
+        let mut instance = db.write_keys(&keys).await;
+        for key in keys {
+            let existed = instance.get_entry(&key).is_some();
+            let version = instance.watch(&key);
+            self.watched.push(WatchedKey {
+                db: Arc::clone(db),
+                key,
+                version,
+                existed,
+            });
+        }
+    }
+
+    pub async fn unwatch(&mut self) {
+        release(std::mem::take(&mut self.watched)).await;
+    }
+
+    /// Whether any watched key was written, deleted or expired since it was watched.
+    pub async fn is_dirty(&self) -> bool {
+        for watched in &self.watched {
+            let instance = watched.db.read_key(&watched.key).await;
+            if instance.version(&watched.key) != watched.version
+                || (watched.existed && instance.get_entry(&watched.key).is_none())
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+async fn release(watched: Vec<WatchedKey>) {
+    for watched in watched {
+        watched
+            .db
+            .write_key(&watched.key)
+            .await
+            .unwatch(&watched.key);
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.watched.is_empty() {
+            tokio::spawn(release(std::mem::take(&mut self.watched)));
+        }
+    }
+}
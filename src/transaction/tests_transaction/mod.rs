@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::storage::{DataType, Entry};
+
+    fn db() -> Arc<ShardedDb> {
+        Arc::new(ShardedDb::new())
+    }
+
+    fn entry(value: &str) -> Entry {
+        Entry::new(DataType::String(value.to_owned().into()))
+    }
+
+    #[test]
+    fn test_queue_and_finish() {
+        let mut transaction = Transaction::new();
+        assert!(!transaction.is_active());
+        assert!(transaction.begin());
+        assert!(!transaction.begin());
+
+        transaction.queue(UserCommand::Ping, Vec::new());
+        assert_eq!(
+            transaction.finish(),
+            Some(vec![(UserCommand::Ping, Vec::new())])
+        );
+        assert!(!transaction.is_active());
+
+        // A queuing error discards the whole transaction
+        transaction.begin();
+        transaction.queue(UserCommand::Ping, Vec::new());
+        transaction.fail();
+        assert_eq!(transaction.finish(), None);
+
+        // The failure does not leak into the next transaction
+        transaction.begin();
+        assert_eq!(transaction.finish(), Some(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn test_dirty_after_write() {
+        let db = db();
+        let mut transaction = Transaction::new();
+        db.write()
+            .await
+            .insert_entry("key".to_owned(), entry("value"));
+
+        transaction.watch(&db, vec!["key".to_owned()]).await;
+        assert!(!transaction.is_dirty().await);
+        db.write()
+            .await
+            .insert_entry("key".to_owned(), entry("other"));
+        assert!(transaction.is_dirty().await);
+
+        transaction.unwatch().await;
+        assert!(!transaction.is_dirty().await);
+    }
+
+    #[tokio::test]
+    async fn test_dirty_after_lazy_expiry() {
+        let db = db();
+        let mut transaction = Transaction::new();
+        let mut expiring = entry("value");
+        expiring.expires_at = Some(SystemClock.now_millis() + 20);
+        db.write().await.insert_entry("key".to_owned(), expiring);
+
+        transaction.watch(&db, vec!["key".to_owned()]).await;
+        assert!(!transaction.is_dirty().await);
+
+        // Nothing purges the key, yet it is gone by the time EXEC looks
+        tokio::time::sleep(std::time::Duration::from_millis(40)).await;
+        assert!(transaction.is_dirty().await);
+        transaction.unwatch().await;
+    }
+
+    #[tokio::test]
+    async fn test_watch_across_databases() {
+        let (first, second) = (db(), db());
+        let mut transaction = Transaction::new();
+        transaction.watch(&first, vec!["key".to_owned()]).await;
+        transaction.watch(&second, vec!["key".to_owned()]).await;
+
+        // The same key name in an unwatched database changes nothing
+        db().write()
+            .await
+            .insert_entry("key".to_owned(), entry("value"));
+        assert!(!transaction.is_dirty().await);
+        second
+            .write()
+            .await
+            .insert_entry("key".to_owned(), entry("value"));
+        assert!(transaction.is_dirty().await);
+
+        transaction.unwatch().await;
+        assert_eq!(second.read().await.version("key"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drop_releases_watches() {
+        let db = db();
+        let mut transaction = Transaction::new();
+        transaction.watch(&db, vec!["key".to_owned()]).await;
+        db.write()
+            .await
+            .insert_entry("key".to_owned(), entry("value"));
+        assert_ne!(db.read().await.version("key"), 0);
+
+        drop(transaction);
+        tokio::task::yield_now().await;
+        assert_eq!(db.read().await.version("key"), 0);
+    }
+}